@@ -0,0 +1,236 @@
+use crate::hal::I2c;
+use crate::log::{Log, LogCode};
+use crate::sensor::SensorError;
+
+/// I2C address of the LIS3DH accelerometer with SA0 low.
+pub const LIS3DH_ADDRESS: u8 = 0x18;
+/// Acceleration on any axis above this is a shock, e.g. the cabinet being knocked or dropped.
+/// Caught by the chip's interrupt generator, so it must be within the ±8 g full scale.
+pub const SHOCK_MG: u16 = 2000;
+/// Square of the cosine of the largest lean from the installed orientation that isn't a tilt (30°).
+pub const TILT_COS_SQUARED: f32 = 0.75;
+const WHO_AM_I: u8 = 0x0F;
+const LIS3DH_ID: u8 = 0x33;
+const CTRL_REG1: u8 = 0x20;
+const CTRL_REG3: u8 = 0x22;
+const CTRL_REG4: u8 = 0x23;
+const CTRL_REG5: u8 = 0x24;
+const OUT_X_L: u8 = 0x28;
+const INT1_CFG: u8 = 0x30;
+const INT1_SRC: u8 = 0x31;
+const INT1_THS: u8 = 0x32;
+const INT1_DURATION: u8 = 0x33;
+const AUTO_INCREMENT: u8 = 0x80; // Register address flag for multi-byte reads.
+const ODR_100HZ_XYZ: u8 = 0x57; // 100 Hz, normal power, all axes: short knocks still span a sample.
+const HIGH_RESOLUTION_8G: u8 = 0x28; // ±8 g full scale, 12 bits: 4 mg per digit.
+const MG_PER_DIGIT: i16 = 4;
+const I1_IA1: u8 = 0x40; // Interrupt generator 1 drives INT1.
+const LIR_INT1: u8 = 0x08; // Latch it until INT1_SRC is read.
+const HIGH_ANY_AXIS: u8 = 0x2A; // X, Y or Z high event, OR-combined.
+const INT_ACTIVE: u8 = 0x40; // IA in INT1_SRC.
+const THRESHOLD_MG_PER_LSB: u16 = 62; // INT1_THS step at ±8 g.
+
+const _: () = assert!(SHOCK_MG / THRESHOLD_MG_PER_LSB <= 0x7F && SHOCK_MG < 8000);
+
+/// LIS3DH three-axis accelerometer.
+pub struct Accelerometer<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C> Accelerometer<I2C> {
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    /// The bus, e.g. to change its speed.
+    pub fn i2c_mut(&mut self) -> &mut I2C {
+        &mut self.i2c
+    }
+}
+
+impl<I2C: I2c> Accelerometer<I2C> {
+    /// Check the device is there and start continuous measurement, with INT1 raised and latched
+    /// by any axis going over `SHOCK_MG`.
+    pub async fn init(&mut self) -> Result<(), SensorError> {
+        let mut id = [0u8];
+        self.i2c.write_read(self.address, &[WHO_AM_I], &mut id).await.or(Err(SensorError::Bus))?;
+        if id[0] != LIS3DH_ID {
+            return Err(SensorError::Bus);
+        }
+        let threshold = (SHOCK_MG / THRESHOLD_MG_PER_LSB) as u8;
+        for write in [
+            [CTRL_REG4, HIGH_RESOLUTION_8G],
+            [CTRL_REG3, I1_IA1],
+            [CTRL_REG5, LIR_INT1],
+            [INT1_THS, threshold],
+            [INT1_DURATION, 0],
+            [INT1_CFG, HIGH_ANY_AXIS],
+            [CTRL_REG1, ODR_100HZ_XYZ],
+        ] {
+            self.i2c.write(self.address, &write).await.or(Err(SensorError::Bus))?;
+        }
+        Ok(())
+    }
+
+    /// Read the latest (x, y, z) acceleration in mg.
+    pub async fn read_mg(&mut self) -> Result<[i16; 3], SensorError> {
+        let mut buf = [0u8; 6];
+        self.i2c.write_read(self.address, &[OUT_X_L | AUTO_INCREMENT], &mut buf).await.or(Err(SensorError::Bus))?;
+        // Readings are left-justified in 16 bits.
+        Ok([0, 2, 4].map(|i| (i16::from_le_bytes([buf[i], buf[i + 1]]) >> 4) * MG_PER_DIGIT))
+    }
+
+    /// Whether there was a shock since the last call, clearing the latched interrupt.
+    pub async fn take_shock(&mut self) -> Result<bool, SensorError> {
+        let mut source = [0u8];
+        self.i2c.write_read(self.address, &[INT1_SRC], &mut source).await.or(Err(SensorError::Bus))?;
+        Ok(source[0] & INT_ACTIVE != 0)
+    }
+}
+
+/// A movement of the device worth recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MotionEvent {
+    Shock(u16), // Peak acceleration on one axis, mg.
+    Tilted, // Leaning more than 30° from the installed orientation, e.g. knocked over.
+    Upright, // Back within 30° of the installed orientation.
+}
+
+impl MotionEvent {
+    /// Payload of `LogCode::Motion`: the kind in bits 16..24, the peak for a shock in bits 0..16.
+    pub fn code(self) -> u32 {
+        match self {
+            MotionEvent::Shock(peak_mg) => u32::from(peak_mg),
+            MotionEvent::Tilted => 1 << 16,
+            MotionEvent::Upright => 2 << 16,
+        }
+    }
+}
+
+/// Turns accelerometer readings into shock and tilt events.
+///
+/// The first reading taken at rest is the installed orientation, kept across restarts with
+/// `reference` and `with_reference`; call `level` to take it again, e.g. after installing the
+/// device somewhere else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MotionDetector {
+    reference: Option<[i16; 3]>,
+    tilted: bool,
+}
+
+impl MotionDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Carry on with a saved installed orientation, or take the next reading as it if None.
+    pub fn with_reference(reference: Option<[i16; 3]>) -> Self {
+        Self { reference, tilted: false }
+    }
+
+    /// The installed orientation in mg, once taken.
+    pub fn reference(&self) -> Option<[i16; 3]> {
+        self.reference
+    }
+
+    /// Take the next reading as the installed orientation.
+    pub fn level(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Take a reading in mg into account. Returns an event, after logging `Motion` with its code.
+    pub fn update(&mut self, mg: [i16; 3], log: &mut impl Log) -> Option<MotionEvent> {
+        let peak_mg = mg.iter().map(|axis| axis.unsigned_abs()).max().unwrap_or(0);
+        let event = if peak_mg > SHOCK_MG {
+            // Orientation means nothing while the device is being shaken.
+            Some(MotionEvent::Shock(peak_mg))
+        } else {
+            let reference = *self.reference.get_or_insert(mg);
+            let tilted = !within_tilt(reference, mg);
+            let changed = tilted != self.tilted;
+            self.tilted = tilted;
+            match (changed, tilted) {
+                (true, true) => Some(MotionEvent::Tilted),
+                (true, false) => Some(MotionEvent::Upright),
+                (false, _) => None,
+            }
+        };
+        if let Some(event) = event {
+            log.warn(LogCode::Motion, event.code());
+        }
+        event
+    }
+
+    /// Note a shock caught by the accelerometer's interrupt, with a reading taken after it.
+    /// Logs `Motion` and returns the event.
+    pub fn shocked(&mut self, mg: [i16; 3], log: &mut impl Log) -> MotionEvent {
+        // The reading comes after the peak, so it may well be under the threshold.
+        let peak_mg = mg.iter().map(|axis| axis.unsigned_abs()).max().unwrap_or(0).max(SHOCK_MG);
+        let event = MotionEvent::Shock(peak_mg);
+        log.warn(LogCode::Motion, event.code());
+        event
+    }
+
+    pub fn is_tilted(&self) -> bool {
+        self.tilted
+    }
+}
+
+// Whether the angle between the two gravity vectors is within the tilt limit, without a square root.
+fn within_tilt(a: [i16; 3], b: [i16; 3]) -> bool {
+    let dot: f32 = a.iter().zip(b).map(|(&a, b)| f32::from(a) * f32::from(b)).sum();
+    let norm_squared = |v: [i16; 3]| v.iter().map(|&axis| f32::from(axis) * f32::from(axis)).sum::<f32>();
+    dot > 0.0 && dot * dot >= TILT_COS_SQUARED * norm_squared(a) * norm_squared(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{CaptureLog, Level};
+    use embassy_futures::block_on;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    #[test]
+    fn test_driver() {
+        let expectations = [
+            Transaction::write_read(LIS3DH_ADDRESS, vec![WHO_AM_I], vec![LIS3DH_ID]),
+            Transaction::write(LIS3DH_ADDRESS, vec![CTRL_REG4, HIGH_RESOLUTION_8G]),
+            Transaction::write(LIS3DH_ADDRESS, vec![CTRL_REG3, I1_IA1]),
+            Transaction::write(LIS3DH_ADDRESS, vec![CTRL_REG5, LIR_INT1]),
+            Transaction::write(LIS3DH_ADDRESS, vec![INT1_THS, 32]), // 1984 mg.
+            Transaction::write(LIS3DH_ADDRESS, vec![INT1_DURATION, 0]),
+            Transaction::write(LIS3DH_ADDRESS, vec![INT1_CFG, HIGH_ANY_AXIS]),
+            Transaction::write(LIS3DH_ADDRESS, vec![CTRL_REG1, ODR_100HZ_XYZ]),
+            // x = 16 mg, y = -1000 mg, z = 0.
+            Transaction::write_read(LIS3DH_ADDRESS, vec![0xA8], vec![0x40, 0x00, 0x60, 0xF0, 0x00, 0x00]),
+            Transaction::write_read(LIS3DH_ADDRESS, vec![INT1_SRC], vec![INT_ACTIVE | 0x02]),
+        ];
+        let mut accelerometer = Accelerometer::new(Mock::new(&expectations), LIS3DH_ADDRESS);
+        assert_eq!(block_on(accelerometer.init()), Ok(()));
+        assert_eq!(block_on(accelerometer.read_mg()), Ok([16, -1000, 0]));
+        assert_eq!(block_on(accelerometer.take_shock()), Ok(true));
+        accelerometer.i2c_mut().done();
+    }
+
+    #[test]
+    fn test_shock_and_tilt() {
+        let mut detector = MotionDetector::new();
+        let mut log = CaptureLog::default();
+        assert_eq!(detector.update([0, 0, 1000], &mut log), None);
+        assert_eq!(detector.update([400, 0, 920], &mut log), None); // About 23°.
+        assert_eq!(detector.update([2500, 0, 1000], &mut log), Some(MotionEvent::Shock(2500)));
+        assert_eq!(detector.update([1000, 0, 50], &mut log), Some(MotionEvent::Tilted));
+        assert!(detector.is_tilted());
+        assert_eq!(detector.update([0, 0, -1000], &mut log), None); // Upside down is still tilted.
+        assert_eq!(detector.update([0, 50, 1000], &mut log), Some(MotionEvent::Upright));
+        assert_eq!(log.entries[1], (Level::Warn, LogCode::Motion, 1 << 16));
+        detector.level();
+        assert_eq!(detector.update([1000, 0, 0], &mut log), None);
+        // A restart carries on with the saved orientation.
+        let mut restarted = MotionDetector::with_reference(detector.reference());
+        assert_eq!(restarted.update([0, 0, 1000], &mut log), Some(MotionEvent::Tilted));
+        assert_eq!(restarted.shocked([300, 0, 1000], &mut log), MotionEvent::Shock(SHOCK_MG));
+    }
+}
@@ -0,0 +1,911 @@
+use crate::alarm::AlarmProfile;
+use crate::crc::crc32;
+use crate::errors::{ErrorCode, PackedErrors};
+use crate::logger::PauseReason;
+use crate::timestamp::Timestamp;
+
+/// Upper limits of the time-in-band histogram bands, °C. Each band includes its upper limit,
+/// matching the alarm thresholds, and the last band is everything above the last limit.
+pub const BAND_LIMITS_CELSIUS: [f32; 4] = [-0.5, 2.0, 8.0, 15.0];
+/// Number of time-in-band histogram bands.
+pub const BANDS: usize = BAND_LIMITS_CELSIUS.len() + 1;
+/// Most temperature channels one aggregator handles: vaccine, ambient and two extra probes.
+pub const MAX_CHANNELS: usize = 4;
+/// Channels beyond the vaccine and ambient ones, each kept in a record's `probes`.
+pub const PROBE_SLOTS: usize = MAX_CHANNELS - 2;
+/// Layout version written by `AggregationRecord::to_bytes`.
+///
+/// New versions only append fields, so records stored by older firmware decode with defaults
+/// for the missing fields, and records from newer firmware decode without the fields it added.
+pub const RECORD_VERSION: u8 = 5;
+/// Size of a serialized `AggregationRecord`: version, length, then little-endian words.
+pub const AGGREGATION_RECORD_LEN: usize = 2 + RECORD_WORDS * 4;
+const _: () = assert!(AGGREGATION_RECORD_LEN <= u8::MAX as usize); // It goes in the length byte.
+// Words in a version 1 record, the least any version has.
+const RECORD_V1_WORDS: usize = 19 + BANDS;
+// Words in a `RECORD_VERSION` record.
+const RECORD_WORDS: usize = RECORD_V1_WORDS + 5 + PROBE_SLOTS * PROBE_WORDS;
+// Words per entry of `AggregationRecord::probes`.
+const PROBE_WORDS: usize = 9;
+
+/// How a serialized record word is to be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FieldType {
+    U32,
+    F32, // IEEE 754 single precision.
+    Bitmap, // Flags, see the field's definition.
+}
+
+impl FieldType {
+    pub fn name(self) -> &'static str {
+        match self {
+            FieldType::U32 => "u32",
+            FieldType::F32 => "f32",
+            FieldType::Bitmap => "bitmap",
+        }
+    }
+}
+
+/// One word of a serialized `AggregationRecord`, and how to read and write it.
+#[derive(Clone, Copy)]
+pub struct RecordField {
+    pub name: &'static str,
+    pub field_type: FieldType,
+    pub unit: &'static str, // Empty for counts and bitmaps.
+    pub scale: f32, // The value in `unit` is the stored value times this.
+    pub since_version: u8, // First `RECORD_VERSION` with the field.
+    pub get: fn(&AggregationRecord) -> u32,
+    pub set: fn(&mut AggregationRecord, u32),
+}
+
+const fn field(name: &'static str, field_type: FieldType, unit: &'static str, get: fn(&AggregationRecord) -> u32, set: fn(&mut AggregationRecord, u32)) -> RecordField {
+    RecordField { name, field_type, unit, scale: 1.0, since_version: 1, get, set }
+}
+
+impl RecordField {
+    // The field was added in `version`.
+    const fn since(self, version: u8) -> Self {
+        Self { since_version: version, ..self }
+    }
+}
+
+/// The words of a serialized `AggregationRecord`, in order. Both the serializer and the data
+/// dictionary are generated from this, so they can't disagree.
+pub const RECORD_FIELDS: [RecordField; RECORD_WORDS] = {
+    use FieldType::{Bitmap, F32, U32};
+    [
+        field("start", U32, "s", |r| r.start.seconds, |r, v| r.start.seconds = v), // Since the epoch.
+        field("tvc_seconds", U32, "s", |r| r.tvc_seconds, |r, v| r.tvc_seconds = v),
+        field("tvc_integral", F32, "degC*s", |r| r.tvc_integral.to_bits(), |r, v| r.tvc_integral = f32::from_bits(v)),
+        field("tamb_integral", F32, "degC*s", |r| r.tamb_integral.to_bits(), |r, v| r.tamb_integral = f32::from_bits(v)),
+        field("tvc_min", F32, "degC", |r| r.tvc_min.to_bits(), |r, v| r.tvc_min = f32::from_bits(v)),
+        field("tvc_max", F32, "degC", |r| r.tvc_max.to_bits(), |r, v| r.tvc_max = f32::from_bits(v)),
+        field("tamb_min", F32, "degC", |r| r.tamb_min.to_bits(), |r, v| r.tamb_min = f32::from_bits(v)),
+        field("tamb_max", F32, "degC", |r| r.tamb_max.to_bits(), |r, v| r.tamb_max = f32::from_bits(v)),
+        field("high_seconds", U32, "s", |r| r.high_seconds, |r, v| r.high_seconds = v),
+        field("low_seconds", U32, "s", |r| r.low_seconds, |r, v| r.low_seconds = v),
+        field("high_alarm_seconds", U32, "s", |r| r.high_alarm_seconds, |r, v| r.high_alarm_seconds = v),
+        field("low_alarm_seconds", U32, "s", |r| r.low_alarm_seconds, |r, v| r.low_alarm_seconds = v),
+        field("door_openings", U32, "", |r| r.door_openings, |r, v| r.door_openings = v),
+        field("door_open_seconds", U32, "s", |r| r.door_open_seconds, |r, v| r.door_open_seconds = v),
+        field("power_off_seconds", U32, "s", |r| r.power_off_seconds, |r, v| r.power_off_seconds = v),
+        field("logger_errors", Bitmap, "", |r| r.logger_errors.as_u32(), |r, v| r.logger_errors = PackedErrors::from_u32(v)),
+        field("differential_max", F32, "degC", |r| r.differential_max.to_bits(), |r, v| r.differential_max = f32::from_bits(v)),
+        field("band0_seconds", U32, "s", |r| r.band_seconds[0], |r, v| r.band_seconds[0] = v),
+        field("band1_seconds", U32, "s", |r| r.band_seconds[1], |r, v| r.band_seconds[1] = v),
+        field("band2_seconds", U32, "s", |r| r.band_seconds[2], |r, v| r.band_seconds[2] = v),
+        field("band3_seconds", U32, "s", |r| r.band_seconds[3], |r, v| r.band_seconds[3] = v),
+        field("band4_seconds", U32, "s", |r| r.band_seconds[4], |r, v| r.band_seconds[4] = v),
+        field("paused_seconds", U32, "s", |r| r.paused_seconds, |r, v| r.paused_seconds = v),
+        field("pause_reasons", Bitmap, "", |r| r.pause_reasons, |r, v| r.pause_reasons = v),
+        field("tvc_quality", Bitmap, "", |r| r.tvc_quality, |r, v| r.tvc_quality = v).since(2),
+        field("tamb_quality", Bitmap, "", |r| r.tamb_quality, |r, v| r.tamb_quality = v).since(2),
+        field("kind", U32, "", |r| r.kind as u32, |r, v| r.kind = RecordKind::from_u32(v)).since(3), // `RecordKind`.
+        field("probe0_channel", U32, "", |r| Channel::code(r.probes[0].channel), |r, v| r.probes[0].channel = Channel::from_code(v)).since(4),
+        field("probe0_seconds", U32, "s", |r| r.probes[0].record.seconds, |r, v| r.probes[0].record.seconds = v).since(4),
+        field("probe0_integral", F32, "degC*s", |r| r.probes[0].record.integral.to_bits(), |r, v| r.probes[0].record.integral = f32::from_bits(v)).since(4),
+        field("probe0_min", F32, "degC", |r| r.probes[0].record.min.to_bits(), |r, v| r.probes[0].record.min = f32::from_bits(v)).since(4),
+        field("probe0_max", F32, "degC", |r| r.probes[0].record.max.to_bits(), |r, v| r.probes[0].record.max = f32::from_bits(v)).since(4),
+        field("probe0_high_seconds", U32, "s", |r| r.probes[0].record.high_seconds, |r, v| r.probes[0].record.high_seconds = v).since(4),
+        field("probe0_low_seconds", U32, "s", |r| r.probes[0].record.low_seconds, |r, v| r.probes[0].record.low_seconds = v).since(4),
+        field("probe0_high_alarm_seconds", U32, "s", |r| r.probes[0].record.high_alarm_seconds, |r, v| r.probes[0].record.high_alarm_seconds = v).since(4),
+        field("probe0_low_alarm_seconds", U32, "s", |r| r.probes[0].record.low_alarm_seconds, |r, v| r.probes[0].record.low_alarm_seconds = v).since(4),
+        field("probe1_channel", U32, "", |r| Channel::code(r.probes[1].channel), |r, v| r.probes[1].channel = Channel::from_code(v)).since(4),
+        field("probe1_seconds", U32, "s", |r| r.probes[1].record.seconds, |r, v| r.probes[1].record.seconds = v).since(4),
+        field("probe1_integral", F32, "degC*s", |r| r.probes[1].record.integral.to_bits(), |r, v| r.probes[1].record.integral = f32::from_bits(v)).since(4),
+        field("probe1_min", F32, "degC", |r| r.probes[1].record.min.to_bits(), |r, v| r.probes[1].record.min = f32::from_bits(v)).since(4),
+        field("probe1_max", F32, "degC", |r| r.probes[1].record.max.to_bits(), |r, v| r.probes[1].record.max = f32::from_bits(v)).since(4),
+        field("probe1_high_seconds", U32, "s", |r| r.probes[1].record.high_seconds, |r, v| r.probes[1].record.high_seconds = v).since(4),
+        field("probe1_low_seconds", U32, "s", |r| r.probes[1].record.low_seconds, |r, v| r.probes[1].record.low_seconds = v).since(4),
+        field("probe1_high_alarm_seconds", U32, "s", |r| r.probes[1].record.high_alarm_seconds, |r, v| r.probes[1].record.high_alarm_seconds = v).since(4),
+        field("probe1_low_alarm_seconds", U32, "s", |r| r.probes[1].record.low_alarm_seconds, |r, v| r.probes[1].record.low_alarm_seconds = v).since(4),
+        field("motion_events", U32, "", |r| r.motion_events, |r, v| r.motion_events = v).since(4),
+        field("door_alarm_seconds", U32, "s", |r| r.door_alarm_seconds, |r, v| r.door_alarm_seconds = v).since(5),
+    ]
+};
+const _: () = assert!(PROBE_SLOTS == 2, "RECORD_FIELDS has fields for two probes");
+
+/// What a record covers. Older records, without the field, are all `Period`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RecordKind {
+    #[default]
+    Period = 0, // One record period, as aggregated.
+    DaySummary = 1, // A UTC day of records, merged by compaction.
+}
+
+impl RecordKind {
+    /// The kind stored as `value`. Kinds added by newer firmware read as `Period`.
+    pub fn from_u32(value: u32) -> Self {
+        match value {
+            1 => RecordKind::DaySummary,
+            _ => RecordKind::Period,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RecordKind::Period => "period",
+            RecordKind::DaySummary => "day_summary",
+        }
+    }
+}
+
+/// Summary of one record period, with temperatures integrated over time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AggregationRecord {
+    pub start: Timestamp,
+    pub tvc_seconds: u32, // Time covered by temperature readings.
+    pub tvc_integral: f32, // Vaccine temperature × seconds, °C·s.
+    pub tamb_integral: f32, // Ambient temperature × seconds, °C·s.
+    pub tvc_min: f32,
+    pub tvc_max: f32,
+    pub tamb_min: f32,
+    pub tamb_max: f32,
+    pub differential_max: f32, // Largest TAMB − TVC, for judging insulation.
+    pub high_seconds: u32, // TVC above the profile's high threshold.
+    pub low_seconds: u32, // TVC at or below the profile's low threshold.
+    pub high_alarm_seconds: u32, // Part of `high_seconds` after the alarm delay.
+    pub low_alarm_seconds: u32, // Part of `low_seconds` after the alarm delay.
+    pub door_openings: u32,
+    pub door_open_seconds: u32,
+    pub power_off_seconds: u32,
+    pub paused_seconds: u32, // Logging paused, so not counted as missing readings.
+    pub pause_reasons: u32, // Bitmap of `PauseReason::mask` for pauses during the period.
+    pub logger_errors: PackedErrors, // Faults reported during the period.
+    pub band_seconds: [u32; BANDS], // Time with the TVC in each band of `BAND_LIMITS_CELSIUS`.
+    pub tvc_quality: u32, // Bitmap of `SampleFlag::mask` over the TVC readings used.
+    pub tamb_quality: u32, // Bitmap of `SampleFlag::mask` over the TAMB readings used.
+    pub kind: RecordKind,
+    pub probes: [ProbeRecord; PROBE_SLOTS], // The extra channels after TVC and TAMB, in channel order.
+    pub motion_events: u32, // Shocks and tilts of the device.
+    pub door_alarm_seconds: u32, // Part of `door_open_seconds` after the profile's door delay, whether or not the alarm sounded.
+}
+
+/// An extra channel's part of an `AggregationRecord`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProbeRecord {
+    pub channel: Option<Channel>, // None for an unused slot.
+    pub record: ChannelRecord,
+}
+
+impl AggregationRecord {
+    pub fn new(start: Timestamp) -> Self {
+        Self {
+            start,
+            tvc_seconds: 0,
+            tvc_integral: 0.0,
+            tamb_integral: 0.0,
+            tvc_min: 0.0,
+            tvc_max: 0.0,
+            tamb_min: 0.0,
+            tamb_max: 0.0,
+            differential_max: 0.0,
+            high_seconds: 0,
+            low_seconds: 0,
+            high_alarm_seconds: 0,
+            low_alarm_seconds: 0,
+            door_openings: 0,
+            door_open_seconds: 0,
+            power_off_seconds: 0,
+            paused_seconds: 0,
+            pause_reasons: 0,
+            logger_errors: PackedErrors::default(),
+            band_seconds: [0; BANDS],
+            tvc_quality: 0,
+            tamb_quality: 0,
+            kind: RecordKind::Period,
+            probes: [ProbeRecord::default(); PROBE_SLOTS],
+            motion_events: 0,
+            door_alarm_seconds: 0,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; AGGREGATION_RECORD_LEN] {
+        let mut bytes = [0u8; AGGREGATION_RECORD_LEN];
+        (bytes[0], bytes[1]) = (RECORD_VERSION, AGGREGATION_RECORD_LEN as u8);
+        for (chunk, field) in bytes[2..].chunks_exact_mut(4).zip(&RECORD_FIELDS) {
+            chunk.copy_from_slice(&(field.get)(self).to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decode a record written by `to_bytes` of any version. Returns None if the bytes aren't a record.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (&version, &len) = (bytes.first()?, bytes.get(1)?);
+        let words = bytes.get(2..usize::from(len))?;
+        if version == 0 || words.len() < RECORD_V1_WORDS * 4 {
+            return None;
+        }
+        // Words past the end of an older record read as zero, which is each field's default.
+        let mut words = words.chunks_exact(4).map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        let mut record = Self::new(Timestamp { seconds: 0 });
+        for field in &RECORD_FIELDS {
+            (field.set)(&mut record, words.next().unwrap_or(0));
+        }
+        Some(record)
+    }
+
+    /// Roll a later record up into this one, e.g. to summarize a day of records.
+    /// Extremes are only taken from records with readings.
+    pub fn merge(&mut self, other: &AggregationRecord) {
+        if other.tvc_seconds > 0 {
+            if self.tvc_seconds > 0 {
+                self.tvc_min = self.tvc_min.min(other.tvc_min);
+                self.tvc_max = self.tvc_max.max(other.tvc_max);
+                self.tamb_min = self.tamb_min.min(other.tamb_min);
+                self.tamb_max = self.tamb_max.max(other.tamb_max);
+                self.differential_max = self.differential_max.max(other.differential_max);
+            } else {
+                (self.tvc_min, self.tvc_max) = (other.tvc_min, other.tvc_max);
+                (self.tamb_min, self.tamb_max) = (other.tamb_min, other.tamb_max);
+                self.differential_max = other.differential_max;
+            }
+        }
+        self.tvc_seconds += other.tvc_seconds;
+        self.tvc_integral += other.tvc_integral;
+        self.tamb_integral += other.tamb_integral;
+        self.high_seconds += other.high_seconds;
+        self.low_seconds += other.low_seconds;
+        self.high_alarm_seconds += other.high_alarm_seconds;
+        self.low_alarm_seconds += other.low_alarm_seconds;
+        self.door_openings += other.door_openings;
+        self.motion_events += other.motion_events;
+        self.door_open_seconds += other.door_open_seconds;
+        self.door_alarm_seconds += other.door_alarm_seconds;
+        self.power_off_seconds += other.power_off_seconds;
+        self.paused_seconds += other.paused_seconds;
+        self.pause_reasons |= other.pause_reasons;
+        for code in other.logger_errors.iter() {
+            self.logger_errors.push(code);
+        }
+        for (seconds, other) in self.band_seconds.iter_mut().zip(other.band_seconds) {
+            *seconds += other;
+        }
+        self.tvc_quality |= other.tvc_quality;
+        self.tamb_quality |= other.tamb_quality;
+        for (probe, other) in self.probes.iter_mut().zip(&other.probes) {
+            if probe.channel.is_none() {
+                probe.channel = other.channel;
+            }
+            if probe.channel == other.channel {
+                probe.record.merge(&other.record);
+            }
+        }
+    }
+
+    /// True if a temperature or door alarm was raised or the power was off during the period.
+    pub fn has_alarm(&self) -> bool {
+        self.high_alarm_seconds > 0 || self.low_alarm_seconds > 0 || self.door_alarm_seconds > 0 || self.power_off_seconds > 0
+    }
+
+    /// Time-weighted mean of TVC, or None without readings.
+    pub fn tvc_average(&self) -> Option<f32> {
+        time_average(self.tvc_integral, self.tvc_seconds)
+    }
+
+    /// Time-weighted mean of TAMB over the same intervals as TVC, or None without readings.
+    pub fn tamb_average(&self) -> Option<f32> {
+        time_average(self.tamb_integral, self.tvc_seconds)
+    }
+
+    /// Time-weighted mean of TAMB − TVC, or None without readings.
+    /// Both channels are integrated over the same intervals, so this is their difference of means.
+    pub fn differential_mean(&self) -> Option<f32> {
+        time_average(self.tamb_integral - self.tvc_integral, self.tvc_seconds)
+    }
+}
+
+// Mean of a °C·s integral over `seconds`. None without readings, or if the integral is missing,
+// e.g. read back from erased flash as NaN.
+fn time_average(integral: f32, seconds: u32) -> Option<f32> {
+    let average = integral / seconds as f32;
+    (seconds > 0 && average.is_finite()).then_some(average)
+}
+
+/// Position of a temperature probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Channel {
+    Vaccine,
+    Ambient,
+    Evaporator,
+    Condenser,
+    SecondaryVaccine, // The backup of a redundant pair of vaccine probes, see `ProbeCrossCheck`.
+}
+
+impl Channel {
+    const ALL: [Channel; 5] = [Channel::Vaccine, Channel::Ambient, Channel::Evaporator, Channel::Condenser, Channel::SecondaryVaccine];
+
+    // As stored in a record: 0 for none, else one more than the position in `ALL`.
+    fn code(channel: Option<Channel>) -> u32 {
+        channel.map_or(0, |channel| channel as u32 + 1)
+    }
+
+    fn from_code(code: u32) -> Option<Channel> {
+        Self::ALL.get(code.checked_sub(1)? as usize).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Vaccine => "vaccine",
+            Channel::Ambient => "ambient",
+            Channel::Evaporator => "evaporator",
+            Channel::Condenser => "condenser",
+            Channel::SecondaryVaccine => "secondary_vaccine",
+        }
+    }
+}
+
+/// One channel's temperatures over a record, integrated over time.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelRecord {
+    pub seconds: u32, // Time covered by readings.
+    pub integral: f32, // Temperature × seconds, °C·s.
+    pub min: f32,
+    pub max: f32,
+    pub high_seconds: u32, // Above the channel profile's high threshold.
+    pub low_seconds: u32, // At or below the channel profile's low threshold.
+    pub high_alarm_seconds: u32, // Part of `high_seconds` after the alarm delay.
+    pub low_alarm_seconds: u32, // Part of `low_seconds` after the alarm delay.
+}
+
+impl ChannelRecord {
+    /// Roll a later record of the same channel up into this one.
+    pub fn merge(&mut self, other: &ChannelRecord) {
+        if other.seconds > 0 {
+            (self.min, self.max) = if self.seconds > 0 { (self.min.min(other.min), self.max.max(other.max)) } else { (other.min, other.max) };
+        }
+        self.seconds += other.seconds;
+        self.integral += other.integral;
+        self.high_seconds += other.high_seconds;
+        self.low_seconds += other.low_seconds;
+        self.high_alarm_seconds += other.high_alarm_seconds;
+        self.low_alarm_seconds += other.low_alarm_seconds;
+    }
+
+    /// Time-weighted average, or None without readings.
+    pub fn average(&self) -> Option<f32> {
+        time_average(self.integral, self.seconds)
+    }
+}
+
+/// Integrates one channel, and tracks its excursions if it has an alarm profile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelAggregator {
+    channel: Channel,
+    profile: Option<AlarmProfile>,
+    record: ChannelRecord,
+    integral_error: f32, // Low-order part of `record.integral` lost to rounding, °C·s.
+    has_extremes: bool, // Whether the minimum and maximum have been set.
+    high_run_seconds: u32, // Length of the current high excursion so far.
+    low_run_seconds: u32, // Length of the current low excursion so far.
+}
+
+impl ChannelAggregator {
+    pub fn new(channel: Channel, profile: Option<AlarmProfile>) -> Self {
+        Self { channel, profile, record: ChannelRecord::default(), integral_error: 0.0, has_extremes: false, high_run_seconds: 0, low_run_seconds: 0 }
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    pub fn profile(&self) -> Option<AlarmProfile> {
+        self.profile
+    }
+
+    /// The channel's part of the record in progress.
+    pub fn record(&self) -> &ChannelRecord {
+        &self.record
+    }
+
+    /// Record a reading for the minimum and maximum.
+    pub fn add_sample(&mut self, value: f32) {
+        let record = &mut self.record;
+        if self.has_extremes {
+            record.min = record.min.min(value);
+            record.max = record.max.max(value);
+        } else {
+            (record.min, record.max) = (value, value);
+            self.has_extremes = true;
+        }
+    }
+
+    /// Integrate a reading held for `seconds`.
+    pub fn add_held(&mut self, value: f32, seconds: u32) {
+        if seconds > 0 {
+            self.add_sample(value);
+        }
+        let record = &mut self.record;
+        record.seconds += seconds;
+        compensated_add(&mut record.integral, &mut self.integral_error, value * seconds as f32);
+        let Some(profile) = self.profile else {
+            return;
+        };
+        if profile.is_high(value) {
+            record.high_seconds += seconds;
+            record.high_alarm_seconds += excursion_alarm_seconds(&mut self.high_run_seconds, seconds, profile.high_delay_seconds);
+        } else {
+            self.high_run_seconds = 0;
+        }
+        if profile.is_low(value) {
+            record.low_seconds += seconds;
+            record.low_alarm_seconds += excursion_alarm_seconds(&mut self.low_run_seconds, seconds, profile.low_delay_seconds);
+        } else {
+            self.low_run_seconds = 0;
+        }
+    }
+
+    /// The readings stopped, so any excursion in progress ends.
+    pub fn end_excursions(&mut self) {
+        self.high_run_seconds = 0;
+        self.low_run_seconds = 0;
+    }
+
+    /// Lengths of the high and low excursions in progress, in seconds.
+    pub fn excursion_runs(&self) -> (u32, u32) {
+        (self.high_run_seconds, self.low_run_seconds)
+    }
+
+    /// Carry on excursions saved with `excursion_runs`, e.g. across a restart.
+    pub fn resume_excursions(&mut self, high_run_seconds: u32, low_run_seconds: u32) {
+        self.high_run_seconds = high_run_seconds;
+        self.low_run_seconds = low_run_seconds;
+    }
+
+    // The accumulated state, for `TemperatureAggregator::checksum`.
+    fn words(&self) -> [u32; 12] {
+        let record = &self.record;
+        [
+            record.seconds,
+            record.integral.to_bits(),
+            record.min.to_bits(),
+            record.max.to_bits(),
+            record.high_seconds,
+            record.low_seconds,
+            record.high_alarm_seconds,
+            record.low_alarm_seconds,
+            self.integral_error.to_bits(),
+            u32::from(self.has_extremes),
+            self.high_run_seconds,
+            self.low_run_seconds,
+        ]
+    }
+
+    // Complete the channel's part of the record. Excursions carry over into the next one.
+    fn finalize(&mut self) -> ChannelRecord {
+        self.has_extremes = false;
+        self.integral_error = 0.0;
+        core::mem::take(&mut self.record)
+    }
+}
+
+// Kahan summation: add `value` to `sum`, carrying the rounding error in `error` into the next addition.
+// A plain f32 sum over a long record of short readings drifts by tenths of a degree.
+fn compensated_add(sum: &mut f32, error: &mut f32, value: f32) {
+    let corrected = value - *error;
+    let total = *sum + corrected;
+    *error = (total - *sum) - corrected;
+    *sum = total;
+}
+
+/// Accumulates one `AggregationRecord` at a time.
+///
+/// Each temperature channel is integrated separately, with its own alarm profile if it has one.
+/// The vaccine and ambient channels always come first and fill the record's TVC and TAMB fields;
+/// extra probes, e.g. in a large cold room, are added with `add_channel`.
+///
+/// Excursion durations carry over from one record to the next, so alarm delays
+/// apply to excursions that span record boundaries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemperatureAggregator {
+    channels: [ChannelAggregator; MAX_CHANNELS], // Readings are given in this order.
+    channel_count: usize,
+    record: AggregationRecord,
+    samples: u32, // Readings taken during the record.
+    has_differential: bool, // Whether `differential_max` has been set.
+    door_run_seconds: u32, // Time the door has been open so far, across records.
+}
+
+impl TemperatureAggregator {
+    /// An aggregator for the vaccine channel, alarmed with `profile`, and the ambient channel.
+    pub fn new(start: Timestamp, profile: AlarmProfile) -> Self {
+        let mut channels = [ChannelAggregator::new(Channel::Vaccine, None); MAX_CHANNELS];
+        channels[0] = ChannelAggregator::new(Channel::Vaccine, Some(profile));
+        channels[1] = ChannelAggregator::new(Channel::Ambient, None);
+        Self { channels, channel_count: 2, record: AggregationRecord::new(start), samples: 0, has_differential: false, door_run_seconds: 0 }
+    }
+
+    /// Add a channel after the existing ones. Returns false if there are already `MAX_CHANNELS`.
+    pub fn add_channel(&mut self, channel: Channel, profile: Option<AlarmProfile>) -> bool {
+        let Some(slot) = self.channels.get_mut(self.channel_count) else {
+            return false;
+        };
+        *slot = ChannelAggregator::new(channel, profile);
+        self.channel_count += 1;
+        true
+    }
+
+    /// The channels, in the order their readings are given.
+    pub fn channels(&self) -> &[ChannelAggregator] {
+        &self.channels[..self.channel_count]
+    }
+
+    /// Record a reading for the minimum and maximum.
+    pub fn add_sample(&mut self, tvc: f32, tamb: f32) {
+        self.add_readings(&[tvc, tamb]);
+    }
+
+    /// Record one reading per channel, in channel order, for the minimums and maximums.
+    /// Channels without a reading are left out.
+    pub fn add_readings(&mut self, values: &[f32]) {
+        for (channel, &value) in self.channels[..self.channel_count].iter_mut().zip(values) {
+            channel.add_sample(value);
+        }
+        self.include_differential(values);
+        self.samples += 1;
+        self.update_record();
+    }
+
+    /// Integrate a reading held for `seconds`. A reading held over from the previous record
+    /// also counts towards this record's minimum and maximum.
+    pub fn add_held(&mut self, tvc: f32, tamb: f32, seconds: u32) {
+        self.add_held_readings(&[tvc, tamb], seconds);
+    }
+
+    /// Integrate one reading per channel, in channel order, held for `seconds`.
+    pub fn add_held_readings(&mut self, values: &[f32], seconds: u32) {
+        for (channel, &value) in self.channels[..self.channel_count].iter_mut().zip(values) {
+            channel.add_held(value, seconds);
+        }
+        if seconds > 0 {
+            self.include_differential(values);
+        }
+        if let Some(&tvc) = values.first() {
+            self.record.band_seconds[band(tvc)] += seconds;
+        }
+        self.update_record();
+    }
+
+    /// The readings stopped, so any excursion in progress ends.
+    pub fn end_excursions(&mut self) {
+        for channel in &mut self.channels {
+            channel.end_excursions();
+        }
+    }
+
+    /// Lengths of the vaccine channel's high and low excursions in progress, in seconds.
+    pub fn excursion_runs(&self) -> (u32, u32) {
+        self.channels[0].excursion_runs()
+    }
+
+    /// Carry on the vaccine channel's excursions saved with `excursion_runs`.
+    pub fn resume_excursions(&mut self, high_run_seconds: u32, low_run_seconds: u32) {
+        self.channels[0].resume_excursions(high_run_seconds, low_run_seconds);
+    }
+
+    pub fn door_opened(&mut self) {
+        self.record.door_openings += 1;
+    }
+
+    /// Count a shock or tilt of the device.
+    pub fn moved(&mut self) {
+        self.record.motion_events += 1;
+    }
+
+    /// The door has been open for `seconds` more. Time past the vaccine profile's door delay is
+    /// door alarm time.
+    pub fn add_door_open(&mut self, seconds: u32) {
+        self.record.door_open_seconds += seconds;
+        if let Some(profile) = self.channels[0].profile {
+            self.record.door_alarm_seconds += excursion_alarm_seconds(&mut self.door_run_seconds, seconds, profile.door_seconds);
+        }
+    }
+
+    pub fn door_closed(&mut self) {
+        self.door_run_seconds = 0;
+    }
+
+    /// Time the door has been open so far, in seconds.
+    pub fn door_run_seconds(&self) -> u32 {
+        self.door_run_seconds
+    }
+
+    /// Carry on a door opening saved with `door_run_seconds`.
+    pub fn resume_door_run(&mut self, door_run_seconds: u32) {
+        self.door_run_seconds = door_run_seconds;
+    }
+
+    pub fn add_power_off(&mut self, seconds: u32) {
+        self.record.power_off_seconds += seconds;
+    }
+
+    /// Logging was paused for `reason` for `seconds`.
+    pub fn add_paused(&mut self, reason: PauseReason, seconds: u32) {
+        self.record.paused_seconds += seconds;
+        self.record.pause_reasons |= reason.mask();
+    }
+
+    /// Note how the TVC and TAMB readings in use were obtained, as `TemperatureSample` flags.
+    pub fn add_quality(&mut self, tvc: u8, tamb: u8) {
+        self.record.tvc_quality |= u32::from(tvc);
+        self.record.tamb_quality |= u32::from(tamb);
+    }
+
+    pub fn report_error(&mut self, code: ErrorCode) {
+        self.record.logger_errors.push(code);
+    }
+
+    /// The record in progress, for inspection before it is finalized.
+    pub fn current(&self) -> &AggregationRecord {
+        &self.record
+    }
+
+    /// Readings taken since the record started.
+    pub fn sample_count(&self) -> u32 {
+        self.samples
+    }
+
+    /// Returns true if nothing has been recorded since the record started.
+    pub fn is_empty(&self) -> bool {
+        self.samples == 0
+            && self.record == AggregationRecord::new(self.record.start)
+            && self.channels().iter().all(|channel| *channel.record() == ChannelRecord::default())
+    }
+
+    /// CRC-32 over everything accumulated so far, to catch the state being corrupted in RAM, e.g.
+    /// by a bit flip or a stack overflow, before it ends up in a record.
+    pub fn checksum(&self) -> u32 {
+        let header = [self.channel_count as u32, self.samples, u32::from(self.has_differential), self.door_run_seconds];
+        let channels = self.channels.iter().flat_map(|channel| channel.words());
+        let crc = header.into_iter().chain(channels).fold(0, |crc, word| crc32(crc, &word.to_le_bytes()));
+        crc32(crc, &self.record.to_bytes())
+    }
+
+    /// Complete the current record, with the extra channels in its `probes`, and start the next
+    /// one at `next_start`.
+    pub fn finalize(&mut self, next_start: Timestamp) -> AggregationRecord {
+        self.samples = 0;
+        self.has_differential = false;
+        for channel in &mut self.channels {
+            channel.finalize();
+        }
+        core::mem::replace(&mut self.record, AggregationRecord::new(next_start))
+    }
+
+    fn include_differential(&mut self, values: &[f32]) {
+        let [tvc, tamb, ..] = *values else {
+            return;
+        };
+        let record = &mut self.record;
+        record.differential_max = if self.has_differential { record.differential_max.max(tamb - tvc) } else { tamb - tvc };
+        self.has_differential = true;
+    }
+
+    // Copy the vaccine and ambient channels into the record's TVC and TAMB fields, and the rest into its probes.
+    fn update_record(&mut self) {
+        let (vaccine, ambient) = (self.channels[0].record, self.channels[1].record);
+        let record = &mut self.record;
+        (record.tvc_seconds, record.tvc_integral, record.tvc_min, record.tvc_max) = (vaccine.seconds, vaccine.integral, vaccine.min, vaccine.max);
+        (record.tamb_integral, record.tamb_min, record.tamb_max) = (ambient.integral, ambient.min, ambient.max);
+        (record.high_seconds, record.low_seconds) = (vaccine.high_seconds, vaccine.low_seconds);
+        (record.high_alarm_seconds, record.low_alarm_seconds) = (vaccine.high_alarm_seconds, vaccine.low_alarm_seconds);
+        for (probe, channel) in record.probes.iter_mut().zip(&self.channels[2..self.channel_count]) {
+            *probe = ProbeRecord { channel: Some(channel.channel), record: channel.record };
+        }
+    }
+}
+
+// Index of the time-in-band histogram band that `tvc` falls in.
+fn band(tvc: f32) -> usize {
+    BAND_LIMITS_CELSIUS.iter().filter(|&&limit| tvc > limit).count()
+}
+
+// Extend an excursion by `seconds` and return how many of them are past `delay`.
+fn excursion_alarm_seconds(run_seconds: &mut u32, seconds: u32, delay: u32) -> u32 {
+    let before = *run_seconds;
+    *run_seconds = before.saturating_add(seconds);
+    run_seconds.saturating_sub(before.max(delay))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample::SampleFlag;
+
+    #[test]
+    fn test_integration_and_extremes() {
+        let mut aggregator = TemperatureAggregator::new(Timestamp { seconds: 0 }, AlarmProfile::FRIDGE);
+        assert!(aggregator.is_empty());
+        aggregator.add_sample(4.0, 20.0);
+        aggregator.add_held(4.0, 20.0, 300);
+        aggregator.add_sample(6.0, 22.0);
+        aggregator.add_held(6.0, 22.0, 600);
+        aggregator.door_opened();
+        aggregator.add_door_open(30);
+        let record = aggregator.finalize(Timestamp { seconds: 900 });
+        assert_eq!(record.tvc_seconds, 900);
+        assert_eq!(record.tvc_integral, 4.0 * 300.0 + 6.0 * 600.0);
+        assert_eq!((record.tvc_min, record.tvc_max, record.tamb_min, record.tamb_max), (4.0, 6.0, 20.0, 22.0));
+        assert_eq!((record.door_openings, record.door_open_seconds), (1, 30));
+        assert_eq!(record.band_seconds, [0, 0, 900, 0, 0]);
+        assert_eq!(record.differential_max, 16.0);
+        assert_eq!(record.differential_mean(), Some((16.0 * 300.0 + 16.0 * 600.0) / 900.0));
+        assert_eq!(record.tvc_average(), Some(4800.0 / 900.0));
+        assert_eq!(record.tamb_average(), Some(19200.0 / 900.0));
+        assert!(aggregator.is_empty());
+        let empty = AggregationRecord::new(Timestamp { seconds: 900 });
+        assert_eq!((empty.tvc_average(), empty.tamb_average(), empty.differential_mean()), (None, None, None));
+        let erased = AggregationRecord { tamb_integral: f32::from_bits(u32::MAX), ..record };
+        assert_eq!((erased.tvc_average(), erased.tamb_average(), erased.differential_mean()), (Some(4800.0 / 900.0), None, None));
+    }
+
+    #[test]
+    fn test_alarm_delay_spans_records() {
+        let mut aggregator = TemperatureAggregator::new(Timestamp { seconds: 0 }, AlarmProfile::FRIDGE);
+        aggregator.add_held(-1.0, 20.0, 2400);
+        let first = aggregator.finalize(Timestamp { seconds: 2400 });
+        assert_eq!((first.low_seconds, first.low_alarm_seconds), (2400, 0));
+        aggregator.add_held(-1.0, 20.0, 2400);
+        let second = aggregator.finalize(Timestamp { seconds: 4800 });
+        assert_eq!((second.low_seconds, second.low_alarm_seconds), (2400, 1200));
+        // Back in range, so the next excursion starts its delay again.
+        aggregator.add_held(5.0, 20.0, 60);
+        aggregator.add_held(9.0, 20.0, 60);
+        let third = aggregator.finalize(Timestamp { seconds: 4920 });
+        assert_eq!((third.high_seconds, third.high_alarm_seconds, third.low_seconds), (60, 0, 0));
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut aggregator = TemperatureAggregator::new(Timestamp { seconds: 900 }, AlarmProfile::FRIDGE);
+        aggregator.add_sample(9.5, -3.25);
+        aggregator.add_held(9.5, -3.25, 600);
+        aggregator.door_opened();
+        aggregator.report_error(ErrorCode::SensorFail);
+        let record = aggregator.finalize(Timestamp { seconds: 1800 });
+        assert_eq!(AggregationRecord::from_bytes(&record.to_bytes()), Some(record));
+    }
+
+    #[test]
+    fn test_door_alarm() {
+        let mut aggregator = TemperatureAggregator::new(Timestamp { seconds: 0 }, AlarmProfile::FRIDGE);
+        aggregator.door_opened();
+        aggregator.add_door_open(200);
+        let first = aggregator.finalize(Timestamp { seconds: 900 });
+        // The opening carries on into the next record, past the 5 minute delay.
+        aggregator.add_door_open(400);
+        aggregator.door_closed();
+        aggregator.door_opened();
+        aggregator.add_door_open(100);
+        let second = aggregator.finalize(Timestamp { seconds: 1800 });
+        assert_eq!((first.door_open_seconds, first.door_alarm_seconds, first.has_alarm()), (200, 0, false));
+        assert_eq!((second.door_open_seconds, second.door_alarm_seconds, second.has_alarm()), (500, 300, true));
+    }
+
+    #[test]
+    fn test_record_versions() {
+        let record = AggregationRecord { tvc_seconds: 900, door_openings: 2, ..AggregationRecord::new(Timestamp { seconds: 900 }) };
+        let bytes = record.to_bytes();
+        assert_eq!(bytes[0], RECORD_VERSION);
+        // A newer version with a field appended still decodes.
+        let mut newer = bytes.to_vec();
+        newer.extend_from_slice(&[0xAA; 4]);
+        (newer[0], newer[1]) = (RECORD_VERSION + 1, newer.len() as u8);
+        assert_eq!(AggregationRecord::from_bytes(&newer), Some(record));
+        // An older version decodes with defaults for the fields added since.
+        let quality = AggregationRecord { tvc_quality: SampleFlag::Substituted.mask().into(), ..record };
+        let mut older = quality.to_bytes()[..2 + RECORD_V1_WORDS * 4].to_vec();
+        (older[0], older[1]) = (1, older.len() as u8);
+        assert_eq!(AggregationRecord::from_bytes(&older), Some(record));
+        assert_eq!(AggregationRecord::from_bytes(&bytes[..40]), None); // Cut short.
+        let mut short = bytes;
+        short[1] = 40;
+        assert_eq!(AggregationRecord::from_bytes(&short), None);
+        assert_eq!(AggregationRecord::from_bytes(&[0; AGGREGATION_RECORD_LEN]), None);
+    }
+
+    #[test]
+    fn test_extra_channels() {
+        let mut aggregator = TemperatureAggregator::new(Timestamp { seconds: 0 }, AlarmProfile::FRIDGE);
+        assert!(aggregator.add_channel(Channel::Evaporator, Some(AlarmProfile::FREEZER)));
+        assert!(aggregator.add_channel(Channel::Condenser, None));
+        assert!(!aggregator.add_channel(Channel::Condenser, None)); // Full.
+        aggregator.add_readings(&[5.0, 25.0, -30.0, 40.0]);
+        aggregator.add_held_readings(&[5.0, 25.0, -30.0, 40.0], 600);
+        aggregator.add_held_readings(&[5.0, 25.0, -10.0, 45.0], 300);
+        let evaporator = *aggregator.channels()[2].record();
+        assert_eq!((evaporator.min, evaporator.max, evaporator.high_seconds), (-30.0, -10.0, 300));
+        assert_eq!(evaporator.average(), Some((-30.0 * 600.0 - 10.0 * 300.0) / 900.0));
+        assert_eq!(aggregator.channels()[3].channel().name(), "condenser");
+        assert_eq!(aggregator.channels()[3].record().max, 45.0);
+        let condenser = *aggregator.channels()[3].record();
+        let record = aggregator.finalize(Timestamp { seconds: 900 });
+        assert_eq!((record.tvc_seconds, record.tvc_max, record.tamb_max, record.high_seconds), (900, 5.0, 25.0, 0));
+        assert_eq!(record.probes, [ProbeRecord { channel: Some(Channel::Evaporator), record: evaporator }, ProbeRecord { channel: Some(Channel::Condenser), record: condenser }]);
+        assert_eq!(AggregationRecord::from_bytes(&record.to_bytes()).unwrap().probes, record.probes);
+        assert!(aggregator.is_empty());
+    }
+
+    #[test]
+    fn test_bands() {
+        let bands: Vec<usize> = [-3.0, -0.5, -0.4, 2.0, 8.0, 8.1, 15.0, 30.0].into_iter().map(band).collect();
+        assert_eq!(bands, [0, 0, 1, 1, 2, 3, 3, 4]);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut aggregator = TemperatureAggregator::new(Timestamp { seconds: 0 }, AlarmProfile::FRIDGE);
+        aggregator.add_sample(4.0, 20.0);
+        aggregator.add_held(4.0, 20.0, 900);
+        let first = aggregator.finalize(Timestamp { seconds: 900 });
+        aggregator.add_sample(9.0, 30.0);
+        aggregator.add_held(9.0, 30.0, 900);
+        aggregator.report_error(ErrorCode::SensorFail);
+        let second = aggregator.finalize(Timestamp { seconds: 1800 });
+        let mut day = AggregationRecord::new(Timestamp { seconds: 0 });
+        for record in [first, AggregationRecord::new(Timestamp { seconds: 1800 }), second] {
+            day.merge(&record);
+        }
+        assert_eq!(day.tvc_seconds, 1800);
+        assert_eq!((day.tvc_min, day.tvc_max, day.tamb_min, day.tamb_max), (4.0, 9.0, 20.0, 30.0));
+        assert_eq!((day.differential_max, day.differential_mean()), (21.0, Some(18.5)));
+        assert_eq!((day.high_seconds, day.logger_errors), (900, second.logger_errors));
+        assert_eq!(day.band_seconds, [0, 0, 900, 900, 0]);
+    }
+
+    #[test]
+    fn test_long_record_precision() {
+        // Eight hours of one-second readings, against a double-precision reference.
+        let mut aggregator = TemperatureAggregator::new(Timestamp { seconds: 0 }, AlarmProfile::FRIDGE);
+        let (mut tvc_reference, mut tamb_reference) = (0.0f64, 0.0f64);
+        for second in 0..8 * 3600u32 {
+            let tvc = 4.0 + (second % 97) as f32 * 0.013;
+            let tamb = 24.3 + (second % 61) as f32 * 0.007;
+            aggregator.add_held(tvc, tamb, 1);
+            tvc_reference += f64::from(tvc);
+            tamb_reference += f64::from(tamb);
+        }
+        let record = aggregator.finalize(Timestamp { seconds: 8 * 3600 });
+        let seconds = f64::from(record.tvc_seconds);
+        assert!((f64::from(record.tvc_integral) - tvc_reference).abs() / seconds < 1e-5, "{}", record.tvc_integral);
+        assert!((f64::from(record.tamb_integral) - tamb_reference).abs() / seconds < 1e-5, "{}", record.tamb_integral);
+        // The carried error doesn't leak into the next record.
+        aggregator.add_held(5.0, 20.0, 60);
+        assert_eq!(aggregator.finalize(Timestamp { seconds: 8 * 3600 + 60 }).tvc_integral, 300.0);
+    }
+
+    #[test]
+    fn test_freezer_profile() {
+        let mut aggregator = TemperatureAggregator::new(Timestamp { seconds: 0 }, AlarmProfile::FREEZER);
+        aggregator.add_held(-20.0, 20.0, 600);
+        aggregator.add_held(-10.0, 20.0, 4200);
+        let record = aggregator.finalize(Timestamp { seconds: 4800 });
+        assert_eq!((record.low_seconds, record.high_seconds, record.high_alarm_seconds), (0, 4200, 600));
+    }
+}
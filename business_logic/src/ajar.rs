@@ -0,0 +1,113 @@
+use crate::log::{Log, LogCode};
+use crate::timestamp::Timestamp;
+
+/// Length of the windows over which the TVC rise and compressor duty are measured.
+pub const AJAR_WINDOW_SECONDS: u32 = 30 * 60;
+/// Smallest TVC rise over a window that counts towards a suspected ajar door.
+pub const AJAR_RISE_CELSIUS: f32 = 0.3;
+/// Compressor duty over a window at or above which the cabinet is working unusually hard.
+pub const AJAR_DUTY_PERCENT: u32 = 80;
+/// Consecutive suspicious windows before a door is suspected ajar.
+pub const AJAR_WINDOWS: u8 = 2;
+
+// Start of the window being measured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Window {
+    start: Timestamp,
+    tvc: f32,
+    run_seconds: u32,
+}
+
+/// Flags a door that is probably ajar even though the door switch reads closed.
+///
+/// A door left slightly open lets warm air in faster than the compressor removes it, so the
+/// TVC keeps creeping up while the compressor runs almost continuously. Door switches do fail
+/// in the field, so this is reported separately from the switch-based door alarm.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AjarDetector {
+    window: Option<Window>,
+    suspicious_windows: u8,
+    suspected: bool,
+}
+
+impl AjarDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a TVC reading into account, with the compressor's total run seconds and the door switch.
+    /// Returns true when the door becomes suspected ajar, after logging `SuspectedAjar` with the
+    /// compressor duty in percent over the last window.
+    pub fn update(&mut self, now: Timestamp, tvc: f32, run_seconds: u32, door_open: bool, log: &mut impl Log) -> bool {
+        if door_open {
+            // The switch works, and the door alarm covers an open door.
+            *self = Self::new();
+            return false;
+        }
+        let Some(window) = self.window else {
+            self.window = Some(Window { start: now, tvc, run_seconds });
+            return false;
+        };
+        let elapsed = now.seconds.saturating_sub(window.start.seconds);
+        if elapsed < AJAR_WINDOW_SECONDS {
+            return false;
+        }
+        let duty_percent = run_seconds.saturating_sub(window.run_seconds).saturating_mul(100) / elapsed;
+        self.window = Some(Window { start: now, tvc, run_seconds });
+        if tvc - window.tvc >= AJAR_RISE_CELSIUS && duty_percent >= AJAR_DUTY_PERCENT {
+            self.suspicious_windows = self.suspicious_windows.saturating_add(1);
+        } else {
+            self.suspicious_windows = 0;
+            self.suspected = false;
+        }
+        if self.suspicious_windows < AJAR_WINDOWS || self.suspected {
+            return false;
+        }
+        self.suspected = true;
+        log.warn(LogCode::SuspectedAjar, duty_percent);
+        true
+    }
+
+    pub fn is_suspected(&self) -> bool {
+        self.suspected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::at;
+    use crate::log::{CaptureLog, Level};
+
+    #[test]
+    fn test_creeping_rise_with_high_duty() {
+        let mut detector = AjarDetector::new();
+        let mut log = CaptureLog::default();
+        assert!(!detector.update(at(0), 4.0, 0, false, &mut log));
+        assert!(!detector.update(at(1800), 4.4, 1700, false, &mut log));
+        assert!(detector.update(at(3600), 4.8, 3400, false, &mut log));
+        assert!(!detector.update(at(5400), 5.2, 5200, false, &mut log)); // Reported once.
+        assert!(detector.is_suspected());
+        assert_eq!(log.entries, [(Level::Warn, LogCode::SuspectedAjar, 94)]);
+        // Closing the door properly lets the TVC recover.
+        detector.update(at(7200), 4.5, 6000, false, &mut log);
+        assert!(!detector.is_suspected());
+    }
+
+    #[test]
+    fn test_normal_cycling_and_open_door() {
+        let mut detector = AjarDetector::new();
+        let mut log = CaptureLog::default();
+        // Rising, but the compressor is cycling normally.
+        for (window, tvc) in [4.0, 4.5, 5.0, 5.5].into_iter().enumerate() {
+            let window = window as u32;
+            assert!(!detector.update(at(window * 1800), tvc, window * 900, false, &mut log));
+        }
+        // The switch reports the door open, so the door alarm handles it.
+        let mut detector = AjarDetector::new();
+        for window in 0..4 {
+            assert!(!detector.update(at(window * 1800), 4.0 + window as f32, window * 1800, true, &mut log));
+        }
+        assert!(log.entries.is_empty());
+    }
+}
@@ -0,0 +1,471 @@
+use crate::crc::crc32;
+use crate::escalation::Escalation;
+use crate::timestamp::Timestamp;
+
+/// How long an acknowledgement silences the buzzer.
+pub const SNOOZE_SECONDS: u32 = 30 * 60;
+/// TVC above this raises a high-temperature alarm.
+pub const HIGH_ALARM_CELSIUS: f32 = 8.0;
+/// TVC at or below this raises a freeze alarm.
+pub const FREEZE_ALARM_CELSIUS: f32 = -0.5;
+/// A high excursion must last this long before its time counts as alarm time.
+pub const HIGH_ALARM_DELAY_SECONDS: u32 = 10 * 3600;
+/// A freeze excursion must last this long before its time counts as alarm time.
+pub const FREEZE_ALARM_DELAY_SECONDS: u32 = 60 * 60;
+/// A door held open longer than this raises a door alarm.
+pub const DOOR_ALARM_SECONDS: u32 = 5 * 60;
+/// Words in a serialized `AlarmState`, including its CRC.
+pub const ALARM_STATE_WORDS: usize = 7;
+
+/// Thresholds and durations of the temperature and door alarms for one kind of appliance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AlarmProfile {
+    pub high_celsius: f32, // TVC above this is a high excursion.
+    pub low_celsius: f32, // TVC at or below this is a low (freeze) excursion.
+    pub high_delay_seconds: u32, // A high excursion lasting this long is an alarm.
+    pub low_delay_seconds: u32, // A low excursion lasting this long is an alarm.
+    pub door_seconds: u32, // A door held open longer than this is an alarm.
+}
+
+impl Default for AlarmProfile {
+    fn default() -> Self {
+        Self::FRIDGE
+    }
+}
+
+impl AlarmProfile {
+    /// Vaccine refrigerator, +2 to +8 °C with WHO freeze and heat alarm delays.
+    pub const FRIDGE: AlarmProfile = AlarmProfile {
+        high_celsius: HIGH_ALARM_CELSIUS,
+        low_celsius: FREEZE_ALARM_CELSIUS,
+        high_delay_seconds: HIGH_ALARM_DELAY_SECONDS,
+        low_delay_seconds: FREEZE_ALARM_DELAY_SECONDS,
+        door_seconds: DOOR_ALARM_SECONDS,
+    };
+    /// Vaccine freezer, -25 to -15 °C.
+    pub const FREEZER: AlarmProfile = AlarmProfile {
+        high_celsius: -15.0,
+        low_celsius: -25.0,
+        high_delay_seconds: 60 * 60,
+        low_delay_seconds: 60 * 60,
+        door_seconds: DOOR_ALARM_SECONDS,
+    };
+    /// Walk-in cold room, +2 to +8 °C. Staff go in and out, so the door may stay open longer.
+    pub const COLD_ROOM: AlarmProfile = AlarmProfile {
+        high_celsius: HIGH_ALARM_CELSIUS,
+        low_celsius: 2.0,
+        high_delay_seconds: 60 * 60,
+        low_delay_seconds: 60 * 60,
+        door_seconds: 15 * 60,
+    };
+
+    /// The profile selected by two strap inputs, each reading low when its jumper is fitted.
+    /// Returns None with no jumpers fitted, meaning the configured profile applies.
+    pub fn from_strap(strap0_low: bool, strap1_low: bool) -> Option<Self> {
+        match (strap1_low, strap0_low) {
+            (false, false) => None,
+            (false, true) => Some(Self::FRIDGE),
+            (true, false) => Some(Self::FREEZER),
+            (true, true) => Some(Self::COLD_ROOM),
+        }
+    }
+
+    pub fn is_high(&self, tvc: f32) -> bool {
+        tvc > self.high_celsius
+    }
+
+    pub fn is_low(&self, tvc: f32) -> bool {
+        tvc <= self.low_celsius
+    }
+}
+
+/// Classes of alarm that can be annunciated, highest priority first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AlarmKind {
+    Freeze,
+    HighTemp,
+    Power,
+    Door,
+}
+
+impl AlarmKind {
+    /// All alarm kinds, in priority order.
+    pub const ALL: [AlarmKind; 4] = [AlarmKind::Freeze, AlarmKind::HighTemp, AlarmKind::Power, AlarmKind::Door];
+
+    /// Bit for this kind in alarm bitmaps, e.g. `Config::alarm_relay_mask`.
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// Single-character icon for the display.
+    pub fn icon(self) -> char {
+        match self {
+            AlarmKind::Freeze => 'F',
+            AlarmKind::HighTemp => 'H',
+            AlarmKind::Power => 'P',
+            AlarmKind::Door => 'D',
+        }
+    }
+
+    /// The repeating buzzer pattern used to annunciate this alarm.
+    /// An escalated alarm keeps its rhythm but repeats faster.
+    pub fn buzzer_pattern(self, escalated: bool) -> &'static [BeepStep] {
+        match (self, escalated) {
+            (AlarmKind::Freeze, false) => &[
+                BeepStep { on_ms: 100, off_ms: 100 },
+                BeepStep { on_ms: 100, off_ms: 100 },
+                BeepStep { on_ms: 100, off_ms: 1700 },
+            ],
+            (AlarmKind::Freeze, true) => &[
+                BeepStep { on_ms: 100, off_ms: 100 },
+                BeepStep { on_ms: 100, off_ms: 100 },
+                BeepStep { on_ms: 100, off_ms: 500 },
+            ],
+            (AlarmKind::HighTemp, false) => &[BeepStep { on_ms: 500, off_ms: 500 }],
+            (AlarmKind::HighTemp, true) => &[BeepStep { on_ms: 250, off_ms: 250 }],
+            (AlarmKind::Power, false) => &[BeepStep { on_ms: 1000, off_ms: 4000 }],
+            (AlarmKind::Power, true) => &[BeepStep { on_ms: 1000, off_ms: 1000 }],
+            (AlarmKind::Door, false) => &[BeepStep { on_ms: 200, off_ms: 9800 }],
+            (AlarmKind::Door, true) => &[BeepStep { on_ms: 200, off_ms: 1800 }],
+        }
+    }
+}
+
+/// One on/off step of a buzzer pattern, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BeepStep {
+    pub on_ms: u16,
+    pub off_ms: u16,
+}
+
+/// Decides which alarm, if any, should sound, taking acknowledgements into account.
+///
+/// Acknowledging silences the alarms active at that moment for `SNOOZE_SECONDS`.
+/// An alarm that becomes active during the snooze, or that clears and re-occurs,
+/// sounds immediately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Annunciator {
+    active: u8,
+    snoozed: u8,
+    snooze_until: Option<Timestamp>,
+}
+
+impl Annunciator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether an alarm of the given kind is currently active.
+    pub fn set_active(&mut self, kind: AlarmKind, active: bool) {
+        if active {
+            self.active |= kind.mask();
+        } else {
+            self.active &= !kind.mask();
+            self.snoozed &= !kind.mask();
+        }
+    }
+
+    pub fn is_active(&self, kind: AlarmKind) -> bool {
+        self.active & kind.mask() != 0
+    }
+
+    /// Returns true if any alarm is active, whether or not it has been acknowledged.
+    pub fn any_active(&self) -> bool {
+        self.active != 0
+    }
+
+    /// Silence the currently active alarms for `SNOOZE_SECONDS`.
+    /// Returns true if anything was silenced, so the caller can log the acknowledgement.
+    pub fn acknowledge(&mut self, now: Timestamp) -> bool {
+        if self.sounding(now).is_none() {
+            return false;
+        }
+        self.snoozed = self.active;
+        self.snooze_until = Some(Timestamp { seconds: now.seconds + SNOOZE_SECONDS });
+        true
+    }
+
+    /// The highest-priority alarm that should be sounding at `now`.
+    pub fn sounding(&mut self, now: Timestamp) -> Option<AlarmKind> {
+        if self.snooze_until.is_some_and(|until| now.seconds >= until.seconds) {
+            self.snoozed = 0;
+            self.snooze_until = None;
+        }
+        let audible = self.active & !self.snoozed;
+        AlarmKind::ALL.into_iter().find(|kind| audible & kind.mask() != 0)
+    }
+}
+
+/// The alarms after an update, with acknowledgements and escalation applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AlarmStatus {
+    pub active: u8, // Active alarms, acknowledged or not, as `AlarmKind::mask` bits.
+    pub sounding: Option<AlarmKind>, // See `Annunciator::sounding`.
+    pub escalated: u8, // See `Escalation::escalated_mask`.
+    pub now: Timestamp,
+}
+
+impl AlarmStatus {
+    pub fn is_active(&self, kind: AlarmKind) -> bool {
+        self.active & kind.mask() != 0
+    }
+
+    pub fn is_escalated(&self, kind: AlarmKind) -> bool {
+        self.escalated & kind.mask() != 0
+    }
+}
+
+/// An output for the alarms, e.g. the buzzer, relay, status LED, telemetry or the burst capture,
+/// so a new output is added by passing one more sink to `AlarmNotifier::update` rather than by
+/// changing the alarm logic.
+///
+/// Outputs that report alarms, like telemetry, follow their starts and clears; outputs that show
+/// them, like the buzzer, follow the status, which carries the snooze and escalation.
+pub trait AlarmSink {
+    fn on_alarm_start(&mut self, _kind: AlarmKind, _at: Timestamp) {}
+    fn on_alarm_clear(&mut self, _kind: AlarmKind, _at: Timestamp) {}
+
+    /// The alarms after every update, whether or not any started or cleared.
+    fn on_status(&mut self, _status: &AlarmStatus) {}
+}
+
+/// Tells the alarm sinks when the annunciator's alarms start and clear, and their status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AlarmNotifier {
+    notified: u8, // Alarms the sinks were last told are active, as `AlarmKind::mask` bits.
+}
+
+impl AlarmNotifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A notifier that takes the alarms already active in `annunciator` as told, e.g. ones
+    /// restored after a reset, which didn't start again. Those that ended meanwhile are told as
+    /// cleared once their inputs clear them, and the status shows the rest from the first update.
+    pub fn following(annunciator: &Annunciator) -> Self {
+        Self { notified: annunciator.active }
+    }
+
+    /// Bring `escalation` up to date with `annunciator`, tell each of `sinks` about the alarms
+    /// that started or cleared since the last call, highest priority first, as happening at `now`,
+    /// then give them the status.
+    pub fn update(&mut self, annunciator: &mut Annunciator, escalation: &mut Escalation, now: Timestamp, sinks: &mut [&mut dyn AlarmSink]) {
+        for kind in AlarmKind::ALL {
+            escalation.set_active(kind, annunciator.is_active(kind), now);
+        }
+        for kind in AlarmKind::ALL {
+            let active = annunciator.is_active(kind);
+            if active == (self.notified & kind.mask() != 0) {
+                continue;
+            }
+            self.notified ^= kind.mask();
+            for sink in sinks.iter_mut() {
+                if active {
+                    sink.on_alarm_start(kind, now);
+                } else {
+                    sink.on_alarm_clear(kind, now);
+                }
+            }
+        }
+        let status = AlarmStatus { active: annunciator.active, sounding: annunciator.sounding(now), escalated: escalation.escalated_mask(now), now };
+        for sink in sinks.iter_mut() {
+            sink.on_status(&status);
+        }
+    }
+}
+
+/// The alarm state machines, with their timestamps, to save on every change and restore after a
+/// reset or power cut, so neither ends a snooze nor restarts escalation. The temperature
+/// excursions leading up to an alarm are carried by the logger's `WarmStart`.
+///
+/// Whether each alarm is still active is only known again once its input is read, so whatever
+/// drives it must start from the restored state, or an alarm that ended meanwhile stays active.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlarmState {
+    pub annunciator: Annunciator,
+    pub active_since: [Option<Timestamp>; AlarmKind::ALL.len()], // See `Escalation::active_since`.
+}
+
+impl AlarmState {
+    pub fn capture(annunciator: &Annunciator, escalation: &Escalation) -> Self {
+        Self { annunciator: *annunciator, active_since: escalation.active_since() }
+    }
+
+    /// Put the saved state back, keeping `escalation`'s rules.
+    pub fn restore(&self, annunciator: &mut Annunciator, escalation: &mut Escalation) {
+        *annunciator = self.annunciator;
+        escalation.resume(self.active_since);
+    }
+
+    pub fn to_words(&self) -> [u32; ALARM_STATE_WORDS] {
+        let annunciator = &self.annunciator;
+        // Bit 16 marks a snooze, and bits 24 up which alarms have an escalation start time.
+        let since_mask = self.active_since.iter().enumerate().fold(0, |mask, (i, since)| mask | u32::from(since.is_some()) << (24 + i));
+        let flags = u32::from(annunciator.active) | u32::from(annunciator.snoozed) << 8 | u32::from(annunciator.snooze_until.is_some()) << 16 | since_mask;
+        let since = self.active_since.map(|since| since.map_or(0, |since| since.seconds));
+        let mut words = [flags, annunciator.snooze_until.map_or(0, |until| until.seconds), since[0], since[1], since[2], since[3], 0];
+        words[ALARM_STATE_WORDS - 1] = alarm_state_checksum(&words);
+        words
+    }
+
+    /// Decode words written by `to_words`. Returns None if the CRC doesn't match.
+    pub fn from_words(words: &[u32; ALARM_STATE_WORDS]) -> Option<Self> {
+        if alarm_state_checksum(words) != words[ALARM_STATE_WORDS - 1] {
+            return None;
+        }
+        let flags = words[0];
+        let annunciator = Annunciator {
+            active: flags as u8,
+            snoozed: (flags >> 8) as u8,
+            snooze_until: (flags & 1 << 16 != 0).then_some(Timestamp { seconds: words[1] }),
+        };
+        let active_since = core::array::from_fn(|i| (flags & 1 << (24 + i) != 0).then_some(Timestamp { seconds: words[2 + i] }));
+        Some(Self { annunciator, active_since })
+    }
+}
+
+// CRC-32 over all words but the last, which holds it.
+fn alarm_state_checksum(words: &[u32; ALARM_STATE_WORDS]) -> u32 {
+    words[..ALARM_STATE_WORDS - 1].iter().fold(0, |crc, word| crc32(crc, &word.to_le_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles() {
+        assert!(AlarmProfile::FRIDGE.is_high(8.1));
+        assert!(AlarmProfile::FRIDGE.is_low(-0.5));
+        assert!(!AlarmProfile::FREEZER.is_high(-20.0));
+        assert!(AlarmProfile::FREEZER.is_low(-25.0));
+        assert!(AlarmProfile::COLD_ROOM.is_low(1.5));
+        assert_eq!(AlarmProfile::from_strap(false, false), None);
+        assert_eq!(AlarmProfile::from_strap(false, true), Some(AlarmProfile::FREEZER));
+        assert_eq!(AlarmProfile::from_strap(true, true), Some(AlarmProfile::COLD_ROOM));
+    }
+
+    #[test]
+    fn test_escalated_patterns_faster() {
+        let period = |pattern: &[BeepStep]| pattern.iter().map(|step| u32::from(step.on_ms + step.off_ms)).sum::<u32>();
+        for kind in AlarmKind::ALL {
+            assert!(period(kind.buzzer_pattern(true)) < period(kind.buzzer_pattern(false)), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut annunciator = Annunciator::new();
+        let now = Timestamp { seconds: 0 };
+        assert_eq!(annunciator.sounding(now), None);
+        annunciator.set_active(AlarmKind::Door, true);
+        assert_eq!(annunciator.sounding(now), Some(AlarmKind::Door));
+        annunciator.set_active(AlarmKind::Freeze, true);
+        assert_eq!(annunciator.sounding(now), Some(AlarmKind::Freeze));
+        annunciator.set_active(AlarmKind::Freeze, false);
+        assert_eq!(annunciator.sounding(now), Some(AlarmKind::Door));
+    }
+
+    #[test]
+    fn test_acknowledge_snoozes() {
+        let mut annunciator = Annunciator::new();
+        assert!(!annunciator.acknowledge(Timestamp { seconds: 0 }));
+        annunciator.set_active(AlarmKind::HighTemp, true);
+        assert!(annunciator.acknowledge(Timestamp { seconds: 100 }));
+        assert_eq!(annunciator.sounding(Timestamp { seconds: 100 + SNOOZE_SECONDS - 1 }), None);
+        assert_eq!(annunciator.sounding(Timestamp { seconds: 100 + SNOOZE_SECONDS }), Some(AlarmKind::HighTemp));
+    }
+
+    #[test]
+    fn test_new_alarm_sounds_during_snooze() {
+        let mut annunciator = Annunciator::new();
+        annunciator.set_active(AlarmKind::Door, true);
+        assert!(annunciator.acknowledge(Timestamp { seconds: 0 }));
+        annunciator.set_active(AlarmKind::Power, true);
+        assert_eq!(annunciator.sounding(Timestamp { seconds: 10 }), Some(AlarmKind::Power));
+        // A snoozed alarm that clears and re-occurs sounds again.
+        annunciator.set_active(AlarmKind::Power, false);
+        annunciator.set_active(AlarmKind::Door, false);
+        annunciator.set_active(AlarmKind::Door, true);
+        assert_eq!(annunciator.sounding(Timestamp { seconds: 20 }), Some(AlarmKind::Door));
+    }
+
+    #[derive(Default)]
+    struct Transitions(Vec<(AlarmKind, bool, u32)>, Option<AlarmStatus>);
+
+    impl AlarmSink for Transitions {
+        fn on_alarm_start(&mut self, kind: AlarmKind, at: Timestamp) {
+            self.0.push((kind, true, at.seconds));
+        }
+
+        fn on_alarm_clear(&mut self, kind: AlarmKind, at: Timestamp) {
+            self.0.push((kind, false, at.seconds));
+        }
+
+        fn on_status(&mut self, status: &AlarmStatus) {
+            self.1 = Some(*status);
+        }
+    }
+
+    #[test]
+    fn test_notifier_tells_every_sink() {
+        let (mut annunciator, mut escalation) = (Annunciator::new(), Escalation::default());
+        annunciator.set_active(AlarmKind::Power, true);
+        let mut notifier = AlarmNotifier::following(&annunciator); // Restored, so not told again.
+        let (mut telemetry, mut led) = (Transitions::default(), Transitions::default());
+        annunciator.set_active(AlarmKind::Door, true);
+        annunciator.set_active(AlarmKind::HighTemp, true);
+        notifier.update(&mut annunciator, &mut escalation, Timestamp { seconds: 100 }, &mut [&mut telemetry, &mut led]);
+        notifier.update(&mut annunciator, &mut escalation, Timestamp { seconds: 200 }, &mut [&mut telemetry, &mut led]); // No change.
+        annunciator.set_active(AlarmKind::Power, false);
+        notifier.update(&mut annunciator, &mut escalation, Timestamp { seconds: 300 }, &mut [&mut telemetry]);
+        assert_eq!(telemetry.0, [(AlarmKind::HighTemp, true, 100), (AlarmKind::Door, true, 100), (AlarmKind::Power, false, 300)]);
+        assert_eq!(led.0, telemetry.0[..2]);
+        // The status shows the restored alarm from the first update, sounding first by priority.
+        let status = led.1.unwrap();
+        assert_eq!((status.active, status.sounding, status.now.seconds), (annunciator.active | AlarmKind::Power.mask(), Some(AlarmKind::HighTemp), 200));
+        assert!(!telemetry.1.unwrap().is_active(AlarmKind::Power));
+    }
+
+    #[test]
+    fn test_status_follows_snooze_and_escalation() {
+        let (mut annunciator, mut escalation) = (Annunciator::new(), Escalation::default());
+        let mut notifier = AlarmNotifier::new();
+        let mut sink = Transitions::default();
+        annunciator.set_active(AlarmKind::Freeze, true);
+        notifier.update(&mut annunciator, &mut escalation, Timestamp { seconds: 0 }, &mut [&mut sink]);
+        assert!(annunciator.acknowledge(Timestamp { seconds: 10 }));
+        let later = Timestamp { seconds: SNOOZE_SECONDS + 9 }; // Escalated after 30 minutes, and still snoozed.
+        notifier.update(&mut annunciator, &mut escalation, later, &mut [&mut sink]);
+        let status = sink.1.unwrap();
+        assert_eq!(status.sounding, None);
+        assert!(status.is_active(AlarmKind::Freeze) && status.is_escalated(AlarmKind::Freeze));
+        assert_eq!(escalation.active_since()[AlarmKind::Freeze as usize], Some(Timestamp { seconds: 0 }));
+    }
+
+    #[test]
+    fn test_alarm_state_round_trip() {
+        let mut annunciator = Annunciator::new();
+        let mut escalation = Escalation::default();
+        annunciator.set_active(AlarmKind::HighTemp, true);
+        escalation.set_active(AlarmKind::HighTemp, true, Timestamp { seconds: 1000 });
+        assert!(annunciator.acknowledge(Timestamp { seconds: 2000 }));
+        let words = AlarmState::capture(&annunciator, &escalation).to_words();
+
+        // After a reset.
+        let (mut restored, mut restored_escalation) = (Annunciator::new(), Escalation::default());
+        AlarmState::from_words(&words).unwrap().restore(&mut restored, &mut restored_escalation);
+        assert_eq!(restored, annunciator);
+        assert_eq!(restored.sounding(Timestamp { seconds: 2000 + SNOOZE_SECONDS - 1 }), None); // Still snoozed.
+        assert_eq!(restored_escalation.level(AlarmKind::HighTemp, Timestamp { seconds: 1000 + 2 * 3600 }), 1);
+        assert_eq!(restored_escalation.active_since()[AlarmKind::Door as usize], None);
+
+        let mut corrupted = words;
+        corrupted[2] ^= 1;
+        assert_eq!(AlarmState::from_words(&corrupted), None);
+    }
+}
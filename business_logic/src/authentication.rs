@@ -0,0 +1,99 @@
+use arrayvec::ArrayString;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::provisioning::DeviceKey;
+
+/// Length of an HMAC-SHA256 tag.
+pub const TAG_LEN: usize = 32;
+
+pub type Tag = [u8; TAG_LEN];
+
+/// Computes the authentication tag (an HMAC-SHA256 MAC) of an export as it is written, so a
+/// whole report never has to be held in RAM.
+///
+/// Auditors holding the device key check the tag with `verify`, which shows the data wasn't
+/// modified after it was downloaded. It is not a signature: the key is shared, so anyone who
+/// can check a tag can also make one, and it can't prove to a third party which device wrote it.
+#[derive(Clone)]
+pub struct Authenticator(Hmac<Sha256>);
+
+impl Authenticator {
+    pub fn new(key: &DeviceKey) -> Self {
+        Self(Hmac::new_from_slice(&key.0).expect("HMAC takes keys of any length"))
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finish(self) -> Tag {
+        self.0.finalize().into_bytes().into()
+    }
+}
+
+// Lets text exports be written straight through the authenticator.
+impl core::fmt::Write for Authenticator {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.update(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Check `tag` over `data` in constant time.
+pub fn verify(key: &DeviceKey, data: &[u8], tag: &[u8]) -> bool {
+    let mut mac = Authenticator::new(key).0;
+    mac.update(data);
+    mac.verify_slice(tag).is_ok()
+}
+
+/// Lowercase hex, for appending a tag to a text export.
+pub fn to_hex(tag: &Tag) -> ArrayString<{ 2 * TAG_LEN }> {
+    let mut hex = ArrayString::new();
+    for byte in tag {
+        for nibble in [byte >> 4, byte & 0xF] {
+            hex.push(char::from_digit(nibble as u32, 16).unwrap_or('0'));
+        }
+    }
+    hex
+}
+
+/// Parse a tag written by `to_hex`.
+pub fn from_hex(hex: &str) -> Option<Tag> {
+    if hex.len() != 2 * TAG_LEN {
+        return None;
+    }
+    let mut tag = [0u8; TAG_LEN];
+    for (byte, pair) in tag.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn test_rfc4231_case_2() {
+        // The RFC key is shorter than a device key, so feed the MAC directly.
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"Jefe").unwrap();
+        mac.update(b"what do ya want for nothing?");
+        let expected = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+        assert_eq!(to_hex(&mac.finalize().into_bytes().into()).as_str(), expected);
+    }
+
+    #[test]
+    fn test_tag_and_verify() {
+        let key = DeviceKey([0x42; 32]);
+        let mut authenticator = Authenticator::new(&key);
+        writeln!(authenticator, "start,{}", 900).unwrap();
+        let tag = authenticator.finish();
+        assert!(verify(&key, b"start,900\n", &tag));
+        assert!(!verify(&key, b"start,901\n", &tag));
+        assert!(!verify(&DeviceKey([0x43; 32]), b"start,900\n", &tag));
+        assert_eq!(from_hex(&to_hex(&tag)), Some(tag));
+        assert_eq!(from_hex("12"), None);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::timestamp::Timestamp;
+
+/// Usable capacity of the backup battery pack.
+pub const BATTERY_CAPACITY_MAH: u32 = 2600;
+/// Average current drawn from the battery while running without external power.
+pub const BATTERY_LOAD_UA: u32 = 1500;
+/// Length of the persisted fuel gauge record in bytes.
+pub const FUEL_GAUGE_RECORD_LEN: usize = 12;
+const UAS_PER_MAH: u64 = 3_600_000; // Microamp-seconds in one milliamp-hour.
+
+/// Coulomb-counting estimate of the battery state of charge.
+///
+/// Charge drawn is accumulated in microamp-seconds so that many small
+/// increments don't lose precision. The consumption rate since the battery was
+/// installed is used to predict when it will need replacing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FuelGauge {
+    capacity_mah: u32,
+    used_uas: u64,
+    installed_at: Timestamp,
+}
+
+impl FuelGauge {
+    pub fn new(capacity_mah: u32, installed_at: Timestamp) -> Self {
+        Self { capacity_mah, used_uas: 0, installed_at }
+    }
+
+    /// A fresh battery was fitted.
+    pub fn replaced(&mut self, now: Timestamp) {
+        self.used_uas = 0;
+        self.installed_at = now;
+    }
+
+    /// Account for `current_ua` drawn from the battery for `seconds`.
+    pub fn record(&mut self, current_ua: u32, seconds: u32) {
+        self.used_uas = self.used_uas.saturating_add(u64::from(current_ua) * u64::from(seconds));
+    }
+
+    /// Charge drawn since the battery was installed.
+    pub fn used_mah(&self) -> u32 {
+        (self.used_uas / UAS_PER_MAH).min(u64::from(u32::MAX)) as u32
+    }
+
+    pub fn percent_remaining(&self) -> u8 {
+        let capacity_uas = u64::from(self.capacity_mah) * UAS_PER_MAH;
+        if capacity_uas == 0 {
+            return 0;
+        }
+        let remaining_uas = capacity_uas.saturating_sub(self.used_uas);
+        (remaining_uas * 100 / capacity_uas) as u8
+    }
+
+    /// When the battery is expected to be exhausted at the average rate of use so far.
+    /// Returns None until there is some consumption to extrapolate from.
+    pub fn estimated_replacement(&self, now: Timestamp) -> Option<Timestamp> {
+        let elapsed = u64::from(now.seconds.checked_sub(self.installed_at.seconds)?);
+        if elapsed == 0 || self.used_uas == 0 {
+            return None;
+        }
+        let remaining_uas = (u64::from(self.capacity_mah) * UAS_PER_MAH).saturating_sub(self.used_uas);
+        let seconds_left = remaining_uas.saturating_mul(elapsed) / self.used_uas;
+        let seconds = u64::from(now.seconds).saturating_add(seconds_left).min(u64::from(u32::MAX));
+        Some(Timestamp { seconds: seconds as u32 })
+    }
+
+    /// Serialize the state that must survive a reset.
+    pub fn to_bytes(&self) -> [u8; FUEL_GAUGE_RECORD_LEN] {
+        let mut bytes = [0u8; FUEL_GAUGE_RECORD_LEN];
+        bytes[..8].copy_from_slice(&self.used_uas.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.installed_at.seconds.to_le_bytes());
+        bytes
+    }
+
+    /// Restore state saved by `to_bytes`.
+    pub fn from_bytes(capacity_mah: u32, bytes: &[u8; FUEL_GAUGE_RECORD_LEN]) -> Self {
+        let mut used = [0u8; 8];
+        used.copy_from_slice(&bytes[..8]);
+        let mut installed = [0u8; 4];
+        installed.copy_from_slice(&bytes[8..]);
+        Self {
+            capacity_mah,
+            used_uas: u64::from_le_bytes(used),
+            installed_at: Timestamp { seconds: u32::from_le_bytes(installed) },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_remaining() {
+        let mut gauge = FuelGauge::new(1000, Timestamp { seconds: 0 });
+        assert_eq!(gauge.percent_remaining(), 100);
+        gauge.record(1_000_000, 3600); // 1 A for an hour.
+        assert_eq!(gauge.used_mah(), 1000);
+        assert_eq!(gauge.percent_remaining(), 0);
+        gauge.replaced(Timestamp { seconds: 10 });
+        gauge.record(250_000, 3600);
+        assert_eq!(gauge.percent_remaining(), 75);
+    }
+
+    #[test]
+    fn test_estimated_replacement() {
+        let mut gauge = FuelGauge::new(1000, Timestamp { seconds: 1000 });
+        assert_eq!(gauge.estimated_replacement(Timestamp { seconds: 2000 }), None);
+        gauge.record(100_000, 3600); // 10% used in 1000 seconds.
+        assert_eq!(gauge.estimated_replacement(Timestamp { seconds: 2000 }), Some(Timestamp { seconds: 11_000 }));
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut gauge = FuelGauge::new(BATTERY_CAPACITY_MAH, Timestamp { seconds: 12345 });
+        gauge.record(BATTERY_LOAD_UA, 86400);
+        assert_eq!(FuelGauge::from_bytes(BATTERY_CAPACITY_MAH, &gauge.to_bytes()), gauge);
+    }
+}
@@ -0,0 +1,248 @@
+use arrayvec::ArrayVec;
+
+use crate::alarm::AlarmKind;
+use crate::log::{Log, LogCode};
+use crate::sample::TemperatureSample;
+use crate::timestamp::Timestamp;
+
+/// Time between samples while a burst is being captured.
+pub const BURST_PERIOD_SECONDS: u32 = 30;
+/// How long a burst runs after its trigger.
+pub const BURST_DURATION_SECONDS: u32 = 600;
+/// Samples in a complete burst.
+pub const BURST_SAMPLES: usize = (BURST_DURATION_SECONDS / BURST_PERIOD_SECONDS) as usize;
+/// Completed bursts kept. A new one replaces the oldest.
+pub const BURST_CAPTURES: usize = 4;
+/// Length of `BurstCapture::to_bytes`: the trigger, start and sample count, then each sample.
+pub const BURST_CAPTURE_LEN: usize = 6 + BURST_SAMPLES * SAMPLE_LEN;
+const SAMPLE_LEN: usize = 12; // Seconds after the start as a u16, both temperatures, both qualities.
+
+/// The event a burst was captured around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BurstTrigger {
+    DoorOpened,
+    Alarm(AlarmKind), // The alarm became active.
+}
+
+impl BurstTrigger {
+    /// Compact form for log payloads: 0 for the door, 1 plus the alarm kind for an alarm.
+    pub fn code(self) -> u32 {
+        match self {
+            BurstTrigger::DoorOpened => 0,
+            BurstTrigger::Alarm(kind) => 1 + kind as u32,
+        }
+    }
+
+    fn from_code(code: u32) -> Option<Self> {
+        match code {
+            0 => Some(BurstTrigger::DoorOpened),
+            _ => AlarmKind::ALL.into_iter().find(|&kind| 1 + kind as u32 == code).map(BurstTrigger::Alarm),
+        }
+    }
+}
+
+/// Raw samples taken at `BURST_PERIOD_SECONDS` after a trigger, for looking at an excursion in
+/// more detail than the records give.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BurstCapture {
+    pub trigger: BurstTrigger,
+    pub start: Timestamp, // When the triggering event happened.
+    pub samples: ArrayVec<TemperatureSample, BURST_SAMPLES>,
+}
+
+impl BurstCapture {
+    /// Serialize for flash. Humidity isn't kept.
+    pub fn to_bytes(&self) -> [u8; BURST_CAPTURE_LEN] {
+        let mut bytes = [0u8; BURST_CAPTURE_LEN];
+        bytes[0] = self.trigger.code() as u8;
+        bytes[1..5].copy_from_slice(&self.start.seconds.to_le_bytes());
+        bytes[5] = self.samples.len() as u8;
+        for (chunk, sample) in bytes[6..].chunks_exact_mut(SAMPLE_LEN).zip(&self.samples) {
+            // Samples before the start are never kept, and a burst ends within a u16 of it.
+            chunk[0..2].copy_from_slice(&((sample.timestamp.seconds - self.start.seconds) as u16).to_le_bytes());
+            chunk[2..6].copy_from_slice(&sample.tvc.to_bits().to_le_bytes());
+            chunk[6..10].copy_from_slice(&sample.tamb.to_bits().to_le_bytes());
+            (chunk[10], chunk[11]) = (sample.tvc_quality, sample.tamb_quality);
+        }
+        bytes
+    }
+
+    /// Restore a capture saved by `to_bytes`, or None if the bytes don't hold one.
+    pub fn from_bytes(bytes: &[u8; BURST_CAPTURE_LEN]) -> Option<Self> {
+        let trigger = BurstTrigger::from_code(bytes[0].into())?;
+        let start = Timestamp { seconds: u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]) };
+        let count = usize::from(bytes[5]);
+        if count > BURST_SAMPLES {
+            return None;
+        }
+        let word = |chunk: &[u8], at: usize| f32::from_bits(u32::from_le_bytes([chunk[at], chunk[at + 1], chunk[at + 2], chunk[at + 3]]));
+        let samples = bytes[6..]
+            .chunks_exact(SAMPLE_LEN)
+            .take(count)
+            .map(|chunk| TemperatureSample {
+                timestamp: Timestamp { seconds: start.seconds.wrapping_add(u16::from_le_bytes([chunk[0], chunk[1]]).into()) },
+                tvc: word(chunk, 2),
+                tamb: word(chunk, 6),
+                tvc_quality: chunk[10],
+                tamb_quality: chunk[11],
+                #[cfg(feature = "humidity")]
+                humidity: None,
+            })
+            .collect();
+        Some(Self { trigger, start, samples })
+    }
+}
+
+/// Captures a burst of raw samples around door openings and alarms, and keeps the latest ones.
+///
+/// The sampling task should use `BURST_PERIOD_SECONDS` while `is_active`; samples arriving
+/// faster are kept as they come until the burst is full. Completed bursts take `BURST_CAPTURES`
+/// slots in turn, so each can be saved in a slot of its own and restored after a reset.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BurstRecorder {
+    active: Option<BurstCapture>,
+    slots: [Option<BurstCapture>; BURST_CAPTURES],
+    next_slot: usize, // Empty, or holding the oldest capture.
+}
+
+impl BurstRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Carry on from the captures saved in each slot, e.g. after a reset.
+    pub fn restore(slots: [Option<BurstCapture>; BURST_CAPTURES]) -> Self {
+        let next_slot = slots
+            .iter()
+            .position(Option::is_none)
+            .or_else(|| (0..BURST_CAPTURES).min_by_key(|&slot| slots[slot].as_ref().map(|capture| capture.start.seconds)))
+            .unwrap_or(0);
+        Self { active: None, slots, next_slot }
+    }
+
+    /// Start a burst for `trigger`, which happened at `now`. A burst already running carries on
+    /// instead, since it covers the new event too; returns whether a new one started.
+    pub fn trigger(&mut self, trigger: BurstTrigger, now: Timestamp) -> bool {
+        if self.active.is_some() {
+            return false;
+        }
+        self.active = Some(BurstCapture { trigger, start: now, samples: ArrayVec::new() });
+        true
+    }
+
+    /// Whether a burst is running, so samples should be taken at `BURST_PERIOD_SECONDS`.
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Add `sample` to the running burst, if any. The burst completes once it is full or its
+    /// time is up; returns the slot and capture of a burst that completed, to save.
+    pub fn sample(&mut self, sample: TemperatureSample, log: &mut impl Log) -> Option<(usize, &BurstCapture)> {
+        if self.timed_out(sample.timestamp) {
+            return self.complete(log);
+        }
+        let active = self.active.as_mut()?;
+        if sample.timestamp.seconds < active.start.seconds {
+            return None;
+        }
+        active.samples.push(sample);
+        if !active.samples.is_full() {
+            return None;
+        }
+        self.complete(log)
+    }
+
+    /// Complete the running burst if its time is up at `now`, e.g. while the sensor isn't
+    /// answering. Returns its slot and capture, to save.
+    pub fn poll(&mut self, now: Timestamp, log: &mut impl Log) -> Option<(usize, &BurstCapture)> {
+        if !self.timed_out(now) {
+            return None;
+        }
+        self.complete(log)
+    }
+
+    /// Completed bursts, oldest first.
+    pub fn captures(&self) -> impl Iterator<Item = &BurstCapture> {
+        (0..BURST_CAPTURES).filter_map(|index| self.slots[(self.next_slot + index) % BURST_CAPTURES].as_ref())
+    }
+
+    fn timed_out(&self, now: Timestamp) -> bool {
+        self.active.as_ref().is_some_and(|active| now.seconds >= active.start.seconds.saturating_add(BURST_DURATION_SECONDS))
+    }
+
+    fn complete(&mut self, log: &mut impl Log) -> Option<(usize, &BurstCapture)> {
+        let capture = self.active.take()?;
+        log.info(LogCode::BurstCaptured, (capture.trigger.code() << 16) | capture.samples.len() as u32);
+        let slot = self.next_slot;
+        self.next_slot = (slot + 1) % BURST_CAPTURES;
+        Some((slot, self.slots[slot].insert(capture)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::reading;
+    use crate::log::{CaptureLog, Level};
+
+    #[test]
+    fn test_burst_after_door_opening() {
+        let mut bursts = BurstRecorder::new();
+        let mut log = CaptureLog::default();
+        bursts.sample(reading(970, 5.0), &mut log);
+        assert!(!bursts.is_active());
+        assert!(bursts.trigger(BurstTrigger::DoorOpened, Timestamp { seconds: 1000 }));
+        assert!(!bursts.trigger(BurstTrigger::Alarm(AlarmKind::HighTemp), Timestamp { seconds: 1100 }));
+        for seconds in (1000..1600).step_by(30) {
+            bursts.sample(reading(seconds, 6.0), &mut log);
+        }
+        assert!(!bursts.is_active());
+        let capture = bursts.captures().next().unwrap();
+        assert_eq!(capture.trigger, BurstTrigger::DoorOpened);
+        assert_eq!(capture.samples.len(), BURST_SAMPLES);
+        assert_eq!(capture.samples[1].timestamp, Timestamp { seconds: 1030 });
+        assert_eq!(log.entries, [(Level::Info, LogCode::BurstCaptured, BURST_SAMPLES as u32)]);
+    }
+
+    #[test]
+    fn test_burst_times_out_and_oldest_replaced() {
+        let mut bursts = BurstRecorder::new();
+        let mut log = CaptureLog::default();
+        bursts.trigger(BurstTrigger::Alarm(AlarmKind::Freeze), Timestamp { seconds: 0 });
+        bursts.sample(reading(0, 1.0), &mut log);
+        bursts.poll(Timestamp { seconds: 599 }, &mut log);
+        assert!(bursts.is_active());
+        bursts.poll(Timestamp { seconds: 600 }, &mut log);
+        assert!(!bursts.is_active());
+        assert_eq!(log.entries, [(Level::Info, LogCode::BurstCaptured, (1 << 16) | 1)]);
+        for start in 1..=BURST_CAPTURES as u32 {
+            bursts.trigger(BurstTrigger::DoorOpened, Timestamp { seconds: start * 1000 });
+            bursts.poll(Timestamp { seconds: start * 1000 + 600 }, &mut log);
+        }
+        assert_eq!(bursts.captures().count(), BURST_CAPTURES);
+        // The first burst was replaced.
+        assert_eq!(bursts.captures().next().map(|capture| (capture.start.seconds, capture.samples.len())), Some((1000, 0)));
+    }
+
+    #[test]
+    fn test_saved_and_restored() {
+        let mut bursts = BurstRecorder::new();
+        let mut log = CaptureLog::default();
+        let mut saved: [Option<BurstCapture>; BURST_CAPTURES] = Default::default();
+        for start in 0..=BURST_CAPTURES as u32 {
+            bursts.trigger(BurstTrigger::Alarm(AlarmKind::HighTemp), Timestamp { seconds: start * 1000 });
+            bursts.sample(TemperatureSample { tvc_quality: 2, ..reading(start * 1000 + 30, 8.5) }, &mut log);
+            let (slot, capture) = bursts.poll(Timestamp { seconds: start * 1000 + 600 }, &mut log).unwrap();
+            saved[slot] = BurstCapture::from_bytes(&capture.to_bytes());
+            assert_eq!(saved[slot].as_ref(), Some(capture));
+        }
+        // The fifth burst took the first one's slot; the next takes the second's.
+        let mut restored = BurstRecorder::restore(saved);
+        assert_eq!(restored.captures().collect::<Vec<_>>(), bursts.captures().collect::<Vec<_>>());
+        restored.trigger(BurstTrigger::DoorOpened, Timestamp { seconds: 9000 });
+        assert_eq!(restored.poll(Timestamp { seconds: 9600 }, &mut log).map(|(slot, _)| slot), Some(1));
+        assert_eq!(BurstRecorder::restore(Default::default()).captures().count(), 0);
+        assert_eq!(BurstCapture::from_bytes(&[0xFF; BURST_CAPTURE_LEN]), None);
+    }
+}
@@ -0,0 +1,174 @@
+use arrayvec::ArrayVec;
+
+/// Address every device on the bus acts on, and none answers.
+pub const BROADCAST_ADDRESS: u8 = 0;
+/// Highest address a device can have, as on Modbus.
+pub const MAX_BUS_ADDRESS: u8 = 247;
+/// Address of a device that was never given one.
+pub const DEFAULT_BUS_ADDRESS: u8 = 1;
+/// Longest payload in one frame.
+pub const MAX_FRAME_PAYLOAD: usize = 64;
+/// Longest frame on the wire: address, length, payload, CRC-16.
+pub const MAX_FRAME_LEN: usize = 1 + 1 + MAX_FRAME_PAYLOAD + 2;
+const BITS_PER_CHARACTER: u32 = 11; // Start, 8 data, parity or second stop, stop.
+const MIN_TURNAROUND_US: u32 = 1750; // Modbus RTU's fixed gap above 19200 baud.
+
+/// Why received bytes aren't a valid frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameError {
+    TooShort,
+    TooLong, // The payload is longer than `MAX_FRAME_PAYLOAD`.
+    Length, // The length byte doesn't match the bytes received.
+    Crc,
+}
+
+/// One message on a shared RS-485 bus: the address of the device it is for, or from when it is
+/// a reply, and the protocol payload.
+///
+/// On the wire: address, payload length, payload, then CRC-16/MODBUS over all of them, low byte
+/// first. Frames are separated by silence on the line, so the receiver reads until the line
+/// goes idle and decodes what it got.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub address: u8,
+    pub payload: ArrayVec<u8, MAX_FRAME_PAYLOAD>,
+}
+
+impl Frame {
+    pub fn new(address: u8, payload: &[u8]) -> Result<Self, FrameError> {
+        let payload = ArrayVec::try_from(payload).or(Err(FrameError::TooLong))?;
+        Ok(Self { address, payload })
+    }
+
+    pub fn encode(&self) -> ArrayVec<u8, MAX_FRAME_LEN> {
+        let mut bytes = ArrayVec::new();
+        bytes.push(self.address);
+        bytes.push(self.payload.len() as u8);
+        bytes.extend(self.payload.iter().copied());
+        let crc = crc16_modbus(&bytes);
+        bytes.extend(crc.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, FrameError> {
+        let [address, len, ..] = *bytes else {
+            return Err(FrameError::TooShort);
+        };
+        if usize::from(len) > MAX_FRAME_PAYLOAD {
+            return Err(FrameError::TooLong);
+        }
+        if bytes.len() != usize::from(len) + 4 {
+            return Err(FrameError::Length);
+        }
+        let (body, crc) = bytes.split_at(bytes.len() - 2);
+        if crc16_modbus(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+            return Err(FrameError::Crc);
+        }
+        Self::new(address, &body[2..])
+    }
+}
+
+/// One logger on a bus polled by a gateway.
+///
+/// A device only answers requests addressed to it, and the gateway waits for the answer or a
+/// timeout before polling the next one, so only one device drives the bus at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BusNode {
+    address: u8,
+}
+
+impl BusNode {
+    /// A device with `address`, or None if it is the broadcast address or above `MAX_BUS_ADDRESS`.
+    pub fn new(address: u8) -> Option<Self> {
+        is_valid_address(address).then_some(Self { address })
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Whether to act on `frame`, and whether to answer it. Frames for other devices are ignored;
+    /// broadcasts are acted on but never answered, since every device would answer at once.
+    pub fn accepts(&self, frame: &Frame) -> Option<bool> {
+        match frame.address {
+            BROADCAST_ADDRESS => Some(false),
+            address if address == self.address => Some(true),
+            _ => None,
+        }
+    }
+
+    /// A reply with `payload`, carrying this device's address so the gateway knows who answered.
+    pub fn reply(&self, payload: &[u8]) -> Result<Frame, FrameError> {
+        Frame::new(self.address, payload)
+    }
+}
+
+/// Whether `address` can be given to one device.
+pub fn is_valid_address(address: u8) -> bool {
+    (1..=MAX_BUS_ADDRESS).contains(&address)
+}
+
+/// Silence to leave after a request before driving the bus with a reply, so the gateway's
+/// transmitter has switched off: 3.5 characters, or a fixed 1750 µs at high baud rates, as on
+/// Modbus RTU. Also the silence that ends a frame.
+pub fn turnaround_us(baud_rate: u32) -> u32 {
+    let us = (7 * BITS_PER_CHARACTER * 1_000_000).div_ceil(2 * baud_rate.max(1));
+    us.max(MIN_TURNAROUND_US)
+}
+
+/// CRC-16/MODBUS: polynomial 0xA001 reflected, starting from 0xFFFF.
+pub fn crc16_modbus(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF_u16;
+    for &byte in data {
+        crc ^= u16::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc16_modbus() {
+        assert_eq!(crc16_modbus(b"123456789"), 0x4B37);
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let frame = Frame::new(17, b"STATUS").unwrap();
+        let bytes = frame.encode();
+        assert_eq!(bytes[..2], [17, 6]);
+        assert_eq!(bytes.len(), 10);
+        assert_eq!(Frame::decode(&bytes), Ok(frame));
+        let mut damaged = bytes.clone();
+        damaged[3] ^= 1;
+        assert_eq!(Frame::decode(&damaged), Err(FrameError::Crc));
+        assert_eq!(Frame::decode(&bytes[..9]), Err(FrameError::Length));
+        assert_eq!(Frame::decode(&[17]), Err(FrameError::TooShort));
+        assert_eq!(Frame::decode(&[17, 65, 0, 0]), Err(FrameError::TooLong));
+        assert_eq!(Frame::new(17, &[0; MAX_FRAME_PAYLOAD + 1]), Err(FrameError::TooLong));
+    }
+
+    #[test]
+    fn test_addressing() {
+        assert_eq!(BusNode::new(BROADCAST_ADDRESS), None);
+        assert_eq!(BusNode::new(MAX_BUS_ADDRESS + 1), None);
+        let node = BusNode::new(17).unwrap();
+        assert_eq!(node.accepts(&Frame::new(17, b"STATUS").unwrap()), Some(true));
+        assert_eq!(node.accepts(&Frame::new(18, b"STATUS").unwrap()), None);
+        assert_eq!(node.accepts(&Frame::new(BROADCAST_ADDRESS, b"SYNC").unwrap()), Some(false));
+        assert_eq!(node.reply(b"OK").unwrap().address, 17);
+    }
+
+    #[test]
+    fn test_turnaround() {
+        assert_eq!(turnaround_us(9600), 4011);
+        assert_eq!(turnaround_us(115_200), MIN_TURNAROUND_US);
+    }
+}
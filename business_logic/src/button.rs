@@ -0,0 +1,348 @@
+use embassy_futures::select::{select, Either};
+
+use crate::display::DisplayPage;
+use crate::hal::{DelayNs, InputPin, Monotonic, Wait};
+
+/// Holding the button at least this long is a long press.
+pub const LONG_PRESS_MS: u64 = 1500;
+/// Holding the button at least this long is a very long press.
+pub const VERY_LONG_PRESS_MS: u64 = 5000;
+/// A second short press starting within this time of the first release is a double press.
+pub const DOUBLE_PRESS_GAP_MS: u64 = 400;
+/// Edges within this time of a press or release are the contacts bouncing.
+pub const BUTTON_DEBOUNCE_MS: u32 = 50;
+
+/// A classified button gesture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Press {
+    Short,
+    Double,
+    Long,
+    VeryLong,
+}
+
+/// Classifies raw button press/release times (in milliseconds) into gestures.
+///
+/// A short press is only reported once `DOUBLE_PRESS_GAP_MS` has passed without
+/// a second press, so the caller must call `poll` at `deadline`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PressClassifier {
+    pressed_at: Option<u64>,
+    pending_short: Option<u64>, // Release time of a short press that may become a double press.
+    second_press: bool,
+}
+
+impl PressClassifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The button was pressed. Returns a pending short press if its double-press window had already expired.
+    pub fn pressed(&mut self, now_ms: u64) -> Option<Press> {
+        let expired = self.poll(now_ms);
+        self.second_press = self.pending_short.take().is_some();
+        self.pressed_at = Some(now_ms);
+        expired
+    }
+
+    /// The button was released. Returns the gesture if it is already known.
+    pub fn released(&mut self, now_ms: u64) -> Option<Press> {
+        let held_ms = now_ms.saturating_sub(self.pressed_at.take()?);
+        let second_press = core::mem::take(&mut self.second_press);
+        if held_ms >= VERY_LONG_PRESS_MS {
+            Some(Press::VeryLong)
+        } else if held_ms >= LONG_PRESS_MS {
+            Some(Press::Long)
+        } else if second_press {
+            Some(Press::Double)
+        } else {
+            self.pending_short = Some(now_ms);
+            None
+        }
+    }
+
+    /// Time at which `poll` should be called to confirm a pending short press.
+    pub fn deadline(&self) -> Option<u64> {
+        self.pending_short.map(|released| released + DOUBLE_PRESS_GAP_MS)
+    }
+
+    /// Returns a short press once its double-press window has expired.
+    pub fn poll(&mut self, now_ms: u64) -> Option<Press> {
+        if self.deadline().is_some_and(|deadline| now_ms >= deadline) {
+            self.pending_short = None;
+            Some(Press::Short)
+        } else {
+            None
+        }
+    }
+}
+
+/// The button task: classifies the presses of `input`, which goes low while the button is held,
+/// and passes each gesture to `send` once it is known. Runs until `input` fails.
+///
+/// Edges during a debounce are missed, so the level is read after each: a press shorter than the
+/// debounce has already ended, and a press may already have started.
+pub async fn run_button<P: Wait + InputPin>(input: &mut P, delay: &mut impl DelayNs, clock: &impl Monotonic, mut send: impl FnMut(Press)) -> Result<(), P::Error> {
+    let mut classifier = PressClassifier::new();
+    loop {
+        // Wait for a press, or for a pending short press to be confirmed.
+        if input.is_high()? {
+            if let Some(deadline) = classifier.deadline() {
+                let wait_ms = deadline.saturating_sub(clock.now_ms()) as u32;
+                match select(delay.delay_ms(wait_ms), input.wait_for_falling_edge()).await {
+                    Either::First(()) => {
+                        if let Some(press) = classifier.poll(clock.now_ms()) {
+                            send(press);
+                        }
+                        continue;
+                    }
+                    Either::Second(pressed) => pressed?,
+                }
+            } else {
+                input.wait_for_falling_edge().await?;
+            }
+        }
+        if let Some(press) = classifier.pressed(clock.now_ms()) {
+            send(press);
+        }
+        delay.delay_ms(BUTTON_DEBOUNCE_MS).await;
+        if input.is_low()? {
+            input.wait_for_rising_edge().await?;
+        }
+        if let Some(press) = classifier.released(clock.now_ms()) {
+            send(press);
+        }
+        delay.delay_ms(BUTTON_DEBOUNCE_MS).await;
+    }
+}
+
+/// What the firmware should do in response to a button gesture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UiAction {
+    ShowPage(DisplayPage),
+    AcknowledgeAlarms,
+}
+
+/// Menu state machine driven by button gestures.
+///
+/// Short press cycles the status pages, double press returns to the main status page,
+/// long press acknowledges alarms, and very long press enters or leaves diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Ui {
+    page: DisplayPage,
+}
+
+impl Ui {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn page(&self) -> DisplayPage {
+        self.page
+    }
+
+    pub fn handle(&mut self, press: Press) -> Option<UiAction> {
+        let page = match (press, self.page) {
+            (Press::Long, _) => return Some(UiAction::AcknowledgeAlarms),
+            (Press::VeryLong, DisplayPage::Diagnostics) => DisplayPage::Status,
+            (Press::VeryLong, _) => DisplayPage::Diagnostics,
+            // Diagnostics is only left with a very long press.
+            (_, DisplayPage::Diagnostics) => return None,
+            (Press::Short, DisplayPage::Status) => DisplayPage::TvcStats,
+            (Press::Short, DisplayPage::TvcStats) => DisplayPage::History,
+            (Press::Short, DisplayPage::History) => DisplayPage::Status,
+            (Press::Double, _) => DisplayPage::Status,
+        };
+        self.page = page;
+        Some(UiAction::ShowPage(page))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::{Cell, RefCell};
+    use core::future::poll_fn;
+    use core::task::Poll;
+    use embassy_futures::block_on;
+    use embedded_hal::digital::{ErrorKind, ErrorType};
+    use std::collections::VecDeque;
+
+    // A button pressed and released at set times, on a clock that only moves when the task waits.
+    struct Button {
+        now_ns: Cell<u64>,
+        level: Cell<bool>, // After the last edge passed.
+        edges: RefCell<VecDeque<(u64, bool)>>, // Time in ms and level after each edge, ending the task once they run out.
+    }
+
+    impl Button {
+        fn new(presses: &[(u64, u64)]) -> Self {
+            let edges = presses.iter().flat_map(|&(down, up)| [(down, false), (up, true)]).collect();
+            Self { now_ns: Cell::new(0), level: Cell::new(true), edges: RefCell::new(edges) }
+        }
+
+        // Skip to the next edge to `level`, passing over edges to the other level.
+        fn edge(&self, level: bool) -> Result<(), ErrorKind> {
+            loop {
+                let (at_ms, to) = self.edges.borrow_mut().pop_front().ok_or(ErrorKind::Other)?;
+                self.level.set(to);
+                // Edges while the task wasn't waiting are missed, as with an interrupt.
+                if at_ms * 1_000_000 >= self.now_ns.get() {
+                    self.now_ns.set(at_ms * 1_000_000);
+                    if to == level {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        // Wait `ns`, or give way once to an edge due sooner, e.g. one raced against the wait.
+        async fn sleep(&self, ns: u64) {
+            let until = self.now_ns.get() + ns;
+            let mut gave_way = false;
+            poll_fn(|_| {
+                if !gave_way && self.edges.borrow().front().is_some_and(|&(at_ms, _)| at_ms * 1_000_000 < until) {
+                    gave_way = true;
+                    return Poll::Pending;
+                }
+                self.now_ns.set(self.now_ns.get().max(until));
+                Poll::Ready(())
+            })
+            .await
+        }
+    }
+
+    impl ErrorType for &Button {
+        type Error = ErrorKind;
+    }
+
+    impl InputPin for &Button {
+        // Passes the edges up to now, which a wait will then have missed.
+        fn is_high(&mut self) -> Result<bool, ErrorKind> {
+            let mut edges = self.edges.borrow_mut();
+            while let Some(&(_, to)) = edges.front().filter(|&&(at_ms, _)| at_ms * 1_000_000 <= self.now_ns.get()) {
+                self.level.set(to);
+                edges.pop_front();
+            }
+            Ok(self.level.get())
+        }
+
+        fn is_low(&mut self) -> Result<bool, ErrorKind> {
+            Ok(!self.is_high()?)
+        }
+    }
+
+    impl Wait for &Button {
+        async fn wait_for_high(&mut self) -> Result<(), ErrorKind> {
+            self.edge(true)
+        }
+
+        async fn wait_for_low(&mut self) -> Result<(), ErrorKind> {
+            self.edge(false)
+        }
+
+        async fn wait_for_rising_edge(&mut self) -> Result<(), ErrorKind> {
+            self.edge(true)
+        }
+
+        async fn wait_for_falling_edge(&mut self) -> Result<(), ErrorKind> {
+            self.edge(false)
+        }
+
+        async fn wait_for_any_edge(&mut self) -> Result<(), ErrorKind> {
+            let level = self.edges.borrow().front().is_some_and(|&(_, to)| to);
+            self.edge(level)
+        }
+    }
+
+    impl DelayNs for &Button {
+        async fn delay_ns(&mut self, ns: u32) {
+            self.sleep(ns.into()).await;
+        }
+
+        async fn delay_ms(&mut self, ms: u32) {
+            self.sleep(u64::from(ms) * 1_000_000).await;
+        }
+    }
+
+    impl Monotonic for &Button {
+        fn now_ms(&self) -> u64 {
+            self.now_ns.get() / 1_000_000
+        }
+    }
+
+    #[test]
+    fn test_button_task() {
+        let button = Button::new(&[(1000, 1100), (5000, 5100), (5300, 5400), (8000, 8000 + LONG_PRESS_MS), (12_000, 12_100)]);
+        let mut presses = Vec::new();
+        let result = block_on(run_button(&mut &button, &mut &button, &&button, |press| presses.push((press, (&button).now_ms()))));
+        assert_eq!(result, Err(ErrorKind::Other)); // Out of presses.
+        // A short press is only known once its double-press window has passed.
+        let short = |released| (Press::Short, released + DOUBLE_PRESS_GAP_MS);
+        assert_eq!(presses, [short(1100), (Press::Double, 5400), (Press::Long, 8000 + LONG_PRESS_MS), short(12_100)]);
+    }
+
+    #[test]
+    fn test_press_shorter_than_debounce() {
+        let bounce = u64::from(BUTTON_DEBOUNCE_MS);
+        let button = Button::new(&[(1000, 1000 + bounce / 2), (3000, 3100), (3100 + bounce / 2, 3300)]);
+        let mut presses = Vec::new();
+        let result = block_on(run_button(&mut &button, &mut &button, &&button, |press| presses.push((press, (&button).now_ms()))));
+        assert_eq!(result, Err(ErrorKind::Other));
+        // The first press was over before its debounce ended, so it is released then. The last
+        // started during the debounce of a release, so it is taken then, and makes a double press.
+        assert_eq!(presses, [(Press::Short, 1000 + bounce + DOUBLE_PRESS_GAP_MS), (Press::Double, 3300)]);
+    }
+
+    #[test]
+    fn test_classify_long_presses() {
+        let mut classifier = PressClassifier::new();
+        classifier.pressed(0);
+        assert_eq!(classifier.released(LONG_PRESS_MS), Some(Press::Long));
+        classifier.pressed(10_000);
+        assert_eq!(classifier.released(10_000 + VERY_LONG_PRESS_MS), Some(Press::VeryLong));
+        assert_eq!(classifier.deadline(), None);
+    }
+
+    #[test]
+    fn test_classify_short_and_double() {
+        let mut classifier = PressClassifier::new();
+        classifier.pressed(0);
+        assert_eq!(classifier.released(100), None);
+        assert_eq!(classifier.deadline(), Some(100 + DOUBLE_PRESS_GAP_MS));
+        assert_eq!(classifier.poll(200), None);
+        assert_eq!(classifier.poll(100 + DOUBLE_PRESS_GAP_MS), Some(Press::Short));
+
+        classifier.pressed(1000);
+        assert_eq!(classifier.released(1100), None);
+        assert_eq!(classifier.pressed(1200), None);
+        assert_eq!(classifier.released(1300), Some(Press::Double));
+        assert_eq!(classifier.deadline(), None);
+    }
+
+    #[test]
+    fn test_expired_short_reported_on_next_press() {
+        let mut classifier = PressClassifier::new();
+        classifier.pressed(0);
+        classifier.released(100);
+        assert_eq!(classifier.pressed(2000), Some(Press::Short));
+        assert_eq!(classifier.released(2100), None);
+    }
+
+    #[test]
+    fn test_ui_navigation() {
+        let mut ui = Ui::new();
+        assert_eq!(ui.handle(Press::Short), Some(UiAction::ShowPage(DisplayPage::TvcStats)));
+        assert_eq!(ui.handle(Press::Short), Some(UiAction::ShowPage(DisplayPage::History)));
+        assert_eq!(ui.handle(Press::Short), Some(UiAction::ShowPage(DisplayPage::Status)));
+        assert_eq!(ui.handle(Press::Long), Some(UiAction::AcknowledgeAlarms));
+        assert_eq!(ui.handle(Press::VeryLong), Some(UiAction::ShowPage(DisplayPage::Diagnostics)));
+        assert_eq!(ui.handle(Press::Short), None);
+        assert_eq!(ui.handle(Press::Long), Some(UiAction::AcknowledgeAlarms));
+        assert_eq!(ui.handle(Press::VeryLong), Some(UiAction::ShowPage(DisplayPage::Status)));
+        ui.handle(Press::Short);
+        assert_eq!(ui.handle(Press::Double), Some(UiAction::ShowPage(DisplayPage::Status)));
+    }
+}
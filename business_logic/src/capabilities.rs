@@ -0,0 +1,155 @@
+/// Command byte of a `GetCapabilities` request, the whole payload of a bus frame.
+pub const GET_CAPABILITIES: u8 = 0x01;
+/// Length of the reply payload: the command byte, then the bitmap, little-endian.
+pub const CAPABILITIES_REPLY_LEN: usize = 1 + 4;
+
+/// Something a logger build may have, one bit each in `Capabilities`. Bits are never reused, so
+/// host tools can read any firmware's bitmap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Capability {
+    VaccineSensor = 0,
+    AmbientSensor = 1,
+    HumiditySensor = 2,
+    Accelerometer = 3,
+    ProbeChannels = 4, // External DS18B20 probes, see `Config::probe_roms`.
+    DoorSwitch = 5,
+    MainsSense = 6,
+    Display = 7,
+    RamStore = 8,
+    FlashStore = 9,
+    Uart = 16,
+    Rs485Bus = 17,
+    Usb = 18,
+    Nfc = 19,
+    Authentication = 24, // Firmware features compiled in, from here on.
+    Encryption = 25,
+    HumidityFeature = 26,
+    AccelerometerFeature = 27,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 18] = [
+        Capability::VaccineSensor,
+        Capability::AmbientSensor,
+        Capability::HumiditySensor,
+        Capability::Accelerometer,
+        Capability::ProbeChannels,
+        Capability::DoorSwitch,
+        Capability::MainsSense,
+        Capability::Display,
+        Capability::RamStore,
+        Capability::FlashStore,
+        Capability::Uart,
+        Capability::Rs485Bus,
+        Capability::Usb,
+        Capability::Nfc,
+        Capability::Authentication,
+        Capability::Encryption,
+        Capability::HumidityFeature,
+        Capability::AccelerometerFeature,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::VaccineSensor => "vaccine_sensor",
+            Capability::AmbientSensor => "ambient_sensor",
+            Capability::HumiditySensor => "humidity_sensor",
+            Capability::Accelerometer => "accelerometer",
+            Capability::ProbeChannels => "probe_channels",
+            Capability::DoorSwitch => "door_switch",
+            Capability::MainsSense => "mains_sense",
+            Capability::Display => "display",
+            Capability::RamStore => "ram_store",
+            Capability::FlashStore => "flash_store",
+            Capability::Uart => "uart",
+            Capability::Rs485Bus => "rs485_bus",
+            Capability::Usb => "usb",
+            Capability::Nfc => "nfc",
+            Capability::Authentication => "authentication",
+            Capability::Encryption => "encryption",
+            Capability::HumidityFeature => "humidity_feature",
+            Capability::AccelerometerFeature => "accelerometer_feature",
+        }
+    }
+
+    fn mask(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// What this logger has, so one host tool can adapt to every build: the hardware found at boot,
+/// and the firmware features compiled in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// The firmware features this build was compiled with, and no hardware yet.
+    pub fn compiled() -> Self {
+        let features = [
+            (Capability::Authentication, cfg!(feature = "authentication")),
+            (Capability::Encryption, cfg!(feature = "encryption")),
+            (Capability::HumidityFeature, cfg!(feature = "humidity")),
+            (Capability::AccelerometerFeature, cfg!(feature = "accelerometer")),
+        ];
+        features.into_iter().fold(Self::default(), |capabilities, (capability, present)| capabilities.with(capability, present))
+    }
+
+    /// These capabilities, with `capability` if `present`.
+    pub fn with(self, capability: Capability, present: bool) -> Self {
+        Self(if present { self.0 | capability.mask() } else { self.0 & !capability.mask() })
+    }
+
+    pub fn has(&self, capability: Capability) -> bool {
+        self.0 & capability.mask() != 0
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+
+    /// The reply to a bus frame's `payload`, if it is a `GetCapabilities` request.
+    pub fn answer(&self, payload: &[u8]) -> Option<[u8; CAPABILITIES_REPLY_LEN]> {
+        (payload == [GET_CAPABILITIES]).then(|| {
+            let mut reply = [GET_CAPABILITIES; CAPABILITIES_REPLY_LEN];
+            reply[1..].copy_from_slice(&self.0.to_le_bytes());
+            reply
+        })
+    }
+
+    /// Decode a reply from `answer`, for host tools. Bits this firmware doesn't know are kept.
+    pub fn from_reply(reply: &[u8]) -> Option<Self> {
+        match *reply {
+            [GET_CAPABILITIES, b0, b1, b2, b3] => Some(Self(u32::from_le_bytes([b0, b1, b2, b3]))),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bits_are_distinct() {
+        let all = Capability::ALL.iter().fold(Capabilities::default(), |capabilities, &capability| {
+            assert!(!capabilities.has(capability), "{} shares a bit", capability.name());
+            capabilities.with(capability, true)
+        });
+        assert_eq!(all.as_u32().count_ones() as usize, Capability::ALL.len());
+    }
+
+    #[test]
+    fn test_request_and_reply() {
+        let capabilities = Capabilities::compiled().with(Capability::VaccineSensor, true).with(Capability::RamStore, true).with(Capability::Usb, false);
+        assert!(capabilities.has(Capability::VaccineSensor));
+        assert_eq!(capabilities.has(Capability::Authentication), cfg!(feature = "authentication"));
+        assert!(!capabilities.has(Capability::FlashStore));
+        let reply = capabilities.answer(&[GET_CAPABILITIES]).unwrap();
+        assert_eq!(Capabilities::from_reply(&reply), Some(capabilities));
+        assert_eq!(capabilities.answer(&[GET_CAPABILITIES, 0]), None);
+        assert_eq!(capabilities.answer(&[]), None);
+        assert_eq!(Capabilities::from_reply(&reply[..4]), None);
+    }
+}
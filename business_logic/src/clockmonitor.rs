@@ -0,0 +1,99 @@
+use crate::log::{Log, LogCode};
+use crate::timestamp::Timestamp;
+
+/// The RTC standing still for this long by the monotonic timer means its crystal stopped.
+pub const RTC_STALL_MS: u64 = 3000;
+
+/// Watches the RTC against the monotonic timer, which runs from a different oscillator, and
+/// keeps time from the monotonic timer while the RTC's crystal has stopped.
+///
+/// Without it a broken or disturbed crystal freezes every timestamp until someone notices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ClockMonitor {
+    last_change: Option<(Timestamp, u64)>, // Latest RTC reading and the monotonic time it changed at, in ms.
+    degraded: bool, // Whether the RTC stopped, so the time is extrapolated from `last_change`.
+    lost_seconds: u32, // Time the RTC lost while stopped, added to its readings until it is corrected.
+}
+
+impl ClockMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take an RTC reading at monotonic time `now_ms`. Returns the time to use: the RTC's while it
+    /// ticks, extrapolated from its last change while it doesn't. Logs `ClockDegraded` when the RTC
+    /// stops and `ClockRestored` when it starts again.
+    pub fn update(&mut self, rtc: Timestamp, now_ms: u64, log: &mut impl Log) -> Timestamp {
+        let Some((last, changed_ms)) = self.last_change else {
+            self.last_change = Some((rtc, now_ms));
+            return self.corrected(rtc);
+        };
+        let still_ms = now_ms.saturating_sub(changed_ms);
+        let extrapolated = Timestamp { seconds: last.seconds.saturating_add((still_ms / 1000) as u32) };
+        if rtc != last {
+            if self.degraded {
+                // Ticking again, but behind by the time it stood still.
+                self.degraded = false;
+                let lost = extrapolated.seconds.saturating_sub(rtc.seconds);
+                self.lost_seconds = self.lost_seconds.saturating_add(lost);
+                log.info(LogCode::ClockRestored, lost);
+            }
+            self.last_change = Some((rtc, now_ms));
+            return self.corrected(rtc);
+        }
+        if !self.degraded && still_ms >= RTC_STALL_MS {
+            self.degraded = true;
+            log.warn(LogCode::ClockDegraded, still_ms as u32);
+        }
+        self.corrected(if self.degraded { extrapolated } else { rtc })
+    }
+
+    /// Whether the time is coming from the monotonic timer because the RTC stopped.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// Seconds to move the RTC forward by, to make up for the time it stood still.
+    /// Start a new monitor once the RTC is set.
+    pub fn correction_seconds(&self) -> u32 {
+        self.lost_seconds
+    }
+
+    fn corrected(&self, rtc: Timestamp) -> Timestamp {
+        Timestamp { seconds: rtc.seconds.saturating_add(self.lost_seconds) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::at;
+    use crate::log::{CaptureLog, Level};
+
+    #[test]
+    fn test_ticking_rtc_is_used() {
+        let mut monitor = ClockMonitor::new();
+        let mut log = CaptureLog::default();
+        for second in 0..10 {
+            assert_eq!(monitor.update(at(500 + second), u64::from(second) * 1000 + 400, &mut log), at(500 + second));
+            assert_eq!(monitor.update(at(500 + second), u64::from(second) * 1000 + 900, &mut log), at(500 + second));
+        }
+        assert!(!monitor.is_degraded() && log.entries.is_empty());
+    }
+
+    #[test]
+    fn test_stall_and_recovery() {
+        let mut monitor = ClockMonitor::new();
+        let mut log = CaptureLog::default();
+        monitor.update(at(100), 0, &mut log);
+        assert_eq!(monitor.update(at(100), 2_500, &mut log), at(100));
+        assert_eq!(monitor.update(at(100), 3_000, &mut log), at(103));
+        assert!(monitor.is_degraded());
+        assert_eq!(monitor.update(at(100), 60_000, &mut log), at(160));
+        // The crystal starts again: carry on from the extrapolated time until the RTC is corrected.
+        assert_eq!(monitor.update(at(101), 61_000, &mut log), at(161));
+        assert_eq!(monitor.update(at(102), 62_000, &mut log), at(162));
+        assert_eq!(monitor.correction_seconds(), 60);
+        assert_eq!(log.entries, [(Level::Warn, LogCode::ClockDegraded, 3_000), (Level::Info, LogCode::ClockRestored, 60)]);
+    }
+}
@@ -0,0 +1,331 @@
+use core::ops::RangeInclusive;
+
+use crate::alarm::AlarmProfile;
+use crate::log::{Log, LogCode};
+use crate::selftest::{SelfTestItem, SelfTestReport};
+use crate::timestamp::Timestamp;
+use crate::wallclock::epoch_anchor;
+#[cfg(feature = "authentication")]
+use crate::{
+    provisioning::DeviceKey,
+    authentication::{self, Authenticator, Tag},
+};
+
+/// Version of the `CommissioningRecord` serialization.
+pub const COMMISSIONING_RECORD_VERSION: u8 = 1;
+/// Length of a serialized `CommissioningRecord`.
+pub const COMMISSIONING_RECORD_LEN: usize = 2 + 4 + 4 + 4 + 5 * 4 + 1 + 4 + 4;
+/// Ambient readings accepted when checking the sensors, °C.
+pub const AMBIENT_RANGE_CELSIUS: RangeInclusive<f32> = -10.0..=50.0;
+
+/// Steps of the commissioning flow, in the order they are done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CommissioningStep {
+    SetTime,
+    SetThresholds,
+    SelfTest,
+    OpenDoor,
+    CloseDoor,
+    CheckSensors,
+    Complete,
+}
+
+impl CommissioningStep {
+    /// What to ask the operator for, over the serial console.
+    pub fn prompt(self) -> &'static str {
+        match self {
+            CommissioningStep::SetTime => "Set the clock",
+            CommissioningStep::SetThresholds => "Set the alarm thresholds",
+            CommissioningStep::SelfTest => "Run the self-test",
+            CommissioningStep::OpenDoor => "Open the door",
+            CommissioningStep::CloseDoor => "Close the door",
+            CommissioningStep::CheckSensors => "Wait for the appliance to reach temperature, then check the sensors",
+            CommissioningStep::Complete => "Commissioning complete",
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            CommissioningStep::SetTime => CommissioningStep::SetThresholds,
+            CommissioningStep::SetThresholds => CommissioningStep::SelfTest,
+            CommissioningStep::SelfTest => CommissioningStep::OpenDoor,
+            CommissioningStep::OpenDoor => CommissioningStep::CloseDoor,
+            CommissioningStep::CloseDoor => CommissioningStep::CheckSensors,
+            CommissioningStep::CheckSensors | CommissioningStep::Complete => CommissioningStep::Complete,
+        }
+    }
+}
+
+/// What the operator or the hardware did, fed to the wizard as it happens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommissioningInput {
+    TimeSet { clock: Timestamp, unix_seconds: u32 }, // The clock was set to `clock`, at this real UTC time.
+    ThresholdsSet(AlarmProfile),
+    SelfTestRun(SelfTestReport),
+    Door { open: bool }, // From the door switch, not the operator.
+    Readings { tvc: f32, tamb: f32 }, // Latest readings of the sensors, °C.
+}
+
+/// Why an input didn't complete its step. The wizard stays at the step, so it can be retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CommissioningError {
+    WrongStep, // The input doesn't belong to the current step, e.g. a door event while setting the clock.
+    InvalidTime, // The clock is further ahead of the real time than the Unix epoch, see `epoch_anchor`.
+    InvalidThresholds, // The low threshold isn't below the high one.
+    SelfTestFailed, // An item failed or wasn't tested.
+    OutOfRange, // The vaccine reading is outside the thresholds, or the ambient one outside `AMBIENT_RANGE_CELSIUS`.
+}
+
+/// What was checked when the logger was commissioned, kept as evidence of its installation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CommissioningRecord {
+    pub completed: Timestamp,
+    pub clock_set: Timestamp,
+    pub epoch_anchor: u32, // For `Config::epoch_anchor`.
+    pub profile: AlarmProfile,
+    pub self_test_passed: u8, // `SelfTestReport::passed_bitmap`.
+    pub tvc: f32, // Readings accepted by the sensor check, °C.
+    pub tamb: f32,
+}
+
+impl CommissioningRecord {
+    /// Serialize for the NV store: version, length, then the fields in declaration order.
+    pub fn to_bytes(&self) -> [u8; COMMISSIONING_RECORD_LEN] {
+        let mut bytes = [0u8; COMMISSIONING_RECORD_LEN];
+        bytes[0] = COMMISSIONING_RECORD_VERSION;
+        bytes[1] = COMMISSIONING_RECORD_LEN as u8;
+        let words = [
+            self.completed.seconds,
+            self.clock_set.seconds,
+            self.epoch_anchor,
+            self.profile.high_celsius.to_bits(),
+            self.profile.low_celsius.to_bits(),
+            self.profile.high_delay_seconds,
+            self.profile.low_delay_seconds,
+            self.profile.door_seconds,
+        ];
+        for (chunk, word) in bytes[2..34].chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes[34] = self.self_test_passed;
+        bytes[35..39].copy_from_slice(&self.tvc.to_le_bytes());
+        bytes[39..43].copy_from_slice(&self.tamb.to_le_bytes());
+        bytes
+    }
+
+    /// Deserialize what `to_bytes` wrote, returning the record and the bytes after it, e.g.
+    /// its MAC. None if they aren't a record of this or an earlier version.
+    pub fn from_bytes(bytes: &[u8]) -> Option<(Self, &[u8])> {
+        let (&version, &len) = (bytes.first()?, bytes.get(1)?);
+        if version == 0 || version > COMMISSIONING_RECORD_VERSION || usize::from(len) < COMMISSIONING_RECORD_LEN {
+            return None;
+        }
+        let (record, rest) = bytes.split_at_checked(usize::from(len))?;
+        let word = |offset: usize| u32::from_le_bytes([record[offset], record[offset + 1], record[offset + 2], record[offset + 3]]);
+        let record = Self {
+            completed: Timestamp { seconds: word(2) },
+            clock_set: Timestamp { seconds: word(6) },
+            epoch_anchor: word(10),
+            profile: AlarmProfile {
+                high_celsius: f32::from_bits(word(14)),
+                low_celsius: f32::from_bits(word(18)),
+                high_delay_seconds: word(22),
+                low_delay_seconds: word(26),
+                door_seconds: word(30),
+            },
+            self_test_passed: record[34],
+            tvc: f32::from_bits(word(35)),
+            tamb: f32::from_bits(word(39)),
+        };
+        Some((record, rest))
+    }
+
+    /// The device's MAC over `to_bytes`, so an auditor holding the device key can check the
+    /// record wasn't altered. Not a signature: anyone with the key could make one.
+    #[cfg(feature = "authentication")]
+    pub fn mac(&self, key: &DeviceKey) -> Tag {
+        let mut authenticator = Authenticator::new(key);
+        authenticator.update(&self.to_bytes());
+        authenticator.finish()
+    }
+
+    #[cfg(feature = "authentication")]
+    pub fn verify(&self, key: &DeviceKey, tag: &[u8]) -> bool {
+        authentication::verify(key, &self.to_bytes(), tag)
+    }
+}
+
+/// Guided commissioning, as a state machine stepping through `CommissioningStep`.
+///
+/// Each completed step is logged as `CommissioningStep`, and the last one as `Commissioned`, once
+/// the record is ready to be given its MAC and stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommissioningWizard {
+    step: CommissioningStep,
+    record: CommissioningRecord,
+}
+
+impl Default for CommissioningWizard {
+    fn default() -> Self {
+        Self {
+            step: CommissioningStep::SetTime,
+            record: CommissioningRecord {
+                completed: Timestamp { seconds: 0 },
+                clock_set: Timestamp { seconds: 0 },
+                epoch_anchor: 0,
+                profile: AlarmProfile::default(),
+                self_test_passed: 0,
+                tvc: 0.0,
+                tamb: 0.0,
+            },
+        }
+    }
+}
+
+impl CommissioningWizard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn step(&self) -> CommissioningStep {
+        self.step
+    }
+
+    /// The record of a completed commissioning, or None before the last step.
+    pub fn record(&self) -> Option<&CommissioningRecord> {
+        (self.step == CommissioningStep::Complete).then_some(&self.record)
+    }
+
+    /// Apply `input` made at `now`, returning the step to prompt for next.
+    pub fn input(&mut self, input: CommissioningInput, now: Timestamp, log: &mut impl Log) -> Result<CommissioningStep, CommissioningError> {
+        match (self.step, input) {
+            (CommissioningStep::SetTime, CommissioningInput::TimeSet { clock, unix_seconds }) => {
+                self.record.epoch_anchor = epoch_anchor(clock, unix_seconds).ok_or(CommissioningError::InvalidTime)?;
+                self.record.clock_set = clock;
+            }
+            (CommissioningStep::SetThresholds, CommissioningInput::ThresholdsSet(profile)) => {
+                if profile.low_celsius.partial_cmp(&profile.high_celsius) != Some(core::cmp::Ordering::Less) {
+                    return Err(CommissioningError::InvalidThresholds);
+                }
+                self.record.profile = profile;
+            }
+            (CommissioningStep::SelfTest, CommissioningInput::SelfTestRun(report)) => {
+                if !SelfTestItem::ALL.iter().all(|&item| report.result(item) == Some(true)) {
+                    return Err(CommissioningError::SelfTestFailed);
+                }
+                self.record.self_test_passed = report.passed_bitmap();
+            }
+            (CommissioningStep::OpenDoor, CommissioningInput::Door { open: true }) => {}
+            (CommissioningStep::CloseDoor, CommissioningInput::Door { open: false }) => {}
+            (CommissioningStep::CheckSensors, CommissioningInput::Readings { tvc, tamb }) => {
+                let profile = &self.record.profile;
+                if profile.is_high(tvc) || profile.is_low(tvc) || !AMBIENT_RANGE_CELSIUS.contains(&tamb) {
+                    return Err(CommissioningError::OutOfRange);
+                }
+                (self.record.tvc, self.record.tamb) = (tvc, tamb);
+                self.record.completed = now;
+            }
+            _ => return Err(CommissioningError::WrongStep),
+        }
+        log.info(LogCode::CommissioningStep, self.step as u32);
+        self.step = self.step.next();
+        if self.step == CommissioningStep::Complete {
+            log.info(LogCode::Commissioned, now.seconds);
+        }
+        Ok(self.step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::at;
+    use crate::log::{CaptureLog, Level};
+
+    fn passed_self_test() -> SelfTestReport {
+        let mut report = SelfTestReport::new();
+        for item in SelfTestItem::ALL {
+            report.record(item, true);
+        }
+        report
+    }
+
+    fn commissioned(log: &mut CaptureLog) -> CommissioningWizard {
+        let mut wizard = CommissioningWizard::new();
+        let inputs = [
+            CommissioningInput::TimeSet { clock: at(1000), unix_seconds: 1_700_000_000 },
+            CommissioningInput::ThresholdsSet(AlarmProfile::FRIDGE),
+            CommissioningInput::SelfTestRun(passed_self_test()),
+            CommissioningInput::Door { open: true },
+            CommissioningInput::Door { open: false },
+            CommissioningInput::Readings { tvc: 5.0, tamb: 24.0 },
+        ];
+        for (seconds, input) in (1000..).step_by(60).zip(inputs) {
+            assert!(wizard.input(input, at(seconds), log).is_ok());
+        }
+        wizard
+    }
+
+    #[test]
+    fn test_complete_flow() {
+        let mut log = CaptureLog::default();
+        let wizard = commissioned(&mut log);
+        assert_eq!(wizard.step(), CommissioningStep::Complete);
+        let record = wizard.record().unwrap();
+        assert_eq!((record.clock_set, record.completed, record.epoch_anchor), (at(1000), at(1300), 1_699_999_000));
+        assert_eq!((record.self_test_passed, record.tvc, record.tamb), (0x3F, 5.0, 24.0));
+        assert_eq!(log.entries.len(), 7);
+        assert_eq!(log.entries[3], (Level::Info, LogCode::CommissioningStep, CommissioningStep::OpenDoor as u32));
+        assert_eq!(log.entries[6], (Level::Info, LogCode::Commissioned, 1300));
+    }
+
+    #[test]
+    fn test_failed_checks_stay_at_step() {
+        let mut wizard = CommissioningWizard::new();
+        let mut log = CaptureLog::default();
+        assert_eq!(wizard.input(CommissioningInput::Door { open: true }, at(0), &mut log), Err(CommissioningError::WrongStep));
+        assert_eq!(wizard.input(CommissioningInput::TimeSet { clock: at(2000), unix_seconds: 1000 }, at(0), &mut log), Err(CommissioningError::InvalidTime));
+        assert_eq!(wizard.input(CommissioningInput::TimeSet { clock: at(0), unix_seconds: 1000 }, at(0), &mut log), Ok(CommissioningStep::SetThresholds));
+        let inverted = AlarmProfile { low_celsius: 9.0, ..AlarmProfile::FRIDGE };
+        assert_eq!(wizard.input(CommissioningInput::ThresholdsSet(inverted), at(0), &mut log), Err(CommissioningError::InvalidThresholds));
+        assert_eq!(wizard.input(CommissioningInput::ThresholdsSet(AlarmProfile::FRIDGE), at(0), &mut log), Ok(CommissioningStep::SelfTest));
+        let mut untested = SelfTestReport::new();
+        untested.record(SelfTestItem::Sensors, true);
+        assert_eq!(wizard.input(CommissioningInput::SelfTestRun(untested), at(0), &mut log), Err(CommissioningError::SelfTestFailed));
+        assert_eq!(wizard.input(CommissioningInput::SelfTestRun(passed_self_test()), at(0), &mut log), Ok(CommissioningStep::OpenDoor));
+        assert_eq!(wizard.input(CommissioningInput::Door { open: false }, at(0), &mut log), Err(CommissioningError::WrongStep));
+        wizard.input(CommissioningInput::Door { open: true }, at(0), &mut log).unwrap();
+        wizard.input(CommissioningInput::Door { open: false }, at(0), &mut log).unwrap();
+        for (tvc, tamb) in [(9.0, 24.0), (-1.0, 24.0), (5.0, 60.0)] {
+            assert_eq!(wizard.input(CommissioningInput::Readings { tvc, tamb }, at(0), &mut log), Err(CommissioningError::OutOfRange));
+        }
+        assert_eq!(wizard.record(), None);
+        assert_eq!(log.entries.len(), 5);
+    }
+
+    #[test]
+    fn test_record_bytes() {
+        let record = *commissioned(&mut CaptureLog::default()).record().unwrap();
+        let mut stored = record.to_bytes().to_vec();
+        assert_eq!(&stored[..2], &[COMMISSIONING_RECORD_VERSION, COMMISSIONING_RECORD_LEN as u8]);
+        stored.extend_from_slice(&[0xA5; 4]); // A MAC after it.
+        assert_eq!(CommissioningRecord::from_bytes(&stored), Some((record, &[0xA5; 4][..])));
+        assert_eq!(CommissioningRecord::from_bytes(&stored[..COMMISSIONING_RECORD_LEN - 1]), None);
+        stored[0] = COMMISSIONING_RECORD_VERSION + 1;
+        assert_eq!(CommissioningRecord::from_bytes(&stored), None);
+    }
+
+    #[cfg(feature = "authentication")]
+    #[test]
+    fn test_authenticated_record() {
+        let key = DeviceKey([7; crate::provisioning::DEVICE_KEY_LEN]);
+        let record = *commissioned(&mut CaptureLog::default()).record().unwrap();
+        let mac = record.mac(&key);
+        assert!(record.verify(&key, &mac));
+        let altered = CommissioningRecord { tvc: 4.0, ..record };
+        assert!(!altered.verify(&key, &mac));
+    }
+}
@@ -0,0 +1,238 @@
+use arrayvec::ArrayString;
+use core::fmt::Write;
+
+use crate::crc::crc32;
+
+/// Longest placement identifier, e.g. "COLDROOM2-SHELF3".
+pub const PLACEMENT_ID_LEN: usize = 16;
+/// Size of a serialized compliance block: magic, classification, range, resolution, placement, CRC-32.
+pub const COMPLIANCE_BLOCK_LEN: usize = 4 + 1 + 1 + 1 + 1 + 2 + 2 + 2 + 2 + PLACEMENT_ID_LEN + 4;
+/// Length of the compliance header line.
+pub const COMPLIANCE_HEADER_LEN: usize = 80;
+const COMPLIANCE_MAGIC: u32 = 0x3832_4E45; // "EN28"
+
+/// What EN 12830 classifies the recorder for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Application {
+    Storage, // "S"
+    Transport, // "T"
+}
+
+/// Climatic environment the recorder is classified for, from indoor (A) to outdoor extremes (D).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Environment {
+    A,
+    B,
+    C,
+    D,
+}
+
+/// EN 12830 accuracy class: the largest permitted error over the measurement range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AccuracyClass {
+    Class0_2,
+    Class0_5,
+    Class1,
+    Class2,
+}
+
+impl AccuracyClass {
+    /// Largest permitted error, °C.
+    pub fn max_error_celsius(self) -> f32 {
+        match self {
+            AccuracyClass::Class0_2 => 0.2,
+            AccuracyClass::Class0_5 => 0.5,
+            AccuracyClass::Class1 => 1.0,
+            AccuracyClass::Class2 => 2.0,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            AccuracyClass::Class0_2 => "0.2",
+            AccuracyClass::Class0_5 => "0.5",
+            AccuracyClass::Class1 => "1",
+            AccuracyClass::Class2 => "2",
+        }
+    }
+}
+
+/// EN 12830 classification and placement of one recorder, written at the factory or by the
+/// installer's provisioning tool, for the device status and report headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ComplianceInfo {
+    pub application: Application,
+    pub environment: Environment,
+    pub accuracy: AccuracyClass,
+    pub range_min_celsius: i16,
+    pub range_max_celsius: i16,
+    pub resolution_millicelsius: u16, // Smallest change the recorder shows, m°C.
+    pub placement: [u8; PLACEMENT_ID_LEN], // ASCII, padded with zeros.
+}
+
+impl ComplianceInfo {
+    /// Whether the fields make sense together: a non-empty range, and a resolution no coarser
+    /// than the accuracy class.
+    pub fn is_valid(&self) -> bool {
+        self.range_min_celsius < self.range_max_celsius
+            && self.resolution_millicelsius > 0
+            && f32::from(self.resolution_millicelsius) / 1000.0 <= self.accuracy.max_error_celsius()
+            && self.placement.is_ascii()
+    }
+
+    /// The placement identifier, without padding.
+    pub fn placement(&self) -> &str {
+        let len = self.placement.iter().position(|&byte| byte == 0).unwrap_or(PLACEMENT_ID_LEN);
+        core::str::from_utf8(&self.placement[..len]).unwrap_or("")
+    }
+
+    /// Set the placement identifier. Returns false, leaving it unchanged, if it is too long or not ASCII.
+    pub fn set_placement(&mut self, placement: &str) -> bool {
+        if placement.len() > PLACEMENT_ID_LEN || !placement.is_ascii() {
+            return false;
+        }
+        self.placement = [0; PLACEMENT_ID_LEN];
+        self.placement[..placement.len()].copy_from_slice(placement.as_bytes());
+        true
+    }
+
+    /// Classification as marked on the recorder, e.g. "EN 12830 S A 1 -30..70C".
+    pub fn classification(&self) -> ArrayString<32> {
+        let application = match self.application {
+            Application::Storage => 'S',
+            Application::Transport => 'T',
+        };
+        let mut classification = ArrayString::new();
+        let _ = write!(
+            classification,
+            "EN 12830 {} {:?} {} {}..{}C",
+            application,
+            self.environment,
+            self.accuracy.label(),
+            self.range_min_celsius,
+            self.range_max_celsius,
+        );
+        classification
+    }
+
+    /// One-line header for the device status and reports.
+    pub fn header(&self) -> ArrayString<COMPLIANCE_HEADER_LEN> {
+        let mut header = ArrayString::new();
+        let _ = write!(
+            header,
+            "{} RES {}.{:03}C AT {}",
+            self.classification().as_str(),
+            self.resolution_millicelsius / 1000,
+            self.resolution_millicelsius % 1000,
+            self.placement(),
+        );
+        header
+    }
+
+    pub fn to_bytes(&self) -> [u8; COMPLIANCE_BLOCK_LEN] {
+        let mut bytes = [0u8; COMPLIANCE_BLOCK_LEN];
+        bytes[0..4].copy_from_slice(&COMPLIANCE_MAGIC.to_le_bytes());
+        bytes[4] = self.application as u8;
+        bytes[5] = self.environment as u8;
+        bytes[6] = self.accuracy as u8;
+        // Byte 7 is reserved, keeping the fields after it aligned.
+        bytes[8..10].copy_from_slice(&self.range_min_celsius.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.range_max_celsius.to_le_bytes());
+        bytes[12..14].copy_from_slice(&self.resolution_millicelsius.to_le_bytes());
+        // Bytes 14..16 are reserved.
+        bytes[16..16 + PLACEMENT_ID_LEN].copy_from_slice(&self.placement);
+        let crc = crc32(0, &bytes[..COMPLIANCE_BLOCK_LEN - 4]);
+        bytes[COMPLIANCE_BLOCK_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Decode a stored block. Returns None if it was never written, is damaged or doesn't make sense.
+    pub fn from_bytes(bytes: &[u8; COMPLIANCE_BLOCK_LEN]) -> Option<Self> {
+        let word = |offset: usize| u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+        let half = |offset: usize| [bytes[offset], bytes[offset + 1]];
+        if word(0) != COMPLIANCE_MAGIC || word(COMPLIANCE_BLOCK_LEN - 4) != crc32(0, &bytes[..COMPLIANCE_BLOCK_LEN - 4]) {
+            return None;
+        }
+        let mut placement = [0u8; PLACEMENT_ID_LEN];
+        placement.copy_from_slice(&bytes[16..16 + PLACEMENT_ID_LEN]);
+        let info = Self {
+            application: match bytes[4] {
+                0 => Application::Storage,
+                1 => Application::Transport,
+                _ => return None,
+            },
+            environment: match bytes[5] {
+                0 => Environment::A,
+                1 => Environment::B,
+                2 => Environment::C,
+                3 => Environment::D,
+                _ => return None,
+            },
+            accuracy: match bytes[6] {
+                0 => AccuracyClass::Class0_2,
+                1 => AccuracyClass::Class0_5,
+                2 => AccuracyClass::Class1,
+                3 => AccuracyClass::Class2,
+                _ => return None,
+            },
+            range_min_celsius: i16::from_le_bytes(half(8)),
+            range_max_celsius: i16::from_le_bytes(half(10)),
+            resolution_millicelsius: u16::from_le_bytes(half(12)),
+            placement,
+        };
+        info.is_valid().then_some(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> ComplianceInfo {
+        let mut info = ComplianceInfo {
+            application: Application::Storage,
+            environment: Environment::A,
+            accuracy: AccuracyClass::Class1,
+            range_min_celsius: -30,
+            range_max_celsius: 70,
+            resolution_millicelsius: 100,
+            placement: [0; PLACEMENT_ID_LEN],
+        };
+        assert!(info.set_placement("COLDROOM2-SHELF3"));
+        info
+    }
+
+    #[test]
+    fn test_header() {
+        let info = info();
+        assert_eq!(info.classification().as_str(), "EN 12830 S A 1 -30..70C");
+        assert_eq!(info.header().as_str(), "EN 12830 S A 1 -30..70C RES 0.100C AT COLDROOM2-SHELF3");
+    }
+
+    #[test]
+    fn test_validity_and_placement() {
+        let mut info = info();
+        assert!(info.is_valid());
+        assert!(!info.set_placement("COLDROOM2-SHELF3X"));
+        assert!(info.set_placement("FRIDGE1"));
+        assert_eq!(info.placement(), "FRIDGE1");
+        assert!(!ComplianceInfo { range_max_celsius: -30, ..info }.is_valid());
+        assert!(!ComplianceInfo { resolution_millicelsius: 0, ..info }.is_valid());
+        // A 0.5 °C display can't back up a 0.2 °C accuracy claim.
+        assert!(!ComplianceInfo { accuracy: AccuracyClass::Class0_2, resolution_millicelsius: 500, ..info }.is_valid());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let info = info();
+        let mut bytes = info.to_bytes();
+        assert_eq!(ComplianceInfo::from_bytes(&bytes), Some(info));
+        assert_eq!(ComplianceInfo::from_bytes(&[0xFF; COMPLIANCE_BLOCK_LEN]), None); // Erased.
+        bytes[20] ^= 1;
+        assert_eq!(ComplianceInfo::from_bytes(&bytes), None);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::timestamp::Timestamp;
+
+/// Debounced changes of the compressor activity input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CompressorEvent {
+    Started,
+    Stopped,
+}
+
+/// Tracks compressor run time and number of starts from `CompressorEvent`s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Compressor {
+    running_since: Option<Timestamp>,
+    run_seconds: u32,
+    starts: u32,
+}
+
+impl Compressor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update the run time bookkeeping with an event that occurred at `timestamp`.
+    /// Repeated events (e.g. two `Started` in a row) are ignored.
+    pub fn process_event(&mut self, event: CompressorEvent, timestamp: Timestamp) {
+        match (event, self.running_since) {
+            (CompressorEvent::Started, None) => {
+                self.running_since = Some(timestamp);
+                self.starts += 1;
+            }
+            (CompressorEvent::Stopped, Some(since)) => {
+                self.run_seconds += timestamp.seconds.saturating_sub(since.seconds);
+                self.running_since = None;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running_since.is_some()
+    }
+
+    /// Total seconds the compressor has run, including the current run up to `now`.
+    pub fn compressor_run_seconds(&self, now: Timestamp) -> u32 {
+        match self.running_since {
+            Some(since) => self.run_seconds + now.seconds.saturating_sub(since.seconds),
+            None => self.run_seconds,
+        }
+    }
+
+    /// Number of times the compressor has started.
+    pub fn starts(&self) -> u32 {
+        self.starts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_seconds() {
+        let mut compressor = Compressor::new();
+        compressor.process_event(CompressorEvent::Started, Timestamp { seconds: 100 });
+        assert!(compressor.is_running());
+        assert_eq!(compressor.compressor_run_seconds(Timestamp { seconds: 150 }), 50);
+        compressor.process_event(CompressorEvent::Stopped, Timestamp { seconds: 400 });
+        assert_eq!(compressor.compressor_run_seconds(Timestamp { seconds: 1000 }), 300);
+        compressor.process_event(CompressorEvent::Started, Timestamp { seconds: 1000 });
+        assert_eq!(compressor.compressor_run_seconds(Timestamp { seconds: 1010 }), 310);
+        assert_eq!(compressor.starts(), 2);
+    }
+
+    #[test]
+    fn test_repeated_events_ignored() {
+        let mut compressor = Compressor::new();
+        compressor.process_event(CompressorEvent::Stopped, Timestamp { seconds: 10 });
+        assert_eq!(compressor.compressor_run_seconds(Timestamp { seconds: 20 }), 0);
+        compressor.process_event(CompressorEvent::Started, Timestamp { seconds: 20 });
+        compressor.process_event(CompressorEvent::Started, Timestamp { seconds: 30 });
+        compressor.process_event(CompressorEvent::Stopped, Timestamp { seconds: 40 });
+        assert_eq!(compressor.compressor_run_seconds(Timestamp { seconds: 40 }), 20);
+        assert_eq!(compressor.starts(), 1);
+    }
+}
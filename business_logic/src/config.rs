@@ -0,0 +1,546 @@
+use crate::aggregator::Channel;
+use crate::alarm::{AlarmKind, AlarmProfile, DOOR_ALARM_SECONDS, FREEZE_ALARM_CELSIUS, FREEZE_ALARM_DELAY_SECONDS, HIGH_ALARM_CELSIUS, HIGH_ALARM_DELAY_SECONDS};
+use crate::bus::{is_valid_address, DEFAULT_BUS_ADDRESS};
+use crate::crosscheck::{ProbeCrossCheck, DEFAULT_PROBE_DISAGREE_CELSIUS, DEFAULT_PROBE_DISAGREE_SAMPLES};
+use crate::display::{DisplayFilter, DEFAULT_DISPLAY_FILTER_SAMPLES, MAX_DISPLAY_FILTER_SAMPLES};
+use crate::door::{DoorSwitchConfig, SwitchPolarity, SwitchPull, MAX_DOOR_DEBOUNCE_MS};
+use crate::localtime::{LocalTime, UTC_OFFSET_RANGE_MINUTES};
+use crate::log::{Log, LogCode};
+use crate::logger::SamplePolicy;
+use crate::onewire::{Rom, DS18B20_FAMILY};
+use crate::relay::DEFAULT_RELAY_MASK;
+use crate::sampling::{AdaptiveSampling, DEFAULT_FAST_PERIOD_SECONDS, DEFAULT_NORMAL_PERIOD_SECONDS};
+use crate::selfheating::{SelfHeatingModel, DEFAULT_SELF_HEATING_CELSIUS_PER_SECOND, DEFAULT_SELF_HEATING_TIME_CONSTANT_SECONDS};
+use crate::timestamp::Timestamp;
+use crate::units::TemperatureUnit;
+use crate::wallclock::epoch_anchor;
+
+/// Layout version written by `Config::to_bytes`.
+///
+/// New versions only append fields, so a record from an older version is migrated by
+/// giving the missing fields their defaults.
+pub const CONFIG_VERSION: u8 = 15;
+/// Length of the persisted configuration in bytes, including the two header bytes.
+pub const CONFIG_RECORD_LEN: usize = 2 + 7 * 4 + 2 + 4 + 1 + 4 + 2 + 1 + PROBE_CHANNELS.len() * 8 + 4 + 4 + 1 + 1 + 2 + 4 + 1 + 2 + 1 + 1 + 1 + 4 + 4 + 1;
+const _: () = assert!(CONFIG_RECORD_LEN <= u8::MAX as usize); // It goes in the length byte.
+/// Serial speed used unless configured otherwise.
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+/// Channels measured by external DS18B20 probes, in the order of `Config::probe_roms`.
+pub const PROBE_CHANNELS: [Channel; 2] = [Channel::Evaporator, Channel::Condenser];
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// Why a configuration was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    SamplePeriod, // Zero, the fast period is longer than the normal one, or the phase isn't within the normal one.
+    AlarmThresholds, // The freeze threshold is not below the high threshold.
+    RecordPeriod, // Does not divide a day evenly.
+    UtcOffset, // Outside `UTC_OFFSET_RANGE_MINUTES`.
+    ProbeRom, // Not a valid DS18B20 ROM code, or mapped to two channels.
+    SelfHeating, // Negative warming, or a zero time constant.
+    DoorDebounce, // Zero, or above `MAX_DOOR_DEBOUNCE_MS`.
+    BaudRate,
+    BusAddress, // The broadcast address, or above `MAX_BUS_ADDRESS`.
+    ReportTime, // Not a time of day.
+    RelayMask, // Has a bit that isn't an `AlarmKind`.
+    DisplayFilter, // Zero, or above `MAX_DISPLAY_FILTER_SAMPLES`.
+    ProbeCrossCheck, // A delta that isn't positive, or zero samples.
+    UnsupportedVersion, // Written by newer firmware.
+    Corrupt, // Too short for its version, or a field is out of range.
+}
+
+/// Settings that can change without a firmware update.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    pub normal_period_seconds: u32,
+    pub fast_period_seconds: u32,
+    pub high_alarm_celsius: f32,
+    pub freeze_alarm_celsius: f32,
+    pub high_alarm_delay_seconds: u32,
+    pub freeze_alarm_delay_seconds: u32,
+    pub door_alarm_seconds: u32,
+    pub buzzer_enabled: bool,
+    pub door_alarm_enabled: bool,
+    pub baud_rate: u32,
+    pub display_unit: TemperatureUnit,
+    pub record_period_seconds: u32, // Added in version 2.
+    pub utc_offset_minutes: i16, // Added in version 3.
+    pub indicator_mode: bool, // Latch excursions like a shipping indicator. Added in version 4.
+    pub probe_roms: [Option<Rom>; PROBE_CHANNELS.len()], // DS18B20 probe for each of `PROBE_CHANNELS`. Added in version 5.
+    pub self_heating_celsius_per_second: f32, // Added in version 6.
+    pub self_heating_time_constant_seconds: u32, // Added in version 6.
+    pub door_switch: DoorSwitchConfig, // Added in version 7.
+    pub sample_phase_seconds: u32, // Offset of the samples from the wall-clock period boundaries. Added in version 8.
+    pub bus_address: u8, // Address on a shared RS-485 bus. Added in version 9.
+    pub daily_report_minute: Option<u16>, // Local time of the daily report, minutes after midnight; None to leave it to the host. Added in version 10.
+    pub alarm_relay_mask: u8, // Alarm classes that assert the relay output, bit `AlarmKind as u8`. Added in version 11.
+    pub test_mode: bool, // Accept `simulate` commands, for commissioning checks. Added in version 12.
+    pub display_filter_samples: u8, // Readings averaged for the display, 1 to show them raw. Added in version 13.
+    pub epoch_anchor: Option<u32>, // Unix time of `seconds = 0`, see `wallclock::epoch_anchor`; None until commissioned. Added in version 14.
+    pub probe_disagree_celsius: f32, // Largest deviation between redundant vaccine probes that counts as agreement. Added in version 15.
+    pub probe_disagree_samples: u8, // Readings in a row the probes must disagree for before switching over. Added in version 15.
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            normal_period_seconds: DEFAULT_NORMAL_PERIOD_SECONDS,
+            fast_period_seconds: DEFAULT_FAST_PERIOD_SECONDS,
+            high_alarm_celsius: HIGH_ALARM_CELSIUS,
+            freeze_alarm_celsius: FREEZE_ALARM_CELSIUS,
+            high_alarm_delay_seconds: HIGH_ALARM_DELAY_SECONDS,
+            freeze_alarm_delay_seconds: FREEZE_ALARM_DELAY_SECONDS,
+            door_alarm_seconds: DOOR_ALARM_SECONDS,
+            buzzer_enabled: true,
+            door_alarm_enabled: true,
+            baud_rate: DEFAULT_BAUD_RATE,
+            display_unit: TemperatureUnit::Celsius,
+            record_period_seconds: SamplePolicy::STANDARD.record_period_seconds,
+            utc_offset_minutes: 0,
+            indicator_mode: false,
+            probe_roms: [None; PROBE_CHANNELS.len()],
+            self_heating_celsius_per_second: DEFAULT_SELF_HEATING_CELSIUS_PER_SECOND,
+            self_heating_time_constant_seconds: DEFAULT_SELF_HEATING_TIME_CONSTANT_SECONDS,
+            door_switch: DoorSwitchConfig::default(),
+            sample_phase_seconds: 0,
+            bus_address: DEFAULT_BUS_ADDRESS,
+            daily_report_minute: None,
+            alarm_relay_mask: DEFAULT_RELAY_MASK,
+            test_mode: false,
+            display_filter_samples: DEFAULT_DISPLAY_FILTER_SAMPLES,
+            epoch_anchor: None,
+            probe_disagree_celsius: DEFAULT_PROBE_DISAGREE_CELSIUS,
+            probe_disagree_samples: DEFAULT_PROBE_DISAGREE_SAMPLES,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.fast_period_seconds == 0
+            || self.fast_period_seconds > self.normal_period_seconds
+            || self.sample_phase_seconds >= self.normal_period_seconds
+        {
+            return Err(ConfigError::SamplePeriod);
+        }
+        if self.freeze_alarm_celsius.partial_cmp(&self.high_alarm_celsius) != Some(core::cmp::Ordering::Less) {
+            return Err(ConfigError::AlarmThresholds);
+        }
+        if !(1200..=1_000_000).contains(&self.baud_rate) {
+            return Err(ConfigError::BaudRate);
+        }
+        if !is_valid_address(self.bus_address) {
+            return Err(ConfigError::BusAddress);
+        }
+        if !self.sample_policy().is_valid() {
+            return Err(ConfigError::RecordPeriod);
+        }
+        if !UTC_OFFSET_RANGE_MINUTES.contains(&self.utc_offset_minutes) {
+            return Err(ConfigError::UtcOffset);
+        }
+        for (index, rom) in self.probe_roms.iter().enumerate() {
+            if let Some(rom) = rom
+                && (!rom.is_valid() || rom.family() != DS18B20_FAMILY || self.probe_roms[..index].contains(&Some(*rom)))
+            {
+                return Err(ConfigError::ProbeRom);
+            }
+        }
+        if !self.self_heating().is_valid() {
+            return Err(ConfigError::SelfHeating);
+        }
+        if !(1..=MAX_DOOR_DEBOUNCE_MS).contains(&self.door_switch.debounce_ms) {
+            return Err(ConfigError::DoorDebounce);
+        }
+        if self.daily_report_minute.is_some_and(|minute| minute >= MINUTES_PER_DAY) {
+            return Err(ConfigError::ReportTime);
+        }
+        if self.alarm_relay_mask >> AlarmKind::ALL.len() != 0 {
+            return Err(ConfigError::RelayMask);
+        }
+        if !(1..=MAX_DISPLAY_FILTER_SAMPLES).contains(&self.display_filter_samples) {
+            return Err(ConfigError::DisplayFilter);
+        }
+        if !(self.probe_disagree_celsius > 0.0 && self.probe_disagree_celsius.is_finite()) || self.probe_disagree_samples == 0 {
+            return Err(ConfigError::ProbeCrossCheck);
+        }
+        Ok(())
+    }
+
+    /// Sampling periods for the temperature task.
+    pub fn sampling(&self) -> AdaptiveSampling {
+        AdaptiveSampling {
+            normal_period_seconds: self.normal_period_seconds,
+            fast_period_seconds: self.fast_period_seconds,
+            phase_offset_seconds: self.sample_phase_seconds,
+            ..AdaptiveSampling::default()
+        }
+    }
+
+    /// Record timing for the logger.
+    pub fn sample_policy(&self) -> SamplePolicy {
+        SamplePolicy::with_record_period(self.record_period_seconds)
+    }
+
+    /// Coefficients for removing the sensors' self-heating from readings.
+    pub fn self_heating(&self) -> SelfHeatingModel {
+        SelfHeatingModel {
+            celsius_per_second: self.self_heating_celsius_per_second,
+            time_constant_seconds: self.self_heating_time_constant_seconds,
+        }
+    }
+
+    /// Alarm thresholds and delays for the logger and the annunciator.
+    pub fn alarm_profile(&self) -> AlarmProfile {
+        AlarmProfile {
+            high_celsius: self.high_alarm_celsius,
+            low_celsius: self.freeze_alarm_celsius,
+            high_delay_seconds: self.high_alarm_delay_seconds,
+            low_delay_seconds: self.freeze_alarm_delay_seconds,
+            door_seconds: self.door_alarm_seconds,
+        }
+    }
+
+    /// Replace all alarm thresholds and delays with those of `profile`.
+    pub fn set_alarm_profile(&mut self, profile: AlarmProfile) {
+        self.high_alarm_celsius = profile.high_celsius;
+        self.freeze_alarm_celsius = profile.low_celsius;
+        self.high_alarm_delay_seconds = profile.high_delay_seconds;
+        self.freeze_alarm_delay_seconds = profile.low_delay_seconds;
+        self.door_alarm_seconds = profile.door_seconds;
+    }
+
+    /// Follow the clock being set from `before` to `after`, as in `LoggerEvent::ClockSet`, so the
+    /// epoch anchor still gives the real times of the records from then on. `unix_seconds` is
+    /// the real time it was set at if known, e.g. from a host; otherwise the anchor moves by the
+    /// change, and stays unknown if it was. Returns whether the anchor changed.
+    pub fn clock_set(&mut self, before: Timestamp, after: Timestamp, unix_seconds: Option<u32>) -> bool {
+        let anchor = match unix_seconds {
+            Some(unix_seconds) => epoch_anchor(after, unix_seconds),
+            None => self
+                .epoch_anchor
+                .and_then(|anchor| u32::try_from(i64::from(anchor) + i64::from(before.seconds) - i64::from(after.seconds)).ok()),
+        };
+        let changed = anchor != self.epoch_anchor;
+        self.epoch_anchor = anchor;
+        changed
+    }
+
+    /// Local time without daylight saving, which is configured separately.
+    pub fn local_time(&self) -> LocalTime {
+        LocalTime::new(self.utc_offset_minutes).unwrap_or_default()
+    }
+
+    /// Smoothing of the temperatures on the display.
+    pub fn display_filter(&self) -> DisplayFilter {
+        DisplayFilter::new(self.display_filter_samples)
+    }
+
+    /// Cross-checking of redundant vaccine probes.
+    pub fn probe_cross_check(&self) -> ProbeCrossCheck {
+        ProbeCrossCheck::new(self.probe_disagree_celsius, self.probe_disagree_samples)
+    }
+
+    /// The channel measured by the probe with ROM code `rom`, if it is mapped to one.
+    pub fn probe_channel(&self, rom: Rom) -> Option<Channel> {
+        let index = self.probe_roms.iter().position(|&mapped| mapped == Some(rom))?;
+        Some(PROBE_CHANNELS[index])
+    }
+
+    /// Replace the configuration with a valid `new` one, logging a change entry with a bitmap of
+    /// the fields that changed, in declaration order. Returns the bitmap.
+    pub fn apply(&mut self, new: Config, log: &mut impl Log) -> Result<u32, ConfigError> {
+        new.validate()?;
+        let changed = self.changed_fields(&new);
+        if changed != 0 {
+            log.info(LogCode::ConfigChanged, changed);
+            *self = new;
+        }
+        Ok(changed)
+    }
+
+    fn changed_fields(&self, other: &Config) -> u32 {
+        let changes = [
+            self.normal_period_seconds != other.normal_period_seconds,
+            self.fast_period_seconds != other.fast_period_seconds,
+            self.high_alarm_celsius != other.high_alarm_celsius,
+            self.freeze_alarm_celsius != other.freeze_alarm_celsius,
+            self.high_alarm_delay_seconds != other.high_alarm_delay_seconds,
+            self.freeze_alarm_delay_seconds != other.freeze_alarm_delay_seconds,
+            self.door_alarm_seconds != other.door_alarm_seconds,
+            self.buzzer_enabled != other.buzzer_enabled,
+            self.door_alarm_enabled != other.door_alarm_enabled,
+            self.baud_rate != other.baud_rate,
+            self.display_unit != other.display_unit,
+            self.record_period_seconds != other.record_period_seconds,
+            self.utc_offset_minutes != other.utc_offset_minutes,
+            self.indicator_mode != other.indicator_mode,
+            self.probe_roms != other.probe_roms,
+            self.self_heating_celsius_per_second != other.self_heating_celsius_per_second,
+            self.self_heating_time_constant_seconds != other.self_heating_time_constant_seconds,
+            self.door_switch != other.door_switch,
+            self.sample_phase_seconds != other.sample_phase_seconds,
+            self.bus_address != other.bus_address,
+            self.daily_report_minute != other.daily_report_minute,
+            self.alarm_relay_mask != other.alarm_relay_mask,
+            self.test_mode != other.test_mode,
+            self.display_filter_samples != other.display_filter_samples,
+            self.epoch_anchor != other.epoch_anchor,
+            self.probe_disagree_celsius != other.probe_disagree_celsius,
+            self.probe_disagree_samples != other.probe_disagree_samples,
+        ];
+        changes.iter().enumerate().fold(0, |bitmap, (bit, &changed)| bitmap | (u32::from(changed) << bit))
+    }
+
+    /// Serialize for the NV store: version, length, then the fields in declaration order.
+    pub fn to_bytes(&self) -> [u8; CONFIG_RECORD_LEN] {
+        let mut bytes = [0u8; CONFIG_RECORD_LEN];
+        bytes[0] = CONFIG_VERSION;
+        bytes[1] = CONFIG_RECORD_LEN as u8;
+        let words = [
+            self.normal_period_seconds,
+            self.fast_period_seconds,
+            self.high_alarm_celsius.to_bits(),
+            self.freeze_alarm_celsius.to_bits(),
+            self.high_alarm_delay_seconds,
+            self.freeze_alarm_delay_seconds,
+            self.door_alarm_seconds,
+        ];
+        for (chunk, word) in bytes[2..30].chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes[30] = u8::from(self.buzzer_enabled);
+        bytes[31] = u8::from(self.door_alarm_enabled);
+        bytes[32..36].copy_from_slice(&self.baud_rate.to_le_bytes());
+        bytes[36] = self.display_unit as u8;
+        bytes[37..41].copy_from_slice(&self.record_period_seconds.to_le_bytes());
+        bytes[41..43].copy_from_slice(&self.utc_offset_minutes.to_le_bytes());
+        bytes[43] = u8::from(self.indicator_mode);
+        // An unmapped channel is stored as 0, which is never a valid ROM code.
+        for (chunk, rom) in bytes[44..].chunks_exact_mut(8).zip(self.probe_roms) {
+            chunk.copy_from_slice(&rom.map_or(0, |rom| rom.0).to_le_bytes());
+        }
+        bytes[60..64].copy_from_slice(&self.self_heating_celsius_per_second.to_bits().to_le_bytes());
+        bytes[64..68].copy_from_slice(&self.self_heating_time_constant_seconds.to_le_bytes());
+        bytes[68] = self.door_switch.polarity as u8;
+        bytes[69] = self.door_switch.pull as u8;
+        bytes[70..72].copy_from_slice(&self.door_switch.debounce_ms.to_le_bytes());
+        bytes[72..76].copy_from_slice(&self.sample_phase_seconds.to_le_bytes());
+        bytes[76] = self.bus_address;
+        // No report is stored as u16::MAX, which is never a time of day.
+        bytes[77..79].copy_from_slice(&self.daily_report_minute.unwrap_or(u16::MAX).to_le_bytes());
+        bytes[79] = self.alarm_relay_mask;
+        bytes[80] = u8::from(self.test_mode);
+        bytes[81] = self.display_filter_samples;
+        bytes[82..86].copy_from_slice(&self.epoch_anchor.unwrap_or(u32::MAX).to_le_bytes());
+        bytes[86..90].copy_from_slice(&self.probe_disagree_celsius.to_bits().to_le_bytes());
+        bytes[90] = self.probe_disagree_samples;
+        bytes
+    }
+
+    /// Restore a configuration saved by `to_bytes` of this or an older version, and validate it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ConfigError> {
+        let (&version, &len) = (bytes.first().ok_or(ConfigError::Corrupt)?, bytes.get(1).ok_or(ConfigError::Corrupt)?);
+        if version > CONFIG_VERSION {
+            return Err(ConfigError::UnsupportedVersion);
+        }
+        let bytes = bytes.get(..usize::from(len)).ok_or(ConfigError::Corrupt)?;
+        let word = |offset: usize| bytes.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        let flag = |offset: usize| bytes.get(offset).map(|&b| b != 0);
+        let defaults = Config::default();
+        let mut probe_roms = defaults.probe_roms;
+        for (index, rom) in probe_roms.iter_mut().enumerate() {
+            if let Some(b) = bytes.get(44 + index * 8..52 + index * 8) {
+                let code = u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]);
+                *rom = (code != 0).then_some(Rom(code));
+            }
+        }
+        let config = Config {
+            normal_period_seconds: word(2).unwrap_or(defaults.normal_period_seconds),
+            fast_period_seconds: word(6).unwrap_or(defaults.fast_period_seconds),
+            high_alarm_celsius: word(10).map_or(defaults.high_alarm_celsius, f32::from_bits),
+            freeze_alarm_celsius: word(14).map_or(defaults.freeze_alarm_celsius, f32::from_bits),
+            high_alarm_delay_seconds: word(18).unwrap_or(defaults.high_alarm_delay_seconds),
+            freeze_alarm_delay_seconds: word(22).unwrap_or(defaults.freeze_alarm_delay_seconds),
+            door_alarm_seconds: word(26).unwrap_or(defaults.door_alarm_seconds),
+            buzzer_enabled: flag(30).unwrap_or(defaults.buzzer_enabled),
+            door_alarm_enabled: flag(31).unwrap_or(defaults.door_alarm_enabled),
+            baud_rate: word(32).unwrap_or(defaults.baud_rate),
+            display_unit: match bytes.get(36) {
+                None => defaults.display_unit,
+                Some(0) => TemperatureUnit::Celsius,
+                Some(1) => TemperatureUnit::Fahrenheit,
+                Some(_) => return Err(ConfigError::Corrupt),
+            },
+            record_period_seconds: word(37).unwrap_or(defaults.record_period_seconds),
+            utc_offset_minutes: bytes.get(41..43).map_or(defaults.utc_offset_minutes, |b| i16::from_le_bytes([b[0], b[1]])),
+            indicator_mode: flag(43).unwrap_or(defaults.indicator_mode),
+            probe_roms,
+            self_heating_celsius_per_second: word(60).map_or(defaults.self_heating_celsius_per_second, f32::from_bits),
+            self_heating_time_constant_seconds: word(64).unwrap_or(defaults.self_heating_time_constant_seconds),
+            door_switch: DoorSwitchConfig {
+                polarity: match bytes.get(68) {
+                    None => defaults.door_switch.polarity,
+                    Some(0) => SwitchPolarity::NormallyOpen,
+                    Some(1) => SwitchPolarity::NormallyClosed,
+                    Some(_) => return Err(ConfigError::Corrupt),
+                },
+                pull: match bytes.get(69) {
+                    None => defaults.door_switch.pull,
+                    Some(0) => SwitchPull::Up,
+                    Some(1) => SwitchPull::Down,
+                    Some(_) => return Err(ConfigError::Corrupt),
+                },
+                debounce_ms: bytes.get(70..72).map_or(defaults.door_switch.debounce_ms, |b| u16::from_le_bytes([b[0], b[1]])),
+            },
+            sample_phase_seconds: word(72).unwrap_or(defaults.sample_phase_seconds),
+            bus_address: bytes.get(76).copied().unwrap_or(defaults.bus_address),
+            daily_report_minute: bytes.get(77..79).map_or(defaults.daily_report_minute, |b| {
+                Some(u16::from_le_bytes([b[0], b[1]])).filter(|&minute| minute != u16::MAX)
+            }),
+            alarm_relay_mask: bytes.get(79).copied().unwrap_or(defaults.alarm_relay_mask),
+            test_mode: flag(80).unwrap_or(defaults.test_mode),
+            display_filter_samples: bytes.get(81).copied().unwrap_or(defaults.display_filter_samples),
+            epoch_anchor: word(82).map_or(defaults.epoch_anchor, |anchor| Some(anchor).filter(|&anchor| anchor != u32::MAX)),
+            probe_disagree_celsius: word(86).map_or(defaults.probe_disagree_celsius, f32::from_bits),
+            probe_disagree_samples: bytes.get(90).copied().unwrap_or(defaults.probe_disagree_samples),
+        };
+        config.validate().map_err(|_| ConfigError::Corrupt)?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{CaptureLog, Level};
+    use crate::test_support::at;
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(Config::default().validate(), Ok(()));
+        let config = Config { fast_period_seconds: 600, ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::SamplePeriod));
+        let config = Config { sample_phase_seconds: DEFAULT_NORMAL_PERIOD_SECONDS, ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::SamplePeriod));
+        let config = Config { freeze_alarm_celsius: 8.0, ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::AlarmThresholds));
+        let config = Config { utc_offset_minutes: -13 * 60, ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::UtcOffset));
+        let config = Config { high_alarm_celsius: f32::NAN, ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::AlarmThresholds));
+        let config = Config { self_heating_time_constant_seconds: 0, ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::SelfHeating));
+        let config = Config { door_switch: DoorSwitchConfig { debounce_ms: 0, ..DoorSwitchConfig::default() }, ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::DoorDebounce));
+        let config = Config { bus_address: 0, ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::BusAddress));
+        let config = Config { daily_report_minute: Some(24 * 60), ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::ReportTime));
+        let config = Config { alarm_relay_mask: 1 << 4, ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::RelayMask));
+        let config = Config { display_filter_samples: 0, ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::DisplayFilter));
+        let config = Config { probe_disagree_celsius: f32::NAN, ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::ProbeCrossCheck));
+        let config = Config { probe_disagree_samples: 0, ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::ProbeCrossCheck));
+    }
+
+    #[test]
+    fn test_probe_mapping() {
+        let rom = Rom(0x0B00_0001_B81C_0228);
+        let config = Config { probe_roms: [None, Some(rom)], ..Config::default() };
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(config.probe_channel(rom), Some(Channel::Condenser));
+        assert_eq!(Config::default().probe_channel(rom), None);
+        let config = Config { probe_roms: [Some(rom), Some(rom)], ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::ProbeRom));
+        let config = Config { probe_roms: [Some(Rom(rom.0 ^ 1 << 8)), None], ..Config::default() };
+        assert_eq!(config.validate(), Err(ConfigError::ProbeRom));
+    }
+
+    #[test]
+    fn test_alarm_profile() {
+        assert_eq!(Config::default().alarm_profile(), AlarmProfile::FRIDGE);
+        let mut config = Config::default();
+        config.set_alarm_profile(AlarmProfile::FREEZER);
+        assert_eq!(config.alarm_profile(), AlarmProfile::FREEZER);
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_clock_set_moves_anchor() {
+        let mut config = Config::default();
+        assert!(!config.clock_set(at(1000), at(5000), None)); // Still unknown.
+        assert!(config.clock_set(at(5000), at(6000), Some(1_700_000_000)));
+        assert_eq!(config.epoch_anchor, Some(1_699_994_000));
+        // Set forward an hour: the same real time is an hour later on the clock.
+        assert!(config.clock_set(at(7000), at(10_600), None));
+        assert_eq!(config.epoch_anchor, Some(1_699_990_400));
+        assert!(!config.clock_set(at(10_600), at(10_600), Some(1_699_990_400 + 10_600)));
+    }
+
+    #[test]
+    fn test_round_trip_and_migration() {
+        let config = Config {
+            door_alarm_seconds: 120,
+            display_unit: TemperatureUnit::Fahrenheit,
+            utc_offset_minutes: 330,
+            indicator_mode: true,
+            probe_roms: [None, Some(Rom(0x0B00_0001_B81C_0228))],
+            self_heating_celsius_per_second: 0.25,
+            door_switch: DoorSwitchConfig { polarity: SwitchPolarity::NormallyOpen, pull: SwitchPull::Down, debounce_ms: 20 },
+            sample_phase_seconds: 30,
+            bus_address: 17,
+            daily_report_minute: Some(6 * 60 + 30),
+            alarm_relay_mask: 0,
+            test_mode: true,
+            display_filter_samples: 8,
+            epoch_anchor: Some(1_700_000_000),
+            probe_disagree_celsius: 0.5,
+            probe_disagree_samples: 5,
+            ..Config::default()
+        };
+        assert_eq!(Config::from_bytes(&config.to_bytes()), Ok(config));
+        // A record from a version without the trailing fields gets their defaults.
+        let mut older = config.to_bytes();
+        older[1] = 30;
+        let migrated = Config::from_bytes(&older[..30]).unwrap();
+        assert_eq!(migrated.door_alarm_seconds, 120);
+        assert_eq!(migrated.display_unit, TemperatureUnit::Celsius);
+        let research = Config { record_period_seconds: 300, ..config };
+        let mut version_1 = research.to_bytes();
+        (version_1[0], version_1[1]) = (1, 37);
+        // Everything from the record period on takes its default.
+        let expected = Config { door_alarm_seconds: 120, display_unit: TemperatureUnit::Fahrenheit, ..Config::default() };
+        assert_eq!(Config::from_bytes(&version_1[..37]), Ok(expected));
+        let mut version_7 = config.to_bytes();
+        (version_7[0], version_7[1]) = (7, 72);
+        let version_7_defaults = Config { sample_phase_seconds: 0, bus_address: DEFAULT_BUS_ADDRESS, daily_report_minute: None, alarm_relay_mask: DEFAULT_RELAY_MASK, test_mode: false, display_filter_samples: DEFAULT_DISPLAY_FILTER_SAMPLES, epoch_anchor: None, probe_disagree_celsius: DEFAULT_PROBE_DISAGREE_CELSIUS, probe_disagree_samples: DEFAULT_PROBE_DISAGREE_SAMPLES, ..config };
+        assert_eq!(Config::from_bytes(&version_7[..72]), Ok(version_7_defaults));
+        assert_eq!(Config::from_bytes(&Config::default().to_bytes()), Ok(Config::default()));
+        let mut newer = config.to_bytes();
+        newer[0] = CONFIG_VERSION + 1;
+        assert_eq!(Config::from_bytes(&newer), Err(ConfigError::UnsupportedVersion));
+        assert_eq!(Config::from_bytes(&older[..20]), Err(ConfigError::Corrupt));
+    }
+
+    #[test]
+    fn test_apply_logs_changes() {
+        let mut config = Config::default();
+        let mut log = CaptureLog::default();
+        let new = Config { buzzer_enabled: false, baud_rate: 9600, ..config };
+        assert_eq!(config.apply(new, &mut log), Ok(0b10_1000_0000));
+        assert_eq!(log.entries, [(Level::Info, LogCode::ConfigChanged, 0b10_1000_0000)]);
+        assert_eq!(config, new);
+        assert_eq!(config.apply(Config { baud_rate: 0, ..config }, &mut log), Err(ConfigError::BaudRate));
+        assert_eq!(config.apply(new, &mut log), Ok(0));
+        assert_eq!(log.entries.len(), 1);
+    }
+}
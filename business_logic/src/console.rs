@@ -0,0 +1,355 @@
+use core::fmt::{self, Write};
+use core::str::FromStr;
+
+use arrayvec::{ArrayString, ArrayVec};
+
+use crate::capabilities::{Capabilities, Capability};
+use crate::debug::DebugCommand;
+use crate::export::ExportFilter;
+use crate::notes::NOTE_LEN;
+use crate::simulate::SimulateCommand;
+use crate::timestamp::Timestamp;
+use crate::watch::WatchCommand;
+#[cfg(feature = "authentication")]
+use crate::{
+    authentication::{self, Tag},
+    lifecycle::LifecycleState,
+};
+
+/// Longest console line, without its line ending.
+pub const MAX_LINE_LEN: usize = 96;
+/// Most image bytes in one `update data` line, a whole number of flash program units.
+pub const UPDATE_CHUNK_LEN: usize = 32;
+
+/// A command for the logger task, which owns the logger and its records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LoggerCommand {
+    Debug(DebugCommand),
+    Watch(WatchCommand),
+}
+
+/// A step of the commissioning wizard the operator takes, see `CommissioningWizard`. The door
+/// steps come from the door switch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CommissionCommand {
+    Start, // `commission`: start again from the first step.
+    Time(u32), // `commission time <unix seconds>`: the real UTC time now.
+    Thresholds(f32, f32), // `commission thresholds <low> <high>`, °C.
+    SelfTest, // `commission selftest`: the results of the self-test at boot.
+    Check, // `commission check`: the latest readings.
+    Stop, // `commission stop`
+}
+
+/// A command for the device task, which owns the lifecycle, the indicator and the inputs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceCommand {
+    Commission(CommissionCommand),
+    SetClock(u32), // `clock set <unix seconds>`: the real time from a host, in UTC.
+    Note(ArrayString<NOTE_LEN>), // `note <text>`: an operator note, e.g. `note defrost performed`.
+    TestRelay, // `relay test`: pulse the alarm relay, to check the wiring, see `AlarmRelay::test_pulse`.
+    TestMode(bool), // `test mode on|off`: whether `simulate` commands are accepted, saved in the settings.
+    Simulate(SimulateCommand), // `simulate ...`, see `SimulateCommand::parse`.
+    #[cfg(feature = "authentication")]
+    Lifecycle(LifecycleState, Tag), // `lifecycle <state> <tag>`, the tag from `lifecycle::command_tag` in hex.
+    #[cfg(feature = "authentication")]
+    ClearIndicator(Tag), // `indicator clear <tag>`, the tag from `indicator::clear_tag` in hex.
+}
+
+/// A step of a firmware update, carried out by the console itself, see `ImageStager`. Each is
+/// answered before the uploader sends the next.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateCommand {
+    Begin(u32, u32), // `update begin <length> <crc32 in hex>`: start an upload of the image.
+    Data(ArrayVec<u8, UPDATE_CHUNK_LEN>), // `update data <hex>`: the next bytes of the image.
+    Finish, // `update finish`: check the image as received and as written.
+    Boot, // `update boot`: boot the checked image on trial, see `BootState`.
+    Bootloader, // `update bootloader`: reset into the ROM bootloader.
+}
+
+/// `export <start> <end> [alarms <before> <after>] [xmodem]`: the report of the records in
+/// `start..end`, seconds since the epoch, as CSV, see `ReportChunks`. With `alarms`, only the
+/// records near an alarm, with `before` and `after` seconds of context, see `ExportFilter`. The
+/// logger task streams it, as it owns the records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ExportCommand {
+    pub start: Timestamp,
+    pub end: Timestamp,
+    pub filter: ExportFilter,
+    pub xmodem: bool, // Sent with `XmodemSender`, for a terminal emulator to save, rather than as plain text.
+}
+
+/// A console command, by the task that carries it out.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Capabilities, // `capabilities`, answered by the console itself, see `write_capabilities`.
+    Dictionary, // `dictionary`, answered by the console itself, see `dictionary::write_dictionary`.
+    Update(UpdateCommand),
+    BusAddress(u8), // `bus address <address>`: the device's address on a shared RS-485 bus, saved in the settings.
+    ReportTime(Option<u16>), // `report time <minute>` or `report time off`: when the daily report is sent, minutes after local midnight, saved in the settings.
+    Logger(LoggerCommand),
+    Export(ExportCommand),
+    Device(DeviceCommand),
+}
+
+/// Why a console line wasn't carried out, answered as `error` and the variant, e.g. `error Invalid`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CommandError {
+    Unknown, // No command starts with the line's first word.
+    Invalid, // The command's arguments are missing or malformed.
+    BadLine, // Longer than `MAX_LINE_LEN`, or not ASCII.
+}
+
+impl Command {
+    /// Parse a console line: words separated by spaces, the first naming the command.
+    pub fn parse(line: &str) -> Result<Self, CommandError> {
+        if let Some(command) = DebugCommand::parse(line) {
+            return Ok(Command::Logger(LoggerCommand::Debug(command)));
+        }
+        if let Some(command) = WatchCommand::parse(line) {
+            return Ok(Command::Logger(LoggerCommand::Watch(command)));
+        }
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            // The rest of the line, spaces and all.
+            Some("note") => {
+                let text = line.trim().strip_prefix("note").unwrap_or_default();
+                return Ok(Command::Device(DeviceCommand::Note(ArrayString::from(text.trim()).or(Err(CommandError::Invalid))?)));
+            }
+            Some("simulate") => return Ok(Command::Device(DeviceCommand::Simulate(SimulateCommand::parse(line).or(Err(CommandError::Invalid))?))),
+            Some("capabilities") => Command::Capabilities,
+            Some("dictionary") => Command::Dictionary,
+            Some("update") => Command::Update(parse_update(&mut words)?),
+            Some("commission") => Command::Device(DeviceCommand::Commission(parse_commission(&mut words)?)),
+            Some("bus") => match words.next() {
+                Some("address") => Command::BusAddress(number(&mut words)?),
+                _ => return Err(CommandError::Invalid),
+            },
+            Some("test") => match (words.next(), words.next()) {
+                (Some("mode"), Some("on")) => Command::Device(DeviceCommand::TestMode(true)),
+                (Some("mode"), Some("off")) => Command::Device(DeviceCommand::TestMode(false)),
+                _ => return Err(CommandError::Invalid),
+            },
+            Some("relay") => match words.next() {
+                Some("test") => Command::Device(DeviceCommand::TestRelay),
+                _ => return Err(CommandError::Invalid),
+            },
+            Some("report") => match (words.next(), words.next()) {
+                (Some("time"), Some("off")) => Command::ReportTime(None),
+                (Some("time"), Some(minute)) => Command::ReportTime(Some(minute.parse().or(Err(CommandError::Invalid))?)),
+                _ => return Err(CommandError::Invalid),
+            },
+            Some("export") => {
+                let start = Timestamp { seconds: number(&mut words)? };
+                let end = Timestamp { seconds: number(&mut words)? };
+                let mut next = words.next();
+                let filter = match next {
+                    Some("alarms") => {
+                        let before_seconds = number(&mut words)?;
+                        let after_seconds = number(&mut words)?;
+                        next = words.next();
+                        ExportFilter::AlarmOnly { before_seconds, after_seconds }
+                    }
+                    _ => ExportFilter::All,
+                };
+                let xmodem = match next {
+                    None => false,
+                    Some("xmodem") => true,
+                    Some(_) => return Err(CommandError::Invalid),
+                };
+                Command::Export(ExportCommand { start, end, filter, xmodem })
+            }
+            Some("clock") => match words.next() {
+                Some("set") => Command::Device(DeviceCommand::SetClock(number(&mut words)?)),
+                _ => return Err(CommandError::Invalid),
+            },
+            #[cfg(feature = "authentication")]
+            Some("lifecycle") => {
+                let state = words.next().and_then(|name| LifecycleState::ALL.into_iter().find(|state| state.name() == name));
+                let tag = words.next().and_then(authentication::from_hex);
+                Command::Device(DeviceCommand::Lifecycle(state.ok_or(CommandError::Invalid)?, tag.ok_or(CommandError::Invalid)?))
+            }
+            #[cfg(feature = "authentication")]
+            Some("indicator") => match (words.next(), words.next().and_then(authentication::from_hex)) {
+                (Some("clear"), Some(tag)) => Command::Device(DeviceCommand::ClearIndicator(tag)),
+                _ => return Err(CommandError::Invalid),
+            },
+            Some("debug" | "watch") => return Err(CommandError::Invalid),
+            _ => return Err(CommandError::Unknown),
+        };
+        words.next().is_none().then_some(command).ok_or(CommandError::Invalid)
+    }
+}
+
+fn parse_commission<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<CommissionCommand, CommandError> {
+    Ok(match words.next() {
+        None => CommissionCommand::Start,
+        Some("time") => CommissionCommand::Time(number(words)?),
+        Some("thresholds") => {
+            let low = number(words)?;
+            CommissionCommand::Thresholds(low, number(words)?)
+        }
+        Some("selftest") => CommissionCommand::SelfTest,
+        Some("check") => CommissionCommand::Check,
+        Some("stop") => CommissionCommand::Stop,
+        Some(_) => return Err(CommandError::Invalid),
+    })
+}
+
+fn parse_update<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<UpdateCommand, CommandError> {
+    Ok(match words.next() {
+        Some("begin") => {
+            let length = number(words)?;
+            let crc = words.next().and_then(|word| u32::from_str_radix(word, 16).ok()).ok_or(CommandError::Invalid)?;
+            UpdateCommand::Begin(length, crc)
+        }
+        Some("data") => UpdateCommand::Data(words.next().and_then(chunk_from_hex).ok_or(CommandError::Invalid)?),
+        Some("finish") => UpdateCommand::Finish,
+        Some("boot") => UpdateCommand::Boot,
+        Some("bootloader") => UpdateCommand::Bootloader,
+        _ => return Err(CommandError::Invalid),
+    })
+}
+
+fn chunk_from_hex(hex: &str) -> Option<ArrayVec<u8, UPDATE_CHUNK_LEN>> {
+    if !hex.len().is_multiple_of(2) || hex.len() > 2 * UPDATE_CHUNK_LEN {
+        return None;
+    }
+    hex.as_bytes().chunks_exact(2).map(|pair| u8::from_str_radix(core::str::from_utf8(pair).ok()?, 16).ok()).collect()
+}
+
+fn number<'a, T: FromStr>(words: &mut impl Iterator<Item = &'a str>) -> Result<T, CommandError> {
+    words.next().and_then(|word| word.parse().ok()).ok_or(CommandError::Invalid)
+}
+
+/// Collects the bytes from the console into lines and parses them. A line ends with `\r`, `\n`
+/// or both; empty lines are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct LineReader {
+    line: ArrayString<MAX_LINE_LEN>,
+    bad: bool, // The line so far was too long or not ASCII, so it is dropped at its end.
+}
+
+impl LineReader {
+    pub const fn new() -> Self {
+        Self { line: ArrayString::new_const(), bad: false }
+    }
+
+    /// Take the next byte. Returns the command once its line ends.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Command, CommandError>> {
+        if byte != b'\r' && byte != b'\n' {
+            self.bad |= !byte.is_ascii() || self.line.try_push(char::from(byte)).is_err();
+            return None;
+        }
+        let result = match self.bad {
+            true => Some(Err(CommandError::BadLine)),
+            false if self.line.trim().is_empty() => None,
+            false => Some(Command::parse(&self.line)),
+        };
+        self.line.clear();
+        self.bad = false;
+        result
+    }
+}
+
+/// The answer to `capabilities`: the bitmap in hex, then the names of the capabilities present.
+pub fn write_capabilities(out: &mut impl Write, capabilities: Capabilities) -> fmt::Result {
+    write!(out, "capabilities bits={:08X} names=", capabilities.as_u32())?;
+    let mut present = Capability::ALL.into_iter().filter(|&capability| capabilities.has(capability));
+    if let Some(first) = present.next() {
+        out.write_str(first.name())?;
+    }
+    for capability in present {
+        write!(out, ",{}", capability.name())?;
+    }
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(reader: &mut LineReader, bytes: &[u8]) -> Vec<Result<Command, CommandError>> {
+        bytes.iter().filter_map(|&byte| reader.push(byte)).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Command::parse("debug agg"), Ok(Command::Logger(LoggerCommand::Debug(DebugCommand::Aggregator))));
+        assert_eq!(Command::parse(" watch stop"), Ok(Command::Logger(LoggerCommand::Watch(WatchCommand::Stop))));
+        assert_eq!(Command::parse("capabilities"), Ok(Command::Capabilities));
+        assert_eq!(Command::parse("dictionary"), Ok(Command::Dictionary));
+        let commission = |command| Ok(Command::Device(DeviceCommand::Commission(command)));
+        assert_eq!(Command::parse("commission"), commission(CommissionCommand::Start));
+        assert_eq!(Command::parse("commission time 1700000000"), commission(CommissionCommand::Time(1_700_000_000)));
+        assert_eq!(Command::parse("commission thresholds 2 8.5"), commission(CommissionCommand::Thresholds(2.0, 8.5)));
+        assert_eq!(Command::parse("commission check"), commission(CommissionCommand::Check));
+        assert_eq!(Command::parse("clock set 1700000000"), Ok(Command::Device(DeviceCommand::SetClock(1_700_000_000))));
+        let note = |text| Ok(Command::Device(DeviceCommand::Note(ArrayString::from(text).unwrap())));
+        assert_eq!(Command::parse(" note  defrost,  then restocked "), note("defrost,  then restocked"));
+        assert_eq!(Command::parse("note"), note(""));
+        let export = ExportCommand { start: Timestamp { seconds: 900 }, end: Timestamp { seconds: 86_400 }, filter: ExportFilter::All, xmodem: false };
+        assert_eq!(Command::parse("export 900 86400"), Ok(Command::Export(export)));
+        assert_eq!(Command::parse("export 900 86400 xmodem"), Ok(Command::Export(ExportCommand { xmodem: true, ..export })));
+        let alarms = ExportFilter::AlarmOnly { before_seconds: 1800, after_seconds: 900 };
+        assert_eq!(Command::parse("export 900 86400 alarms 1800 900 xmodem"), Ok(Command::Export(ExportCommand { filter: alarms, xmodem: true, ..export })));
+        assert_eq!(Command::parse("bus address 17"), Ok(Command::BusAddress(17)));
+        assert_eq!(Command::parse("relay test"), Ok(Command::Device(DeviceCommand::TestRelay)));
+        assert_eq!(Command::parse("test mode on"), Ok(Command::Device(DeviceCommand::TestMode(true))));
+        assert_eq!(Command::parse("simulate door open"), Ok(Command::Device(DeviceCommand::Simulate(SimulateCommand::Door { open: true }))));
+        assert_eq!(Command::parse("report time 390"), Ok(Command::ReportTime(Some(390))));
+        assert_eq!(Command::parse("report time off"), Ok(Command::ReportTime(None)));
+        let long = format!("note {}", "x".repeat(NOTE_LEN + 1));
+        for line in ["commission time", "commission time soon", "commission thresholds 2", "commission stop now", "clock set", "clock 1700000000", "export 900", "export 900 86400 zmodem", "export 900 86400 alarms 1800", "export 900 86400 xmodem alarms 0 0", "bus address 300", "relay", "relay test now", "test mode", "simulate door ajar", "simulate humidity 50", "report time", "report time 6:30", "dictionary now", "debug", "watch now", &long] {
+            assert_eq!(Command::parse(line), Err(CommandError::Invalid), "{}", line);
+        }
+        assert_eq!(Command::parse("reboot"), Err(CommandError::Unknown));
+    }
+
+    #[test]
+    fn test_parse_update() {
+        let update = |command| Ok(Command::Update(command));
+        assert_eq!(Command::parse("update begin 1024 CBF43926"), update(UpdateCommand::Begin(1024, 0xCBF4_3926)));
+        let chunk = ArrayVec::try_from(&[0x01, 0xAB, 0xFF][..]).unwrap();
+        assert_eq!(Command::parse("update data 01abFF"), update(UpdateCommand::Data(chunk)));
+        assert_eq!(Command::parse("update finish"), update(UpdateCommand::Finish));
+        let long = format!("update data {}", "00".repeat(UPDATE_CHUNK_LEN + 1));
+        for line in ["update", "update begin 1024", "update data 0", "update data 0g", long.as_str()] {
+            assert_eq!(Command::parse(line), Err(CommandError::Invalid), "{}", line);
+        }
+    }
+
+    #[cfg(feature = "authentication")]
+    #[test]
+    fn test_parse_authenticated() {
+        let tag = [0xA5; crate::authentication::TAG_LEN];
+        let hex = authentication::to_hex(&tag);
+        let line = format!("lifecycle transport {}", hex);
+        assert_eq!(Command::parse(&line), Ok(Command::Device(DeviceCommand::Lifecycle(LifecycleState::Transport, tag))));
+        assert_eq!(Command::parse(&format!("indicator clear {}", hex)), Ok(Command::Device(DeviceCommand::ClearIndicator(tag))));
+        assert_eq!(Command::parse(&format!("lifecycle shipping {}", hex)), Err(CommandError::Invalid));
+        assert_eq!(Command::parse("indicator clear 12"), Err(CommandError::Invalid));
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut reader = LineReader::new();
+        assert_eq!(read(&mut reader, b"\r\ncapabil"), []);
+        assert_eq!(read(&mut reader, b"ities\r\n\nwatch\n"), [Ok(Command::Capabilities), Ok(Command::Logger(LoggerCommand::Watch(WatchCommand::Start)))]);
+        let long = [b'x'; MAX_LINE_LEN + 1];
+        assert_eq!(read(&mut reader, &long), []);
+        assert_eq!(read(&mut reader, b"\ncapabilities\n"), [Err(CommandError::BadLine), Ok(Command::Capabilities)]);
+        assert_eq!(read(&mut reader, "watch \u{b0}\n".as_bytes()), [Err(CommandError::BadLine)]);
+    }
+
+    #[test]
+    fn test_capabilities_line() {
+        let mut out = String::new();
+        let capabilities = Capabilities::default().with(Capability::VaccineSensor, true).with(Capability::Uart, true);
+        write_capabilities(&mut out, capabilities).unwrap();
+        assert_eq!(out, "capabilities bits=00010001 names=vaccine_sensor,uart\n");
+    }
+}
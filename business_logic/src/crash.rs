@@ -0,0 +1,133 @@
+use arrayvec::ArrayString;
+use core::fmt::{self, Write};
+
+/// Maximum length of a saved panic message; longer messages are truncated.
+pub const CRASH_MESSAGE_LEN: usize = 64;
+/// Number of stack words saved from the faulting stack pointer.
+pub const CRASH_STACK_WORDS: usize = 16;
+/// Size of a serialized crash record: magic, kind/length, PC, LR, stack, message, checksum.
+pub const CRASH_RECORD_WORDS: usize = 4 + CRASH_STACK_WORDS + CRASH_MESSAGE_LEN / 4 + 1;
+const CRASH_RECORD_MAGIC: u32 = 0xDEAD_C0DE;
+
+/// What stopped the firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CrashKind {
+    Panic,
+    HardFault,
+}
+
+/// Diagnostic snapshot saved by the panic and hard fault handlers and read back at the next boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrashRecord {
+    pub kind: CrashKind,
+    pub pc: u32,
+    pub lr: u32,
+    pub stack: [u32; CRASH_STACK_WORDS],
+    pub message: ArrayString<CRASH_MESSAGE_LEN>,
+}
+
+impl CrashRecord {
+    pub fn new(kind: CrashKind) -> Self {
+        Self { kind, pc: 0, lr: 0, stack: [0; CRASH_STACK_WORDS], message: ArrayString::new() }
+    }
+
+    /// Format the message, truncating it to `CRASH_MESSAGE_LEN` bytes.
+    pub fn set_message(&mut self, args: fmt::Arguments) {
+        self.message.clear();
+        let _ = Truncating(&mut self.message).write_fmt(args);
+    }
+
+    pub fn to_words(&self) -> [u32; CRASH_RECORD_WORDS] {
+        let mut words = [0u32; CRASH_RECORD_WORDS];
+        let kind = match self.kind {
+            CrashKind::Panic => 0,
+            CrashKind::HardFault => 1,
+        };
+        words[0] = CRASH_RECORD_MAGIC;
+        words[1] = kind | ((self.message.len() as u32) << 8);
+        words[2] = self.pc;
+        words[3] = self.lr;
+        words[4..4 + CRASH_STACK_WORDS].copy_from_slice(&self.stack);
+        let mut message = [0u8; CRASH_MESSAGE_LEN];
+        message[..self.message.len()].copy_from_slice(self.message.as_bytes());
+        for (word, bytes) in words[4 + CRASH_STACK_WORDS..].iter_mut().zip(message.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        words[CRASH_RECORD_WORDS - 1] = checksum(&words[..CRASH_RECORD_WORDS - 1]);
+        words
+    }
+
+    /// Decode a saved record. Returns None if no valid record is present.
+    pub fn from_words(words: &[u32; CRASH_RECORD_WORDS]) -> Option<Self> {
+        if words[0] != CRASH_RECORD_MAGIC || words[CRASH_RECORD_WORDS - 1] != checksum(&words[..CRASH_RECORD_WORDS - 1]) {
+            return None;
+        }
+        let kind = match words[1] & 0xFF {
+            0 => CrashKind::Panic,
+            1 => CrashKind::HardFault,
+            _ => return None,
+        };
+        let mut message = [0u8; CRASH_MESSAGE_LEN];
+        for (bytes, word) in message.chunks_exact_mut(4).zip(&words[4 + CRASH_STACK_WORDS..CRASH_RECORD_WORDS - 1]) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        let len = ((words[1] >> 8) as usize).min(CRASH_MESSAGE_LEN);
+        let mut record = Self::new(kind);
+        record.pc = words[2];
+        record.lr = words[3];
+        record.stack.copy_from_slice(&words[4..4 + CRASH_STACK_WORDS]);
+        record.message.push_str(core::str::from_utf8(&message[..len]).ok()?);
+        Some(record)
+    }
+}
+
+fn checksum(words: &[u32]) -> u32 {
+    words.iter().fold(0, |sum, &word| sum.rotate_left(5) ^ word)
+}
+
+// Writes as much as fits instead of failing on overflow.
+struct Truncating<'a>(&'a mut ArrayString<CRASH_MESSAGE_LEN>);
+
+impl Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.0.try_push(c).is_err() {
+                return Err(fmt::Error);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut record = CrashRecord::new(CrashKind::HardFault);
+        record.pc = 0x0800_1234;
+        record.lr = 0xFFFF_FFF9;
+        record.stack[3] = 42;
+        record.set_message(format_args!("bad value {}", 7));
+        let words = record.to_words();
+        assert_eq!(CrashRecord::from_words(&words), Some(record));
+    }
+
+    #[test]
+    fn test_invalid_records_rejected() {
+        assert_eq!(CrashRecord::from_words(&[0; CRASH_RECORD_WORDS]), None);
+        let mut words = CrashRecord::new(CrashKind::Panic).to_words();
+        words[2] ^= 1;
+        assert_eq!(CrashRecord::from_words(&words), None);
+    }
+
+    #[test]
+    fn test_message_truncated() {
+        let mut record = CrashRecord::new(CrashKind::Panic);
+        record.set_message(format_args!("{}", "x".repeat(100)));
+        assert_eq!(record.message.len(), CRASH_MESSAGE_LEN);
+        assert_eq!(CrashRecord::from_words(&record.to_words()).unwrap().message, record.message);
+    }
+}
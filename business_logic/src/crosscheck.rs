@@ -0,0 +1,118 @@
+use crate::log::{Log, LogCode};
+
+/// Largest difference between the vaccine probes that still counts as agreement, unless configured otherwise.
+pub const DEFAULT_PROBE_DISAGREE_CELSIUS: f32 = 1.0;
+/// Readings in a row the probes must disagree for before switching over, unless configured otherwise.
+pub const DEFAULT_PROBE_DISAGREE_SAMPLES: u8 = 3;
+
+/// One of the two redundant vaccine probes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VaccineProbe {
+    Primary,
+    Secondary,
+}
+
+/// Chooses which of two vaccine probes the alarms follow.
+///
+/// Both probes are logged; the alarms follow the primary until it deviates from the secondary
+/// by more than `max_delta_celsius` for `samples` readings in a row. Then the alarms follow the
+/// secondary until `reset`, e.g. after the primary was replaced, since a drifting probe rarely
+/// recovers by itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbeCrossCheck {
+    max_delta_celsius: f32,
+    samples: u8,
+    disagreeing: u8, // Readings in a row the probes have disagreed for.
+    active: VaccineProbe,
+}
+
+impl Default for ProbeCrossCheck {
+    fn default() -> Self {
+        Self::new(DEFAULT_PROBE_DISAGREE_CELSIUS, DEFAULT_PROBE_DISAGREE_SAMPLES)
+    }
+}
+
+impl ProbeCrossCheck {
+    pub fn new(max_delta_celsius: f32, samples: u8) -> Self {
+        Self { max_delta_celsius, samples, disagreeing: 0, active: VaccineProbe::Primary }
+    }
+
+    /// The probe the alarms follow.
+    pub fn active(&self) -> VaccineProbe {
+        self.active
+    }
+
+    /// Cross-check one reading from each probe, either of which may have failed, and return the
+    /// vaccine temperature for the alarms. Logs `SensorDisagree` with the deviation in hundredths
+    /// of a degree when switching to the secondary.
+    pub fn check(&mut self, primary: Option<f32>, secondary: Option<f32>, log: &mut impl Log) -> Option<f32> {
+        let (Some(primary), Some(secondary)) = (primary, secondary) else {
+            // Nothing to compare: use the probe that was read, the active one if both were.
+            return match self.active {
+                VaccineProbe::Primary => primary.or(secondary),
+                VaccineProbe::Secondary => secondary.or(primary),
+            };
+        };
+        if self.active == VaccineProbe::Primary {
+            let deviation = (primary - secondary).abs();
+            if deviation.is_nan() || deviation > self.max_delta_celsius {
+                self.disagreeing = self.disagreeing.saturating_add(1);
+            } else {
+                self.disagreeing = 0;
+            }
+            if self.disagreeing >= self.samples {
+                self.active = VaccineProbe::Secondary;
+                log.warn(LogCode::SensorDisagree, (deviation * 100.0) as u32);
+            }
+        }
+        Some(match self.active {
+            VaccineProbe::Primary => primary,
+            VaccineProbe::Secondary => secondary,
+        })
+    }
+
+    /// Follow the primary probe again, e.g. after it was replaced.
+    pub fn reset(&mut self) {
+        self.disagreeing = 0;
+        self.active = VaccineProbe::Primary;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{CaptureLog, Level};
+
+    #[test]
+    fn test_switch_over() {
+        let mut check = ProbeCrossCheck::new(1.0, 3);
+        let mut log = CaptureLog::default();
+        assert_eq!(check.check(Some(5.0), Some(5.5), &mut log), Some(5.0));
+        // Two readings apart, then one in agreement: the count starts again.
+        assert_eq!(check.check(Some(7.0), Some(5.0), &mut log), Some(7.0));
+        assert_eq!(check.check(Some(7.0), Some(5.0), &mut log), Some(7.0));
+        assert_eq!(check.check(Some(5.0), Some(5.0), &mut log), Some(5.0));
+        for _ in 0..2 {
+            assert_eq!(check.check(Some(9.0), Some(5.0), &mut log), Some(9.0));
+        }
+        assert_eq!(check.check(Some(9.5), Some(5.0), &mut log), Some(5.0));
+        assert_eq!(check.active(), VaccineProbe::Secondary);
+        assert_eq!(log.entries, [(Level::Warn, LogCode::SensorDisagree, 450)]);
+        // The primary agreeing again doesn't switch back.
+        assert_eq!(check.check(Some(5.0), Some(5.1), &mut log), Some(5.1));
+        check.reset();
+        assert_eq!(check.check(Some(5.0), Some(5.1), &mut log), Some(5.0));
+    }
+
+    #[test]
+    fn test_failed_probe() {
+        let mut check = ProbeCrossCheck::default();
+        let mut log = CaptureLog::default();
+        assert_eq!(check.check(None, Some(4.0), &mut log), Some(4.0));
+        assert_eq!(check.check(Some(6.0), None, &mut log), Some(6.0));
+        assert_eq!(check.check(None, None, &mut log), None);
+        assert_eq!(check.active(), VaccineProbe::Primary);
+        assert!(log.entries.is_empty());
+    }
+}
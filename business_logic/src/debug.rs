@@ -0,0 +1,124 @@
+use core::fmt::{self, Write};
+
+use crate::logger::Logger;
+
+/// A `debug` console command, for looking inside the logger in the field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DebugCommand {
+    Aggregator, // `debug agg`: the record in progress, see `write_aggregator_state`.
+}
+
+impl DebugCommand {
+    /// Parse a console line such as `debug agg`. None if it isn't a debug command.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let command = match (words.next(), words.next()) {
+            (Some("debug"), Some("agg")) => DebugCommand::Aggregator,
+            _ => return None,
+        };
+        words.next().is_none().then_some(command)
+    }
+}
+
+/// Write the live state of `logger` for `debug agg`, read-only. One line per part, named by its
+/// first word, then space-separated `key=value` pairs: times in seconds since the epoch,
+/// temperatures in °C and integrals in °C·s, with `-` for a value there isn't.
+///
+/// The parts are the logger itself, the reading it holds, the record in progress and each
+/// channel with its running sums and excursion timers.
+pub fn write_aggregator_state(out: &mut impl Write, logger: &Logger) -> fmt::Result {
+    let (door_open, power_off) = logger.door_and_power();
+    write!(out, "logger status={} now={} record_start=", logger.status().name(), logger.now().seconds)?;
+    write_option(out, logger.record_start().map(|start| start.seconds))?;
+    write!(out, " door_open={} power_off={} integrity_faults={}", door_open, power_off, logger.integrity_faults())?;
+    if let Some((reason, until)) = logger.pause() {
+        write!(out, " pause={:?} pause_until={}", reason, until.seconds)?;
+    }
+    let deadline = logger.next_deadline(logger.now());
+    writeln!(out, " next_deadline={} next_deadline_kind={:?}", deadline.at.seconds, deadline.kind)?;
+    match logger.held_sample() {
+        Some((sample, expires)) => writeln!(
+            out,
+            "sample at={} tvc={:.3} tamb={:.3} tvc_quality={:X} tamb_quality={:X} hold_until={}",
+            sample.timestamp.seconds, sample.tvc, sample.tamb, sample.tvc_quality, sample.tamb_quality, expires.seconds
+        )?,
+        None => writeln!(out, "sample at=-")?,
+    }
+    let aggregator = logger.aggregator();
+    let record = aggregator.current();
+    writeln!(
+        out,
+        "record start={} samples={} door_openings={} door_open_seconds={} power_off_seconds={} paused_seconds={} errors={:08X}",
+        record.start.seconds,
+        aggregator.sample_count(),
+        record.door_openings,
+        record.door_open_seconds,
+        record.power_off_seconds,
+        record.paused_seconds,
+        record.logger_errors.as_u32()
+    )?;
+    for channel in aggregator.channels() {
+        let sums = channel.record();
+        let (high_run, low_run) = channel.excursion_runs();
+        write!(out, "channel name={} seconds={} integral={:.3} ", channel.channel().name(), sums.seconds, sums.integral)?;
+        if sums.seconds > 0 {
+            write!(out, "min={:.3} max={:.3}", sums.min, sums.max)?;
+        } else {
+            write!(out, "min=- max=-")?;
+        }
+        write!(out, " high_seconds={} low_seconds={} high_run={} low_run={}", sums.high_seconds, sums.low_seconds, high_run, low_run)?;
+        match channel.profile() {
+            Some(profile) => writeln!(out, " high_delay={} low_delay={}", profile.high_delay_seconds, profile.low_delay_seconds)?,
+            None => writeln!(out, " high_delay=- low_delay=-")?,
+        }
+    }
+    Ok(())
+}
+
+fn write_option(out: &mut impl Write, value: Option<u32>) -> fmt::Result {
+    match value {
+        Some(value) => write!(out, "{}", value),
+        None => out.write_str("-"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(DebugCommand::parse("debug agg"), Some(DebugCommand::Aggregator));
+        assert_eq!(DebugCommand::parse("  debug   agg "), Some(DebugCommand::Aggregator));
+        assert_eq!(DebugCommand::parse("debug agg now"), None);
+        assert_eq!(DebugCommand::parse("debug"), None);
+        assert_eq!(DebugCommand::parse("simulate door open"), None);
+    }
+
+    #[test]
+    fn test_aggregator_state() {
+        let mut logger = Logger::default();
+        let mut text = String::new();
+        write_aggregator_state(&mut text, &logger).unwrap();
+        assert!(text.starts_with("logger status=idle now=0 record_start=- "));
+        assert!(text.contains("\nsample at=-\n"));
+        for event in [sample(1000, 9.0), sample(1300, 10.0)] {
+            logger.process_event(event, |_| {}).unwrap();
+        }
+        text.clear();
+        write_aggregator_state(&mut text, &logger).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("logger status=logging now=1300 record_start=900 door_open=false"));
+        assert!(lines[0].ends_with("next_deadline=1800 next_deadline_kind=RecordBoundary"));
+        assert_eq!(lines[1], "sample at=1300 tvc=10.000 tamb=25.000 tvc_quality=0 tamb_quality=0 hold_until=2200");
+        assert!(lines[2].starts_with("record start=900 samples=2 "));
+        assert_eq!(
+            lines[3],
+            "channel name=vaccine seconds=300 integral=2700.000 min=9.000 max=10.000 high_seconds=300 low_seconds=0 high_run=300 low_run=0 high_delay=36000 low_delay=3600"
+        );
+        assert!(lines[4].starts_with("channel name=ambient seconds=300 "));
+    }
+}
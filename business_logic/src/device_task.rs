@@ -0,0 +1,1314 @@
+use core::fmt;
+use core::future::Future;
+
+#[cfg(feature = "accelerometer")]
+use crate::accelerometer::MotionEvent;
+use crate::ajar::AjarDetector;
+use crate::alarm::{AlarmKind, AlarmNotifier, AlarmProfile, AlarmSink, AlarmState, AlarmStatus, Annunciator};
+use crate::battery::{FuelGauge, BATTERY_LOAD_UA};
+use crate::burst::{BurstCapture, BurstRecorder, BurstTrigger, BURST_CAPTURES};
+use crate::button::{Press, Ui, UiAction};
+use crate::commissioning::{CommissioningInput, CommissioningRecord, CommissioningStep, CommissioningWizard};
+use crate::compressor::{Compressor, CompressorEvent};
+use crate::config::Config;
+use crate::console::{CommissionCommand, DeviceCommand};
+use crate::display::{DisplayFilter, DisplayModel, DisplayPage};
+use crate::door::{DoorAlarm, DoorSwitchMonitor};
+use crate::errors::{ErrorCode, ErrorLog, PackedErrors};
+use crate::escalation::Escalation;
+use crate::event_queue::{Overflow, Queued};
+use crate::health::DeviceHealth;
+use crate::history::{DailyHistory, DayStatus};
+use crate::indicator::{ExcursionIndicator, IndicatorState};
+use crate::led::{DeviceStatus, StatusFlags};
+use crate::lifecycle::{Lifecycle, LifecycleState};
+use crate::lifetime::{LifetimeCounters, LifetimeStore, LIFETIME_RECORD_LEN};
+use crate::localtime::LocalTime;
+use crate::log::{Log, LogCode};
+use crate::logger::LoggerEvent;
+use crate::mains::{MainsMonitor, MainsState, PowerEvent, MAINS_SAMPLE_SECONDS};
+use crate::notes::NoteLog;
+use crate::power::{ClockProfile, PowerManager, PowerSource};
+#[cfg(feature = "authentication")]
+use crate::provisioning::DeviceKey;
+use crate::relay::AlarmRelay;
+use crate::sample::{SampleFlag, TemperatureSample};
+use crate::selftest::SelfTestReport;
+use crate::shutdown::PowerFailCheckpoint;
+#[cfg(feature = "humidity")]
+use crate::stats::MinMaxAvg;
+use crate::stats::RollingStats;
+use crate::timestamp::Timestamp;
+use crate::usb::{UsbEvent, UsbSessions};
+use crate::wallclock::EPOCH_UNIX_SECONDS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DoorEvent {
+    Opened,
+    Closed,
+}
+
+/// Inputs for the device task, from the driver tasks and the logger task.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceEvent {
+    Door(DoorEvent),
+    ButtonPress(Press),
+    TempReading((f32, f32), (u8, u8)), // (ambient temperature, vaccine temperature), and their `SampleFlag` bitmaps.
+    SensorFault, // A temperature sensor read failed.
+    Compressor(CompressorEvent),
+    MainsReading(u16), // Raw ADC reading of the mains-derived supply divider, every `MAINS_SAMPLE_SECONDS`.
+    Power(PowerEvent, Timestamp), // Mains came or went, at the time of the edge.
+    Usb(UsbEvent),
+    #[cfg(feature = "humidity")]
+    HumidityReading(Option<f32>), // Relative humidity in %, or None if the read failed.
+    #[cfg(feature = "accelerometer")]
+    Motion(MotionEvent),
+    TemperatureAlarms(bool, bool), // Whether the high temperature and freeze alarms are active.
+    Command(DeviceCommand), // From the console.
+    PowerFail(Option<PowerFailCheckpoint>), // The supply is failing; the last record, to keep.
+}
+
+impl Queued for DeviceEvent {
+    fn overflow(&self) -> Overflow {
+        match self {
+            // Readings come again, so a newer one replaces one still waiting.
+            DeviceEvent::TempReading(..) => Overflow::Coalesce(0),
+            DeviceEvent::SensorFault => Overflow::Coalesce(0),
+            DeviceEvent::MainsReading(_) => Overflow::Coalesce(1),
+            #[cfg(feature = "humidity")]
+            DeviceEvent::HumidityReading(_) => Overflow::Coalesce(2),
+            DeviceEvent::ButtonPress(_) => Overflow::Drop, // The user presses again.
+            DeviceEvent::Command(_) => Overflow::Drop, // Unanswered, so the operator sends it again.
+            _ => Overflow::Keep,
+        }
+    }
+}
+
+/// Where the device task gets its events, e.g. an embassy channel.
+pub trait DeviceEvents {
+    /// Wait for the next event. None once there will be no more, e.g. at the end of a test.
+    fn receive(&mut self) -> impl Future<Output = Option<DeviceEvent>>;
+
+    /// Events still waiting, for the queue's high-water mark.
+    fn waiting(&self) -> usize;
+}
+
+/// State the device task keeps for the next boot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Saved<'a> {
+    Alarms(AlarmState),
+    Burst(usize, &'a BurstCapture), // Slot and capture of a completed burst.
+    Indicator(IndicatorState),
+    Lifetime(usize, [u8; LIFETIME_RECORD_LEN]), // Slot and record, see `LifetimeStore`.
+    FuelGauge(FuelGauge),
+    Errors(ErrorLog), // Saved for the lifetime counts.
+    Lifecycle(Lifecycle),
+    Commissioning(&'a CommissioningRecord), // With its MAC, and the settings it chose for the next boot.
+    Checkpoint(PowerFailCheckpoint), // The record in progress as the supply fails.
+    Notes(&'a NoteLog), // After each note, for the exports.
+    TestMode(bool), // Into the settings, so it lasts until turned off.
+}
+
+/// A day that completed, for `Device::day_complete`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DayReport<'a> {
+    pub day: DayStatus,
+    pub history: &'a DailyHistory,
+    pub errors: PackedErrors, // Faults reported since the last day completed.
+    pub health: &'a DeviceHealth,
+    pub lifetime: &'a LifetimeCounters,
+}
+
+/// The peripherals and the other tasks, as the device task drives them.
+pub trait Device {
+    /// Wall-clock time now, from the RTC.
+    fn now(&self) -> Timestamp;
+
+    /// Microseconds since boot, never going back, for the lifetime counters and the handling times.
+    fn monotonic_us(&self) -> u64;
+
+    /// Seconds since first boot, for the diagnostics page and the health counters.
+    fn uptime_seconds(&self) -> u32;
+
+    /// Whether the RTC crystal stopped and the time is kept from the system clock.
+    fn clock_degraded(&self) -> bool;
+
+    /// Move the RTC forward by the time lost while its crystal was stopped, once it runs again.
+    /// Returns the seconds moved.
+    fn correct_clock(&mut self) -> u32;
+
+    /// `now`, after each event, for the tasks that keep to wall-clock times without the RTC.
+    fn anchor_clock(&mut self, now: Timestamp);
+
+    /// Set the RTC to `now`, the real time `unix_seconds` from a host, and move the settings'
+    /// epoch anchor with it. Returns the time it replaced.
+    fn set_clock(&mut self, now: Timestamp, unix_seconds: u32) -> Timestamp;
+
+    /// Hand `event` to the logger task.
+    fn log_event(&mut self, event: LoggerEvent);
+
+    /// The door opened or closed, for the sampling period.
+    fn set_door_open(&mut self, open: bool);
+
+    /// A burst of fast samples started, so the sampling task should sample at once.
+    fn burst_started(&mut self);
+
+    /// Whether a burst is being captured, for the sampling period.
+    fn set_bursting(&mut self, active: bool);
+
+    /// Sound the buzzer for `alarm`, with whether it has escalated, or silence it.
+    fn sound(&mut self, alarm: Option<(AlarmKind, bool)>);
+
+    /// Assert or release the alarm relay.
+    fn set_relay(&mut self, asserted: bool);
+
+    /// Show `model` on the display.
+    fn show(&mut self, model: &DisplayModel);
+
+    /// Show `status` on the status LED.
+    fn show_status(&mut self, status: DeviceStatus);
+
+    /// Switch to the clock profile chosen by the power manager.
+    fn set_clock_profile(&mut self, profile: ClockProfile);
+
+    /// Keep `saved` for the next boot.
+    fn save(&mut self, saved: Saved<'_>);
+
+    /// The lifecycle changed, so the logger task records the events of the new state.
+    fn set_lifecycle(&mut self, state: LifecycleState);
+
+    /// Answer a console command with one line, without its line ending.
+    fn reply(&mut self, line: fmt::Arguments<'_>);
+
+    /// Fill in the counters the other tasks keep, e.g. I2C errors and queue overflows.
+    fn driver_health(&self, health: &mut DeviceHealth);
+
+    /// A day completed, e.g. to report it.
+    fn day_complete(&mut self, report: DayReport<'_>);
+}
+
+/// Everything but the logging pipeline: the door, UI and display, daily history, mains and
+/// battery, power fail, USB, bursts, lifetime counters and alarm annunciation. It turns the
+/// driver tasks' `DeviceEvent`s into `LoggerEvent`s for the `LoggerTask`, which raises the
+/// temperature alarms and sends them back.
+pub struct DeviceTask {
+    profile: AlarmProfile,
+    buzzer_enabled: bool,
+    local_time: LocalTime,
+    lifecycle: Lifecycle,
+    compressor: Compressor,
+    ajar: AjarDetector,
+    mains: MainsMonitor,
+    mains_state: MainsState,
+    mains_on: bool, // Until the mains-present input says otherwise.
+    usb: UsbSessions,
+    power_manager: PowerManager,
+    fuel_gauge: FuelGauge,
+    annunciator: Annunciator,
+    escalation: Escalation,
+    saved_alarms: AlarmState,
+    notifier: AlarmNotifier,
+    sounding: Option<(AlarmKind, bool)>, // Last sent to the buzzer, with whether it had escalated.
+    relay: AlarmRelay,
+    relay_asserted: bool, // Last sent to the relay.
+    door_alarm: DoorAlarm,
+    door_monitor: DoorSwitchMonitor,
+    door_open: bool,
+    bursts: BurstRecorder,
+    indicator: Option<ExcursionIndicator>, // In indicator mode only.
+    status_flags: StatusFlags,
+    status: DeviceStatus, // Last shown on the status LED.
+    display_model: DisplayModel,
+    shown_model: DisplayModel, // Last sent to the display.
+    display_filter: DisplayFilter,
+    ui: Ui,
+    tvc_stats: RollingStats,
+    history: DailyHistory,
+    errors: ErrorLog,
+    last_sample_at: Option<Timestamp>,
+    clock_degraded: bool,
+    health: DeviceHealth,
+    lifetime_store: LifetimeStore,
+    lifetime: LifetimeCounters,
+    counted_until_us: u64, // Monotonic time the lifetime counters have run to.
+    self_test: SelfTestReport, // From boot, for commissioning.
+    commissioning: Option<CommissioningWizard>, // While the console runs the wizard.
+    notes: NoteLog,
+    test_mode: bool, // Accept `simulate` commands, see `Config::test_mode`.
+    #[cfg(feature = "authentication")]
+    key: Option<DeviceKey>, // None if the device wasn't provisioned, so no command is authorized.
+    #[cfg(feature = "humidity")]
+    humidity: Option<f32>,
+    #[cfg(feature = "humidity")]
+    humidity_stats: MinMaxAvg,
+}
+
+impl DeviceTask {
+    pub fn new(settings: &Config, lifecycle: Lifecycle, fuel_gauge: FuelGauge) -> Self {
+        let profile = settings.alarm_profile();
+        let annunciator = Annunciator::new();
+        let escalation = Escalation::default();
+        let display_model = DisplayModel { unit: settings.display_unit, battery_percent: Some(fuel_gauge.percent_remaining()), ..DisplayModel::default() };
+        Self {
+            profile,
+            buzzer_enabled: settings.buzzer_enabled,
+            local_time: settings.local_time(),
+            lifecycle,
+            compressor: Compressor::new(),
+            ajar: AjarDetector::new(),
+            mains: MainsMonitor::default(),
+            mains_state: MainsState::Normal,
+            mains_on: true,
+            usb: UsbSessions::new(),
+            power_manager: PowerManager::new(),
+            fuel_gauge,
+            annunciator,
+            escalation,
+            saved_alarms: AlarmState::capture(&annunciator, &escalation),
+            notifier: AlarmNotifier::following(&annunciator),
+            sounding: None,
+            relay: AlarmRelay::new(settings.alarm_relay_mask),
+            relay_asserted: false,
+            door_alarm: DoorAlarm::new(settings.door_alarm_enabled, profile.door_seconds),
+            door_monitor: DoorSwitchMonitor::new(),
+            door_open: false,
+            bursts: BurstRecorder::new(),
+            indicator: settings.indicator_mode.then(|| ExcursionIndicator::new(profile, IndicatorState::default())),
+            status_flags: StatusFlags::default(),
+            status: DeviceStatus::Ok,
+            display_model,
+            shown_model: display_model,
+            display_filter: settings.display_filter(),
+            ui: Ui::new(),
+            tvc_stats: RollingStats::new(),
+            history: DailyHistory::new(),
+            errors: ErrorLog::new(),
+            last_sample_at: None,
+            clock_degraded: false,
+            health: DeviceHealth::new(),
+            lifetime_store: LifetimeStore::new(),
+            lifetime: LifetimeCounters::new(),
+            counted_until_us: 0,
+            self_test: SelfTestReport::new(),
+            commissioning: None,
+            notes: NoteLog::new(),
+            test_mode: settings.test_mode,
+            #[cfg(feature = "authentication")]
+            key: None,
+            #[cfg(feature = "humidity")]
+            humidity: None,
+            #[cfg(feature = "humidity")]
+            humidity_stats: MinMaxAvg::new(),
+        }
+    }
+
+    /// The task carrying on the alarms under way before a reset or power cut, with their snoozes
+    /// and escalation. The door and mains are taken as they were until their inputs are read.
+    pub fn with_alarm_state(mut self, state: AlarmState) -> Self {
+        state.restore(&mut self.annunciator, &mut self.escalation);
+        self.saved_alarms = state;
+        self.notifier = AlarmNotifier::following(&self.annunciator);
+        self.mains_on = !self.annunciator.is_active(AlarmKind::Power);
+        self.door_open = self.annunciator.is_active(AlarmKind::Door);
+        // A door alarm under way goes on while the door stays open.
+        if self.door_open {
+            let since = self.escalation.active_since()[AlarmKind::Door as usize].unwrap_or(Timestamp { seconds: 0 });
+            self.door_alarm = self.door_alarm.resumed(since);
+        }
+        self
+    }
+
+    /// The task with the bursts saved in each slot.
+    pub fn with_bursts(self, slots: [Option<BurstCapture>; BURST_CAPTURES]) -> Self {
+        Self { bursts: BurstRecorder::restore(slots), ..self }
+    }
+
+    /// The task with the excursion indicator as saved, in indicator mode, so a latched excursion
+    /// survives resets.
+    pub fn with_indicator(mut self, state: IndicatorState) -> Self {
+        self.indicator = self.indicator.map(|_| ExcursionIndicator::new(self.profile, state));
+        self
+    }
+
+    /// The task carrying on the lifetime counters from the newer of their two slots.
+    pub fn with_lifetime(self, slots: [&[u8; LIFETIME_RECORD_LEN]; 2]) -> Self {
+        let (lifetime_store, lifetime) = LifetimeStore::restore(slots);
+        Self { lifetime_store, lifetime, ..self }
+    }
+
+    /// The task counting `restart_count` restarts so far.
+    pub fn with_restart_count(mut self, restart_count: u32) -> Self {
+        self.health.restart_count = restart_count;
+        self
+    }
+
+    /// The task carrying on the error counts saved before a reset.
+    pub fn with_errors(self, errors: ErrorLog) -> Self {
+        Self { errors, ..self }
+    }
+
+    /// The task with the results of the self-test at boot, for commissioning.
+    pub fn with_self_test(self, self_test: SelfTestReport) -> Self {
+        Self { self_test, ..self }
+    }
+
+    /// The task carrying on the operator notes saved before a reset.
+    pub fn with_notes(self, notes: NoteLog) -> Self {
+        Self { notes, ..self }
+    }
+
+    /// The task with the device key, which authorizes the lifecycle and indicator commands.
+    #[cfg(feature = "authentication")]
+    pub fn with_key(self, key: DeviceKey) -> Self {
+        Self { key: Some(key), ..self }
+    }
+
+    /// Count a fault, e.g. a failed self-test, and note it in the record in progress.
+    pub fn report_error(&mut self, code: ErrorCode, device: &mut impl Device) {
+        self.errors.report(code);
+        device.log_event(LoggerEvent::Fault(self.logged_at(device.now()), code));
+    }
+
+    pub fn lifetime(&self) -> &LifetimeCounters {
+        &self.lifetime
+    }
+
+    pub fn health(&self) -> &DeviceHealth {
+        &self.health
+    }
+
+    /// Relative humidity over the readings so far.
+    #[cfg(feature = "humidity")]
+    pub fn humidity_stats(&self) -> &MinMaxAvg {
+        &self.humidity_stats
+    }
+
+    /// Handle the events from `events` until the supply fails, or there are no more.
+    pub async fn run(&mut self, events: &mut impl DeviceEvents, device: &mut impl Device, log: &mut impl Log) {
+        self.start(device);
+        while let Some(event) = events.receive().await {
+            self.health.queue_depth(events.waiting() + 1); // Including the event just received.
+            let failing = matches!(event, DeviceEvent::PowerFail(_));
+            self.handle(event, device, log);
+            if failing {
+                return;
+            }
+        }
+    }
+
+    /// Show the state restored at boot, before the first event.
+    pub fn start(&mut self, device: &mut impl Device) {
+        let excursion_latched = self.indicator.is_some_and(|indicator| indicator.state().is_latched());
+        self.status_flags.excursion_latched = excursion_latched;
+        self.display_model.excursion_latched = excursion_latched;
+        self.shown_model = self.display_model;
+        self.status = self.status_flags.status();
+        self.counted_until_us = device.monotonic_us();
+        device.show_status(self.status);
+        device.show(&self.display_model);
+    }
+
+    /// Handle one event, then bring the alarms and their outputs up to date.
+    /// After `DeviceEvent::PowerFail` only the state to keep is saved and the buzzer silenced,
+    /// for the caller to shut down.
+    pub fn handle(&mut self, event: DeviceEvent, device: &mut impl Device, log: &mut impl Log) {
+        let handling_started = device.monotonic_us();
+        // Count the time up to this event with the alarms as they were, in whole seconds.
+        let elapsed = (handling_started.saturating_sub(self.counted_until_us) / 1_000_000) as u32;
+        self.counted_until_us += u64::from(elapsed) * 1_000_000;
+        self.lifetime.run(elapsed, self.annunciator.is_active(AlarmKind::HighTemp), self.annunciator.is_active(AlarmKind::Freeze));
+        match event {
+            DeviceEvent::Door(DoorEvent::Opened) => self.door_opened(device, log),
+            DeviceEvent::Door(DoorEvent::Closed) => {
+                self.door_open = false;
+                device.set_door_open(false);
+                self.door_alarm.closed();
+                let now = device.now();
+                device.log_event(LoggerEvent::DoorClosed(now));
+                if self.door_monitor.changed(now, false, log).is_some() {
+                    self.report_error(ErrorCode::DoorSwitchFault, device);
+                }
+                if self.commissioning.is_some_and(|wizard| wizard.step() == CommissioningStep::CloseDoor) {
+                    self.commissioning_input(CommissioningInput::Door { open: false }, device, log);
+                }
+            }
+            DeviceEvent::ButtonPress(press) => match self.ui.handle(press) {
+                Some(UiAction::ShowPage(page)) => self.display_model.page = page,
+                Some(UiAction::AcknowledgeAlarms) => {
+                    let now = device.now();
+                    if self.annunciator.acknowledge(now) {
+                        log.info(LogCode::AlarmAcknowledged, now.seconds);
+                    }
+                }
+                None => {}
+            },
+            DeviceEvent::TempReading(temperature, quality) => self.reading(temperature, quality, device, log),
+            #[cfg(feature = "humidity")]
+            DeviceEvent::HumidityReading(reading) => {
+                self.humidity = reading;
+                if let Some(rh) = reading {
+                    self.humidity_stats.add(rh);
+                }
+            }
+            #[cfg(feature = "accelerometer")]
+            DeviceEvent::Motion(event) => {
+                let now = device.now();
+                if event != MotionEvent::Upright {
+                    device.log_event(LoggerEvent::Moved(now));
+                }
+                if let Some(day) = self.history.note_motion(self.local_time.to_local(now)) {
+                    self.day_complete(day, device);
+                }
+            }
+            DeviceEvent::Command(command) => self.command(command, device, log),
+            DeviceEvent::TemperatureAlarms(high, freeze) => {
+                self.annunciator.set_active(AlarmKind::HighTemp, high);
+                self.annunciator.set_active(AlarmKind::Freeze, freeze);
+            }
+            DeviceEvent::SensorFault => {
+                self.status_flags.sensor_fault = true;
+                self.report_error(ErrorCode::SensorFail, device);
+            }
+            DeviceEvent::Compressor(event) => {
+                let starts = self.compressor.starts();
+                self.compressor.process_event(event, device.now());
+                if self.compressor.starts() > starts {
+                    self.lifetime.compressor_started();
+                }
+            }
+            DeviceEvent::MainsReading(raw) => self.mains_reading(raw, device, log),
+            DeviceEvent::Power(event, at) => {
+                self.mains_on = event == PowerEvent::On;
+                self.annunciator.set_active(AlarmKind::Power, !self.mains_on);
+                let at = self.logged_at(at);
+                device.log_event(if self.mains_on { LoggerEvent::PowerRestored(at) } else { LoggerEvent::PowerLost(at) });
+                self.update_power_source(device, log);
+            }
+            DeviceEvent::Usb(event) => {
+                self.usb.process_event(event, device.now(), log);
+                self.status_flags.usb_connected = self.usb.is_connected();
+                // TODO: start the USB device for downloads and configuration while connected, once there is a USB stack.
+                self.update_power_source(device, log);
+            }
+            DeviceEvent::PowerFail(checkpoint) => {
+                // Save what would be lost while the hold-up capacitor lasts. Programming a
+                // lifetime slot takes well under a millisecond; should it be cut short, the other
+                // slot still holds the last hourly commit.
+                if let Some(checkpoint) = checkpoint {
+                    device.save(Saved::Checkpoint(checkpoint));
+                }
+                let (slot, bytes) = self.lifetime_store.commit(&self.lifetime, device.now());
+                device.save(Saved::Lifetime(slot, bytes));
+                device.save(Saved::FuelGauge(self.fuel_gauge));
+                device.save(Saved::Errors(self.errors));
+                self.sounding = None;
+                device.sound(None);
+                return;
+            }
+        }
+        self.update(device, log);
+        self.health.loop_latency(device.monotonic_us().saturating_sub(handling_started) as u32);
+    }
+
+    fn door_opened(&mut self, device: &mut impl Device, log: &mut impl Log) {
+        self.door_open = true;
+        device.set_door_open(true);
+        let now = device.now();
+        self.door_alarm.opened(now);
+        self.lifetime.door_opened();
+        device.log_event(LoggerEvent::DoorOpened(now));
+        if self.bursts.trigger(BurstTrigger::DoorOpened, now) {
+            device.burst_started();
+        }
+        if self.door_monitor.changed(now, true, log).is_some() {
+            self.report_error(ErrorCode::DoorSwitchFault, device);
+        }
+        if self.commissioning.is_some_and(|wizard| wizard.step() == CommissioningStep::OpenDoor) {
+            self.commissioning_input(CommissioningInput::Door { open: true }, device, log);
+        }
+        if self.lifecycle.state().records(&LoggerEvent::DoorOpened(now)) {
+            self.display_model.door_openings += 1;
+            if let Some(day) = self.history.note_door_opening(self.local_time.to_local(now)) {
+                self.day_complete(day, device);
+            }
+        }
+    }
+
+    fn command(&mut self, command: DeviceCommand, device: &mut impl Device, log: &mut impl Log) {
+        match command {
+            DeviceCommand::Commission(command) => self.commission(command, device, log),
+            DeviceCommand::SetClock(unix_seconds) => {
+                // The clock counts from the epoch in UTC, so nothing before it can be set.
+                let Some(seconds) = unix_seconds.checked_sub(EPOCH_UNIX_SECONDS) else {
+                    return device.reply(format_args!("error InvalidTime"));
+                };
+                let after = Timestamp { seconds };
+                let before = device.set_clock(after, unix_seconds);
+                log.info(LogCode::ClockSet, seconds.wrapping_sub(before.seconds));
+                device.log_event(LoggerEvent::ClockSet(before, after));
+                device.anchor_clock(after);
+                device.reply(format_args!("ok"));
+            }
+            DeviceCommand::Note(text) => match self.notes.add(device.now(), &text, log) {
+                Ok(()) => {
+                    device.save(Saved::Notes(&self.notes));
+                    device.reply(format_args!("ok"));
+                }
+                Err(error) => device.reply(format_args!("error {:?}", error)),
+            },
+            // Released by the first event after the pulse, e.g. the next mains reading.
+            DeviceCommand::TestRelay => {
+                self.relay.test_pulse(device.now());
+                device.reply(format_args!("ok"));
+            }
+            DeviceCommand::TestMode(on) => {
+                self.test_mode = on;
+                device.save(Saved::TestMode(on));
+                device.reply(format_args!("ok"));
+            }
+            // Straight to the logger task, as if the inputs had seen them.
+            DeviceCommand::Simulate(command) => match command.inject(self.test_mode, device.now(), log) {
+                Ok(events) => {
+                    events.into_iter().for_each(|event| device.log_event(event));
+                    device.reply(format_args!("ok"));
+                }
+                Err(error) => device.reply(format_args!("error {:?}", error)),
+            },
+            #[cfg(feature = "authentication")]
+            DeviceCommand::Lifecycle(next, tag) => {
+                let Some(key) = &self.key else {
+                    return device.reply(format_args!("error NotProvisioned"));
+                };
+                match self.lifecycle.command(next, key, &tag, log) {
+                    Ok(()) => {
+                        self.lifecycle_changed(device);
+                        device.reply(format_args!("ok"));
+                    }
+                    Err(error) => device.reply(format_args!("error {:?}", error)),
+                }
+            }
+            #[cfg(feature = "authentication")]
+            DeviceCommand::ClearIndicator(tag) => {
+                let cleared = match (&mut self.indicator, &self.key) {
+                    (Some(indicator), Some(key)) => indicator.clear(key, &tag).then(|| indicator.state()),
+                    _ => None,
+                };
+                let Some(state) = cleared else {
+                    return device.reply(format_args!("error Unauthorized"));
+                };
+                log.info(LogCode::IndicatorCleared, device.now().seconds);
+                device.save(Saved::Indicator(state));
+                self.status_flags.excursion_latched = false;
+                self.display_model.excursion_latched = false;
+                device.reply(format_args!("ok"));
+            }
+        }
+    }
+
+    fn commission(&mut self, command: CommissionCommand, device: &mut impl Device, log: &mut impl Log) {
+        let input = match command {
+            CommissionCommand::Start => {
+                let wizard = CommissioningWizard::new();
+                self.commissioning = Some(wizard);
+                return device.reply(format_args!("commission {}", wizard.step().prompt()));
+            }
+            CommissionCommand::Stop => {
+                self.commissioning = None;
+                return device.reply(format_args!("ok"));
+            }
+            CommissionCommand::Time(unix_seconds) => CommissioningInput::TimeSet { clock: device.now(), unix_seconds },
+            CommissionCommand::Thresholds(low_celsius, high_celsius) => {
+                CommissioningInput::ThresholdsSet(AlarmProfile { low_celsius, high_celsius, ..self.profile })
+            }
+            CommissionCommand::SelfTest => CommissioningInput::SelfTestRun(self.self_test),
+            CommissionCommand::Check => match (self.display_model.tvc, self.display_model.tamb) {
+                (Some(tvc), Some(tamb)) => CommissioningInput::Readings { tvc, tamb },
+                _ => return device.reply(format_args!("error NoReadings")),
+            },
+        };
+        self.commissioning_input(input, device, log);
+    }
+
+    // Take a step of the wizard, answering with the next step's prompt. Once it completes, the
+    // record is saved and the device commissioned.
+    fn commissioning_input(&mut self, input: CommissioningInput, device: &mut impl Device, log: &mut impl Log) {
+        let Some(wizard) = &mut self.commissioning else {
+            return device.reply(format_args!("error NotCommissioning"));
+        };
+        let step = match wizard.input(input, device.now(), log) {
+            Ok(step) => step,
+            Err(error) => return device.reply(format_args!("error {:?}", error)),
+        };
+        if let Some(record) = wizard.record() {
+            device.save(Saved::Commissioning(record));
+            self.commissioning = None;
+            // Already past the factory when it is commissioned again, e.g. for new thresholds.
+            if self.lifecycle.change(LifecycleState::Commissioned, log).is_ok() {
+                self.lifecycle_changed(device);
+            }
+        }
+        device.reply(format_args!("commission {}", step.prompt()));
+    }
+
+    fn lifecycle_changed(&mut self, device: &mut impl Device) {
+        device.save(Saved::Lifecycle(self.lifecycle));
+        device.set_lifecycle(self.lifecycle.state());
+    }
+
+    fn reading(&mut self, temperature: (f32, f32), quality: (u8, u8), device: &mut impl Device, log: &mut impl Log) {
+        // A substituted reading stands in for a sensor that is still failing.
+        self.status_flags.sensor_fault = (quality.0 | quality.1) & SampleFlag::Substituted.mask() != 0;
+        let (tamb, tvc) = self.display_filter.update(temperature.0, temperature.1);
+        (self.display_model.tamb, self.display_model.tvc) = (Some(tamb), Some(tvc));
+        let sample = TemperatureSample {
+            timestamp: device.now(),
+            tamb: temperature.0,
+            tvc: temperature.1,
+            tamb_quality: quality.0,
+            tvc_quality: quality.1,
+            #[cfg(feature = "humidity")]
+            humidity: self.humidity,
+        };
+        self.tvc_stats.add(sample.timestamp, sample.tvc);
+        let last_day = self.tvc_stats.last_day(sample.timestamp);
+        self.display_model.tvc_min = last_day.min();
+        self.display_model.tvc_max = last_day.max();
+        self.display_model.tvc_avg = last_day.avg();
+        if let Some(last) = self.last_sample_at
+            && sample.timestamp.seconds < last.seconds
+        {
+            log.warn(LogCode::ClockWentBack, last.seconds - sample.timestamp.seconds);
+            self.report_error(ErrorCode::ClockAnomaly, device);
+        }
+        self.last_sample_at = Some(sample.timestamp);
+        device.log_event(LoggerEvent::Sample(sample));
+        if let Some((slot, capture)) = self.bursts.sample(sample, log) {
+            device.save(Saved::Burst(slot, capture));
+        }
+        let recording = self.lifecycle.state().records(&LoggerEvent::Sample(sample));
+        if recording
+            && let Some(indicator) = &mut self.indicator
+            && indicator.update(sample.tvc, sample.timestamp)
+        {
+            log.warn(LogCode::IndicatorLatched, sample.timestamp.seconds);
+            device.save(Saved::Indicator(indicator.state()));
+            self.status_flags.excursion_latched = true;
+            self.display_model.excursion_latched = true;
+        }
+        let run_seconds = self.compressor.compressor_run_seconds(sample.timestamp);
+        self.ajar.update(sample.timestamp, sample.tvc, run_seconds, self.door_open, log);
+        // The logger task raises the alarms themselves; the history needs them for this reading.
+        let (high, freeze) = (self.profile.is_high(sample.tvc), self.profile.is_low(sample.tvc));
+        // Days roll over at local midnight.
+        if recording
+            && let Some(day) = self.history.record(self.local_time.to_local(sample.timestamp), high, freeze)
+        {
+            self.day_complete(day, device);
+        }
+        self.display_model.history = self.history.ticker();
+        self.display_model.history_today = self.history.today();
+    }
+
+    fn mains_reading(&mut self, raw: u16, device: &mut impl Device, log: &mut impl Log) {
+        let state = self.mains.add_reading(raw);
+        self.display_model.mains_volts = Some(self.mains.adc_to_volts(raw));
+        // Outages come from the mains-present input; the readings show how healthy the supply is.
+        if state != self.mains_state {
+            self.mains_state = state;
+            if state == MainsState::Normal {
+                log.info(LogCode::MainsStateChanged, state as u32);
+            } else {
+                log.warn(LogCode::MainsStateChanged, state as u32);
+            }
+        }
+        if PowerSource::from_inputs(self.mains_on, self.usb.is_connected()) == PowerSource::Battery {
+            let percent = self.fuel_gauge.percent_remaining();
+            self.fuel_gauge.record(BATTERY_LOAD_UA, MAINS_SAMPLE_SECONDS);
+            // Saved at each whole percent used, and at power fail.
+            if self.fuel_gauge.percent_remaining() != percent {
+                device.save(Saved::FuelGauge(self.fuel_gauge));
+            }
+            self.display_model.battery_percent = Some(self.fuel_gauge.percent_remaining());
+        }
+        if self.update_power_source(device, log)
+            && let Some(replace_at) = self.fuel_gauge.estimated_replacement(device.now())
+        {
+            log.info(LogCode::BatteryReplaceBy, replace_at.seconds);
+        }
+    }
+
+    // Follow the power source, from the mains and USB inputs. Returns whether the clock profile changed.
+    fn update_power_source(&mut self, device: &mut impl Device, log: &mut impl Log) -> bool {
+        let source = PowerSource::from_inputs(self.mains_on, self.usb.is_connected());
+        let Some(profile) = self.power_manager.update(source, device.now(), log) else {
+            return false;
+        };
+        device.set_clock_profile(profile);
+        true
+    }
+
+    // `at`, or the last reading if that was later, since the logger drops events behind one it has.
+    fn logged_at(&self, at: Timestamp) -> Timestamp {
+        Timestamp { seconds: at.seconds.max(self.last_sample_at.map_or(0, |last| last.seconds)) }
+    }
+
+    fn day_complete(&mut self, day: DayStatus, device: &mut impl Device) {
+        self.health.uptime_seconds = device.uptime_seconds();
+        device.driver_health(&mut self.health);
+        let errors = self.errors.take_packed();
+        device.save(Saved::Errors(self.errors));
+        device.day_complete(DayReport { day, history: &self.history, errors, health: &self.health, lifetime: &self.lifetime });
+    }
+
+    // After every event: the timeouts, the alarms and their outputs, which also ends expired snoozes.
+    fn update(&mut self, device: &mut impl Device, log: &mut impl Log) {
+        let now = device.now();
+        device.anchor_clock(now);
+        if let Some((slot, capture)) = self.bursts.poll(now, log) {
+            device.save(Saved::Burst(slot, capture));
+        }
+        if let Some((slot, bytes)) = self.lifetime_store.commit_if_due(&self.lifetime, now) {
+            device.save(Saved::Lifetime(slot, bytes));
+        }
+        if device.clock_degraded() != self.clock_degraded {
+            self.clock_degraded = !self.clock_degraded;
+            if self.clock_degraded {
+                self.report_error(ErrorCode::ClockAnomaly, device);
+            }
+        }
+        device.correct_clock();
+        if self.door_monitor.poll(now, log).is_some() {
+            self.report_error(ErrorCode::DoorSwitchFault, device);
+        }
+        self.annunciator.set_active(AlarmKind::Door, self.door_alarm.is_active(now));
+
+        let mut buzzer = BuzzerOutput { enabled: self.buzzer_enabled, lifecycle: self.lifecycle, alarm: self.sounding };
+        let mut bursts = BurstOnAlarm { bursts: &mut self.bursts, started: false };
+        self.notifier.update(
+            &mut self.annunciator,
+            &mut self.escalation,
+            now,
+            &mut [&mut AlarmTelemetry(log), &mut bursts, &mut buzzer, &mut self.relay, &mut self.status_flags],
+        );
+        if bursts.started {
+            device.burst_started();
+        }
+        device.set_bursting(self.bursts.is_active());
+        if buzzer.alarm != self.sounding {
+            self.sounding = buzzer.alarm;
+            device.sound(self.sounding);
+        }
+        if self.relay.is_asserted() != self.relay_asserted {
+            self.relay_asserted = self.relay.is_asserted();
+            device.set_relay(self.relay_asserted);
+        }
+        let alarms = AlarmState::capture(&self.annunciator, &self.escalation);
+        if alarms != self.saved_alarms {
+            self.saved_alarms = alarms;
+            device.save(Saved::Alarms(alarms));
+        }
+
+        self.display_model.alarms = AlarmKind::ALL.map(|kind| self.annunciator.is_active(kind));
+        self.display_model.sensor_fault = self.status_flags.sensor_fault;
+        if self.display_model.page == DisplayPage::Diagnostics {
+            self.display_model.uptime_seconds = device.uptime_seconds();
+        }
+        if self.display_model != self.shown_model {
+            self.shown_model = self.display_model;
+            device.show(&self.display_model);
+        }
+        if self.status_flags.status() != self.status {
+            self.status = self.status_flags.status();
+            device.show_status(self.status);
+        }
+    }
+}
+
+/// Logs the alarms starting and clearing.
+// TODO: also send them upstream once there is a telemetry link.
+struct AlarmTelemetry<'a, L>(&'a mut L);
+
+impl<L: Log> AlarmSink for AlarmTelemetry<'_, L> {
+    fn on_alarm_start(&mut self, kind: AlarmKind, _at: Timestamp) {
+        self.0.warn(LogCode::AlarmStarted, kind as u32);
+    }
+
+    fn on_alarm_clear(&mut self, kind: AlarmKind, _at: Timestamp) {
+        self.0.info(LogCode::AlarmCleared, kind as u32);
+    }
+}
+
+/// The alarm the buzzer should sound, unless it is turned off or the lifecycle state keeps that
+/// alarm quiet.
+struct BuzzerOutput {
+    enabled: bool,
+    lifecycle: Lifecycle,
+    alarm: Option<(AlarmKind, bool)>, // With whether it has escalated.
+}
+
+impl AlarmSink for BuzzerOutput {
+    fn on_status(&mut self, status: &AlarmStatus) {
+        self.alarm = status
+            .sounding
+            .filter(|&kind| self.enabled && self.lifecycle.state().sounds(kind))
+            .map(|kind| (kind, status.is_escalated(kind)));
+    }
+}
+
+/// Captures a burst of fast samples around the start of a temperature alarm.
+struct BurstOnAlarm<'a> {
+    bursts: &'a mut BurstRecorder,
+    started: bool, // Whether a burst started.
+}
+
+impl AlarmSink for BurstOnAlarm<'_> {
+    fn on_alarm_start(&mut self, kind: AlarmKind, at: Timestamp) {
+        if matches!(kind, AlarmKind::HighTemp | AlarmKind::Freeze) && self.bursts.trigger(BurstTrigger::Alarm(kind), at) {
+            self.started = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::string::{String, ToString};
+    use std::vec::Vec;
+
+    use crate::alarm::DOOR_ALARM_SECONDS;
+    use crate::lifecycle::LifecycleState;
+    use crate::log::{CaptureLog, Level, NullLog};
+    use crate::relay::RELAY_TEST_SECONDS;
+    use crate::simulate::SimulateCommand;
+    use crate::test_support::{at, reading};
+    use arrayvec::ArrayString;
+    use embassy_futures::block_on;
+
+    // What the task did to the device, in order.
+    #[derive(Debug, Clone, PartialEq)]
+    enum Output {
+        Logged(LoggerEvent),
+        DoorOpen(bool),
+        BurstStarted,
+        Sound(Option<(AlarmKind, bool)>),
+        Relay(bool),
+        Status(DeviceStatus),
+        Saved(&'static str),
+        Profile(ClockProfile),
+        Day(char, PackedErrors),
+        Lifecycle(LifecycleState),
+        Reply(String),
+    }
+
+    #[derive(Default)]
+    struct MockDevice {
+        seconds: u32,
+        outputs: Vec<Output>,
+        shown: Option<DisplayModel>,
+        bursting: bool,
+    }
+
+    impl MockDevice {
+        fn logged(&self) -> Vec<LoggerEvent> {
+            self.outputs.iter().filter_map(|output| if let Output::Logged(event) = output { Some(*event) } else { None }).collect()
+        }
+
+        fn has(&self, output: &Output) -> bool {
+            self.outputs.contains(output)
+        }
+    }
+
+    impl Device for MockDevice {
+        fn now(&self) -> Timestamp {
+            at(self.seconds)
+        }
+
+        fn monotonic_us(&self) -> u64 {
+            u64::from(self.seconds) * 1_000_000
+        }
+
+        fn uptime_seconds(&self) -> u32 {
+            self.seconds
+        }
+
+        fn clock_degraded(&self) -> bool {
+            false
+        }
+
+        fn correct_clock(&mut self) -> u32 {
+            0
+        }
+
+        fn anchor_clock(&mut self, _now: Timestamp) {}
+
+        fn set_clock(&mut self, now: Timestamp, _unix_seconds: u32) -> Timestamp {
+            let before = self.now();
+            self.seconds = now.seconds;
+            before
+        }
+
+        fn log_event(&mut self, event: LoggerEvent) {
+            self.outputs.push(Output::Logged(event));
+        }
+
+        fn set_door_open(&mut self, open: bool) {
+            self.outputs.push(Output::DoorOpen(open));
+        }
+
+        fn burst_started(&mut self) {
+            self.outputs.push(Output::BurstStarted);
+        }
+
+        fn set_bursting(&mut self, active: bool) {
+            self.bursting = active;
+        }
+
+        fn sound(&mut self, alarm: Option<(AlarmKind, bool)>) {
+            self.outputs.push(Output::Sound(alarm));
+        }
+
+        fn set_relay(&mut self, asserted: bool) {
+            self.outputs.push(Output::Relay(asserted));
+        }
+
+        fn show(&mut self, model: &DisplayModel) {
+            self.shown = Some(*model);
+        }
+
+        fn show_status(&mut self, status: DeviceStatus) {
+            self.outputs.push(Output::Status(status));
+        }
+
+        fn set_clock_profile(&mut self, profile: ClockProfile) {
+            self.outputs.push(Output::Profile(profile));
+        }
+
+        fn save(&mut self, saved: Saved<'_>) {
+            let name = match saved {
+                Saved::Alarms(_) => "alarms",
+                Saved::Burst(..) => "burst",
+                Saved::Indicator(_) => "indicator",
+                Saved::Lifetime(..) => "lifetime",
+                Saved::FuelGauge(_) => "fuel gauge",
+                Saved::Errors(_) => "errors",
+                Saved::Checkpoint(_) => "checkpoint",
+                Saved::Lifecycle(_) => "lifecycle",
+                Saved::Commissioning(_) => "commissioning",
+                Saved::Notes(_) => "notes",
+                Saved::TestMode(_) => "test mode",
+            };
+            self.outputs.push(Output::Saved(name));
+        }
+
+        fn set_lifecycle(&mut self, state: LifecycleState) {
+            self.outputs.push(Output::Lifecycle(state));
+        }
+
+        fn reply(&mut self, line: fmt::Arguments<'_>) {
+            self.outputs.push(Output::Reply(line.to_string()));
+        }
+
+        fn driver_health(&self, health: &mut DeviceHealth) {
+            health.i2c_errors = 3;
+        }
+
+        fn day_complete(&mut self, report: DayReport<'_>) {
+            assert_eq!(report.health.i2c_errors, 3);
+            self.outputs.push(Output::Day(report.day.symbol(), report.errors));
+        }
+    }
+
+    // Events for `DeviceTask::run`, in order.
+    struct Script(VecDeque<DeviceEvent>);
+
+    impl DeviceEvents for Script {
+        async fn receive(&mut self) -> Option<DeviceEvent> {
+            self.0.pop_front()
+        }
+
+        fn waiting(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    fn task() -> DeviceTask {
+        DeviceTask::new(&Config::default(), Lifecycle::new(LifecycleState::Logging), FuelGauge::new(1000, at(0)))
+    }
+
+    fn handle(task: &mut DeviceTask, device: &mut MockDevice, seconds: u32, event: DeviceEvent) {
+        device.seconds = seconds;
+        task.handle(event, device, &mut NullLog);
+    }
+
+    #[test]
+    fn test_door_alarm() {
+        let mut task = task();
+        let mut device = MockDevice::default();
+        task.start(&mut device);
+        handle(&mut task, &mut device, 100, DeviceEvent::Door(DoorEvent::Opened));
+        assert_eq!(device.logged(), [LoggerEvent::DoorOpened(at(100))]);
+        assert!(device.has(&Output::DoorOpen(true)) && device.has(&Output::BurstStarted) && device.bursting);
+        assert_eq!(task.lifetime().door_openings, 1);
+        handle(&mut task, &mut device, 101 + DOOR_ALARM_SECONDS, DeviceEvent::MainsReading(2000));
+        assert!(device.has(&Output::Sound(Some((AlarmKind::Door, false)))));
+        assert!(!device.has(&Output::Relay(true)), "the door doesn't assert the relay by default");
+        assert!(device.has(&Output::Status(DeviceStatus::Alarm)) && device.has(&Output::Saved("alarms")));
+        assert_eq!(device.shown.unwrap().alarms, [false, false, false, true]);
+        handle(&mut task, &mut device, 200 + DOOR_ALARM_SECONDS, DeviceEvent::Door(DoorEvent::Closed));
+        assert_eq!(device.outputs.iter().filter(|output| matches!(output, Output::Sound(None))).count(), 1);
+        assert_eq!(device.logged()[1..], [LoggerEvent::DoorClosed(at(200 + DOOR_ALARM_SECONDS))]);
+    }
+
+    #[test]
+    fn test_temperature_alarm_sounds_and_acknowledges() {
+        let mut task = task();
+        let mut device = MockDevice::default();
+        let mut log = CaptureLog::default();
+        task.start(&mut device);
+        device.seconds = 100;
+        task.handle(DeviceEvent::TemperatureAlarms(true, false), &mut device, &mut log);
+        assert!(device.has(&Output::Sound(Some((AlarmKind::HighTemp, false)))) && device.has(&Output::Relay(true)));
+        assert!(device.has(&Output::BurstStarted), "an alarm starts a burst");
+        assert_eq!(log.entries, [(Level::Warn, LogCode::AlarmStarted, AlarmKind::HighTemp as u32)]);
+        // A long press acknowledges the buzzer, but the relay stays asserted.
+        device.seconds = 110;
+        task.handle(DeviceEvent::ButtonPress(Press::Long), &mut device, &mut log);
+        assert!(device.has(&Output::Sound(None)) && !device.has(&Output::Relay(false)));
+        assert!(log.entries.contains(&(Level::Info, LogCode::AlarmAcknowledged, 110)));
+    }
+
+    #[test]
+    fn test_relay_test_pulse() {
+        let mut task = task();
+        let mut device = MockDevice::default();
+        task.start(&mut device);
+        handle(&mut task, &mut device, 100, DeviceEvent::Command(DeviceCommand::TestRelay));
+        assert_eq!(last_reply(&device), "ok");
+        assert!(device.has(&Output::Relay(true)));
+        handle(&mut task, &mut device, 100 + RELAY_TEST_SECONDS - 1, DeviceEvent::MainsReading(2000));
+        assert!(!device.has(&Output::Relay(false)));
+        handle(&mut task, &mut device, 100 + RELAY_TEST_SECONDS, DeviceEvent::MainsReading(2000));
+        assert!(device.has(&Output::Relay(false)));
+    }
+
+    #[test]
+    fn test_simulate() {
+        let mut task = task();
+        let mut device = MockDevice::default();
+        task.start(&mut device);
+        let simulate = |command| DeviceEvent::Command(DeviceCommand::Simulate(command));
+        handle(&mut task, &mut device, 100, simulate(SimulateCommand::Door { open: true }));
+        assert_eq!(last_reply(&device), "error TestModeOff");
+        assert!(device.logged().is_empty());
+        handle(&mut task, &mut device, 110, DeviceEvent::Command(DeviceCommand::TestMode(true)));
+        assert!(device.has(&Output::Saved("test mode")));
+        handle(&mut task, &mut device, 120, simulate(SimulateCommand::Door { open: true }));
+        assert_eq!(last_reply(&device), "ok");
+        assert_eq!(device.logged(), [LoggerEvent::Fault(at(120), ErrorCode::Simulated), LoggerEvent::DoorOpened(at(120))]);
+    }
+
+    #[test]
+    fn test_readings() {
+        let mut task = task();
+        let mut device = MockDevice::default();
+        task.start(&mut device);
+        handle(&mut task, &mut device, 100, DeviceEvent::TempReading((25.0, 5.0), (0, 0)));
+        let [LoggerEvent::Sample(sample)] = device.logged()[..] else { panic!("expected a sample") };
+        assert_eq!((sample.timestamp, sample.tamb, sample.tvc), (at(100), 25.0, 5.0));
+        assert_eq!(device.shown.unwrap().tvc, Some(5.0));
+        // Faults go into the records, never behind a reading already logged.
+        handle(&mut task, &mut device, 90, DeviceEvent::TempReading((25.0, 5.0), (0, 0)));
+        handle(&mut task, &mut device, 95, DeviceEvent::SensorFault);
+        assert_eq!(
+            device.logged()[1..],
+            [
+                LoggerEvent::Fault(at(100), ErrorCode::ClockAnomaly),
+                LoggerEvent::Sample(reading(90, 5.0)),
+                LoggerEvent::Fault(at(95), ErrorCode::SensorFail),
+            ]
+        );
+        assert!(device.has(&Output::Status(DeviceStatus::SensorFault)));
+        // The day's faults are reported when it completes, and the counts saved.
+        handle(&mut task, &mut device, 86_400, DeviceEvent::TempReading((25.0, 5.0), (0, 0)));
+        let mut errors = PackedErrors::default();
+        errors.push(ErrorCode::ClockAnomaly);
+        errors.push(ErrorCode::SensorFail);
+        let day = device.outputs.iter().position(|output| *output == Output::Day('.', errors)).unwrap();
+        assert_eq!(device.outputs[day - 1], Output::Saved("errors"));
+    }
+
+    #[test]
+    fn test_error_counts_carry_on() {
+        let mut errors = ErrorLog::new();
+        errors.report(ErrorCode::FlashFail);
+        let mut task = task().with_errors(errors);
+        let mut device = MockDevice { seconds: 100, ..MockDevice::default() };
+        task.report_error(ErrorCode::FlashFail, &mut device);
+        assert_eq!(task.errors.count(ErrorCode::FlashFail), 2);
+        assert_eq!(device.logged(), [LoggerEvent::Fault(at(100), ErrorCode::FlashFail)]);
+    }
+
+    #[test]
+    fn test_battery() {
+        let mut task = task();
+        let mut device = MockDevice::default();
+        let mut log = CaptureLog::default();
+        task.start(&mut device);
+        device.seconds = 100;
+        task.handle(DeviceEvent::Power(PowerEvent::Off, at(90)), &mut device, &mut log);
+        assert_eq!(device.logged(), [LoggerEvent::PowerLost(at(90))]);
+        assert!(device.has(&Output::Sound(Some((AlarmKind::Power, false)))));
+        // The gauge is saved as each percent is used, and the low-power profile applies once the supply has settled.
+        for seconds in (100..130).step_by(MAINS_SAMPLE_SECONDS as usize) {
+            device.seconds = seconds;
+            task.handle(DeviceEvent::MainsReading(2000), &mut device, &mut log);
+        }
+        assert!(device.has(&Output::Saved("fuel gauge")) && !device.has(&Output::Profile(ClockProfile::LOW_POWER)));
+        device.seconds = 130;
+        task.handle(DeviceEvent::MainsReading(2000), &mut device, &mut log);
+        assert!(device.has(&Output::Profile(ClockProfile::LOW_POWER)));
+        assert!(log.entries.iter().any(|&(_, code, _)| code == LogCode::BatteryReplaceBy));
+        assert_eq!(device.shown.unwrap().battery_percent, Some(99));
+    }
+
+    #[test]
+    fn test_restored_alarms() {
+        let mut annunciator = Annunciator::new();
+        let mut escalation = Escalation::default();
+        annunciator.set_active(AlarmKind::Door, true);
+        escalation.set_active(AlarmKind::Door, true, at(1000));
+        let state = AlarmState::capture(&annunciator, &escalation);
+        let mut task = task().with_alarm_state(state);
+        let mut device = MockDevice::default();
+        task.start(&mut device);
+        // Still open after the reset, so the alarm goes on rather than starting again.
+        handle(&mut task, &mut device, 1100, DeviceEvent::MainsReading(2000));
+        assert!(device.has(&Output::Sound(Some((AlarmKind::Door, false)))));
+        assert!(!device.has(&Output::Saved("alarms")));
+        handle(&mut task, &mut device, 1110, DeviceEvent::Door(DoorEvent::Closed));
+        assert!(device.has(&Output::Sound(None)) && device.has(&Output::Saved("alarms")));
+    }
+
+    fn last_reply(device: &MockDevice) -> &str {
+        device.outputs.iter().rev().find_map(|output| if let Output::Reply(line) = output { Some(line.as_str()) } else { None }).unwrap()
+    }
+
+    #[test]
+    fn test_commissioning() {
+        let mut passed = SelfTestReport::new();
+        for item in crate::selftest::SelfTestItem::ALL {
+            passed.record(item, true);
+        }
+        let mut task = DeviceTask::new(&Config::default(), Lifecycle::new(LifecycleState::FactoryTest), FuelGauge::new(1000, at(0))).with_self_test(passed);
+        let mut device = MockDevice::default();
+        task.start(&mut device);
+        let commission = |command| DeviceEvent::Command(DeviceCommand::Commission(command));
+        handle(&mut task, &mut device, 100, commission(CommissionCommand::Time(1_700_000_000)));
+        assert_eq!(last_reply(&device), "error NotCommissioning");
+        handle(&mut task, &mut device, 100, commission(CommissionCommand::Start));
+        assert_eq!(last_reply(&device), "commission Set the clock");
+        handle(&mut task, &mut device, 100, commission(CommissionCommand::Time(1_700_000_000)));
+        handle(&mut task, &mut device, 110, commission(CommissionCommand::Thresholds(8.0, 2.0)));
+        assert_eq!(last_reply(&device), "error InvalidThresholds");
+        handle(&mut task, &mut device, 110, commission(CommissionCommand::Thresholds(2.0, 8.0)));
+        handle(&mut task, &mut device, 120, commission(CommissionCommand::SelfTest));
+        assert_eq!(last_reply(&device), "commission Open the door");
+        // The door steps come from the switch.
+        handle(&mut task, &mut device, 130, DeviceEvent::Door(DoorEvent::Opened));
+        handle(&mut task, &mut device, 140, DeviceEvent::Door(DoorEvent::Closed));
+        assert_eq!(last_reply(&device), "commission Wait for the appliance to reach temperature, then check the sensors");
+        handle(&mut task, &mut device, 150, commission(CommissionCommand::Check));
+        assert_eq!(last_reply(&device), "error NoReadings");
+        handle(&mut task, &mut device, 160, DeviceEvent::TempReading((24.0, 5.0), (0, 0)));
+        handle(&mut task, &mut device, 170, commission(CommissionCommand::Check));
+        assert_eq!(last_reply(&device), "commission Commissioning complete");
+        assert!(device.has(&Output::Saved("commissioning")) && device.has(&Output::Saved("lifecycle")));
+        assert!(device.has(&Output::Lifecycle(LifecycleState::Commissioned)));
+        assert_eq!(task.commissioning, None);
+    }
+
+    #[test]
+    fn test_notes() {
+        let mut task = task();
+        let mut device = MockDevice::default();
+        task.start(&mut device);
+        let note = |text| DeviceEvent::Command(DeviceCommand::Note(ArrayString::from(text).unwrap()));
+        handle(&mut task, &mut device, 100, note(""));
+        assert_eq!(last_reply(&device), "error Empty");
+        assert!(!device.has(&Output::Saved("notes")));
+        handle(&mut task, &mut device, 200, note("defrost performed"));
+        assert_eq!(last_reply(&device), "ok");
+        assert!(device.has(&Output::Saved("notes")));
+        assert_eq!(task.notes.iter().map(|note| note.timestamp).collect::<Vec<_>>(), [at(200)]);
+    }
+
+    #[test]
+    fn test_set_clock() {
+        let mut task = task();
+        let mut device = MockDevice::default();
+        task.start(&mut device);
+        handle(&mut task, &mut device, 100, DeviceEvent::Command(DeviceCommand::SetClock(EPOCH_UNIX_SECONDS - 1)));
+        assert_eq!(last_reply(&device), "error InvalidTime");
+        let mut log = CaptureLog::default();
+        task.handle(DeviceEvent::Command(DeviceCommand::SetClock(EPOCH_UNIX_SECONDS + 40)), &mut device, &mut log);
+        assert_eq!(last_reply(&device), "ok");
+        assert_eq!(device.now(), at(40));
+        assert_eq!(device.logged(), [LoggerEvent::ClockSet(at(100), at(40))]);
+        assert_eq!(log.entries, [(Level::Info, LogCode::ClockSet, -60i32 as u32)]);
+    }
+
+    #[cfg(feature = "authentication")]
+    #[test]
+    fn test_authenticated_commands() {
+        use crate::indicator::clear_tag;
+        use crate::lifecycle::command_tag;
+        use crate::provisioning::DEVICE_KEY_LEN;
+
+        let key = DeviceKey([7; DEVICE_KEY_LEN]);
+        let settings = Config { indicator_mode: true, ..Config::default() };
+        let latched = IndicatorState { freeze: false, heat: true, latched_at: Some(at(50)) };
+        let lifecycle = Lifecycle::new(LifecycleState::Logging);
+        let mut task = DeviceTask::new(&settings, lifecycle, FuelGauge::new(1000, at(0))).with_indicator(latched).with_key(key);
+        let mut device = MockDevice::default();
+        task.start(&mut device);
+        let wrong = command_tag(&DeviceKey([8; DEVICE_KEY_LEN]), &lifecycle, LifecycleState::Storage);
+        handle(&mut task, &mut device, 100, DeviceEvent::Command(DeviceCommand::Lifecycle(LifecycleState::Storage, wrong)));
+        assert_eq!(last_reply(&device), "error Unauthorized");
+        let tag = command_tag(&key, &lifecycle, LifecycleState::Storage);
+        handle(&mut task, &mut device, 100, DeviceEvent::Command(DeviceCommand::Lifecycle(LifecycleState::Storage, tag)));
+        assert_eq!(last_reply(&device), "ok");
+        assert!(device.has(&Output::Saved("lifecycle")) && device.has(&Output::Lifecycle(LifecycleState::Storage)));
+        // The tag is only good once.
+        let replayed = command_tag(&key, &lifecycle, LifecycleState::Logging);
+        handle(&mut task, &mut device, 100, DeviceEvent::Command(DeviceCommand::Lifecycle(LifecycleState::Logging, replayed)));
+        assert_eq!(last_reply(&device), "error Unauthorized");
+
+        assert!(device.shown.unwrap().excursion_latched);
+        handle(&mut task, &mut device, 110, DeviceEvent::Command(DeviceCommand::ClearIndicator(clear_tag(&key, &latched))));
+        assert_eq!(last_reply(&device), "ok");
+        assert!(device.has(&Output::Saved("indicator")) && !device.shown.unwrap().excursion_latched);
+        handle(&mut task, &mut device, 120, DeviceEvent::Command(DeviceCommand::ClearIndicator(clear_tag(&key, &latched))));
+        assert_eq!(last_reply(&device), "error Unauthorized", "nothing left to clear");
+    }
+
+    #[test]
+    fn test_run_until_power_fail() {
+        let mut script = Script(VecDeque::from([
+            DeviceEvent::Door(DoorEvent::Opened),
+            DeviceEvent::PowerFail(None),
+            DeviceEvent::Door(DoorEvent::Closed),
+        ]));
+        let mut task = task();
+        let mut device = MockDevice { seconds: 100, ..MockDevice::default() };
+        block_on(task.run(&mut script, &mut device, &mut NullLog));
+        assert_eq!(device.logged(), [LoggerEvent::DoorOpened(at(100))]);
+        assert_eq!(
+            device.outputs[device.outputs.len() - 4..],
+            [Output::Saved("lifetime"), Output::Saved("fuel gauge"), Output::Saved("errors"), Output::Sound(None)]
+        );
+        assert_eq!(script.0.len(), 1, "nothing is handled after the power fail");
+        assert_eq!(task.health().queue_high_water, 3);
+    }
+}
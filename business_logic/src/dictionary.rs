@@ -0,0 +1,56 @@
+use core::fmt::{self, Write};
+
+use crate::aggregator::{AGGREGATION_RECORD_LEN, BAND_LIMITS_CELSIUS, RECORD_FIELDS, RECORD_VERSION};
+
+/// Room for the whole dictionary, for a device that sends it in one piece.
+pub const DICTIONARY_LEN: usize = 2048;
+
+/// Write a machine-readable description of the record format this firmware writes, as CSV, so
+/// host tools can decode downloads from any firmware version without being updated.
+///
+/// The first line names the format, its version, its length in bytes and its byte order; the
+/// second gives the time-in-band limits, °C. Then a column header and one row per field, with
+/// its byte offset in the serialized record. Generated from `RECORD_FIELDS`, like the serializer.
+pub fn write_dictionary(out: &mut impl Write) -> fmt::Result {
+    writeln!(out, "format,aggregation_record,{},{},le", RECORD_VERSION, AGGREGATION_RECORD_LEN)?;
+    out.write_str("bands")?;
+    for limit in BAND_LIMITS_CELSIUS {
+        write!(out, ",{}", limit)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "name,offset,type,unit,scale,since_version")?;
+    for (index, field) in RECORD_FIELDS.iter().enumerate() {
+        writeln!(out, "{},{},{},{},{},{}", field.name, 2 + 4 * index, field.field_type.name(), field.unit, field.scale, field.since_version)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregator::AggregationRecord;
+    use crate::timestamp::Timestamp;
+
+    #[test]
+    fn test_dictionary_describes_serializer() {
+        let mut text = String::new();
+        write_dictionary(&mut text).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "format,aggregation_record,5,190,le");
+        assert_eq!(lines[1], "bands,-0.5,2,8,15");
+        assert_eq!(lines[3], "start,2,u32,s,1,1");
+        assert_eq!(lines.last(), Some(&"door_alarm_seconds,186,u32,s,1,5"));
+        assert_eq!(lines.len(), 3 + RECORD_FIELDS.len());
+        assert!(text.len() <= DICTIONARY_LEN);
+        // Decode a record using only the dictionary.
+        let record = AggregationRecord { door_openings: 7, tvc_max: 6.5, ..AggregationRecord::new(Timestamp { seconds: 900 }) };
+        let bytes = record.to_bytes();
+        let word = |name: &str| {
+            let row: Vec<&str> = lines[3..].iter().map(|line| line.split(',').collect::<Vec<_>>()).find(|row| row[0] == name).unwrap();
+            let offset: usize = row[1].parse().unwrap();
+            (row[2].to_string(), u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()))
+        };
+        assert_eq!(word("door_openings"), ("u32".to_string(), 7));
+        assert_eq!(word("tvc_max"), ("f32".to_string(), 6.5f32.to_bits()));
+    }
+}
@@ -0,0 +1,111 @@
+use arrayvec::ArrayVec;
+
+/// Most items served from the urgent lane in a row while bulk work waits, so a steady stream of
+/// events still lets a download or maintenance job make progress.
+pub const MAX_URGENT_RUN: u8 = 8;
+
+/// Priority lanes, highest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Lane {
+    Urgent, // Events that drive the alarms, e.g. door and power events and readings.
+    Bulk, // Work that can wait, e.g. record downloads and flash maintenance.
+}
+
+/// Queues work in priority lanes and hands it out urgent lane first, first in first out within
+/// a lane, without starving the bulk lane.
+#[derive(Debug, Clone)]
+pub struct Dispatcher<T, const N: usize> {
+    urgent: ArrayVec<T, N>,
+    bulk: ArrayVec<T, N>,
+    urgent_run: u8, // Urgent items served in a row while bulk work waited.
+}
+
+impl<T, const N: usize> Default for Dispatcher<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Dispatcher<T, N> {
+    pub const fn new() -> Self {
+        Self { urgent: ArrayVec::new_const(), bulk: ArrayVec::new_const(), urgent_run: 0 }
+    }
+
+    /// Queue `item` in `lane`. Gives it back if the lane is full.
+    pub fn push(&mut self, lane: Lane, item: T) -> Result<(), T> {
+        let queue = match lane {
+            Lane::Urgent => &mut self.urgent,
+            Lane::Bulk => &mut self.bulk,
+        };
+        queue.try_push(item).map_err(|error| error.element())
+    }
+
+    /// The next item to work on, and its lane.
+    pub fn pop(&mut self) -> Option<(Lane, T)> {
+        let bulk_due = self.urgent.is_empty() || self.urgent_run >= MAX_URGENT_RUN;
+        if !self.bulk.is_empty() && bulk_due {
+            self.urgent_run = 0;
+            return Some((Lane::Bulk, self.bulk.remove(0)));
+        }
+        if self.urgent.is_empty() {
+            return None;
+        }
+        if !self.bulk.is_empty() {
+            self.urgent_run += 1;
+        }
+        Some((Lane::Urgent, self.urgent.remove(0)))
+    }
+
+    pub fn len(&self, lane: Lane) -> usize {
+        match lane {
+            Lane::Urgent => self.urgent.len(),
+            Lane::Bulk => self.bulk.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.urgent.is_empty() && self.bulk.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_urgent_first_in_order() {
+        let mut dispatcher: Dispatcher<u32, 4> = Dispatcher::new();
+        dispatcher.push(Lane::Bulk, 1).unwrap();
+        dispatcher.push(Lane::Urgent, 2).unwrap();
+        dispatcher.push(Lane::Urgent, 3).unwrap();
+        assert_eq!(dispatcher.pop(), Some((Lane::Urgent, 2)));
+        assert_eq!(dispatcher.pop(), Some((Lane::Urgent, 3)));
+        assert_eq!(dispatcher.pop(), Some((Lane::Bulk, 1)));
+        assert_eq!(dispatcher.pop(), None);
+        for item in 0..4 {
+            dispatcher.push(Lane::Urgent, item).unwrap();
+        }
+        assert_eq!(dispatcher.push(Lane::Urgent, 4), Err(4));
+        assert!(dispatcher.push(Lane::Bulk, 4).is_ok());
+    }
+
+    #[test]
+    fn test_bulk_not_starved() {
+        let mut dispatcher: Dispatcher<u32, 4> = Dispatcher::new();
+        dispatcher.push(Lane::Bulk, 100).unwrap();
+        dispatcher.push(Lane::Bulk, 101).unwrap();
+        // The urgent lane never empties, yet each bulk item waits only `MAX_URGENT_RUN` turns.
+        let mut served = Vec::new();
+        for event in 0..30 {
+            dispatcher.push(Lane::Urgent, event).unwrap();
+            served.push(dispatcher.pop().unwrap());
+        }
+        let bulk_turns: Vec<usize> = served.iter().enumerate().filter(|(_, (lane, _))| *lane == Lane::Bulk).map(|(turn, _)| turn).collect();
+        assert_eq!(bulk_turns, [MAX_URGENT_RUN as usize, 2 * MAX_URGENT_RUN as usize + 1]);
+        assert_eq!(served[MAX_URGENT_RUN as usize], (Lane::Bulk, 100));
+        // Nothing urgent was lost or reordered.
+        let urgent: Vec<u32> = served.iter().filter(|&&(lane, _)| lane == Lane::Urgent).map(|&(_, item)| item).collect();
+        assert!(urgent.windows(2).all(|pair| pair[0] + 1 == pair[1]));
+    }
+}
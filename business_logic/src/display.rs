@@ -0,0 +1,246 @@
+use arrayvec::ArrayString;
+use core::fmt::Write;
+
+use crate::alarm::AlarmKind;
+use crate::history::{DayStatus, HISTORY_DAYS};
+use crate::timestamp::Timestamp;
+use crate::units::TemperatureUnit;
+
+/// Characters per display line (128 px wide display, 6 px per character).
+pub const DISPLAY_COLUMNS: usize = 21;
+/// Number of text lines produced by the display model.
+pub const DISPLAY_LINES: usize = 4;
+/// Samples averaged by the display filter unless configured otherwise.
+pub const DEFAULT_DISPLAY_FILTER_SAMPLES: u8 = 4;
+/// Longest display filter, in samples.
+pub const MAX_DISPLAY_FILTER_SAMPLES: u8 = 32;
+
+pub type DisplayLine = ArrayString<DISPLAY_COLUMNS>;
+
+/// Pages the display can show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DisplayPage {
+    #[default]
+    Status,
+    TvcStats,
+    History,
+    Diagnostics,
+}
+
+/// Everything shown on the live status display.
+/// Rendering to text is done here so it can be tested off-target.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DisplayModel {
+    pub page: DisplayPage,
+    pub unit: TemperatureUnit, // Temperatures below are in °C and converted for display.
+    pub tvc: Option<f32>,
+    pub tamb: Option<f32>,
+    pub alarms: [bool; AlarmKind::ALL.len()], // Indexed in `AlarmKind::ALL` order.
+    pub excursion_latched: bool, // Shown as an X until the indicator is cleared.
+    pub door_openings: u32,
+    pub memory_days_remaining: Option<u16>,
+    pub tvc_min: Option<f32>,
+    pub tvc_max: Option<f32>,
+    pub tvc_avg: Option<f32>,
+    pub sensor_fault: bool,
+    pub mains_volts: Option<f32>,
+    pub battery_percent: Option<u8>,
+    pub uptime_seconds: u32,
+    pub history: ArrayString<HISTORY_DAYS>, // One symbol per completed day, oldest first.
+    pub history_today: DayStatus,
+}
+
+impl DisplayModel {
+    /// Render the current page as lines of text, top to bottom.
+    pub fn lines(&self) -> [DisplayLine; DISPLAY_LINES] {
+        match self.page {
+            DisplayPage::Status => self.status_lines(),
+            DisplayPage::TvcStats => self.tvc_stats_lines(),
+            DisplayPage::History => self.history_lines(),
+            DisplayPage::Diagnostics => self.diagnostics_lines(),
+        }
+    }
+
+    fn status_lines(&self) -> [DisplayLine; DISPLAY_LINES] {
+        let mut lines = [DisplayLine::new(); DISPLAY_LINES];
+        write_temperature(&mut lines[0], "TVC ", self.tvc, self.unit);
+        write_temperature(&mut lines[1], "TAMB", self.tamb, self.unit);
+        if self.excursion_latched {
+            lines[2].push_str("X ");
+        }
+        if self.alarms.iter().any(|&active| active) {
+            lines[2].push_str("ALARM");
+            for (kind, _) in AlarmKind::ALL.iter().zip(self.alarms).filter(|(_, active)| *active) {
+                lines[2].push(' ');
+                lines[2].push(kind.icon());
+            }
+        } else {
+            lines[2].push_str("OK");
+        }
+        // Saturate rather than overflow the line if a count is implausibly large.
+        let _ = write!(&mut lines[3], "DOOR {:<4} MEM ", self.door_openings.min(9999));
+        let _ = match self.memory_days_remaining {
+            Some(days) => write!(&mut lines[3], "{}D", days.min(999)),
+            None => write!(&mut lines[3], "--D"),
+        };
+        lines
+    }
+
+    fn tvc_stats_lines(&self) -> [DisplayLine; DISPLAY_LINES] {
+        let mut lines = [DisplayLine::new(); DISPLAY_LINES];
+        lines[0].push_str("TVC LAST 24H");
+        write_temperature(&mut lines[1], "MIN ", self.tvc_min, self.unit);
+        write_temperature(&mut lines[2], "MAX ", self.tvc_max, self.unit);
+        write_temperature(&mut lines[3], "AVG ", self.tvc_avg, self.unit);
+        lines
+    }
+
+    fn history_lines(&self) -> [DisplayLine; DISPLAY_LINES] {
+        let mut lines = [DisplayLine::new(); DISPLAY_LINES];
+        lines[0].push_str("30 DAY HISTORY");
+        // Oldest 15 days on the first line, newest 15 on the second.
+        let split = self.history.len().saturating_sub(HISTORY_DAYS / 2);
+        lines[1].push_str(&self.history[..split]);
+        lines[2].push_str(&self.history[split..]);
+        let _ = write!(&mut lines[3], "TODAY {}", self.history_today.symbol());
+        lines
+    }
+
+    fn diagnostics_lines(&self) -> [DisplayLine; DISPLAY_LINES] {
+        let mut lines = [DisplayLine::new(); DISPLAY_LINES];
+        lines[0].push_str("DIAGNOSTICS");
+        lines[1].push_str(if self.sensor_fault { "SENSOR FAULT" } else { "SENSOR OK" });
+        let _ = match self.mains_volts {
+            Some(volts) => write!(&mut lines[2], "MAINS {:4.1}V", volts),
+            None => write!(&mut lines[2], "MAINS --.-V"),
+        };
+        if let Some(percent) = self.battery_percent {
+            let _ = write!(&mut lines[2], " BAT {}%", percent);
+        }
+        let uptime = Timestamp { seconds: self.uptime_seconds }.create_iso8601_str();
+        let _ = write!(&mut lines[3], "UP {}", uptime);
+        lines
+    }
+}
+
+/// Exponential moving average of the readings shown, so the display doesn't jitter with each
+/// sample. Only for display and status values; records keep the raw readings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayFilter {
+    alpha: f32, // Weight of a new reading, 2 / (N + 1) for an average over about N samples.
+    tvc: Option<f32>,
+    tamb: Option<f32>,
+}
+
+impl Default for DisplayFilter {
+    fn default() -> Self {
+        Self::new(DEFAULT_DISPLAY_FILTER_SAMPLES)
+    }
+}
+
+impl DisplayFilter {
+    /// A filter averaging over about `samples` readings. 1 shows the raw readings.
+    pub fn new(samples: u8) -> Self {
+        Self { alpha: 2.0 / (f32::from(samples.max(1)) + 1.0), tvc: None, tamb: None }
+    }
+
+    /// Add a reading, returning the values to show as `(tamb, tvc)`. The first reading is shown as is.
+    pub fn update(&mut self, tamb: f32, tvc: f32) -> (f32, f32) {
+        let smooth = |shown: &mut Option<f32>, reading: f32| *shown.insert(shown.map_or(reading, |value| value + self.alpha * (reading - value)));
+        (smooth(&mut self.tamb, tamb), smooth(&mut self.tvc, tvc))
+    }
+}
+
+fn write_temperature(line: &mut DisplayLine, label: &str, celsius: Option<f32>, unit: TemperatureUnit) {
+    let _ = match celsius {
+        Some(celsius) => write!(line, "{} {:5.1}{}", label, unit.from_celsius(celsius), unit.symbol()),
+        None => write!(line, "{}  --.-{}", label, unit.symbol()),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_ok() {
+        let model = DisplayModel {
+            tvc: Some(4.46),
+            tamb: Some(-21.0),
+            door_openings: 12,
+            memory_days_remaining: Some(28),
+            ..Default::default()
+        };
+        let lines = model.lines();
+        assert_eq!(lines[0].as_str(), "TVC    4.5C");
+        assert_eq!(lines[1].as_str(), "TAMB -21.0C");
+        assert_eq!(lines[2].as_str(), "OK");
+        assert_eq!(lines[3].as_str(), "DOOR 12   MEM 28D");
+    }
+
+    #[test]
+    fn test_other_pages() {
+        let mut model = DisplayModel {
+            page: DisplayPage::TvcStats,
+            tvc_min: Some(2.14),
+            tvc_max: Some(7.9),
+            mains_volts: Some(5.04),
+            battery_percent: Some(87),
+            uptime_seconds: 93784,
+            ..Default::default()
+        };
+        let lines = model.lines();
+        assert_eq!(lines[0].as_str(), "TVC LAST 24H");
+        assert_eq!(lines[1].as_str(), "MIN    2.1C");
+        assert_eq!(lines[2].as_str(), "MAX    7.9C");
+        assert_eq!(lines[3].as_str(), "AVG   --.-C");
+        model.page = DisplayPage::History;
+        model.history.push_str("...............H.............F");
+        model.history_today = DayStatus { has_data: true, ..Default::default() };
+        let lines = model.lines();
+        assert_eq!(lines[1].as_str(), "...............");
+        assert_eq!(lines[2].as_str(), "H.............F");
+        assert_eq!(lines[3].as_str(), "TODAY .");
+        model.page = DisplayPage::Diagnostics;
+        let lines = model.lines();
+        assert_eq!(lines[1].as_str(), "SENSOR OK");
+        assert_eq!(lines[2].as_str(), "MAINS  5.0V BAT 87%");
+        assert_eq!(lines[3].as_str(), "UP P1DT2H3M4S");
+    }
+
+    #[test]
+    fn test_lines_alarms_and_missing_values() {
+        let model = DisplayModel {
+            alarms: [false, true, false, true],
+            door_openings: 123_456,
+            ..Default::default()
+        };
+        let lines = model.lines();
+        assert_eq!(lines[0].as_str(), "TVC   --.-C");
+        assert_eq!(lines[2].as_str(), "ALARM H D");
+        assert_eq!(lines[3].as_str(), "DOOR 9999 MEM --D");
+        let model = DisplayModel { excursion_latched: true, ..Default::default() };
+        assert_eq!(model.lines()[2].as_str(), "X OK");
+    }
+
+    #[test]
+    fn test_fahrenheit() {
+        let model = DisplayModel { unit: TemperatureUnit::Fahrenheit, tvc: Some(5.0), tamb: None, ..Default::default() };
+        let lines = model.lines();
+        assert_eq!(lines[0].as_str(), "TVC   41.0F");
+        assert_eq!(lines[1].as_str(), "TAMB  --.-F");
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut filter = DisplayFilter::new(3); // Each reading weighs a half.
+        assert_eq!(filter.update(25.0, 5.0), (25.0, 5.0));
+        assert_eq!(filter.update(25.0, 6.0), (25.0, 5.5));
+        assert_eq!(filter.update(27.0, 5.5), (26.0, 5.5));
+        let mut raw = DisplayFilter::new(1);
+        raw.update(25.0, 5.0);
+        assert_eq!(raw.update(20.0, 7.0), (20.0, 7.0));
+        assert_eq!(DisplayFilter::new(0), DisplayFilter::new(1));
+    }
+}
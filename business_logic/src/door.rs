@@ -0,0 +1,208 @@
+use crate::log::{Log, LogCode};
+use crate::timestamp::Timestamp;
+
+/// Time the door switch input must be stable before a change is accepted.
+pub const DEFAULT_DOOR_DEBOUNCE_MS: u16 = 50;
+/// Longest debounce time that can be configured.
+pub const MAX_DOOR_DEBOUNCE_MS: u16 = 2000;
+/// A door reading open for this long points at a stuck switch or a broken wire.
+pub const DOOR_STUCK_SECONDS: u32 = 12 * 3600;
+/// More door changes than this within `DOOR_CHATTER_WINDOW_SECONDS` point at a loose contact.
+pub const DOOR_CHATTER_CHANGES: u8 = 20;
+pub const DOOR_CHATTER_WINDOW_SECONDS: u32 = 60;
+
+/// Contacts of the door switch, which the closed door presses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SwitchPolarity {
+    NormallyOpen, // Contacts closed while the door is closed.
+    NormallyClosed, // Contacts closed while the door is open.
+}
+
+/// Pull resistor on the door switch input. The switch connects the input to the opposite rail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SwitchPull {
+    Up,
+    Down,
+}
+
+/// Wiring of the door switch, which varies between fridge models.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DoorSwitchConfig {
+    pub polarity: SwitchPolarity,
+    pub pull: SwitchPull,
+    pub debounce_ms: u16,
+}
+
+impl Default for DoorSwitchConfig {
+    fn default() -> Self {
+        Self { polarity: SwitchPolarity::NormallyClosed, pull: SwitchPull::Up, debounce_ms: DEFAULT_DOOR_DEBOUNCE_MS }
+    }
+}
+
+impl DoorSwitchConfig {
+    /// Whether the door is open, given the level of the input.
+    pub fn is_open(&self, level_high: bool) -> bool {
+        let contacts_closed = level_high == (self.pull == SwitchPull::Down);
+        contacts_closed == (self.polarity == SwitchPolarity::NormallyClosed)
+    }
+}
+
+/// What looks wrong with the door switch wiring.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DoorSwitchFault {
+    StuckOpen, // Open for `DOOR_STUCK_SECONDS`.
+    Chatter, // Too many changes in a short time.
+}
+
+/// Raises the door alarm once the door has been open longer than `AlarmProfile::door_seconds`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DoorAlarm {
+    enabled: bool,
+    door_seconds: u32,
+    opened_at: Option<Timestamp>,
+}
+
+impl DoorAlarm {
+    pub fn new(enabled: bool, door_seconds: u32) -> Self {
+        Self { enabled, door_seconds, opened_at: None }
+    }
+
+    /// Carry on a door alarm active since `since` before a reset, as if the door had opened just
+    /// long enough before it, so the alarm goes on while the door stays open.
+    pub fn resumed(self, since: Timestamp) -> Self {
+        Self { opened_at: Some(Timestamp { seconds: since.seconds.saturating_sub(self.door_seconds + 1) }), ..self }
+    }
+
+    pub fn opened(&mut self, at: Timestamp) {
+        self.opened_at = Some(at);
+    }
+
+    pub fn closed(&mut self) {
+        self.opened_at = None;
+    }
+
+    pub fn is_active(&self, now: Timestamp) -> bool {
+        self.enabled && self.opened_at.is_some_and(|opened| now.seconds.saturating_sub(opened.seconds) > self.door_seconds)
+    }
+}
+
+/// Watches the door switch for wiring faults.
+///
+/// A switch stuck in the closed-door position can't be told from a door nobody opens, so only
+/// a door that never closes and a flickering input are caught.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DoorSwitchMonitor {
+    opened_at: Option<Timestamp>,
+    window_start: Option<Timestamp>,
+    changes: u8, // Door changes since `window_start`.
+    fault: Option<DoorSwitchFault>,
+}
+
+impl DoorSwitchMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The switch reported a change. Returns a newly detected fault, after logging `DoorSwitchFault`.
+    pub fn changed(&mut self, now: Timestamp, open: bool, log: &mut impl Log) -> Option<DoorSwitchFault> {
+        self.opened_at = if open { self.opened_at.or(Some(now)) } else { None };
+        if self.fault == Some(DoorSwitchFault::StuckOpen) && !open {
+            self.fault = None;
+        }
+        if self.window_start.is_none_or(|start| now.seconds.saturating_sub(start.seconds) >= DOOR_CHATTER_WINDOW_SECONDS) {
+            (self.window_start, self.changes) = (Some(now), 0);
+        }
+        self.changes = self.changes.saturating_add(1);
+        if self.changes > DOOR_CHATTER_CHANGES && self.fault.is_none() {
+            return self.detected(DoorSwitchFault::Chatter, log);
+        }
+        None
+    }
+
+    /// Check for a door stuck open; call regularly. Returns a newly detected fault, after logging it.
+    pub fn poll(&mut self, now: Timestamp, log: &mut impl Log) -> Option<DoorSwitchFault> {
+        // A chattering input has settled once a whole window passed quietly.
+        let quiet_for = self.window_start.map_or(0, |start| now.seconds.saturating_sub(start.seconds));
+        if self.fault == Some(DoorSwitchFault::Chatter) && quiet_for >= 2 * DOOR_CHATTER_WINDOW_SECONDS {
+            self.fault = None;
+        }
+        let stuck = self.opened_at.is_some_and(|opened| now.seconds.saturating_sub(opened.seconds) >= DOOR_STUCK_SECONDS);
+        if stuck && self.fault.is_none() {
+            return self.detected(DoorSwitchFault::StuckOpen, log);
+        }
+        None
+    }
+
+    pub fn fault(&self) -> Option<DoorSwitchFault> {
+        self.fault
+    }
+
+    fn detected(&mut self, fault: DoorSwitchFault, log: &mut impl Log) -> Option<DoorSwitchFault> {
+        self.fault = Some(fault);
+        log.warn(LogCode::DoorSwitchFault, fault as u32);
+        Some(fault)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::at;
+    use crate::log::{CaptureLog, Level};
+
+    #[test]
+    fn test_door_alarm() {
+        let mut alarm = DoorAlarm::new(true, 300);
+        alarm.opened(at(100));
+        assert!(!alarm.is_active(at(400)));
+        assert!(alarm.is_active(at(401)));
+        alarm.closed();
+        assert!(!alarm.is_active(at(500)));
+        // Restored after a reset, it is still active when the reset took no time at all.
+        assert!(DoorAlarm::new(true, 300).resumed(at(401)).is_active(at(401)));
+        let mut disabled = DoorAlarm::new(false, 300);
+        disabled.opened(at(0));
+        assert!(!disabled.is_active(at(1000)));
+    }
+
+    #[test]
+    fn test_polarity_and_pull() {
+        let config = DoorSwitchConfig::default();
+        assert!(config.is_open(false) && !config.is_open(true));
+        let config = DoorSwitchConfig { polarity: SwitchPolarity::NormallyOpen, ..config };
+        assert!(config.is_open(true) && !config.is_open(false));
+        let config = DoorSwitchConfig { pull: SwitchPull::Down, ..config };
+        assert!(config.is_open(false) && !config.is_open(true));
+    }
+
+    #[test]
+    fn test_stuck_open() {
+        let mut monitor = DoorSwitchMonitor::new();
+        let mut log = CaptureLog::default();
+        assert_eq!(monitor.changed(at(1000), true, &mut log), None);
+        assert_eq!(monitor.poll(at(1000 + DOOR_STUCK_SECONDS - 1), &mut log), None);
+        assert_eq!(monitor.poll(at(1000 + DOOR_STUCK_SECONDS), &mut log), Some(DoorSwitchFault::StuckOpen));
+        assert_eq!(monitor.poll(at(2000 + DOOR_STUCK_SECONDS), &mut log), None); // Reported once.
+        assert_eq!(log.entries, [(Level::Warn, LogCode::DoorSwitchFault, 0)]);
+        monitor.changed(at(3000 + DOOR_STUCK_SECONDS), false, &mut log);
+        assert_eq!(monitor.fault(), None);
+    }
+
+    #[test]
+    fn test_chatter() {
+        let mut monitor = DoorSwitchMonitor::new();
+        let mut log = CaptureLog::default();
+        let faults: Vec<_> = (0..=DOOR_CHATTER_CHANGES)
+            .filter_map(|change| monitor.changed(at(500 + u32::from(change)), change % 2 == 0, &mut log))
+            .collect();
+        assert_eq!(faults, [DoorSwitchFault::Chatter]);
+        assert_eq!(monitor.poll(at(600), &mut log), None);
+        assert_eq!(monitor.fault(), Some(DoorSwitchFault::Chatter));
+        monitor.poll(at(500 + 2 * DOOR_CHATTER_WINDOW_SECONDS), &mut log);
+        assert_eq!(monitor.fault(), None);
+    }
+}
@@ -0,0 +1,138 @@
+use aes::cipher::{BlockEncrypt, InnerIvInit, KeyInit, StreamCipher, StreamCipherCoreWrapper};
+use aes::Aes128;
+
+use crate::aggregator::{AggregationRecord, AGGREGATION_RECORD_LEN};
+use crate::provisioning::DeviceKey;
+use crate::store::{ChainedRecord, CHAINED_RECORD_LEN};
+
+/// Length of an AES block, and of an AES-128 key.
+pub const AES_BLOCK_LEN: usize = 16;
+/// Bytes kept in the clear at the start of a stored record: sequence number, previous hash,
+/// and the record's version and length.
+pub const CLEAR_HEADER_LEN: usize = 4 + 4 + 2;
+/// Length of a record as stored, the same encrypted or not.
+pub const STORED_RECORD_LEN: usize = CHAINED_RECORD_LEN;
+const KEY_LABEL: &[u8; AES_BLOCK_LEN] = b"record payloads\0"; // Derives the record key from the device key.
+
+// AES-128 in CTR mode with the last 32 bits of the counter block counting blocks, big-endian.
+type Aes128Ctr = ctr::CtrCore<Aes128, ctr::flavors::Ctr32BE>;
+
+/// The AES-128 key for stored records, derived from the device key so it is never kept on its own.
+pub fn record_key(device_key: &DeviceKey) -> [u8; AES_BLOCK_LEN] {
+    let (first, second) = device_key.0.split_at(AES_BLOCK_LEN);
+    let mut key = (*KEY_LABEL).into();
+    Aes128::new(first.into()).encrypt_block(&mut key);
+    let mut key: [u8; AES_BLOCK_LEN] = key.into();
+    key.iter_mut().zip(second).for_each(|(byte, second)| *byte ^= second);
+    key
+}
+
+/// Encrypts stored records with AES-CTR, so a logger's flash doesn't give away clinic data to
+/// anyone who reads it out.
+///
+/// The sequence number, previous hash and record version stay in the clear, so a damaged store
+/// can still be recovered and its chain checked for gaps without the key. CTR mode doesn't
+/// detect tampering on its own: the tamper chain and authenticated exports do.
+///
+/// Each record has a counter block of its own, big-endian: the nonce, the record's sequence
+/// number, four zero bytes, and the block number within the record. A keystream used twice
+/// gives away the XOR of two records, so the nonce and sequence number together must never be
+/// repeated under one key. Sequence numbers only grow within a store, compaction included, so
+/// the nonce must change whenever they start again, e.g. by counting the times the store was
+/// formatted.
+pub struct RecordCipher {
+    cipher: Aes128,
+    nonce: u32,
+}
+
+impl RecordCipher {
+    /// Encrypt with `key`, e.g. from `record_key`, and the store's current `nonce`.
+    pub fn new(key: &[u8; AES_BLOCK_LEN], nonce: u32) -> Self {
+        Self { cipher: Aes128::new(key.into()), nonce }
+    }
+
+    /// `chained` as stored, with the record's fields encrypted.
+    pub fn seal(&mut self, chained: &ChainedRecord) -> [u8; STORED_RECORD_LEN] {
+        let mut bytes = chained.to_bytes();
+        self.apply(chained.sequence, &mut bytes[CLEAR_HEADER_LEN..]);
+        bytes
+    }
+
+    /// Decrypt a record written by `seal`. Returns None if the result isn't a record.
+    pub fn open(&mut self, bytes: &[u8]) -> Option<ChainedRecord> {
+        let header = bytes.get(..CLEAR_HEADER_LEN)?;
+        let sequence = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let previous_hash = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let mut record = [0u8; AGGREGATION_RECORD_LEN];
+        let len = usize::from(header[9]).min(AGGREGATION_RECORD_LEN);
+        let payload = bytes.get(CLEAR_HEADER_LEN..8 + len)?;
+        record[..2].copy_from_slice(&header[8..]);
+        record[2..len].copy_from_slice(payload);
+        self.apply(sequence, &mut record[2..len]);
+        Some(ChainedRecord { sequence, previous_hash, record: AggregationRecord::from_bytes(&record[..len])? })
+    }
+
+    // XOR `payload` with the keystream for record `sequence`.
+    fn apply(&mut self, sequence: u32, payload: &mut [u8]) {
+        let core = Aes128Ctr::inner_iv_init(self.cipher.clone(), &counter_block(self.nonce, sequence).into());
+        StreamCipherCoreWrapper::from_core(core).apply_keystream(payload);
+    }
+}
+
+// The first counter block of record `sequence`.
+fn counter_block(nonce: u32, sequence: u32) -> [u8; AES_BLOCK_LEN] {
+    let mut block = [0u8; AES_BLOCK_LEN];
+    block[0..4].copy_from_slice(&nonce.to_be_bytes());
+    block[4..8].copy_from_slice(&sequence.to_be_bytes());
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::RecordChain;
+    use crate::timestamp::Timestamp;
+
+    #[test]
+    fn test_counter_blocks() {
+        // Each block of a record is XORed with AES of its own counter block, as documented.
+        let key = [5; 16];
+        let mut payload = [0u8; 40];
+        RecordCipher::new(&key, 0x0102_0304).apply(7, &mut payload);
+        let aes = Aes128::new(&key.into());
+        for (index, chunk) in payload.chunks(AES_BLOCK_LEN).enumerate() {
+            let mut block = counter_block(0x0102_0304, 7);
+            block[12..].copy_from_slice(&(index as u32).to_be_bytes());
+            let mut keystream = block.into();
+            aes.encrypt_block(&mut keystream);
+            assert_eq!(chunk, &keystream[..chunk.len()]);
+        }
+        assert_eq!(counter_block(0x0102_0304, 7)[..8], [1, 2, 3, 4, 0, 0, 0, 7]);
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let key = record_key(&DeviceKey([7; 32]));
+        assert_ne!(key, record_key(&DeviceKey([8; 32])));
+        let mut chain = RecordChain::new();
+        chain.link(AggregationRecord::new(Timestamp { seconds: 0 }));
+        let mut record = AggregationRecord::new(Timestamp { seconds: 900 });
+        (record.tvc_seconds, record.door_openings) = (900, 3);
+        let chained = chain.link(record);
+        let mut cipher = RecordCipher::new(&key, 1);
+        let stored = cipher.seal(&chained);
+        let mut clear = [0u8; STORED_RECORD_LEN];
+        clear[8..].copy_from_slice(&record.to_bytes());
+        // The header is readable without the key, the record isn't.
+        assert_eq!(stored[..4], 1u32.to_le_bytes());
+        assert_eq!(stored[8..10], clear[8..10]);
+        assert_ne!(stored[10..], clear[10..]);
+        assert_eq!(cipher.open(&stored), Some(chained));
+        // A different nonce, sequence number or key gives a different keystream.
+        assert_ne!(RecordCipher::new(&key, 2).seal(&chained)[10..], stored[10..]);
+        let next = chain.link(record);
+        assert_ne!(cipher.seal(&next)[10..], stored[10..]);
+        assert_ne!(RecordCipher::new(&[0; 16], 1).open(&stored), Some(chained));
+        assert_eq!(cipher.open(&stored[..20]), None);
+    }
+}
@@ -0,0 +1,162 @@
+/// Maximum number of error codes packed into one record.
+pub const PACKED_ERRORS_MAX: usize = 4;
+/// Length of the persisted error statistics in bytes.
+pub const ERROR_STATS_LEN: usize = ErrorCode::COUNT * 4;
+
+/// Faults reported by the firmware modules. The discriminant is the code stored in records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ErrorCode {
+    SensorFail = 1,
+    FlashFail = 2,
+    ClockAnomaly = 3,
+    QueueOverflow = 4,
+    DoorSwitchFault = 5, // Stuck or chattering door switch.
+    StateCorrupted = 6, // The record in progress failed its RAM integrity check.
+    Simulated = 7, // The record holds events injected in test mode.
+}
+
+impl ErrorCode {
+    pub const COUNT: usize = 7;
+    pub const ALL: [ErrorCode; ErrorCode::COUNT] = [
+        ErrorCode::SensorFail,
+        ErrorCode::FlashFail,
+        ErrorCode::ClockAnomaly,
+        ErrorCode::QueueOverflow,
+        ErrorCode::DoorSwitchFault,
+        ErrorCode::StateCorrupted,
+        ErrorCode::Simulated,
+    ];
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        ErrorCode::ALL.into_iter().find(|error| *error as u8 == code)
+    }
+
+    fn index(self) -> usize {
+        self as usize - 1
+    }
+}
+
+/// Up to `PACKED_ERRORS_MAX` distinct error codes packed one per byte, first reported in the low byte.
+/// A zero byte is an empty slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PackedErrors(u32);
+
+impl PackedErrors {
+    pub fn from_u32(packed: u32) -> Self {
+        Self(packed)
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+
+    /// Add a code unless it is already present. Returns false if there was no free slot.
+    pub fn push(&mut self, code: ErrorCode) -> bool {
+        if self.contains(code) {
+            return true;
+        }
+        match (0..PACKED_ERRORS_MAX).find(|slot| (self.0 >> (slot * 8)) & 0xFF == 0) {
+            Some(slot) => {
+                self.0 |= u32::from(code as u8) << (slot * 8);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, code: ErrorCode) -> bool {
+        self.iter().any(|packed| packed == code)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Codes in the order they were reported. Unknown codes are skipped.
+    pub fn iter(&self) -> impl Iterator<Item = ErrorCode> + '_ {
+        self.0.to_le_bytes().into_iter().filter_map(ErrorCode::from_code)
+    }
+}
+
+/// Collects error reports for the record in progress and counts them over the logger's lifetime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ErrorLog {
+    pending: PackedErrors,
+    counts: [u32; ErrorCode::COUNT],
+}
+
+impl ErrorLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report a fault. Every report is counted; the record keeps the first few distinct codes.
+    pub fn report(&mut self, code: ErrorCode) {
+        self.counts[code.index()] = self.counts[code.index()].saturating_add(1);
+        self.pending.push(code);
+    }
+
+    /// The codes to store in the record being finalized, starting afresh for the next one.
+    pub fn take_packed(&mut self) -> PackedErrors {
+        core::mem::take(&mut self.pending)
+    }
+
+    /// Total reports of `code`.
+    pub fn count(&self, code: ErrorCode) -> u32 {
+        self.counts[code.index()]
+    }
+
+    /// Serialize the counts that must survive a reset.
+    pub fn to_bytes(&self) -> [u8; ERROR_STATS_LEN] {
+        let mut bytes = [0u8; ERROR_STATS_LEN];
+        for (chunk, count) in bytes.chunks_exact_mut(4).zip(self.counts) {
+            chunk.copy_from_slice(&count.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Restore counts saved by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8; ERROR_STATS_LEN]) -> Self {
+        let mut log = Self::new();
+        for (count, chunk) in log.counts.iter_mut().zip(bytes.chunks_exact(4)) {
+            *count = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        log
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packing() {
+        let mut packed = PackedErrors::default();
+        assert!(packed.is_empty());
+        assert!(packed.push(ErrorCode::FlashFail));
+        assert!(packed.push(ErrorCode::SensorFail));
+        assert!(packed.push(ErrorCode::FlashFail)); // Duplicates take no slot.
+        assert_eq!(packed.as_u32(), 0x0000_0102);
+        assert!(packed.push(ErrorCode::ClockAnomaly));
+        assert!(packed.push(ErrorCode::QueueOverflow));
+        let codes: Vec<ErrorCode> = PackedErrors::from_u32(packed.as_u32()).iter().collect();
+        assert_eq!(codes, [ErrorCode::FlashFail, ErrorCode::SensorFail, ErrorCode::ClockAnomaly, ErrorCode::QueueOverflow]);
+    }
+
+    #[test]
+    fn test_error_log() {
+        let mut log = ErrorLog::new();
+        log.report(ErrorCode::SensorFail);
+        log.report(ErrorCode::SensorFail);
+        log.report(ErrorCode::QueueOverflow);
+        assert_eq!(log.take_packed().as_u32(), 0x0000_0401);
+        assert!(log.take_packed().is_empty());
+        assert_eq!(log.count(ErrorCode::SensorFail), 2);
+        let restored = ErrorLog::from_bytes(&log.to_bytes());
+        assert_eq!(restored.count(ErrorCode::QueueOverflow), 1);
+        assert_eq!(restored.count(ErrorCode::FlashFail), 0);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::alarm::AlarmKind;
+use crate::timestamp::Timestamp;
+
+/// An alarm of `kind` that has been active for `after_seconds` escalates one level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EscalationRule {
+    pub kind: AlarmKind,
+    pub after_seconds: u32,
+}
+
+const fn rule(kind: AlarmKind, after_seconds: u32) -> EscalationRule {
+    EscalationRule { kind, after_seconds }
+}
+
+/// Default rules. Freezing destroys vaccine fastest, so it escalates first.
+pub static ESCALATION_RULES: [EscalationRule; 5] = [
+    rule(AlarmKind::Freeze, 30 * 60),
+    rule(AlarmKind::HighTemp, 2 * 3600),
+    rule(AlarmKind::HighTemp, 8 * 3600), // Second level: the vaccine is close to its heat budget.
+    rule(AlarmKind::Power, 12 * 3600),
+    rule(AlarmKind::Door, 15 * 60),
+];
+
+/// Tracks how long each alarm has been active and how far it has escalated under a rule table.
+///
+/// An acknowledgement doesn't reset escalation; only the alarm clearing does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Escalation {
+    rules: &'static [EscalationRule],
+    active_since: [Option<Timestamp>; AlarmKind::ALL.len()], // Indexed by `AlarmKind`.
+}
+
+impl Default for Escalation {
+    fn default() -> Self {
+        Self::new(&ESCALATION_RULES)
+    }
+}
+
+impl Escalation {
+    pub fn new(rules: &'static [EscalationRule]) -> Self {
+        Self { rules, active_since: [None; AlarmKind::ALL.len()] }
+    }
+
+    /// Set whether an alarm of the given kind is currently active.
+    pub fn set_active(&mut self, kind: AlarmKind, active: bool, now: Timestamp) {
+        let since = &mut self.active_since[kind as usize];
+        match (active, *since) {
+            (true, None) => *since = Some(now),
+            (false, _) => *since = None,
+            (true, Some(_)) => {}
+        }
+    }
+
+    /// When each alarm became active, indexed by `AlarmKind`, e.g. to save across a reset.
+    pub fn active_since(&self) -> [Option<Timestamp>; AlarmKind::ALL.len()] {
+        self.active_since
+    }
+
+    /// Carry on from start times saved with `active_since`.
+    pub fn resume(&mut self, active_since: [Option<Timestamp>; AlarmKind::ALL.len()]) {
+        self.active_since = active_since;
+    }
+
+    /// Number of rules for `kind` whose delay has passed; 0 if not escalated.
+    pub fn level(&self, kind: AlarmKind, now: Timestamp) -> u8 {
+        let Some(since) = self.active_since[kind as usize] else {
+            return 0;
+        };
+        let active_seconds = now.seconds.saturating_sub(since.seconds);
+        self.rules.iter().filter(|rule| rule.kind == kind && active_seconds >= rule.after_seconds).count() as u8
+    }
+
+    pub fn is_escalated(&self, kind: AlarmKind, now: Timestamp) -> bool {
+        self.level(kind, now) > 0
+    }
+
+    /// Bitmap of escalated alarms, bit `AlarmKind as u8`, for status payloads.
+    pub fn escalated_mask(&self, now: Timestamp) -> u8 {
+        AlarmKind::ALL
+            .iter()
+            .filter(|&&kind| self.is_escalated(kind, now))
+            .fold(0, |mask, &kind| mask | 1 << kind as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::at;
+
+    #[test]
+    fn test_levels_over_time() {
+        let mut escalation = Escalation::default();
+        escalation.set_active(AlarmKind::HighTemp, true, at(1000));
+        // Staying active doesn't restart the clock.
+        escalation.set_active(AlarmKind::HighTemp, true, at(5000));
+        assert_eq!(escalation.level(AlarmKind::HighTemp, at(1000 + 2 * 3600 - 1)), 0);
+        assert_eq!(escalation.level(AlarmKind::HighTemp, at(1000 + 2 * 3600)), 1);
+        assert_eq!(escalation.level(AlarmKind::HighTemp, at(1000 + 8 * 3600)), 2);
+        assert_eq!(escalation.escalated_mask(at(1000 + 8 * 3600)), 1 << AlarmKind::HighTemp as u8);
+        escalation.set_active(AlarmKind::HighTemp, false, at(40_000));
+        assert_eq!(escalation.level(AlarmKind::HighTemp, at(40_000)), 0);
+    }
+
+    #[test]
+    fn test_custom_rules() {
+        static RULES: [EscalationRule; 1] = [rule(AlarmKind::Door, 60)];
+        let mut escalation = Escalation::new(&RULES);
+        escalation.set_active(AlarmKind::Door, true, at(0));
+        escalation.set_active(AlarmKind::Freeze, true, at(0));
+        assert!(escalation.is_escalated(AlarmKind::Door, at(60)));
+        assert!(!escalation.is_escalated(AlarmKind::Freeze, at(86_400))); // No rule for freeze.
+    }
+}
@@ -0,0 +1,171 @@
+use arrayvec::ArrayVec;
+
+use crate::log::{Level, Log, LogCode};
+use crate::logger::LoggerEvent;
+
+/// Slots at the end of every `EventQueue` that only events which mustn't be lost may use.
+pub const RESERVED_SLOTS: usize = 2;
+
+/// What may give way for an event when its queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Overflow {
+    Keep, // Never lost, e.g. door, power and alarm events. Takes a reserved slot, or the place of a lesser event.
+    Coalesce(u8), // Replaces the queued event with the same key, e.g. an older temperature reading.
+    Drop, // Dropped, e.g. a button press the user can repeat.
+}
+
+/// Events that say how they are queued.
+pub trait Queued {
+    fn overflow(&self) -> Overflow;
+}
+
+impl Queued for LoggerEvent {
+    fn overflow(&self) -> Overflow {
+        match self {
+            LoggerEvent::Sample(_) => Overflow::Coalesce(0),
+            LoggerEvent::Tick(_) => Overflow::Coalesce(1),
+            _ => Overflow::Keep,
+        }
+    }
+}
+
+/// A first-in first-out queue whose senders never wait: when it is full, the new event's
+/// `Overflow` decides what gives way, so a slow consumer can't hold up the tasks that feed it.
+///
+/// Events stay in arrival order, which the logger needs to accept them. Coalesced events are
+/// only counted; events lost are counted and logged with `LogCode::QueueOverflow`.
+#[derive(Debug, Clone)]
+pub struct EventQueue<T, const N: usize> {
+    events: ArrayVec<T, N>,
+    overflows: u32, // Events lost, including any that should have been kept.
+    coalesced: u32, // Events replaced by a newer one of the same kind.
+}
+
+impl<T, const N: usize> Default for EventQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> EventQueue<T, N> {
+    pub const fn new() -> Self {
+        Self { events: ArrayVec::new_const(), overflows: 0, coalesced: 0 }
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        (!self.events.is_empty()).then(|| self.events.remove(0))
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Events lost so far.
+    pub fn overflows(&self) -> u32 {
+        self.overflows
+    }
+
+    /// Events replaced by a newer one of the same kind so far.
+    pub fn coalesced(&self) -> u32 {
+        self.coalesced
+    }
+}
+
+impl<T: Queued, const N: usize> EventQueue<T, N> {
+    /// Queue `event`, making room by its `Overflow` if the queue is full. Returns false if the
+    /// event itself was dropped.
+    pub fn push(&mut self, event: T, log: &mut impl Log) -> bool {
+        let overflow = event.overflow();
+        let limit = if overflow == Overflow::Keep { N } else { N - RESERVED_SLOTS };
+        if self.events.len() < limit {
+            self.events.push(event);
+            return true;
+        }
+        let victim = match overflow {
+            Overflow::Keep => self.events.iter().position(|queued| queued.overflow() != Overflow::Keep),
+            Overflow::Coalesce(_) => self.events.iter().rposition(|queued| queued.overflow() == overflow),
+            Overflow::Drop => None,
+        };
+        let Some(victim) = victim else {
+            // Losing an event that should have been kept is an error; the rest is expected under load.
+            self.overflows = self.overflows.saturating_add(1);
+            let level = if overflow == Overflow::Keep { Level::Error } else { Level::Warn };
+            log.log(level, LogCode::QueueOverflow, self.overflows);
+            return false;
+        };
+        if overflow == Overflow::Keep {
+            self.overflows = self.overflows.saturating_add(1);
+            log.warn(LogCode::QueueOverflow, self.overflows);
+        } else {
+            self.coalesced = self.coalesced.saturating_add(1);
+        }
+        self.events.remove(victim);
+        self.events.push(event);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::CaptureLog;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Event {
+        Door(u32),
+        Reading(u32),
+        Press(u32),
+    }
+
+    impl Queued for Event {
+        fn overflow(&self) -> Overflow {
+            match self {
+                Event::Door(_) => Overflow::Keep,
+                Event::Reading(_) => Overflow::Coalesce(0),
+                Event::Press(_) => Overflow::Drop,
+            }
+        }
+    }
+
+    fn drain(queue: &mut EventQueue<Event, 5>) -> Vec<Event> {
+        core::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    #[test]
+    fn test_overflow_policy() {
+        let mut queue: EventQueue<Event, 5> = EventQueue::new();
+        let mut log = CaptureLog::default();
+        for event in [Event::Reading(1), Event::Press(2), Event::Reading(3)] {
+            assert!(queue.push(event, &mut log));
+        }
+        // Full but for the reserved slots: a press is dropped, a reading replaces the last one.
+        assert!(!queue.push(Event::Press(4), &mut log));
+        assert!(queue.push(Event::Reading(5), &mut log));
+        assert_eq!(queue.len(), 3);
+        // Door events take the reserved slots, then the places of lesser events.
+        for door in 6..9 {
+            assert!(queue.push(Event::Door(door), &mut log));
+        }
+        assert_eq!(drain(&mut queue), [Event::Press(2), Event::Reading(5), Event::Door(6), Event::Door(7), Event::Door(8)]);
+        assert_eq!((queue.overflows(), queue.coalesced()), (2, 1));
+        assert_eq!(log.entries, [(Level::Warn, LogCode::QueueOverflow, 1), (Level::Warn, LogCode::QueueOverflow, 2)]);
+    }
+
+    #[test]
+    fn test_kept_events_lost_only_when_all_are_kept() {
+        let mut queue: EventQueue<Event, 5> = EventQueue::new();
+        let mut log = CaptureLog::default();
+        for door in 0..5 {
+            assert!(queue.push(Event::Door(door), &mut log));
+        }
+        assert!(!queue.push(Event::Door(5), &mut log));
+        assert!(!queue.push(Event::Reading(6), &mut log)); // Nothing of its kind to replace.
+        assert_eq!(log.entries, [(Level::Error, LogCode::QueueOverflow, 1), (Level::Warn, LogCode::QueueOverflow, 2)]);
+        assert_eq!(drain(&mut queue), (0..5).map(Event::Door).collect::<Vec<_>>());
+    }
+}
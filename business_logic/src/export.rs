@@ -0,0 +1,413 @@
+use arrayvec::{ArrayString, ArrayVec};
+use core::fmt::Write;
+use core::future::Future;
+
+use crate::aggregator::AggregationRecord;
+use crate::gaps::find_gaps;
+use crate::notes::NoteLog;
+use crate::report::Report;
+use crate::store::RecordStore;
+use crate::timestamp::Timestamp;
+use crate::xmodem::ByteSource;
+
+/// Most bytes in one chunk of an export.
+pub const EXPORT_CHUNK_LEN: usize = 256;
+const LINE_LEN: usize = 192; // Longest line of the report CSV.
+/// Most context `ExportFilter::AlarmOnly` adds either side of an alarm, in seconds.
+pub const MAX_ALARM_CONTEXT_SECONDS: u32 = 24 * 3600;
+
+/// A piece of an export, small enough for any output's buffer.
+pub type ExportChunk = ArrayVec<u8, EXPORT_CHUNK_LEN>;
+
+/// Where exports are written: USB, the UART, an SD card or NFC.
+pub trait ExportSink {
+    type Error;
+
+    /// Write one chunk, waiting until the output has taken it.
+    fn write_chunk(&mut self, chunk: &[u8]) -> impl Future<Output = Result<(), Self::Error>>;
+}
+
+/// Write every chunk of an export to `sink`. Returns the number of bytes written.
+pub async fn write_export<K: ExportSink>(chunks: impl Iterator<Item = ExportChunk>, sink: &mut K) -> Result<u32, K::Error> {
+    let mut written = 0u32;
+    for chunk in chunks {
+        sink.write_chunk(&chunk).await?;
+        written = written.saturating_add(chunk.len() as u32);
+    }
+    Ok(written)
+}
+
+/// Which records in the period an export includes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ExportFilter {
+    #[default]
+    All,
+    /// Records with an alarm (`AggregationRecord::has_alarm`), and those starting up to
+    /// `before_seconds` before or `after_seconds` after one, for auditors who only care about
+    /// excursions. Each is capped at `MAX_ALARM_CONTEXT_SECONDS`.
+    AlarmOnly { before_seconds: u32, after_seconds: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Summary,
+    Columns,
+    Record(usize), // Index in the store of the next record to look at.
+    Notes(usize), // Note lines written.
+    Gaps(usize), // Gap lines written.
+    Done,
+}
+
+/// The report over `start..end` as CSV, generated a chunk at a time.
+///
+/// Only the current line is held in RAM: the totals are worked out by reading the store through
+/// once, and the records are read again one at a time as the chunks are pulled. The store must
+/// not change until the export is finished.
+///
+/// The summary always covers the whole period, whatever the filter leaves out of the records.
+pub struct ReportChunks<'a, S> {
+    store: &'a S,
+    report: Report,
+    filter: ExportFilter,
+    gap_period_seconds: Option<u32>, // Record period, to list the gaps with.
+    notes: Option<&'a NoteLog>,
+    section: Section,
+    line: ArrayString<LINE_LEN>,
+    line_pos: usize, // Bytes of `line` already in a chunk.
+}
+
+impl<'a, S: RecordStore> ReportChunks<'a, S> {
+    pub fn new(store: &'a S, start: Timestamp, end: Timestamp) -> Self {
+        Self::with_report(store, Report::generate(store.iter(), start, end))
+    }
+
+    /// Export `report`, e.g. one the caller completed with the indicator and lifetime counters.
+    /// The records are those in the report's period.
+    pub fn with_report(store: &'a S, report: Report) -> Self {
+        Self { store, report, filter: ExportFilter::All, gap_period_seconds: None, notes: None, section: Section::Summary, line: ArrayString::new(), line_pos: 0 }
+    }
+
+    /// Only export the records `filter` selects.
+    pub fn filtered(self, filter: ExportFilter) -> Self {
+        Self { filter, ..self }
+    }
+
+    /// End the export with the gaps in the records of the period, including any at its start and
+    /// end, one `gap,start,end,seconds,cause` line each, so its completeness can be audited. Gaps
+    /// are judged against `record_period_seconds`.
+    pub fn with_gaps(self, record_period_seconds: u32) -> Self {
+        Self { gap_period_seconds: Some(record_period_seconds), ..self }
+    }
+
+    /// Follow the records with the operator notes made in the period, one `note,time,"text"`
+    /// line each.
+    pub fn with_notes(self, notes: &'a NoteLog) -> Self {
+        Self { notes: Some(notes), ..self }
+    }
+
+    // The record at `index` in the store, if it is in the report's period.
+    fn in_period(&self, index: usize) -> Option<AggregationRecord> {
+        self.store.get(index).filter(|record| (self.report.start.seconds..self.report.end.seconds).contains(&record.start.seconds))
+    }
+
+    // Whether the record at `index` in the store passes the filter.
+    fn selected(&self, index: usize) -> bool {
+        match self.filter {
+            ExportFilter::All => true,
+            ExportFilter::AlarmOnly { before_seconds, after_seconds } => {
+                let Some(start) = self.store.get(index).map(|record| record.start.seconds) else {
+                    return false;
+                };
+                // An alarm starting up to `before_seconds` later, or `after_seconds` earlier. The
+                // store is in time order, so the search stops at the first record outside that.
+                let (before, after) = (before_seconds.min(MAX_ALARM_CONTEXT_SECONDS), after_seconds.min(MAX_ALARM_CONTEXT_SECONDS));
+                let window = start.saturating_sub(after)..=start.saturating_add(before);
+                let near = |index: &usize| self.store.get(*index).is_some_and(|record| window.contains(&record.start.seconds));
+                let alarm = |index: usize| self.store.get(index).is_some_and(|record| record.has_alarm());
+                (index..self.store.len()).take_while(near).any(alarm) || (0..index).rev().take_while(near).any(alarm)
+            }
+        }
+    }
+
+    // Put the next line in `line`. Returns false at the end of the export.
+    fn next_line(&mut self) -> bool {
+        self.line.clear();
+        self.line_pos = 0;
+        let report = &self.report;
+        // Empty if the anchor isn't known.
+        let anchor = |anchor: Option<u32>| -> ArrayString<10> {
+            let mut text = ArrayString::new();
+            if let Some(anchor) = anchor {
+                let _ = write!(text, "{}", anchor);
+            }
+            text
+        };
+        let celsius = |value: Option<f32>| -> ArrayString<16> {
+            let mut text = ArrayString::new();
+            if let Some(value) = value {
+                let _ = write!(text, "{:.2}", value);
+            }
+            text
+        };
+        let _ = match self.section {
+            Section::Summary => {
+                self.section = Section::Columns;
+                writeln!(
+                    self.line,
+                    "report,{},{},{},{},{},{},{},{},{},{},{},{:08X},{}",
+                    report.start.seconds,
+                    report.end.seconds,
+                    report.records,
+                    report.covered_seconds,
+                    celsius(report.tvc_average),
+                    celsius(report.tvc_min),
+                    celsius(report.tvc_max),
+                    report.high_alarm_seconds,
+                    report.low_alarm_seconds,
+                    report.door_openings,
+                    report.power_off_seconds,
+                    report.logger_errors.as_u32(),
+                    anchor(report.epoch_anchor),
+                )
+            }
+            Section::Columns => {
+                self.section = Section::Record(0);
+                writeln!(self.line, "start,tvc_seconds,tvc_avg,tvc_min,tvc_max,high_alarm_seconds,low_alarm_seconds,door_openings,power_off_seconds")
+            }
+            Section::Record(index) => {
+                let found = (index..self.store.len()).find_map(|index| self.in_period(index).filter(|_| self.selected(index)).map(|record| (index, record)));
+                let Some((index, record)) = found else {
+                    self.section = Section::Notes(0);
+                    return self.next_line();
+                };
+                self.section = Section::Record(index + 1);
+                let has_tvc = record.tvc_seconds > 0;
+                writeln!(
+                    self.line,
+                    "{},{},{},{},{},{},{},{},{}",
+                    record.start.seconds,
+                    record.tvc_seconds,
+                    celsius(record.tvc_average()),
+                    celsius(has_tvc.then_some(record.tvc_min)),
+                    celsius(has_tvc.then_some(record.tvc_max)),
+                    record.high_alarm_seconds,
+                    record.low_alarm_seconds,
+                    record.door_openings,
+                    record.power_off_seconds,
+                )
+            }
+            Section::Notes(written) => {
+                let Some(note) = self.notes.and_then(|notes| notes.between(report.start, report.end).nth(written)) else {
+                    self.section = if self.gap_period_seconds.is_some() { Section::Gaps(0) } else { Section::Done };
+                    return self.next_line();
+                };
+                self.section = Section::Notes(written + 1);
+                writeln!(self.line, "note,{},\"{}\"", note.timestamp.seconds, note.text)
+            }
+            Section::Gaps(written) => {
+                let Some(period_seconds) = self.gap_period_seconds else {
+                    return false;
+                };
+                // Found again from the start for each line, rather than holding the search's state.
+                let records = (0..self.store.len()).filter_map(|index| self.in_period(index));
+                let Some(gap) = find_gaps(records, period_seconds, report.start, report.end).nth(written) else {
+                    self.section = Section::Done;
+                    return false;
+                };
+                self.section = Section::Gaps(written + 1);
+                writeln!(self.line, "gap,{},{},{},{}", gap.start.seconds, gap.end.seconds, gap.seconds(), gap.cause.name())
+            }
+            Section::Done => return false,
+        };
+        true
+    }
+}
+
+impl<S: RecordStore> Iterator for ReportChunks<'_, S> {
+    type Item = ExportChunk;
+
+    fn next(&mut self) -> Option<ExportChunk> {
+        let mut chunk = ExportChunk::new();
+        while !chunk.is_full() {
+            if self.line_pos == self.line.len() && !self.next_line() {
+                break;
+            }
+            let rest = &self.line.as_bytes()[self.line_pos..];
+            let len = rest.len().min(chunk.remaining_capacity());
+            let _ = chunk.try_extend_from_slice(&rest[..len]);
+            self.line_pos += len;
+        }
+        (!chunk.is_empty()).then_some(chunk)
+    }
+}
+
+/// Reads an export as a byte stream, e.g. for `XmodemSender`, which wants its own block size.
+pub struct ChunkReader<I> {
+    chunks: I,
+    chunk: ExportChunk,
+    pos: usize, // Bytes of `chunk` already read.
+}
+
+impl<I: Iterator<Item = ExportChunk>> ChunkReader<I> {
+    pub fn new(chunks: I) -> Self {
+        Self { chunks, chunk: ExportChunk::new(), pos: 0 }
+    }
+}
+
+impl<I: Iterator<Item = ExportChunk>> ByteSource for ChunkReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        if self.pos == self.chunk.len() {
+            let Some(chunk) = self.chunks.next() else {
+                return 0;
+            };
+            (self.chunk, self.pos) = (chunk, 0);
+        }
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregator::AggregationRecord;
+    use crate::log::NullLog;
+    use crate::store::RamStore;
+    use crate::wallclock::EPOCH_UNIX_SECONDS;
+    use crate::xmodem::XmodemSender;
+    use embassy_futures::block_on;
+
+    fn store() -> RamStore<128> {
+        let mut store = RamStore::new();
+        for index in 0..100 {
+            let mut record = AggregationRecord::new(Timestamp { seconds: index * 900 });
+            record.tvc_seconds = 900;
+            record.tvc_integral = 900.0 * 5.0;
+            (record.tvc_min, record.tvc_max) = (4.5, 5.5);
+            record.door_openings = index % 3;
+            store.append(record);
+        }
+        store
+    }
+
+    struct Collect(Vec<u8>);
+
+    impl ExportSink for Collect {
+        type Error = ();
+
+        async fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), ()> {
+            self.0.extend_from_slice(chunk);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_report_in_chunks() {
+        let store = store();
+        let chunks: Vec<ExportChunk> = ReportChunks::new(&store, Timestamp { seconds: 9000 }, Timestamp { seconds: 45_000 }).collect();
+        assert!(chunks.iter().all(|chunk| !chunk.is_empty() && chunk.len() <= EXPORT_CHUNK_LEN));
+        assert!(chunks[..chunks.len() - 1].iter().all(|chunk| chunk.is_full()));
+        let text = String::from_utf8(chunks.concat()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2 + 40);
+        assert_eq!(lines[0], "report,9000,45000,40,36000,5.00,4.50,5.50,0,0,40,0,00000000,");
+        let anchored = Report { epoch_anchor: Some(EPOCH_UNIX_SECONDS), ..Report::generate(store.iter(), Timestamp { seconds: 0 }, Timestamp { seconds: 900 }) };
+        let text = String::from_utf8(ReportChunks::with_report(&store, anchored).flatten().collect()).unwrap();
+        assert!(text.starts_with("report,0,900,1,900,5.00,4.50,5.50,0,0,0,0,00000000,951868800\n"));
+        assert_eq!(lines[2], "9000,900,5.00,4.50,5.50,0,0,1,0");
+        assert_eq!(lines[41], "44100,900,5.00,4.50,5.50,0,0,1,0");
+    }
+
+    #[test]
+    fn test_alarm_only() {
+        let records = store();
+        let mut store = RamStore::<128>::new();
+        for (index, mut record) in records.iter().enumerate() {
+            record.high_alarm_seconds = if index == 20 { 300 } else { 0 };
+            record.power_off_seconds = if index == 60 { 60 } else { 0 };
+            record.door_alarm_seconds = if index == 80 { 120 } else { 0 };
+            store.append(record);
+        }
+        let period = (Timestamp { seconds: 0 }, Timestamp { seconds: 90_000 });
+        let starts = |filter| -> Vec<String> {
+            let text = String::from_utf8(ReportChunks::new(&store, period.0, period.1).filtered(filter).flatten().collect()).unwrap();
+            assert!(text.starts_with("report,0,90000,100,"));
+            text.lines().skip(2).map(|line| line.split(',').next().unwrap().to_string()).collect()
+        };
+        assert_eq!(starts(ExportFilter::AlarmOnly { before_seconds: 0, after_seconds: 0 }), ["18000", "54000", "72000"]);
+        assert_eq!(
+            starts(ExportFilter::AlarmOnly { before_seconds: 1800, after_seconds: 900 }),
+            ["16200", "17100", "18000", "18900", "52200", "53100", "54000", "54900", "70200", "71100", "72000", "72900"]
+        );
+        assert_eq!(starts(ExportFilter::All).len(), 100);
+        // A day after, however much more is asked for.
+        let mut store = RamStore::<128>::new();
+        for (index, mut record) in records.iter().enumerate() {
+            record.high_alarm_seconds = if index == 0 { 300 } else { 0 };
+            store.append(record);
+        }
+        let text = String::from_utf8(ReportChunks::new(&store, period.0, period.1).filtered(ExportFilter::AlarmOnly { before_seconds: 0, after_seconds: u32::MAX }).flatten().collect()).unwrap();
+        assert_eq!(text.lines().skip(2).last().and_then(|line| line.split(',').next()), Some("86400"));
+    }
+
+    #[test]
+    fn test_gap_lines() {
+        let records = store();
+        let mut store = RamStore::<128>::new();
+        for (index, mut record) in records.iter().enumerate().filter(|(index, _)| !(10..12).contains(index)) {
+            if index == 30 {
+                record.tvc_seconds = 400; // Reset partway through.
+            }
+            if index != 31 {
+                store.append(record);
+            }
+        }
+        let period = (Timestamp { seconds: 0 }, Timestamp { seconds: 90_000 });
+        let text = String::from_utf8(ReportChunks::new(&store, period.0, period.1).with_gaps(900).flatten().collect()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2 + 97 + 2);
+        assert_eq!(lines[99..], ["gap,9000,10800,1800,unknown", "gap,27900,28800,900,restart"]);
+        let alarm_only = ReportChunks::new(&store, period.0, period.1).filtered(ExportFilter::AlarmOnly { before_seconds: 0, after_seconds: 0 }).with_gaps(900);
+        let text = String::from_utf8(alarm_only.flatten().collect()).unwrap();
+        assert_eq!(text.lines().filter(|line| line.starts_with("gap,")).count(), 2);
+        // Missing records at either end of the period are gaps too.
+        let text = String::from_utf8(ReportChunks::new(&store, Timestamp { seconds: 450 }, Timestamp { seconds: 91_800 }).with_gaps(900).flatten().collect()).unwrap();
+        let gaps: Vec<&str> = text.lines().filter(|line| line.starts_with("gap,")).collect();
+        assert_eq!(gaps, ["gap,450,900,450,unknown", "gap,9000,10800,1800,unknown", "gap,27900,28800,900,restart", "gap,90000,91800,1800,unknown"]);
+    }
+
+    #[test]
+    fn test_note_lines() {
+        let store = store();
+        let mut notes = NoteLog::new();
+        for (seconds, text) in [(100, "door checked"), (1000, "defrost, then restocked"), (5000, "moved")] {
+            notes.add(Timestamp { seconds }, text, &mut NullLog).unwrap();
+        }
+        let text = String::from_utf8(ReportChunks::new(&store, Timestamp { seconds: 0 }, Timestamp { seconds: 1800 }).with_notes(&notes).with_gaps(900).flatten().collect()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[2..], ["0,900,5.00,4.50,5.50,0,0,0,0", "900,900,5.00,4.50,5.50,0,0,1,0", "note,100,\"door checked\"", "note,1000,\"defrost, then restocked\""]);
+    }
+
+    #[test]
+    fn test_outputs() {
+        let store = store();
+        let period = (Timestamp { seconds: 0 }, Timestamp { seconds: 90_000 });
+        let expected: Vec<u8> = ReportChunks::new(&store, period.0, period.1).flatten().collect();
+        let mut sink = Collect(Vec::new());
+        assert_eq!(block_on(write_export(ReportChunks::new(&store, period.0, period.1), &mut sink)), Ok(expected.len() as u32));
+        assert_eq!(sink.0, expected);
+        // Through XMODEM, in blocks of another size.
+        let mut sender = XmodemSender::new(ChunkReader::new(ReportChunks::new(&store, period.0, period.1)));
+        let mut received = Vec::new();
+        let mut packet = sender.receive(b'C').unwrap().to_vec();
+        while packet.len() > 1 {
+            received.extend_from_slice(&packet[3..packet.len() - 2]);
+            packet = sender.receive(0x06).unwrap().to_vec();
+        }
+        assert_eq!(received[..expected.len()], expected);
+        assert!(received[expected.len()..].iter().all(|&byte| byte == 0x1A));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::crc::crc32;
+use crate::log::{Log, LogCode};
+
+/// Each of the STM32L476JE's two flash banks. A bank holds one firmware image.
+pub const BANK_SIZE_BYTES: u32 = 256 * 1024;
+/// Flash erase page.
+pub const FLASH_PAGE_BYTES: u32 = 2048;
+/// Pages at the end of each bank kept out of the image, for data and the self-test's scratch
+/// page. `memory.x` links the image into the rest of the bank.
+pub const RESERVED_PAGES: u32 = 32;
+/// Largest image, and so the most that can be staged: a bank, less its reserved pages.
+pub const IMAGE_CAPACITY_BYTES: u32 = BANK_SIZE_BYTES - RESERVED_PAGES * FLASH_PAGE_BYTES;
+/// Flash is programmed in double words, so every chunk but the last must be a multiple of this.
+pub const PROGRAM_UNIT_BYTES: u32 = 8;
+/// A new image that hasn't marked itself healthy after this many boots is rolled back.
+pub const MAX_TRIAL_BOOTS: u8 = 3;
+const BOOT_STATE_MAGIC: u32 = 0xB0_07;
+
+/// Why an uploaded image was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UpdateError {
+    TooLarge, // The announced image is empty or doesn't fit the staging area.
+    Misaligned, // A chunk followed one that wasn't a whole number of program units.
+    Overrun, // More data arrived than was announced.
+    Length, // Less data arrived than was announced.
+    Crc, // The data doesn't match the announced CRC.
+    Flash, // Writing the staging area failed.
+    NotStaging, // Data or a check without an upload under way.
+}
+
+/// Checks an uploaded image as it is staged into spare flash, chunk by chunk.
+///
+/// The uploader announces the length and CRC-32 of the image first, so a truncated
+/// or corrupted upload is caught before anything hands over to the bootloader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageStager {
+    length: u32,
+    crc: u32,
+    received: u32,
+    running_crc: u32, // CRC-32 of the bytes received so far.
+}
+
+impl ImageStager {
+    /// Start staging an image of `length` bytes whose CRC-32 is `crc`.
+    pub fn new(length: u32, crc: u32) -> Result<Self, UpdateError> {
+        if length == 0 || length > IMAGE_CAPACITY_BYTES {
+            return Err(UpdateError::TooLarge);
+        }
+        Ok(Self { length, crc, received: 0, running_crc: 0 })
+    }
+
+    /// Account for the next chunk and return the offset into the staging area to write it at.
+    pub fn accept(&mut self, chunk: &[u8]) -> Result<u32, UpdateError> {
+        if !self.received.is_multiple_of(PROGRAM_UNIT_BYTES) {
+            return Err(UpdateError::Misaligned);
+        }
+        let offset = self.received;
+        let end = u32::try_from(chunk.len()).ok().and_then(|len| offset.checked_add(len));
+        match end {
+            Some(end) if end <= self.length => self.received = end,
+            _ => return Err(UpdateError::Overrun),
+        }
+        self.running_crc = crc32(self.running_crc, chunk);
+        Ok(offset)
+    }
+
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+
+    /// Check that the whole image arrived intact, and log the outcome.
+    pub fn finish(&self, log: &mut impl Log) -> Result<(), UpdateError> {
+        let result = if self.received != self.length {
+            Err(UpdateError::Length)
+        } else if self.running_crc != self.crc {
+            Err(UpdateError::Crc)
+        } else {
+            Ok(())
+        };
+        match result {
+            Ok(()) => log.info(LogCode::FirmwareStaged, self.length),
+            Err(error) => log.warn(LogCode::FirmwareRejected, error as u32),
+        }
+        result
+    }
+
+    /// Check the image as it ended up in flash, read back through `read(offset, buffer)`, against
+    /// the announced CRC. Call this before handing over to the staged image, as a program that
+    /// silently failed would otherwise leave a bank that doesn't boot.
+    pub fn verify(&self, mut read: impl FnMut(u32, &mut [u8]) -> bool) -> Result<(), UpdateError> {
+        if self.received != self.length {
+            return Err(UpdateError::Length);
+        }
+        let mut buffer = [0u8; 64];
+        let mut crc = 0;
+        for offset in (0..self.length).step_by(buffer.len()) {
+            let chunk = &mut buffer[..(self.length - offset).min(64) as usize];
+            if !read(offset, chunk) {
+                return Err(UpdateError::Flash);
+            }
+            crc = crc32(crc, chunk);
+        }
+        if crc == self.crc { Ok(()) } else { Err(UpdateError::Crc) }
+    }
+}
+
+/// One of the two flash banks, each holding a complete firmware image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Bank {
+    A,
+    B,
+}
+
+impl Bank {
+    pub fn other(self) -> Self {
+        match self {
+            Bank::A => Bank::B,
+            Bank::B => Bank::A,
+        }
+    }
+}
+
+/// What to do with the image that just booted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BootAction {
+    Run,
+    RollBack(Bank), // Boot the given bank instead, as the image on trial never became healthy.
+}
+
+/// Which bank boots and whether its image has proven itself, kept in a backup register.
+///
+/// A freshly installed image runs on trial: each boot counts against `MAX_TRIAL_BOOTS`
+/// until the image marks itself healthy. Running out of boots, e.g. through repeated
+/// watchdog resets, switches back to the previous image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BootState {
+    pub active: Bank,
+    pub trial_boots: u8, // Boots of the active image so far, while on trial.
+    pub confirmed: bool, // The active image has marked itself healthy.
+}
+
+impl BootState {
+    /// A confirmed image in `active`, e.g. when the backup register holds nothing valid.
+    pub fn new(active: Bank) -> Self {
+        Self { active, trial_boots: 0, confirmed: true }
+    }
+
+    /// Put the image just staged in the other bank on trial. Boot it next.
+    pub fn start_trial(&mut self) {
+        *self = Self { active: self.active.other(), trial_boots: 0, confirmed: false };
+    }
+
+    /// Count a boot of the active image and decide whether it may run.
+    pub fn on_boot(&mut self, log: &mut impl Log) -> BootAction {
+        if self.confirmed {
+            return BootAction::Run;
+        }
+        self.trial_boots = self.trial_boots.saturating_add(1);
+        if self.trial_boots <= MAX_TRIAL_BOOTS {
+            return BootAction::Run;
+        }
+        *self = Self::new(self.active.other());
+        log.error(LogCode::FirmwareRolledBack, self.active as u32);
+        BootAction::RollBack(self.active)
+    }
+
+    /// Called by the running image once it has shown it works.
+    /// Returns true if this ended a trial, so the caller can save the state.
+    pub fn mark_healthy(&mut self, log: &mut impl Log) -> bool {
+        if self.confirmed {
+            return false;
+        }
+        self.confirmed = true;
+        log.info(LogCode::FirmwareConfirmed, self.trial_boots as u32);
+        true
+    }
+
+    pub fn to_word(&self) -> u32 {
+        BOOT_STATE_MAGIC << 16 | (self.confirmed as u32) << 9 | (self.active as u32) << 8 | self.trial_boots as u32
+    }
+
+    /// Decode a saved state. Returns None if the register holds no valid state.
+    pub fn from_word(word: u32) -> Option<Self> {
+        if word >> 16 != BOOT_STATE_MAGIC || word & 0xFC00 != 0 {
+            return None;
+        }
+        let active = if word & 1 << 8 != 0 { Bank::B } else { Bank::A };
+        Some(Self { active, trial_boots: word as u8, confirmed: word & 1 << 9 != 0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{CaptureLog, Level, NullLog};
+
+    #[test]
+    fn test_staging() {
+        let image: Vec<u8> = (0..20u8).collect();
+        let mut log = CaptureLog::default();
+        let mut stager = ImageStager::new(20, crc32(0, &image)).unwrap();
+        assert_eq!(stager.accept(&image[..16]), Ok(0));
+        assert_eq!(stager.finish(&mut log), Err(UpdateError::Length));
+        assert_eq!(stager.accept(&image[16..]), Ok(16));
+        assert_eq!(stager.finish(&mut log), Ok(()));
+        assert_eq!(
+            log.entries,
+            [(Level::Warn, LogCode::FirmwareRejected, UpdateError::Length as u32), (Level::Info, LogCode::FirmwareStaged, 20)]
+        );
+    }
+
+    #[test]
+    fn test_trial_and_rollback() {
+        let mut log = CaptureLog::default();
+        let mut state = BootState::new(Bank::A);
+        assert_eq!(state.on_boot(&mut log), BootAction::Run);
+        state.start_trial();
+        for _ in 0..MAX_TRIAL_BOOTS {
+            assert_eq!(state.on_boot(&mut log), BootAction::Run);
+            assert_eq!(BootState::from_word(state.to_word()), Some(state));
+        }
+        assert_eq!(state.on_boot(&mut log), BootAction::RollBack(Bank::A));
+        assert_eq!(state, BootState::new(Bank::A));
+        assert_eq!(log.entries, [(Level::Error, LogCode::FirmwareRolledBack, Bank::A as u32)]);
+    }
+
+    #[test]
+    fn test_mark_healthy() {
+        let mut log = CaptureLog::default();
+        let mut state = BootState::new(Bank::A);
+        state.start_trial();
+        assert_eq!(state.on_boot(&mut log), BootAction::Run);
+        assert!(state.mark_healthy(&mut log));
+        assert!(!state.mark_healthy(&mut log));
+        for _ in 0..=MAX_TRIAL_BOOTS {
+            assert_eq!(state.on_boot(&mut log), BootAction::Run);
+        }
+        assert_eq!(BootState::from_word(state.to_word()), Some(BootState { active: Bank::B, trial_boots: 1, confirmed: true }));
+        assert_eq!(BootState::from_word(0), None);
+    }
+
+    #[test]
+    fn test_rejections() {
+        assert_eq!(ImageStager::new(0, 0), Err(UpdateError::TooLarge));
+        assert_eq!(ImageStager::new(IMAGE_CAPACITY_BYTES + 1, 0), Err(UpdateError::TooLarge));
+        let mut stager = ImageStager::new(16, 0).unwrap();
+        assert_eq!(stager.accept(&[0; 17]), Err(UpdateError::Overrun));
+        assert_eq!(stager.accept(&[0; 4]), Ok(0));
+        assert_eq!(stager.accept(&[0; 4]), Err(UpdateError::Misaligned));
+        let mut stager = ImageStager::new(4, 0).unwrap();
+        stager.accept(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(stager.finish(&mut NullLog), Err(UpdateError::Crc));
+    }
+
+    #[test]
+    fn test_verify_reads_back() {
+        let image: Vec<u8> = (0..100u8).collect();
+        let mut stager = ImageStager::new(100, crc32(0, &image)).unwrap();
+        assert_eq!(stager.verify(|_, _| true), Err(UpdateError::Length));
+        stager.accept(&image).unwrap();
+        let mut flash = image.clone();
+        fn read(flash: &[u8], offset: u32, buffer: &mut [u8]) -> bool {
+            buffer.copy_from_slice(&flash[offset as usize..offset as usize + buffer.len()]);
+            true
+        }
+        assert_eq!(stager.verify(|offset, buffer| read(&flash, offset, buffer)), Ok(()));
+        flash[70] = 0xFF; // A program that didn't take.
+        assert_eq!(stager.verify(|offset, buffer| read(&flash, offset, buffer)), Err(UpdateError::Crc));
+        assert_eq!(stager.verify(|_, _| false), Err(UpdateError::Flash));
+    }
+}
@@ -0,0 +1,145 @@
+use arrayvec::ArrayVec;
+
+/// Longest a flash page erase stalls the CPU, ms. Typically 22 ms on the STM32L4.
+pub const PAGE_ERASE_MS: u64 = 25;
+/// Margin kept between an erase and a sample on either side, for the sensor rail and conversion.
+pub const SAMPLE_GUARD_MS: u64 = 50;
+/// Time after a door or button event during which its handling may still be running.
+pub const EVENT_QUIET_MS: u64 = 200;
+/// Longest an erase waits for an idle window, so the store never runs out of erased pages.
+pub const MAX_ERASE_DEFER_MS: u64 = 60_000;
+/// Erases that can wait at once.
+pub const MAX_PENDING_ERASES: usize = 8;
+
+/// Defers flash page erases, which stall the CPU, to windows away from sensor samples and events,
+/// and keeps the worst stall they caused for the device health.
+#[derive(Debug, Clone, Default)]
+pub struct FlashScheduler {
+    pending: ArrayVec<(u32, u64), MAX_PENDING_ERASES>, // Page and when it was requested, ms, oldest first.
+    next_sample_ms: Option<u64>,
+    quiet_until_ms: u64,
+    worst_stall_us: u32,
+    forced: u32, // Erases done outside an idle window because they waited too long.
+}
+
+impl FlashScheduler {
+    pub const fn new() -> Self {
+        Self { pending: ArrayVec::new_const(), next_sample_ms: None, quiet_until_ms: 0, worst_stall_us: 0, forced: 0 }
+    }
+
+    /// Queue an erase of `page`. Returns false if too many are waiting, so the caller must erase it
+    /// now or retry later.
+    pub fn request_erase(&mut self, page: u32, now_ms: u64) -> bool {
+        if self.pending.iter().any(|&(pending, _)| pending == page) {
+            return true;
+        }
+        self.pending.try_push((page, now_ms)).is_ok()
+    }
+
+    /// The sampling task will take its next sample at `at_ms`.
+    pub fn next_sample(&mut self, at_ms: u64) {
+        self.next_sample_ms = Some(at_ms);
+    }
+
+    /// A door, button or other event is being handled at `now_ms`.
+    pub fn event(&mut self, now_ms: u64) {
+        self.quiet_until_ms = self.quiet_until_ms.max(now_ms + EVENT_QUIET_MS);
+    }
+
+    /// Whether an erase starting at `now_ms` would stay clear of samples and events.
+    pub fn is_idle(&self, now_ms: u64) -> bool {
+        now_ms >= self.quiet_until_ms
+            && self.next_sample_ms.is_none_or(|sample_ms| {
+                // Ends before the next sample, or the sample it is due after is over.
+                now_ms + PAGE_ERASE_MS + SAMPLE_GUARD_MS <= sample_ms || now_ms >= sample_ms + SAMPLE_GUARD_MS
+            })
+    }
+
+    /// The page to erase now, if any: the oldest request, once there is an idle window or it has
+    /// waited `MAX_ERASE_DEFER_MS`.
+    pub fn poll(&mut self, now_ms: u64) -> Option<u32> {
+        let &(page, requested_ms) = self.pending.first()?;
+        let overdue = now_ms >= requested_ms + MAX_ERASE_DEFER_MS;
+        if !overdue && !self.is_idle(now_ms) {
+            return None;
+        }
+        if !self.is_idle(now_ms) {
+            self.forced += 1;
+        }
+        self.pending.remove(0);
+        Some(page)
+    }
+
+    /// Time from `now_ms` at which `poll` should be called next, or None with nothing to erase.
+    pub fn next_poll_ms(&self, now_ms: u64) -> Option<u64> {
+        let &(_, requested_ms) = self.pending.first()?;
+        let mut at = self.quiet_until_ms.max(now_ms);
+        if let Some(sample_ms) = self.next_sample_ms
+            && !self.is_idle(at)
+        {
+            at = at.max(sample_ms + SAMPLE_GUARD_MS);
+        }
+        Some(at.min(requested_ms + MAX_ERASE_DEFER_MS).max(now_ms))
+    }
+
+    /// An erase just finished, having stalled the CPU for `stall_us`.
+    pub fn erased(&mut self, stall_us: u32) {
+        self.worst_stall_us = self.worst_stall_us.max(stall_us);
+    }
+
+    /// Longest any erase stalled the CPU, µs.
+    pub fn worst_stall_us(&self) -> u32 {
+        self.worst_stall_us
+    }
+
+    /// Erases that couldn't wait for an idle window.
+    pub fn forced_erases(&self) -> u32 {
+        self.forced
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_erase_waits_for_idle_window() {
+        let mut scheduler = FlashScheduler::new();
+        scheduler.next_sample(10_000);
+        assert!(scheduler.request_erase(7, 0));
+        assert!(scheduler.request_erase(7, 5));
+        scheduler.event(1_000);
+        assert_eq!(scheduler.poll(1_100), None); // Still handling the event.
+        assert_eq!(scheduler.next_poll_ms(1_100), Some(1_200));
+        assert_eq!(scheduler.poll(1_200), Some(7));
+        assert_eq!(scheduler.poll(1_200), None); // Requested twice, erased once.
+        // Too close to the next sample: wait until it is over.
+        scheduler.request_erase(8, 9_950);
+        assert_eq!(scheduler.poll(9_950), None);
+        assert_eq!(scheduler.next_poll_ms(9_950), Some(10_050));
+        assert_eq!(scheduler.poll(10_050), Some(8));
+        assert_eq!(scheduler.next_poll_ms(10_050), None);
+        scheduler.erased(22_000);
+        scheduler.erased(18_000);
+        assert_eq!(scheduler.worst_stall_us(), 22_000);
+        assert_eq!(scheduler.forced_erases(), 0);
+    }
+
+    #[test]
+    fn test_overdue_erase_is_forced() {
+        let mut scheduler = FlashScheduler::new();
+        for page in 0..MAX_PENDING_ERASES as u32 {
+            assert!(scheduler.request_erase(page, 0));
+        }
+        assert!(!scheduler.request_erase(99, 0));
+        // Events every 100 ms never leave a window.
+        for now_ms in (0..MAX_ERASE_DEFER_MS).step_by(100) {
+            scheduler.event(now_ms);
+            assert_eq!(scheduler.poll(now_ms), None);
+        }
+        scheduler.event(MAX_ERASE_DEFER_MS);
+        assert_eq!(scheduler.next_poll_ms(MAX_ERASE_DEFER_MS - 100), Some(MAX_ERASE_DEFER_MS));
+        assert_eq!(scheduler.poll(MAX_ERASE_DEFER_MS), Some(0));
+        assert_eq!(scheduler.forced_erases(), 1);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::aggregator::{AggregationRecord, RecordKind};
+use crate::errors::ErrorCode;
+use crate::timestamp::Timestamp;
+
+const SECONDS_PER_DAY: u32 = 86400;
+
+/// The likely reason the logger has no data for a while, judged from the records either side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum GapCause {
+    ClockJump, // The clock was set or went wrong, see `ErrorCode::ClockAnomaly`.
+    Storage, // Records couldn't be written, see `ErrorCode::FlashFail`.
+    Restart, // The record before stopped short, as when the logger reset or lost power.
+    Unknown,
+}
+
+impl GapCause {
+    pub fn name(self) -> &'static str {
+        match self {
+            GapCause::ClockJump => "clock_jump",
+            GapCause::Storage => "storage",
+            GapCause::Restart => "restart",
+            GapCause::Unknown => "unknown",
+        }
+    }
+}
+
+/// A period without records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Gap {
+    pub start: Timestamp, // End of the record before, or the start of the period.
+    pub end: Timestamp, // Start of the record after, or the end of the period.
+    pub cause: GapCause,
+}
+
+impl Gap {
+    /// The gap between consecutive records `before` and `after`, if there is one.
+    ///
+    /// A record covers at least `record_period_seconds`, or its readings and pauses if they are
+    /// longer. A day summary from compaction covers its whole day, however much is missing in it.
+    pub fn between(before: &AggregationRecord, after: &AggregationRecord, record_period_seconds: u32) -> Option<Self> {
+        Self::new(Some(before), Some(after), covered_until(before, record_period_seconds), after.start.seconds, record_period_seconds)
+    }
+
+    pub fn seconds(&self) -> u32 {
+        self.end.seconds - self.start.seconds
+    }
+
+    // The gap from `start` to `end` after `before` and before `after`, the records either side if there are any.
+    fn new(before: Option<&AggregationRecord>, after: Option<&AggregationRecord>, start: u32, end: u32, record_period_seconds: u32) -> Option<Self> {
+        if end <= start {
+            return None;
+        }
+        let reported = |code| before.is_some_and(|before| before.logger_errors.contains(code)) || after.is_some_and(|after| after.logger_errors.contains(code));
+        let cause = if after.is_some_and(|after| after.logger_errors.contains(ErrorCode::ClockAnomaly)) {
+            GapCause::ClockJump
+        } else if reported(ErrorCode::FlashFail) {
+            GapCause::Storage
+        } else if before.is_some_and(|before| before.kind == RecordKind::Period && logged_seconds(before) < record_period_seconds) {
+            GapCause::Restart
+        } else {
+            GapCause::Unknown
+        };
+        Some(Self { start: Timestamp { seconds: start }, end: Timestamp { seconds: end }, cause })
+    }
+}
+
+// Time covered by the record's readings and pauses.
+fn logged_seconds(record: &AggregationRecord) -> u32 {
+    record.tvc_seconds.saturating_add(record.paused_seconds)
+}
+
+// When the time `record` covers ends, see `Gap::between`.
+fn covered_until(record: &AggregationRecord, record_period_seconds: u32) -> u32 {
+    let covered = match record.kind {
+        RecordKind::Period => record_period_seconds.max(logged_seconds(record)),
+        RecordKind::DaySummary => SECONDS_PER_DAY,
+    };
+    record.start.seconds.saturating_add(covered)
+}
+
+/// The gaps in the period `start..end`, oldest first, given the records in it: before the first
+/// record, between records, and after the last. `end` should be no later than now, or the time
+/// still to come counts as missing.
+pub fn find_gaps(
+    records: impl IntoIterator<Item = AggregationRecord>,
+    record_period_seconds: u32,
+    start: Timestamp,
+    end: Timestamp,
+) -> impl Iterator<Item = Gap> {
+    let mut records = records.into_iter();
+    let mut before: Option<AggregationRecord> = None;
+    let mut finished = false;
+    core::iter::from_fn(move || {
+        while !finished {
+            let Some(after) = records.next() else {
+                finished = true;
+                let covered = before.map_or(start.seconds, |before| covered_until(&before, record_period_seconds));
+                return Gap::new(before.as_ref(), None, covered, end.seconds, record_period_seconds);
+            };
+            let gap = match &before {
+                Some(before) => Gap::between(before, &after, record_period_seconds),
+                None => Gap::new(None, Some(&after), start.seconds, after.start.seconds, record_period_seconds),
+            };
+            before = Some(after);
+            if gap.is_some() {
+                return gap;
+            }
+        }
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::at;
+
+    fn record(seconds: u32, tvc_seconds: u32) -> AggregationRecord {
+        AggregationRecord { tvc_seconds, ..AggregationRecord::new(Timestamp { seconds }) }
+    }
+
+    #[test]
+    fn test_gaps_and_causes() {
+        let mut stored = record(3600, 900);
+        stored.logger_errors.push(ErrorCode::FlashFail);
+        let mut clock_set = record(20_000, 900);
+        clock_set.logger_errors.push(ErrorCode::ClockAnomaly);
+        let records = [
+            record(0, 900),
+            record(900, 300), // Reset partway through.
+            record(2700, 900),
+            stored,
+            record(5400, 900),
+            clock_set,
+            record(20_900, 900),
+            record(21_800, 900),
+            record(29_700, 900),
+            // A compacted day covers the day, even with readings missing.
+            AggregationRecord { kind: RecordKind::DaySummary, ..record(SECONDS_PER_DAY, 40_000) },
+            record(2 * SECONDS_PER_DAY, 900),
+        ];
+        let gaps: Vec<Gap> = find_gaps(records, 900, at(0), at(2 * SECONDS_PER_DAY + 900)).collect();
+        let found: Vec<(u32, u32, GapCause)> = gaps.iter().map(|gap| (gap.start.seconds, gap.seconds(), gap.cause)).collect();
+        assert_eq!(
+            found,
+            [(1800, 900, GapCause::Restart), (4500, 900, GapCause::Storage), (6300, 13_700, GapCause::ClockJump), (22_700, 7000, GapCause::Unknown), (30_600, 55_800, GapCause::Unknown)]
+        );
+        assert_eq!(find_gaps([record(900, 900), record(0, 900)], 900, at(900), at(900)).count(), 0); // Clock went back.
+    }
+
+    #[test]
+    fn test_gaps_at_the_ends() {
+        let gaps = |records: &[AggregationRecord]| -> Vec<(u32, u32, GapCause)> {
+            find_gaps(records.iter().copied(), 900, at(1800), at(9000)).map(|gap| (gap.start.seconds, gap.end.seconds, gap.cause)).collect()
+        };
+        assert_eq!(gaps(&[]), [(1800, 9000, GapCause::Unknown)]);
+        assert_eq!(gaps(&[record(3600, 900), record(4500, 900)]), [(1800, 3600, GapCause::Unknown), (5400, 9000, GapCause::Unknown)]);
+        // The logger reset partway through the last record, and never came back.
+        assert_eq!(gaps(&[record(1800, 900), record(7200, 300)]), [(2700, 7200, GapCause::Unknown), (8100, 9000, GapCause::Restart)]);
+        assert_eq!(gaps(&[record(1800, 900), record(8100, 900)]), [(2700, 8100, GapCause::Unknown)]);
+    }
+}
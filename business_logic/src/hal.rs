@@ -0,0 +1,45 @@
+use crate::timestamp::Timestamp;
+
+// Pins, EXTI inputs and I2C buses use the embedded-hal traits, which embassy-stm32 implements
+// and embedded-hal-mock mocks for host tests.
+pub use embedded_hal::digital::{InputPin, OutputPin};
+pub use embedded_hal_async::delay::DelayNs;
+pub use embedded_hal_async::digital::Wait;
+pub use embedded_hal_async::i2c::I2c;
+
+/// Real-time clock keeping wall-clock time across resets.
+pub trait Rtc {
+    fn now(&self) -> Timestamp;
+}
+
+/// Time since boot that never goes back, for timing debounces and gestures.
+pub trait Monotonic {
+    fn now_ms(&self) -> u64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use embedded_hal_mock::eh1::digital::{Edge, Mock, State, Transaction};
+
+    // A door switch read through the traits, as a driver would.
+    async fn wait_for_close(input: &mut (impl InputPin + Wait)) -> bool {
+        if input.is_high().unwrap() {
+            input.wait_for_falling_edge().await.unwrap();
+        }
+        input.is_low().unwrap()
+    }
+
+    #[test]
+    fn test_mock_pins() {
+        let expectations = [Transaction::get(State::High), Transaction::wait_for_edge(Edge::Falling), Transaction::get(State::Low)];
+        let mut input = Mock::new(&expectations);
+        assert!(block_on(wait_for_close(&mut input)));
+        input.done();
+        let mut output = Mock::new(&[Transaction::set(State::Low), Transaction::set(State::High)]);
+        output.set_low().unwrap();
+        output.set_high().unwrap();
+        output.done();
+    }
+}
@@ -0,0 +1,106 @@
+use arrayvec::ArrayString;
+use core::fmt::Write;
+
+/// Length of the health footer line.
+pub const HEALTH_FOOTER_LEN: usize = 192;
+
+/// Device health metrics for diagnostics and the daily report footer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DeviceHealth {
+    pub uptime_seconds: u32,
+    pub restart_count: u32,
+    pub queue_high_water: usize, // Most events ever waiting in the main event queue.
+    pub queue_overflows: u32, // Events lost from the event queues because they were full.
+    pub queue_coalesced: u32, // Events replaced by a newer one of the same kind in a full event queue.
+    pub flash_erases: u32,
+    pub i2c_errors: u32,
+    pub worst_loop_latency_us: u32, // Longest time taken to handle one event.
+    pub worst_acquisition_us: u32, // Longest time the sensor rail was up for one sample.
+    pub worst_erase_stall_us: u32, // Longest a flash page erase stalled the CPU.
+}
+
+impl DeviceHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note the number of events waiting in the queue.
+    pub fn queue_depth(&mut self, depth: usize) {
+        self.queue_high_water = self.queue_high_water.max(depth);
+    }
+
+    /// Note how long handling one event took.
+    pub fn loop_latency(&mut self, latency_us: u32) {
+        self.worst_loop_latency_us = self.worst_loop_latency_us.max(latency_us);
+    }
+
+    /// One-line summary for the report footer.
+    pub fn footer(&self) -> ArrayString<HEALTH_FOOTER_LEN> {
+        let mut footer = ArrayString::new();
+        let _ = write!(
+            footer,
+            "UP {}s RST {} QHW {} QOVF {} QCOAL {} ERASE {} I2CERR {} LOOP {}us ACQ {}us STALL {}us",
+            self.uptime_seconds,
+            self.restart_count,
+            self.queue_high_water,
+            self.queue_overflows,
+            self.queue_coalesced,
+            self.flash_erases,
+            self.i2c_errors,
+            self.worst_loop_latency_us,
+            self.worst_acquisition_us,
+            self.worst_erase_stall_us,
+        );
+        footer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_high_water_marks() {
+        let mut health = DeviceHealth::new();
+        health.queue_depth(3);
+        health.queue_depth(1);
+        health.loop_latency(250);
+        health.loop_latency(120);
+        health.uptime_seconds = 86400;
+        health.restart_count = 2;
+        health.i2c_errors = 5;
+        health.worst_acquisition_us = 11_500;
+        health.worst_erase_stall_us = 22_100;
+        assert_eq!(health.queue_high_water, 3);
+        assert_eq!(health.worst_loop_latency_us, 250);
+        assert_eq!(health.footer().as_str(), "UP 86400s RST 2 QHW 3 QOVF 0 QCOAL 0 ERASE 0 I2CERR 5 LOOP 250us ACQ 11500us STALL 22100us");
+    }
+
+    #[test]
+    fn test_marks_only_rise() {
+        let mut health = DeviceHealth::new();
+        assert_eq!((health.queue_high_water, health.worst_loop_latency_us), (0, 0));
+        for (depth, latency) in [(2, 90), (5, 40), (0, 0), (5, 300), (4, 299)] {
+            health.queue_depth(depth);
+            health.loop_latency(latency);
+        }
+        assert_eq!((health.queue_high_water, health.worst_loop_latency_us), (5, 300));
+    }
+
+    #[test]
+    fn test_widest_footer_fits() {
+        let health = DeviceHealth {
+            uptime_seconds: u32::MAX,
+            restart_count: u32::MAX,
+            queue_high_water: usize::MAX,
+            queue_overflows: u32::MAX,
+            queue_coalesced: u32::MAX,
+            flash_erases: u32::MAX,
+            i2c_errors: u32::MAX,
+            worst_loop_latency_us: u32::MAX,
+            worst_acquisition_us: u32::MAX,
+            worst_erase_stall_us: u32::MAX,
+        };
+        assert!(health.footer().ends_with(" STALL 4294967295us"));
+    }
+}
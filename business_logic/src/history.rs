@@ -0,0 +1,207 @@
+use arrayvec::ArrayString;
+
+use crate::timestamp::Timestamp;
+
+/// Number of completed days kept in the history.
+pub const HISTORY_DAYS: usize = 30;
+const SECONDS_PER_DAY: u32 = 86400;
+const SECONDS_PER_HOUR: u32 = 3600;
+
+/// Summary of one day, Fridge-tag style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DayStatus {
+    pub has_data: bool,
+    pub high_alarm: bool,
+    pub freeze_alarm: bool,
+    pub moved: bool, // Shock or tilt detected, for installation and transport audits. Not part of the symbol.
+    pub door_openings_by_hour: [u8; 24], // Door openings in each local hour of the day, saturating.
+}
+
+impl DayStatus {
+    pub fn is_ok(&self) -> bool {
+        self.has_data && !self.high_alarm && !self.freeze_alarm
+    }
+
+    /// Door openings over the whole day.
+    pub fn door_openings(&self) -> u32 {
+        self.door_openings_by_hour.iter().map(|&count| u32::from(count)).sum()
+    }
+
+    /// Door openings outside the local hours `open_hour` to `close_hour`, e.g. a clinic's opening
+    /// hours. The hours may wrap past midnight.
+    pub fn door_openings_outside(&self, open_hour: u8, close_hour: u8) -> u32 {
+        let inside = |hour: u8| {
+            if open_hour <= close_hour {
+                (open_hour..close_hour).contains(&hour)
+            } else {
+                hour >= open_hour || hour < close_hour
+            }
+        };
+        (0..24u8).filter(|&hour| !inside(hour)).map(|hour| u32::from(self.door_openings_by_hour[usize::from(hour)])).sum()
+    }
+
+    /// One-character summary: '.' OK, 'H' high alarm, 'F' freeze alarm, 'X' both, ' ' no data.
+    pub fn symbol(&self) -> char {
+        match (self.has_data, self.high_alarm, self.freeze_alarm) {
+            (_, true, true) => 'X',
+            (_, true, false) => 'H',
+            (_, false, true) => 'F',
+            (true, false, false) => '.',
+            (false, false, false) => ' ',
+        }
+    }
+}
+
+/// Per-day OK/alarm status for the last `HISTORY_DAYS` completed days,
+/// maintained incrementally as samples arrive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DailyHistory {
+    days: [DayStatus; HISTORY_DAYS], // Ring buffer of completed days.
+    next: usize, // Index in `days` of the oldest day, which is overwritten next.
+    today: Option<u32>, // Day number of the day in progress.
+    current: DayStatus,
+}
+
+impl DailyHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the alarm state at `timestamp`.
+    /// Returns the status of the previous day if this sample completed it.
+    pub fn record(&mut self, timestamp: Timestamp, high_alarm: bool, freeze_alarm: bool) -> Option<DayStatus> {
+        let completed = self.advance(timestamp);
+        self.current.has_data = true;
+        self.current.high_alarm |= high_alarm;
+        self.current.freeze_alarm |= freeze_alarm;
+        completed
+    }
+
+    /// Flag the day of `timestamp` as one in which the device was moved or knocked.
+    /// Returns the status of the previous day if this completed it.
+    pub fn note_motion(&mut self, timestamp: Timestamp) -> Option<DayStatus> {
+        let completed = self.advance(timestamp);
+        self.current.moved = true;
+        completed
+    }
+
+    /// Count a door opening at `timestamp`, in local time, in the hour it happened.
+    /// Returns the status of the previous day if this completed it.
+    pub fn note_door_opening(&mut self, timestamp: Timestamp) -> Option<DayStatus> {
+        let completed = self.advance(timestamp);
+        let hour = (timestamp.seconds % SECONDS_PER_DAY / SECONDS_PER_HOUR) as usize;
+        self.current.door_openings_by_hour[hour] = self.current.door_openings_by_hour[hour].saturating_add(1);
+        completed
+    }
+
+    // Move on to the day of `timestamp`, returning the day this completed.
+    fn advance(&mut self, timestamp: Timestamp) -> Option<DayStatus> {
+        let day = timestamp.seconds / SECONDS_PER_DAY;
+        let mut completed = None;
+        match self.today {
+            Some(today) if day > today => {
+                completed = Some(self.current);
+                self.push(self.current);
+                // Days without any samples are kept as "no data".
+                for _ in 1..(day - today).min(HISTORY_DAYS as u32 + 1) {
+                    self.push(DayStatus::default());
+                }
+                self.current = DayStatus::default();
+                self.today = Some(day);
+            }
+            Some(_) => {} // Same day, or the clock went backwards: keep accumulating.
+            None => self.today = Some(day),
+        }
+        completed
+    }
+
+    fn push(&mut self, status: DayStatus) {
+        self.days[self.next] = status;
+        self.next = (self.next + 1) % HISTORY_DAYS;
+    }
+
+    /// Completed days, oldest first.
+    pub fn completed(&self) -> impl Iterator<Item = DayStatus> + '_ {
+        self.days[self.next..].iter().chain(self.days[..self.next].iter()).copied()
+    }
+
+    /// Status of the day in progress so far.
+    pub fn today(&self) -> DayStatus {
+        self.current
+    }
+
+    /// One character per completed day, oldest first.
+    pub fn ticker(&self) -> ArrayString<HISTORY_DAYS> {
+        let mut ticker = ArrayString::new();
+        for day in self.completed() {
+            ticker.push(day.symbol());
+        }
+        ticker
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at_day(day: u32) -> Timestamp {
+        Timestamp { seconds: day * SECONDS_PER_DAY + 3600 }
+    }
+
+    #[test]
+    fn test_days_complete_incrementally() {
+        let mut history = DailyHistory::new();
+        assert_eq!(history.record(at_day(0), false, false), None);
+        assert_eq!(history.record(at_day(0), true, false), None);
+        let completed = history.record(at_day(1), false, false).unwrap();
+        assert!(completed.high_alarm);
+        assert!(history.today().is_ok());
+        history.record(at_day(2), false, true);
+        let ticker = history.ticker();
+        assert_eq!(ticker.len(), HISTORY_DAYS);
+        assert!(ticker.ends_with("H."));
+        assert_eq!(history.today().symbol(), 'F');
+        assert!(!history.today().moved);
+        let completed = history.note_motion(at_day(3)).unwrap();
+        assert!(!completed.moved && history.today().moved);
+        assert_eq!(history.today().symbol(), ' '); // Moved, but no readings yet.
+    }
+
+    #[test]
+    fn test_gap_days_and_wraparound() {
+        let mut history = DailyHistory::new();
+        history.record(at_day(0), false, true);
+        history.record(at_day(3), false, false);
+        assert!(history.ticker().ends_with("F  "));
+        for day in 4..40 {
+            history.record(at_day(day), day == 39, false);
+        }
+        // The oldest days have scrolled out; day 39 is still in progress.
+        assert_eq!(history.ticker().as_str(), ".".repeat(HISTORY_DAYS));
+        assert_eq!(history.today().symbol(), 'H');
+        // A very long gap leaves a history of missing days.
+        history.record(at_day(1000), false, false);
+        assert_eq!(history.ticker().as_str(), " ".repeat(HISTORY_DAYS));
+    }
+
+    #[test]
+    fn test_door_openings_by_hour() {
+        let mut history = DailyHistory::new();
+        history.record(at_day(0), false, false);
+        for _ in 0..300 {
+            history.note_door_opening(Timestamp { seconds: 10 * SECONDS_PER_HOUR + 59 });
+        }
+        history.note_door_opening(Timestamp { seconds: 22 * SECONDS_PER_HOUR });
+        history.note_door_opening(Timestamp { seconds: 2 * SECONDS_PER_HOUR });
+        let today = history.today();
+        assert_eq!(today.door_openings_by_hour[10], u8::MAX);
+        assert_eq!(today.door_openings(), 257);
+        assert_eq!(today.door_openings_outside(8, 17), 2);
+        assert_eq!(today.door_openings_outside(21, 3), 255); // A night shift.
+        assert_eq!(today.symbol(), '.');
+        let completed = history.note_door_opening(at_day(1)).unwrap();
+        assert_eq!(completed.door_openings(), 257);
+        assert_eq!(history.today().door_openings_by_hour[1], 1);
+    }
+}
@@ -0,0 +1,50 @@
+/// SHT4x command for a high-precision temperature and humidity measurement.
+pub const SHT4X_MEASURE_HIGH_PRECISION: u8 = 0xFD;
+/// Maximum SHT4x high-precision measurement duration, in milliseconds.
+pub const SHT4X_MEASUREMENT_TIME_MS: u64 = 10;
+
+/// CRC-8 used by Sensirion sensors (polynomial 0x31, initial value 0xFF).
+pub fn sht4x_crc(data: &[u8]) -> u8 {
+    let mut crc = 0xFF_u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x31 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Converts the 6-byte SHT4x measurement response (T, CRC, RH, CRC) to relative humidity in %.
+/// Returns None if the humidity CRC does not match.
+pub fn sht4x_relative_humidity(frame: &[u8; 6]) -> Option<f32> {
+    if sht4x_crc(&frame[3..5]) != frame[5] {
+        return None;
+    }
+    let raw = u16::from_be_bytes([frame[3], frame[4]]);
+    // Conversion from the datasheet, cropped to the physical range.
+    let rh = -6.0 + 125.0 * f32::from(raw) / 65535.0;
+    Some(rh.clamp(0.0, 100.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sht4x_crc() {
+        // Example from the Sensirion datasheet.
+        assert_eq!(sht4x_crc(&[0xBE, 0xEF]), 0x92);
+    }
+
+    #[test]
+    fn test_sht4x_relative_humidity() {
+        let frame = [0x00, 0x00, 0x81, 0x80, 0x00, sht4x_crc(&[0x80, 0x00])];
+        let rh = sht4x_relative_humidity(&frame).unwrap();
+        assert!((rh - 56.5).abs() < 0.01);
+        let bad_crc = [0x00, 0x00, 0x81, 0x80, 0x00, 0x00];
+        assert_eq!(sht4x_relative_humidity(&bad_crc), None);
+        let low = [0x00, 0x00, 0x81, 0x00, 0x00, sht4x_crc(&[0x00, 0x00])];
+        assert_eq!(sht4x_relative_humidity(&low), Some(0.0));
+    }
+}
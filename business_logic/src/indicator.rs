@@ -0,0 +1,149 @@
+use crate::alarm::AlarmProfile;
+use crate::timestamp::Timestamp;
+#[cfg(feature = "authentication")]
+use crate::provisioning::DeviceKey;
+#[cfg(feature = "authentication")]
+use crate::authentication::{self, Authenticator, Tag};
+
+const INDICATOR_MAGIC: u32 = 0x1D1C_0000;
+
+/// Excursions latched by the indicator, kept in non-volatile storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IndicatorState {
+    pub freeze: bool,
+    pub heat: bool,
+    pub latched_at: Option<Timestamp>, // When the first excursion latched.
+}
+
+impl IndicatorState {
+    pub fn is_latched(&self) -> bool {
+        self.freeze || self.heat
+    }
+
+    pub fn to_words(&self) -> [u32; 2] {
+        let flags = u32::from(self.freeze) | u32::from(self.heat) << 1;
+        [INDICATOR_MAGIC | flags, self.latched_at.map_or(0, |at| at.seconds)]
+    }
+
+    /// Decode a saved state. Returns None if nothing valid was saved.
+    pub fn from_words(words: [u32; 2]) -> Option<Self> {
+        if words[0] & !0b11 != INDICATOR_MAGIC {
+            return None;
+        }
+        let (freeze, heat) = (words[0] & 1 != 0, words[0] & 2 != 0);
+        let latched_at = (freeze || heat).then_some(Timestamp { seconds: words[1] });
+        Some(Self { freeze, heat, latched_at })
+    }
+}
+
+/// Irreversible freeze and heat indicator, like a chemical shipping indicator.
+///
+/// An excursion lasting as long as the profile's alarm delay latches, and stays latched
+/// across resets until cleared by an authenticated command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExcursionIndicator {
+    profile: AlarmProfile,
+    state: IndicatorState,
+    freeze_since: Option<Timestamp>,
+    heat_since: Option<Timestamp>,
+}
+
+impl ExcursionIndicator {
+    /// Resume with the state saved before the last reset.
+    pub fn new(profile: AlarmProfile, state: IndicatorState) -> Self {
+        Self { profile, state, freeze_since: None, heat_since: None }
+    }
+
+    pub fn state(&self) -> IndicatorState {
+        self.state
+    }
+
+    /// Take a reading into account. Returns true if the state changed, so the caller can save it.
+    pub fn update(&mut self, tvc: f32, now: Timestamp) -> bool {
+        let before = self.state;
+        let freeze = latch(&mut self.freeze_since, self.profile.is_low(tvc), self.profile.low_delay_seconds, now);
+        let heat = latch(&mut self.heat_since, self.profile.is_high(tvc), self.profile.high_delay_seconds, now);
+        self.state.freeze |= freeze;
+        self.state.heat |= heat;
+        if self.state.is_latched() && self.state.latched_at.is_none() {
+            self.state.latched_at = Some(now);
+        }
+        self.state != before
+    }
+
+    /// Clear the latched state if `tag` is the device's authorization for clearing it.
+    /// The tag covers the latch time, so one issued for an earlier excursion can't be replayed.
+    #[cfg(feature = "authentication")]
+    pub fn clear(&mut self, key: &DeviceKey, tag: &[u8]) -> bool {
+        if !self.state.is_latched() || !authentication::verify(key, &clear_message(&self.state), tag) {
+            return false;
+        }
+        *self = Self::new(self.profile, IndicatorState::default());
+        true
+    }
+}
+
+/// The tag that authorizes clearing `state`, computed by the host holding the device key.
+#[cfg(feature = "authentication")]
+pub fn clear_tag(key: &DeviceKey, state: &IndicatorState) -> Tag {
+    let mut authenticator = Authenticator::new(key);
+    authenticator.update(&clear_message(state));
+    authenticator.finish()
+}
+
+#[cfg(feature = "authentication")]
+fn clear_message(state: &IndicatorState) -> [u8; 9] {
+    let mut message = *b"CLEAR\0\0\0\0";
+    message[5..].copy_from_slice(&state.to_words()[1].to_le_bytes());
+    message
+}
+
+// Track an excursion and return true once it has lasted `delay` seconds.
+fn latch(since: &mut Option<Timestamp>, excursion: bool, delay: u32, now: Timestamp) -> bool {
+    if !excursion {
+        *since = None;
+        return false;
+    }
+    let start = *since.get_or_insert(now);
+    now.seconds.saturating_sub(start.seconds) >= delay
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::at;
+
+    #[test]
+    fn test_latches_after_delay() {
+        let mut indicator = ExcursionIndicator::new(AlarmProfile::FRIDGE, IndicatorState::default());
+        assert!(!indicator.update(-1.0, at(0)));
+        assert!(!indicator.update(5.0, at(1800))); // Recovered before the delay.
+        assert!(!indicator.update(-1.0, at(2000)));
+        assert!(indicator.update(-1.0, at(2000 + 3600)));
+        assert!(!indicator.update(5.0, at(9000))); // Stays latched.
+        let state = indicator.state();
+        assert_eq!(state, IndicatorState { freeze: true, heat: false, latched_at: Some(at(5600)) });
+        assert_eq!(IndicatorState::from_words(state.to_words()), Some(state));
+        assert_eq!(IndicatorState::from_words([0, 0]), None);
+        // Resumes latched after a reset.
+        assert!(ExcursionIndicator::new(AlarmProfile::FRIDGE, state).state().is_latched());
+    }
+
+    #[cfg(feature = "authentication")]
+    #[test]
+    fn test_authenticated_clear() {
+        let key = DeviceKey([9; 32]);
+        let mut indicator = ExcursionIndicator::new(AlarmProfile::FRIDGE, IndicatorState::default());
+        indicator.update(9.0, at(0));
+        indicator.update(9.0, at(36_000));
+        let tag = clear_tag(&key, &indicator.state());
+        assert!(!indicator.clear(&DeviceKey([8; 32]), &tag));
+        assert!(indicator.clear(&key, &tag));
+        assert!(!indicator.state().is_latched());
+        // The same tag doesn't clear a later excursion.
+        indicator.update(9.0, at(40_000));
+        indicator.update(9.0, at(76_000));
+        assert!(!indicator.clear(&key, &tag));
+    }
+}
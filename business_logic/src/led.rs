@@ -0,0 +1,138 @@
+use crate::alarm::{AlarmSink, AlarmStatus};
+
+/// Colour of a status LED step. Single-colour LEDs treat every colour other than `Off` as on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedColor {
+    Off,
+    Green,
+    Red,
+    Amber, // Both dies of a bi-color LED.
+}
+
+impl LedColor {
+    pub fn is_on(self) -> bool {
+        self != LedColor::Off
+    }
+}
+
+/// One step of a repeating LED pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LedStep {
+    pub color: LedColor,
+    pub ms: u16,
+}
+
+/// Overall device status shown on the status LED, highest priority first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DeviceStatus {
+    EscalatedAlarm,
+    Alarm,
+    ExcursionLatched,
+    SensorFault,
+    MemoryLow,
+    UsbConnected,
+    Ok,
+}
+
+const fn step(color: LedColor, ms: u16) -> LedStep {
+    LedStep { color, ms }
+}
+
+// Pattern table, indexed by `DeviceStatus`.
+static PATTERNS: [&[LedStep]; 7] = [
+    // EscalatedAlarm: fast red and amber alternation, so it looks different from a plain alarm.
+    &[step(LedColor::Red, 100), step(LedColor::Amber, 100)],
+    // Alarm: fast red flashing.
+    &[step(LedColor::Red, 100), step(LedColor::Off, 100)],
+    // ExcursionLatched: slow red blink, until the indicator is cleared.
+    &[step(LedColor::Red, 1000), step(LedColor::Off, 1000)],
+    // SensorFault: red double blink.
+    &[step(LedColor::Red, 100), step(LedColor::Off, 200), step(LedColor::Red, 100), step(LedColor::Off, 1600)],
+    // MemoryLow: slow amber blink.
+    &[step(LedColor::Amber, 200), step(LedColor::Off, 1800)],
+    // UsbConnected: steady green.
+    &[step(LedColor::Green, 1000)],
+    // Ok: short green blink every 5 s to save power.
+    &[step(LedColor::Green, 50), step(LedColor::Off, 4950)],
+];
+
+impl DeviceStatus {
+    /// The repeating LED pattern for this status.
+    pub fn led_pattern(self) -> &'static [LedStep] {
+        PATTERNS[self as usize]
+    }
+}
+
+/// Conditions that feed the status LED.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StatusFlags {
+    pub alarm: bool,
+    pub escalated: bool, // An active alarm has persisted long enough to escalate.
+    pub excursion_latched: bool, // See `ExcursionIndicator`.
+    pub sensor_fault: bool,
+    pub memory_low: bool,
+    pub usb_connected: bool,
+}
+
+/// The alarm flags follow the alarms' status.
+impl AlarmSink for StatusFlags {
+    fn on_status(&mut self, status: &AlarmStatus) {
+        self.alarm = status.active != 0;
+        self.escalated = status.escalated != 0;
+    }
+}
+
+impl StatusFlags {
+    /// The highest-priority status implied by the flags.
+    pub fn status(&self) -> DeviceStatus {
+        if self.alarm && self.escalated {
+            DeviceStatus::EscalatedAlarm
+        } else if self.alarm {
+            DeviceStatus::Alarm
+        } else if self.excursion_latched {
+            DeviceStatus::ExcursionLatched
+        } else if self.sensor_fault {
+            DeviceStatus::SensorFault
+        } else if self.memory_low {
+            DeviceStatus::MemoryLow
+        } else if self.usb_connected {
+            DeviceStatus::UsbConnected
+        } else {
+            DeviceStatus::Ok
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_priority() {
+        let mut flags = StatusFlags::default();
+        assert_eq!(flags.status(), DeviceStatus::Ok);
+        flags.usb_connected = true;
+        assert_eq!(flags.status(), DeviceStatus::UsbConnected);
+        flags.sensor_fault = true;
+        assert_eq!(flags.status(), DeviceStatus::SensorFault);
+        flags.excursion_latched = true;
+        assert_eq!(flags.status(), DeviceStatus::ExcursionLatched);
+        flags.alarm = true;
+        assert_eq!(flags.status(), DeviceStatus::Alarm);
+        flags.escalated = true;
+        assert_eq!(flags.status(), DeviceStatus::EscalatedAlarm);
+    }
+
+    #[test]
+    fn test_led_patterns() {
+        assert_eq!(DeviceStatus::UsbConnected.led_pattern(), &[step(LedColor::Green, 1000)]);
+        assert!(DeviceStatus::EscalatedAlarm.led_pattern().iter().all(|s| s.color.is_on()));
+        for status in [DeviceStatus::Alarm, DeviceStatus::ExcursionLatched, DeviceStatus::SensorFault, DeviceStatus::MemoryLow, DeviceStatus::Ok] {
+            let pattern = status.led_pattern();
+            assert!(pattern.iter().any(|s| s.color.is_on()));
+            assert!(pattern.iter().any(|s| !s.color.is_on()));
+        }
+        assert!(DeviceStatus::Alarm.led_pattern().iter().all(|s| matches!(s.color, LedColor::Red | LedColor::Off)));
+    }
+}
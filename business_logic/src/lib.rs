@@ -15,7 +15,83 @@ pub fn process_data(input: &str) -> Status {
     }
 }
 
+#[cfg(feature = "accelerometer")]
+pub mod accelerometer;
+pub mod aggregator;
+pub mod ajar;
+pub mod alarm;
+#[cfg(feature = "authentication")]
+pub mod authentication;
+pub mod battery;
+pub mod burst;
+pub mod bus;
+pub mod button;
+pub mod capabilities;
+pub mod clockmonitor;
+pub mod commissioning;
+pub mod compliance;
+pub mod compressor;
+pub mod config;
+pub mod console;
+pub mod crash;
+pub mod crc;
+pub mod crosscheck;
+pub mod debug;
+pub mod device_task;
+pub mod dictionary;
+pub mod dispatch;
+pub mod display;
+pub mod door;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+pub mod errors;
+pub mod escalation;
+pub mod event_queue;
+pub mod export;
+pub mod firmware;
+pub mod flash_scheduler;
+pub mod gaps;
+pub mod hal;
+pub mod health;
+pub mod history;
+#[cfg(feature = "humidity")]
+pub mod humidity;
+pub mod indicator;
+pub mod led;
+pub mod lifecycle;
+pub mod lifetime;
+pub mod localtime;
+pub mod log;
+pub mod logger;
+pub mod logger_task;
+pub mod mains;
+pub mod notes;
+pub mod nvstore;
+pub mod onewire;
+pub mod power;
+pub mod provisioning;
+pub mod relay;
+pub mod report;
+pub mod report_scheduler;
+pub mod sample;
+pub mod sampling;
+pub mod selfheating;
+pub mod selftest;
+pub mod sensor;
+pub mod shutdown;
+pub mod simulate;
+pub mod stats;
+pub mod storage;
+pub mod store;
+#[cfg(test)]
+mod test_support;
 pub mod timestamp;
+pub mod units;
+pub mod usb;
+pub mod wallclock;
+pub mod watch;
+pub mod watchdog;
+pub mod xmodem;
 
 #[cfg(test)]
 mod tests {
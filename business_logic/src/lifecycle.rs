@@ -0,0 +1,207 @@
+use crate::alarm::AlarmKind;
+use crate::log::{Log, LogCode};
+use crate::logger::LoggerEvent;
+#[cfg(feature = "authentication")]
+use crate::provisioning::DeviceKey;
+#[cfg(feature = "authentication")]
+use crate::authentication::{self, Authenticator, Tag};
+
+const LIFECYCLE_MAGIC: u32 = 0x1C;
+
+/// Where the device is in its life, which decides what is recorded and which alarms sound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LifecycleState {
+    FactoryTest,
+    Commissioned, // Set up for a site, but not installed yet.
+    Logging,
+    Transport, // On the way to or between sites, e.g. in a cold box.
+    Storage, // Out of use, e.g. in a depot.
+    Decommissioned,
+}
+
+impl LifecycleState {
+    pub const ALL: [LifecycleState; 6] = [
+        LifecycleState::FactoryTest,
+        LifecycleState::Commissioned,
+        LifecycleState::Logging,
+        LifecycleState::Transport,
+        LifecycleState::Storage,
+        LifecycleState::Decommissioned,
+    ];
+
+    /// Name of the state, e.g. in console commands.
+    pub fn name(self) -> &'static str {
+        match self {
+            LifecycleState::FactoryTest => "factory_test",
+            LifecycleState::Commissioned => "commissioned",
+            LifecycleState::Logging => "logging",
+            LifecycleState::Transport => "transport",
+            LifecycleState::Storage => "storage",
+            LifecycleState::Decommissioned => "decommissioned",
+        }
+    }
+
+    /// Whether the device may go straight from this state to `next`.
+    pub fn can_become(self, next: LifecycleState) -> bool {
+        use LifecycleState::*;
+        matches!(
+            (self, next),
+            (FactoryTest, Commissioned)
+                | (Commissioned | Transport | Storage, Logging)
+                | (Commissioned | Logging | Storage, Transport)
+                | (Logging | Transport, Storage)
+                | (Commissioned | Logging | Transport | Storage, Decommissioned)
+                | (Decommissioned, FactoryTest) // Refurbished.
+        )
+    }
+
+    /// Whether `event` goes into the records in this state. In transport there is no door to
+    /// watch and running on battery is normal, so only the temperatures, faults and knocks count.
+    pub fn records(self, event: &LoggerEvent) -> bool {
+        match self {
+            LifecycleState::Logging => true,
+            LifecycleState::Transport => {
+                matches!(event, LoggerEvent::Sample(_) | LoggerEvent::Fault(..) | LoggerEvent::Tick(_) | LoggerEvent::ClockSet(..) | LoggerEvent::Moved(_))
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether an alarm of `kind` sounds in this state.
+    pub fn sounds(self, kind: AlarmKind) -> bool {
+        match self {
+            LifecycleState::Logging => true,
+            LifecycleState::Transport => matches!(kind, AlarmKind::HighTemp | AlarmKind::Freeze),
+            _ => false,
+        }
+    }
+}
+
+/// Why a lifecycle change was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LifecycleError {
+    NotAllowed, // See `LifecycleState::can_become`.
+    Unauthorized, // The command's tag doesn't check.
+}
+
+/// The lifecycle state, with a count of changes so an authorized command can't be replayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Lifecycle {
+    state: LifecycleState,
+    changes: u16,
+}
+
+impl Lifecycle {
+    pub fn new(state: LifecycleState) -> Self {
+        Self { state, changes: 0 }
+    }
+
+    pub fn state(&self) -> LifecycleState {
+        self.state
+    }
+
+    /// Move to `next`, logging `LifecycleChanged` with the old state in bits 8..16 and the new one
+    /// in bits 0..8. For changes the firmware makes itself; commands from outside go through `command`.
+    pub fn change(&mut self, next: LifecycleState, log: &mut impl Log) -> Result<(), LifecycleError> {
+        if !self.state.can_become(next) {
+            return Err(LifecycleError::NotAllowed);
+        }
+        log.info(LogCode::LifecycleChanged, (self.state as u32) << 8 | next as u32);
+        self.state = next;
+        self.changes = self.changes.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Move to `next` if `tag` is the device's authorization for this change, see `command_tag`.
+    #[cfg(feature = "authentication")]
+    pub fn command(&mut self, next: LifecycleState, key: &DeviceKey, tag: &[u8], log: &mut impl Log) -> Result<(), LifecycleError> {
+        if !authentication::verify(key, &self.command_message(next), tag) {
+            return Err(LifecycleError::Unauthorized);
+        }
+        self.change(next, log)
+    }
+
+    pub fn to_word(&self) -> u32 {
+        LIFECYCLE_MAGIC << 24 | u32::from(self.changes) << 8 | self.state as u32
+    }
+
+    /// Decode a saved lifecycle. Returns None if nothing valid was saved.
+    pub fn from_word(word: u32) -> Option<Self> {
+        if word >> 24 != LIFECYCLE_MAGIC {
+            return None;
+        }
+        let state = *LifecycleState::ALL.get((word & 0xFF) as usize)?;
+        Some(Self { state, changes: (word >> 8) as u16 })
+    }
+
+    // The state change and the number of changes so far, so each tag is only good once.
+    #[cfg(feature = "authentication")]
+    fn command_message(&self, next: LifecycleState) -> [u8; 9] {
+        let mut message = *b"STATE\0\0\0\0";
+        message[5..7].copy_from_slice(&self.changes.to_le_bytes());
+        (message[7], message[8]) = (self.state as u8, next as u8);
+        message
+    }
+}
+
+/// The tag that authorizes `lifecycle` to move to `next`, computed by the host holding the device key.
+#[cfg(feature = "authentication")]
+pub fn command_tag(key: &DeviceKey, lifecycle: &Lifecycle, next: LifecycleState) -> Tag {
+    let mut authenticator = Authenticator::new(key);
+    authenticator.update(&lifecycle.command_message(next));
+    authenticator.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{CaptureLog, Level};
+    use crate::timestamp::Timestamp;
+
+    #[test]
+    fn test_transitions_are_logged() {
+        let mut lifecycle = Lifecycle::new(LifecycleState::FactoryTest);
+        let mut log = CaptureLog::default();
+        assert_eq!(lifecycle.change(LifecycleState::Logging, &mut log), Err(LifecycleError::NotAllowed));
+        for next in [LifecycleState::Commissioned, LifecycleState::Logging, LifecycleState::Transport] {
+            assert_eq!(lifecycle.change(next, &mut log), Ok(()));
+        }
+        assert_eq!(lifecycle.state(), LifecycleState::Transport);
+        assert_eq!(log.entries[2], (Level::Info, LogCode::LifecycleChanged, 0x0203));
+        assert_eq!(Lifecycle::from_word(lifecycle.to_word()), Some(lifecycle));
+        assert_eq!(Lifecycle::from_word(0), None);
+        assert!(LifecycleState::ALL.iter().all(|state| !state.can_become(*state)));
+    }
+
+    #[test]
+    fn test_gating() {
+        let tick = LoggerEvent::Tick(Timestamp { seconds: 0 });
+        let door = LoggerEvent::DoorOpened(Timestamp { seconds: 0 });
+        assert!(LifecycleState::Logging.records(&door));
+        assert!(LifecycleState::Transport.records(&tick) && !LifecycleState::Transport.records(&door));
+        assert!(!LifecycleState::Storage.records(&tick));
+        assert!(LifecycleState::Transport.sounds(AlarmKind::Freeze));
+        assert!(!LifecycleState::Transport.sounds(AlarmKind::Power));
+        assert!(!LifecycleState::Commissioned.sounds(AlarmKind::HighTemp));
+    }
+
+    #[cfg(feature = "authentication")]
+    #[test]
+    fn test_authenticated_command() {
+        let key = DeviceKey([3; 32]);
+        let mut lifecycle = Lifecycle::new(LifecycleState::Logging);
+        let mut log = CaptureLog::default();
+        let tag = command_tag(&key, &lifecycle, LifecycleState::Storage);
+        let result = lifecycle.command(LifecycleState::Transport, &key, &tag, &mut log);
+        assert_eq!(result, Err(LifecycleError::Unauthorized));
+        assert_eq!(lifecycle.command(LifecycleState::Storage, &key, &tag, &mut log), Ok(()));
+        // Back to logging and into storage again: the old tag no longer works.
+        let tag_back = command_tag(&key, &lifecycle, LifecycleState::Logging);
+        assert_eq!(lifecycle.command(LifecycleState::Logging, &key, &tag_back, &mut log), Ok(()));
+        let result = lifecycle.command(LifecycleState::Storage, &key, &tag, &mut log);
+        assert_eq!(result, Err(LifecycleError::Unauthorized));
+    }
+}
@@ -0,0 +1,187 @@
+use arrayvec::ArrayString;
+use core::fmt::Write;
+
+use crate::crc::crc32;
+use crate::timestamp::Timestamp;
+
+/// Length of one saved copy of the counters: sequence number, counters, CRC-32.
+pub const LIFETIME_RECORD_LEN: usize = 4 + 5 * 4 + 4;
+/// Time between saves, so a reset loses at most this much counting.
+pub const LIFETIME_COMMIT_SECONDS: u32 = 3600;
+/// Length of the lifetime summary line.
+pub const LIFETIME_SUMMARY_LEN: usize = 64;
+const SECONDS_PER_HOUR: u32 = 3600;
+
+/// Totals over the logger's whole service life, kept across resets by `LifetimeStore`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LifetimeCounters {
+    pub door_openings: u32,
+    pub high_alarm_seconds: u32,
+    pub freeze_alarm_seconds: u32,
+    pub powered_seconds: u32,
+    pub compressor_starts: u32,
+}
+
+impl LifetimeCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count `seconds` of running, and of the alarms that were active for them.
+    pub fn run(&mut self, seconds: u32, high_alarm: bool, freeze_alarm: bool) {
+        self.powered_seconds = self.powered_seconds.saturating_add(seconds);
+        if high_alarm {
+            self.high_alarm_seconds = self.high_alarm_seconds.saturating_add(seconds);
+        }
+        if freeze_alarm {
+            self.freeze_alarm_seconds = self.freeze_alarm_seconds.saturating_add(seconds);
+        }
+    }
+
+    pub fn door_opened(&mut self) {
+        self.door_openings = self.door_openings.saturating_add(1);
+    }
+
+    pub fn compressor_started(&mut self) {
+        self.compressor_starts = self.compressor_starts.saturating_add(1);
+    }
+
+    /// One-line summary for the device status and reports, with times in whole hours.
+    pub fn summary(&self) -> ArrayString<LIFETIME_SUMMARY_LEN> {
+        let mut summary = ArrayString::new();
+        let _ = write!(
+            summary,
+            "LIFE PWR {}h HIGH {}h FRZ {}h DOOR {} COMP {}",
+            self.powered_seconds / SECONDS_PER_HOUR,
+            self.high_alarm_seconds / SECONDS_PER_HOUR,
+            self.freeze_alarm_seconds / SECONDS_PER_HOUR,
+            self.door_openings,
+            self.compressor_starts,
+        );
+        summary
+    }
+
+    fn words(&self) -> [u32; 5] {
+        [self.door_openings, self.high_alarm_seconds, self.freeze_alarm_seconds, self.powered_seconds, self.compressor_starts]
+    }
+}
+
+/// Saves `LifetimeCounters` to two slots in turn, so a reset in the middle of writing one leaves
+/// the other intact. Each copy carries a sequence number and a CRC, and the newest copy that
+/// checks is restored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LifetimeStore {
+    sequence: u32, // Of the newest copy.
+    last_commit: Option<Timestamp>,
+}
+
+impl LifetimeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restore the counters from the two slots. They start from zero if neither copy checks,
+    /// e.g. on a new device.
+    pub fn restore(slots: [&[u8; LIFETIME_RECORD_LEN]; 2]) -> (Self, LifetimeCounters) {
+        let newest = slots.into_iter().filter_map(parse).reduce(|newest, copy| {
+            // Sequence numbers wrap, so compare them by their difference.
+            if copy.0.wrapping_sub(newest.0) as i32 > 0 { copy } else { newest }
+        });
+        match newest {
+            Some((sequence, counters)) => (Self { sequence, last_commit: None }, counters),
+            None => (Self::new(), LifetimeCounters::new()),
+        }
+    }
+
+    /// Save `counters` if `LIFETIME_COMMIT_SECONDS` passed since the last save, or since the
+    /// first call. Returns the slot to write and its contents.
+    pub fn commit_if_due(&mut self, counters: &LifetimeCounters, now: Timestamp) -> Option<(usize, [u8; LIFETIME_RECORD_LEN])> {
+        let last = *self.last_commit.get_or_insert(now);
+        (now.seconds.saturating_sub(last.seconds) >= LIFETIME_COMMIT_SECONDS).then(|| self.commit(counters, now))
+    }
+
+    /// Save `counters` now, e.g. before shutting down. Returns the slot to write, which is never
+    /// the one holding the newest copy, and its contents.
+    pub fn commit(&mut self, counters: &LifetimeCounters, now: Timestamp) -> (usize, [u8; LIFETIME_RECORD_LEN]) {
+        self.sequence = self.sequence.wrapping_add(1);
+        self.last_commit = Some(now);
+        let mut bytes = [0u8; LIFETIME_RECORD_LEN];
+        bytes[..4].copy_from_slice(&self.sequence.to_le_bytes());
+        for (chunk, word) in bytes[4..24].chunks_exact_mut(4).zip(counters.words()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        let crc = crc32(0, &bytes[..24]);
+        bytes[24..].copy_from_slice(&crc.to_le_bytes());
+        ((self.sequence % 2) as usize, bytes)
+    }
+}
+
+// The sequence number and counters of a copy, if its CRC checks.
+fn parse(bytes: &[u8; LIFETIME_RECORD_LEN]) -> Option<(u32, LifetimeCounters)> {
+    let word = |offset: usize| u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+    if crc32(0, &bytes[..24]) != word(24) {
+        return None;
+    }
+    let counters = LifetimeCounters {
+        door_openings: word(4),
+        high_alarm_seconds: word(8),
+        freeze_alarm_seconds: word(12),
+        powered_seconds: word(16),
+        compressor_starts: word(20),
+    };
+    Some((word(0), counters))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counting_and_summary() {
+        let mut counters = LifetimeCounters::new();
+        counters.run(7200, true, false);
+        counters.run(3600, false, true);
+        counters.door_opened();
+        counters.compressor_started();
+        counters.compressor_started();
+        assert_eq!(counters.powered_seconds, 10800);
+        assert_eq!(counters.summary().as_str(), "LIFE PWR 3h HIGH 2h FRZ 1h DOOR 1 COMP 2");
+    }
+
+    #[test]
+    fn test_commits_alternate_and_survive_tearing() {
+        let mut slots = [[0xFF; LIFETIME_RECORD_LEN]; 2];
+        let (mut store, mut counters) = LifetimeStore::restore([&slots[0], &slots[1]]);
+        assert_eq!(counters, LifetimeCounters::new());
+        assert_eq!(store.commit_if_due(&counters, Timestamp { seconds: 100 }), None);
+        counters.door_opened();
+        assert_eq!(store.commit_if_due(&counters, Timestamp { seconds: 3699 }), None);
+        let (slot, bytes) = store.commit_if_due(&counters, Timestamp { seconds: 3700 }).unwrap();
+        assert_eq!(slot, 1);
+        slots[slot] = bytes;
+        counters.door_opened();
+        let (slot, bytes) = store.commit(&counters, Timestamp { seconds: 4000 });
+        assert_eq!(slot, 0);
+        slots[slot] = bytes;
+        assert_eq!(LifetimeStore::restore([&slots[0], &slots[1]]).1.door_openings, 2);
+        // A reset part way through the next write leaves the previous copy.
+        counters.door_opened();
+        let (slot, bytes) = store.commit(&counters, Timestamp { seconds: 8000 });
+        slots[slot][..12].copy_from_slice(&bytes[..12]);
+        let (mut restored, restored_counters) = LifetimeStore::restore([&slots[0], &slots[1]]);
+        assert_eq!(restored_counters.door_openings, 2);
+        // And the next write goes over the torn copy.
+        assert_eq!(restored.commit(&restored_counters, Timestamp { seconds: 9000 }).0, slot);
+    }
+
+    #[test]
+    fn test_sequence_wraps() {
+        let counters = LifetimeCounters { door_openings: 5, ..LifetimeCounters::new() };
+        let mut store = LifetimeStore { sequence: u32::MAX - 1, last_commit: None };
+        let (_, older) = store.commit(&LifetimeCounters::new(), Timestamp { seconds: 0 });
+        let (_, newer) = store.commit(&counters, Timestamp { seconds: 0 });
+        assert_eq!(LifetimeStore::restore([&newer, &older]).1, counters);
+        assert_eq!(LifetimeStore::restore([&older, &newer]).1, counters);
+    }
+}
@@ -0,0 +1,113 @@
+use arrayvec::{ArrayString, ArrayVec};
+
+use crate::timestamp::Timestamp;
+
+/// Most daylight saving periods that can be configured at once.
+pub const MAX_DST_PERIODS: usize = 8;
+/// Furthest UTC offsets in use, in minutes.
+pub const UTC_OFFSET_RANGE_MINUTES: core::ops::RangeInclusive<i16> = -12 * 60..=14 * 60;
+
+/// A stretch of time with an extra offset, e.g. one summer of daylight saving time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DstPeriod {
+    pub start: Timestamp,
+    pub end: Timestamp, // Exclusive.
+    pub extra_minutes: i16,
+}
+
+/// Converts the logger's epoch to local time for display and reports.
+///
+/// Records keep the monotonic epoch; only rendering and the daily roll-over use local time.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LocalTime {
+    utc_offset_minutes: i16,
+    dst: ArrayVec<DstPeriod, MAX_DST_PERIODS>,
+}
+
+impl LocalTime {
+    /// Returns None if the offset is outside `UTC_OFFSET_RANGE_MINUTES`.
+    pub fn new(utc_offset_minutes: i16) -> Option<Self> {
+        UTC_OFFSET_RANGE_MINUTES
+            .contains(&utc_offset_minutes)
+            .then(|| Self { utc_offset_minutes, dst: ArrayVec::new() })
+    }
+
+    /// Add a daylight saving period. Returns false if the table is full.
+    pub fn add_dst_period(&mut self, period: DstPeriod) -> bool {
+        self.dst.try_push(period).is_ok()
+    }
+
+    /// Offset from the epoch to local time at `timestamp`, in seconds.
+    pub fn offset_seconds(&self, timestamp: Timestamp) -> i32 {
+        let dst = self
+            .dst
+            .iter()
+            .find(|period| (period.start.seconds..period.end.seconds).contains(&timestamp.seconds))
+            .map_or(0, |period| period.extra_minutes);
+        (i32::from(self.utc_offset_minutes) + i32::from(dst)) * 60
+    }
+
+    /// `timestamp` in local time, clamped to the range of `Timestamp`.
+    pub fn to_local(&self, timestamp: Timestamp) -> Timestamp {
+        let seconds = i64::from(timestamp.seconds) + i64::from(self.offset_seconds(timestamp));
+        Timestamp { seconds: seconds.clamp(0, i64::from(u32::MAX)) as u32 }
+    }
+
+    /// Local time rendered like `Timestamp::create_iso8601_str`.
+    pub fn render(&self, timestamp: Timestamp) -> ArrayString<32> {
+        self.to_local(timestamp).create_iso8601_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offsets() {
+        assert_eq!(LocalTime::new(15 * 60), None);
+        let mut local = LocalTime::new(-5 * 60).unwrap();
+        let dst = DstPeriod { start: Timestamp { seconds: 100_000 }, end: Timestamp { seconds: 200_000 }, extra_minutes: 60 };
+        assert!(local.add_dst_period(dst));
+        assert_eq!(local.to_local(Timestamp { seconds: 86400 }).seconds, 86400 - 5 * 3600);
+        assert_eq!(local.offset_seconds(Timestamp { seconds: 150_000 }), -4 * 3600);
+        assert_eq!(local.offset_seconds(Timestamp { seconds: 200_000 }), -5 * 3600);
+        assert_eq!(local.to_local(Timestamp { seconds: 60 }).seconds, 0);
+        assert_eq!(local.render(Timestamp { seconds: 86400 }).as_str(), "P0DT19H0M0S");
+    }
+
+    #[test]
+    fn test_dst_edges() {
+        // UTC+1, with an hour of daylight saving time between two instants.
+        let mut local = LocalTime::new(60).unwrap();
+        let (start, end) = (1000 * 3600, 2000 * 3600);
+        assert!(local.add_dst_period(DstPeriod { start: Timestamp { seconds: start }, end: Timestamp { seconds: end }, extra_minutes: 60 }));
+        let at = |seconds| local.to_local(Timestamp { seconds }).seconds;
+        // Local time skips an hour at the start of the period, the start itself being in it.
+        assert_eq!(at(start - 1), start - 1 + 3600);
+        assert_eq!(at(start), start + 2 * 3600);
+        // And goes over an hour again at the end, which is back on standard time.
+        assert_eq!(at(end - 1), end - 1 + 2 * 3600);
+        assert_eq!(at(end), end + 3600);
+        assert_eq!(at(end), at(end - 3600));
+    }
+
+    #[test]
+    fn test_dst_periods() {
+        // Ireland's winter time is the offset, negative, with standard time in the summer.
+        let mut local = LocalTime::new(60).unwrap();
+        let period = |start, end, extra_minutes| DstPeriod { start: Timestamp { seconds: start }, end: Timestamp { seconds: end }, extra_minutes };
+        assert!(local.add_dst_period(period(0, 1000, -60)));
+        assert!(local.add_dst_period(period(5000, 6000, -60)));
+        assert_eq!(local.offset_seconds(Timestamp { seconds: 500 }), 0);
+        assert_eq!(local.offset_seconds(Timestamp { seconds: 3000 }), 3600);
+        assert_eq!(local.offset_seconds(Timestamp { seconds: 5999 }), 0);
+        for i in 2..MAX_DST_PERIODS as u32 {
+            assert!(local.add_dst_period(period(10_000 * i, 10_000 * i + 1, 60)));
+        }
+        assert!(!local.add_dst_period(period(0, 1, 60)));
+        // Clamped at the end of the range of `Timestamp`.
+        assert_eq!(LocalTime::new(14 * 60).unwrap().to_local(Timestamp { seconds: u32::MAX - 10 }).seconds, u32::MAX);
+    }
+}
@@ -0,0 +1,136 @@
+/// Severity of a diagnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+/// What a diagnostic is about. The meaning of the payload depends on the code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LogCode {
+    OnBattery, // Payload: seconds before the low-power profile is applied.
+    ClockProfileChanged, // Payload: new system clock in Hz.
+    ConfigChanged, // Payload: bitmap of the changed fields.
+    FirmwareStaged, // Payload: length of the validated image in bytes.
+    FirmwareRejected, // Payload: the `UpdateError` as a number.
+    FirmwareConfirmed, // Payload: boots the new image took to become healthy.
+    FirmwareRolledBack, // Payload: the bank now booting, 0 for A and 1 for B.
+    SuspectedAjar, // Payload: compressor duty in percent over the last window.
+    RecordsCompacted, // Payload: number of records freed.
+    LifecycleChanged, // Payload: old `LifecycleState` in bits 8..16, new one in bits 0..8.
+    DoorSwitchFault, // Payload: the `DoorSwitchFault` as a number.
+    Motion, // Payload: `MotionEvent::code`.
+    RecordStored, // Payload: start of the record, seconds since the epoch.
+    EventOutOfOrder, // Payload: seconds the dropped event was behind the last one.
+    EventTooFarAhead, // Payload: seconds the dropped event was ahead of the last one.
+    ClockDegraded, // Payload: milliseconds the RTC stood still before the monotonic timer took over.
+    ClockRestored, // Payload: seconds the RTC lost while stopped.
+    ClockSet, // Payload: seconds the clock moved, as an i32.
+    BurstCaptured, // Payload: `BurstTrigger::code` in bits 16..32, samples captured in bits 0..16.
+    NoteAdded, // Payload: time of the operator note, seconds since the epoch.
+    UsbConnected, // Payload: time of the connection, seconds since the epoch.
+    UsbDisconnected, // Payload: seconds the session lasted.
+    StateCorrupted, // Payload: times the record in progress has been restored after corruption.
+    ReportSent, // Payload: end of the scheduled report's period, seconds since the epoch.
+    ReportFailed, // Payload: attempts at sending the scheduled report so far.
+    ReportAbandoned, // Payload: end of the period of the scheduled report given up on.
+    QueueOverflow, // Payload: events coalesced or dropped from the queue so far.
+    SimulatedEvent, // Payload: 1 temperature, 2 door opened, 3 door closed, 4 power lost, 5 power restored.
+    CommissioningStep, // Payload: the `CommissioningStep` completed, as a number.
+    Commissioned, // Payload: time commissioning completed, seconds since the epoch.
+    SensorDisagree, // Payload: deviation of the primary vaccine probe from the secondary, hundredths of a °C.
+    RecordsSaved, // Payload: sequence number of the last record now in flash.
+    StorageFailed, // Payload: sequence number of the first record that couldn't be saved.
+    AlarmStarted, // Payload: the `AlarmKind` as a number.
+    AlarmCleared, // Payload: the `AlarmKind` as a number.
+    AlarmAcknowledged, // Payload: time of the acknowledgement, seconds since the epoch.
+    ClockWentBack, // Payload: seconds the reading was behind the last one.
+    IndicatorLatched, // Payload: time the excursion indicator latched, seconds since the epoch.
+    IndicatorCleared, // Payload: time the latched excursion was cleared, seconds since the epoch.
+    MainsStateChanged, // Payload: the new `MainsState` as a number.
+    BatteryReplaceBy, // Payload: time the battery is expected to run out, seconds since the epoch.
+}
+
+/// Destination for diagnostics emitted by the business logic.
+///
+/// The firmware logs through defmt; host tests capture the entries so they can be asserted on.
+pub trait Log {
+    fn log(&mut self, level: Level, code: LogCode, payload: u32);
+
+    fn info(&mut self, code: LogCode, payload: u32) {
+        self.log(Level::Info, code, payload);
+    }
+
+    fn warn(&mut self, code: LogCode, payload: u32) {
+        self.log(Level::Warn, code, payload);
+    }
+
+    fn error(&mut self, code: LogCode, payload: u32) {
+        self.log(Level::Error, code, payload);
+    }
+}
+
+/// Discards all diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NullLog;
+
+impl Log for NullLog {
+    fn log(&mut self, _level: Level, _code: LogCode, _payload: u32) {}
+}
+
+/// Forwards diagnostics to defmt.
+#[cfg(feature = "defmt")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DefmtLog;
+
+#[cfg(feature = "defmt")]
+impl Log for DefmtLog {
+    fn log(&mut self, level: Level, code: LogCode, payload: u32) {
+        match level {
+            Level::Info => defmt::info!("{} {=u32}", code, payload),
+            Level::Warn => defmt::warn!("{} {=u32}", code, payload),
+            Level::Error => defmt::error!("{} {=u32}", code, payload),
+        }
+    }
+}
+
+/// Records diagnostics for assertions in host tests.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct CaptureLog {
+    pub entries: Vec<(Level, LogCode, u32)>,
+}
+
+#[cfg(test)]
+impl Log for CaptureLog {
+    fn log(&mut self, level: Level, code: LogCode, payload: u32) {
+        self.entries.push((level, code, payload));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        let mut log = CaptureLog::default();
+        log.info(LogCode::RecordStored, 900);
+        log.warn(LogCode::EventOutOfOrder, 5);
+        log.error(LogCode::StorageFailed, 7);
+        log.log(Level::Warn, LogCode::QueueOverflow, 1);
+        assert_eq!(
+            log.entries,
+            [
+                (Level::Info, LogCode::RecordStored, 900),
+                (Level::Warn, LogCode::EventOutOfOrder, 5),
+                (Level::Error, LogCode::StorageFailed, 7),
+                (Level::Warn, LogCode::QueueOverflow, 1),
+            ]
+        );
+        NullLog.error(LogCode::StorageFailed, 7);
+    }
+}
@@ -0,0 +1,980 @@
+use crate::aggregator::{AggregationRecord, TemperatureAggregator};
+use crate::alarm::AlarmProfile;
+use crate::crc::crc32;
+use crate::errors::ErrorCode;
+use crate::sample::TemperatureSample;
+use crate::timestamp::{Timestamp, TimestampError};
+
+/// Length of one aggregation record in a standard deployment.
+pub const RECORD_PERIOD_SECONDS: u32 = 15 * 60;
+/// A reading stands for the temperature until the next one, but for no longer than this.
+pub const MAX_HOLD_SECONDS: u32 = 15 * 60;
+/// Logging resumes by itself after at most this long, so a forgotten pause can't stop it for good.
+pub const MAX_PAUSE_SECONDS: u32 = 24 * 3600;
+/// Events further ahead of the last one are taken for clock errors. The logger is ticked at its
+/// `next_deadline`, at least every record, so real gaps are far shorter, and the logger starts
+/// afresh after a restart.
+pub const MAX_TIME_JUMP_SECONDS: u32 = 31 * 24 * 3600;
+/// Words in a serialized `WarmStart`, including its CRC.
+pub const WARM_START_WORDS: usize = 9;
+/// Most numbered events of one second that are put back in order when one arrives late.
+pub const REORDER_WINDOW: usize = 4;
+const SECONDS_PER_DAY: u32 = 86400;
+
+/// Record timing of a deployment, so standard and research deployments run the same firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SamplePolicy {
+    pub record_period_seconds: u32,
+    pub max_hold_seconds: u32,
+}
+
+impl Default for SamplePolicy {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+impl SamplePolicy {
+    pub const STANDARD: SamplePolicy =
+        SamplePolicy { record_period_seconds: RECORD_PERIOD_SECONDS, max_hold_seconds: MAX_HOLD_SECONDS };
+    pub const RESEARCH: SamplePolicy = SamplePolicy::with_record_period(5 * 60);
+
+    /// Records of `seconds`, each reading held for at most one record.
+    pub const fn with_record_period(seconds: u32) -> Self {
+        Self { record_period_seconds: seconds, max_hold_seconds: seconds }
+    }
+
+    /// Records must divide a day evenly, so that they line up with day boundaries.
+    pub fn is_valid(&self) -> bool {
+        self.record_period_seconds > 0 && SECONDS_PER_DAY.is_multiple_of(self.record_period_seconds) && self.max_hold_seconds > 0
+    }
+
+    /// The first record boundary after `timestamp`.
+    pub fn next_boundary(&self, timestamp: Timestamp) -> Timestamp {
+        Timestamp { seconds: self.period_start(timestamp).seconds.saturating_add(self.record_period_seconds) }
+    }
+
+    fn period_start(&self, timestamp: Timestamp) -> Timestamp {
+        Timestamp { seconds: timestamp.seconds - timestamp.seconds % self.record_period_seconds }
+    }
+}
+
+/// Why logging was paused, e.g. so analysts can tell planned defrosts from maintenance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PauseReason {
+    Defrost,
+    Maintenance,
+    Cleaning,
+    Relocation,
+}
+
+impl PauseReason {
+    /// Bit for this reason in `AggregationRecord::pause_reasons`.
+    pub fn mask(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// Inputs to the logger, in time order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LoggerEvent {
+    Sample(TemperatureSample),
+    DoorOpened(Timestamp),
+    DoorClosed(Timestamp),
+    PowerLost(Timestamp),
+    PowerRestored(Timestamp),
+    Fault(Timestamp, ErrorCode), // Noted in the record in progress.
+    Tick(Timestamp), // Advance time without any other change, e.g. to complete a record.
+    Paused(Timestamp, PauseReason, u32), // Stop logging temperatures for up to this many seconds.
+    Resumed(Timestamp), // End a pause early.
+    ClockSet(Timestamp, Timestamp), // The clock was set: the time just before, and the new time.
+    Moved(Timestamp), // A shock or tilt of the device, counted in the record in progress.
+}
+
+impl LoggerEvent {
+    pub fn timestamp(&self) -> Timestamp {
+        match self {
+            LoggerEvent::Sample(sample) => sample.timestamp,
+            LoggerEvent::DoorOpened(timestamp)
+            | LoggerEvent::DoorClosed(timestamp)
+            | LoggerEvent::PowerLost(timestamp)
+            | LoggerEvent::PowerRestored(timestamp)
+            | LoggerEvent::Fault(timestamp, _)
+            | LoggerEvent::Tick(timestamp)
+            | LoggerEvent::Paused(timestamp, ..)
+            | LoggerEvent::Resumed(timestamp)
+            | LoggerEvent::Moved(timestamp)
+            | LoggerEvent::ClockSet(_, timestamp) => *timestamp,
+        }
+    }
+}
+
+/// What the logger is doing, see `Logger::status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LoggerStatus {
+    Idle, // No event yet.
+    Logging, // A reading is held, so the temperatures are being integrated.
+    NoReading, // The last reading's hold expired, or there hasn't been one.
+    Paused, // See `Logger::pause`.
+}
+
+impl LoggerStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            LoggerStatus::Idle => "idle",
+            LoggerStatus::Logging => "logging",
+            LoggerStatus::NoReading => "no_reading",
+            LoggerStatus::Paused => "paused",
+        }
+    }
+}
+
+/// What a deadline from `Logger::next_deadline` is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DeadlineKind {
+    AlarmDelay, // The excursion in progress reaches its alarm delay, if the last reading holds.
+    DayBoundary, // The last record of the day completes.
+    RecordBoundary, // The record in progress completes.
+    StorageMaintenance, // As set with `Logger::schedule_maintenance`.
+}
+
+/// The next time the logger needs to be woken, and why.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Deadline {
+    pub at: Timestamp,
+    pub kind: DeadlineKind,
+}
+
+/// What the logger needs to carry on after a brief reset, e.g. by the watchdog, rather than start
+/// afresh: an excursion hours into its alarm delay keeps its length, and the last reading and the
+/// door and power states still hold. The record in progress is not kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WarmStart {
+    pub at: Timestamp, // Time of the last event.
+    pub tvc: f32, // Last reading, if one is held.
+    pub tamb: f32,
+    pub tvc_quality: u8, // `SampleFlag` bitmaps of the last reading.
+    pub tamb_quality: u8,
+    pub hold_until: Option<u32>, // When the last reading's hold expires, None if none is held.
+    pub high_run_seconds: u32, // Length of the vaccine channel's high excursion so far.
+    pub low_run_seconds: u32, // Length of the vaccine channel's low excursion so far.
+    pub door_run_seconds: u32, // How long the door has been open so far.
+    pub door_open: bool,
+    pub power_off: bool,
+}
+
+impl WarmStart {
+    pub fn to_words(&self) -> [u32; WARM_START_WORDS] {
+        // The reading's quality bitmaps share the word with the flags, in bits 8 up.
+        let flags = u32::from(self.hold_until.is_some())
+            | u32::from(self.door_open) << 1
+            | u32::from(self.power_off) << 2
+            | u32::from(self.tvc_quality) << 8
+            | u32::from(self.tamb_quality) << 16;
+        let mut words = [
+            self.at.seconds,
+            self.tvc.to_bits(),
+            self.tamb.to_bits(),
+            self.hold_until.unwrap_or(0),
+            self.high_run_seconds,
+            self.low_run_seconds,
+            flags,
+            self.door_run_seconds,
+            0,
+        ];
+        words[WARM_START_WORDS - 1] = warm_start_checksum(&words);
+        words
+    }
+
+    /// Decode words written by `to_words`. Returns None if the CRC doesn't match, e.g. after a
+    /// power-on, when the memory holding them is random.
+    pub fn from_words(words: &[u32; WARM_START_WORDS]) -> Option<Self> {
+        if warm_start_checksum(words) != words[WARM_START_WORDS - 1] {
+            return None;
+        }
+        let flags = words[6];
+        Some(Self {
+            at: Timestamp { seconds: words[0] },
+            tvc: f32::from_bits(words[1]),
+            tamb: f32::from_bits(words[2]),
+            tvc_quality: (flags >> 8) as u8,
+            tamb_quality: (flags >> 16) as u8,
+            hold_until: (flags & 1 != 0).then_some(words[3]),
+            high_run_seconds: words[4],
+            low_run_seconds: words[5],
+            door_run_seconds: words[7],
+            door_open: flags & 2 != 0,
+            power_off: flags & 4 != 0,
+        })
+    }
+}
+
+// CRC-32 over all words but the last, which holds it.
+fn warm_start_checksum(words: &[u32; WARM_START_WORDS]) -> u32 {
+    words[..WARM_START_WORDS - 1].iter().fold(0, |crc, word| crc32(crc, &word.to_le_bytes()))
+}
+
+// What the events of the current second change, as it was before the first of them, and the
+// numbered events applied since in sequence order, so one that arrives late can be put in place.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SecondStart {
+    aggregator: TemperatureAggregator,
+    held: Option<(TemperatureSample, u32)>,
+    door_open: bool,
+    power_off: bool,
+    pause: Option<(PauseReason, u32)>,
+    events: [Option<(u32, LoggerEvent)>; REORDER_WINDOW],
+}
+
+/// Turns a stream of events into `AggregationRecord`s, one per record period
+/// aligned to the epoch. Periods in which nothing happened produce no record.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Logger {
+    policy: SamplePolicy,
+    aggregator: TemperatureAggregator,
+    record_start: Option<Timestamp>, // None until the first event.
+    now: Timestamp, // Time up to which everything has been integrated.
+    held: Option<(TemperatureSample, u32)>, // Latest reading and the time its hold expires.
+    door_open: bool,
+    power_off: bool,
+    pause: Option<(PauseReason, u32)>, // While paused, why and when logging resumes by itself.
+    last_sequence: Option<u32>, // Sequence number of the last event processed, if it had one.
+    second_start: Option<SecondStart>, // None unless all of this second's events were numbered.
+    checksum: u32, // `TemperatureAggregator::checksum` after the last event.
+    checkpoint: TemperatureAggregator, // Copy of the aggregator after the last event, to restore.
+    integrity_faults: u32,
+    maintenance: Option<Timestamp>, // When the store next wants maintenance, e.g. compaction.
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new(SamplePolicy::STANDARD, AlarmProfile::FRIDGE)
+    }
+}
+
+impl Logger {
+    pub fn new(policy: SamplePolicy, profile: AlarmProfile) -> Self {
+        let aggregator = TemperatureAggregator::new(Timestamp { seconds: 0 }, profile);
+        Self {
+            policy,
+            aggregator,
+            record_start: None,
+            now: Timestamp { seconds: 0 },
+            held: None,
+            door_open: false,
+            power_off: false,
+            pause: None,
+            last_sequence: None,
+            second_start: None,
+            checksum: aggregator.checksum(),
+            checkpoint: aggregator,
+            integrity_faults: 0,
+            maintenance: None,
+        }
+    }
+
+    /// What the logger is doing, e.g. for diagnostics.
+    pub fn status(&self) -> LoggerStatus {
+        if self.record_start.is_none() {
+            LoggerStatus::Idle
+        } else if self.pause.is_some() {
+            LoggerStatus::Paused
+        } else if self.held.is_some() {
+            LoggerStatus::Logging
+        } else {
+            LoggerStatus::NoReading
+        }
+    }
+
+    /// The aggregator with the record in progress, for inspection.
+    pub fn aggregator(&self) -> &TemperatureAggregator {
+        &self.aggregator
+    }
+
+    /// Time up to which everything has been integrated.
+    pub fn now(&self) -> Timestamp {
+        self.now
+    }
+
+    /// Start of the record in progress, None until the first event.
+    pub fn record_start(&self) -> Option<Timestamp> {
+        self.record_start
+    }
+
+    /// The reading standing for the temperatures now, and when its hold expires.
+    pub fn held_sample(&self) -> Option<(TemperatureSample, Timestamp)> {
+        self.held.map(|(sample, expires)| (sample, Timestamp { seconds: expires }))
+    }
+
+    /// While paused, why, and when logging resumes by itself.
+    pub fn pause(&self) -> Option<(PauseReason, Timestamp)> {
+        self.pause.map(|(reason, until)| (reason, Timestamp { seconds: until }))
+    }
+
+    /// Whether the door and the power are taken to be open and off.
+    pub fn door_and_power(&self) -> (bool, bool) {
+        (self.door_open, self.power_off)
+    }
+
+    /// Times the record in progress was found corrupted in RAM and restored.
+    pub fn integrity_faults(&self) -> u32 {
+        self.integrity_faults
+    }
+
+    /// Wake for storage maintenance at `at` as well, or no longer if None.
+    pub fn schedule_maintenance(&mut self, at: Option<Timestamp>) {
+        self.maintenance = at;
+    }
+
+    /// The nearest deadline after `now`, so the caller can program a single wakeup rather than
+    /// poll. Of deadlines at the same time, the first in `DeadlineKind` order is given.
+    pub fn next_deadline(&self, now: Timestamp) -> Deadline {
+        let day = Timestamp { seconds: (now.seconds - now.seconds % SECONDS_PER_DAY).saturating_add(SECONDS_PER_DAY) };
+        let candidates = [
+            self.alarm_deadline().map(|at| (at, DeadlineKind::AlarmDelay)),
+            Some((day, DeadlineKind::DayBoundary)),
+            Some((self.policy.next_boundary(now), DeadlineKind::RecordBoundary)),
+            self.maintenance.map(|at| (at, DeadlineKind::StorageMaintenance)),
+        ];
+        let (at, kind) = candidates
+            .into_iter()
+            .flatten()
+            .filter(|(at, _)| at.seconds > now.seconds)
+            .min_by_key(|(at, _)| at.seconds)
+            .unwrap_or((day, DeadlineKind::DayBoundary)); // The day boundary is always ahead.
+        Deadline { at, kind }
+    }
+
+    // When the vaccine channel's excursion reaches its alarm delay, if the reading held now is out
+    // of range and still holds by then.
+    fn alarm_deadline(&self) -> Option<Timestamp> {
+        let (sample, expires) = self.held.filter(|_| self.pause.is_none())?;
+        let profile = self.aggregator.channels()[0].profile()?;
+        let (high_run_seconds, low_run_seconds) = self.aggregator.excursion_runs();
+        let (run, delay) = if profile.is_high(sample.tvc) {
+            (high_run_seconds, profile.high_delay_seconds)
+        } else if profile.is_low(sample.tvc) {
+            (low_run_seconds, profile.low_delay_seconds)
+        } else {
+            return None;
+        };
+        let at = self.now.seconds.saturating_add(delay.checked_sub(run)?);
+        (at <= expires).then_some(Timestamp { seconds: at })
+    }
+
+    /// The state to save after each event, so `warm_start` can carry on from it after a reset.
+    /// None before the first event.
+    pub fn warm_start_state(&self) -> Option<WarmStart> {
+        self.record_start?;
+        let (high_run_seconds, low_run_seconds) = self.aggregator.excursion_runs();
+        let held = self.held.map(|(sample, _)| sample);
+        let (tvc, tamb) = held.map_or((0.0, 0.0), |sample| (sample.tvc, sample.tamb));
+        let (tvc_quality, tamb_quality) = held.map_or((0, 0), |sample| (sample.tvc_quality, sample.tamb_quality));
+        Some(WarmStart {
+            at: self.now,
+            tvc,
+            tamb,
+            tvc_quality,
+            tamb_quality,
+            hold_until: self.held.map(|(_, expires)| expires),
+            high_run_seconds,
+            low_run_seconds,
+            door_run_seconds: self.aggregator.door_run_seconds(),
+            door_open: self.door_open,
+            power_off: self.power_off,
+        })
+    }
+
+    /// Carry on from a state saved before a reset, before the first event. The state is used
+    /// only if it is no older at `now` than a reading may be held, so the gap is short enough
+    /// for the last reading to stand for it; otherwise the logger starts afresh.
+    /// Returns whether the state was used.
+    pub fn warm_start(&mut self, state: &WarmStart, now: Timestamp) -> bool {
+        let fresh = now.seconds >= state.at.seconds && now.seconds - state.at.seconds <= self.policy.max_hold_seconds;
+        if self.record_start.is_some() || !fresh {
+            return false;
+        }
+        self.start_record(self.policy.period_start(state.at));
+        self.now = state.at;
+        self.held = state.hold_until.map(|expires| {
+            let sample = TemperatureSample {
+                timestamp: state.at,
+                tamb: state.tamb,
+                tvc: state.tvc,
+                tamb_quality: state.tamb_quality,
+                tvc_quality: state.tvc_quality,
+                #[cfg(feature = "humidity")]
+                humidity: None,
+            };
+            (sample, expires)
+        });
+        self.door_open = state.door_open;
+        self.power_off = state.power_off;
+        self.aggregator.resume_excursions(state.high_run_seconds, state.low_run_seconds);
+        self.aggregator.resume_door_run(state.door_run_seconds);
+        self.seal();
+        true
+    }
+
+    /// Process one event, passing each record it completes to `store`.
+    /// Events in the same second are applied in the order they arrive.
+    pub fn process_event(&mut self, event: LoggerEvent, store: impl FnMut(AggregationRecord)) -> Result<(), TimestampError> {
+        self.process(event, None, store)
+    }
+
+    /// Process an event numbered in the order it happened, e.g. from a counter shared by the tasks
+    /// that observe events. Within a second events are ordered by `sequence`, so one that arrives
+    /// after a later-numbered one, e.g. a door opening overtaken by its closing, is put back in
+    /// its place. It is rejected only if the second had unnumbered events or more than
+    /// `REORDER_WINDOW` of them. Numbers may wrap around.
+    pub fn process_sequenced(
+        &mut self,
+        event: LoggerEvent,
+        sequence: u32,
+        store: impl FnMut(AggregationRecord),
+    ) -> Result<(), TimestampError> {
+        self.process(event, Some(sequence), store)
+    }
+
+    fn process(&mut self, event: LoggerEvent, sequence: Option<u32>, store: impl FnMut(AggregationRecord)) -> Result<(), TimestampError> {
+        self.check_integrity();
+        let result = self.apply(event, sequence, store);
+        self.seal();
+        result
+    }
+
+    fn apply(&mut self, event: LoggerEvent, sequence: Option<u32>, mut store: impl FnMut(AggregationRecord)) -> Result<(), TimestampError> {
+        let timestamp = event.timestamp();
+        if let LoggerEvent::ClockSet(before, _) = event {
+            // Complete the record at the old time and carry on from the new one.
+            let in_order = before.seconds >= self.now.seconds && before.seconds - self.now.seconds <= MAX_TIME_JUMP_SECONDS;
+            if self.record_start.is_some() && in_order {
+                self.advance(before, &mut store);
+            }
+            self.restart(&mut store);
+        }
+        if self.record_start.is_some() {
+            let same_second = timestamp.seconds == self.now.seconds;
+            let overtaken = matches!((self.last_sequence, sequence), (Some(last), Some(next)) if (next.wrapping_sub(last) as i32) < 0);
+            if same_second
+                && overtaken
+                && let Some(sequence) = sequence
+                && self.reorder(event, sequence)
+            {
+                return Ok(());
+            }
+            if timestamp.seconds < self.now.seconds || (same_second && overtaken) {
+                return Err(TimestampError::OutOfOrder { expected_after: self.now, got: timestamp });
+            }
+            if timestamp.seconds - self.now.seconds > MAX_TIME_JUMP_SECONDS {
+                return Err(TimestampError::TooFarFuture { latest: self.now, got: timestamp });
+            }
+        }
+        if sequence.is_some() {
+            self.last_sequence = sequence;
+        }
+        let new_second = self.record_start.is_none() || timestamp.seconds != self.now.seconds;
+        self.advance(timestamp, &mut store);
+        match sequence {
+            Some(sequence) if !matches!(event, LoggerEvent::ClockSet(..)) => {
+                if new_second {
+                    self.second_start = Some(SecondStart {
+                        aggregator: self.aggregator,
+                        held: self.held,
+                        door_open: self.door_open,
+                        power_off: self.power_off,
+                        pause: self.pause,
+                        events: [None; REORDER_WINDOW],
+                    });
+                }
+                let slot = self.second_start.as_mut().and_then(|start| start.events.iter_mut().find(|slot| slot.is_none()));
+                match slot {
+                    Some(slot) => *slot = Some((sequence, event)),
+                    None => self.second_start = None, // Too many to reorder.
+                }
+            }
+            _ => self.second_start = None,
+        }
+        self.apply_effect(event);
+        Ok(())
+    }
+
+    // Replay the current second from its start with `event` in its place among the numbered
+    // events. Returns false, changing nothing, if they aren't all at hand or there is no room.
+    fn reorder(&mut self, event: LoggerEvent, sequence: u32) -> bool {
+        let Some(start) = &mut self.second_start else {
+            return false;
+        };
+        if start.events[REORDER_WINDOW - 1].is_some() || matches!(event, LoggerEvent::ClockSet(..)) {
+            return false;
+        }
+        let later = |&(other, _): &(u32, LoggerEvent)| (other.wrapping_sub(sequence) as i32) > 0;
+        let at = start.events.iter().position(|slot| slot.as_ref().is_none_or(later)).unwrap_or(REORDER_WINDOW);
+        start.events[at..].rotate_right(1);
+        start.events[at] = Some((sequence, event));
+        let start = *start;
+        self.aggregator = start.aggregator;
+        self.held = start.held;
+        self.door_open = start.door_open;
+        self.power_off = start.power_off;
+        self.pause = start.pause;
+        for (_, event) in start.events.into_iter().flatten() {
+            self.apply_effect(event);
+        }
+        true
+    }
+
+    // Apply what `event` changes at its time, which everything has been integrated up to.
+    fn apply_effect(&mut self, event: LoggerEvent) {
+        let timestamp = event.timestamp();
+        match event {
+            // Readings taken while paused, e.g. during a defrost, aren't logged.
+            LoggerEvent::Sample(_) if self.pause.is_some() => {}
+            LoggerEvent::Sample(sample) => {
+                if self.held.is_none() {
+                    // The readings stopped for a while, so any excursion ended with them.
+                    self.aggregator.end_excursions();
+                }
+                self.aggregator.add_sample(sample.tvc, sample.tamb);
+                self.aggregator.add_quality(sample.tvc_quality, sample.tamb_quality);
+                self.held = Some((sample, timestamp.seconds.saturating_add(self.policy.max_hold_seconds)));
+            }
+            LoggerEvent::DoorOpened(_) if !self.door_open => {
+                self.door_open = true;
+                self.aggregator.door_opened();
+            }
+            LoggerEvent::DoorClosed(_) => {
+                self.door_open = false;
+                self.aggregator.door_closed();
+            }
+            LoggerEvent::PowerLost(_) => self.power_off = true,
+            LoggerEvent::PowerRestored(_) => self.power_off = false,
+            LoggerEvent::Fault(_, code) => self.aggregator.report_error(code),
+            LoggerEvent::Paused(_, reason, seconds) => {
+                // The last reading doesn't stand for the paused time, and an excursion ends with the pause.
+                self.held = None;
+                self.pause = Some((reason, timestamp.seconds.saturating_add(seconds.min(MAX_PAUSE_SECONDS))));
+            }
+            LoggerEvent::Resumed(_) => self.pause = None,
+            LoggerEvent::Moved(_) => self.aggregator.moved(),
+            // Noted in the first record after the change, for analysts comparing the times.
+            LoggerEvent::ClockSet(..) => self.aggregator.report_error(ErrorCode::ClockAnomaly),
+            LoggerEvent::DoorOpened(_) | LoggerEvent::Tick(_) => {}
+        }
+    }
+
+    /// Complete the record in progress, e.g. before shutting down.
+    pub fn flush(&mut self, store: impl FnMut(AggregationRecord)) {
+        self.check_integrity();
+        self.complete(store);
+        self.seal();
+    }
+
+    // Check the aggregator against the checksum taken after the last change. If it was corrupted,
+    // restore the checkpoint, or failing that start the record again, and note the fault in it.
+    // If the aggregator still matches the checkpoint, it is the checksum that was hit.
+    fn check_integrity(&mut self) {
+        let checksum = self.aggregator.checksum();
+        if checksum == self.checksum {
+            return;
+        }
+        self.integrity_faults = self.integrity_faults.saturating_add(1);
+        let checkpoint = self.checkpoint.checksum();
+        if checkpoint == checksum {
+            self.checksum = checksum;
+            return;
+        }
+        if checkpoint == self.checksum {
+            self.aggregator = self.checkpoint;
+        } else {
+            self.aggregator.finalize(self.record_start.unwrap_or(self.now));
+        }
+        self.aggregator.report_error(ErrorCode::StateCorrupted);
+        self.seal();
+    }
+
+    // Take the checksum and checkpoint after a change.
+    fn seal(&mut self) {
+        self.checksum = self.aggregator.checksum();
+        self.checkpoint = self.aggregator;
+    }
+
+    fn complete(&mut self, mut store: impl FnMut(AggregationRecord)) {
+        self.second_start = None; // The record it would go back to is gone.
+        if let Some(start) = self.record_start
+            && !self.aggregator.is_empty()
+        {
+            store(self.aggregator.finalize(start));
+        }
+    }
+
+    fn restart(&mut self, store: impl FnMut(AggregationRecord)) {
+        self.complete(store);
+        // The door and power states still hold, but nothing else spans the jump.
+        self.record_start = None;
+        self.held = None;
+        self.pause = None;
+        self.last_sequence = None;
+        self.aggregator.end_excursions();
+    }
+
+    // Integrate the current state up to `to`, completing records at period boundaries.
+    fn advance(&mut self, to: Timestamp, store: &mut impl FnMut(AggregationRecord)) {
+        let Some(mut record_start) = self.record_start else {
+            self.start_record(self.policy.period_start(to));
+            self.now = to;
+            return;
+        };
+        while self.now.seconds < to.seconds {
+            let boundary = record_start.seconds + self.policy.record_period_seconds;
+            let step_end = to.seconds.min(boundary).min(self.pause.map_or(u32::MAX, |(_, until)| until));
+            if let Some((sample, expires)) = self.held {
+                let covered = step_end.min(expires).saturating_sub(self.now.seconds);
+                if covered > 0 {
+                    self.aggregator.add_held(sample.tvc, sample.tamb, covered);
+                    self.aggregator.add_quality(sample.tvc_quality, sample.tamb_quality);
+                }
+                // Kept until strictly past its expiry, so a reading arriving exactly then continues the series.
+                if expires < step_end {
+                    self.held = None;
+                }
+            }
+            let seconds = step_end - self.now.seconds;
+            if self.door_open {
+                self.aggregator.add_door_open(seconds);
+            }
+            if self.power_off {
+                self.aggregator.add_power_off(seconds);
+            }
+            if let Some((reason, until)) = self.pause {
+                self.aggregator.add_paused(reason, seconds);
+                if until == step_end {
+                    self.pause = None; // Timed out.
+                }
+            }
+            self.now = Timestamp { seconds: step_end };
+            if step_end == boundary {
+                if !self.aggregator.is_empty() {
+                    store(self.aggregator.finalize(Timestamp { seconds: boundary }));
+                }
+                record_start = Timestamp { seconds: boundary };
+                // With nothing to integrate, skip straight to the period containing `to`.
+                let idle = self.held.is_none() && !self.door_open && !self.power_off && self.pause.is_none();
+                if idle && boundary < to.seconds {
+                    record_start = self.policy.period_start(to);
+                    self.now = to;
+                }
+                self.start_record(record_start);
+            }
+        }
+    }
+
+    fn start_record(&mut self, start: Timestamp) {
+        self.record_start = Some(start);
+        self.aggregator.finalize(start);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample;
+    use crate::sample::SampleFlag;
+
+    fn run(events: &[LoggerEvent]) -> Vec<AggregationRecord> {
+        run_with(SamplePolicy::STANDARD, events)
+    }
+
+    fn run_with(policy: SamplePolicy, events: &[LoggerEvent]) -> Vec<AggregationRecord> {
+        let mut logger = Logger::new(policy, AlarmProfile::FRIDGE);
+        let mut records = Vec::new();
+        for event in events {
+            logger.process_event(*event, |record| records.push(record)).unwrap();
+        }
+        logger.flush(|record| records.push(record));
+        records
+    }
+
+    #[test]
+    fn test_records_split_at_boundaries() {
+        let records = run(&[sample(600, 4.0), sample(1200, 6.0), LoggerEvent::Tick(Timestamp { seconds: 1800 })]);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].start.seconds, 0);
+        assert_eq!(records[0].tvc_seconds, 300);
+        assert_eq!(records[0].tvc_integral, 4.0 * 300.0);
+        assert_eq!(records[1].start.seconds, 900);
+        assert_eq!(records[1].tvc_seconds, 900);
+        assert_eq!(records[1].tvc_integral, 4.0 * 300.0 + 6.0 * 600.0);
+        assert_eq!((records[1].tvc_min, records[1].tvc_max), (4.0, 6.0)); // 4 °C held over from the first record.
+    }
+
+    #[test]
+    fn test_gap_limits_hold_and_skips_empty_periods() {
+        let records = run(&[sample(0, 5.0), sample(100_000, 5.0)]);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].tvc_seconds, MAX_HOLD_SECONDS);
+        assert_eq!(records[1].start.seconds, 99_900);
+    }
+
+    #[test]
+    fn test_door_and_power() {
+        let records = run(&[
+            LoggerEvent::DoorOpened(Timestamp { seconds: 800 }),
+            LoggerEvent::DoorOpened(Timestamp { seconds: 850 }),
+            LoggerEvent::DoorClosed(Timestamp { seconds: 1000 }),
+            LoggerEvent::PowerLost(Timestamp { seconds: 1000 }),
+            LoggerEvent::Moved(Timestamp { seconds: 1200 }),
+            LoggerEvent::PowerRestored(Timestamp { seconds: 1600 }),
+        ]);
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].door_openings, records[0].door_open_seconds), (1, 100));
+        assert_eq!((records[0].motion_events, records[1].motion_events), (0, 1));
+        assert_eq!((records[1].door_open_seconds, records[1].power_off_seconds), (100, 600));
+    }
+
+    #[test]
+    fn test_clock_set() {
+        let records = run(&[
+            sample(0, 4.0),
+            LoggerEvent::ClockSet(Timestamp { seconds: 600 }, Timestamp { seconds: 100_000 }),
+            sample(100_050, 5.0),
+            LoggerEvent::ClockSet(Timestamp { seconds: 100_200 }, Timestamp { seconds: 50 }), // Back again.
+            sample(60, 6.0),
+        ]);
+        let starts: Vec<u32> = records.iter().map(|record| record.start.seconds).collect();
+        assert_eq!(starts, [0, 99_900, 0]);
+        assert_eq!((records[0].tvc_seconds, records[1].tvc_seconds), (600, 150));
+        assert_eq!(records[0].logger_errors.iter().count(), 0);
+        assert!(records[1..].iter().all(|record| record.logger_errors.iter().eq([ErrorCode::ClockAnomaly])));
+    }
+
+    #[test]
+    fn test_sample_quality() {
+        let substituted = LoggerEvent::Sample(TemperatureSample {
+            timestamp: Timestamp { seconds: 600 },
+            tamb: 25.0,
+            tvc: 5.0,
+            tamb_quality: SampleFlag::Calibrated.mask(),
+            tvc_quality: SampleFlag::Substituted.mask() | SampleFlag::Calibrated.mask(),
+            #[cfg(feature = "humidity")]
+            humidity: None,
+        });
+        let records = run(&[sample(0, 4.0), substituted, sample(1200, 4.0), LoggerEvent::Tick(Timestamp { seconds: 2700 })]);
+        let flags = |record: &AggregationRecord| (record.tvc_quality, record.tamb_quality);
+        assert_eq!(flags(&records[0]), (0b110, 0b100));
+        assert_eq!(flags(&records[1]), (0b110, 0b100)); // Held over until the next reading.
+        assert_eq!(flags(&records[2]), (0, 0));
+    }
+
+    #[test]
+    fn test_pause_times_out() {
+        let records = run(&[
+            sample(0, 5.0),
+            LoggerEvent::Paused(Timestamp { seconds: 600 }, PauseReason::Defrost, 1200),
+            sample(900, 20.0), // Not logged.
+            sample(2400, 5.0),
+        ]);
+        assert_eq!(records.len(), 3);
+        assert_eq!((records[0].tvc_seconds, records[0].paused_seconds), (600, 300));
+        assert_eq!(records[0].pause_reasons, PauseReason::Defrost.mask());
+        assert_eq!((records[1].tvc_seconds, records[1].paused_seconds, records[1].tvc_max), (0, 900, 0.0));
+        assert_eq!(records[1].pause_reasons, PauseReason::Defrost.mask());
+        assert_eq!((records[2].paused_seconds, records[2].pause_reasons), (0, 0));
+        // Resumed early, and bounded.
+        let records = run(&[
+            LoggerEvent::Paused(Timestamp { seconds: 0 }, PauseReason::Maintenance, 600),
+            LoggerEvent::Resumed(Timestamp { seconds: 300 }),
+            LoggerEvent::Paused(Timestamp { seconds: 900 }, PauseReason::Cleaning, u32::MAX),
+            LoggerEvent::Tick(Timestamp { seconds: 200_000 }),
+        ]);
+        assert_eq!(records[0].paused_seconds, 300);
+        assert_eq!(records.iter().map(|record| record.paused_seconds).sum::<u32>(), 300 + MAX_PAUSE_SECONDS);
+    }
+
+    #[test]
+    fn test_research_policy() {
+        assert!(SamplePolicy::RESEARCH.is_valid());
+        assert!(!SamplePolicy::with_record_period(7 * 60).is_valid());
+        let records = run_with(SamplePolicy::RESEARCH, &[sample(0, 4.0), sample(900, 4.0)]);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].start.seconds, 900);
+        assert_eq!(records[0].tvc_seconds, 300);
+    }
+
+    #[test]
+    fn test_out_of_order_rejected() {
+        let mut logger = Logger::default();
+        logger.process_event(sample(1000, 4.0), |_| {}).unwrap();
+        let expected_after = Timestamp { seconds: 1000 };
+        let got = Timestamp { seconds: 999 };
+        assert_eq!(logger.process_event(sample(999, 4.0), |_| {}), Err(TimestampError::OutOfOrder { expected_after, got }));
+        let got = Timestamp { seconds: 1001 + MAX_TIME_JUMP_SECONDS };
+        let error = logger.process_event(LoggerEvent::Tick(got), |_| {}).unwrap_err();
+        assert_eq!(error, TimestampError::TooFarFuture { latest: expected_after, got });
+        assert_eq!(error.seconds_off(), MAX_TIME_JUMP_SECONDS + 1);
+        // After the clock is set the logger follows it, either way.
+        let mut records = Vec::new();
+        logger.process_event(LoggerEvent::ClockSet(expected_after, got), |record| records.push(record)).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(logger.process_event(LoggerEvent::Tick(got), |_| {}), Ok(()));
+        logger.process_event(LoggerEvent::ClockSet(got, Timestamp { seconds: 5 }), |_| {}).unwrap();
+        assert_eq!(logger.process_event(sample(10, 4.0), |_| {}), Ok(()));
+    }
+
+    #[test]
+    fn test_same_second_sequence() {
+        let at = Timestamp { seconds: 100 };
+        // Opened and closed in the same second: counted once and closed, whichever arrives first.
+        let (opened, closed) = (LoggerEvent::DoorOpened(at), LoggerEvent::DoorClosed(at));
+        for (first, second) in [((0, opened), (1, closed)), ((1, closed), (0, opened))] {
+            let mut logger = Logger::default();
+            let mut records = Vec::new();
+            logger.process_event(sample(0, 4.0), |_| {}).unwrap();
+            logger.process_sequenced(first.1, first.0, |_| {}).unwrap();
+            assert_eq!(logger.process_sequenced(second.1, second.0, |_| {}), Ok(()));
+            logger.process_event(LoggerEvent::Tick(Timestamp { seconds: 900 }), |record| records.push(record)).unwrap();
+            assert_eq!((records[0].door_openings, records[0].door_open_seconds), (1, 0));
+        }
+        // Later seconds win over the sequence, and numbers wrap.
+        let mut logger = Logger::default();
+        logger.process_sequenced(sample(100, 4.0), u32::MAX, |_| {}).unwrap();
+        assert_eq!(logger.process_sequenced(sample(100, 5.0), 0, |_| {}), Ok(()));
+        assert_eq!(logger.process_sequenced(sample(100, 6.0), u32::MAX - 1, |_| {}), Ok(()));
+        assert_eq!(logger.held.map(|(sample, _)| sample.tvc), Some(5.0)); // Still the last numbered.
+        assert_eq!(logger.process_sequenced(sample(101, 4.0), 5, |_| {}), Ok(()));
+        assert_eq!(logger.process_sequenced(sample(102, 4.0), 2, |_| {}), Ok(()));
+        let at = Timestamp { seconds: 102 };
+        // Beyond the window, or after an unnumbered event of the second, a late event is rejected.
+        for sequence in 3..REORDER_WINDOW as u32 + 2 {
+            logger.process_sequenced(LoggerEvent::Tick(at), sequence, |_| {}).unwrap();
+        }
+        assert_eq!(logger.process_sequenced(LoggerEvent::Tick(at), 0, |_| {}), Err(TimestampError::OutOfOrder { expected_after: at, got: at }));
+        let at = Timestamp { seconds: 103 };
+        logger.process_sequenced(LoggerEvent::Tick(at), 10, |_| {}).unwrap();
+        logger.process_event(LoggerEvent::Tick(at), |_| {}).unwrap();
+        assert!(logger.process_sequenced(LoggerEvent::Tick(at), 9, |_| {}).is_err());
+    }
+
+    #[test]
+    fn test_corrupted_state_restored() {
+        let mut logger = Logger::default();
+        let mut records = Vec::new();
+        logger.process_event(sample(0, 4.0), |_| {}).unwrap();
+        logger.process_event(sample(300, 5.0), |_| {}).unwrap();
+        // A stray write changes the record in progress behind the logger's back.
+        logger.aggregator.add_held(40.0, 40.0, 100);
+        logger.process_event(sample(600, 6.0), |_| {}).unwrap();
+        assert_eq!(logger.integrity_faults(), 1);
+        logger.process_event(LoggerEvent::Tick(Timestamp { seconds: 900 }), |record| records.push(record)).unwrap();
+        assert_eq!((records[0].tvc_seconds, records[0].tvc_max), (900, 6.0));
+        assert!(records[0].logger_errors.contains(ErrorCode::StateCorrupted));
+        // A damaged checksum leaves the record alone, as the checkpoint still agrees with it.
+        logger.process_event(LoggerEvent::DoorOpened(Timestamp { seconds: 950 }), |_| {}).unwrap();
+        logger.checksum ^= 1;
+        logger.process_event(sample(1000, 5.0), |_| {}).unwrap();
+        assert_eq!(logger.integrity_faults(), 2);
+        assert!(!logger.aggregator.current().logger_errors.contains(ErrorCode::StateCorrupted));
+        // With the checkpoint damaged too, the record starts again.
+        logger.aggregator.door_opened();
+        logger.checkpoint.add_held(40.0, 40.0, 100);
+        logger.flush(|record| records.push(record));
+        assert_eq!(logger.integrity_faults(), 3);
+        assert_eq!((records[1].start.seconds, records[1].door_openings, records[1].tvc_seconds), (900, 0, 0));
+        assert!(records[1].logger_errors.contains(ErrorCode::StateCorrupted));
+    }
+
+    #[test]
+    fn test_warm_start_keeps_excursion() {
+        // Nine hours at 12 °C, then a reset, then two more hours.
+        let mut logger = Logger::default();
+        for seconds in (0..=9 * 3600).step_by(900) {
+            logger.process_event(sample(seconds, 12.0), |_| {}).unwrap();
+        }
+        logger.process_event(LoggerEvent::DoorOpened(Timestamp { seconds: 9 * 3600 }), |_| {}).unwrap();
+        let state = WarmStart::from_words(&logger.warm_start_state().unwrap().to_words()).unwrap();
+        assert_eq!((state.high_run_seconds, state.hold_until, state.door_open), (9 * 3600, Some(9 * 3600 + 900), true));
+
+        let mut restarted = Logger::default();
+        assert!(restarted.warm_start(&state, Timestamp { seconds: 9 * 3600 + 60 }));
+        let mut records = Vec::new();
+        for seconds in (9 * 3600 + 900..=11 * 3600).step_by(900) {
+            restarted.process_event(sample(seconds, 12.0), |record| records.push(record)).unwrap();
+        }
+        restarted.flush(|record| records.push(record));
+        assert_eq!(records[0].start.seconds, 9 * 3600);
+        assert_eq!(records[0].door_open_seconds, 900); // Still open, but not opened again.
+        assert_eq!(records[0].door_openings, 0);
+        assert_eq!(records.iter().map(|record| record.high_alarm_seconds).sum::<u32>(), 3600);
+        assert_eq!(records.iter().map(|record| record.door_alarm_seconds).sum::<u32>(), 2 * 3600 - 300);
+        assert_eq!(restarted.warm_start_state().unwrap().door_run_seconds, 2 * 3600);
+    }
+
+    #[test]
+    fn test_warm_start_keeps_reading_quality() {
+        let mut logger = Logger::default();
+        let LoggerEvent::Sample(reading) = sample(1000, 5.0) else { unreachable!() };
+        let flagged = TemperatureSample { tvc_quality: SampleFlag::Retried.mask(), tamb_quality: SampleFlag::Substituted.mask(), ..reading };
+        logger.process_event(LoggerEvent::Sample(flagged), |_| {}).unwrap();
+        let state = WarmStart::from_words(&logger.warm_start_state().unwrap().to_words()).unwrap();
+        let mut restarted = Logger::default();
+        assert!(restarted.warm_start(&state, Timestamp { seconds: 1060 }));
+        let mut records = Vec::new();
+        restarted.process_event(LoggerEvent::Tick(Timestamp { seconds: 1800 }), |record| records.push(record)).unwrap();
+        // The held reading still counts with its flags after the reset.
+        assert_eq!((records[0].tvc_quality, records[0].tamb_quality), (SampleFlag::Retried.mask().into(), SampleFlag::Substituted.mask().into()));
+    }
+
+    #[test]
+    fn test_warm_start_rejects_stale_state() {
+        let mut logger = Logger::default();
+        assert_eq!(logger.warm_start_state(), None);
+        logger.process_event(sample(1000, 12.0), |_| {}).unwrap();
+        let state = logger.warm_start_state().unwrap();
+        let mut restarted = Logger::default();
+        assert!(!restarted.warm_start(&state, Timestamp { seconds: 1000 + MAX_HOLD_SECONDS + 1 }));
+        assert!(!restarted.warm_start(&state, Timestamp { seconds: 999 }));
+        assert_eq!(restarted.warm_start_state(), None);
+        let mut words = state.to_words();
+        words[4] ^= 1;
+        assert_eq!(WarmStart::from_words(&words), None);
+    }
+
+    #[test]
+    fn test_next_deadline() {
+        let at = |seconds| Timestamp { seconds };
+        let mut logger = Logger::default();
+        assert_eq!(logger.next_deadline(at(1000)), Deadline { at: at(1800), kind: DeadlineKind::RecordBoundary });
+        assert_eq!(logger.next_deadline(at(86_000)), Deadline { at: at(86_400), kind: DeadlineKind::DayBoundary });
+        logger.schedule_maintenance(Some(at(1200)));
+        assert_eq!(logger.next_deadline(at(1000)), Deadline { at: at(1200), kind: DeadlineKind::StorageMaintenance });
+        logger.schedule_maintenance(None);
+
+        // A high excursion 9 h 50 min long reaches the 10 h delay within the reading's hold.
+        let start = 36_000;
+        let state = WarmStart {
+            at: at(start),
+            tvc: 12.0,
+            tamb: 25.0,
+            tvc_quality: 0,
+            tamb_quality: 0,
+            hold_until: Some(start + 900),
+            high_run_seconds: 9 * 3600 + 50 * 60,
+            low_run_seconds: 0,
+            door_run_seconds: 0,
+            door_open: false,
+            power_off: false,
+        };
+        assert!(logger.warm_start(&state, at(start)));
+        assert_eq!(logger.next_deadline(at(start)), Deadline { at: at(start + 600), kind: DeadlineKind::AlarmDelay });
+        // A reading in range ends the excursion.
+        logger.process_event(sample(start + 60, 5.0), |_| {}).unwrap();
+        assert_eq!(logger.next_deadline(at(start + 60)).kind, DeadlineKind::RecordBoundary);
+    }
+}
@@ -0,0 +1,519 @@
+use core::fmt::{self, Write};
+use core::future::Future;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::aggregator::AggregationRecord;
+use crate::alarm::{AlarmKind, AlarmProfile};
+use crate::console::LoggerCommand;
+use crate::debug::{write_aggregator_state, DebugCommand};
+use crate::dispatch::{Dispatcher, Lane};
+use crate::event_queue::{Overflow, Queued};
+use crate::lifecycle::LifecycleState;
+use crate::log::{Log, LogCode};
+use crate::logger::{Deadline, Logger, LoggerEvent, WarmStart};
+use crate::storage::StorageReport;
+use crate::store::{ChainedRecord, RamStore, RecordStore};
+use crate::timestamp::{Timestamp, TimestampError};
+use crate::watch::Watch;
+
+/// A logger event with the number it was given when it was queued, so the logger can put back
+/// in order events that overtake each other on the way, see `Logger::process_sequenced`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Numbered {
+    pub sequence: u32,
+    pub event: LoggerEvent,
+}
+
+impl Queued for Numbered {
+    fn overflow(&self) -> Overflow {
+        self.event.overflow()
+    }
+}
+
+/// The counter shared by everything that queues events for the logger task.
+#[derive(Debug, Default)]
+pub struct EventNumbers(AtomicU32);
+
+impl EventNumbers {
+    pub const fn new() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    /// Give `event` the next number.
+    pub fn number(&self, event: LoggerEvent) -> Numbered {
+        Numbered { sequence: self.0.fetch_add(1, Ordering::Relaxed), event }
+    }
+}
+
+/// Where the logger task gets its events, e.g. an embassy channel.
+pub trait EventSource {
+    /// Wait for the next event. None once there will be no more, e.g. at the end of a replayed log.
+    fn receive(&mut self) -> impl Future<Output = Option<Numbered>>;
+}
+
+/// Outputs for the alarms the logger task raises, e.g. the annunciator.
+pub trait AlarmOutput {
+    /// An alarm of `kind` became active or cleared.
+    fn set_active(&mut self, kind: AlarmKind, active: bool) -> impl Future<Output = ()>;
+}
+
+/// Told what the logger task does as it happens, e.g. for the console's `watch` mode.
+/// `()` is told nothing. The hook is the task's rather than `Logger`'s, since records are only
+/// complete once they are stored.
+pub trait Subscriber {
+    /// The logger accepted `event`.
+    fn event(&mut self, _event: &LoggerEvent) {}
+
+    /// A record was completed and stored.
+    fn record(&mut self, _record: &AggregationRecord) {}
+}
+
+impl Subscriber for () {}
+
+/// Background work on the store, done a step at a time between events, e.g. a record download
+/// or flash maintenance.
+pub trait BulkJob<S> {
+    /// Do one short step. Returns true once the job is finished.
+    fn step(&mut self, store: &mut S, log: &mut impl Log) -> bool;
+}
+
+/// Merge old records into daily summaries with `RamStore::compact`, in one step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compaction {
+    pub now: Timestamp,
+    pub age_days: u32,
+}
+
+impl<const N: usize> BulkJob<RamStore<N>> for Compaction {
+    fn step(&mut self, store: &mut RamStore<N>, log: &mut impl Log) -> bool {
+        store.compact(self.now, self.age_days, log);
+        true
+    }
+}
+
+/// Work for `LoggerTask::dispatch`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoggerWork<J> {
+    Event(Numbered),
+    Job(J),
+}
+
+impl<J> LoggerWork<J> {
+    /// The lane to queue this in: events drive the alarms, so they go ahead of jobs.
+    pub fn lane(&self) -> Lane {
+        match self {
+            LoggerWork::Event(_) => Lane::Urgent,
+            LoggerWork::Job(_) => Lane::Bulk,
+        }
+    }
+}
+
+/// The logging pipeline: events in, completed records into the store, temperature alarms out.
+///
+/// Records are completed as time passes, so the caller should send a `LoggerEvent::Tick` at
+/// `next_deadline` when nothing else happens.
+///
+/// Only the logging pipeline runs here. The `DeviceTask` turns the inputs into `LoggerEvent`s
+/// and drives the display, UI, power handling and the alarm outputs.
+pub struct LoggerTask<S> {
+    logger: Logger,
+    profile: AlarmProfile,
+    lifecycle: LifecycleState,
+    store: S,
+    high: bool, // Whether the last recorded reading was above the profile.
+    freeze: bool, // Whether the last recorded reading was at or below the profile.
+    next_unsent: u32, // Sequence number of the first record not yet handed to the storage task.
+    saved: Option<u32>, // Sequence number of the last record the storage task saved.
+}
+
+impl<S: RecordStore> LoggerTask<S> {
+    pub fn new(logger: Logger, profile: AlarmProfile, lifecycle: LifecycleState, store: S) -> Self {
+        Self { logger, profile, lifecycle, store, high: false, freeze: false, next_unsent: 0, saved: None }
+    }
+
+    /// The task with the temperature alarms active before a reset, restored from `AlarmState`,
+    /// so the first reading clears them if they have ended.
+    pub fn with_alarms(self, high: bool, freeze: bool) -> Self {
+        Self { high, freeze, ..self }
+    }
+
+    /// The task carrying on after record `through`, already saved to flash before a reset.
+    pub fn with_saved_through(self, through: u32) -> Self {
+        Self { saved: Some(through), next_unsent: through.wrapping_add(1), ..self }
+    }
+
+    /// Follow a lifecycle change. Events the new state doesn't record are dropped from then on.
+    pub fn set_lifecycle(&mut self, lifecycle: LifecycleState) {
+        self.lifecycle = lifecycle;
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// The logger, for inspection, e.g. by the `debug agg` console command.
+    pub fn logger(&self) -> &Logger {
+        &self.logger
+    }
+
+    /// Carry out a console command, answering on `out`: `debug agg` with the logger's state, and
+    /// `watch` by starting or stopping `watch`.
+    pub fn command<W: Write>(&self, command: LoggerCommand, watch: &mut Watch<W>, out: &mut impl Write) -> fmt::Result {
+        match command {
+            LoggerCommand::Debug(DebugCommand::Aggregator) => write_aggregator_state(out, &self.logger),
+            LoggerCommand::Watch(command) => {
+                watch.command(command);
+                writeln!(out, "ok")
+            }
+        }
+    }
+
+    /// Hand the completed records not yet sent to the storage task to `send`, oldest first,
+    /// stopping early if it returns false, e.g. because the storage task's channel is full.
+    pub fn send_unsaved(&mut self, mut send: impl FnMut(ChainedRecord) -> bool) {
+        let next_unsent = self.next_unsent;
+        for chained in self.store.iter_chained().filter(|chained| chained.sequence.wrapping_sub(next_unsent) as i32 >= 0) {
+            if !send(chained) {
+                return;
+            }
+            self.next_unsent = chained.sequence.wrapping_add(1);
+        }
+    }
+
+    /// Follow a report from the storage task.
+    pub fn storage_report(&mut self, report: StorageReport, log: &mut impl Log) {
+        match report {
+            StorageReport::Saved { through } => {
+                self.saved = Some(through);
+                log.info(LogCode::RecordsSaved, through);
+            }
+            StorageReport::Failed { first, .. } => {
+                // Send the records again, to go in the storage task's next page.
+                if first.wrapping_sub(self.next_unsent) as i32 <= 0 {
+                    self.next_unsent = first;
+                }
+                log.error(LogCode::StorageFailed, first);
+            }
+        }
+    }
+
+    /// Sequence number of the last record saved to flash, if any.
+    pub fn saved_through(&self) -> Option<u32> {
+        self.saved
+    }
+
+    /// The nearest time the logger needs to be woken, see `Logger::next_deadline`.
+    pub fn next_deadline(&self, now: Timestamp) -> Deadline {
+        self.logger.next_deadline(now)
+    }
+
+    /// The logger's state to save after each event, see `Logger::warm_start_state`.
+    pub fn warm_start_state(&self) -> Option<WarmStart> {
+        self.logger.warm_start_state()
+    }
+
+    /// Handle events until the source runs dry, then complete the record in progress and
+    /// return it, if there was one.
+    pub async fn run(&mut self, source: &mut impl EventSource, alarms: &mut impl AlarmOutput, log: &mut impl Log) -> Option<AggregationRecord> {
+        while let Some(event) = source.receive().await {
+            self.handle(event, alarms, log).await;
+        }
+        self.flush(log)
+    }
+
+    /// Handle one event: aggregate it, store the records it completes and update the alarms.
+    pub async fn handle(&mut self, event: Numbered, alarms: &mut impl AlarmOutput, log: &mut impl Log) {
+        self.handle_watched(event, alarms, log, &mut ()).await;
+    }
+
+    /// `handle`, telling `subscriber` about the event if the logger accepts it, and about the
+    /// records it completes.
+    pub async fn handle_watched(&mut self, numbered: Numbered, alarms: &mut impl AlarmOutput, log: &mut impl Log, subscriber: &mut impl Subscriber) {
+        let event = numbered.event;
+        if !self.lifecycle.records(&event) {
+            return;
+        }
+        let store = &mut self.store;
+        let faults = self.logger.integrity_faults();
+        let result = self.logger.process_sequenced(event, numbered.sequence, |record| {
+            log.info(LogCode::RecordStored, record.start.seconds);
+            store.append(record);
+            subscriber.record(&record);
+        });
+        if self.logger.integrity_faults() != faults {
+            log.error(LogCode::StateCorrupted, self.logger.integrity_faults());
+        }
+        if let Err(error) = result {
+            let code = match error {
+                TimestampError::TooFarFuture { .. } => LogCode::EventTooFarAhead,
+                _ => LogCode::EventOutOfOrder,
+            };
+            log.warn(code, error.seconds_off());
+            return;
+        }
+        subscriber.event(&event);
+        if let LoggerEvent::Sample(sample) = event {
+            let (high, freeze) = (self.profile.is_high(sample.tvc), self.profile.is_low(sample.tvc));
+            if high != self.high {
+                self.high = high;
+                alarms.set_active(AlarmKind::HighTemp, high).await;
+            }
+            if freeze != self.freeze {
+                self.freeze = freeze;
+                alarms.set_active(AlarmKind::Freeze, freeze).await;
+            }
+        }
+    }
+
+    /// Do the next piece of work from `dispatcher`, putting an unfinished job back at the end of
+    /// its lane. Returns false if there was nothing to do.
+    pub async fn dispatch<J: BulkJob<S>, const N: usize>(
+        &mut self,
+        dispatcher: &mut Dispatcher<LoggerWork<J>, N>,
+        alarms: &mut impl AlarmOutput,
+        log: &mut impl Log,
+    ) -> bool {
+        self.dispatch_watched(dispatcher, alarms, log, &mut ()).await
+    }
+
+    /// `dispatch`, handling events with `handle_watched`.
+    pub async fn dispatch_watched<J: BulkJob<S>, const N: usize>(
+        &mut self,
+        dispatcher: &mut Dispatcher<LoggerWork<J>, N>,
+        alarms: &mut impl AlarmOutput,
+        log: &mut impl Log,
+        subscriber: &mut impl Subscriber,
+    ) -> bool {
+        let Some((_, work)) = dispatcher.pop() else {
+            return false;
+        };
+        match work {
+            LoggerWork::Event(event) => self.handle_watched(event, alarms, log, subscriber).await,
+            LoggerWork::Job(mut job) => {
+                if !job.step(&mut self.store, log) {
+                    // There is room: the job was just taken from the lane.
+                    let _ = dispatcher.push(Lane::Bulk, LoggerWork::Job(job));
+                }
+            }
+        }
+        true
+    }
+
+    /// Store the record in progress, e.g. before shutting down, and return it if there was one.
+    pub fn flush(&mut self, log: &mut impl Log) -> Option<AggregationRecord> {
+        let store = &mut self.store;
+        let mut flushed = None;
+        self.logger.flush(|record| {
+            log.info(LogCode::RecordStored, record.start.seconds);
+            store.append(record);
+            flushed = Some(record);
+        });
+        flushed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample;
+    use crate::dispatch::MAX_URGENT_RUN;
+    use crate::log::{CaptureLog, Level};
+    use crate::store::{RamStore, RecordChain};
+    use crate::timestamp::Timestamp;
+    use crate::watch::WatchCommand;
+    use embassy_futures::block_on;
+
+    static NUMBERS: EventNumbers = EventNumbers::new();
+
+    fn numbered(event: LoggerEvent) -> Numbered {
+        NUMBERS.number(event)
+    }
+
+    struct Replay(std::vec::IntoIter<LoggerEvent>);
+
+    impl EventSource for Replay {
+        async fn receive(&mut self) -> Option<Numbered> {
+            self.0.next().map(numbered)
+        }
+    }
+
+    #[derive(Default)]
+    struct Alarms(Vec<(AlarmKind, bool)>);
+
+    impl AlarmOutput for Alarms {
+        async fn set_active(&mut self, kind: AlarmKind, active: bool) {
+            self.0.push((kind, active));
+        }
+    }
+
+    fn task() -> LoggerTask<RamStore<8>> {
+        LoggerTask::new(Logger::default(), AlarmProfile::FRIDGE, LifecycleState::Logging, RamStore::new())
+    }
+
+    #[test]
+    fn test_run_stores_records_and_raises_alarms() {
+        let mut task = task();
+        let events = vec![sample(0, 4.0), sample(600, 9.0), sample(1200, 5.0), LoggerEvent::Tick(Timestamp { seconds: 1500 })];
+        let (mut alarms, mut log) = (Alarms::default(), CaptureLog::default());
+        let flushed = block_on(task.run(&mut Replay(events.into_iter()), &mut alarms, &mut log));
+        assert_eq!(flushed.map(|record| record.start.seconds), Some(900));
+        assert_eq!(task.flush(&mut log), None); // Nothing since.
+        assert_eq!(alarms.0, [(AlarmKind::HighTemp, true), (AlarmKind::HighTemp, false)]);
+        let starts: Vec<u32> = task.store().iter().map(|record| record.start.seconds).collect();
+        assert_eq!(starts, [0, 900]); // The second one flushed at the end.
+        assert_eq!(log.entries[0], (Level::Info, LogCode::RecordStored, 0));
+    }
+
+    #[test]
+    fn test_restored_alarms_clear() {
+        let mut task = task().with_alarms(true, false);
+        let (mut alarms, mut log) = (Alarms::default(), CaptureLog::default());
+        block_on(task.handle(numbered(sample(0, 9.0)), &mut alarms, &mut log));
+        assert!(alarms.0.is_empty()); // Still high, nothing to say.
+        block_on(task.handle(numbered(sample(60, 5.0)), &mut alarms, &mut log));
+        assert_eq!(alarms.0, [(AlarmKind::HighTemp, false)]);
+    }
+
+    // Reads the store a record at a time, like a download.
+    #[derive(Debug)]
+    struct Download {
+        next: usize,
+        read: Vec<u32>,
+    }
+
+    impl<S: RecordStore> BulkJob<S> for &mut Download {
+        fn step(&mut self, store: &mut S, _log: &mut impl Log) -> bool {
+            if let Some(record) = store.get(self.next) {
+                self.read.push(record.start.seconds);
+                self.next += 1;
+            }
+            self.next >= store.len()
+        }
+    }
+
+    #[test]
+    fn test_dispatch_events_ahead_of_jobs() {
+        let mut task = task();
+        let (mut alarms, mut log) = (Alarms::default(), CaptureLog::default());
+        for seconds in [0, 900, 1800] {
+            block_on(task.handle(numbered(sample(seconds, 5.0)), &mut alarms, &mut log));
+        }
+        let mut download = Download { next: 0, read: Vec::new() };
+        let mut dispatcher: Dispatcher<LoggerWork<&mut Download>, 4> = Dispatcher::new();
+        dispatcher.push(Lane::Bulk, LoggerWork::Job(&mut download)).unwrap();
+        // A steady stream of events, the first of them raising an alarm.
+        let mut served = 0;
+        for seconds in (2400..).step_by(60).take(3 * MAX_URGENT_RUN as usize) {
+            let event = LoggerWork::Event(numbered(sample(seconds, 9.0)));
+            dispatcher.push(event.lane(), event).unwrap();
+            assert!(block_on(task.dispatch(&mut dispatcher, &mut alarms, &mut log)));
+            served += 1;
+            if served == 1 {
+                assert_eq!(alarms.0, [(AlarmKind::HighTemp, true)]);
+            }
+        }
+        while block_on(task.dispatch(&mut dispatcher, &mut alarms, &mut log)) {}
+        // The download went on between the events, and read the records they completed too.
+        assert!(dispatcher.is_empty());
+        drop(dispatcher);
+        assert_eq!(download.read, [0, 900, 1800, 2700]);
+    }
+
+    #[test]
+    fn test_lifecycle_and_order() {
+        let mut task = task();
+        let (mut alarms, mut log) = (Alarms::default(), CaptureLog::default());
+        task.set_lifecycle(LifecycleState::Storage);
+        block_on(task.handle(numbered(sample(0, -5.0)), &mut alarms, &mut log));
+        assert!(alarms.0.is_empty());
+        task.set_lifecycle(LifecycleState::Logging);
+        block_on(task.handle(numbered(sample(1000, -5.0)), &mut alarms, &mut log));
+        block_on(task.handle(numbered(sample(900, 4.0)), &mut alarms, &mut log));
+        assert_eq!(alarms.0, [(AlarmKind::Freeze, true)]);
+        assert_eq!(log.entries, [(Level::Warn, LogCode::EventOutOfOrder, 100)]);
+    }
+
+    #[test]
+    fn test_overtaken_events_put_back_in_order() {
+        let mut task = task();
+        let (mut alarms, mut log) = (Alarms::default(), CaptureLog::default());
+        block_on(task.handle(numbered(sample(0, 5.0)), &mut alarms, &mut log));
+        // The door opened and closed in the same second, but the closing arrived first.
+        let opened = numbered(LoggerEvent::DoorOpened(Timestamp { seconds: 100 }));
+        let closed = numbered(LoggerEvent::DoorClosed(Timestamp { seconds: 100 }));
+        block_on(task.handle(closed, &mut alarms, &mut log));
+        block_on(task.handle(opened, &mut alarms, &mut log));
+        assert!(log.entries.is_empty());
+        block_on(task.handle(numbered(LoggerEvent::Tick(Timestamp { seconds: 900 })), &mut alarms, &mut log));
+        let record = task.store().iter().next().unwrap();
+        assert_eq!(record.door_openings, 1);
+        assert_eq!(record.door_open_seconds, 0); // Closed again straight away.
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut task = task();
+        let (mut alarms, mut log) = (Alarms::default(), CaptureLog::default());
+        let mut watch = Watch::new(String::new());
+        let mut out = String::new();
+        task.command(LoggerCommand::Watch(WatchCommand::Start), &mut watch, &mut out).unwrap();
+        assert_eq!(out, "ok\n");
+        let mut dispatcher: Dispatcher<LoggerWork<Compaction>, 4> = Dispatcher::new();
+        dispatcher.push(Lane::Urgent, LoggerWork::Event(numbered(sample(0, 5.0)))).unwrap();
+        assert!(block_on(task.dispatch_watched(&mut dispatcher, &mut alarms, &mut log, &mut watch)));
+        assert!(watch.out_mut().starts_with("sample at=0 "));
+        out.clear();
+        task.command(LoggerCommand::Debug(DebugCommand::Aggregator), &mut watch, &mut out).unwrap();
+        assert!(out.starts_with("logger status="));
+    }
+
+    #[test]
+    fn test_records_handed_to_storage() {
+        let mut task = task();
+        let (mut alarms, mut log) = (Alarms::default(), CaptureLog::default());
+        for seconds in [0, 900, 1800, 2700] {
+            block_on(task.handle(numbered(sample(seconds, 5.0)), &mut alarms, &mut log));
+        }
+        // Three records completed; the channel takes two, then the last on the next try.
+        let mut sent = Vec::new();
+        task.send_unsaved(|chained| sent.len() < 2 && {
+            sent.push(chained.sequence);
+            true
+        });
+        task.send_unsaved(|chained| {
+            sent.push(chained.sequence);
+            true
+        });
+        task.send_unsaved(|_| panic!("sent twice"));
+        assert_eq!(sent, [0, 1, 2]);
+        task.storage_report(StorageReport::Saved { through: 1 }, &mut log);
+        task.storage_report(StorageReport::Failed { first: 1, last: 2, error: crate::storage::StorageError::Program }, &mut log);
+        assert_eq!(task.saved_through(), Some(1));
+        assert_eq!(log.entries[3..], [(Level::Info, LogCode::RecordsSaved, 1), (Level::Error, LogCode::StorageFailed, 1)]);
+        // The failed records are sent again.
+        sent.clear();
+        task.send_unsaved(|chained| {
+            sent.push(chained.sequence);
+            true
+        });
+        assert_eq!(sent, [1, 2]);
+    }
+
+    #[test]
+    fn test_carries_on_after_saved_records() {
+        let saved = RecordChain { next_sequence: 5, last_hash: 0 }.link(AggregationRecord::new(Timestamp { seconds: 0 }));
+        let store = RamStore::<8>::new().with_chain(RecordChain::after(&saved));
+        let mut task = LoggerTask::new(Logger::default(), AlarmProfile::FRIDGE, LifecycleState::Logging, store).with_saved_through(saved.sequence);
+        let (mut alarms, mut log) = (Alarms::default(), CaptureLog::default());
+        for seconds in [0, 900] {
+            block_on(task.handle(numbered(sample(seconds, 5.0)), &mut alarms, &mut log));
+        }
+        let mut sent = Vec::new();
+        task.send_unsaved(|chained| {
+            sent.push((chained.sequence, chained.previous_hash));
+            true
+        });
+        assert_eq!(sent, [(6, saved.hash())]);
+        assert_eq!(task.saved_through(), Some(5));
+    }
+}
@@ -0,0 +1,189 @@
+use crate::stats::MinMaxAvg;
+
+/// Full-scale reading of the 12-bit ADC.
+pub const ADC_FULL_SCALE: u16 = 4095;
+/// ADC reference voltage.
+pub const DEFAULT_VREF_VOLTS: f32 = 3.3;
+/// Supply volts per volt at the ADC pin (divider of 100k over 47k).
+pub const DEFAULT_DIVIDER_RATIO: f32 = 147.0 / 47.0;
+/// Below this the mains-derived supply is browned out.
+pub const DEFAULT_BROWNOUT_VOLTS: f32 = 4.5;
+/// Below this the mains-derived supply is considered absent.
+pub const DEFAULT_OUTAGE_VOLTS: f32 = 1.0;
+/// Time the mains-present input must hold a new level before the change is accepted, several
+/// mains cycles so the detector's ripple doesn't count.
+pub const MAINS_SETTLE_MS: u64 = 100;
+/// Time between mains supply voltage readings.
+pub const MAINS_SAMPLE_SECONDS: u32 = 10;
+
+/// Accepted changes of the mains-present input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerEvent {
+    On,
+    Off,
+}
+
+/// Debounces the mains-present input, which is read on every edge rather than polled.
+///
+/// A new level only counts once it has held for `MAINS_SETTLE_MS`, so contact bounce and short
+/// glitches, e.g. from a compressor starting, are ignored. An accepted change is timed at the
+/// edge that started it, not when it settled, so records get the time the mains actually went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MainsPresence {
+    present: Option<bool>, // Accepted level. None until the first one settles.
+    candidate: Option<(bool, u64)>, // A different level, and when it appeared, in ms.
+    glitches: u32,
+}
+
+impl MainsPresence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The input read `present` at `at_ms`, on the monotonic timer. Call after every edge.
+    pub fn input(&mut self, present: bool, at_ms: u64) {
+        if self.present == Some(present) {
+            // Back to the accepted level before the other one settled.
+            if self.candidate.take().is_some() {
+                self.glitches = self.glitches.saturating_add(1);
+            }
+        } else if self.candidate.is_none_or(|(level, _)| level != present) {
+            self.candidate = Some((present, at_ms));
+        }
+    }
+
+    /// A change that has settled by `now_ms`, with the time its edge came in ms.
+    pub fn poll(&mut self, now_ms: u64) -> Option<(PowerEvent, u64)> {
+        let (level, since) = self.candidate.filter(|&(_, since)| now_ms.saturating_sub(since) >= MAINS_SETTLE_MS)?;
+        self.present = Some(level);
+        self.candidate = None;
+        Some((if level { PowerEvent::On } else { PowerEvent::Off }, since))
+    }
+
+    /// When a change will have settled if the input holds, to wake `poll` at.
+    pub fn settles_at_ms(&self) -> Option<u64> {
+        self.candidate.map(|(_, since)| since + MAINS_SETTLE_MS)
+    }
+
+    /// Whether mains is present, once known.
+    pub fn is_present(&self) -> Option<bool> {
+        self.present
+    }
+
+    /// Changes that didn't last long enough to count.
+    pub fn glitches(&self) -> u32 {
+        self.glitches
+    }
+}
+
+/// Classification of a single mains supply reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MainsState {
+    Normal,
+    Brownout,
+    Outage,
+}
+
+/// Converts ADC readings of the mains-derived supply to volts, classifies them,
+/// and keeps min/avg/max statistics for the current record.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MainsMonitor {
+    pub vref_volts: f32,
+    pub divider_ratio: f32,
+    pub brownout_volts: f32,
+    pub outage_volts: f32,
+    stats: MinMaxAvg,
+}
+
+impl Default for MainsMonitor {
+    fn default() -> Self {
+        Self {
+            vref_volts: DEFAULT_VREF_VOLTS,
+            divider_ratio: DEFAULT_DIVIDER_RATIO,
+            brownout_volts: DEFAULT_BROWNOUT_VOLTS,
+            outage_volts: DEFAULT_OUTAGE_VOLTS,
+            stats: MinMaxAvg::new(),
+        }
+    }
+}
+
+impl MainsMonitor {
+    /// Convert a raw ADC reading to the supply voltage before the divider.
+    pub fn adc_to_volts(&self, raw: u16) -> f32 {
+        f32::from(raw.min(ADC_FULL_SCALE)) * self.vref_volts / f32::from(ADC_FULL_SCALE) * self.divider_ratio
+    }
+
+    pub fn classify(&self, volts: f32) -> MainsState {
+        if volts < self.outage_volts {
+            MainsState::Outage
+        } else if volts < self.brownout_volts {
+            MainsState::Brownout
+        } else {
+            MainsState::Normal
+        }
+    }
+
+    /// Add a raw ADC reading to the statistics and return its classification.
+    pub fn add_reading(&mut self, raw: u16) -> MainsState {
+        let volts = self.adc_to_volts(raw);
+        self.stats.add(volts);
+        self.classify(volts)
+    }
+
+    /// Supply voltage statistics since the last reset.
+    pub fn stats(&self) -> &MinMaxAvg {
+        &self.stats
+    }
+
+    /// Clear the statistics at the start of a new record.
+    pub fn reset(&mut self) {
+        self.stats.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adc_to_volts() {
+        let monitor = MainsMonitor { divider_ratio: 2.0, ..Default::default() };
+        assert_eq!(monitor.adc_to_volts(0), 0.0);
+        assert!((monitor.adc_to_volts(ADC_FULL_SCALE) - 6.6).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_classify_and_stats() {
+        let mut monitor = MainsMonitor { divider_ratio: 2.0, ..Default::default() };
+        assert_eq!(monitor.add_reading(3102), MainsState::Normal); // ~5.0 V
+        assert_eq!(monitor.add_reading(2482), MainsState::Brownout); // ~4.0 V
+        assert_eq!(monitor.add_reading(10), MainsState::Outage);
+        assert_eq!(monitor.stats().count(), 3);
+        assert!((monitor.stats().max().unwrap() - 5.0).abs() < 0.01);
+        monitor.reset();
+        assert_eq!(monitor.stats().count(), 0);
+    }
+
+    #[test]
+    fn test_presence_filter() {
+        let mut presence = MainsPresence::new();
+        presence.input(true, 0);
+        assert_eq!(presence.poll(99), None);
+        assert_eq!(presence.settles_at_ms(), Some(100));
+        assert_eq!(presence.poll(100), Some((PowerEvent::On, 0)));
+        assert_eq!(presence.settles_at_ms(), None);
+        // A dropout shorter than the settle time is a glitch.
+        presence.input(false, 1000);
+        presence.input(true, 1040);
+        assert_eq!(presence.poll(2000), None);
+        assert_eq!(presence.glitches(), 1);
+        // A bouncing edge is timed from the start of the level that held.
+        presence.input(false, 5000);
+        presence.input(false, 5010);
+        assert_eq!(presence.poll(5050), None);
+        assert_eq!(presence.poll(5100), Some((PowerEvent::Off, 5000)));
+        assert_eq!(presence.is_present(), Some(false));
+    }
+}
@@ -0,0 +1,161 @@
+use arrayvec::{ArrayString, ArrayVec};
+
+use crate::log::{Log, LogCode};
+use crate::timestamp::Timestamp;
+
+/// Longest operator note, in characters.
+pub const NOTE_LEN: usize = 32;
+/// Notes kept, as many as one NV value holds. A new one replaces the oldest.
+pub const MAX_NOTES: usize = 6;
+/// Length of `NoteLog::to_bytes`.
+pub const NOTE_LOG_LEN: usize = 4 + 1 + MAX_NOTES * NOTE_ENTRY_LEN;
+const NOTE_ENTRY_LEN: usize = 4 + 1 + NOTE_LEN; // Time, text length, text padded with zeros.
+
+/// Why a note was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NoteError {
+    Empty,
+    TooLong, // More than `NOTE_LEN` characters.
+    InvalidCharacter, // Not printable ASCII, or a double quote, which exports couldn't carry.
+}
+
+/// A short remark from an operator, e.g. "defrost performed", explaining what the records show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    pub timestamp: Timestamp,
+    pub text: ArrayString<NOTE_LEN>,
+}
+
+/// The latest `MAX_NOTES` operator notes, for the event log and exports.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NoteLog {
+    notes: ArrayVec<Note, MAX_NOTES>, // Oldest first.
+    dropped: u32, // Notes replaced by newer ones.
+}
+
+impl NoteLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a note made at `timestamp`, logging `NoteAdded`. Surrounding spaces are trimmed.
+    pub fn add(&mut self, timestamp: Timestamp, text: &str, log: &mut impl Log) -> Result<(), NoteError> {
+        let text = validate(text)?;
+        if self.notes.is_full() {
+            self.notes.remove(0);
+            self.dropped = self.dropped.saturating_add(1);
+        }
+        self.notes.push(Note { timestamp, text });
+        log.info(LogCode::NoteAdded, timestamp.seconds);
+        Ok(())
+    }
+
+    /// Notes made in `start..end`, e.g. a report's period, oldest first.
+    pub fn between(&self, start: Timestamp, end: Timestamp) -> impl Iterator<Item = &Note> {
+        self.notes.iter().filter(move |note| (start.seconds..end.seconds).contains(&note.timestamp.seconds))
+    }
+
+    /// All notes kept, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Note> {
+        self.notes.iter()
+    }
+
+    /// Notes that were replaced by newer ones, so an export can say it is incomplete.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// The notes for the NV store: the dropped count, the number of notes, then each note.
+    pub fn to_bytes(&self) -> [u8; NOTE_LOG_LEN] {
+        let mut bytes = [0u8; NOTE_LOG_LEN];
+        bytes[0..4].copy_from_slice(&self.dropped.to_le_bytes());
+        bytes[4] = self.notes.len() as u8;
+        for (note, entry) in self.notes.iter().zip(bytes[5..].chunks_exact_mut(NOTE_ENTRY_LEN)) {
+            entry[0..4].copy_from_slice(&note.timestamp.seconds.to_le_bytes());
+            entry[4] = note.text.len() as u8;
+            entry[5..5 + note.text.len()].copy_from_slice(note.text.as_bytes());
+        }
+        bytes
+    }
+
+    /// Notes saved with `to_bytes`. None if the bytes don't hold valid notes.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; NOTE_LOG_LEN] = bytes.try_into().ok()?;
+        let count = usize::from(bytes[4]);
+        if count > MAX_NOTES {
+            return None;
+        }
+        let mut log = Self { notes: ArrayVec::new(), dropped: u32::from_le_bytes(bytes[0..4].try_into().ok()?) };
+        for entry in bytes[5..].chunks_exact(NOTE_ENTRY_LEN).take(count) {
+            let text = core::str::from_utf8(entry.get(5..5 + usize::from(entry[4]))?).ok()?;
+            let timestamp = Timestamp { seconds: u32::from_le_bytes(entry[0..4].try_into().ok()?) };
+            log.notes.push(Note { timestamp, text: validate(text).ok()? });
+        }
+        Some(log)
+    }
+}
+
+// The note's text without its surrounding spaces, if an export can carry it.
+fn validate(text: &str) -> Result<ArrayString<NOTE_LEN>, NoteError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(NoteError::Empty);
+    }
+    if text.chars().any(|c| !(c.is_ascii_graphic() || c == ' ') || c == '"') {
+        return Err(NoteError::InvalidCharacter);
+    }
+    ArrayString::from(text).or(Err(NoteError::TooLong))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{CaptureLog, Level, NullLog};
+    use crate::test_support::at;
+
+    #[test]
+    fn test_add_and_validate() {
+        let mut notes = NoteLog::new();
+        let mut log = CaptureLog::default();
+        assert_eq!(notes.add(at(100), "  defrost performed ", &mut log), Ok(()));
+        assert_eq!(notes.add(at(200), " ", &mut log), Err(NoteError::Empty));
+        assert_eq!(notes.add(at(200), "door left \"ajar\"", &mut log), Err(NoteError::InvalidCharacter));
+        assert_eq!(notes.add(at(200), "line\nbreak", &mut log), Err(NoteError::InvalidCharacter));
+        assert_eq!(notes.add(at(200), "Kühlschrank", &mut log), Err(NoteError::InvalidCharacter));
+        assert_eq!(notes.add(at(200), &"x".repeat(NOTE_LEN + 1), &mut log), Err(NoteError::TooLong));
+        assert_eq!(notes.iter().map(|note| note.text.as_str()).collect::<Vec<_>>(), ["defrost performed"]);
+        assert_eq!(log.entries, [(Level::Info, LogCode::NoteAdded, 100)]);
+    }
+
+    #[test]
+    fn test_bounded_and_by_period() {
+        let mut notes = NoteLog::new();
+        let mut log = CaptureLog::default();
+        for index in 0..MAX_NOTES as u32 + 2 {
+            notes.add(at(index * 100), "checked", &mut log).unwrap();
+        }
+        assert_eq!(notes.dropped(), 2);
+        assert_eq!(notes.iter().next().unwrap().timestamp, at(200));
+        let times: Vec<u32> = notes.between(at(300), at(600)).map(|note| note.timestamp.seconds).collect();
+        assert_eq!(times, [300, 400, 500]);
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let mut notes = NoteLog::new();
+        assert_eq!(NoteLog::from_bytes(&notes.to_bytes()), Some(NoteLog::new()));
+        for index in 0..MAX_NOTES as u32 + 1 {
+            notes.add(at(index * 100), "defrost performed", &mut NullLog).unwrap();
+        }
+        notes.add(at(900), &"x".repeat(NOTE_LEN), &mut NullLog).unwrap();
+        let bytes = notes.to_bytes();
+        assert_eq!(NoteLog::from_bytes(&bytes), Some(notes));
+        let mut corrupted = bytes;
+        corrupted[5 + 5] = b'"';
+        assert_eq!(NoteLog::from_bytes(&corrupted), None);
+        corrupted[4] = MAX_NOTES as u8 + 1;
+        assert_eq!(NoteLog::from_bytes(&corrupted), None);
+        assert_eq!(NoteLog::from_bytes(&bytes[1..]), None);
+    }
+}
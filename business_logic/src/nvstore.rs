@@ -0,0 +1,377 @@
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::crc::crc32;
+
+/// Longest value one entry holds.
+pub const NV_MAX_VALUE_LEN: usize = 248;
+const ALIGN: u32 = 8; // Entries start on double words, the STM32L4's program unit.
+const HEADER_LEN: u32 = 8;
+const PAGE_MAGIC: u32 = 0x4E56_5331; // "NVS1".
+const ERASED: u8 = 0xFF;
+
+/// What an entry in the NV store holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum NvKey {
+    Settings = 1, // `Config::to_bytes`.
+    Indicator = 2, // `IndicatorState::to_words`, little-endian.
+    Lifecycle = 3, // `Lifecycle::to_word`, little-endian; its change count stops tags being replayed.
+    TiltReference = 4, // `MotionDetector::reference`, three little-endian i16 in mg.
+    LifetimeA = 5, // The two slots of `LifetimeStore`, written in turn.
+    LifetimeB = 6,
+    Commissioning = 7, // `CommissioningRecord::to_bytes`, then its MAC if the device has a key.
+    AlarmState = 8, // `AlarmState::to_words`, little-endian.
+    BurstA = 9, // The slots of `BurstRecorder`, each a `BurstCapture::to_bytes`.
+    BurstB = 10,
+    BurstC = 11,
+    BurstD = 12,
+    RecordNonce = 13, // Times the record store started over, little-endian u32; the nonce of its `RecordCipher`.
+    FuelGauge = 14, // `FuelGauge::to_bytes`.
+    Crash = 15, // The last crash, `CrashRecord::to_words`, little-endian.
+    ErrorCounts = 16, // `ErrorLog::to_bytes`.
+    Notes = 17, // `NoteLog::to_bytes`.
+}
+
+/// Why the NV store couldn't save or read a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NvError {
+    TooLong, // The value is longer than `NV_MAX_VALUE_LEN`.
+    Full, // The newest values don't fit in one page.
+    Flash, // Erasing, programming or reading the flash failed.
+}
+
+/// Small values that must survive resets and power loss, e.g. the settings, kept in two pages
+/// of flash.
+///
+/// Saving a value appends an entry to the active page: a header with the key, the length and a
+/// CRC-32, then the value padded to a double word. The newest intact entry of a key is its
+/// value, so a reset part way through a save leaves the previous one. When the active page is
+/// full, the newest entries are copied to the other page, whose header is programmed last, with
+/// the next generation number, so an interrupted copy leaves the old page active.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NvStore {
+    base: u32, // Offset in flash of the first of the two pages.
+    page_len: u32,
+    active: u32, // The active page, 0 or 1.
+    generation: u32, // Of the active page.
+    free: u32, // Offset in the active page of the first erased entry.
+}
+
+// Key, value length and CRC-32 of an entry.
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    key: u8,
+    len: u32,
+    crc: u32,
+}
+
+impl NvStore {
+    /// Find the active one of the two pages at `base` in `flash`, formatting the first if neither
+    /// is, e.g. on a new device.
+    pub fn mount<F: NorFlash>(flash: &mut F, base: u32) -> Result<Self, NvError> {
+        let mut store = Self { base, page_len: F::ERASE_SIZE as u32, active: 0, generation: 0, free: HEADER_LEN };
+        let newest = match [store.generation_of(flash, 0)?, store.generation_of(flash, 1)?] {
+            // Generations wrap, so compare them by their difference.
+            [Some(first), Some(second)] => Some(if second.wrapping_sub(first) as i32 > 0 { (1, second) } else { (0, first) }),
+            [Some(first), None] => Some((0, first)),
+            [None, Some(second)] => Some((1, second)),
+            [None, None] => None,
+        };
+        match newest {
+            Some((page, generation)) => (store.active, store.generation) = (page, generation),
+            None => {
+                store.erase(flash, 0)?;
+                store.program_page_header(flash, 0, 0)?;
+            }
+        }
+        store.free = store.end_of_entries(flash)?;
+        Ok(store)
+    }
+
+    /// Read the value of `key` into `value`. Returns its length, or None if it was never saved
+    /// or is longer than `value`.
+    pub fn read<F: NorFlash>(&self, flash: &mut F, key: NvKey, value: &mut [u8]) -> Result<Option<usize>, NvError> {
+        let mut buffer = [0u8; NV_MAX_VALUE_LEN];
+        let mut newest = None;
+        let mut offset = HEADER_LEN;
+        while let Some(header) = self.header(flash, self.active, offset)? {
+            if header.key == key as u8 && self.read_value(flash, self.active, offset, header, &mut buffer)? {
+                newest = Some(header.len as usize);
+                if let Some(value) = value.get_mut(..header.len as usize) {
+                    value.copy_from_slice(&buffer[..header.len as usize]);
+                }
+            }
+            offset += entry_len(header.len);
+        }
+        Ok(newest.filter(|&len| len <= value.len()))
+    }
+
+    /// Save `value` as the value of `key`.
+    pub fn write<F: NorFlash>(&mut self, flash: &mut F, key: NvKey, value: &[u8]) -> Result<(), NvError> {
+        if value.len() > NV_MAX_VALUE_LEN {
+            return Err(NvError::TooLong);
+        }
+        let len = entry_len(value.len() as u32);
+        if self.free + len > self.page_len {
+            return self.copy_to_other_page(flash, key as u8, value);
+        }
+        // Past the entry whether or not it programs, as a failed program may have left some of it.
+        let offset = self.free;
+        self.free += len;
+        self.program_entry(flash, self.active, offset, key as u8, value)
+    }
+
+    // Copy the newest value of every key but `key` to the other page, add `value` for `key` and
+    // make that page active.
+    fn copy_to_other_page<F: NorFlash>(&mut self, flash: &mut F, key: u8, value: &[u8]) -> Result<(), NvError> {
+        let (from, to) = (self.active, 1 - self.active);
+        self.erase(flash, to)?;
+        let mut buffer = [0u8; NV_MAX_VALUE_LEN];
+        let mut free = HEADER_LEN;
+        let mut offset = HEADER_LEN;
+        while let Some(header) = self.header(flash, from, offset)? {
+            let next = offset + entry_len(header.len);
+            if header.key != key && !self.superseded(flash, from, next, header.key)? && self.read_value(flash, from, offset, header, &mut buffer)? {
+                if free + entry_len(header.len) > self.page_len {
+                    return Err(NvError::Full);
+                }
+                self.program_entry(flash, to, free, header.key, &buffer[..header.len as usize])?;
+                free += entry_len(header.len);
+            }
+            offset = next;
+        }
+        if free + entry_len(value.len() as u32) > self.page_len {
+            return Err(NvError::Full);
+        }
+        self.program_entry(flash, to, free, key, value)?;
+        free += entry_len(value.len() as u32);
+        self.program_page_header(flash, to, self.generation.wrapping_add(1))?;
+        (self.active, self.generation, self.free) = (to, self.generation.wrapping_add(1), free);
+        Ok(())
+    }
+
+    // Whether an intact entry for `key` follows `offset` in `page`.
+    fn superseded<F: NorFlash>(&self, flash: &mut F, page: u32, mut offset: u32, key: u8) -> Result<bool, NvError> {
+        let mut buffer = [0u8; NV_MAX_VALUE_LEN];
+        while let Some(header) = self.header(flash, page, offset)? {
+            if header.key == key && self.read_value(flash, page, offset, header, &mut buffer)? {
+                return Ok(true);
+            }
+            offset += entry_len(header.len);
+        }
+        Ok(false)
+    }
+
+    // Offset of the first erased entry in the active page. A damaged header ends the entries
+    // too, but nothing more can be programmed after it, so the page counts as full.
+    fn end_of_entries<F: NorFlash>(&self, flash: &mut F) -> Result<u32, NvError> {
+        let mut offset = HEADER_LEN;
+        while let Some(header) = self.header(flash, self.active, offset)? {
+            offset += entry_len(header.len);
+        }
+        let mut bytes = [0u8; HEADER_LEN as usize];
+        if offset + HEADER_LEN <= self.page_len {
+            self.read_bytes(flash, self.active, offset, &mut bytes)?;
+            if bytes.iter().any(|&byte| byte != ERASED) {
+                return Ok(self.page_len);
+            }
+        }
+        Ok(offset)
+    }
+
+    // The header of the entry at `offset` in `page`, or None at the end of the entries.
+    fn header<F: NorFlash>(&self, flash: &mut F, page: u32, offset: u32) -> Result<Option<Header>, NvError> {
+        if offset + HEADER_LEN > self.page_len {
+            return Ok(None);
+        }
+        let mut bytes = [0u8; HEADER_LEN as usize];
+        self.read_bytes(flash, page, offset, &mut bytes)?;
+        let len = u32::from(u16::from_le_bytes([bytes[2], bytes[3]]));
+        if bytes[0] == ERASED || len as usize > NV_MAX_VALUE_LEN || offset + entry_len(len) > self.page_len {
+            return Ok(None);
+        }
+        Ok(Some(Header { key: bytes[0], len, crc: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) }))
+    }
+
+    // Read the value of the entry at `offset` into `buffer`. Returns whether it is intact.
+    fn read_value<F: NorFlash>(&self, flash: &mut F, page: u32, offset: u32, header: Header, buffer: &mut [u8; NV_MAX_VALUE_LEN]) -> Result<bool, NvError> {
+        let value = &mut buffer[..header.len as usize];
+        self.read_bytes(flash, page, offset + HEADER_LEN, value)?;
+        Ok(value_crc(header.key, value) == header.crc)
+    }
+
+    fn program_entry<F: NorFlash>(&self, flash: &mut F, page: u32, offset: u32, key: u8, value: &[u8]) -> Result<(), NvError> {
+        let mut entry = [ERASED; HEADER_LEN as usize + NV_MAX_VALUE_LEN];
+        entry[0] = key;
+        entry[1] = 0;
+        entry[2..4].copy_from_slice(&(value.len() as u16).to_le_bytes());
+        entry[4..8].copy_from_slice(&value_crc(key, value).to_le_bytes());
+        entry[8..8 + value.len()].copy_from_slice(value);
+        let len = entry_len(value.len() as u32) as usize;
+        flash.write(self.page_offset(page) + offset, &entry[..len]).map_err(|_| NvError::Flash)
+    }
+
+    fn program_page_header<F: NorFlash>(&self, flash: &mut F, page: u32, generation: u32) -> Result<(), NvError> {
+        let mut header = [0u8; HEADER_LEN as usize];
+        header[..4].copy_from_slice(&PAGE_MAGIC.to_le_bytes());
+        header[4..].copy_from_slice(&generation.to_le_bytes());
+        flash.write(self.page_offset(page), &header).map_err(|_| NvError::Flash)
+    }
+
+    // The generation of `page`, or None if it isn't a formatted page.
+    fn generation_of<F: NorFlash>(&self, flash: &mut F, page: u32) -> Result<Option<u32>, NvError> {
+        let mut header = [0u8; HEADER_LEN as usize];
+        self.read_bytes(flash, page, 0, &mut header)?;
+        let word = |offset: usize| u32::from_le_bytes([header[offset], header[offset + 1], header[offset + 2], header[offset + 3]]);
+        Ok((word(0) == PAGE_MAGIC).then(|| word(4)))
+    }
+
+    fn erase<F: NorFlash>(&self, flash: &mut F, page: u32) -> Result<(), NvError> {
+        let start = self.page_offset(page);
+        flash.erase(start, start + self.page_len).map_err(|_| NvError::Flash)
+    }
+
+    fn read_bytes<F: NorFlash>(&self, flash: &mut F, page: u32, offset: u32, bytes: &mut [u8]) -> Result<(), NvError> {
+        flash.read(self.page_offset(page) + offset, bytes).map_err(|_| NvError::Flash)
+    }
+
+    fn page_offset(&self, page: u32) -> u32 {
+        self.base + page * self.page_len
+    }
+}
+
+// Bytes an entry with a value of `len` bytes takes, header and padding included.
+fn entry_len(len: u32) -> u32 {
+    HEADER_LEN + len.next_multiple_of(ALIGN)
+}
+
+fn value_crc(key: u8, value: &[u8]) -> u32 {
+    crc32(crc32(0, &[key]), value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind, ReadNorFlash};
+
+    const PAGE: usize = 128;
+
+    // Two pages of flash that, like the STM32L4's, can only be programmed once between erases.
+    struct MemFlash {
+        bytes: Vec<u8>,
+        fail_after: Option<usize>, // Programs to allow before the power fails.
+    }
+
+    impl MemFlash {
+        fn new() -> Self {
+            Self { bytes: vec![ERASED; 2 * PAGE], fail_after: None }
+        }
+    }
+
+    impl ErrorType for MemFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for MemFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            bytes.copy_from_slice(&self.bytes[offset as usize..offset as usize + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    impl NorFlash for MemFlash {
+        const WRITE_SIZE: usize = 8;
+        const ERASE_SIZE: usize = PAGE;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            self.bytes[from as usize..to as usize].fill(ERASED);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            assert_eq!((offset as usize % Self::WRITE_SIZE, bytes.len() % Self::WRITE_SIZE), (0, 0));
+            if let Some(left) = &mut self.fail_after {
+                if *left == 0 {
+                    return Err(NorFlashErrorKind::Other);
+                }
+                *left -= 1;
+            }
+            let target = &mut self.bytes[offset as usize..offset as usize + bytes.len()];
+            assert!(target.iter().all(|&byte| byte == ERASED), "programmed twice");
+            target.copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    fn read(store: &NvStore, flash: &mut MemFlash) -> Option<Vec<u8>> {
+        let mut value = [0u8; NV_MAX_VALUE_LEN];
+        let len = store.read(flash, NvKey::Settings, &mut value).unwrap()?;
+        Some(value[..len].to_vec())
+    }
+
+    #[test]
+    fn test_newest_value_survives_remount() {
+        let mut flash = MemFlash::new();
+        let mut store = NvStore::mount(&mut flash, 0).unwrap();
+        assert_eq!(read(&store, &mut flash), None);
+        store.write(&mut flash, NvKey::Settings, &[1, 2, 3]).unwrap();
+        store.write(&mut flash, NvKey::Settings, &[4, 5, 6, 7]).unwrap();
+        assert_eq!(read(&store, &mut flash), Some(vec![4, 5, 6, 7]));
+        let store = NvStore::mount(&mut flash, 0).unwrap();
+        assert_eq!(read(&store, &mut flash), Some(vec![4, 5, 6, 7]));
+        assert_eq!(store.read(&mut flash, NvKey::Settings, &mut [0; 3]), Ok(None)); // Too long for the buffer.
+    }
+
+    #[test]
+    fn test_torn_write_keeps_previous_value() {
+        let mut flash = MemFlash::new();
+        let mut store = NvStore::mount(&mut flash, 0).unwrap();
+        store.write(&mut flash, NvKey::Settings, &[1; 12]).unwrap();
+        store.write(&mut flash, NvKey::Settings, &[2; 12]).unwrap();
+        flash.bytes[40] ^= 1; // In the value of the second entry.
+        let mut store = NvStore::mount(&mut flash, 0).unwrap();
+        assert_eq!(read(&store, &mut flash), Some(vec![1; 12]));
+        // Saves go after the damaged entry.
+        store.write(&mut flash, NvKey::Settings, &[3; 12]).unwrap();
+        assert_eq!(read(&NvStore::mount(&mut flash, 0).unwrap(), &mut flash), Some(vec![3; 12]));
+    }
+
+    #[test]
+    fn test_full_page_is_copied() {
+        let mut flash = MemFlash::new();
+        let mut store = NvStore::mount(&mut flash, 0).unwrap();
+        // Five 24-byte entries fill a 128-byte page after its header; the sixth moves to the other.
+        for value in 0..6u8 {
+            store.write(&mut flash, NvKey::Settings, &[value; 16]).unwrap();
+        }
+        assert_eq!(store.active, 1);
+        assert_eq!(read(&store, &mut flash), Some(vec![5; 16]));
+        let store = NvStore::mount(&mut flash, 0).unwrap();
+        assert_eq!((store.active, store.generation), (1, 1));
+        assert_eq!(read(&store, &mut flash), Some(vec![5; 16]));
+    }
+
+    #[test]
+    fn test_interrupted_copy_keeps_old_page() {
+        let mut flash = MemFlash::new();
+        let mut store = NvStore::mount(&mut flash, 0).unwrap();
+        for value in 0..5u8 {
+            store.write(&mut flash, NvKey::Settings, &[value; 16]).unwrap();
+        }
+        flash.fail_after = Some(1); // The new entry is copied, but the page header isn't programmed.
+        assert_eq!(store.write(&mut flash, NvKey::Settings, &[9; 16]), Err(NvError::Flash));
+        flash.fail_after = None;
+        let store = NvStore::mount(&mut flash, 0).unwrap();
+        assert_eq!(store.active, 0);
+        assert_eq!(read(&store, &mut flash), Some(vec![4; 16]));
+        assert_eq!(NvStore::mount(&mut flash, 0).unwrap().write(&mut flash, NvKey::Settings, &[0; NV_MAX_VALUE_LEN + 1]), Err(NvError::TooLong));
+    }
+}
@@ -0,0 +1,330 @@
+use arrayvec::ArrayVec;
+// Bit timing is in microseconds, so the bit-banged bus busy-waits rather than yielding.
+use embedded_hal::delay::DelayNs;
+
+use crate::hal::{InputPin, OutputPin};
+use crate::sensor::SensorError;
+
+/// Most probes found by one ROM search.
+pub const MAX_PROBES: usize = 8;
+/// Family code of the DS18B20 temperature probe.
+pub const DS18B20_FAMILY: u8 = 0x28;
+/// Time a DS18B20 takes for a 12-bit conversion.
+pub const DS18B20_CONVERSION_TIME_MS: u64 = 750;
+const SEARCH_ROM: u8 = 0xF0;
+const MATCH_ROM: u8 = 0x55;
+const SKIP_ROM: u8 = 0xCC;
+const CONVERT_T: u8 = 0x44;
+const READ_SCRATCHPAD: u8 = 0xBE;
+const SCRATCHPAD_LEN: usize = 9;
+const TEMPERATURE_LSB_CELSIUS: f32 = 0.0625;
+
+/// Bit-level access to a 1-Wire bus, whether bit-banged or through a UART.
+pub trait OneWire {
+    /// Send a reset pulse. Returns true if any device answered with a presence pulse.
+    fn reset(&mut self) -> bool;
+    fn write_bit(&mut self, bit: bool);
+    fn read_bit(&mut self) -> bool;
+
+    /// Write a byte, least significant bit first.
+    fn write_byte(&mut self, byte: u8) {
+        for bit in 0..8 {
+            self.write_bit(byte >> bit & 1 != 0);
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        (0..8).fold(0, |byte, bit| byte | u8::from(self.read_bit()) << bit)
+    }
+}
+
+/// 64-bit ROM code of a 1-Wire device: family code in the low byte, serial number, then CRC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Rom(pub u64);
+
+impl Rom {
+    pub fn family(self) -> u8 {
+        self.0 as u8
+    }
+
+    /// Whether the CRC in the top byte matches the rest of the code.
+    pub fn is_valid(self) -> bool {
+        let bytes = self.0.to_le_bytes();
+        crc8(&bytes[..7]) == bytes[7]
+    }
+}
+
+/// Dallas/Maxim CRC-8 (polynomial x⁸ + x⁵ + x⁴ + 1), as used in ROM codes and scratchpads.
+pub fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ 0x8C } else { crc >> 1 };
+        }
+        crc
+    })
+}
+
+/// Find the ROM codes of the devices on the bus, in the order of the search algorithm.
+pub fn search(bus: &mut impl OneWire) -> ArrayVec<Rom, MAX_PROBES> {
+    let mut roms = ArrayVec::new();
+    let mut rom = 0u64;
+    let mut last_discrepancy: Option<u8> = None; // Bit where the previous pass took the 0 branch last.
+    loop {
+        if !bus.reset() {
+            return roms;
+        }
+        bus.write_byte(SEARCH_ROM);
+        let mut last_zero = None;
+        for bit in 0..64u8 {
+            let direction = match (bus.read_bit(), bus.read_bit()) {
+                (true, true) => return roms, // Nobody is taking part any more.
+                (id, complement) if id != complement => id,
+                // Devices differ here: retrace the previous path, then take the 1 branch where we took 0 last time.
+                _ => {
+                    let direction = match last_discrepancy {
+                        Some(last) if bit < last => rom >> bit & 1 != 0,
+                        Some(last) => bit == last,
+                        None => false,
+                    };
+                    if !direction {
+                        last_zero = Some(bit);
+                    }
+                    direction
+                }
+            };
+            rom = rom & !(1 << bit) | u64::from(direction) << bit;
+            bus.write_bit(direction);
+        }
+        if Rom(rom).is_valid() && roms.try_push(Rom(rom)).is_err() {
+            return roms;
+        }
+        match last_zero {
+            Some(_) => last_discrepancy = last_zero,
+            None => return roms,
+        }
+    }
+}
+
+/// Start a temperature conversion on every DS18B20 on the bus at once. Read the results
+/// after `DS18B20_CONVERSION_TIME_MS`.
+pub fn start_conversion(bus: &mut impl OneWire) -> Result<(), SensorError> {
+    if !bus.reset() {
+        return Err(SensorError::Bus);
+    }
+    bus.write_byte(SKIP_ROM);
+    bus.write_byte(CONVERT_T);
+    Ok(())
+}
+
+/// Read the last converted temperature of the DS18B20 with ROM code `rom`, in °C.
+pub fn read_temperature(bus: &mut impl OneWire, rom: Rom) -> Result<f32, SensorError> {
+    if !bus.reset() {
+        return Err(SensorError::Bus);
+    }
+    bus.write_byte(MATCH_ROM);
+    for byte in rom.0.to_le_bytes() {
+        bus.write_byte(byte);
+    }
+    bus.write_byte(READ_SCRATCHPAD);
+    let mut scratchpad = [0u8; SCRATCHPAD_LEN];
+    for byte in &mut scratchpad {
+        *byte = bus.read_byte();
+    }
+    // A missing probe reads as all ones, which fails the CRC too.
+    if crc8(&scratchpad[..SCRATCHPAD_LEN - 1]) != scratchpad[SCRATCHPAD_LEN - 1] {
+        return Err(SensorError::Crc);
+    }
+    Ok(f32::from(i16::from_le_bytes([scratchpad[0], scratchpad[1]])) * TEMPERATURE_LSB_CELSIUS)
+}
+
+/// 1-Wire bus bit-banged on an open-drain pin with an external pull-up.
+///
+/// The slots are timed with busy waits, so interrupts that take more than a few microseconds
+/// corrupt them; a corrupted read shows up as a CRC error.
+pub struct BitBangOneWire<P, D> {
+    pin: P,
+    delay: D,
+}
+
+impl<P, D> BitBangOneWire<P, D> {
+    pub fn new(pin: P, delay: D) -> Self {
+        Self { pin, delay }
+    }
+}
+
+impl<P: InputPin + OutputPin, D: DelayNs> OneWire for BitBangOneWire<P, D> {
+    fn reset(&mut self) -> bool {
+        let _ = self.pin.set_low();
+        self.delay.delay_us(480);
+        let _ = self.pin.set_high();
+        self.delay.delay_us(70);
+        let present = self.pin.is_low().unwrap_or(false);
+        self.delay.delay_us(410);
+        present
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        let _ = self.pin.set_low();
+        self.delay.delay_us(if bit { 6 } else { 60 });
+        let _ = self.pin.set_high();
+        self.delay.delay_us(if bit { 64 } else { 10 });
+    }
+
+    fn read_bit(&mut self) -> bool {
+        let _ = self.pin.set_low();
+        self.delay.delay_us(6);
+        let _ = self.pin.set_high();
+        self.delay.delay_us(9);
+        let bit = self.pin.is_high().unwrap_or(true);
+        self.delay.delay_us(55);
+        bit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A valid ROM code for a DS18B20 with the given serial number.
+    fn rom(serial: u64) -> Rom {
+        let code = u64::from(DS18B20_FAMILY) | serial << 8;
+        Rom(code | u64::from(crc8(&code.to_le_bytes()[..7])) << 56)
+    }
+
+    #[derive(Clone, Copy)]
+    enum State {
+        Command,
+        Search { bit: u8, phase: u8 },
+        MatchRom { bit: u8, rom: u64 },
+        Function,
+        Reading { bit: usize },
+    }
+
+    // Devices on a simulated bus, with the wired-AND behaviour the search relies on.
+    struct FakeBus {
+        devices: Vec<(Rom, [u8; SCRATCHPAD_LEN])>,
+        active: Vec<bool>, // Devices still taking part after the ROM command.
+        state: State,
+        byte: (u8, u8), // Command bits received so far, and how many.
+        converting: bool,
+    }
+
+    impl FakeBus {
+        fn new(devices: Vec<(Rom, [u8; SCRATCHPAD_LEN])>) -> Self {
+            let active = vec![true; devices.len()];
+            Self { devices, active, state: State::Command, byte: (0, 0), converting: false }
+        }
+
+        fn and(&self, bit_of: impl Fn(&Rom) -> bool) -> bool {
+            self.devices.iter().zip(&self.active).filter(|(_, active)| **active).all(|(device, _)| bit_of(&device.0))
+        }
+    }
+
+    impl OneWire for FakeBus {
+        fn reset(&mut self) -> bool {
+            self.active.iter_mut().for_each(|active| *active = true);
+            (self.state, self.byte) = (State::Command, (0, 0));
+            !self.devices.is_empty()
+        }
+
+        fn write_bit(&mut self, bit: bool) {
+            match self.state {
+                State::Search { bit: index, .. } => {
+                    for (device, active) in self.devices.iter().zip(&mut self.active) {
+                        *active &= (device.0.0 >> index & 1 != 0) == bit;
+                    }
+                    self.state = State::Search { bit: index + 1, phase: 0 };
+                }
+                State::MatchRom { bit: index, rom } => {
+                    let rom = rom | u64::from(bit) << index;
+                    self.state = if index == 63 {
+                        for (device, active) in self.devices.iter().zip(&mut self.active) {
+                            *active = device.0.0 == rom;
+                        }
+                        State::Function
+                    } else {
+                        State::MatchRom { bit: index + 1, rom }
+                    };
+                }
+                State::Command | State::Function => {
+                    self.byte = (self.byte.0 | u8::from(bit) << self.byte.1, self.byte.1 + 1);
+                    if self.byte.1 < 8 {
+                        return;
+                    }
+                    let command = self.byte.0;
+                    self.byte = (0, 0);
+                    self.state = match (self.state, command) {
+                        (State::Command, SEARCH_ROM) => State::Search { bit: 0, phase: 0 },
+                        (State::Command, MATCH_ROM) => State::MatchRom { bit: 0, rom: 0 },
+                        (State::Command, SKIP_ROM) => State::Function,
+                        (State::Function, CONVERT_T) => {
+                            self.converting = true;
+                            State::Function
+                        }
+                        (State::Function, READ_SCRATCHPAD) => State::Reading { bit: 0 },
+                        _ => panic!("unexpected command {:#04x}", command),
+                    };
+                }
+                State::Reading { .. } => panic!("write while reading"),
+            }
+        }
+
+        fn read_bit(&mut self) -> bool {
+            match self.state {
+                State::Search { bit, phase } => {
+                    self.state = State::Search { bit, phase: phase + 1 };
+                    self.and(|rom| (rom.0 >> bit & 1 != 0) != (phase == 1))
+                }
+                State::Reading { bit } => {
+                    self.state = State::Reading { bit: bit + 1 };
+                    let index = self.active.iter().position(|active| *active).unwrap();
+                    self.devices[index].1[bit / 8] >> (bit % 8) & 1 != 0
+                }
+                _ => true, // Released bus.
+            }
+        }
+    }
+
+    fn scratchpad(raw: i16) -> [u8; SCRATCHPAD_LEN] {
+        let mut scratchpad = [0u8; SCRATCHPAD_LEN];
+        scratchpad[..2].copy_from_slice(&raw.to_le_bytes());
+        scratchpad[8] = crc8(&scratchpad[..8]);
+        scratchpad
+    }
+
+    #[test]
+    fn test_crc8() {
+        // ROM code example from Maxim application note 27.
+        assert_eq!(crc8(&[0x02, 0x1C, 0xB8, 0x01, 0x00, 0x00, 0x00]), 0xA2);
+        assert!(rom(0x1234).is_valid());
+        assert!(!Rom(rom(0x1234).0 ^ 1 << 20).is_valid());
+        assert_eq!(rom(0x1234).family(), DS18B20_FAMILY);
+    }
+
+    #[test]
+    fn test_search_finds_every_probe() {
+        let probes = [rom(0x0001), rom(0x0003), rom(0x8000_0000), rom(0x7F)];
+        let mut bus = FakeBus::new(probes.iter().map(|&rom| (rom, scratchpad(0))).collect());
+        let mut found: Vec<u64> = search(&mut bus).iter().map(|rom| rom.0).collect();
+        found.sort();
+        let mut expected: Vec<u64> = probes.iter().map(|rom| rom.0).collect();
+        expected.sort();
+        assert_eq!(found, expected);
+        assert!(search(&mut FakeBus::new(Vec::new())).is_empty());
+    }
+
+    #[test]
+    fn test_read_temperature() {
+        let (cold, warm) = (rom(1), rom(2));
+        let mut bus = FakeBus::new(vec![(cold, scratchpad(-10 * 16 - 8)), (warm, scratchpad(25 * 16))]);
+        assert_eq!(start_conversion(&mut bus), Ok(()));
+        assert!(bus.converting);
+        assert_eq!(read_temperature(&mut bus, cold), Ok(-10.5));
+        assert_eq!(read_temperature(&mut bus, warm), Ok(25.0));
+        bus.devices[1].1[0] ^= 1;
+        assert_eq!(read_temperature(&mut bus, warm), Err(SensorError::Crc));
+        assert_eq!(start_conversion(&mut FakeBus::new(Vec::new())), Err(SensorError::Bus));
+    }
+}
@@ -0,0 +1,235 @@
+use crate::log::{Log, LogCode};
+use crate::timestamp::Timestamp;
+
+/// The battery must be the only source for this long before clocks are reduced,
+/// so a brief mains glitch doesn't cause a reconfiguration.
+pub const BATTERY_SETTLE_SECONDS: u32 = 30;
+
+/// Where the logger is currently drawing power from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerSource {
+    Mains,
+    Usb,
+    Battery,
+}
+
+impl PowerSource {
+    pub fn from_inputs(mains_present: bool, usb_present: bool) -> Self {
+        if mains_present {
+            PowerSource::Mains
+        } else if usb_present {
+            PowerSource::Usb
+        } else {
+            PowerSource::Battery
+        }
+    }
+
+    /// The clock configuration appropriate for this source.
+    pub fn clock_profile(self) -> ClockProfile {
+        match self {
+            PowerSource::Mains | PowerSource::Usb => ClockProfile::FULL_SPEED,
+            PowerSource::Battery => ClockProfile::LOW_POWER,
+        }
+    }
+}
+
+/// System and bus clock speeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ClockProfile {
+    pub sysclk_hz: u32,
+    pub i2c_hz: u32,
+}
+
+impl ClockProfile {
+    /// PLL on, 48 MHz system clock.
+    pub const FULL_SPEED: ClockProfile = ClockProfile { sysclk_hz: 48_000_000, i2c_hz: 400_000 };
+    /// PLL off, 2 MHz MSI system clock.
+    pub const LOW_POWER: ClockProfile = ClockProfile { sysclk_hz: 2_000_000, i2c_hz: 100_000 };
+}
+
+/// Decides when to switch clock profiles as the power source changes.
+///
+/// Full speed is restored as soon as external power returns; the low-power
+/// profile is only applied once the logger has been on battery for
+/// `BATTERY_SETTLE_SECONDS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerManager {
+    profile: ClockProfile,
+    on_battery_since: Option<Timestamp>,
+}
+
+impl Default for PowerManager {
+    fn default() -> Self {
+        Self { profile: ClockProfile::FULL_SPEED, on_battery_since: None }
+    }
+}
+
+impl PowerManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The profile currently in effect.
+    pub fn profile(&self) -> ClockProfile {
+        self.profile
+    }
+
+    /// Update with the current power source. Returns the new profile if it should be applied now.
+    pub fn update(&mut self, source: PowerSource, now: Timestamp, log: &mut impl Log) -> Option<ClockProfile> {
+        let wanted = match source {
+            PowerSource::Battery => {
+                let since = *self.on_battery_since.get_or_insert_with(|| {
+                    log.info(LogCode::OnBattery, BATTERY_SETTLE_SECONDS);
+                    now
+                });
+                if now.seconds.saturating_sub(since.seconds) < BATTERY_SETTLE_SECONDS {
+                    return None;
+                }
+                source.clock_profile()
+            }
+            _ => {
+                self.on_battery_since = None;
+                source.clock_profile()
+            }
+        };
+        if wanted == self.profile {
+            return None;
+        }
+        self.profile = wanted;
+        log.info(LogCode::ClockProfileChanged, wanted.sysclk_hz);
+        Some(wanted)
+    }
+}
+
+/// Switchable power rails for peripherals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Rail {
+    Sensors,
+    Display,
+    ExternalFlash,
+}
+
+impl Rail {
+    pub const COUNT: usize = 3;
+    pub const ALL: [Rail; Rail::COUNT] = [Rail::Sensors, Rail::Display, Rail::ExternalFlash];
+
+    /// Time after switching the rail on before its devices can be used.
+    pub fn settle_ms(self) -> u64 {
+        match self {
+            Rail::Sensors => 51, // Temperature sensor power-up and first conversion.
+            Rail::Display => 100, // SSD1306 VDD to VCC/charge pump stable.
+            Rail::ExternalFlash => 1, // SPI NOR power-up (tVSL).
+        }
+    }
+}
+
+/// Reference counts of the users of each rail.
+///
+/// A rail is switched on by its first user and off when its last user releases it,
+/// so no rail stays on while every task is sleeping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RailCounts {
+    counts: [u8; Rail::COUNT],
+}
+
+impl RailCounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a user of the rail. Returns true if the rail must be switched on.
+    pub fn acquire(&mut self, rail: Rail) -> bool {
+        let count = &mut self.counts[rail as usize];
+        *count = count.saturating_add(1);
+        *count == 1
+    }
+
+    /// Remove a user of the rail. Returns true if the rail must be switched off.
+    /// Unbalanced releases are ignored.
+    pub fn release(&mut self, rail: Rail) -> bool {
+        let count = &mut self.counts[rail as usize];
+        if *count == 0 {
+            return false;
+        }
+        *count -= 1;
+        *count == 0
+    }
+
+    pub fn is_on(&self, rail: Rail) -> bool {
+        self.counts[rail as usize] > 0
+    }
+
+    /// Returns true if no rail has any users, so the device may sleep.
+    pub fn all_off(&self) -> bool {
+        self.counts.iter().all(|&count| count == 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{CaptureLog, Level, NullLog};
+
+    #[test]
+    fn test_power_source() {
+        assert_eq!(PowerSource::from_inputs(true, true), PowerSource::Mains);
+        assert_eq!(PowerSource::from_inputs(false, true), PowerSource::Usb);
+        assert_eq!(PowerSource::from_inputs(false, false), PowerSource::Battery);
+    }
+
+    #[test]
+    fn test_battery_settles_before_low_power() {
+        let mut manager = PowerManager::new();
+        assert_eq!(manager.update(PowerSource::Mains, Timestamp { seconds: 0 }, &mut NullLog), None);
+        assert_eq!(manager.update(PowerSource::Battery, Timestamp { seconds: 10 }, &mut NullLog), None);
+        assert_eq!(manager.update(PowerSource::Battery, Timestamp { seconds: 39 }, &mut NullLog), None);
+        assert_eq!(manager.update(PowerSource::Battery, Timestamp { seconds: 40 }, &mut NullLog), Some(ClockProfile::LOW_POWER));
+        assert_eq!(manager.update(PowerSource::Battery, Timestamp { seconds: 50 }, &mut NullLog), None);
+        assert_eq!(manager.update(PowerSource::Usb, Timestamp { seconds: 60 }, &mut NullLog), Some(ClockProfile::FULL_SPEED));
+    }
+
+    #[test]
+    fn test_brief_outage_ignored() {
+        let mut manager = PowerManager::new();
+        assert_eq!(manager.update(PowerSource::Battery, Timestamp { seconds: 0 }, &mut NullLog), None);
+        assert_eq!(manager.update(PowerSource::Mains, Timestamp { seconds: 5 }, &mut NullLog), None);
+        // The settle time restarts on the next outage.
+        assert_eq!(manager.update(PowerSource::Battery, Timestamp { seconds: 100 }, &mut NullLog), None);
+        assert_eq!(manager.update(PowerSource::Battery, Timestamp { seconds: 129 }, &mut NullLog), None);
+        assert_eq!(manager.profile(), ClockProfile::FULL_SPEED);
+    }
+
+    #[test]
+    fn test_rail_counts() {
+        let mut rails = RailCounts::new();
+        assert!(rails.all_off());
+        assert!(rails.acquire(Rail::Sensors));
+        assert!(!rails.acquire(Rail::Sensors));
+        assert!(rails.acquire(Rail::Display));
+        assert!(!rails.release(Rail::Sensors));
+        assert!(rails.is_on(Rail::Sensors));
+        assert!(rails.release(Rail::Sensors));
+        assert!(!rails.release(Rail::Sensors));
+        assert!(rails.release(Rail::Display));
+        assert!(rails.all_off());
+    }
+
+    #[test]
+    fn test_update_diagnostics() {
+        let mut manager = PowerManager::new();
+        let mut log = CaptureLog::default();
+        manager.update(PowerSource::Battery, Timestamp { seconds: 0 }, &mut log);
+        manager.update(PowerSource::Battery, Timestamp { seconds: 10 }, &mut log);
+        manager.update(PowerSource::Battery, Timestamp { seconds: 30 }, &mut log);
+        assert_eq!(
+            log.entries,
+            [
+                (Level::Info, LogCode::OnBattery, BATTERY_SETTLE_SECONDS),
+                (Level::Info, LogCode::ClockProfileChanged, ClockProfile::LOW_POWER.sysclk_hz),
+            ]
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use crate::crc::crc32;
+
+/// Length of the secret used to authenticate exports (HMAC).
+pub const DEVICE_KEY_LEN: usize = 32;
+/// Size of a serialized provisioning block: magic, serial number, key, CRC-32.
+pub const PROVISIONING_BLOCK_LEN: usize = 4 + 4 + DEVICE_KEY_LEN + 4;
+const PROVISIONING_MAGIC: u32 = 0x5052_4F56; // "PROV"
+
+/// Secret shared between one device and the auditors who verify its exports.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DeviceKey(pub [u8; DEVICE_KEY_LEN]);
+
+// Never print the key itself.
+impl core::fmt::Debug for DeviceKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("DeviceKey(..)")
+    }
+}
+
+/// Identity and secrets written once at the factory, and never changed by the firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProvisioningBlock {
+    pub serial_number: u32,
+    pub key: DeviceKey,
+}
+
+impl ProvisioningBlock {
+    pub fn to_bytes(&self) -> [u8; PROVISIONING_BLOCK_LEN] {
+        let mut bytes = [0u8; PROVISIONING_BLOCK_LEN];
+        bytes[0..4].copy_from_slice(&PROVISIONING_MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.serial_number.to_le_bytes());
+        bytes[8..8 + DEVICE_KEY_LEN].copy_from_slice(&self.key.0);
+        let crc = crc32(0, &bytes[..PROVISIONING_BLOCK_LEN - 4]);
+        bytes[PROVISIONING_BLOCK_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Decode a stored block. Returns None if the device was never provisioned or the block is damaged.
+    pub fn from_bytes(bytes: &[u8; PROVISIONING_BLOCK_LEN]) -> Option<Self> {
+        let word = |offset: usize| u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+        if word(0) != PROVISIONING_MAGIC || word(PROVISIONING_BLOCK_LEN - 4) != crc32(0, &bytes[..PROVISIONING_BLOCK_LEN - 4]) {
+            return None;
+        }
+        let mut key = [0u8; DEVICE_KEY_LEN];
+        key.copy_from_slice(&bytes[8..8 + DEVICE_KEY_LEN]);
+        Some(Self { serial_number: word(4), key: DeviceKey(key) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let block = ProvisioningBlock { serial_number: 1234, key: DeviceKey([7; DEVICE_KEY_LEN]) };
+        let mut bytes = block.to_bytes();
+        assert_eq!(ProvisioningBlock::from_bytes(&bytes), Some(block));
+        assert_eq!(ProvisioningBlock::from_bytes(&[0xFF; PROVISIONING_BLOCK_LEN]), None); // Erased.
+        bytes[10] ^= 1;
+        assert_eq!(ProvisioningBlock::from_bytes(&bytes), None);
+        assert_eq!(format!("{:?}", block.key), "DeviceKey(..)");
+    }
+}
@@ -0,0 +1,106 @@
+use crate::alarm::{AlarmKind, AlarmSink, AlarmStatus};
+use crate::timestamp::Timestamp;
+
+/// How long a test pulse asserts the output, long enough for an alarm panel to register it.
+pub const RELAY_TEST_SECONDS: u32 = 5;
+/// Alarm classes that switch the output unless configured otherwise: all but the door, which
+/// staff deal with locally.
+pub const DEFAULT_RELAY_MASK: u8 = 1 << AlarmKind::Freeze as u8 | 1 << AlarmKind::HighTemp as u8 | 1 << AlarmKind::Power as u8;
+
+/// A digital output for an external siren or a building alarm panel, asserted while any enabled
+/// class of alarm is active, whether or not the buzzer was acknowledged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlarmRelay {
+    mask: u8, // Enabled alarm classes, bit `AlarmKind as u8`.
+    test_until: Option<Timestamp>,
+    asserted: bool, // As of the last status.
+}
+
+impl AlarmRelay {
+    pub fn new(mask: u8) -> Self {
+        Self { mask, test_until: None, asserted: false }
+    }
+
+    /// Assert the output for `RELAY_TEST_SECONDS` from `now`, to check the wiring.
+    pub fn test_pulse(&mut self, now: Timestamp) {
+        self.test_until = Some(Timestamp { seconds: now.seconds.saturating_add(RELAY_TEST_SECONDS) });
+    }
+
+    /// Whether the output should be asserted, as of the last status.
+    pub fn is_asserted(&self) -> bool {
+        self.asserted
+    }
+}
+
+impl AlarmSink for AlarmRelay {
+    fn on_status(&mut self, status: &AlarmStatus) {
+        if self.test_until.is_some_and(|until| status.now.seconds >= until.seconds) {
+            self.test_until = None;
+        }
+        self.asserted = self.test_until.is_some() || AlarmKind::ALL.iter().any(|&kind| self.mask & kind.mask() != 0 && status.is_active(kind));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::{AlarmNotifier, Annunciator};
+    use crate::escalation::Escalation;
+
+    // Whether `relay` is asserted after an update at `seconds`.
+    fn asserted(relay: &mut AlarmRelay, annunciator: &mut Annunciator, seconds: u32) -> bool {
+        AlarmNotifier::new().update(annunciator, &mut Escalation::default(), Timestamp { seconds }, &mut [relay]);
+        relay.is_asserted()
+    }
+
+    #[test]
+    fn test_mask_and_pulse() {
+        let mut relay = AlarmRelay::new(DEFAULT_RELAY_MASK);
+        let mut annunciator = Annunciator::new();
+        let now = Timestamp { seconds: 100 };
+        annunciator.set_active(AlarmKind::Door, true);
+        assert!(!asserted(&mut relay, &mut annunciator, 100));
+        annunciator.set_active(AlarmKind::Power, true);
+        assert!(asserted(&mut relay, &mut annunciator, 100));
+        annunciator.acknowledge(now);
+        assert!(asserted(&mut relay, &mut annunciator, 100)); // Only the buzzer is silenced.
+        annunciator.set_active(AlarmKind::Power, false);
+        relay.test_pulse(now);
+        assert!(asserted(&mut relay, &mut annunciator, 104));
+        assert!(!asserted(&mut relay, &mut annunciator, 105));
+        assert!(asserted(&mut AlarmRelay::new(0xFF), &mut annunciator, 100));
+    }
+
+    #[test]
+    fn test_follows_each_enabled_class() {
+        for kind in AlarmKind::ALL {
+            let mut annunciator = Annunciator::new();
+            let mut relay = AlarmRelay::new(DEFAULT_RELAY_MASK);
+            let enabled = kind != AlarmKind::Door;
+            annunciator.set_active(kind, true);
+            assert_eq!(asserted(&mut relay, &mut annunciator, 100), enabled, "{:?}", kind);
+            annunciator.set_active(kind, false);
+            assert!(!asserted(&mut relay, &mut annunciator, 200));
+            // Only its own class switches a relay enabled for one.
+            let mut only = AlarmRelay::new(kind.mask());
+            annunciator.set_active(kind, true);
+            assert!(asserted(&mut only, &mut annunciator, 300));
+            assert!(!asserted(&mut AlarmRelay::new(!kind.mask()), &mut annunciator, 300));
+        }
+    }
+
+    #[test]
+    fn test_held_while_any_enabled_alarm_is_active() {
+        let mut relay = AlarmRelay::new(DEFAULT_RELAY_MASK);
+        let mut annunciator = Annunciator::new();
+        assert!(!relay.is_asserted());
+        annunciator.set_active(AlarmKind::HighTemp, true);
+        annunciator.set_active(AlarmKind::Power, true);
+        assert!(asserted(&mut relay, &mut annunciator, 100));
+        annunciator.set_active(AlarmKind::HighTemp, false);
+        assert!(asserted(&mut relay, &mut annunciator, 200));
+        annunciator.set_active(AlarmKind::Power, false);
+        assert!(!asserted(&mut relay, &mut annunciator, 300));
+        assert!(!asserted(&mut AlarmRelay::new(0), &mut annunciator, 300));
+    }
+}
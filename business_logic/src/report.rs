@@ -0,0 +1,150 @@
+use crate::aggregator::AggregationRecord;
+use crate::compliance::ComplianceInfo;
+use crate::errors::PackedErrors;
+use crate::indicator::IndicatorState;
+use crate::lifetime::LifetimeCounters;
+use crate::timestamp::Timestamp;
+
+/// Totals over the records in a reporting period, e.g. the 30-day report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Report {
+    pub start: Timestamp,
+    pub end: Timestamp, // Exclusive.
+    pub records: u32,
+    pub covered_seconds: u32, // Time covered by temperature readings.
+    pub uncovered_seconds: u32, // Rest of the period apart from pauses, e.g. while a sensor failed.
+    pub paused_seconds: u32, // Logging paused on purpose, e.g. for a defrost.
+    pub tvc_average: Option<f32>, // Time-weighted, None without readings.
+    pub tvc_min: Option<f32>,
+    pub tvc_max: Option<f32>,
+    pub high_seconds: u32,
+    pub low_seconds: u32,
+    pub high_alarm_seconds: u32,
+    pub low_alarm_seconds: u32,
+    pub door_openings: u32,
+    pub door_open_seconds: u32,
+    pub power_off_seconds: u32,
+    pub logger_errors: PackedErrors, // First few distinct codes over the period.
+    pub indicator: IndicatorState, // Set by the caller from the excursion indicator, if enabled.
+    pub lifetime: LifetimeCounters, // Set by the caller, for the device's whole service life.
+    pub compliance: Option<ComplianceInfo>, // Set by the caller from the compliance block, if one was written.
+    pub epoch_anchor: Option<u32>, // Set by the caller from `Config::epoch_anchor`, so hosts can convert the times.
+}
+
+impl Report {
+    /// Summarize the records starting in `start..end`. A period ending before it starts is
+    /// empty, and ends at `start`.
+    pub fn generate(records: impl IntoIterator<Item = AggregationRecord>, start: Timestamp, end: Timestamp) -> Self {
+        let end = Timestamp { seconds: end.seconds.max(start.seconds) };
+        let mut report = Self {
+            start,
+            end,
+            records: 0,
+            covered_seconds: 0,
+            uncovered_seconds: 0,
+            paused_seconds: 0,
+            tvc_average: None,
+            tvc_min: None,
+            tvc_max: None,
+            high_seconds: 0,
+            low_seconds: 0,
+            high_alarm_seconds: 0,
+            low_alarm_seconds: 0,
+            door_openings: 0,
+            door_open_seconds: 0,
+            power_off_seconds: 0,
+            logger_errors: PackedErrors::default(),
+            indicator: IndicatorState::default(),
+            lifetime: LifetimeCounters::default(),
+            compliance: None,
+            epoch_anchor: None,
+        };
+        let mut tvc_integral = 0.0;
+        let in_period = |record: &AggregationRecord| (start.seconds..end.seconds).contains(&record.start.seconds);
+        for record in records.into_iter().filter(in_period) {
+            report.records += 1;
+            report.covered_seconds += record.tvc_seconds;
+            if record.tvc_seconds > 0 {
+                tvc_integral += record.tvc_integral;
+                report.tvc_min = Some(report.tvc_min.map_or(record.tvc_min, |min| min.min(record.tvc_min)));
+                report.tvc_max = Some(report.tvc_max.map_or(record.tvc_max, |max| max.max(record.tvc_max)));
+            }
+            report.high_seconds += record.high_seconds;
+            report.low_seconds += record.low_seconds;
+            report.high_alarm_seconds += record.high_alarm_seconds;
+            report.low_alarm_seconds += record.low_alarm_seconds;
+            report.door_openings += record.door_openings;
+            report.door_open_seconds += record.door_open_seconds;
+            report.power_off_seconds += record.power_off_seconds;
+            report.paused_seconds += record.paused_seconds;
+            for code in record.logger_errors.iter() {
+                report.logger_errors.push(code);
+            }
+        }
+        report.uncovered_seconds = (end.seconds - start.seconds).saturating_sub(report.covered_seconds + report.paused_seconds);
+        report.tvc_average = (report.covered_seconds > 0).then(|| tvc_integral / report.covered_seconds as f32);
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorCode;
+
+    fn record(seconds: u32, tvc: f32, high_seconds: u32) -> AggregationRecord {
+        let mut record = AggregationRecord::new(Timestamp { seconds });
+        record.tvc_seconds = 900;
+        record.tvc_integral = tvc * 900.0;
+        (record.tvc_min, record.tvc_max) = (tvc, tvc);
+        record.high_seconds = high_seconds;
+        record
+    }
+
+    #[test]
+    fn test_report_totals() {
+        let mut faulty = record(1800, 9.0, 900);
+        faulty.logger_errors.push(ErrorCode::FlashFail);
+        let mut defrost = AggregationRecord::new(Timestamp { seconds: 2700 });
+        defrost.paused_seconds = 600;
+        let records = [record(0, 4.0, 0), record(900, 5.0, 0), faulty, defrost, record(3600, 20.0, 900)];
+        let report = Report::generate(records, Timestamp { seconds: 0 }, Timestamp { seconds: 3600 });
+        assert_eq!(report.records, 4);
+        assert_eq!((report.covered_seconds, report.paused_seconds, report.uncovered_seconds), (2700, 600, 300));
+        assert_eq!(report.tvc_average, Some(6.0));
+        assert_eq!((report.tvc_min, report.tvc_max), (Some(4.0), Some(9.0)));
+        assert_eq!(report.high_seconds, 900);
+        assert!(report.logger_errors.contains(ErrorCode::FlashFail));
+        let empty = Report::generate(records, Timestamp { seconds: 7200 }, Timestamp { seconds: 9000 });
+        assert_eq!((empty.records, empty.tvc_average, empty.uncovered_seconds), (0, None, 1800));
+        let backwards = Report::generate(records, Timestamp { seconds: 3600 }, Timestamp { seconds: 0 });
+        assert_eq!((backwards.records, backwards.end, backwards.uncovered_seconds), (0, Timestamp { seconds: 3600 }, 0));
+    }
+
+    #[test]
+    fn test_period_bounds() {
+        // Records starting at the start of the period are in it, those at its end are not.
+        let records = [record(899, 1.0, 0), record(900, 2.0, 0), record(1799, 3.0, 0), record(1800, 4.0, 0)];
+        let report = Report::generate(records, Timestamp { seconds: 900 }, Timestamp { seconds: 1800 });
+        assert_eq!(report.records, 2);
+        assert_eq!((report.tvc_min, report.tvc_max), (Some(2.0), Some(3.0)));
+        // Covered time can exceed a short period; nothing is uncovered then.
+        assert_eq!((report.covered_seconds, report.uncovered_seconds), (1800, 0));
+    }
+
+    #[test]
+    fn test_records_without_readings() {
+        let mut outage = AggregationRecord::new(Timestamp { seconds: 900 });
+        outage.power_off_seconds = 900;
+        outage.logger_errors.push(ErrorCode::SensorFail);
+        let mut faulty = record(0, 5.0, 0);
+        faulty.logger_errors.push(ErrorCode::SensorFail);
+        faulty.logger_errors.push(ErrorCode::ClockAnomaly);
+        let report = Report::generate([faulty, outage], Timestamp { seconds: 0 }, Timestamp { seconds: 1800 });
+        // The outage's empty extremes don't count as readings.
+        assert_eq!((report.tvc_min, report.tvc_max, report.tvc_average), (Some(5.0), Some(5.0), Some(5.0)));
+        assert_eq!((report.power_off_seconds, report.uncovered_seconds), (900, 900));
+        assert_eq!(report.logger_errors.iter().collect::<Vec<_>>(), [ErrorCode::SensorFail, ErrorCode::ClockAnomaly]);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::export::{write_export, ExportChunk, ExportSink};
+use crate::localtime::LocalTime;
+use crate::log::{Log, LogCode};
+use crate::timestamp::Timestamp;
+
+const DAY_SECONDS: u32 = 24 * 60 * 60;
+/// Seconds before the first retry of a report that couldn't be sent, doubled for each retry after.
+pub const REPORT_RETRY_SECONDS: u32 = 60;
+/// Attempts at sending a day's report before it is given up.
+pub const REPORT_MAX_ATTEMPTS: u8 = 6;
+
+/// Generates the daily report at a configured local time, without waiting for a host to ask,
+/// and retries sending it while the link is down.
+///
+/// Each report covers the day up to its scheduled time. A report that is still unsent when the
+/// next one is due is replaced by it; the records stay in the store for a later download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportScheduler {
+    minute_of_day: u16,
+    next: Option<Timestamp>, // When the next report is due. None until the first poll.
+    pending: Option<Pending>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pending {
+    end: Timestamp,
+    attempts: u8,
+    retry_at: Option<Timestamp>, // None while an attempt is in progress.
+}
+
+impl ReportScheduler {
+    /// Report at `minute_of_day` minutes after local midnight, e.g. `Config::daily_report_minute`.
+    pub fn new(minute_of_day: u16) -> Self {
+        Self { minute_of_day, next: None, pending: None }
+    }
+
+    /// Seconds from `now` until `poll` next has something to do, to arm a timer with.
+    pub fn seconds_until(&mut self, now: Timestamp, local: &LocalTime) -> u32 {
+        let next = *self.next.get_or_insert(self.next_due(now, local));
+        let retry = self.pending.and_then(|pending| pending.retry_at).map_or(u32::MAX, |at| at.seconds);
+        next.seconds.min(retry).saturating_sub(now.seconds)
+    }
+
+    /// The period of the report to generate and send now, if one is due or due a retry. Report
+    /// how the attempt went with `sent` or `failed`.
+    pub fn poll(&mut self, now: Timestamp, local: &LocalTime, log: &mut impl Log) -> Option<(Timestamp, Timestamp)> {
+        let next = *self.next.get_or_insert(self.next_due(now, local));
+        // The clock may have been set back, e.g. by a time sync; reschedule from the new time.
+        if now.seconds.saturating_add(DAY_SECONDS) < next.seconds {
+            self.next = Some(self.next_due(now, local));
+        } else if now.seconds >= next.seconds {
+            if let Some(unsent) = self.pending {
+                log.error(LogCode::ReportAbandoned, unsent.end.seconds);
+            }
+            self.pending = Some(Pending { end: next, attempts: 0, retry_at: Some(now) });
+            self.next = Some(self.next_due(now, local));
+        }
+        let pending = self.pending.as_mut()?;
+        if pending.retry_at.is_none_or(|at| now.seconds < at.seconds) {
+            return None;
+        }
+        pending.retry_at = None;
+        Some((Timestamp { seconds: pending.end.seconds.saturating_sub(DAY_SECONDS) }, pending.end))
+    }
+
+    /// The report from the last `poll` was delivered.
+    pub fn sent(&mut self, log: &mut impl Log) {
+        if let Some(pending) = self.pending.take() {
+            log.info(LogCode::ReportSent, pending.end.seconds);
+        }
+    }
+
+    /// The report from the last `poll` couldn't be delivered, e.g. the link was down. It is
+    /// retried later, until `REPORT_MAX_ATTEMPTS`.
+    pub fn failed(&mut self, now: Timestamp, log: &mut impl Log) {
+        let Some(pending) = self.pending.as_mut() else {
+            return;
+        };
+        pending.attempts += 1;
+        if pending.attempts >= REPORT_MAX_ATTEMPTS {
+            log.error(LogCode::ReportAbandoned, pending.end.seconds);
+            self.pending = None;
+            return;
+        }
+        log.warn(LogCode::ReportFailed, u32::from(pending.attempts));
+        let backoff = REPORT_RETRY_SECONDS << (pending.attempts - 1);
+        pending.retry_at = Some(Timestamp { seconds: now.seconds.saturating_add(backoff) });
+    }
+
+    /// Write the report from the last `poll` to `sink`, and note whether that worked.
+    pub async fn send<K: ExportSink>(&mut self, chunks: impl Iterator<Item = ExportChunk>, sink: &mut K, now: Timestamp, log: &mut impl Log) -> Result<u32, K::Error> {
+        let result = write_export(chunks, sink).await;
+        match result {
+            Ok(_) => self.sent(log),
+            Err(_) => self.failed(now, log),
+        }
+        result
+    }
+
+    // The first scheduled time after `now`.
+    fn next_due(&self, now: Timestamp, local: &LocalTime) -> Timestamp {
+        let local_now = local.to_local(now).seconds;
+        let scheduled = u32::from(self.minute_of_day) * 60;
+        let today = local_now - local_now % DAY_SECONDS;
+        let due = if today + scheduled > local_now { today + scheduled } else { today + DAY_SECONDS + scheduled };
+        Timestamp { seconds: now.seconds.saturating_add(due - local_now) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregator::AggregationRecord;
+    use crate::export::ReportChunks;
+    use crate::log::{CaptureLog, Level};
+    use crate::store::{RamStore, RecordStore};
+    use embassy_futures::block_on;
+
+    fn at(seconds: u32) -> Timestamp {
+        Timestamp { seconds }
+    }
+
+    struct Link {
+        up: bool,
+        bytes: Vec<u8>,
+    }
+
+    impl ExportSink for Link {
+        type Error = ();
+
+        async fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), ()> {
+            if !self.up {
+                return Err(());
+            }
+            self.bytes.extend_from_slice(chunk);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_daily_at_local_time() {
+        let local = LocalTime::new(2 * 60).unwrap();
+        let mut scheduler = ReportScheduler::new(6 * 60); // 06:00 local is 04:00 on the epoch.
+        let mut log = CaptureLog::default();
+        assert_eq!(scheduler.seconds_until(at(3600), &local), 3 * 3600);
+        assert_eq!(scheduler.poll(at(4 * 3600 - 1), &local, &mut log), None);
+        assert_eq!(scheduler.poll(at(4 * 3600 + 5), &local, &mut log), Some((at(0), at(4 * 3600))));
+        scheduler.sent(&mut log);
+        assert_eq!(log.entries, [(Level::Info, LogCode::ReportSent, 4 * 3600)]);
+        assert_eq!(scheduler.seconds_until(at(4 * 3600 + 5), &local), DAY_SECONDS - 5);
+        // Set back a day: the report is rescheduled rather than waited for.
+        assert_eq!(scheduler.poll(at(3600), &local, &mut log), None);
+        assert_eq!(scheduler.seconds_until(at(3600), &local), 3 * 3600);
+    }
+
+    #[test]
+    fn test_retries_while_link_down() {
+        let local = LocalTime::default();
+        let mut store: RamStore<8> = RamStore::new();
+        store.append(AggregationRecord::new(at(DAY_SECONDS + 900)));
+        let mut scheduler = ReportScheduler::new(0);
+        let mut link = Link { up: false, bytes: Vec::new() };
+        let mut log = CaptureLog::default();
+        let mut now = at(2 * DAY_SECONDS);
+        let attempt = |scheduler: &mut ReportScheduler, link: &mut Link, now: Timestamp, log: &mut CaptureLog| {
+            let (start, end) = scheduler.poll(now, &local, log)?;
+            Some(block_on(scheduler.send(ReportChunks::new(&store, start, end), link, now, log)))
+        };
+        assert_eq!(scheduler.seconds_until(at(DAY_SECONDS + 1), &local), DAY_SECONDS - 1);
+        assert_eq!(attempt(&mut scheduler, &mut link, now, &mut log), Some(Err(())));
+        assert_eq!(attempt(&mut scheduler, &mut link, now, &mut log), None); // Not yet.
+        assert_eq!(scheduler.seconds_until(now, &local), REPORT_RETRY_SECONDS);
+        now.seconds += REPORT_RETRY_SECONDS;
+        assert_eq!(attempt(&mut scheduler, &mut link, now, &mut log), Some(Err(())));
+        assert_eq!(scheduler.seconds_until(now, &local), 2 * REPORT_RETRY_SECONDS);
+        link.up = true;
+        now.seconds += 2 * REPORT_RETRY_SECONDS;
+        assert!(matches!(attempt(&mut scheduler, &mut link, now, &mut log), Some(Ok(_))));
+        assert!(link.bytes.starts_with(format!("report,{},{},1,", DAY_SECONDS, 2 * DAY_SECONDS).as_bytes()));
+        assert_eq!(
+            log.entries,
+            [
+                (Level::Warn, LogCode::ReportFailed, 1),
+                (Level::Warn, LogCode::ReportFailed, 2),
+                (Level::Info, LogCode::ReportSent, 2 * DAY_SECONDS),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gives_up() {
+        let local = LocalTime::default();
+        let mut scheduler = ReportScheduler::new(0);
+        let mut log = CaptureLog::default();
+        assert_eq!(scheduler.seconds_until(at(DAY_SECONDS - 1), &local), 1);
+        let mut now = at(DAY_SECONDS);
+        for _ in 0..REPORT_MAX_ATTEMPTS {
+            assert!(scheduler.poll(now, &local, &mut log).is_some());
+            scheduler.failed(now, &mut log);
+            now.seconds += 3600;
+        }
+        assert_eq!(log.entries.last(), Some(&(Level::Error, LogCode::ReportAbandoned, DAY_SECONDS)));
+        assert_eq!(scheduler.poll(now, &local, &mut log), None);
+        assert_eq!(scheduler.poll(at(2 * DAY_SECONDS), &local, &mut log), Some((at(DAY_SECONDS), at(2 * DAY_SECONDS))));
+    }
+}
@@ -0,0 +1,46 @@
+use crate::timestamp::Timestamp;
+
+/// How a temperature reading was obtained. A reading without any of these flags was read first
+/// time from its channel's own sensor and used as read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SampleFlag {
+    Retried, // Read after a failed attempt.
+    Substituted, // Stood in for a failed read, e.g. the channel's last good reading or a backup sensor's.
+    Calibrated, // A calibration offset was applied.
+}
+
+impl SampleFlag {
+    /// Bit for this flag in `TemperatureSample::tvc_quality` and `tamb_quality`.
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// One reading of the temperature sensors, taken at `timestamp`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TemperatureSample {
+    pub timestamp: Timestamp,
+    pub tamb: f32, // Ambient temperature, °C.
+    pub tvc: f32, // Vaccine temperature, °C.
+    pub tamb_quality: u8, // Bitmap of `SampleFlag::mask` for `tamb`, 0 for a plain reading.
+    pub tvc_quality: u8, // Bitmap of `SampleFlag::mask` for `tvc`, 0 for a plain reading.
+    #[cfg(feature = "humidity")]
+    pub humidity: Option<f32>, // Relative humidity, %, if the sensor was read successfully.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flag_masks() {
+        let flags = [SampleFlag::Retried, SampleFlag::Substituted, SampleFlag::Calibrated];
+        assert_eq!(flags.map(SampleFlag::mask), [0x01, 0x02, 0x04]);
+        // A reading can carry several flags, e.g. a retried read that was then calibrated.
+        let quality = SampleFlag::Retried.mask() | SampleFlag::Calibrated.mask();
+        let carried: Vec<SampleFlag> = flags.into_iter().filter(|flag| quality & flag.mask() != 0).collect();
+        assert_eq!(carried, [SampleFlag::Retried, SampleFlag::Calibrated]);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::timestamp::Timestamp;
+
+/// Normal time between temperature samples, chosen to save power.
+pub const DEFAULT_NORMAL_PERIOD_SECONDS: u32 = 300;
+/// Time between temperature samples while an excursion may be starting or ending.
+pub const DEFAULT_FAST_PERIOD_SECONDS: u32 = 60;
+/// TVC above this is close enough to the +8 °C limit to sample quickly.
+pub const DEFAULT_HIGH_APPROACH_CELSIUS: f32 = 7.5;
+/// TVC below this is close enough to the +2 °C limit to sample quickly.
+pub const DEFAULT_LOW_APPROACH_CELSIUS: f32 = 2.5;
+
+/// Chooses how long to wait before the next temperature sample.
+///
+/// The sensor task samples slowly while the vaccine temperature (TVC) is comfortably
+/// inside the safe range, and switches to the fast period when TVC is near or outside
+/// a threshold, or when the door is open, so that the onset and recovery of an
+/// excursion are captured precisely.
+///
+/// Samples fall on multiples of the period in wall-clock time, shifted by the phase offset, so
+/// with a 300 s period every device samples at hh:00, hh:05 and so on and their records line up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveSampling {
+    pub normal_period_seconds: u32,
+    pub fast_period_seconds: u32,
+    pub high_approach_celsius: f32,
+    pub low_approach_celsius: f32,
+    pub phase_offset_seconds: u32, // Shift from the wall-clock boundaries, e.g. to spread a fleet's radio traffic.
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self {
+            normal_period_seconds: DEFAULT_NORMAL_PERIOD_SECONDS,
+            fast_period_seconds: DEFAULT_FAST_PERIOD_SECONDS,
+            high_approach_celsius: DEFAULT_HIGH_APPROACH_CELSIUS,
+            low_approach_celsius: DEFAULT_LOW_APPROACH_CELSIUS,
+            phase_offset_seconds: 0,
+        }
+    }
+}
+
+impl AdaptiveSampling {
+    /// Returns true if the fast sampling period should be used.
+    /// `tvc` is None if the last sensor read failed, in which case we retry quickly.
+    pub fn is_fast(&self, tvc: Option<f32>, door_open: bool) -> bool {
+        match tvc {
+            Some(tvc) => door_open || tvc >= self.high_approach_celsius || tvc <= self.low_approach_celsius,
+            None => true,
+        }
+    }
+
+    /// Returns the number of seconds to wait before taking the next sample.
+    pub fn next_period_seconds(&self, tvc: Option<f32>, door_open: bool) -> u32 {
+        if self.is_fast(tvc, door_open) {
+            self.fast_period_seconds
+        } else {
+            self.normal_period_seconds
+        }
+    }
+
+    /// The policy while a burst of samples is being captured around an event: never slower than
+    /// `period_seconds`.
+    pub fn bursting(&self, period_seconds: u32) -> Self {
+        Self {
+            normal_period_seconds: self.normal_period_seconds.min(period_seconds),
+            fast_period_seconds: self.fast_period_seconds.min(period_seconds),
+            ..*self
+        }
+    }
+
+    /// Returns the time of the next sample after `now`: the next boundary of the period chosen by
+    /// `next_period_seconds`, shifted by the phase offset.
+    pub fn next_sample_at(&self, now: Timestamp, tvc: Option<f32>, door_open: bool) -> Timestamp {
+        let period = self.next_period_seconds(tvc, door_open).max(1);
+        let phase = self.phase_offset_seconds % period;
+        let into_period = (now.seconds % period + period - phase) % period;
+        Timestamp { seconds: now.seconds.saturating_add(period - into_period) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normal_period_in_range() {
+        let policy = AdaptiveSampling::default();
+        assert_eq!(policy.next_period_seconds(Some(5.0), false), DEFAULT_NORMAL_PERIOD_SECONDS);
+    }
+
+    #[test]
+    fn test_fast_period_near_or_over_threshold() {
+        let policy = AdaptiveSampling::default();
+        assert_eq!(policy.next_period_seconds(Some(7.6), false), DEFAULT_FAST_PERIOD_SECONDS);
+        assert_eq!(policy.next_period_seconds(Some(9.0), false), DEFAULT_FAST_PERIOD_SECONDS);
+        assert_eq!(policy.next_period_seconds(Some(2.4), false), DEFAULT_FAST_PERIOD_SECONDS);
+        assert_eq!(policy.next_period_seconds(Some(-1.0), false), DEFAULT_FAST_PERIOD_SECONDS);
+    }
+
+    #[test]
+    fn test_fast_period_door_open_or_no_reading() {
+        let policy = AdaptiveSampling::default();
+        assert_eq!(policy.next_period_seconds(Some(5.0), true), DEFAULT_FAST_PERIOD_SECONDS);
+        assert_eq!(policy.next_period_seconds(None, false), DEFAULT_FAST_PERIOD_SECONDS);
+    }
+
+    #[test]
+    fn test_next_sample_on_boundaries() {
+        let at = |seconds| Timestamp { seconds };
+        let policy = AdaptiveSampling::default();
+        assert_eq!(policy.next_sample_at(at(3600), Some(5.0), false), at(3900));
+        assert_eq!(policy.next_sample_at(at(3601), Some(5.0), false), at(3900));
+        assert_eq!(policy.next_sample_at(at(3899), Some(5.0), false), at(3900));
+        assert_eq!(policy.next_sample_at(at(3601), Some(9.0), false), at(3660));
+        let policy = AdaptiveSampling { phase_offset_seconds: 30, ..policy };
+        assert_eq!(policy.next_sample_at(at(3600), Some(5.0), false), at(3630));
+        assert_eq!(policy.next_sample_at(at(3630), Some(5.0), false), at(3930));
+        assert_eq!(policy.next_sample_at(at(10), Some(9.0), false), at(30));
+        assert_eq!(policy.next_sample_at(at(3645), Some(9.0), false), at(3690));
+    }
+}
@@ -0,0 +1,102 @@
+/// Warming of the sensors per second of rail on-time, before it decays. None until it has been
+/// measured on the board, so readings are left as they are.
+pub const DEFAULT_SELF_HEATING_CELSIUS_PER_SECOND: f32 = 0.0;
+/// Time for the warming to decay to about a third once the rail is off.
+pub const DEFAULT_SELF_HEATING_TIME_CONSTANT_SECONDS: u32 = 20;
+
+/// Coefficients of a first-order model of how the sensors warm themselves while powered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SelfHeatingModel {
+    pub celsius_per_second: f32,
+    pub time_constant_seconds: u32,
+}
+
+impl Default for SelfHeatingModel {
+    fn default() -> Self {
+        Self {
+            celsius_per_second: DEFAULT_SELF_HEATING_CELSIUS_PER_SECOND,
+            time_constant_seconds: DEFAULT_SELF_HEATING_TIME_CONSTANT_SECONDS,
+        }
+    }
+}
+
+impl SelfHeatingModel {
+    /// A model that leaves readings unchanged.
+    pub const NONE: SelfHeatingModel = SelfHeatingModel { celsius_per_second: 0.0, time_constant_seconds: 1 };
+
+    pub fn is_valid(&self) -> bool {
+        self.celsius_per_second >= 0.0 && self.celsius_per_second.is_finite() && self.time_constant_seconds > 0
+    }
+}
+
+/// Estimates the self-heating offset from recent rail on-time and removes it from readings.
+///
+/// Each power-up of the sensor rail adds warming in proportion to how long it was on, and the
+/// warming decays exponentially while the rail is off. Readings are taken at the end of an on-time,
+/// so they include its warming.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SelfHeating {
+    model: SelfHeatingModel,
+    offset_celsius: f32,
+}
+
+impl SelfHeating {
+    pub fn new(model: SelfHeatingModel) -> Self {
+        Self { model, offset_celsius: 0.0 }
+    }
+
+    /// Note that the rail was on for `on_ms`, after being off for `off_ms`. Returns the offset now.
+    pub fn powered(&mut self, off_ms: u32, on_ms: u32) -> f32 {
+        let x = off_ms as f32 / (self.model.time_constant_seconds as f32 * 1000.0);
+        self.offset_celsius *= decay(x);
+        self.offset_celsius += self.model.celsius_per_second * on_ms as f32 / 1000.0;
+        self.offset_celsius
+    }
+
+    /// Estimated warming of the sensors in °C.
+    pub fn offset_celsius(&self) -> f32 {
+        self.offset_celsius
+    }
+
+    /// A reading taken at the end of the last on-time, with the warming removed.
+    pub fn compensate(&self, celsius: f32) -> f32 {
+        celsius - self.offset_celsius
+    }
+}
+
+// e^-x for x ≥ 0, from a truncated series of e^x. Exact enough for a correction of a few
+// hundredths of a degree, and like e^-x it decreases from 1 towards 0.
+fn decay(x: f32) -> f32 {
+    1.0 / (1.0 + x + x * x / 2.0 + x * x * x / 6.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_builds_up_and_decays() {
+        let mut heating = SelfHeating::new(SelfHeatingModel { celsius_per_second: 0.5, ..SelfHeatingModel::default() });
+        assert_eq!(heating.powered(u32::MAX, 60), 0.03);
+        assert!((heating.compensate(5.03) - 5.0).abs() < 1e-6);
+        // Sampling every few seconds keeps some of the last warming.
+        let offset = heating.powered(2_000, 60);
+        assert!(offset > 0.055 && offset < 0.06, "{}", offset);
+        // After many time constants off only the new on-time counts.
+        assert!((heating.powered(600_000, 60) - 0.03).abs() < 1e-4);
+        assert!((decay(1.0) - (-1.0f32).exp()).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_no_model() {
+        for model in [SelfHeatingModel::NONE, SelfHeatingModel::default()] {
+            let mut heating = SelfHeating::new(model);
+            heating.powered(0, 10_000);
+            assert_eq!(heating.compensate(4.0), 4.0);
+        }
+        assert!(SelfHeatingModel::NONE.is_valid() && SelfHeatingModel::default().is_valid());
+        assert!(!SelfHeatingModel { celsius_per_second: -0.1, ..SelfHeatingModel::default() }.is_valid());
+        assert!(!SelfHeatingModel { time_constant_seconds: 0, ..SelfHeatingModel::default() }.is_valid());
+    }
+}
@@ -0,0 +1,145 @@
+use arrayvec::ArrayString;
+use core::fmt::Write;
+
+/// Hardware exercised by the self-test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SelfTestItem {
+    Sensors,
+    Rtc,
+    Flash,
+    Button,
+    LedBuzzer,
+    Adc,
+}
+
+impl SelfTestItem {
+    pub const COUNT: usize = 6;
+    pub const ALL: [SelfTestItem; SelfTestItem::COUNT] = [
+        SelfTestItem::Sensors,
+        SelfTestItem::Rtc,
+        SelfTestItem::Flash,
+        SelfTestItem::Button,
+        SelfTestItem::LedBuzzer,
+        SelfTestItem::Adc,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SelfTestItem::Sensors => "SENSORS",
+            SelfTestItem::Rtc => "RTC",
+            SelfTestItem::Flash => "FLASH",
+            SelfTestItem::Button => "BUTTON",
+            SelfTestItem::LedBuzzer => "LED/BUZZER",
+            SelfTestItem::Adc => "ADC",
+        }
+    }
+
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Pass/fail results of a self-test run, one bit per `SelfTestItem`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SelfTestReport {
+    tested: u8,
+    failed: u8,
+}
+
+impl SelfTestReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, item: SelfTestItem, passed: bool) {
+        self.tested |= item.mask();
+        if passed {
+            self.failed &= !item.mask();
+        } else {
+            self.failed |= item.mask();
+        }
+    }
+
+    /// Result for one item, or None if it wasn't tested.
+    pub fn result(&self, item: SelfTestItem) -> Option<bool> {
+        (self.tested & item.mask() != 0).then_some(self.failed & item.mask() == 0)
+    }
+
+    /// Bitmap of the items that passed.
+    pub fn passed_bitmap(&self) -> u8 {
+        self.tested & !self.failed
+    }
+
+    /// Bitmap of the items that failed.
+    pub fn failed_bitmap(&self) -> u8 {
+        self.failed
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.failed == 0
+    }
+
+    /// One-line summary with the pass and fail bitmaps in hex, e.g. "SELFTEST PASS 3F/00".
+    pub fn summary(&self) -> ArrayString<32> {
+        let mut summary = ArrayString::new();
+        let verdict = if self.all_passed() { "PASS" } else { "FAIL" };
+        let _ = write!(summary, "SELFTEST {} {:02X}/{:02X}", verdict, self.passed_bitmap(), self.failed_bitmap());
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut report = SelfTestReport::new();
+        assert_eq!(report.result(SelfTestItem::Rtc), None);
+        for item in SelfTestItem::ALL {
+            report.record(item, true);
+        }
+        assert_eq!(report.summary().as_str(), "SELFTEST PASS 3F/00");
+        report.record(SelfTestItem::Flash, false);
+        assert_eq!(report.result(SelfTestItem::Flash), Some(false));
+        assert_eq!(report.result(SelfTestItem::Rtc), Some(true));
+        assert!(!report.all_passed());
+        assert_eq!(report.summary().as_str(), "SELFTEST FAIL 3B/04");
+    }
+
+    #[test]
+    fn test_each_failure_bit() {
+        let bits = [
+            (SelfTestItem::Sensors, 0x01),
+            (SelfTestItem::Rtc, 0x02),
+            (SelfTestItem::Flash, 0x04),
+            (SelfTestItem::Button, 0x08),
+            (SelfTestItem::LedBuzzer, 0x10),
+            (SelfTestItem::Adc, 0x20),
+        ];
+        for (item, bit) in bits {
+            let mut report = SelfTestReport::new();
+            for other in SelfTestItem::ALL {
+                report.record(other, other != item);
+            }
+            assert_eq!((report.passed_bitmap(), report.failed_bitmap()), (0x3F & !bit, bit), "{}", item.name());
+            assert_eq!(report.result(item), Some(false));
+            assert!(!report.all_passed());
+            assert_eq!(report.summary().as_str(), format!("SELFTEST FAIL {:02X}/{:02X}", 0x3F & !bit, bit));
+        }
+    }
+
+    #[test]
+    fn test_untested_and_retested_items() {
+        let mut report = SelfTestReport::new();
+        report.record(SelfTestItem::Adc, false);
+        // Items not tested neither pass nor fail.
+        assert_eq!((report.passed_bitmap(), report.failed_bitmap()), (0x00, 0x20));
+        assert_eq!(report.result(SelfTestItem::Sensors), None);
+        report.record(SelfTestItem::Adc, true);
+        assert_eq!(report.result(SelfTestItem::Adc), Some(true));
+        assert!(report.all_passed());
+        assert_eq!(report.summary().as_str(), "SELFTEST PASS 20/00");
+    }
+}
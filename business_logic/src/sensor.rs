@@ -0,0 +1,239 @@
+use crate::hal::{DelayNs, I2c};
+use crate::sample::SampleFlag;
+#[cfg(feature = "humidity")]
+use embassy_futures::join::join;
+#[cfg(feature = "humidity")]
+use crate::humidity::{sht4x_relative_humidity, SHT4X_MEASURE_HIGH_PRECISION, SHT4X_MEASUREMENT_TIME_MS};
+
+/// Register holding the temperature reading of the TMP117 sensors.
+pub const TEMPERATURE_REGISTER: u8 = 0x00;
+/// Temperature of one least significant bit of a TMP117 reading, °C.
+const TEMPERATURE_LSB_CELSIUS: f32 = 0.0078125;
+/// I2C address of the SHT4x humidity sensor (C variant, to avoid 0x44/0x45).
+#[cfg(feature = "humidity")]
+pub const HUMIDITY_ADDRESS: u8 = 0x46;
+
+/// Why a sensor read failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SensorError {
+    Bus, // The I2C transfer failed.
+    Crc, // The reading arrived corrupted.
+}
+
+/// Readings from one pass over all the sensors. Each sensor is read whether or not the others
+/// failed, so a fault on one bus shows against that sensor alone, and a failed temperature read
+/// is tried once more.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Acquisition {
+    pub ambient: Result<f32, SensorError>, // °C.
+    pub vaccine: Result<f32, SensorError>, // °C.
+    pub ambient_quality: u8, // Bitmap of `SampleFlag::mask` for `ambient`.
+    pub vaccine_quality: u8, // Bitmap of `SampleFlag::mask` for `vaccine`.
+    #[cfg(feature = "humidity")]
+    pub humidity: Result<f32, SensorError>, // On the ambient sensor's bus.
+}
+
+impl Acquisition {
+    /// (ambient, vaccine) in °C, if both were read.
+    pub fn temperatures(&self) -> Result<(f32, f32), SensorError> {
+        Ok((self.ambient?, self.vaccine?))
+    }
+
+    /// Stand `ambient`, e.g. the last good ambient reading, in for a failed one, flagged
+    /// `SampleFlag::Substituted`, so a fault on the ambient sensor's bus doesn't stop the vaccine
+    /// readings being logged. The vaccine reading is never substituted, since the alarms follow it.
+    pub fn substitute_ambient(&mut self, ambient: Option<f32>) {
+        if let (Err(_), Some(ambient)) = (self.ambient, ambient) {
+            self.ambient = Ok(ambient);
+            self.ambient_quality |= SampleFlag::Substituted.mask();
+        }
+    }
+}
+
+/// The ambient and vaccine temperature sensors, each on its own bus handle.
+///
+/// The handles may share one bus, or the vaccine sensor may be wired to a separate I2C
+/// peripheral, so that a lockup of the ambient sensor's bus can't stop vaccine readings.
+/// The humidity sensor is on the ambient sensor's bus.
+///
+/// The caller is responsible for powering the sensors before reading them.
+pub struct DualTempSensor<A, V = A> {
+    amb_i2c: A,
+    amb_address: u8,
+    vax_i2c: V,
+    vax_address: u8,
+}
+
+impl<A, V> DualTempSensor<A, V> {
+    pub fn new(amb_i2c: A, amb_address: u8, vax_i2c: V, vax_address: u8) -> Self {
+        Self { amb_i2c, amb_address, vax_i2c, vax_address }
+    }
+
+    /// The ambient sensor's bus, e.g. to change its speed.
+    pub fn ambient_i2c_mut(&mut self) -> &mut A {
+        &mut self.amb_i2c
+    }
+
+    /// The vaccine sensor's bus.
+    pub fn vaccine_i2c_mut(&mut self) -> &mut V {
+        &mut self.vax_i2c
+    }
+}
+
+impl<A: I2c, V: I2c> DualTempSensor<A, V> {
+    /// Read (ambient, vaccine) temperatures in °C.
+    pub async fn read_temperature_celsius(&mut self) -> Result<(f32, f32), SensorError> {
+        let ((amb, _), (vax, _)) = self.read_both().await;
+        Ok((amb?, vax?))
+    }
+
+    // Read both sensors, the second even if the first failed, with their `SampleFlag` bitmaps.
+    async fn read_both(&mut self) -> ((Result<f32, SensorError>, u8), (Result<f32, SensorError>, u8)) {
+        let amb = read_retrying(&mut self.amb_i2c, self.amb_address).await;
+        let vax = read_retrying(&mut self.vax_i2c, self.vax_address).await;
+        (amb, vax)
+    }
+
+    /// Read every sensor in one pass, for a single power-up of the sensor rail. The temperatures
+    /// are read while the humidity sensor converts, so the pass lasts about one conversion.
+    #[cfg_attr(not(feature = "humidity"), allow(unused_variables))]
+    pub async fn acquire(&mut self, delay: &mut impl DelayNs) -> Acquisition {
+        #[cfg(feature = "humidity")]
+        {
+            let started = self.amb_i2c.write(HUMIDITY_ADDRESS, &[SHT4X_MEASURE_HIGH_PRECISION]).await.or(Err(SensorError::Bus));
+            let conversion = delay.delay_ms(SHT4X_MEASUREMENT_TIME_MS as u32);
+            let ((), ((ambient, ambient_quality), (vaccine, vaccine_quality))) = join(conversion, self.read_both()).await;
+            let humidity = match started {
+                Ok(()) => self.read_humidity_result().await,
+                Err(error) => Err(error),
+            };
+            Acquisition { ambient, vaccine, ambient_quality, vaccine_quality, humidity }
+        }
+        #[cfg(not(feature = "humidity"))]
+        {
+            let ((ambient, ambient_quality), (vaccine, vaccine_quality)) = self.read_both().await;
+            Acquisition { ambient, vaccine, ambient_quality, vaccine_quality }
+        }
+    }
+
+    /// Read relative humidity in %.
+    #[cfg(feature = "humidity")]
+    pub async fn read_relative_humidity(&mut self, delay: &mut impl DelayNs) -> Result<f32, SensorError> {
+        self.amb_i2c.write(HUMIDITY_ADDRESS, &[SHT4X_MEASURE_HIGH_PRECISION]).await.or(Err(SensorError::Bus))?;
+        delay.delay_ms(SHT4X_MEASUREMENT_TIME_MS as u32).await;
+        self.read_humidity_result().await
+    }
+
+    #[cfg(feature = "humidity")]
+    async fn read_humidity_result(&mut self) -> Result<f32, SensorError> {
+        let mut buf = [0u8; 6];
+        self.amb_i2c.read(HUMIDITY_ADDRESS, &mut buf).await.or(Err(SensorError::Bus))?;
+        sht4x_relative_humidity(&buf).ok_or(SensorError::Crc)
+    }
+}
+
+// Read a sensor, once more if the first read fails, with the `SampleFlag` bitmap of the reading.
+async fn read_retrying(i2c: &mut impl I2c, address: u8) -> (Result<f32, SensorError>, u8) {
+    match read_celsius(i2c, address).await {
+        Err(_) => (read_celsius(i2c, address).await, SampleFlag::Retried.mask()),
+        reading => (reading, 0),
+    }
+}
+
+async fn read_celsius(i2c: &mut impl I2c, address: u8) -> Result<f32, SensorError> {
+    let mut buf = [0u8; 2];
+    i2c.write_read(address, &[TEMPERATURE_REGISTER], &mut buf).await.or(Err(SensorError::Bus))?;
+    Ok(f32::from(i16::from_be_bytes(buf)) * TEMPERATURE_LSB_CELSIUS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    #[test]
+    fn test_read_temperatures() {
+        let expectations = [
+            Transaction::write_read(0x48, vec![TEMPERATURE_REGISTER], vec![0x0C, 0x80]), // 25 °C
+            Transaction::write_read(0x49, vec![TEMPERATURE_REGISTER], vec![0xFE, 0x00]), // -4 °C
+        ];
+        // Both sensors on one bus: the handles share the mock's expectations.
+        let bus = Mock::new(&expectations);
+        let mut sensor = DualTempSensor::new(bus.clone(), 0x48, bus, 0x49);
+        assert_eq!(block_on(sensor.read_temperature_celsius()), Ok((25.0, -4.0)));
+        sensor.ambient_i2c_mut().done();
+    }
+
+    #[test]
+    fn test_bus_error() {
+        use embedded_hal_mock::eh1::delay::NoopDelay;
+        // The ambient sensor's bus fails, retry and all; the vaccine sensor on its own bus is
+        // still read, at the second attempt.
+        let failed = |address| Transaction::write_read(address, vec![TEMPERATURE_REGISTER], vec![0, 0]).with_error(embedded_hal::i2c::ErrorKind::Other);
+        let ambient = [failed(0x48), failed(0x48)];
+        let vaccine = [failed(0x49), Transaction::write_read(0x49, vec![TEMPERATURE_REGISTER], vec![0x02, 0x80])]; // 5 °C
+        #[cfg(feature = "humidity")]
+        let ambient = [
+            vec![Transaction::write(HUMIDITY_ADDRESS, vec![SHT4X_MEASURE_HIGH_PRECISION]).with_error(embedded_hal::i2c::ErrorKind::Other)],
+            ambient.to_vec(),
+        ]
+        .concat();
+        let mut sensor = DualTempSensor::new(Mock::new(&ambient), 0x48, Mock::new(&vaccine), 0x49);
+        let mut acquisition = block_on(sensor.acquire(&mut NoopDelay));
+        assert_eq!((acquisition.ambient, acquisition.vaccine), (Err(SensorError::Bus), Ok(5.0)));
+        assert_eq!(acquisition.vaccine_quality, SampleFlag::Retried.mask());
+        assert_eq!(acquisition.temperatures(), Err(SensorError::Bus));
+        // The last good ambient reading stands in, so the vaccine reading can be logged.
+        acquisition.substitute_ambient(Some(21.0));
+        assert_eq!(acquisition.temperatures(), Ok((21.0, 5.0)));
+        assert_eq!(acquisition.ambient_quality, SampleFlag::Retried.mask() | SampleFlag::Substituted.mask());
+        sensor.ambient_i2c_mut().done();
+        sensor.vaccine_i2c_mut().done();
+    }
+
+    #[test]
+    fn test_acquire() {
+        use embedded_hal_mock::eh1::delay::NoopDelay;
+        let temperatures = vec![
+            Transaction::write_read(0x48, vec![TEMPERATURE_REGISTER], vec![0x0C, 0x80]),
+            Transaction::write_read(0x49, vec![TEMPERATURE_REGISTER], vec![0x02, 0x80]), // 5 °C
+        ];
+        #[cfg(not(feature = "humidity"))]
+        let expectations = temperatures;
+        // The temperatures are read between starting the humidity conversion and reading its result.
+        #[cfg(feature = "humidity")]
+        let expectations = {
+            let frame = vec![0x66, 0x66, 0x00, 0x72, 0xB0, crate::humidity::sht4x_crc(&[0x72, 0xB0])];
+            let start = Transaction::write(HUMIDITY_ADDRESS, vec![SHT4X_MEASURE_HIGH_PRECISION]);
+            [vec![start], temperatures, vec![Transaction::read(HUMIDITY_ADDRESS, frame)]].concat()
+        };
+        let bus = Mock::new(&expectations);
+        let mut sensor = DualTempSensor::new(bus.clone(), 0x48, bus, 0x49);
+        let acquisition = block_on(sensor.acquire(&mut NoopDelay));
+        assert_eq!(acquisition.temperatures(), Ok((25.0, 5.0)));
+        assert_eq!((acquisition.ambient_quality, acquisition.vaccine_quality), (0, 0));
+        #[cfg(feature = "humidity")]
+        assert!((acquisition.humidity.unwrap() - 50.0).abs() < 0.01);
+        sensor.ambient_i2c_mut().done();
+    }
+
+    #[cfg(feature = "humidity")]
+    #[test]
+    fn test_read_humidity() {
+        use embedded_hal_mock::eh1::delay::NoopDelay;
+        // 50 %RH with its CRC, after an unchecked temperature word.
+        let frame = vec![0x66, 0x66, 0x00, 0x72, 0xB0, crate::humidity::sht4x_crc(&[0x72, 0xB0])];
+        let expectations = [
+            Transaction::write(HUMIDITY_ADDRESS, vec![SHT4X_MEASURE_HIGH_PRECISION]),
+            Transaction::read(HUMIDITY_ADDRESS, frame),
+        ];
+        let mut sensor = DualTempSensor::new(Mock::new(&expectations), 0x48, Mock::new(&[]), 0x49);
+        let humidity = block_on(sensor.read_relative_humidity(&mut NoopDelay)).unwrap();
+        assert!((humidity - 50.0).abs() < 0.01);
+        sensor.ambient_i2c_mut().done();
+        sensor.vaccine_i2c_mut().done();
+    }
+}
@@ -0,0 +1,144 @@
+use crate::aggregator::AggregationRecord;
+use crate::crc::crc32;
+use crate::timestamp::Timestamp;
+
+/// Words in a serialized `PowerFailCheckpoint`, including its CRC.
+pub const CHECKPOINT_WORDS: usize = 10;
+
+/// The main fields of the record in progress when the supply failed, small enough for the
+/// battery-backed RTC registers, so the minutes since the last stored record survive.
+///
+/// The temperatures of the vaccine channel, the alarm times and the door and power counts are
+/// kept; the ambient channel and the time-in-band histogram are not.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PowerFailCheckpoint {
+    pub start: Timestamp,
+    pub tvc_seconds: u32,
+    pub tvc_integral: f32,
+    pub tvc_min: f32,
+    pub tvc_max: f32,
+    pub high_alarm_seconds: u32,
+    pub low_alarm_seconds: u32,
+    pub door_openings: u32,
+    pub power_off_seconds: u32,
+}
+
+impl PowerFailCheckpoint {
+    pub fn from_record(record: &AggregationRecord) -> Self {
+        Self {
+            start: record.start,
+            tvc_seconds: record.tvc_seconds,
+            tvc_integral: record.tvc_integral,
+            tvc_min: record.tvc_min,
+            tvc_max: record.tvc_max,
+            high_alarm_seconds: record.high_alarm_seconds,
+            low_alarm_seconds: record.low_alarm_seconds,
+            door_openings: record.door_openings,
+            power_off_seconds: record.power_off_seconds,
+        }
+    }
+
+    /// The record to store after the restart, without the fields that weren't kept.
+    pub fn to_record(&self) -> AggregationRecord {
+        AggregationRecord {
+            tvc_seconds: self.tvc_seconds,
+            tvc_integral: self.tvc_integral,
+            tvc_min: self.tvc_min,
+            tvc_max: self.tvc_max,
+            high_alarm_seconds: self.high_alarm_seconds,
+            low_alarm_seconds: self.low_alarm_seconds,
+            door_openings: self.door_openings,
+            power_off_seconds: self.power_off_seconds,
+            ..AggregationRecord::new(self.start)
+        }
+    }
+
+    pub fn to_words(&self) -> [u32; CHECKPOINT_WORDS] {
+        let mut words = [
+            self.start.seconds,
+            self.tvc_seconds,
+            self.tvc_integral.to_bits(),
+            self.tvc_min.to_bits(),
+            self.tvc_max.to_bits(),
+            self.high_alarm_seconds,
+            self.low_alarm_seconds,
+            self.door_openings,
+            self.power_off_seconds,
+            0,
+        ];
+        words[CHECKPOINT_WORDS - 1] = checksum(&words);
+        words
+    }
+
+    /// Decode words written by `to_words`. Returns None if the CRC doesn't match, e.g. after the
+    /// checkpoint was cleared or the backup domain lost power.
+    pub fn from_words(words: &[u32; CHECKPOINT_WORDS]) -> Option<Self> {
+        if checksum(words) != words[CHECKPOINT_WORDS - 1] {
+            return None;
+        }
+        Some(Self {
+            start: Timestamp { seconds: words[0] },
+            tvc_seconds: words[1],
+            tvc_integral: f32::from_bits(words[2]),
+            tvc_min: f32::from_bits(words[3]),
+            tvc_max: f32::from_bits(words[4]),
+            high_alarm_seconds: words[5],
+            low_alarm_seconds: words[6],
+            door_openings: words[7],
+            power_off_seconds: words[8],
+        })
+    }
+}
+
+// CRC-32 over all words but the last, which holds it.
+fn checksum(words: &[u32; CHECKPOINT_WORDS]) -> u32 {
+    words[..CHECKPOINT_WORDS - 1].iter().fold(0, |crc, word| crc32(crc, &word.to_le_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let record = AggregationRecord {
+            tvc_seconds: 420,
+            tvc_integral: 420.0 * 5.0,
+            tvc_min: 4.5,
+            tvc_max: 5.5,
+            tamb_max: 25.0,
+            door_openings: 2,
+            ..AggregationRecord::new(Timestamp { seconds: 900 })
+        };
+        let checkpoint = PowerFailCheckpoint::from_record(&record);
+        let words = checkpoint.to_words();
+        assert_eq!(PowerFailCheckpoint::from_words(&words), Some(checkpoint));
+        let restored = checkpoint.to_record();
+        assert_eq!(restored, AggregationRecord { tamb_max: 0.0, ..record });
+        let mut damaged = words;
+        damaged[7] = 3;
+        assert_eq!(PowerFailCheckpoint::from_words(&damaged), None);
+        assert_eq!(PowerFailCheckpoint::from_words(&[0; CHECKPOINT_WORDS]), None); // Cleared.
+    }
+
+    #[test]
+    fn test_any_damaged_word_rejected() {
+        let record = AggregationRecord { tvc_seconds: 60, tvc_integral: 300.0, tvc_min: 5.0, tvc_max: 5.0, ..AggregationRecord::new(Timestamp { seconds: 900 }) };
+        let words = PowerFailCheckpoint::from_record(&record).to_words();
+        for i in 0..CHECKPOINT_WORDS {
+            let mut damaged = words;
+            damaged[i] ^= 1 << (i % 32);
+            assert_eq!(PowerFailCheckpoint::from_words(&damaged), None, "word {}", i);
+        }
+    }
+
+    #[test]
+    fn test_record_without_readings() {
+        // A record cut short before its first reading keeps its empty extremes and the outage.
+        let record = AggregationRecord { power_off_seconds: 120, ..AggregationRecord::new(Timestamp { seconds: u32::MAX - 899 }) };
+        let checkpoint = PowerFailCheckpoint::from_words(&PowerFailCheckpoint::from_record(&record).to_words()).unwrap();
+        assert_eq!((checkpoint.tvc_min, checkpoint.tvc_max), (record.tvc_min, record.tvc_max));
+        assert_eq!(checkpoint.to_record(), record);
+    }
+}
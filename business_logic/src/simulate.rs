@@ -0,0 +1,137 @@
+use arrayvec::ArrayVec;
+
+use crate::errors::ErrorCode;
+use crate::log::{Log, LogCode};
+use crate::logger::LoggerEvent;
+use crate::sample::TemperatureSample;
+use crate::timestamp::Timestamp;
+
+/// Ambient temperature of a simulated reading unless one is given, °C.
+pub const SIMULATED_AMBIENT_CELSIUS: f32 = 25.0;
+
+/// A `simulate` console command, for checking the alarms and records during commissioning.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SimulateCommand {
+    Temperature { tvc: f32, tamb: f32 }, // `simulate temp <vaccine> [ambient]`, °C.
+    Door { open: bool }, // `simulate door open|closed`.
+    Power { on: bool }, // `simulate power on|off`.
+}
+
+/// Why a `simulate` command was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SimulateError {
+    TestModeOff, // `Config::test_mode` isn't set.
+    UnknownCommand,
+    BadArgument, // Missing, or not a number or state.
+}
+
+impl SimulateCommand {
+    /// Parse a console line such as `simulate temp 9.5` or `simulate door open`.
+    pub fn parse(line: &str) -> Result<Self, SimulateError> {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("simulate") {
+            return Err(SimulateError::UnknownCommand);
+        }
+        let command = match (words.next(), words.next()) {
+            (Some("temp"), Some(tvc)) => {
+                let celsius = |word: &str| word.parse::<f32>().ok().filter(|value| value.is_finite()).ok_or(SimulateError::BadArgument);
+                let tamb = words.next().map_or(Ok(SIMULATED_AMBIENT_CELSIUS), celsius)?;
+                SimulateCommand::Temperature { tvc: celsius(tvc)?, tamb }
+            }
+            (Some("door"), Some("open")) => SimulateCommand::Door { open: true },
+            (Some("door"), Some("closed")) => SimulateCommand::Door { open: false },
+            (Some("power"), Some("on")) => SimulateCommand::Power { on: true },
+            (Some("power"), Some("off")) => SimulateCommand::Power { on: false },
+            (Some("temp" | "door" | "power"), _) => return Err(SimulateError::BadArgument),
+            _ => return Err(SimulateError::UnknownCommand),
+        };
+        match words.next() {
+            Some(_) => Err(SimulateError::BadArgument),
+            None => Ok(command),
+        }
+    }
+
+    /// The events to inject at `now` through the normal pipeline, if `test_mode` allows it.
+    ///
+    /// Each injection is logged as `SimulatedEvent`, and a `Simulated` fault comes first so the
+    /// record it lands in is marked, and can't be mistaken for a real excursion.
+    pub fn inject(&self, test_mode: bool, now: Timestamp, log: &mut impl Log) -> Result<ArrayVec<LoggerEvent, 2>, SimulateError> {
+        if !test_mode {
+            return Err(SimulateError::TestModeOff);
+        }
+        let (code, event) = match *self {
+            SimulateCommand::Temperature { tvc, tamb } => (
+                1,
+                LoggerEvent::Sample(TemperatureSample {
+                    timestamp: now,
+                    tamb,
+                    tvc,
+                    tamb_quality: 0,
+                    tvc_quality: 0,
+                    #[cfg(feature = "humidity")]
+                    humidity: None,
+                }),
+            ),
+            SimulateCommand::Door { open: true } => (2, LoggerEvent::DoorOpened(now)),
+            SimulateCommand::Door { open: false } => (3, LoggerEvent::DoorClosed(now)),
+            SimulateCommand::Power { on: false } => (4, LoggerEvent::PowerLost(now)),
+            SimulateCommand::Power { on: true } => (5, LoggerEvent::PowerRestored(now)),
+        };
+        log.warn(LogCode::SimulatedEvent, code);
+        Ok(ArrayVec::from([LoggerEvent::Fault(now, ErrorCode::Simulated), event]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::{AlarmKind, AlarmProfile};
+    use crate::lifecycle::LifecycleState;
+    use crate::log::{CaptureLog, Level};
+    use crate::logger::Logger;
+    use crate::logger_task::{AlarmOutput, EventNumbers, LoggerTask};
+    use crate::store::{RamStore, RecordStore};
+    use embassy_futures::block_on;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(SimulateCommand::parse("simulate temp 9.5"), Ok(SimulateCommand::Temperature { tvc: 9.5, tamb: 25.0 }));
+        assert_eq!(SimulateCommand::parse("simulate  temp -1 30"), Ok(SimulateCommand::Temperature { tvc: -1.0, tamb: 30.0 }));
+        assert_eq!(SimulateCommand::parse("simulate door open"), Ok(SimulateCommand::Door { open: true }));
+        assert_eq!(SimulateCommand::parse("simulate power off"), Ok(SimulateCommand::Power { on: false }));
+        assert_eq!(SimulateCommand::parse("simulate temp nan"), Err(SimulateError::BadArgument));
+        assert_eq!(SimulateCommand::parse("simulate door ajar"), Err(SimulateError::BadArgument));
+        assert_eq!(SimulateCommand::parse("simulate power off now"), Err(SimulateError::BadArgument));
+        assert_eq!(SimulateCommand::parse("simulate humidity 50"), Err(SimulateError::UnknownCommand));
+        assert_eq!(SimulateCommand::parse("status"), Err(SimulateError::UnknownCommand));
+    }
+
+    #[derive(Default)]
+    struct Alarms(Vec<(AlarmKind, bool)>);
+
+    impl AlarmOutput for Alarms {
+        async fn set_active(&mut self, kind: AlarmKind, active: bool) {
+            self.0.push((kind, active));
+        }
+    }
+
+    #[test]
+    fn test_injected_through_pipeline_and_marked() {
+        let mut task: LoggerTask<RamStore<4>> = LoggerTask::new(Logger::default(), AlarmProfile::FRIDGE, LifecycleState::Logging, RamStore::new());
+        let (mut alarms, mut log, numbers) = (Alarms::default(), CaptureLog::default(), EventNumbers::new());
+        let command = SimulateCommand::parse("simulate temp 9.5").unwrap();
+        let now = Timestamp { seconds: 60 };
+        assert_eq!(command.inject(false, now, &mut log), Err(SimulateError::TestModeOff));
+        for event in command.inject(true, now, &mut log).unwrap() {
+            block_on(task.handle(numbers.number(event), &mut alarms, &mut log));
+        }
+        assert_eq!(alarms.0, [(AlarmKind::HighTemp, true)]);
+        assert_eq!(log.entries[0], (Level::Warn, LogCode::SimulatedEvent, 1));
+        task.flush(&mut log);
+        let record = task.store().get(0).unwrap();
+        assert!(record.logger_errors.contains(ErrorCode::Simulated));
+        assert_eq!(record.tvc_max, 9.5);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::timestamp::Timestamp;
+
+/// Bucket length of the 24-hour window.
+pub const DAY_BUCKET_SECONDS: u32 = 3600;
+/// Bucket length of the 7-day window.
+pub const WEEK_BUCKET_SECONDS: u32 = 6 * 3600;
+
+/// Running minimum, maximum, and average of a series of readings.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MinMaxAvg {
+    count: u32,
+    sum: f32,
+    min: f32,
+    max: f32,
+}
+
+impl MinMaxAvg {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a reading to the statistics.
+    pub fn add(&mut self, value: f32) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Number of readings added since the last reset.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn min(&self) -> Option<f32> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<f32> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    /// Average of the readings, or None if there are none.
+    pub fn avg(&self) -> Option<f32> {
+        (self.count > 0).then(|| self.sum / self.count as f32)
+    }
+
+    /// Clear the statistics, e.g. at the start of a new record.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Combine with statistics of other readings.
+    pub fn merge(&mut self, other: &MinMaxAvg) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = *other;
+            return;
+        }
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+}
+
+/// Statistics of the readings in a sliding window of `N` buckets of `bucket_seconds` each.
+///
+/// Readings are folded into their bucket as they arrive, and a query combines at most
+/// `N` buckets, so nothing is ever rescanned. The window moves a bucket at a time: it
+/// covers the current, partly filled bucket and the `N - 1` before it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RollingWindow<const N: usize> {
+    bucket_seconds: u32,
+    buckets: [(u32, MinMaxAvg); N], // Bucket number, i.e. start time / `bucket_seconds`, and its statistics.
+}
+
+impl<const N: usize> RollingWindow<N> {
+    pub fn new(bucket_seconds: u32) -> Self {
+        Self { bucket_seconds, buckets: [(0, MinMaxAvg::new()); N] }
+    }
+
+    pub fn add(&mut self, timestamp: Timestamp, value: f32) {
+        let number = timestamp.seconds / self.bucket_seconds;
+        let (slot_number, stats) = &mut self.buckets[number as usize % N];
+        if *slot_number != number {
+            if number < *slot_number {
+                return; // Older than the window.
+            }
+            *slot_number = number;
+            stats.reset();
+        }
+        stats.add(value);
+    }
+
+    /// Statistics of the window ending at `now`.
+    pub fn summary(&self, now: Timestamp) -> MinMaxAvg {
+        let newest = now.seconds / self.bucket_seconds;
+        let oldest = newest.saturating_sub(N as u32 - 1);
+        let mut summary = MinMaxAvg::new();
+        for (number, stats) in &self.buckets {
+            if (oldest..=newest).contains(number) {
+                summary.merge(stats);
+            }
+        }
+        summary
+    }
+}
+
+/// Vaccine temperature statistics over the last 24 hours and the last 7 days.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RollingStats {
+    pub day: RollingWindow<24>,
+    pub week: RollingWindow<28>,
+}
+
+impl Default for RollingStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RollingStats {
+    pub fn new() -> Self {
+        Self { day: RollingWindow::new(DAY_BUCKET_SECONDS), week: RollingWindow::new(WEEK_BUCKET_SECONDS) }
+    }
+
+    pub fn add(&mut self, timestamp: Timestamp, tvc: f32) {
+        self.day.add(timestamp, tvc);
+        self.week.add(timestamp, tvc);
+    }
+
+    pub fn last_day(&self, now: Timestamp) -> MinMaxAvg {
+        self.day.summary(now)
+    }
+
+    pub fn last_week(&self, now: Timestamp) -> MinMaxAvg {
+        self.week.summary(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_min_max_avg() {
+        let mut stats = MinMaxAvg::new();
+        assert_eq!(stats.avg(), None);
+        stats.add(4.0);
+        stats.add(2.0);
+        stats.add(6.0);
+        assert_eq!(stats.count(), 3);
+        assert_eq!(stats.min(), Some(2.0));
+        assert_eq!(stats.max(), Some(6.0));
+        assert_eq!(stats.avg(), Some(4.0));
+        stats.reset();
+        assert_eq!(stats.min(), None);
+    }
+
+    #[test]
+    fn test_rolling_windows() {
+        let at = |hours: u32| Timestamp { seconds: hours * 3600 + 60 };
+        let mut stats = RollingStats::new();
+        stats.add(at(0), 2.0);
+        stats.add(at(5), 9.0);
+        stats.add(at(30), 5.0);
+        let day = stats.last_day(at(30));
+        assert_eq!((day.min(), day.max(), day.count()), (Some(5.0), Some(5.0), 1));
+        let week = stats.last_week(at(30));
+        assert_eq!((week.min(), week.max(), week.avg()), (Some(2.0), Some(9.0), Some(16.0 / 3.0)));
+        // Nothing new for a week: everything has left both windows.
+        assert_eq!(stats.last_week(at(30 + 7 * 24)).count(), 0);
+        // A late reading counts if its bucket is still in the window.
+        stats.add(at(29), 3.0);
+        assert_eq!(stats.last_day(at(30)).min(), Some(3.0));
+        // One whose bucket has been reused for a newer hour is dropped.
+        stats.add(at(6), 1.0);
+        assert_eq!(stats.last_day(at(30)).min(), Some(3.0));
+        assert_eq!(stats.last_week(at(30)).min(), Some(1.0));
+    }
+}
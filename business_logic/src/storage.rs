@@ -0,0 +1,361 @@
+use core::future::Future;
+
+#[cfg(feature = "encryption")]
+use crate::encryption::RecordCipher;
+use crate::store::{ChainedRecord, CHAINED_RECORD_LEN};
+
+/// Why the storage task couldn't save records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StorageError {
+    Erase, // A page erase failed.
+    Program, // Programming the records failed.
+    Read, // Reading back the records failed.
+}
+
+/// What the storage task reports back to the logger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StorageReport {
+    Saved { through: u32 }, // Records up to this sequence number are in flash.
+    Failed { first: u32, last: u32, error: StorageError }, // These records couldn't be saved.
+}
+
+/// Flash for records, programmed a page at a time by `StorageTask`. A page is erased before the
+/// first program of each pass, and only its erased part is programmed after that.
+pub trait FlashBackend {
+    /// Programs start and end on multiples of this many bytes, e.g. 8 for the STM32L4's double words.
+    const WRITE_LEN: usize;
+
+    /// Number of pages for records.
+    fn pages(&self) -> u32;
+
+    fn erase(&mut self, page: u32) -> impl Future<Output = Result<(), StorageError>>;
+
+    /// Program `bytes` at `offset` into `page`.
+    fn program(&mut self, page: u32, offset: usize, bytes: &[u8]) -> impl Future<Output = Result<(), StorageError>>;
+
+    /// Read `bytes.len()` bytes at `offset` in `page`.
+    fn read(&mut self, page: u32, offset: usize, bytes: &mut [u8]) -> impl Future<Output = Result<(), StorageError>>;
+}
+
+/// Where the storage task gets completed records, e.g. an embassy channel.
+pub trait RecordSource {
+    /// Wait for the next record. None once there will be no more.
+    fn receive(&mut self) -> impl Future<Output = Option<ChainedRecord>>;
+
+    /// The next record if one is already waiting.
+    fn try_receive(&mut self) -> Option<ChainedRecord>;
+}
+
+/// Where the storage task sends its reports, e.g. back to the logger task.
+pub trait StorageReports {
+    fn report(&mut self, report: StorageReport) -> impl Future<Output = ()>;
+}
+
+/// Owns the flash holding the records, so its erases and programs never hold up the event loop.
+///
+/// Records are kept in slots of `CHAINED_RECORD_LEN` rounded up to the backend's write size,
+/// padded with 0xFF. The records that arrive together are programmed together, in one program
+/// per page, and the pages are used as a ring, the oldest erased when the store wraps.
+///
+/// With the encryption feature and a cipher, the records are kept as `RecordCipher::seal` makes them.
+pub struct StorageTask<B, const PAGE: usize> {
+    backend: B,
+    buffer: [u8; PAGE], // The page being filled, as it is to be in flash.
+    page: u32,
+    filled: usize, // Bytes of `buffer` holding records.
+    programmed: usize, // Bytes of `buffer` already in flash.
+    pending: Option<(u32, u32)>, // First and last sequence numbers of the records not yet programmed.
+    #[cfg(feature = "encryption")]
+    cipher: Option<RecordCipher>, // Encrypts the records, if set.
+}
+
+impl<B: FlashBackend, const PAGE: usize> StorageTask<B, PAGE> {
+    const SLOT_LEN: usize = CHAINED_RECORD_LEN.next_multiple_of(B::WRITE_LEN);
+
+    /// Fill the flash from the start of `page`, which is erased first.
+    pub fn new(backend: B, page: u32) -> Self {
+        Self {
+            backend,
+            buffer: [0xFF; PAGE],
+            page,
+            filled: 0,
+            programmed: 0,
+            pending: None,
+            #[cfg(feature = "encryption")]
+            cipher: None,
+        }
+    }
+
+    /// Carry on after the newest record already in flash, e.g. at boot, in a new page rather than
+    /// after a record that may have been cut short. Also returns that record, for the chain and
+    /// the logger to carry on from; None if the flash holds no records.
+    pub async fn resume(backend: B) -> (Self, Option<ChainedRecord>) {
+        Self::new(backend, 0).carry_on().await
+    }
+
+    /// `resume` a store of records encrypted with `cipher`, and encrypt the new ones with it.
+    #[cfg(feature = "encryption")]
+    pub async fn resume_encrypted(backend: B, cipher: RecordCipher) -> (Self, Option<ChainedRecord>) {
+        Self { cipher: Some(cipher), ..Self::new(backend, 0) }.carry_on().await
+    }
+
+    /// Encrypt the records saved from now on with `cipher`, e.g. with a new nonce once the
+    /// store has started over.
+    #[cfg(feature = "encryption")]
+    pub fn set_cipher(&mut self, cipher: RecordCipher) {
+        self.cipher = Some(cipher);
+    }
+
+    // Find the newest record in flash and carry on after it, see `resume`.
+    async fn carry_on(mut self) -> (Self, Option<ChainedRecord>) {
+        let mut newest: Option<(u32, ChainedRecord)> = None;
+        let mut slot = [0u8; CHAINED_RECORD_LEN];
+        for page in 0..self.backend.pages() {
+            // A page's records are in order, up to its first slot that isn't one.
+            let mut last = None;
+            for offset in (0..=PAGE - Self::SLOT_LEN).step_by(Self::SLOT_LEN) {
+                if self.backend.read(page, offset, &mut slot).await.is_err() {
+                    break;
+                }
+                let Some(record) = self.decode(&slot) else { break };
+                last = Some(record);
+            }
+            // Sequence numbers wrap, so compare them by their difference.
+            if let Some(last) = last
+                && newest.is_none_or(|(_, newest)| last.sequence.wrapping_sub(newest.sequence) as i32 > 0)
+            {
+                newest = Some((page, last));
+            }
+        }
+        if let Some((page, _)) = newest {
+            self.page = page;
+            self.next_page();
+        }
+        (self, newest.map(|(_, record)| record))
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Save records until the source runs dry. Each record waiting when one arrives is taken
+    /// with it, so a burst of records costs one program per page.
+    pub async fn run(&mut self, source: &mut impl RecordSource, reports: &mut impl StorageReports) {
+        while let Some(record) = source.receive().await {
+            self.add(record, reports).await;
+            while let Some(record) = source.try_receive() {
+                self.add(record, reports).await;
+            }
+            self.program(reports).await;
+        }
+    }
+
+    // Copy `record` into the page, first programming the page and moving to the next if it is full.
+    async fn add(&mut self, record: ChainedRecord, reports: &mut impl StorageReports) {
+        if self.filled + Self::SLOT_LEN > PAGE {
+            self.program(reports).await;
+            // Unless a failed program has already moved on.
+            if self.filled > 0 {
+                self.next_page();
+            }
+        }
+        let bytes = self.encode(&record);
+        self.buffer[self.filled..self.filled + CHAINED_RECORD_LEN].copy_from_slice(&bytes);
+        self.filled += Self::SLOT_LEN;
+        let first = self.pending.map_or(record.sequence, |(first, _)| first);
+        self.pending = Some((first, record.sequence));
+    }
+
+    // Program the records added since the last program, erasing the page first if they are its
+    // first. A page that fails is given up on, with the records not yet in it.
+    async fn program(&mut self, reports: &mut impl StorageReports) {
+        let Some((first, last)) = self.pending.take() else {
+            return;
+        };
+        let erased = if self.programmed == 0 { self.backend.erase(self.page).await } else { Ok(()) };
+        let result = match erased {
+            Ok(()) => self.backend.program(self.page, self.programmed, &self.buffer[self.programmed..self.filled]).await,
+            Err(error) => Err(error),
+        };
+        match result {
+            Ok(()) => {
+                self.programmed = self.filled;
+                reports.report(StorageReport::Saved { through: last }).await;
+            }
+            Err(error) => {
+                self.next_page();
+                reports.report(StorageReport::Failed { first, last, error }).await;
+            }
+        }
+    }
+
+    // `record` as it is kept in flash.
+    fn encode(&mut self, record: &ChainedRecord) -> [u8; CHAINED_RECORD_LEN] {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &mut self.cipher {
+            return cipher.seal(record);
+        }
+        record.to_bytes()
+    }
+
+    // The record in `slot`, if it holds one.
+    fn decode(&mut self, slot: &[u8]) -> Option<ChainedRecord> {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &mut self.cipher {
+            return cipher.open(slot);
+        }
+        ChainedRecord::from_bytes(slot).map(|(record, _)| record)
+    }
+
+    fn next_page(&mut self) {
+        self.page = (self.page + 1) % self.backend.pages();
+        self.buffer = [0xFF; PAGE];
+        (self.filled, self.programmed) = (0, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregator::AggregationRecord;
+    use crate::store::RecordChain;
+    use crate::timestamp::Timestamp;
+    use embassy_futures::block_on;
+
+    const SLOT: usize = CHAINED_RECORD_LEN.next_multiple_of(8);
+    const PAGE: usize = 2 * SLOT;
+
+    #[derive(Default)]
+    struct MemFlash {
+        pages: Vec<Vec<u8>>,
+        programs: Vec<(u32, usize, usize)>, // Page, offset and length of each program.
+        bad_page: Option<u32>,
+    }
+
+    impl FlashBackend for MemFlash {
+        const WRITE_LEN: usize = 8;
+
+        fn pages(&self) -> u32 {
+            self.pages.len() as u32
+        }
+
+        async fn erase(&mut self, page: u32) -> Result<(), StorageError> {
+            self.pages[page as usize] = vec![0xFF; PAGE];
+            Ok(())
+        }
+
+        async fn program(&mut self, page: u32, offset: usize, bytes: &[u8]) -> Result<(), StorageError> {
+            assert_eq!((offset % Self::WRITE_LEN, bytes.len() % Self::WRITE_LEN), (0, 0));
+            self.programs.push((page, offset, bytes.len()));
+            if self.bad_page == Some(page) {
+                return Err(StorageError::Program);
+            }
+            let target = &mut self.pages[page as usize][offset..offset + bytes.len()];
+            assert!(target.iter().all(|&byte| byte == 0xFF), "programmed twice");
+            target.copy_from_slice(bytes);
+            Ok(())
+        }
+
+        async fn read(&mut self, page: u32, offset: usize, bytes: &mut [u8]) -> Result<(), StorageError> {
+            bytes.copy_from_slice(&self.pages[page as usize][offset..offset + bytes.len()]);
+            Ok(())
+        }
+    }
+
+    // Records arriving in bursts: each inner list is waiting together.
+    struct Bursts(std::vec::IntoIter<Vec<ChainedRecord>>, Vec<ChainedRecord>);
+
+    impl RecordSource for Bursts {
+        async fn receive(&mut self) -> Option<ChainedRecord> {
+            self.1 = self.0.next()?;
+            self.try_receive()
+        }
+
+        fn try_receive(&mut self) -> Option<ChainedRecord> {
+            (!self.1.is_empty()).then(|| self.1.remove(0))
+        }
+    }
+
+    impl StorageReports for Vec<StorageReport> {
+        async fn report(&mut self, report: StorageReport) {
+            self.push(report);
+        }
+    }
+
+    fn records(count: u32) -> Vec<ChainedRecord> {
+        let mut chain = RecordChain::new();
+        (0..count).map(|index| chain.link(AggregationRecord::new(Timestamp { seconds: 900 * index }))).collect()
+    }
+
+    fn run(flash: MemFlash, bursts: Vec<Vec<ChainedRecord>>) -> (StorageTask<MemFlash, PAGE>, Vec<StorageReport>) {
+        let mut task = StorageTask::new(flash, 0);
+        let mut reports = Vec::new();
+        block_on(task.run(&mut Bursts(bursts.into_iter(), Vec::new()), &mut reports));
+        (task, reports)
+    }
+
+    #[test]
+    fn test_coalesces_into_page_programs() {
+        let flash = MemFlash { pages: vec![vec![0; PAGE]; 2], ..MemFlash::default() };
+        let records = records(4);
+        let (task, reports) = run(flash, vec![records[..1].to_vec(), records[1..].to_vec()]);
+        // The first record alone, then the burst: the rest of page 0 in one program, then page 1.
+        assert_eq!(task.backend().programs, [(0, 0, SLOT), (0, SLOT, SLOT), (1, 0, 2 * SLOT)]);
+        assert_eq!(reports, [StorageReport::Saved { through: 0 }, StorageReport::Saved { through: 1 }, StorageReport::Saved { through: 3 }]);
+        let stored = &task.backend().pages[1];
+        assert_eq!(ChainedRecord::from_bytes(&stored[SLOT..]).map(|(record, _)| record), Some(records[3]));
+    }
+
+    #[test]
+    fn test_failed_page_is_skipped() {
+        let flash = MemFlash { pages: vec![vec![0; PAGE]; 3], bad_page: Some(0), ..MemFlash::default() };
+        let records = records(4);
+        let (task, reports) = run(flash, vec![records[..2].to_vec(), records[2..].to_vec()]);
+        let failed = StorageReport::Failed { first: 0, last: 1, error: StorageError::Program };
+        assert_eq!(reports, [failed, StorageReport::Saved { through: 3 }]);
+        // The next page is used, not the one after it.
+        assert_eq!(task.backend().programs.last(), Some(&(1, 0, 2 * SLOT)));
+
+        // Filling a page whose program fails moves on only once.
+        let flash = MemFlash { pages: vec![vec![0; PAGE]; 3], bad_page: Some(0), ..MemFlash::default() };
+        let (task, reports) = run(flash, vec![records[..3].to_vec()]);
+        assert_eq!(reports, [failed, StorageReport::Saved { through: 2 }]);
+        assert_eq!(task.backend().programs.last(), Some(&(1, 0, SLOT)));
+    }
+
+    #[test]
+    fn test_resume_after_newest_record() {
+        let resume = |pages: Vec<Vec<u8>>| block_on(StorageTask::<MemFlash, PAGE>::resume(MemFlash { pages, ..MemFlash::default() }));
+        let records = records(8);
+        // The ring has wrapped: page 0 holds the newest record, ahead of pages 1 and 2.
+        let (task, _) = run(MemFlash { pages: vec![vec![0xFF; PAGE]; 3], ..MemFlash::default() }, vec![records[..7].to_vec()]);
+        let mut pages = task.backend.pages;
+        let (mut task, newest) = resume(pages.clone());
+        assert_eq!(newest, Some(records[6]));
+        let mut reports = Vec::new();
+        block_on(task.run(&mut Bursts(vec![records[7..].to_vec()].into_iter(), Vec::new()), &mut reports));
+        assert_eq!(task.backend().programs, [(1, 0, SLOT)]); // A fresh page, not the rest of page 0.
+        assert_eq!(reports, [StorageReport::Saved { through: 7 }]);
+        pages[0].fill(0xFF);
+        assert_eq!(resume(pages).1, Some(records[5]));
+        assert_eq!(resume(vec![vec![0xFF; PAGE]; 2]).1, None);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_records() {
+        let cipher = || RecordCipher::new(&[3; 16], 1);
+        let records = records(3);
+        let mut task = StorageTask::<_, PAGE>::new(MemFlash { pages: vec![vec![0xFF; PAGE]; 2], ..MemFlash::default() }, 0);
+        task.set_cipher(cipher());
+        block_on(task.run(&mut Bursts(vec![records.clone()].into_iter(), Vec::new()), &mut Vec::new()));
+        let pages = task.backend.pages;
+        // Kept as sealed, and read back with the same cipher only.
+        assert_eq!(pages[1][..CHAINED_RECORD_LEN], cipher().seal(&records[2]));
+        let resume = |cipher| block_on(StorageTask::<MemFlash, PAGE>::resume_encrypted(MemFlash { pages: pages.clone(), ..MemFlash::default() }, cipher)).1;
+        assert_eq!(resume(cipher()), Some(records[2]));
+        assert_ne!(resume(RecordCipher::new(&[3; 16], 2)), Some(records[2]));
+    }
+}
@@ -0,0 +1,383 @@
+use crate::aggregator::{AggregationRecord, RecordKind, AGGREGATION_RECORD_LEN};
+use crate::crc::crc32;
+use crate::log::{Log, LogCode};
+use crate::timestamp::Timestamp;
+
+/// Compaction only runs once the store is at least this full.
+pub const COMPACTION_THRESHOLD_PERCENT: usize = 80;
+/// Records older than this are merged into daily summaries.
+pub const COMPACTION_AGE_DAYS: u32 = 30;
+/// Length of a serialized `ChainedRecord`: sequence number, previous hash, then the record.
+pub const CHAINED_RECORD_LEN: usize = 4 + 4 + AGGREGATION_RECORD_LEN;
+/// Length of a `DownloadHeader`: magic, version, length, then the epoch anchor.
+pub const DOWNLOAD_HEADER_LEN: usize = 4 + 1 + 1 + 4;
+const DOWNLOAD_MAGIC: [u8; 4] = *b"SLDL";
+const DOWNLOAD_VERSION: u8 = 1;
+const SECONDS_PER_DAY: u32 = 86400;
+
+/// A stored record with its place in the store's tamper chain.
+///
+/// Sequence numbers increase by one per record, so a host can see records missing from a
+/// download, and each record carries the hash of the one before it, so a changed, removed
+/// or reordered record breaks the chain. An authenticated export (see `authentication`) stops the chain simply being recomputed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChainedRecord {
+    pub sequence: u32,
+    pub previous_hash: u32, // `hash()` of the record with the previous sequence number.
+    pub record: AggregationRecord,
+}
+
+impl ChainedRecord {
+    /// CRC-32 over the sequence number, the previous hash and the serialized record.
+    pub fn hash(&self) -> u32 {
+        let mut header = [0u8; 8];
+        header[..4].copy_from_slice(&self.sequence.to_le_bytes());
+        header[4..].copy_from_slice(&self.previous_hash.to_le_bytes());
+        crc32(crc32(0, &header), &self.record.to_bytes())
+    }
+
+    /// The record as stored and downloaded, little-endian.
+    pub fn to_bytes(&self) -> [u8; CHAINED_RECORD_LEN] {
+        let mut bytes = [0u8; CHAINED_RECORD_LEN];
+        bytes[0..4].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.previous_hash.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.record.to_bytes());
+        bytes
+    }
+
+    /// Decode a record written by `to_bytes` of any version from the start of `bytes`. Returns
+    /// it and the number of bytes it took, or None if they aren't a record.
+    pub fn from_bytes(bytes: &[u8]) -> Option<(Self, usize)> {
+        let word = |offset: usize| Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?));
+        let len = 8 + usize::from(*bytes.get(9)?);
+        let record = AggregationRecord::from_bytes(bytes.get(8..len)?)?;
+        Some((Self { sequence: word(0)?, previous_hash: word(4)?, record }, len))
+    }
+}
+
+/// What a binary download starts with, before the records' `ChainedRecord::to_bytes`: what a
+/// host needs to read them that the records don't carry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DownloadHeader {
+    pub epoch_anchor: Option<u32>, // `Config::epoch_anchor` when the download was made, to give the records real times.
+}
+
+impl DownloadHeader {
+    pub fn to_bytes(&self) -> [u8; DOWNLOAD_HEADER_LEN] {
+        let mut bytes = [0u8; DOWNLOAD_HEADER_LEN];
+        bytes[..4].copy_from_slice(&DOWNLOAD_MAGIC);
+        (bytes[4], bytes[5]) = (DOWNLOAD_VERSION, DOWNLOAD_HEADER_LEN as u8);
+        bytes[6..10].copy_from_slice(&self.epoch_anchor.unwrap_or(u32::MAX).to_le_bytes());
+        bytes
+    }
+
+    /// Decode the header at the start of `bytes`, of any version. Returns it and its length, or
+    /// None if they don't start with one, e.g. a download from firmware that didn't write one.
+    pub fn from_bytes(bytes: &[u8]) -> Option<(Self, usize)> {
+        if bytes.get(..4)? != DOWNLOAD_MAGIC || *bytes.get(4)? == 0 {
+            return None;
+        }
+        let len = usize::from(*bytes.get(5)?);
+        let anchor = u32::from_le_bytes(bytes.get(6..10).filter(|_| len >= DOWNLOAD_HEADER_LEN)?.try_into().ok()?);
+        Some((Self { epoch_anchor: Some(anchor).filter(|&anchor| anchor != u32::MAX) }, len))
+    }
+}
+
+/// Where the chain stands, i.e. what the next record appended links to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RecordChain {
+    pub next_sequence: u32,
+    pub last_hash: u32,
+}
+
+impl RecordChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The chain carrying on after `last`, e.g. the newest record in flash at boot.
+    pub fn after(last: &ChainedRecord) -> Self {
+        Self { next_sequence: last.sequence.wrapping_add(1), last_hash: last.hash() }
+    }
+
+    /// Give `record` the next sequence number and link it to the last one.
+    pub fn link(&mut self, record: AggregationRecord) -> ChainedRecord {
+        let chained = ChainedRecord { sequence: self.next_sequence, previous_hash: self.last_hash, record };
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        self.last_hash = chained.hash();
+        chained
+    }
+}
+
+/// Where a downloaded chain first fails to check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChainError {
+    Gap { after: u32 }, // The record after sequence number `after` is missing.
+    Broken { sequence: u32 }, // This record doesn't link to the one before it.
+}
+
+/// Check that consecutive records follow on from each other. The first record is trusted,
+/// since the records before it may have been overwritten in the device.
+pub fn verify_chain(records: impl IntoIterator<Item = ChainedRecord>) -> Result<(), ChainError> {
+    let mut previous: Option<ChainedRecord> = None;
+    for chained in records {
+        if let Some(previous) = previous {
+            if chained.sequence != previous.sequence.wrapping_add(1) {
+                return Err(ChainError::Gap { after: previous.sequence });
+            }
+            if chained.previous_hash != previous.hash() {
+                return Err(ChainError::Broken { sequence: chained.sequence });
+            }
+        }
+        previous = Some(chained);
+    }
+    Ok(())
+}
+
+/// Where completed records are kept until they are downloaded.
+pub trait RecordStore {
+    /// Append a completed record, overwriting the oldest one if the store is full.
+    /// The store links it into its chain.
+    fn append(&mut self, record: AggregationRecord);
+
+    /// Number of records held.
+    fn len(&self) -> usize;
+
+    /// Record `index` with its chain fields, counting from the oldest.
+    fn get_chained(&self, index: usize) -> Option<ChainedRecord>;
+
+    /// Record `index`, counting from the oldest.
+    fn get(&self, index: usize) -> Option<AggregationRecord> {
+        self.get_chained(index).map(|chained| chained.record)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Records, oldest first.
+    fn iter(&self) -> impl Iterator<Item = AggregationRecord> + '_ {
+        (0..self.len()).filter_map(|index| self.get(index))
+    }
+
+    /// Records with their chain fields, oldest first, e.g. for download.
+    fn iter_chained(&self) -> impl Iterator<Item = ChainedRecord> + '_ {
+        (0..self.len()).filter_map(|index| self.get_chained(index))
+    }
+}
+
+/// Ring buffer of the last `N` records in RAM.
+#[derive(Debug, Clone)]
+pub struct RamStore<const N: usize> {
+    records: [ChainedRecord; N],
+    next: usize, // Index in `records` written next.
+    len: usize,
+    chain: RecordChain,
+}
+
+impl<const N: usize> Default for RamStore<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> RamStore<N> {
+    pub fn new() -> Self {
+        let empty = ChainedRecord { sequence: 0, previous_hash: 0, record: AggregationRecord::new(Timestamp { seconds: 0 }) };
+        Self { records: [empty; N], next: 0, len: 0, chain: RecordChain::new() }
+    }
+
+    /// The store with its records linked on from `chain` rather than a new one.
+    pub fn with_chain(self, chain: RecordChain) -> Self {
+        Self { chain, ..self }
+    }
+
+    /// Whether the store is full enough for `compact` to run.
+    pub fn wants_compaction(&self) -> bool {
+        self.len * 100 >= N * COMPACTION_THRESHOLD_PERCENT
+    }
+
+    /// Once the store passes `COMPACTION_THRESHOLD_PERCENT`, merge the records that started more
+    /// than `age_days` before `now` into one `RecordKind::DaySummary` per UTC day, keeping
+    /// long-term statistics while freeing space. Logs and returns the number of records freed.
+    ///
+    /// The kept records are linked into the chain again after the last one appended, so a host
+    /// sees the compaction as a jump in sequence numbers. The store is rewritten in place.
+    pub fn compact(&mut self, now: Timestamp, age_days: u32, log: &mut impl Log) -> usize {
+        if !self.wants_compaction() {
+            return 0;
+        }
+        let cutoff = now.seconds.saturating_sub(age_days.saturating_mul(SECONDS_PER_DAY));
+        let day_start = |record: &AggregationRecord| record.start.seconds - record.start.seconds % SECONDS_PER_DAY;
+        // Count first, so nothing is relinked when there is nothing to free.
+        let (mut old, mut days, mut last_day) = (0, 0, None);
+        for record in self.iter().filter(|record| record.start.seconds < cutoff) {
+            old += 1;
+            if last_day.replace(day_start(&record)) != Some(day_start(&record)) {
+                days += 1;
+            }
+        }
+        if old == days {
+            return 0;
+        }
+        // Each record is written to a slot at or before the one it was read from, since a summary
+        // only goes out once a record after it is read.
+        let first = (self.next + N - self.len) % N;
+        let mut kept = 0;
+        let mut summary: Option<AggregationRecord> = None;
+        for index in 0..self.len {
+            let record = self.records[(first + index) % N].record;
+            let done = if record.start.seconds >= cutoff {
+                [summary.take(), Some(record)]
+            } else {
+                let day = Timestamp { seconds: day_start(&record) };
+                let done = summary.take_if(|summary| summary.start != day);
+                summary.get_or_insert(AggregationRecord { kind: RecordKind::DaySummary, ..AggregationRecord::new(day) }).merge(&record);
+                [done, None]
+            };
+            for record in done.into_iter().flatten() {
+                self.records[(first + kept) % N] = self.chain.link(record);
+                kept += 1;
+            }
+        }
+        if let Some(summary) = summary {
+            self.records[(first + kept) % N] = self.chain.link(summary);
+            kept += 1;
+        }
+        let freed = self.len - kept;
+        (self.len, self.next) = (kept, (first + kept) % N);
+        log.info(LogCode::RecordsCompacted, freed as u32);
+        freed
+    }
+}
+
+impl<const N: usize> RecordStore for RamStore<N> {
+    fn append(&mut self, record: AggregationRecord) {
+        self.records[self.next] = self.chain.link(record);
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get_chained(&self, index: usize) -> Option<ChainedRecord> {
+        (index < self.len).then(|| self.records[(self.next + N - self.len + index) % N])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{CaptureLog, Level};
+
+    fn record(seconds: u32) -> AggregationRecord {
+        AggregationRecord::new(Timestamp { seconds })
+    }
+
+    #[test]
+    fn test_ram_store_wraps() {
+        let mut store = RamStore::<3>::new();
+        assert!(store.is_empty());
+        store.append(record(0));
+        store.append(record(900));
+        assert_eq!(store.get(1), Some(record(900)));
+        assert_eq!(store.get(2), None);
+        store.append(record(1800));
+        store.append(record(2700));
+        let starts: Vec<u32> = store.iter().map(|record| record.start.seconds).collect();
+        assert_eq!(starts, [900, 1800, 2700]);
+        assert_eq!(store.get_chained(0).map(|chained| chained.sequence), Some(1));
+    }
+
+    #[test]
+    fn test_download_header() {
+        for epoch_anchor in [None, Some(1_700_000_000)] {
+            let header = DownloadHeader { epoch_anchor };
+            assert_eq!(DownloadHeader::from_bytes(&header.to_bytes()), Some((header, DOWNLOAD_HEADER_LEN)));
+        }
+        // A later version may be longer.
+        let mut longer = DownloadHeader { epoch_anchor: Some(1) }.to_bytes().to_vec();
+        longer.extend_from_slice(&[0; 4]);
+        (longer[4], longer[5]) = (DOWNLOAD_VERSION + 1, longer.len() as u8);
+        assert_eq!(DownloadHeader::from_bytes(&longer), Some((DownloadHeader { epoch_anchor: Some(1) }, 14)));
+        // Records without a header.
+        assert_eq!(DownloadHeader::from_bytes(&RecordChain::new().link(record(0)).to_bytes()), None);
+    }
+
+    #[test]
+    fn test_chained_round_trip() {
+        let mut chain = RecordChain::new();
+        chain.link(record(0));
+        let chained = chain.link(record(900));
+        let bytes = chained.to_bytes();
+        assert_eq!(bytes[..4], 1u32.to_le_bytes());
+        assert_eq!(ChainedRecord::from_bytes(&bytes), Some((chained, CHAINED_RECORD_LEN)));
+        assert_eq!(ChainedRecord::from_bytes(&bytes[..CHAINED_RECORD_LEN - 1]), None);
+    }
+
+    #[test]
+    fn test_chain_detects_tampering() {
+        let mut store = RamStore::<4>::new();
+        for start in [0, 900, 1800, 2700] {
+            store.append(record(start));
+        }
+        let chained: Vec<ChainedRecord> = store.iter_chained().collect();
+        assert_eq!(verify_chain(chained.iter().copied()), Ok(()));
+        assert_eq!(verify_chain(chained[1..].iter().copied()), Ok(())); // Oldest records overwritten.
+        let mut removed = chained.clone();
+        removed.remove(2);
+        assert_eq!(verify_chain(removed), Err(ChainError::Gap { after: 1 }));
+        let mut changed = chained.clone();
+        changed[1].record.high_seconds = 60;
+        assert_eq!(verify_chain(changed), Err(ChainError::Broken { sequence: 2 }));
+    }
+
+    #[test]
+    fn test_compaction() {
+        let mut store = RamStore::<10>::new();
+        let mut log = CaptureLog::default();
+        let now = Timestamp { seconds: 40 * SECONDS_PER_DAY };
+        let starts = [0, 21_600, 43_200, SECONDS_PER_DAY, SECONDS_PER_DAY + 43_200, 35 * SECONDS_PER_DAY];
+        for start in starts {
+            let mut record = record(start);
+            record.door_openings = 1;
+            store.append(record);
+        }
+        assert_eq!(store.compact(now, COMPACTION_AGE_DAYS, &mut log), 0); // Below the threshold.
+        for start in [36, 37] {
+            store.append(record(start * SECONDS_PER_DAY));
+        }
+        assert_eq!(store.compact(now, COMPACTION_AGE_DAYS, &mut log), 3);
+        assert_eq!(log.entries, [(Level::Info, LogCode::RecordsCompacted, 3)]);
+        let kept: Vec<(u32, u32)> = store.iter().map(|record| (record.start.seconds, record.door_openings)).collect();
+        assert_eq!(kept, [(0, 3), (SECONDS_PER_DAY, 2), (35 * SECONDS_PER_DAY, 1), (36 * SECONDS_PER_DAY, 0), (37 * SECONDS_PER_DAY, 0)]);
+        let chained: Vec<ChainedRecord> = store.iter_chained().collect();
+        assert_eq!(chained[0].sequence, 8);
+        assert_eq!(verify_chain(chained), Ok(()));
+    }
+
+    #[test]
+    fn test_compaction_in_place_after_wrapping() {
+        let mut store = RamStore::<4>::new();
+        let mut log = CaptureLog::default();
+        for start in [0, 3600, 7200, SECONDS_PER_DAY, 35 * SECONDS_PER_DAY] {
+            store.append(AggregationRecord { door_openings: 1, ..record(start) });
+        }
+        let now = Timestamp { seconds: 40 * SECONDS_PER_DAY };
+        assert_eq!(store.compact(now, COMPACTION_AGE_DAYS, &mut log), 1);
+        let kept: Vec<(u32, u32, RecordKind)> = store.iter().map(|record| (record.start.seconds, record.door_openings, record.kind)).collect();
+        assert_eq!(kept, [(0, 2, RecordKind::DaySummary), (SECONDS_PER_DAY, 1, RecordKind::DaySummary), (35 * SECONDS_PER_DAY, 1, RecordKind::Period)]);
+        assert_eq!(verify_chain(store.iter_chained()), Ok(()));
+        store.append(record(36 * SECONDS_PER_DAY));
+        // Only summaries are old now, so nothing is freed or relinked.
+        let before: Vec<ChainedRecord> = store.iter_chained().collect();
+        assert_eq!(store.compact(now, COMPACTION_AGE_DAYS, &mut log), 0);
+        assert!(store.iter_chained().eq(before));
+    }
+}
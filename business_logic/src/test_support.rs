@@ -0,0 +1,28 @@
+//! Fixtures shared by the unit tests. The integration tests have their own, in `tests/common`.
+
+use crate::logger::LoggerEvent;
+use crate::sample::TemperatureSample;
+use crate::timestamp::Timestamp;
+
+/// The time `seconds` after the epoch.
+pub fn at(seconds: u32) -> Timestamp {
+    Timestamp { seconds }
+}
+
+/// A plain reading of `tvc` at `seconds`, with the ambient at 25 °C.
+pub fn reading(seconds: u32, tvc: f32) -> TemperatureSample {
+    TemperatureSample {
+        timestamp: at(seconds),
+        tamb: 25.0,
+        tvc,
+        tamb_quality: 0,
+        tvc_quality: 0,
+        #[cfg(feature = "humidity")]
+        humidity: None,
+    }
+}
+
+/// `reading` as an event for the logger.
+pub fn sample(seconds: u32, tvc: f32) -> LoggerEvent {
+    LoggerEvent::Sample(reading(seconds, tvc))
+}
@@ -1,7 +1,29 @@
 use arrayvec::ArrayString;
 use core::fmt::Write;
 
-// TODO: implement Format for Timestamp
+/// Errors from processing timestamped events, with the times involved for diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum TimestampError {
+    /// Earlier than an event already processed, or overtaken within its second.
+    OutOfOrder { expected_after: Timestamp, got: Timestamp },
+    /// Further ahead of the last event than the clock can have moved, e.g. a corrupted reading.
+    TooFarFuture { latest: Timestamp, got: Timestamp },
+    /// The clock hasn't been set, so its times mean nothing.
+    Unset,
+}
+
+impl TimestampError {
+    /// How far the time was out, in seconds: behind for `OutOfOrder`, ahead for `TooFarFuture`.
+    pub fn seconds_off(&self) -> u32 {
+        match *self {
+            TimestampError::OutOfOrder { expected_after, got } => expected_after.seconds.saturating_sub(got.seconds),
+            TimestampError::TooFarFuture { latest, got } => got.seconds.saturating_sub(latest.seconds),
+            TimestampError::Unset => 0,
+        }
+    }
+}
 
 /// Represents a timestamp in seconds since the epoch.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -58,6 +80,13 @@ impl Timestamp {
 
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Timestamp {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=str}", self.create_iso8601_str().as_str());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,46 @@
+/// Unit temperatures are shown and exported in. Records are always stored in °C.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+impl TemperatureUnit {
+    /// Convert a temperature in °C to this unit.
+    pub fn from_celsius(self, celsius: f32) -> f32 {
+        match self {
+            TemperatureUnit::Celsius => celsius,
+            TemperatureUnit::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+        }
+    }
+
+    /// Convert a difference between two temperatures in °C to this unit.
+    pub fn from_celsius_difference(self, celsius: f32) -> f32 {
+        match self {
+            TemperatureUnit::Celsius => celsius,
+            TemperatureUnit::Fahrenheit => celsius * 9.0 / 5.0,
+        }
+    }
+
+    pub fn symbol(self) -> char {
+        match self {
+            TemperatureUnit::Celsius => 'C',
+            TemperatureUnit::Fahrenheit => 'F',
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversion() {
+        assert_eq!(TemperatureUnit::Celsius.from_celsius(8.0), 8.0);
+        assert_eq!(TemperatureUnit::Fahrenheit.from_celsius(-40.0), -40.0);
+        assert_eq!(TemperatureUnit::Fahrenheit.from_celsius(100.0), 212.0);
+        assert_eq!(TemperatureUnit::Fahrenheit.from_celsius_difference(10.0), 18.0);
+    }
+}
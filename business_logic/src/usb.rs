@@ -0,0 +1,104 @@
+use crate::log::{Log, LogCode};
+use crate::timestamp::Timestamp;
+
+/// Debounced changes of the USB VBUS input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UsbEvent {
+    Connected,
+    Disconnected,
+}
+
+/// Tracks USB connections from `UsbEvent`s, for the power source and the audit trail of who
+/// could have downloaded or changed anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UsbSessions {
+    connected_since: Option<Timestamp>,
+    sessions: u32,
+    connected_seconds: u32, // Total of the finished sessions.
+}
+
+impl UsbSessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle an event that occurred at `timestamp`, logging the start and end of each session.
+    /// Repeated events are ignored.
+    pub fn process_event(&mut self, event: UsbEvent, timestamp: Timestamp, log: &mut impl Log) {
+        match (event, self.connected_since) {
+            (UsbEvent::Connected, None) => {
+                self.connected_since = Some(timestamp);
+                self.sessions = self.sessions.saturating_add(1);
+                log.info(LogCode::UsbConnected, timestamp.seconds);
+            }
+            (UsbEvent::Disconnected, Some(since)) => {
+                let seconds = timestamp.seconds.saturating_sub(since.seconds);
+                self.connected_seconds = self.connected_seconds.saturating_add(seconds);
+                self.connected_since = None;
+                log.info(LogCode::UsbDisconnected, seconds);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected_since.is_some()
+    }
+
+    /// Number of times USB has been connected.
+    pub fn sessions(&self) -> u32 {
+        self.sessions
+    }
+
+    /// Total seconds USB has been connected, including the current session up to `now`.
+    pub fn connected_seconds(&self, now: Timestamp) -> u32 {
+        let current = self.connected_since.map_or(0, |since| now.seconds.saturating_sub(since.seconds));
+        self.connected_seconds.saturating_add(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{CaptureLog, Level, NullLog};
+
+    #[test]
+    fn test_sessions() {
+        let mut usb = UsbSessions::new();
+        let mut log = CaptureLog::default();
+        usb.process_event(UsbEvent::Disconnected, Timestamp { seconds: 10 }, &mut log);
+        usb.process_event(UsbEvent::Connected, Timestamp { seconds: 100 }, &mut log);
+        usb.process_event(UsbEvent::Connected, Timestamp { seconds: 110 }, &mut log);
+        assert!(usb.is_connected());
+        assert_eq!(usb.connected_seconds(Timestamp { seconds: 160 }), 60);
+        usb.process_event(UsbEvent::Disconnected, Timestamp { seconds: 400 }, &mut log);
+        assert!(!usb.is_connected());
+        usb.process_event(UsbEvent::Connected, Timestamp { seconds: 1000 }, &mut log);
+        assert_eq!(usb.sessions(), 2);
+        assert_eq!(usb.connected_seconds(Timestamp { seconds: 1030 }), 330);
+        assert_eq!(
+            log.entries,
+            [
+                (Level::Info, LogCode::UsbConnected, 100),
+                (Level::Info, LogCode::UsbDisconnected, 300),
+                (Level::Info, LogCode::UsbConnected, 1000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_session_times() {
+        let mut usb = UsbSessions::new();
+        assert_eq!(usb.connected_seconds(Timestamp { seconds: 500 }), 0);
+        usb.process_event(UsbEvent::Connected, Timestamp { seconds: 100 }, &mut NullLog);
+        // A clock that went back counts nothing rather than wrapping.
+        assert_eq!(usb.connected_seconds(Timestamp { seconds: 50 }), 0);
+        usb.process_event(UsbEvent::Disconnected, Timestamp { seconds: 90 }, &mut NullLog);
+        assert_eq!((usb.sessions(), usb.connected_seconds(Timestamp { seconds: 1000 })), (1, 0));
+        usb.process_event(UsbEvent::Connected, Timestamp { seconds: 1000 }, &mut NullLog);
+        usb.process_event(UsbEvent::Disconnected, Timestamp { seconds: 1060 }, &mut NullLog);
+        usb.process_event(UsbEvent::Disconnected, Timestamp { seconds: 2000 }, &mut NullLog);
+        assert_eq!((usb.sessions(), usb.connected_seconds(Timestamp { seconds: 3000 })), (2, 60));
+    }
+}
@@ -0,0 +1,198 @@
+use core::future::Future;
+
+use crate::hal::I2c;
+use crate::timestamp::Timestamp;
+
+/// I2C address of the DS3231 real-time clock.
+pub const DS3231_ADDRESS: u8 = 0x68;
+/// The epoch, 1 March 2000 UTC, in Unix time: the epoch anchor of a clock set to real time.
+pub const EPOCH_UNIX_SECONDS: u32 = 951_868_800;
+const FIRST_YEAR: u16 = 2000; // RTC chips count two-digit years from here.
+const LAST_YEAR: u16 = 2099;
+const TIME_REGISTER: u8 = 0x00; // Seconds, minutes, hours, weekday, date, month, year.
+const STATUS_REGISTER: u8 = 0x0F;
+const OSCILLATOR_STOPPED: u8 = 0x80; // Status flag set when the oscillator stopped, e.g. the battery ran flat.
+const CENTURY: u8 = 0x80; // Month register flag, unused while years stay below 2100.
+
+/// Why the wall-clock time couldn't be read or set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockError {
+    Bus, // The clock didn't answer.
+    Stopped, // The clock stopped, e.g. its backup battery ran flat, so the time is lost until set.
+    OutOfRange, // Outside the years the clock can hold.
+}
+
+/// Date and time of day, as RTC chips hold it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CalendarTime {
+    pub year: u16,
+    pub month: u8, // 1 to 12.
+    pub day: u8, // 1 to 31.
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// The Unix time of the logger's `seconds = 0`, from a clock reading `clock` at Unix time
+/// `unix_seconds`. Host tools add it to record times to get real times, even if the clock was
+/// never set to real time. None if the clock is ahead of real time by more than the Unix epoch.
+pub fn epoch_anchor(clock: Timestamp, unix_seconds: u32) -> Option<u32> {
+    unix_seconds.checked_sub(clock.seconds)
+}
+
+impl CalendarTime {
+    /// The time `timestamp` after the epoch, 1 March 2000 (computational year 0).
+    pub fn from_timestamp(timestamp: Timestamp) -> Self {
+        let (days, hour, minute, second) = timestamp.to_dhms();
+        // Neri C, Schneider L. "Euclidean affine functions and their application to calendar
+        // algorithms". Softw Pract Exper. 2022;1-34. doi: 10.1002/spe.3172. Section 5, without the
+        // Euclidean affine function optimizations; names follow the paper.
+        let n_1 = 4 * days + 3; // N1
+        let year_computational = n_1 / 1461; // Y
+        let n_y = n_1 % 1461 / 4; // N_Y
+        let n_2 = 5 * n_y + 461; // N_2
+        let m = n_2 / 153; // M
+        let day = (n_2 % 153 / 5 + 1) as u8; // D_J
+        let j = u32::from(m >= 13); // J = 1{M>=13}
+        Self {
+            year: (year_computational + j) as u16 + FIRST_YEAR,
+            month: (m - 12 * j) as u8,
+            day,
+            hour: hour as u8,
+            minute: minute as u8,
+            second: second as u8,
+        }
+    }
+
+    /// Seconds since the epoch, or None before it or after the years an RTC chip can hold.
+    pub fn to_timestamp(&self) -> Option<Timestamp> {
+        if !(FIRST_YEAR..=LAST_YEAR).contains(&self.year) || (self.year == FIRST_YEAR && self.month < 3) {
+            // The computational calendar starts on 1 March of the first year.
+            return None;
+        }
+        let j = u32::from(self.month <= 2);
+        let y = u32::from(self.year - FIRST_YEAR) - j;
+        let m = u32::from(self.month) + 12 * j;
+        let days = 1461 * y / 4 + (153 * m - 457) / 5 + u32::from(self.day) - 1;
+        let seconds = days * 86400 + u32::from(self.hour) * 3600 + u32::from(self.minute) * 60 + u32::from(self.second);
+        Some(Timestamp { seconds })
+    }
+}
+
+/// A clock keeping the time across resets and power cuts: the MCU's own RTC, or a battery-backed
+/// external chip on boards that have one.
+pub trait WallClock {
+    fn now(&mut self) -> impl Future<Output = Result<Timestamp, ClockError>>;
+
+    fn set(&mut self, time: Timestamp) -> impl Future<Output = Result<(), ClockError>>;
+}
+
+/// DS3231 temperature-compensated RTC with battery backup.
+pub struct Ds3231<I2C> {
+    i2c: I2C,
+}
+
+impl<I2C> Ds3231<I2C> {
+    pub fn new(i2c: I2C) -> Self {
+        Self { i2c }
+    }
+
+    pub fn i2c_mut(&mut self) -> &mut I2C {
+        &mut self.i2c
+    }
+}
+
+impl<I2C: I2c> WallClock for Ds3231<I2C> {
+    async fn now(&mut self) -> Result<Timestamp, ClockError> {
+        let mut status = [0u8];
+        self.i2c.write_read(DS3231_ADDRESS, &[STATUS_REGISTER], &mut status).await.or(Err(ClockError::Bus))?;
+        if status[0] & OSCILLATOR_STOPPED != 0 {
+            return Err(ClockError::Stopped);
+        }
+        let mut registers = [0u8; 7];
+        self.i2c.write_read(DS3231_ADDRESS, &[TIME_REGISTER], &mut registers).await.or(Err(ClockError::Bus))?;
+        let time = CalendarTime {
+            year: FIRST_YEAR + u16::from(from_bcd(registers[6])),
+            month: from_bcd(registers[5] & !CENTURY),
+            day: from_bcd(registers[4]),
+            hour: from_bcd(registers[2] & 0x3F), // Always in 24-hour mode.
+            minute: from_bcd(registers[1]),
+            second: from_bcd(registers[0]),
+        };
+        time.to_timestamp().ok_or(ClockError::OutOfRange)
+    }
+
+    async fn set(&mut self, time: Timestamp) -> Result<(), ClockError> {
+        let time = CalendarTime::from_timestamp(time);
+        if time.year > LAST_YEAR {
+            return Err(ClockError::OutOfRange);
+        }
+        let year = (time.year - FIRST_YEAR) as u8;
+        // The weekday register isn't used, but must hold 1 to 7.
+        let registers = [TIME_REGISTER, to_bcd(time.second), to_bcd(time.minute), to_bcd(time.hour), 1, to_bcd(time.day), to_bcd(time.month), to_bcd(year)];
+        self.i2c.write(DS3231_ADDRESS, &registers).await.or(Err(ClockError::Bus))?;
+        // The time is good again.
+        let mut status = [0u8];
+        self.i2c.write_read(DS3231_ADDRESS, &[STATUS_REGISTER], &mut status).await.or(Err(ClockError::Bus))?;
+        self.i2c.write(DS3231_ADDRESS, &[STATUS_REGISTER, status[0] & !OSCILLATOR_STOPPED]).await.or(Err(ClockError::Bus))
+    }
+}
+
+fn from_bcd(bcd: u8) -> u8 {
+    (bcd >> 4) * 10 + (bcd & 0x0F)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    #[test]
+    fn test_calendar_round_trip() {
+        let epoch = CalendarTime { year: 2000, month: 3, day: 1, hour: 0, minute: 0, second: 0 };
+        assert_eq!(CalendarTime::from_timestamp(Timestamp { seconds: 0 }), epoch);
+        // A leap day, 29 February 2024 12:34:56.
+        let leap = CalendarTime { year: 2024, month: 2, day: 29, hour: 12, minute: 34, second: 56 };
+        let seconds = leap.to_timestamp().unwrap().seconds;
+        assert_eq!(seconds, 8765 * 86400 + 45296);
+        assert_eq!(CalendarTime::from_timestamp(Timestamp { seconds }), leap);
+        assert_eq!(epoch_anchor(Timestamp { seconds }, 1_709_210_096), Some(EPOCH_UNIX_SECONDS));
+        // A clock started from zero an hour before.
+        assert_eq!(epoch_anchor(Timestamp { seconds: 3600 }, 1_709_210_096), Some(1_709_206_496));
+        assert_eq!(epoch_anchor(Timestamp { seconds: 1000 }, 999), None);
+        for seconds in (0..3_000_000_000u32).step_by(7_777_777) {
+            assert_eq!(CalendarTime::from_timestamp(Timestamp { seconds }).to_timestamp(), Some(Timestamp { seconds }));
+        }
+        assert_eq!(CalendarTime { month: 2, ..epoch }.to_timestamp(), None);
+        assert_eq!(CalendarTime { year: 2100, ..epoch }.to_timestamp(), None);
+    }
+
+    #[test]
+    fn test_ds3231() {
+        // 29 February 2024 12:34:56.
+        let time = [0x56, 0x34, 0x12, 0x01, 0x29, 0x02, 0x24];
+        let mut write = vec![TIME_REGISTER];
+        write.extend(time);
+        let expectations = [
+            Transaction::write(DS3231_ADDRESS, write),
+            Transaction::write_read(DS3231_ADDRESS, vec![STATUS_REGISTER], vec![0x88]),
+            Transaction::write(DS3231_ADDRESS, vec![STATUS_REGISTER, 0x08]),
+            Transaction::write_read(DS3231_ADDRESS, vec![STATUS_REGISTER], vec![0x08]),
+            Transaction::write_read(DS3231_ADDRESS, vec![TIME_REGISTER], time.to_vec()),
+            Transaction::write_read(DS3231_ADDRESS, vec![STATUS_REGISTER], vec![0x80]),
+        ];
+        let mut clock = Ds3231::new(Mock::new(&expectations));
+        let timestamp = Timestamp { seconds: 8765 * 86400 + 45296 };
+        assert_eq!(block_on(clock.set(timestamp)), Ok(()));
+        assert_eq!(block_on(clock.now()), Ok(timestamp));
+        assert_eq!(block_on(clock.now()), Err(ClockError::Stopped));
+        clock.i2c_mut().done();
+    }
+}
@@ -0,0 +1,219 @@
+use core::fmt::{self, Write};
+
+use arrayvec::ArrayString;
+
+use crate::aggregator::AggregationRecord;
+use crate::logger::LoggerEvent;
+use crate::logger_task::Subscriber;
+
+/// Longest `watch` line, including its newline: a record line with every field at its widest,
+/// e.g. a temperature of `-f32::MAX`, 43 characters at two decimal places.
+pub const MAX_WATCH_LINE: usize = 320;
+
+/// A `watch` console command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WatchCommand {
+    Start, // `watch`
+    Stop, // `watch stop`
+}
+
+impl WatchCommand {
+    /// Parse a console line such as `watch` or `watch stop`. None if it isn't a watch command.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let command = match (words.next(), words.next()) {
+            (Some("watch"), None) => WatchCommand::Start,
+            (Some("watch"), Some("stop")) => WatchCommand::Stop,
+            _ => return None,
+        };
+        words.next().is_none().then_some(command)
+    }
+}
+
+/// The console's `watch` mode, for checking an installation and for the host app's live view:
+/// while started, a line to `out` for each event the logger accepts and each record it completes.
+/// It subscribes to the `LoggerTask` rather than the `Logger`, which only aggregates.
+///
+/// Lines are the event's name then space-separated `key=value` pairs, times in seconds since the
+/// epoch and temperatures in °C. Ticks aren't shown. A line that doesn't fit in `out`, e.g. a
+/// full buffer the console hasn't sent yet, is counted in `dropped` rather than waited for.
+#[derive(Debug, Clone, Default)]
+pub struct Watch<W> {
+    out: W,
+    active: bool,
+    dropped: u32,
+}
+
+impl<W: Write> Watch<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, active: false, dropped: 0 }
+    }
+
+    pub fn command(&mut self, command: WatchCommand) {
+        self.active = command == WatchCommand::Start;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Lines lost because `out` was full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Where the lines go, e.g. to send and empty the buffer.
+    pub fn out_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    // Write a whole line or none of it.
+    fn line(&mut self, write: impl FnOnce(&mut ArrayString<MAX_WATCH_LINE>) -> fmt::Result) {
+        if !self.active {
+            return;
+        }
+        let mut line = ArrayString::new();
+        if write(&mut line).and_then(|()| self.out.write_str(&line)).is_err() {
+            self.dropped += 1;
+        }
+    }
+}
+
+impl<W: Write> Subscriber for Watch<W> {
+    fn event(&mut self, event: &LoggerEvent) {
+        if !matches!(event, LoggerEvent::Tick(_)) {
+            self.line(|out| write_event_line(out, event));
+        }
+    }
+
+    fn record(&mut self, record: &AggregationRecord) {
+        self.line(|out| write_record_line(out, record));
+    }
+}
+
+/// One `watch` line for `event`.
+pub fn write_event_line(out: &mut impl Write, event: &LoggerEvent) -> fmt::Result {
+    let at = event.timestamp().seconds;
+    match event {
+        LoggerEvent::Sample(sample) => {
+            write!(out, "sample at={} tvc={:.2} tamb={:.2}", at, sample.tvc, sample.tamb)?;
+            write!(out, " tvc_quality={:X} tamb_quality={:X}", sample.tvc_quality, sample.tamb_quality)?;
+            #[cfg(feature = "humidity")]
+            match sample.humidity {
+                Some(humidity) => write!(out, " humidity={:.1}", humidity)?,
+                None => write!(out, " humidity=-")?,
+            }
+            writeln!(out)
+        }
+        LoggerEvent::DoorOpened(_) => writeln!(out, "door_opened at={}", at),
+        LoggerEvent::DoorClosed(_) => writeln!(out, "door_closed at={}", at),
+        LoggerEvent::PowerLost(_) => writeln!(out, "power_lost at={}", at),
+        LoggerEvent::PowerRestored(_) => writeln!(out, "power_restored at={}", at),
+        LoggerEvent::Fault(_, code) => writeln!(out, "fault at={} code={}", at, *code as u8),
+        LoggerEvent::Tick(_) => writeln!(out, "tick at={}", at),
+        LoggerEvent::Paused(_, reason, seconds) => writeln!(out, "paused at={} reason={:?} seconds={}", at, reason, seconds),
+        LoggerEvent::Resumed(_) => writeln!(out, "resumed at={}", at),
+        LoggerEvent::ClockSet(before, _) => writeln!(out, "clock_set at={} before={}", at, before.seconds),
+        LoggerEvent::Moved(_) => writeln!(out, "moved at={}", at),
+    }
+}
+
+/// One `watch` line for a completed record.
+pub fn write_record_line(out: &mut impl Write, record: &AggregationRecord) -> fmt::Result {
+    write!(out, "record start={} tvc_seconds={}", record.start.seconds, record.tvc_seconds)?;
+    match record.tvc_average() {
+        Some(average) => write!(out, " tvc_avg={:.2} tvc_min={:.2} tvc_max={:.2}", average, record.tvc_min, record.tvc_max)?,
+        None => write!(out, " tvc_avg=- tvc_min=- tvc_max=-")?,
+    }
+    writeln!(out, " high_alarm_seconds={} low_alarm_seconds={} door_openings={}", record.high_alarm_seconds, record.low_alarm_seconds, record.door_openings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample;
+    use crate::alarm::{AlarmKind, AlarmProfile};
+    use crate::lifecycle::LifecycleState;
+    use crate::log::NullLog;
+    use crate::logger::Logger;
+    use crate::logger_task::{AlarmOutput, EventNumbers, LoggerTask};
+    use crate::store::RamStore;
+    use crate::timestamp::Timestamp;
+    use embassy_futures::block_on;
+
+    struct NoAlarms;
+
+    impl AlarmOutput for NoAlarms {
+        async fn set_active(&mut self, _kind: AlarmKind, _active: bool) {}
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(WatchCommand::parse("watch"), Some(WatchCommand::Start));
+        assert_eq!(WatchCommand::parse(" watch stop "), Some(WatchCommand::Stop));
+        assert_eq!(WatchCommand::parse("watch now"), None);
+        assert_eq!(WatchCommand::parse("debug agg"), None);
+    }
+
+    #[test]
+    fn test_watch_lines() {
+        let mut task = LoggerTask::new(Logger::default(), AlarmProfile::FRIDGE, LifecycleState::Logging, RamStore::<4>::new());
+        let mut watch = Watch::new(String::new());
+        let numbers = EventNumbers::new();
+        let events = [sample(0, 4.0), LoggerEvent::DoorOpened(Timestamp { seconds: 600 }), sample(900, 5.0), sample(300, 6.0)];
+        block_on(task.handle_watched(numbers.number(events[0]), &mut NoAlarms, &mut NullLog, &mut watch));
+        assert_eq!(watch.out_mut().as_str(), ""); // Not started.
+        watch.command(WatchCommand::Start);
+        for event in &events[1..] {
+            block_on(task.handle_watched(numbers.number(*event), &mut NoAlarms, &mut NullLog, &mut watch));
+        }
+        block_on(task.handle_watched(numbers.number(LoggerEvent::Tick(Timestamp { seconds: 1000 })), &mut NoAlarms, &mut NullLog, &mut watch));
+        // The record completes before the sample that completed it, and the late sample is dropped.
+        let lines: Vec<&str> = watch.out_mut().lines().collect();
+        let humidity = if cfg!(feature = "humidity") { " humidity=-" } else { "" };
+        assert_eq!(
+            lines,
+            [
+                "door_opened at=600",
+                "record start=0 tvc_seconds=900 tvc_avg=4.00 tvc_min=4.00 tvc_max=4.00 high_alarm_seconds=0 low_alarm_seconds=0 door_openings=1",
+                &format!("sample at=900 tvc=5.00 tamb=25.00 tvc_quality=0 tamb_quality=0{}", humidity),
+            ]
+        );
+        watch.command(WatchCommand::Stop);
+        block_on(task.handle_watched(numbers.number(sample(1200, 5.0)), &mut NoAlarms, &mut NullLog, &mut watch));
+        assert_eq!(watch.out_mut().lines().count(), 3);
+    }
+
+    #[test]
+    fn test_full_output_counts_dropped_lines() {
+        let mut watch = Watch::new(ArrayString::<32>::new());
+        watch.command(WatchCommand::Start);
+        watch.event(&LoggerEvent::DoorOpened(Timestamp { seconds: 600 }));
+        watch.event(&sample(900, 5.0)); // Too long for what is left.
+        assert_eq!(watch.dropped(), 1);
+        assert_eq!(watch.out_mut().as_str(), "door_opened at=600\n");
+    }
+
+    #[test]
+    fn test_widest_lines_fit() {
+        let mut watch = Watch::new(String::new());
+        watch.command(WatchCommand::Start);
+        let LoggerEvent::Sample(mut widest) = sample(u32::MAX, -f32::MAX) else { unreachable!() };
+        (widest.tamb, widest.tvc_quality, widest.tamb_quality) = (-f32::MAX, 0xFF, 0xFF);
+        #[cfg(feature = "humidity")]
+        {
+            widest.humidity = Some(-f32::MAX);
+        }
+        watch.event(&LoggerEvent::Sample(widest));
+        watch.event(&LoggerEvent::Paused(Timestamp { seconds: u32::MAX }, crate::logger::PauseReason::Maintenance, u32::MAX));
+        let mut record = AggregationRecord::new(Timestamp { seconds: u32::MAX });
+        (record.tvc_seconds, record.tvc_integral) = (1, -f32::MAX);
+        (record.tvc_min, record.tvc_max) = (-f32::MAX, -f32::MAX);
+        (record.high_alarm_seconds, record.low_alarm_seconds, record.door_openings) = (u32::MAX, u32::MAX, u32::MAX);
+        watch.record(&record);
+        assert_eq!(watch.dropped(), 0);
+        assert_eq!(watch.out_mut().lines().count(), 3);
+        assert!(watch.out_mut().lines().any(|line| line.starts_with("record start=4294967295 tvc_seconds=1 tvc_avg=-3402823")));
+    }
+}
@@ -0,0 +1,140 @@
+/// Tag in the upper half of the restart record word, so stale or random backup register contents are ignored.
+const RESTART_RECORD_TAG: u32 = 0x5744_0000;
+const RESTART_RECORD_TAG_MASK: u32 = 0xFFFF_0000;
+
+/// Long-running tasks supervised by the watchdog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TaskId {
+    Logger,
+    Temperature,
+    Mains,
+    StatusLed,
+}
+
+impl TaskId {
+    pub const COUNT: usize = 4;
+    pub const ALL: [TaskId; TaskId::COUNT] = [TaskId::Logger, TaskId::Temperature, TaskId::Mains, TaskId::StatusLed];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TaskId::Logger => "logger",
+            TaskId::Temperature => "temperature",
+            TaskId::Mains => "mains",
+            TaskId::StatusLed => "status_led",
+        }
+    }
+
+    /// Longest time the task may go without checking in.
+    pub fn heartbeat_timeout_ms(self) -> u64 {
+        match self {
+            TaskId::Logger => 60_000, // Receives a mains reading every 10 seconds.
+            TaskId::Temperature => 660_000, // Two normal sample periods plus margin.
+            TaskId::Mains => 60_000,
+            TaskId::StatusLed => 60_000, // Longest LED pattern is a few seconds.
+        }
+    }
+}
+
+/// Last check-in time of each supervised task.
+///
+/// A task is registered by its first check-in; tasks that have never checked in are not required to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatRegistry {
+    last_seen_ms: [Option<u64>; TaskId::COUNT],
+}
+
+impl Default for HeartbeatRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeartbeatRegistry {
+    pub const fn new() -> Self {
+        Self { last_seen_ms: [None; TaskId::COUNT] }
+    }
+
+    pub fn check_in(&mut self, task: TaskId, now_ms: u64) {
+        self.last_seen_ms[task as usize] = Some(now_ms);
+    }
+
+    /// The first registered task that has not checked in within its timeout, if any.
+    pub fn overdue(&self, now_ms: u64) -> Option<TaskId> {
+        TaskId::ALL.into_iter().find(|&task| {
+            self.last_seen_ms[task as usize]
+                .is_some_and(|seen| now_ms.saturating_sub(seen) > task.heartbeat_timeout_ms())
+        })
+    }
+}
+
+/// Why the logger last restarted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RestartCause {
+    PowerOn,
+    Watchdog,
+    Other,
+}
+
+/// A restart, with the task that stopped checking in if the watchdog caused it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RestartEvent {
+    pub cause: RestartCause,
+    pub missing_task: Option<TaskId>,
+}
+
+impl RestartEvent {
+    /// Word saved in a reset-surviving register before the watchdog is allowed to expire.
+    pub fn missing_task_record(task: TaskId) -> u32 {
+        RESTART_RECORD_TAG | task as u32
+    }
+
+    /// Decode the reset flags and the saved record word.
+    pub fn from_reset(power_on: bool, watchdog: bool, record: u32) -> Self {
+        let cause = if power_on {
+            RestartCause::PowerOn
+        } else if watchdog {
+            RestartCause::Watchdog
+        } else {
+            RestartCause::Other
+        };
+        let missing_task = if cause == RestartCause::Watchdog && record & RESTART_RECORD_TAG_MASK == RESTART_RECORD_TAG {
+            TaskId::ALL.get((record & !RESTART_RECORD_TAG_MASK) as usize).copied()
+        } else {
+            None
+        };
+        Self { cause, missing_task }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overdue_task() {
+        let mut registry = HeartbeatRegistry::new();
+        assert_eq!(registry.overdue(1_000_000), None);
+        registry.check_in(TaskId::Logger, 0);
+        registry.check_in(TaskId::Temperature, 0);
+        assert_eq!(registry.overdue(60_000), None);
+        assert_eq!(registry.overdue(60_001), Some(TaskId::Logger));
+        registry.check_in(TaskId::Logger, 60_000);
+        assert_eq!(registry.overdue(100_000), None);
+        assert_eq!(registry.overdue(700_000), Some(TaskId::Logger));
+        registry.check_in(TaskId::Logger, 700_000);
+        assert_eq!(registry.overdue(700_000), Some(TaskId::Temperature));
+    }
+
+    #[test]
+    fn test_restart_event() {
+        let record = RestartEvent::missing_task_record(TaskId::Mains);
+        let event = RestartEvent::from_reset(false, true, record);
+        assert_eq!(event, RestartEvent { cause: RestartCause::Watchdog, missing_task: Some(TaskId::Mains) });
+        assert_eq!(RestartEvent::from_reset(false, true, 2).missing_task, None);
+        assert_eq!(RestartEvent::from_reset(true, true, record).cause, RestartCause::PowerOn);
+        assert_eq!(RestartEvent::from_reset(false, false, record).missing_task, None);
+    }
+}
@@ -0,0 +1,251 @@
+/// Data bytes in an XMODEM-1K block.
+pub const XMODEM_1K_BLOCK_LEN: usize = 1024;
+/// Longest packet sent: header, block, CRC.
+pub const MAX_PACKET_LEN: usize = 3 + XMODEM_1K_BLOCK_LEN + 2;
+/// Times a packet is sent again after a NAK or timeout before giving up.
+pub const MAX_RETRIES: u8 = 10;
+/// How long to wait for the receiver before `XmodemSender::timeout`, as the protocol expects.
+pub const TIMEOUT_SECONDS: u32 = 10;
+const XMODEM_BLOCK_LEN: usize = 128; // Original XMODEM, for receivers that start with NAK.
+const SOH: u8 = 0x01; // Starts a 128-byte block.
+const STX: u8 = 0x02; // Starts a 1024-byte block.
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const CRC_MODE: u8 = b'C'; // Sent by receivers that want CRC-16, and so accept 1K blocks.
+const PAD: u8 = 0x1A; // Fills the last block, Ctrl-Z.
+
+/// Where the sender gets the file, e.g. a report exporter.
+pub trait ByteSource {
+    /// Fill the start of `buf`, returning how many bytes were written. 0 at the end of the file.
+    fn read(&mut self, buf: &mut [u8]) -> usize;
+}
+
+impl ByteSource for &[u8] {
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.len());
+        let (head, tail) = self.split_at(len);
+        buf[..len].copy_from_slice(head);
+        *self = tail;
+        len
+    }
+}
+
+/// Why a transfer ended without the receiver acknowledging the whole file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum XmodemError {
+    Cancelled, // The receiver sent two CANs.
+    TooManyRetries, // No ACK after `MAX_RETRIES` attempts, or the receiver never started.
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    WaitingStart,
+    SendingBlock,
+    SendingEot,
+    Done,
+}
+
+/// Sends a file to a terminal emulator with XMODEM-1K, or plain XMODEM if the receiver asks for it.
+///
+/// The sender doesn't touch the UART: feed it each byte received, and `timeout` when the receiver
+/// has been silent for `TIMEOUT_SECONDS`, and transmit what they return.
+pub struct XmodemSender<S> {
+    source: S,
+    state: State,
+    block: u8, // Number of the block in `packet`, wrapping.
+    crc: bool, // CRC-16 and 1K blocks, rather than the checksum and 128-byte blocks.
+    packet: [u8; MAX_PACKET_LEN],
+    packet_len: usize,
+    retries: u8,
+    cancel_pending: bool, // The last byte received was a CAN.
+}
+
+impl<S: ByteSource> XmodemSender<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            state: State::WaitingStart,
+            block: 0,
+            crc: true,
+            packet: [0; MAX_PACKET_LEN],
+            packet_len: 0,
+            retries: 0,
+            cancel_pending: false,
+        }
+    }
+
+    /// Handle a byte from the receiver. Returns the bytes to transmit, often none.
+    pub fn receive(&mut self, byte: u8) -> Result<&[u8], XmodemError> {
+        if byte == CAN && core::mem::replace(&mut self.cancel_pending, true) {
+            self.state = State::Done;
+            return Err(XmodemError::Cancelled);
+        }
+        if byte != CAN {
+            self.cancel_pending = false;
+        }
+        match (self.state, byte) {
+            (State::WaitingStart, CRC_MODE | NAK) => {
+                self.crc = byte == CRC_MODE;
+                Ok(self.next_packet())
+            }
+            (State::SendingBlock, ACK) => {
+                self.retries = 0;
+                Ok(self.next_packet())
+            }
+            (State::SendingEot, ACK) => {
+                self.state = State::Done;
+                Ok(&[])
+            }
+            (State::SendingBlock | State::SendingEot, NAK) => self.retry(),
+            _ => Ok(&[]), // Line noise, or a receiver repeating itself.
+        }
+    }
+
+    /// Handle `TIMEOUT_SECONDS` of silence from the receiver. Returns the bytes to transmit.
+    pub fn timeout(&mut self) -> Result<&[u8], XmodemError> {
+        match self.state {
+            State::Done => Ok(&[]),
+            State::WaitingStart => {
+                self.retries += 1;
+                if self.retries > MAX_RETRIES {
+                    self.state = State::Done;
+                    return Err(XmodemError::TooManyRetries);
+                }
+                Ok(&[])
+            }
+            State::SendingBlock | State::SendingEot => self.retry(),
+        }
+    }
+
+    /// Whether the transfer is over, successfully or not.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    fn retry(&mut self) -> Result<&[u8], XmodemError> {
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            self.state = State::Done;
+            return Err(XmodemError::TooManyRetries);
+        }
+        Ok(&self.packet[..self.packet_len])
+    }
+
+    // Load the next block into `packet`, or EOT once the source runs dry.
+    fn next_packet(&mut self) -> &[u8] {
+        self.retries = 0;
+        let block_len = if self.crc { XMODEM_1K_BLOCK_LEN } else { XMODEM_BLOCK_LEN };
+        let data = &mut self.packet[3..3 + block_len];
+        let mut filled = 0;
+        while filled < block_len {
+            let read = self.source.read(&mut data[filled..]);
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+        if filled == 0 {
+            self.state = State::SendingEot;
+            self.packet[0] = EOT;
+            self.packet_len = 1;
+            return &self.packet[..1];
+        }
+        data[filled..].fill(PAD);
+        self.state = State::SendingBlock;
+        self.block = self.block.wrapping_add(1);
+        self.packet[0] = if self.crc { STX } else { SOH };
+        self.packet[1] = self.block;
+        self.packet[2] = !self.block;
+        let end = 3 + block_len;
+        if self.crc {
+            let crc = crc16_xmodem(&self.packet[3..end]);
+            self.packet[end..end + 2].copy_from_slice(&crc.to_be_bytes());
+            self.packet_len = end + 2;
+        } else {
+            self.packet[end] = self.packet[3..end].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+            self.packet_len = end + 1;
+        }
+        &self.packet[..self.packet_len]
+    }
+}
+
+/// CRC-16/XMODEM: polynomial 0x1021, starting from 0.
+pub fn crc16_xmodem(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc16_xmodem() {
+        assert_eq!(crc16_xmodem(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn test_1k_transfer() {
+        let file: Vec<u8> = (0..1500u32).map(|i| i as u8).collect();
+        let mut sender = XmodemSender::new(file.as_slice());
+        assert_eq!(sender.receive(b'x'), Ok(&[][..]));
+        let packet = sender.receive(CRC_MODE).unwrap().to_vec();
+        assert_eq!(packet.len(), MAX_PACKET_LEN);
+        assert_eq!(packet[..3], [STX, 1, 0xFE]);
+        assert_eq!(packet[3..1027], file[..1024]);
+        assert_eq!(u16::from_be_bytes([packet[1027], packet[1028]]), crc16_xmodem(&file[..1024]));
+        // A corrupted block is sent again.
+        assert_eq!(sender.receive(NAK).unwrap(), packet);
+        let packet = sender.receive(ACK).unwrap().to_vec();
+        assert_eq!(packet[..3], [STX, 2, 0xFD]);
+        assert_eq!(packet[3..479], file[1024..]);
+        assert!(packet[479..1027].iter().all(|&byte| byte == PAD));
+        assert_eq!(sender.receive(ACK), Ok(&[EOT][..]));
+        assert_eq!(sender.receive(NAK), Ok(&[EOT][..])); // Some receivers NAK the first EOT.
+        assert!(!sender.is_done());
+        assert_eq!(sender.receive(ACK), Ok(&[][..]));
+        assert!(sender.is_done());
+    }
+
+    #[test]
+    fn test_checksum_mode() {
+        let file = [1u8, 2, 3];
+        let mut sender = XmodemSender::new(&file[..]);
+        let packet = sender.receive(NAK).unwrap().to_vec();
+        assert_eq!(packet.len(), 3 + 128 + 1);
+        assert_eq!(packet[..6], [SOH, 1, 0xFE, 1, 2, 3]);
+        assert_eq!(packet[131], 6u8.wrapping_add(PAD.wrapping_mul(125)));
+        assert_eq!(sender.receive(ACK), Ok(&[EOT][..]));
+    }
+
+    #[test]
+    fn test_cancel_and_retries() {
+        let mut sender = XmodemSender::new(&[0u8; 10][..]);
+        sender.receive(CRC_MODE).unwrap();
+        assert_eq!(sender.receive(CAN), Ok(&[][..]));
+        assert_eq!(sender.receive(CAN), Err(XmodemError::Cancelled));
+        assert!(sender.is_done());
+
+        let mut sender = XmodemSender::new(&[0u8; 10][..]);
+        sender.receive(CRC_MODE).unwrap();
+        for _ in 0..MAX_RETRIES {
+            assert_eq!(sender.timeout().unwrap().len(), MAX_PACKET_LEN);
+        }
+        assert_eq!(sender.timeout(), Err(XmodemError::TooManyRetries));
+
+        let mut sender = XmodemSender::new(&[0u8; 10][..]);
+        for _ in 0..MAX_RETRIES {
+            assert_eq!(sender.timeout(), Ok(&[][..]));
+        }
+        assert_eq!(sender.timeout(), Err(XmodemError::TooManyRetries));
+    }
+}
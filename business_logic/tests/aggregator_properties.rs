@@ -0,0 +1,94 @@
+// Randomized checks of the aggregation invariants. Host only.
+
+mod common;
+
+use business_logic::aggregator::{AggregationRecord, TemperatureAggregator};
+use business_logic::alarm::AlarmProfile;
+use business_logic::logger::{Logger, LoggerEvent, SamplePolicy};
+use business_logic::timestamp::Timestamp;
+use common::sample;
+use proptest::prelude::*;
+
+const TOLERANCE: f32 = 1e-3; // Relative, for averages computed from f32 integrals.
+
+/// (TVC, ambient, seconds held) readings spanning the alarm thresholds.
+fn readings() -> impl Strategy<Value = Vec<(f32, f32, u32)>> {
+    prop::collection::vec((-10.0f32..20.0, -10.0f32..45.0, 0u32..3600), 1..200)
+}
+
+/// Sample times as gaps from the previous sample, some longer than the hold limit.
+fn sample_gaps() -> impl Strategy<Value = Vec<(u32, f32)>> {
+    prop::collection::vec((1u32..3 * SamplePolicy::STANDARD.max_hold_seconds, -10.0f32..20.0), 1..200)
+}
+
+fn assert_record_invariants(record: &AggregationRecord) -> Result<(), TestCaseError> {
+    prop_assert!(record.high_alarm_seconds <= record.high_seconds);
+    prop_assert!(record.low_alarm_seconds <= record.low_seconds);
+    prop_assert!(record.high_seconds + record.low_seconds <= record.tvc_seconds);
+    prop_assert_eq!(record.band_seconds.iter().sum::<u32>(), record.tvc_seconds);
+    if record.tvc_seconds > 0 {
+        let average = record.tvc_average().unwrap();
+        let slack = TOLERANCE * record.tvc_min.abs().max(record.tvc_max.abs()).max(1.0);
+        prop_assert!(record.tvc_min - slack <= average && average <= record.tvc_max + slack, "{:?}", record);
+        let average = record.tamb_average().unwrap();
+        let slack = TOLERANCE * record.tamb_min.abs().max(record.tamb_max.abs()).max(1.0);
+        prop_assert!(record.tamb_min - slack <= average && average <= record.tamb_max + slack, "{:?}", record);
+        let mean = record.differential_mean().unwrap();
+        let slack = TOLERANCE * record.tvc_min.abs().max(record.tvc_max.abs()).max(record.tamb_max.abs()).max(1.0);
+        prop_assert!(mean <= record.differential_max + slack, "{:?}", record);
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn aggregator_invariants(readings in readings()) {
+        let mut aggregator = TemperatureAggregator::new(Timestamp { seconds: 0 }, AlarmProfile::FRIDGE);
+        for &(tvc, tamb, seconds) in &readings {
+            aggregator.add_sample(tvc, tamb);
+            aggregator.add_held(tvc, tamb, seconds);
+        }
+        prop_assert_eq!(aggregator.sample_count() as usize, readings.len());
+        let covered: u32 = readings.iter().map(|reading| reading.2).sum();
+        prop_assert_eq!(aggregator.current().tvc_seconds, covered);
+        assert_record_invariants(aggregator.current())?;
+    }
+
+    #[test]
+    fn integrals_match_f64(readings in prop::collection::vec((-10.0f32..20.0, -10.0f32..45.0, 1u32..30), 1..2000)) {
+        let mut aggregator = TemperatureAggregator::new(Timestamp { seconds: 0 }, AlarmProfile::FRIDGE);
+        let (mut tvc_reference, mut tamb_reference) = (0.0f64, 0.0f64);
+        for &(tvc, tamb, seconds) in &readings {
+            aggregator.add_held(tvc, tamb, seconds);
+            tvc_reference += f64::from(tvc * seconds as f32);
+            tamb_reference += f64::from(tamb * seconds as f32);
+        }
+        let record = aggregator.current();
+        let seconds = f64::from(record.tvc_seconds);
+        prop_assert!((f64::from(record.tvc_integral) - tvc_reference).abs() / seconds < 1e-4, "{:?}", record);
+        prop_assert!((f64::from(record.tamb_integral) - tamb_reference).abs() / seconds < 1e-4, "{:?}", record);
+    }
+
+    #[test]
+    fn logger_covers_held_time(gaps in sample_gaps()) {
+        let mut logger = Logger::default();
+        let mut records = Vec::new();
+        let mut now = 0;
+        for &(gap, tvc) in &gaps {
+            logger.process_event(sample(now, tvc), |record| records.push(record)).unwrap();
+            now += gap;
+        }
+        logger.process_event(LoggerEvent::Tick(Timestamp { seconds: now }), |record| records.push(record)).unwrap();
+        logger.flush(|record| records.push(record));
+
+        // Each reading stands until the next event, but no longer than the hold limit.
+        let covered: u32 = gaps.iter().map(|&(gap, _)| gap.min(SamplePolicy::STANDARD.max_hold_seconds)).sum();
+        prop_assert_eq!(records.iter().map(|record| record.tvc_seconds).sum::<u32>(), covered);
+        for pair in records.windows(2) {
+            prop_assert!(pair[0].start.seconds < pair[1].start.seconds);
+        }
+        for record in &records {
+            assert_record_invariants(record)?;
+        }
+    }
+}
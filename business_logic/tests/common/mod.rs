@@ -0,0 +1,18 @@
+// Fixtures shared by the integration tests, which can't see the library's own test support.
+
+use business_logic::logger::LoggerEvent;
+use business_logic::sample::TemperatureSample;
+use business_logic::timestamp::Timestamp;
+
+/// A plain reading of `tvc` at `seconds`, with the ambient at 25 °C.
+pub fn sample(seconds: u32, tvc: f32) -> LoggerEvent {
+    LoggerEvent::Sample(TemperatureSample {
+        timestamp: Timestamp { seconds },
+        tamb: 25.0,
+        tvc,
+        tamb_quality: 0,
+        tvc_quality: 0,
+        #[cfg(feature = "humidity")]
+        humidity: None,
+    })
+}
@@ -0,0 +1,81 @@
+// A 30-day run of 15-minute samples, compressed into a host test, checked against hand-computed totals.
+
+mod common;
+
+use business_logic::errors::ErrorCode;
+use business_logic::logger::{Logger, LoggerEvent};
+use business_logic::report::Report;
+use business_logic::store::{RamStore, RecordStore};
+use business_logic::timestamp::Timestamp;
+use common::sample;
+
+const DAY: u32 = 86400;
+const HOUR: u32 = 3600;
+const SAMPLE_PERIOD: u32 = 900;
+const DAYS: u32 = 30;
+
+/// Vaccine temperature at `seconds`: 5 °C, with a 12 hour high excursion on day 10 and a
+/// 2 hour freeze on day 20. None during a 4 hour sensor outage on day 25.
+fn tvc_at(seconds: u32) -> Option<f32> {
+    let (day, time) = (seconds / DAY, seconds % DAY);
+    match (day, time) {
+        (10, time) if time < 12 * HOUR => Some(10.0),
+        (20, time) if time < 2 * HOUR => Some(-1.0),
+        (25, time) if time < 4 * HOUR => None,
+        _ => Some(5.0),
+    }
+}
+
+fn scenario() -> Vec<LoggerEvent> {
+    let mut events = Vec::new();
+    for seconds in (0..DAYS * DAY).step_by(SAMPLE_PERIOD as usize) {
+        if let Some(tvc) = tvc_at(seconds) {
+            events.push(sample(seconds, tvc));
+        }
+    }
+    // Three 5 minute door openings a day, between samples.
+    for day in 0..DAYS {
+        for hour in [8, 12, 17] {
+            let opened = day * DAY + hour * HOUR + 60;
+            events.push(LoggerEvent::DoorOpened(Timestamp { seconds: opened }));
+            events.push(LoggerEvent::DoorClosed(Timestamp { seconds: opened + 300 }));
+        }
+    }
+    // A 3 hour mains outage on day 15.
+    events.push(LoggerEvent::PowerLost(Timestamp { seconds: 15 * DAY + 6 * HOUR }));
+    events.push(LoggerEvent::PowerRestored(Timestamp { seconds: 15 * DAY + 9 * HOUR }));
+    // The sensor outage is reported as it starts.
+    events.push(LoggerEvent::Fault(Timestamp { seconds: 25 * DAY + 60 }, ErrorCode::SensorFail));
+    events.push(LoggerEvent::Tick(Timestamp { seconds: DAYS * DAY }));
+    events.sort_by_key(|event| event.timestamp().seconds);
+    events
+}
+
+#[test]
+fn thirty_day_report() {
+    let mut logger = Logger::default();
+    let mut store = Box::new(RamStore::<3000>::new());
+    for event in scenario() {
+        logger.process_event(event, |record| store.append(record)).unwrap();
+    }
+    logger.flush(|record| store.append(record));
+
+    // 2880 periods, less the 16 of the outage. Only the first of those has a record, for the fault.
+    let samples = DAYS * DAY / SAMPLE_PERIOD - 16;
+    assert_eq!(store.len(), samples as usize + 1);
+    let report = Report::generate(store.iter(), Timestamp { seconds: 0 }, Timestamp { seconds: DAYS * DAY });
+    assert_eq!(report.records, samples + 1);
+    // Each sample is held until the next one, 15 minutes later.
+    assert_eq!(report.covered_seconds, samples * SAMPLE_PERIOD);
+    assert_eq!(report.uncovered_seconds, 4 * HOUR);
+    // The 48 samples at 10 °C and 8 at -1 °C shift the average: (2864 × 5 + 48 × 5 − 8 × 6) / 2864.
+    let average = report.tvc_average.unwrap();
+    assert!((average - 14512.0 / 2864.0).abs() < 1e-3, "{}", average);
+    assert_eq!((report.tvc_min, report.tvc_max), (Some(-1.0), Some(10.0)));
+    // High alarm after 10 of the 12 hours; freeze alarm after 1 of the 2 hours.
+    assert_eq!((report.high_seconds, report.high_alarm_seconds), (12 * HOUR, 2 * HOUR));
+    assert_eq!((report.low_seconds, report.low_alarm_seconds), (2 * HOUR, HOUR));
+    assert_eq!((report.door_openings, report.door_open_seconds), (90, 90 * 300));
+    assert_eq!(report.power_off_seconds, 3 * HOUR);
+    assert_eq!(report.logger_errors.iter().collect::<Vec<_>>(), [ErrorCode::SensorFail]);
+}
@@ -0,0 +1,8 @@
+/* STM32L476JE. The image is linked into one 256 KB bank, less the pages reserved at its end
+   (`business_logic::firmware::RESERVED_PAGES`), so it can be staged into the other bank and
+   never overlaps the data pages. */
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 256K - 32 * 2K
+  RAM   : ORIGIN = 0x20000000, LENGTH = 96K
+}
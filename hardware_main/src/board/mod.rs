@@ -0,0 +1,17 @@
+// Everything that differs between hardware revisions: pin maps, sensor addresses and which
+// optional parts are fitted. One revision is selected by Cargo feature, and main.rs takes it all
+// from here rather than testing features itself.
+
+#[cfg(not(any(feature = "rev-a", feature = "rev-b")))]
+compile_error!("Select a board revision with its Cargo feature, e.g. `--features rev-a`.");
+#[cfg(all(feature = "rev-a", feature = "rev-b"))]
+compile_error!("Select only one board revision, e.g. `--no-default-features --features debug,rev-b`.");
+
+#[cfg(feature = "rev-a")]
+mod rev_a;
+#[cfg(feature = "rev-a")]
+pub use rev_a::*;
+#[cfg(feature = "rev-b")]
+mod rev_b;
+#[cfg(feature = "rev-b")]
+pub use rev_b::*;
@@ -0,0 +1,116 @@
+// Rev B. Until its schematic is final this is Rev A's pin map, with the vaccine sensor moved to
+// I2C2 on PB10/PB11, and builds for bring-up only.
+// TODO: confirm against the Rev B schematic, which leaves open:
+// - whether the display and external flash rails get gates, as planned, and on which pins;
+// - whether the buzzer is still fitted, or left to the relay;
+// - the sensor addresses, if the ambient sensor moves off the vaccine sensor's bus;
+// - which EXTI lines are free for the door and accelerometer inputs after the re-layout.
+
+use business_logic::power::Rail;
+use embassy_stm32::adc::Adc;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::{Input, Level, Output, OutputOpenDrain, Pull, Speed};
+use embassy_stm32::i2c::{ErrorInterruptHandler, EventInterruptHandler, I2c};
+use embassy_stm32::mode::Async;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::usart::{BufferedInterruptHandler, BufferedUart, Config as UartConfig};
+use embassy_stm32::{bind_interrupts, peripherals, Peripherals};
+
+use crate::power_gate::RailPin;
+
+pub const NAME: &str = "Rev B";
+pub const AMBIENT_ADDRESS: u8 = 0x45; // I2C address for ambient temperature sensor.
+pub const VACCINE_ADDRESS: u8 = 0x44; // I2C address for vaccine temperature sensor.
+pub const HAS_EXTERNAL_FLASH: bool = true; // Fitted, though there is no driver for it yet.
+
+/// ADC and input for the mains-derived supply voltage divider.
+pub type MainsAdc = peripherals::ADC1;
+pub type MainsPin = peripherals::PA1;
+
+const CONSOLE_BUFFER_LEN: usize = 128; // Each way, a line and a bit.
+
+bind_interrupts!(struct Irqs {
+    I2C1_EV => EventInterruptHandler<peripherals::I2C1>;
+    I2C1_ER => ErrorInterruptHandler<peripherals::I2C1>;
+    I2C2_EV => EventInterruptHandler<peripherals::I2C2>;
+    I2C2_ER => ErrorInterruptHandler<peripherals::I2C2>;
+    I2C3_EV => EventInterruptHandler<peripherals::I2C3>;
+    I2C3_ER => ErrorInterruptHandler<peripherals::I2C3>;
+    USART2 => BufferedInterruptHandler<peripherals::USART2>;
+});
+
+/// The door switch input, configured once the wiring is known from the settings.
+pub struct DoorPin {
+    pin: peripherals::PB4,
+    channel: peripherals::EXTI4,
+}
+
+impl DoorPin {
+    pub fn input(self, pull: Pull) -> ExtiInput<'static> {
+        ExtiInput::new(self.pin, self.channel, pull)
+    }
+}
+
+/// The board's peripherals, set up for how they are wired. Optional parts that aren't fitted are None.
+pub struct Board {
+    pub rails: [Option<RailPin>; Rail::COUNT], // Gates of the switchable rails, see `POWER_GATE`.
+    pub led: Output<'static>,
+    pub buzzer: Option<Output<'static>>, // Active buzzer, sounds while high.
+    pub relay: OutputOpenDrain<'static>, // To a siren or alarm panel, pulled low while asserted.
+    pub button: ExtiInput<'static>,
+    pub compressor: ExtiInput<'static>, // High while the compressor draws current.
+    pub vbus: ExtiInput<'static>, // OTG_FS_VBUS, high while USB is plugged in.
+    pub mains_present: ExtiInput<'static>, // Optocoupler on the mains supply, high while it is present.
+    pub door: DoorPin,
+    pub accelerometer_int: ExtiInput<'static>, // LIS3DH INT1, push-pull and high while a shock is latched.
+    pub straps: [Input<'static>; 2], // Alarm profile jumpers to ground, pulled up.
+    pub adc: Adc<'static, MainsAdc>,
+    pub mains_pin: MainsPin,
+    pub sensor_i2c: I2c<'static, Async>, // Temperature sensors, and the humidity sensor and accelerometer if fitted.
+    pub vaccine_i2c: Option<I2c<'static, Async>>, // A bus of the vaccine sensor's own, None if it is on the sensor bus.
+    pub display_i2c: Option<I2c<'static, Async>>, // The display has its own bus.
+    pub console: BufferedUart<'static>, // USART2 to the service header, interrupt-driven since the I2C buses have the DMA channels.
+    // The same on every revision.
+    pub rtc: peripherals::RTC,
+    pub flash: peripherals::FLASH,
+    pub iwdg: peripherals::IWDG,
+}
+
+impl Board {
+    pub fn new(p: Peripherals) -> Self {
+        // As on Rev A, only the sensor rail is gated until the other gates are confirmed.
+        let sensor_power = Output::new(p.PA15, Level::High, Speed::Low);
+        Self {
+            rails: [Some(RailPin::new(sensor_power, true)), None, None],
+            led: Output::new(p.PB0, Level::High, Speed::Low),
+            buzzer: Some(Output::new(p.PA8, Level::Low, Speed::Low)),
+            relay: OutputOpenDrain::new(p.PB1, Level::High, Speed::Low),
+            button: ExtiInput::new(p.PB5, p.EXTI5, Pull::Up),
+            compressor: ExtiInput::new(p.PA0, p.EXTI0, Pull::Down),
+            vbus: ExtiInput::new(p.PA9, p.EXTI9, Pull::Down),
+            mains_present: ExtiInput::new(p.PB2, p.EXTI2, Pull::Down),
+            door: DoorPin { pin: p.PB4, channel: p.EXTI4 },
+            accelerometer_int: ExtiInput::new(p.PB12, p.EXTI12, Pull::Down), // Pulled down in case it isn't fitted.
+            straps: [Input::new(p.PC2, Pull::Up), Input::new(p.PC3, Pull::Up)],
+            adc: Adc::new(p.ADC1),
+            mains_pin: p.PA1,
+            sensor_i2c: I2c::new(p.I2C1, p.PB6, p.PB7, Irqs, p.DMA1_CH6, p.DMA1_CH7, Hertz(400_000), Default::default()),
+            // Its own bus, so a lockup of I2C1 can't stop the vaccine readings.
+            vaccine_i2c: Some(I2c::new(p.I2C2, p.PB10, p.PB11, Irqs, p.DMA1_CH4, p.DMA1_CH5, Hertz(400_000), Default::default())),
+            display_i2c: Some(I2c::new(p.I2C3, p.PC0, p.PC1, Irqs, p.DMA1_CH2, p.DMA1_CH3, Hertz(400_000), Default::default())),
+            console: BufferedUart::new(
+                p.USART2,
+                Irqs,
+                p.PA3,
+                p.PA2,
+                cortex_m::singleton!(: [u8; CONSOLE_BUFFER_LEN] = [0; CONSOLE_BUFFER_LEN]).unwrap(),
+                cortex_m::singleton!(: [u8; CONSOLE_BUFFER_LEN] = [0; CONSOLE_BUFFER_LEN]).unwrap(),
+                UartConfig::default(), // The baud rate is set from the settings once they are loaded.
+            )
+            .unwrap(),
+            rtc: p.RTC,
+            flash: p.FLASH,
+            iwdg: p.IWDG,
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use core::mem::MaybeUninit;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use business_logic::crash::{CrashKind, CrashRecord, CRASH_RECORD_WORDS, CRASH_STACK_WORDS};
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::{exception, ExceptionFrame};
+
+// Not zeroed at startup, so the record survives the reset that follows a crash. The handlers
+// can't wait for the flash, so the next boot moves the record to the NV store, see
+// `flash_store::save_crash_record`.
+#[unsafe(link_section = ".uninit.CRASH_RECORD")]
+static mut CRASH_RECORD: MaybeUninit<[u32; CRASH_RECORD_WORDS]> = MaybeUninit::uninit();
+// Set once the panic handler has saved its record, so the hard fault it ends in keeps it.
+static PANICKED: AtomicBool = AtomicBool::new(false);
+
+fn save(record: &CrashRecord) {
+    // SAFETY: only written here, with interrupts about to stop for good, and read once at boot.
+    unsafe { (&raw mut CRASH_RECORD).write_volatile(MaybeUninit::new(record.to_words())) };
+}
+
+/// Read the record left by a crash before the last reset, and clear it.
+pub fn take_crash_record() -> Option<CrashRecord> {
+    // SAFETY: called once at boot before anything can crash; any bit pattern is a valid [u32].
+    let words = unsafe { (&raw const CRASH_RECORD).read_volatile().assume_init() };
+    save_cleared();
+    CrashRecord::from_words(&words)
+}
+
+fn save_cleared() {
+    // SAFETY: as for `save`.
+    unsafe { (&raw mut CRASH_RECORD).write_volatile(MaybeUninit::new([0; CRASH_RECORD_WORDS])) };
+}
+
+/// Copy the words above `sp`, which is the top of the stack at the time of the crash.
+fn stack_snapshot(sp: *const u32) -> [u32; CRASH_STACK_WORDS] {
+    let mut stack = [0u32; CRASH_STACK_WORDS];
+    for (i, word) in stack.iter_mut().enumerate() {
+        // SAFETY: the stack grows down, so the words above the stack pointer are RAM.
+        *word = unsafe { sp.add(i).read_volatile() };
+    }
+    stack
+}
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    // The panic handler ends here, having saved a better record.
+    if PANICKED.load(Ordering::Relaxed) {
+        SCB::sys_reset();
+    }
+    let mut record = CrashRecord::new(CrashKind::HardFault);
+    record.pc = frame.pc();
+    record.lr = frame.lr();
+    record.stack = stack_snapshot(frame as *const ExceptionFrame as *const u32);
+    save(&record);
+    SCB::sys_reset();
+}
+
+/// Save the panic, print it over defmt if the build has it, then end in a hard fault like
+/// panic-probe, so a debugger attached shows the backtrace. Without one the hard fault handler
+/// resets.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    // A panic while saving or printing goes straight to the hard fault.
+    if !PANICKED.swap(true, Ordering::Relaxed) {
+        let mut record = CrashRecord::new(CrashKind::Panic);
+        record.lr = cortex_m::register::lr::read();
+        record.pc = cortex_m::register::pc::read();
+        record.stack = stack_snapshot(cortex_m::register::msp::read() as *const u32);
+        record.set_message(format_args!("{}", info));
+        save(&record);
+        #[cfg(feature = "defmt")]
+        defmt::error!("{}", defmt::Display2Format(info));
+    }
+    hard_fault();
+}
+
+/// Raise a hard fault with `udf`, with usage faults disabled so it isn't taken as one.
+fn hard_fault() -> ! {
+    // SAFETY: only clears USGFAULTENA in the SHCSR, with interrupts disabled.
+    unsafe { (*SCB::PTR).shcsr.modify(|shcsr| shcsr & !(1 << 18)) };
+    cortex_m::asm::udf();
+}
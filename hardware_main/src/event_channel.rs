@@ -0,0 +1,47 @@
+use core::cell::RefCell;
+
+use business_logic::event_queue::{EventQueue, Queued};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+
+use crate::BusinessLog;
+
+/// A channel between tasks over an `EventQueue`, for a single receiver.
+pub struct EventChannel<T, const N: usize> {
+    queue: Mutex<ThreadModeRawMutex, RefCell<EventQueue<T, N>>>,
+    ready: Signal<ThreadModeRawMutex, ()>, // Raised on every send, for the single receiver.
+}
+
+impl<T: Queued, const N: usize> EventChannel<T, N> {
+    pub const fn new() -> Self {
+        Self { queue: Mutex::new(RefCell::new(EventQueue::new())), ready: Signal::new() }
+    }
+
+    pub fn send(&self, event: T) {
+        self.queue.lock(|queue| queue.borrow_mut().push(event, &mut BusinessLog));
+        self.ready.signal(());
+    }
+
+    /// Wait for the next event. Only one task may receive.
+    pub async fn receive(&self) -> T {
+        loop {
+            if let Some(event) = self.queue.lock(|queue| queue.borrow_mut().pop()) {
+                return event;
+            }
+            self.ready.wait().await;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.lock(|queue| queue.borrow().len())
+    }
+
+    pub fn overflows(&self) -> u32 {
+        self.queue.lock(|queue| queue.borrow().overflows())
+    }
+
+    pub fn coalesced(&self) -> u32 {
+        self.queue.lock(|queue| queue.borrow().coalesced())
+    }
+}
@@ -0,0 +1,163 @@
+use core::fmt::{self, Write};
+use core::mem::MaybeUninit;
+
+use business_logic::console::UpdateCommand;
+use business_logic::firmware::{Bank, BootAction, BootState, ImageStager, UpdateError, FLASH_PAGE_BYTES, PROGRAM_UNIT_BYTES};
+use cortex_m::peripheral::SCB;
+use embassy_stm32::flash::{Blocking, Flash, FLASH_SIZE};
+use embassy_stm32::pac;
+use embassy_stm32::rtc::Rtc;
+
+use crate::flash_store::with_flash;
+use crate::fmt::info;
+use crate::BusinessLog;
+
+/// Start of the staging area: the second half of flash, which is always the bank not booted from.
+/// It ends at that bank's reserved pages, see `IMAGE_CAPACITY_BYTES`.
+const STAGING_OFFSET: u32 = FLASH_SIZE as u32 / 2;
+const SYSTEM_MEMORY_ADDRESS: u32 = 0x1FFF_0000; // ROM bootloader vector table, see AN2606.
+const BOOTLOADER_REQUEST_MAGIC: u32 = 0xB007_10AD;
+const RTC_BACKUP_BOOT_INDEX: usize = 14; // RTC backup register for the `BootState`, after the power-fail checkpoint.
+const FLASH_KEYS: [u32; 2] = [0x4567_0123, 0xCDEF_89AB];
+const OPTION_KEYS: [u32; 2] = [0x0819_2A3B, 0x4C5D_6E7F];
+
+const _: () = assert!(RTC_BACKUP_BOOT_INDEX < Rtc::BACKUP_REGISTER_COUNT);
+
+// Not zeroed at startup, so the request survives the reset that hands over to the bootloader.
+#[unsafe(link_section = ".uninit.BOOTLOADER_REQUEST")]
+static mut BOOTLOADER_REQUEST: MaybeUninit<u32> = MaybeUninit::uninit();
+
+/// Carry out a console `update` command, for the upload under way in `staging`, and answer it
+/// on `out`. An upload that fails a step is abandoned, so the uploader starts again.
+pub fn command(staging: &mut Option<ImageStager>, command: UpdateCommand, out: &mut impl Write) -> fmt::Result {
+    let under_way = staging.as_mut().ok_or(UpdateError::NotStaging);
+    let result = match command {
+        UpdateCommand::Begin(length, crc) => ImageStager::new(length, crc).map(|stager| *staging = Some(stager)),
+        UpdateCommand::Data(chunk) => under_way.and_then(|stager| with_flash(|flash| stage_chunk(flash, stager, &chunk))),
+        UpdateCommand::Finish => under_way.and_then(|stager| {
+            stager.finish(&mut BusinessLog)?;
+            with_flash(|flash| verify_staged(flash, stager))
+        }),
+        UpdateCommand::Boot => under_way.and_then(|stager| with_flash(|flash| boot_staged_image(flash, stager))),
+        UpdateCommand::Bootloader => request_system_bootloader(),
+    };
+    match result {
+        Ok(()) => writeln!(out, "ok"),
+        Err(error) => {
+            *staging = None;
+            writeln!(out, "error {:?}", error)
+        }
+    }
+}
+
+/// Check the next chunk of the image and write it to the staging area, erasing each page as the
+/// image reaches it, so no one erase holds up the other tasks for long.
+fn stage_chunk(flash: &mut Flash<'static, Blocking>, stager: &mut ImageStager, chunk: &[u8]) -> Result<(), UpdateError> {
+    let offset = stager.accept(chunk)?;
+    let end = stager.received();
+    for page in offset.div_ceil(FLASH_PAGE_BYTES)..end.div_ceil(FLASH_PAGE_BYTES) {
+        let start = STAGING_OFFSET + page * FLASH_PAGE_BYTES;
+        flash.blocking_erase(start, start + FLASH_PAGE_BYTES).map_err(|_| UpdateError::Flash)?;
+    }
+    let offset = STAGING_OFFSET + offset;
+    let whole = chunk.len() - chunk.len() % PROGRAM_UNIT_BYTES as usize;
+    flash.blocking_write(offset, &chunk[..whole]).map_err(|_| UpdateError::Flash)?;
+    if whole < chunk.len() {
+        // Only the last chunk may be short; pad it out with erased bytes.
+        let mut tail = [0xFF; PROGRAM_UNIT_BYTES as usize];
+        tail[..chunk.len() - whole].copy_from_slice(&chunk[whole..]);
+        flash.blocking_write(offset + whole as u32, &tail).map_err(|_| UpdateError::Flash)?;
+    }
+    Ok(())
+}
+
+/// Read the staged image back and check it against the announced CRC.
+fn verify_staged(flash: &mut Flash<'static, Blocking>, stager: &ImageStager) -> Result<(), UpdateError> {
+    stager.verify(|offset, buffer| flash.blocking_read(STAGING_OFFSET + offset, buffer).is_ok())
+}
+
+/// The bank the running image booted from, which is mapped at the start of flash.
+pub fn running_bank() -> Bank {
+    if pac::SYSCFG.memrmp().read().fb_mode() { Bank::B } else { Bank::A }
+}
+
+/// Count this boot against an image on trial, rolling back to the previous image if it has had
+/// all its boots. Call this once the RTC is running, as the state is in its backup registers.
+pub fn on_boot() {
+    let mut state = read_boot_state();
+    let action = state.on_boot(&mut BusinessLog);
+    write_boot_state(&state);
+    if let BootAction::RollBack(bank) = action {
+        boot_bank(bank);
+    }
+}
+
+/// End the trial of a new image, once it has handled a reading.
+pub fn mark_healthy() {
+    let mut state = read_boot_state();
+    if state.mark_healthy(&mut BusinessLog) {
+        write_boot_state(&state);
+    }
+}
+
+// A confirmed running image if the register holds nothing valid, e.g. on a new device or after the backup domain lost power.
+fn read_boot_state() -> BootState {
+    BootState::from_word(pac::RTC.bkpr(RTC_BACKUP_BOOT_INDEX).read().bkp()).unwrap_or(BootState::new(running_bank()))
+}
+
+fn write_boot_state(state: &BootState) {
+    pac::RTC.bkpr(RTC_BACKUP_BOOT_INDEX).write(|w| w.set_bkp(state.to_word()));
+}
+
+/// Boot the image staged in the other bank, on trial, once it reads back intact. A bank
+/// without a valid image must never be selected, so a failed check returns the error instead.
+fn boot_staged_image(flash: &mut Flash<'static, Blocking>, stager: &ImageStager) -> Result<(), UpdateError> {
+    verify_staged(flash, stager)?;
+    let mut state = read_boot_state();
+    state.start_trial();
+    write_boot_state(&state);
+    info!("Booting the staged image in bank {} on trial", state.active);
+    boot_bank(state.active);
+}
+
+/// Boot from `bank` from now on, by programming the BFB2 option bit. Loading the new option bytes resets the chip.
+fn boot_bank(bank: Bank) -> ! {
+    let flash = pac::FLASH;
+    cortex_m::interrupt::disable();
+    while flash.sr().read().bsy() {}
+    for key in FLASH_KEYS {
+        flash.keyr().write_value(key);
+    }
+    for key in OPTION_KEYS {
+        flash.optkeyr().write_value(key);
+    }
+    flash.optr().modify(|w| w.set_bfb(bank == Bank::B));
+    flash.cr().modify(|w| w.set_optstrt(true));
+    while flash.sr().read().bsy() {}
+    flash.cr().modify(|w| w.set_obl_launch(true));
+    // Not reached: launching the option bytes resets the chip.
+    SCB::sys_reset();
+}
+
+/// Reset into the ROM bootloader, which takes an image over the console's UART with the ST tools.
+fn request_system_bootloader() -> ! {
+    info!("Resetting into the ROM bootloader");
+    // SAFETY: a single word only read back at the next boot.
+    unsafe { (&raw mut BOOTLOADER_REQUEST).write_volatile(MaybeUninit::new(BOOTLOADER_REQUEST_MAGIC)) };
+    SCB::sys_reset();
+}
+
+/// Enter the ROM bootloader if the last reset came from `request_system_bootloader`.
+/// Call this before the clocks or any peripherals are configured, as the bootloader expects reset state.
+pub fn enter_requested_bootloader() {
+    // SAFETY: nothing else runs yet; any bit pattern is a valid u32.
+    let request = unsafe { (&raw const BOOTLOADER_REQUEST).read_volatile().assume_init() };
+    if request != BOOTLOADER_REQUEST_MAGIC {
+        return;
+    }
+    // Cleared first, so the next reset boots the firmware again.
+    // SAFETY: as above.
+    unsafe { (&raw mut BOOTLOADER_REQUEST).write_volatile(MaybeUninit::new(0)) };
+    // SAFETY: system memory starts with a valid vector table, and nothing has been set up that it could trip over.
+    unsafe { cortex_m::asm::bootload(SYSTEM_MEMORY_ADDRESS as *const u32) }
+}
@@ -0,0 +1,361 @@
+//! The logger's own data in internal flash, next to the firmware images.
+//!
+//! Each bank ends with `RESERVED_PAGES` pages that no image covers. The data lives in those of
+//! physical bank 1 whichever bank runs, so an update doesn't move it. Their last page is left
+//! out: it is the self-test's scratch page while bank 1 runs.
+
+use core::cell::RefCell;
+use core::sync::atomic::Ordering;
+
+use business_logic::alarm::{AlarmState, ALARM_STATE_WORDS};
+use business_logic::battery::{FuelGauge, BATTERY_CAPACITY_MAH, FUEL_GAUGE_RECORD_LEN};
+use business_logic::burst::{BurstCapture, BURST_CAPTURES, BURST_CAPTURE_LEN};
+use business_logic::commissioning::{CommissioningRecord, COMMISSIONING_RECORD_LEN};
+use business_logic::config::{Config as Settings, CONFIG_VERSION};
+use business_logic::crash::{CrashRecord, CRASH_RECORD_WORDS};
+use business_logic::errors::{ErrorLog, ERROR_STATS_LEN};
+use business_logic::flash_scheduler::FlashScheduler;
+use business_logic::firmware::{Bank, BANK_SIZE_BYTES, FLASH_PAGE_BYTES, IMAGE_CAPACITY_BYTES, RESERVED_PAGES};
+use business_logic::indicator::IndicatorState;
+use business_logic::lifecycle::Lifecycle;
+use business_logic::notes::{NoteLog, NOTE_LOG_LEN};
+use business_logic::lifetime::LIFETIME_RECORD_LEN;
+use business_logic::nvstore::{NvError, NvKey, NvStore, NV_MAX_VALUE_LEN};
+use business_logic::storage::{FlashBackend, StorageError};
+use embassy_stm32::flash::{Blocking, Flash};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Instant, Timer};
+
+use crate::firmware_update::running_bank;
+use crate::fmt::warn;
+use crate::{FLASH_ERASES, WORST_ERASE_STALL_US};
+
+pub const DATA_PAGES: u32 = RESERVED_PAGES - 1;
+pub const NV_STORE_PAGES: u32 = 2; // The NV store's page pair, at the start of the data area.
+
+const _: () = assert!(NV_STORE_PAGES <= DATA_PAGES);
+const _: () = assert!(business_logic::config::CONFIG_RECORD_LEN <= NV_MAX_VALUE_LEN);
+const _: () = assert!(BURST_CAPTURE_LEN <= NV_MAX_VALUE_LEN);
+const _: () = assert!(4 * CRASH_RECORD_WORDS <= NV_MAX_VALUE_LEN);
+const _: () = assert!(ERROR_STATS_LEN <= NV_MAX_VALUE_LEN);
+const _: () = assert!(NOTE_LOG_LEN <= NV_MAX_VALUE_LEN);
+#[cfg(feature = "authentication")]
+const _: () = assert!(COMMISSIONING_RECORD_LEN + business_logic::authentication::TAG_LEN <= NV_MAX_VALUE_LEN);
+
+const LIFETIME_KEYS: [NvKey; 2] = [NvKey::LifetimeA, NvKey::LifetimeB];
+const BURST_KEYS: [NvKey; BURST_CAPTURES] = [NvKey::BurstA, NvKey::BurstB, NvKey::BurstC, NvKey::BurstD];
+
+struct Shared {
+    flash: Flash<'static, Blocking>,
+    nv_store: Option<NvStore>, // None if it couldn't be mounted.
+}
+
+// Flash operations stall the CPU anyway, so a blocking mutex costs nothing extra.
+static FLASH: Mutex<ThreadModeRawMutex, RefCell<Option<Shared>>> = Mutex::new(RefCell::new(None));
+// Holds the record pages' erases back to windows away from the samples and events.
+static SCHEDULER: Mutex<ThreadModeRawMutex, RefCell<FlashScheduler>> = Mutex::new(RefCell::new(FlashScheduler::new()));
+
+/// Take ownership of the flash and mount the NV store, formatting it on a new device.
+pub fn init(mut flash: Flash<'static, Blocking>) {
+    let nv_store = NvStore::mount(&mut flash, data_offset()).inspect_err(|error| warn!("NV store: {}", error)).ok();
+    FLASH.lock(|shared| shared.replace(Some(Shared { flash, nv_store })));
+}
+
+/// Whether the NV store was mounted, so settings and state saved now survive a restart.
+pub fn is_mounted() -> bool {
+    FLASH.lock(|shared| shared.borrow().as_ref().is_some_and(|shared| shared.nv_store.is_some()))
+}
+
+/// Offset from the start of flash of the data area. Bank 1 is mapped second while bank 2 runs.
+pub fn data_offset() -> u32 {
+    match running_bank() {
+        Bank::A => IMAGE_CAPACITY_BYTES,
+        Bank::B => BANK_SIZE_BYTES + IMAGE_CAPACITY_BYTES,
+    }
+}
+
+/// The temperature task takes its next sample at `at`, which erases keep clear of.
+pub fn next_sample(at: Instant) {
+    SCHEDULER.lock(|scheduler| scheduler.borrow_mut().next_sample(at.as_millis()));
+}
+
+/// A door or button event is being handled, so erases wait until it is done.
+pub fn event() {
+    SCHEDULER.lock(|scheduler| scheduler.borrow_mut().event(Instant::now().as_millis()));
+}
+
+/// Erases that couldn't wait for an idle window.
+pub fn forced_erases() -> u32 {
+    SCHEDULER.lock(|scheduler| scheduler.borrow().forced_erases())
+}
+
+// Wait for a window to erase `page` in. Only the storage task erases through the scheduler, so
+// the page `poll` hands back is this one.
+async fn wait_to_erase(page: u32) {
+    if !SCHEDULER.lock(|scheduler| scheduler.borrow_mut().request_erase(page, Instant::now().as_millis())) {
+        return;
+    }
+    loop {
+        let now_ms = Instant::now().as_millis();
+        let next_ms = SCHEDULER.lock(|scheduler| {
+            let mut scheduler = scheduler.borrow_mut();
+            scheduler.poll(now_ms).is_none().then(|| scheduler.next_poll_ms(now_ms)).flatten()
+        });
+        let Some(next_ms) = next_ms else {
+            return;
+        };
+        Timer::at(Instant::from_millis(next_ms)).await;
+    }
+}
+
+/// Run `f` with the flash. Must not be called before `init`.
+pub fn with_flash<R>(f: impl FnOnce(&mut Flash<'static, Blocking>) -> R) -> R {
+    FLASH.lock(|shared| f(&mut shared.borrow_mut().as_mut().expect("flash store initialized").flash))
+}
+
+/// The data area's pages after the NV store, for the storage task's records.
+pub struct RecordPages;
+
+impl RecordPages {
+    fn offset(page: u32, offset: usize) -> u32 {
+        data_offset() + (NV_STORE_PAGES + page) * FLASH_PAGE_BYTES + offset as u32
+    }
+}
+
+impl FlashBackend for RecordPages {
+    const WRITE_LEN: usize = 8;
+
+    fn pages(&self) -> u32 {
+        DATA_PAGES - NV_STORE_PAGES
+    }
+
+    async fn erase(&mut self, page: u32) -> Result<(), StorageError> {
+        wait_to_erase(page).await;
+        let start = Self::offset(page, 0);
+        FLASH_ERASES.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+        let erased = with_flash(|flash| flash.blocking_erase(start, start + FLASH_PAGE_BYTES));
+        WORST_ERASE_STALL_US.fetch_max(started.elapsed().as_micros() as u32, Ordering::Relaxed);
+        erased.map_err(|_| StorageError::Erase)
+    }
+
+    async fn program(&mut self, page: u32, offset: usize, bytes: &[u8]) -> Result<(), StorageError> {
+        with_flash(|flash| flash.blocking_write(Self::offset(page, offset), bytes)).map_err(|_| StorageError::Program)
+    }
+
+    async fn read(&mut self, page: u32, offset: usize, bytes: &mut [u8]) -> Result<(), StorageError> {
+        with_flash(|flash| flash.blocking_read(Self::offset(page, offset), bytes)).map_err(|_| StorageError::Read)
+    }
+}
+
+/// Read the saved value of `key` into `value`. Returns its length, or None if there is none.
+pub fn load(key: NvKey, value: &mut [u8]) -> Option<usize> {
+    FLASH.lock(|shared| {
+        let mut shared = shared.borrow_mut();
+        let Shared { flash, nv_store } = shared.as_mut()?;
+        nv_store.as_ref()?.read(flash, key, value).ok()?
+    })
+}
+
+/// Save `value` as the value of `key`, for the next boot.
+pub fn save(key: NvKey, value: &[u8]) -> Result<(), NvError> {
+    FLASH.lock(|shared| {
+        let mut shared = shared.borrow_mut();
+        let Some(Shared { flash, nv_store: Some(nv_store) }) = shared.as_mut() else {
+            return Err(NvError::Flash);
+        };
+        nv_store.write(flash, key, value)
+    })
+}
+
+/// The saved settings, and whether this firmware version saved them, or None on a new device
+/// or if they don't read back.
+pub fn load_settings() -> Option<(Settings, bool)> {
+    let mut bytes = [0u8; NV_MAX_VALUE_LEN];
+    let len = load(NvKey::Settings, &mut bytes)?;
+    let settings = Settings::from_bytes(&bytes[..len]).ok()?;
+    Some((settings, bytes[0] == CONFIG_VERSION))
+}
+
+/// Save `settings` for the next boot.
+pub fn save_settings(settings: &Settings) {
+    if let Err(error) = save(NvKey::Settings, &settings.to_bytes()) {
+        warn!("Saving the settings: {}", error);
+    }
+}
+
+/// Get the latched excursion indicator state, or None if it was never saved.
+pub fn load_indicator_state() -> Option<IndicatorState> {
+    let mut words = [0u32; 2];
+    IndicatorState::from_words(load_words(NvKey::Indicator, &mut words).then_some(words)?)
+}
+
+pub fn save_indicator_state(state: &IndicatorState) {
+    save_words(NvKey::Indicator, &state.to_words());
+}
+
+/// Get the lifecycle state, or None if it was never saved.
+pub fn load_lifecycle() -> Option<Lifecycle> {
+    let mut word = [0u32];
+    Lifecycle::from_word(load_words(NvKey::Lifecycle, &mut word).then_some(word[0])?)
+}
+
+pub fn save_lifecycle(lifecycle: &Lifecycle) {
+    save_words(NvKey::Lifecycle, &[lifecycle.to_word()]);
+}
+
+/// Get the accelerometer's installed orientation in mg, or None if it was never taken.
+pub fn load_tilt_reference() -> Option<[i16; 3]> {
+    let mut bytes = [0u8; 6];
+    (load(NvKey::TiltReference, &mut bytes) == Some(6)).then(|| [0, 2, 4].map(|i| i16::from_le_bytes([bytes[i], bytes[i + 1]])))
+}
+
+pub fn save_tilt_reference(reference: [i16; 3]) {
+    let mut bytes = [0u8; 6];
+    for (chunk, axis) in bytes.chunks_exact_mut(2).zip(reference) {
+        chunk.copy_from_slice(&axis.to_le_bytes());
+    }
+    if let Err(error) = save(NvKey::TiltReference, &bytes) {
+        warn!("Saving {}: {}", NvKey::TiltReference, error);
+    }
+}
+
+/// Get the alarm state saved at its last change, or None if it was never saved.
+pub fn load_alarm_state() -> Option<AlarmState> {
+    let mut words = [0u32; ALARM_STATE_WORDS];
+    AlarmState::from_words(&load_words(NvKey::AlarmState, &mut words).then_some(words)?)
+}
+
+pub fn save_alarm_state(state: &AlarmState) {
+    save_words(NvKey::AlarmState, &state.to_words());
+}
+
+/// Get the record of the logger's commissioning, or None if it was never commissioned.
+pub fn load_commissioning() -> Option<CommissioningRecord> {
+    let mut bytes = [0u8; NV_MAX_VALUE_LEN];
+    let len = load(NvKey::Commissioning, &mut bytes)?;
+    CommissioningRecord::from_bytes(&bytes[..len]).map(|(record, _mac)| record)
+}
+
+/// Save the record of a completed commissioning, followed by its MAC, empty without a device key.
+pub fn save_commissioning(record: &CommissioningRecord, mac: &[u8]) {
+    let mut bytes = [0u8; NV_MAX_VALUE_LEN];
+    let len = COMMISSIONING_RECORD_LEN + mac.len();
+    bytes[..COMMISSIONING_RECORD_LEN].copy_from_slice(&record.to_bytes());
+    bytes[COMMISSIONING_RECORD_LEN..len].copy_from_slice(mac);
+    if let Err(error) = save(NvKey::Commissioning, &bytes[..len]) {
+        warn!("Saving {}: {}", NvKey::Commissioning, error);
+    }
+}
+
+/// Get both copies of the lifetime counters, for `LifetimeStore::restore`. A copy that was never
+/// saved reads as zeros, which fails its CRC.
+pub fn load_lifetime_slots() -> [[u8; LIFETIME_RECORD_LEN]; 2] {
+    let mut slots = [[0u8; LIFETIME_RECORD_LEN]; 2];
+    for (bytes, key) in slots.iter_mut().zip(LIFETIME_KEYS) {
+        if load(key, bytes) != Some(LIFETIME_RECORD_LEN) {
+            *bytes = [0; LIFETIME_RECORD_LEN];
+        }
+    }
+    slots
+}
+
+pub fn save_lifetime_slot(slot: usize, bytes: &[u8; LIFETIME_RECORD_LEN]) {
+    if let Err(error) = save(LIFETIME_KEYS[slot], bytes) {
+        warn!("Saving the lifetime counters: {}", error);
+    }
+}
+
+/// Get the bursts saved in each slot, for `BurstRecorder::restore`.
+pub fn load_burst_slots() -> [Option<BurstCapture>; BURST_CAPTURES] {
+    BURST_KEYS.map(|key| {
+        let mut bytes = [0u8; BURST_CAPTURE_LEN];
+        (load(key, &mut bytes) == Some(BURST_CAPTURE_LEN)).then(|| BurstCapture::from_bytes(&bytes)).flatten()
+    })
+}
+
+pub fn save_burst(slot: usize, capture: &BurstCapture) {
+    if let Err(error) = save(BURST_KEYS[slot], &capture.to_bytes()) {
+        warn!("Saving a burst: {}", error);
+    }
+}
+
+/// Get the battery's fuel gauge, or None if it was never saved.
+pub fn load_fuel_gauge() -> Option<FuelGauge> {
+    let mut bytes = [0u8; FUEL_GAUGE_RECORD_LEN];
+    (load(NvKey::FuelGauge, &mut bytes) == Some(FUEL_GAUGE_RECORD_LEN)).then(|| FuelGauge::from_bytes(BATTERY_CAPACITY_MAH, &bytes))
+}
+
+pub fn save_fuel_gauge(gauge: &FuelGauge) {
+    if let Err(error) = save(NvKey::FuelGauge, &gauge.to_bytes()) {
+        warn!("Saving {}: {}", NvKey::FuelGauge, error);
+    }
+}
+
+/// Get the record of the last crash, or None if there was none since the store was formatted.
+pub fn load_crash_record() -> Option<CrashRecord> {
+    let mut words = [0u32; CRASH_RECORD_WORDS];
+    CrashRecord::from_words(&load_words(NvKey::Crash, &mut words).then_some(words)?)
+}
+
+pub fn save_crash_record(record: &CrashRecord) {
+    save_words(NvKey::Crash, &record.to_words());
+}
+
+/// Get the error counts, or None if they were never saved.
+pub fn load_error_log() -> Option<ErrorLog> {
+    let mut bytes = [0u8; ERROR_STATS_LEN];
+    (load(NvKey::ErrorCounts, &mut bytes) == Some(ERROR_STATS_LEN)).then(|| ErrorLog::from_bytes(&bytes))
+}
+
+pub fn save_error_log(errors: &ErrorLog) {
+    if let Err(error) = save(NvKey::ErrorCounts, &errors.to_bytes()) {
+        warn!("Saving {}: {}", NvKey::ErrorCounts, error);
+    }
+}
+
+pub fn load_notes() -> Option<NoteLog> {
+    let mut bytes = [0u8; NOTE_LOG_LEN];
+    (load(NvKey::Notes, &mut bytes) == Some(NOTE_LOG_LEN)).then(|| NoteLog::from_bytes(&bytes)).flatten()
+}
+
+pub fn save_notes(notes: &NoteLog) {
+    if let Err(error) = save(NvKey::Notes, &notes.to_bytes()) {
+        warn!("Saving {}: {}", NvKey::Notes, error);
+    }
+}
+
+/// Get the nonce the records in flash were encrypted with, 0 if none was saved.
+#[cfg(feature = "encryption")]
+pub fn load_record_nonce() -> u32 {
+    let mut word = [0u32];
+    if load_words(NvKey::RecordNonce, &mut word) { word[0] } else { 0 }
+}
+
+#[cfg(feature = "encryption")]
+pub fn save_record_nonce(nonce: u32) {
+    save_words(NvKey::RecordNonce, &[nonce]);
+}
+
+// Read a value saved by `save_words` into `words`. Returns whether it had as many words.
+fn load_words(key: NvKey, words: &mut [u32]) -> bool {
+    let mut bytes = [0u8; NV_MAX_VALUE_LEN];
+    if load(key, &mut bytes) != Some(4 * words.len()) {
+        return false;
+    }
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    true
+}
+
+fn save_words(key: NvKey, words: &[u32]) {
+    let mut bytes = [0u8; NV_MAX_VALUE_LEN];
+    for (chunk, word) in bytes.chunks_exact_mut(4).zip(words) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    if let Err(error) = save(key, &bytes[..4 * words.len()]) {
+        warn!("Saving {}: {}", key, error);
+    }
+}
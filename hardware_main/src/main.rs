@@ -1,82 +1,273 @@
 #![no_std]
 #![no_main]
 
+mod board;
+mod crash;
+mod event_channel;
+mod firmware_update;
+mod flash_store;
 mod fmt;
+mod power_fail;
+mod power_gate;
 mod rtclock;
+mod shared_i2c;
+mod ssd1306;
+mod warm_start;
+mod watchdog;
 
+use core::convert::Infallible;
 use core::f32::consts;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
 use arrayvec::ArrayString;
-#[cfg(not(feature = "defmt"))]
-use panic_halt as _;
 use crate::fmt::unwrap;
+#[cfg(feature = "accelerometer")]
+use business_logic::accelerometer::{Accelerometer, MotionDetector, LIS3DH_ADDRESS};
+use business_logic::alarm::{AlarmKind, AlarmProfile};
+use business_logic::battery::{FuelGauge, BATTERY_CAPACITY_MAH};
+use business_logic::burst::BURST_PERIOD_SECONDS;
+use business_logic::button::run_button;
+use business_logic::capabilities::{Capabilities, Capability};
+use business_logic::compliance::{ComplianceInfo, COMPLIANCE_BLOCK_LEN};
+use business_logic::commissioning::CommissioningRecord;
+use business_logic::compressor::CompressorEvent;
+use business_logic::config::Config as Settings;
+use business_logic::console::{write_capabilities, Command, ExportCommand, LineReader, LoggerCommand};
+use business_logic::dictionary::{write_dictionary, DICTIONARY_LEN};
+use business_logic::device_task::{DayReport, Device, DeviceEvent, DeviceEvents, DeviceTask, DoorEvent, Saved};
+use business_logic::dispatch::{Dispatcher, Lane};
+use business_logic::display::DisplayModel;
+use business_logic::door::{DoorSwitchConfig, SwitchPull};
+#[cfg(feature = "encryption")]
+use business_logic::encryption::{record_key, RecordCipher};
+use business_logic::errors::ErrorCode;
+use business_logic::export::{write_export, ChunkReader, ExportSink, ReportChunks};
+use business_logic::event_queue::RESERVED_SLOTS;
+use business_logic::firmware::{BANK_SIZE_BYTES, FLASH_PAGE_BYTES};
+use business_logic::hal;
+use business_logic::health::DeviceHealth;
+use business_logic::led::DeviceStatus;
+use business_logic::lifecycle::{Lifecycle, LifecycleState};
+use business_logic::localtime::LocalTime;
+#[cfg(feature = "defmt")]
+use business_logic::log::DefmtLog as BusinessLog;
+#[cfg(not(feature = "defmt"))]
+use business_logic::log::NullLog as BusinessLog;
+use business_logic::logger::{Logger, LoggerEvent};
+use business_logic::logger_task::{AlarmOutput, BulkJob, Compaction, EventNumbers, EventSource, LoggerTask, LoggerWork, Numbered};
+use business_logic::mains::{MainsPresence, MAINS_SAMPLE_SECONDS};
+use business_logic::power::{ClockProfile, Rail};
+#[cfg(feature = "authentication")]
+use business_logic::provisioning::DeviceKey;
+use business_logic::provisioning::{ProvisioningBlock, PROVISIONING_BLOCK_LEN};
+use business_logic::report::Report;
+use business_logic::report_scheduler::ReportScheduler;
+use business_logic::sampling::AdaptiveSampling;
+use business_logic::selfheating::SelfHeating;
+use business_logic::selftest::{SelfTestItem, SelfTestReport};
+use business_logic::sensor::DualTempSensor;
+use business_logic::shutdown::PowerFailCheckpoint;
+use business_logic::storage::{RecordSource, StorageReport, StorageReports, StorageTask};
+use business_logic::store::{ChainedRecord, RamStore, RecordChain, RecordStore, COMPACTION_AGE_DAYS};
 use business_logic::timestamp::Timestamp;
+use business_logic::usb::UsbEvent;
+use business_logic::watch::Watch;
+use business_logic::watchdog::{RestartCause, TaskId};
+use business_logic::xmodem::{ByteSource, XmodemSender, TIMEOUT_SECONDS};
 
 #[cfg(feature = "defmt")]
-use {defmt_rtt as _, panic_probe as _};
+use defmt_rtt as _;
 
 use embassy_executor::Spawner;
-use embassy_stm32::{bind_interrupts, exti::ExtiInput, peripherals};
-use embassy_stm32::{gpio::{Level, Output, Pull, Speed}, i2c::{ErrorInterruptHandler, EventInterruptHandler, I2c}, rtc::{Rtc, RtcConfig}, time::Hertz, Config};
+use embassy_stm32::{adc::Adc, exti::ExtiInput, flash::Flash, usart::BufferedUart, wdg::IndependentWatchdog};
+use embassy_stm32::{gpio::{Level, Output, OutputOpenDrain, Pull}, rtc::{Rtc, RtcConfig}, time::Hertz, Config};
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
-use embassy_sync::channel::{Channel, Sender};
-use embassy_time::{Duration, Ticker, Timer};
+use embassy_sync::channel::Channel;
+use embassy_sync::pipe::Pipe;
+use embassy_sync::signal::Signal;
+use embassy_futures::join::join;
+use embassy_futures::select::{select, select3, select4, Either, Either3, Either4};
+use embassy_futures::yield_now;
+use embassy_time::{with_timeout, Delay, Duration, Instant, Ticker, Timer, TimeoutError};
+use embedded_io_async::{Read as _, Write as _};
+use board::{Board, MainsAdc, MainsPin, AMBIENT_ADDRESS, VACCINE_ADDRESS};
+use crash::take_crash_record;
+use event_channel::EventChannel;
+use flash_store::RecordPages;
 use fmt::{info, warn};
+use power_fail::POWER_FAILING;
+use power_gate::POWER_GATE;
 use rtclock::{Rtclock};
+use shared_i2c::{I2cHandle, DISPLAY_BUS, SENSOR_BUS, VACCINE_BUS};
+use ssd1306::{Ssd1306, SSD1306_ADDRESS};
+use watchdog::{count_restart, heartbeat, take_restart_event, watchdog_supervisor, WATCHDOG_TIMEOUT_US};
 
-const AMBIENT_ADDRESS: u8 = 0x45; // I2C address for ambient temperature sensor.
-const VACCINE_ADDRESS: u8 = 0x44; // I2C address for vaccine temperature sensor.
-const SENSOR_REGISTER: u8 = 0x00; // Register to read temperature data.
-const SENSOR_CONVERSION_TIME: Duration = Duration::from_millis(51); // Time to wait for sensor conversion.
+// Scratch page: the last reserved page of the bank running, which is mapped first. It is in
+// neither image, so the self-test can't damage the staged or previous one.
+const SELFTEST_FLASH_OFFSET: u32 = BANK_SIZE_BYTES - FLASH_PAGE_BYTES;
+const OTP_ADDRESS: usize = 0x1FFF_7000; // One-time-programmable area holding the provisioning block.
+const COMPLIANCE_OTP_OFFSET: usize = 48; // The compliance block follows the provisioning block, double-word aligned.
+const COMPRESSOR_DEBOUNCE_TIME: Duration = Duration::from_secs(2); // Current transformer output must be stable this long.
+const USB_DEBOUNCE_TIME: Duration = Duration::from_millis(100); // VBUS bounces as the plug goes in.
+const EVENT_QUEUE_LEN: usize = 8 + RESERVED_SLOTS; // Eight of any event, and the reserved slots for those that mustn't be lost.
+const CONSOLE_OUT_LEN: usize = 512; // Console output waiting for the UART, a few lines of `watch` and a reply.
+const CONSOLE_LINE_LEN: usize = 256; // Longest line the console task and the device task send, e.g. the capabilities.
+const RECORD_STORE_LEN: usize = 96; // Recent records kept in RAM, a day of standard ones. The storage task keeps them all in flash.
+#[cfg(feature = "accelerometer")]
+const TILT_CHECK_PERIOD: Duration = Duration::from_secs(10); // Shocks wake the motion task at once.
 
 // Communicate events between tasks using a channel.
-static CHANNEL: Channel<ThreadModeRawMutex, Events, 8> = Channel::new();
+static CHANNEL: EventChannel<DeviceEvent, EVENT_QUEUE_LEN> = EventChannel::new();
+// Events for the records, from the device task to the logger task.
+static LOGGER_EVENTS: EventChannel<Numbered, EVENT_QUEUE_LEN> = EventChannel::new();
+/// Numbers the events for the logger task as they are queued, ticks included.
+static EVENT_NUMBERS: EventNumbers = EventNumbers::new();
+// Completed records for the storage task, and its reports back, between it and the logger task.
+static STORAGE_RECORDS: Channel<ThreadModeRawMutex, ChainedRecord, EVENT_QUEUE_LEN> = Channel::new();
+static STORAGE_REPORTS: Channel<ThreadModeRawMutex, StorageReport, EVENT_QUEUE_LEN> = Channel::new();
+// Console output from every task, sent by the console task. Only the logger task waits for room
+// in it; the others drop a line that doesn't fit rather than block, see `ConsoleOut`.
+static CONSOLE_OUT: Pipe<ThreadModeRawMutex, CONSOLE_OUT_LEN> = Pipe::new();
+// Console commands for the logger task.
+static LOGGER_COMMANDS: Channel<ThreadModeRawMutex, LoggerCommand, 2> = Channel::new();
+static EXPORTS: Channel<ThreadModeRawMutex, ExportCommand, 1> = Channel::new();
+// While an XMODEM export is under way, the console's bytes are the receiver's answers, for the logger task.
+static XMODEM_ACTIVE: AtomicBool = AtomicBool::new(false);
+static XMODEM_RX: Channel<ThreadModeRawMutex, u8, 8> = Channel::new();
+// The daily report time after the console changes it, for the logger task.
+static REPORT_TIME: Signal<ThreadModeRawMutex, Option<u16>> = Signal::new();
+// The lifecycle state after a change, from the device task to the logger task.
+static LIFECYCLE: Signal<ThreadModeRawMutex, LifecycleState> = Signal::new();
+// Whether the high temperature and freeze alarms are active, from the logger task.
+// A signal rather than `CHANNEL`, so the two tasks can't block on each other's full queues.
+static TEMPERATURE_ALARMS: Signal<ThreadModeRawMutex, (bool, bool)> = Signal::new();
+// The alarm the buzzer should currently be sounding, if any, and whether it has escalated.
+static BUZZER: Signal<ThreadModeRawMutex, Option<(AlarmKind, bool)>> = Signal::new();
+// The latest content for the status display.
+static DISPLAY: Signal<ThreadModeRawMutex, DisplayModel> = Signal::new();
+// The status shown on the status LED.
+static STATUS_LED: Signal<ThreadModeRawMutex, DeviceStatus> = Signal::new();
+// Whether the door is open, for the sampling period.
+static DOOR_OPEN: AtomicBool = AtomicBool::new(false);
+// Whether a burst of samples is being captured, and a new one starting, so the sampling task
+// speeds up at once rather than after its current period.
+static BURST_ACTIVE: AtomicBool = AtomicBool::new(false);
+static BURST_STARTED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+// Error and wear counters updated by the driver tasks, for `DeviceHealth`.
+static I2C_ERRORS: AtomicU32 = AtomicU32::new(0);
+static FLASH_ERASES: AtomicU32 = AtomicU32::new(0);
+static WORST_ACQUISITION_US: AtomicU32 = AtomicU32::new(0);
+static WORST_ERASE_STALL_US: AtomicU32 = AtomicU32::new(0);
+// Wall-clock seconds at monotonic time zero, from the device task, so the temperature and logger
+// tasks can keep to wall-clock times without owning the RTC.
+static CLOCK_ANCHOR: AtomicU32 = AtomicU32::new(0);
+
+/// Exercise the peripherals and report which ones work.
+/// The LED and buzzer have no feedback, so they pass once driven; the operator checks them by eye and ear.
+#[allow(clippy::too_many_arguments)]
+async fn run_selftest(
+    temp_sensor: &mut DualTempSensor<I2cHandle>,
+    rt_clock: &impl hal::Rtc,
+    flash: &mut Flash<'static, embassy_stm32::flash::Blocking>,
+    btn: &ExtiInput<'static>,
+    led: &mut Output<'static>,
+    buzzer: Option<&mut Output<'static>>,
+    adc: &mut Adc<'static, MainsAdc>,
+    adc_pin: &mut MainsPin,
+) -> SelfTestReport {
+    let mut report = SelfTestReport::new();
+    let sensors_ok = {
+        let _power = POWER_GATE.acquire(Rail::Sensors).await;
+        temp_sensor.read_temperature_celsius().await.is_ok()
+    };
+    report.record(SelfTestItem::Sensors, sensors_ok);
+
+    let start = rt_clock.now();
+    Timer::after_millis(1100).await;
+    report.record(SelfTestItem::Rtc, rt_clock.now().seconds > start.seconds);
+
+    let pattern = [0xA5, 0x5A, 0x00, 0xFF, 0x12, 0x34, 0x56, 0x78];
+    let mut readback = [0u8; 8];
+    let erase = |flash: &mut Flash<'static, embassy_stm32::flash::Blocking>| {
+        FLASH_ERASES.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+        let ok = flash.blocking_erase(SELFTEST_FLASH_OFFSET, SELFTEST_FLASH_OFFSET + FLASH_PAGE_BYTES).is_ok();
+        WORST_ERASE_STALL_US.fetch_max(started.elapsed().as_micros() as u32, Ordering::Relaxed);
+        ok
+    };
+    let flash_ok = erase(flash)
+        && flash.blocking_write(SELFTEST_FLASH_OFFSET, &pattern).is_ok()
+        && flash.blocking_read(SELFTEST_FLASH_OFFSET, &mut readback).is_ok()
+        && readback == pattern
+        && erase(flash);
+    report.record(SelfTestItem::Flash, flash_ok);
+
+    // Fails if the button input is stuck low.
+    report.record(SelfTestItem::Button, btn.is_high());
 
-enum ButtonEvent {
-    Pressed,
-    Released,
+    led.set_high();
+    if let Some(buzzer) = buzzer {
+        buzzer.set_high();
+        Timer::after_millis(200).await;
+        buzzer.set_low();
+    }
+    report.record(SelfTestItem::LedBuzzer, true);
+
+    // A reading at either rail means the divider or the ADC input is open or shorted.
+    let raw = adc.blocking_read(adc_pin);
+    report.record(SelfTestItem::Adc, raw > 0 && raw < 4095);
+    report
 }
 
-enum Events {
-    Button(ButtonEvent),
-    TempReading((f32, f32)), // (ambient temperature, vaccine temperature)
+/// Read `N` bytes of OTP from `offset`.
+fn read_otp<const N: usize>(offset: usize) -> [u8; N] {
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        // SAFETY: the OTP area is memory-mapped flash, always readable.
+        *byte = unsafe { core::ptr::read_volatile((OTP_ADDRESS + offset + i) as *const u8) };
+    }
+    bytes
 }
 
-struct DualTempSensor<I2C> {
-    i2c: I2C,
-    amb_address: u8,
-    vax_address: u8,
-    enable_bar: Output<'static>,
+/// Read the provisioning block written to OTP at the factory.
+fn read_provisioning_block() -> Option<ProvisioningBlock> {
+    ProvisioningBlock::from_bytes(&read_otp::<PROVISIONING_BLOCK_LEN>(0))
 }
 
-impl<I2C> DualTempSensor<I2C> {
-    pub fn new(i2c: I2C, amb_address: u8, vax_address: u8, enable_bar: Output<'static>) -> Self {
-        Self { i2c, amb_address, vax_address, enable_bar }
-    }
+/// Carry on the records in flash, see `StorageTask::resume`.
+#[cfg(not(feature = "encryption"))]
+async fn resume_records() -> (StorageTask<RecordPages, { FLASH_PAGE_BYTES as usize }>, Option<ChainedRecord>) {
+    StorageTask::resume(RecordPages).await
 }
 
-impl<I2C> DualTempSensor<I2C>
-where
-    I2C: embedded_hal_async::i2c::I2c,
-{
-    pub async fn read_temperature_celsius(&mut self) -> Result<(f32, f32), &str> {
-        self.enable_bar.set_low(); // Enable the temperature sensor.
-        Timer::after(SENSOR_CONVERSION_TIME).await; // Wait for sensor to stabilize.
-        let mut buf = [0u8; 2];
-        self.i2c.write_read(self.amb_address, &[SENSOR_REGISTER], &mut buf).await.or(Err("Failed to read from temperature sensor"))?;
-        let amb_temp = i16::from_be_bytes(buf);
-        self.i2c.write_read(self.vax_address, &[SENSOR_REGISTER], &mut buf).await.or(Err("Failed to read from temperature sensor"))?;
-        let vax_temp = i16::from_be_bytes(buf);
-        self.enable_bar.set_high(); // Disable the temperature sensor.
-        Ok((f32::from(amb_temp) * 0.0078125, f32::from(vax_temp) * 0.0078125)) // Convert to Celsius
+/// Carry on the records in flash, encrypted with the device's record key if it was provisioned.
+/// Sequence numbers start again when there are no records, so the nonce moves on then.
+#[cfg(feature = "encryption")]
+async fn resume_records() -> (StorageTask<RecordPages, { FLASH_PAGE_BYTES as usize }>, Option<ChainedRecord>) {
+    let Some(provisioning) = read_provisioning_block() else {
+        warn!("Not provisioned: records are stored unencrypted");
+        return StorageTask::resume(RecordPages).await;
+    };
+    let key = record_key(&provisioning.key);
+    let nonce = flash_store::load_record_nonce();
+    let (mut storage, newest) = StorageTask::resume_encrypted(RecordPages, RecordCipher::new(&key, nonce)).await;
+    if newest.is_none() {
+        let nonce = nonce.wrapping_add(1);
+        flash_store::save_record_nonce(nonce);
+        storage.set_cipher(RecordCipher::new(&key, nonce));
     }
+    (storage, newest)
 }
 
-
+/// Read the EN 12830 classification and placement written to OTP during provisioning.
+fn read_compliance_info() -> Option<ComplianceInfo> {
+    ComplianceInfo::from_bytes(&read_otp::<COMPLIANCE_BLOCK_LEN>(COMPLIANCE_OTP_OFFSET))
+}
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
+    firmware_update::enter_requested_bootloader();
 
     // Chip peripheral configuration
     let mut config = Config::default();
@@ -118,16 +309,52 @@ async fn main(spawner: Spawner) {
     }
     let p = embassy_stm32::init(config);
 
-    // GPIOs
-    let mut pwrv_nen = Output::new(p.PA15, Level::High, Speed::Low); // Power enable for the temperature sensor.
-    pwrv_nen.set_low(); // Enable the temperature sensor.
-    let mut led = Output::new(p.PB0, Level::High, Speed::Low);
-    let mut btn = ExtiInput::new(p.PB5, p.EXTI5, Pull::Up);
+    let restart = take_restart_event();
+    if restart.cause == RestartCause::Watchdog {
+        warn!("Restart: {}", restart);
+    } else {
+        info!("Restart: {}", restart);
+    }
+    let crash = take_crash_record();
+    if let Some(crash) = &crash {
+        warn!("{} before restart at PC {=u32:#x}, LR {=u32:#x}: {}", crash.kind, crash.pc, crash.lr, crash.message.as_str());
+    }
+    // TODO: append a MAC of exported reports with the device key once the firmware exports them.
+    match read_provisioning_block() {
+        Some(provisioning) => info!("Serial number {}", provisioning.serial_number),
+        None => warn!("Not provisioned: exports cannot be authenticated"),
+    }
+    // TODO: put in the report headers once the firmware exports reports.
+    match read_compliance_info() {
+        Some(compliance) => info!("{=str}", compliance.header().as_str()),
+        None => info!("No EN 12830 classification"),
+    }
+
+    // GPIOs, ADC and I2C buses, as wired on this hardware revision.
+    let board = Board::new(p);
+    info!(
+        "Board {=str}: display {}, buzzer {}, external flash {}",
+        board::NAME,
+        board.display_i2c.is_some(),
+        board.buzzer.is_some(),
+        board::HAS_EXTERNAL_FLASH
+    );
+    POWER_GATE.init(board.rails);
+    let mut led = board.led;
+    let mut buzzer = board.buzzer;
+    let relay_output = board.relay;
+    let btn = board.button;
+    let compressor_input = board.compressor;
+    let vbus = board.vbus;
+    let mains_present = board.mains_present;
+    let mut adc = board.adc;
+    let mut mains_pin = board.mains_pin;
 
     // RTC initialization
-    let mut rtc = Rtc::new(p.RTC, RtcConfig::default());
+    let mut rtc = Rtc::new(board.rtc, RtcConfig::default());
     rtc.set_daylight_savings(false);
-    let rt_clock = if Rtclock::is_running(&rtc) {
+    let rtc_running = Rtclock::is_running(&rtc);
+    let rt_clock = if rtc_running {
         info!("RTC is running, using existing RTCW value...");
         Rtclock::from_running(rtc)
     } else {
@@ -136,95 +363,1047 @@ async fn main(spawner: Spawner) {
         let rtcw = 0_u32; // TODO: Get the RTCW value from non-volatile storage or set to 0.
         Rtclock::from_rtcw(rtc, rtcw)
     };
+    // A new image on trial that keeps resetting before it handles a reading is rolled back.
+    firmware_update::on_boot();
 
 
     // I2C and temp sensor initialization.
-    bind_interrupts!(struct Irqs {
-        I2C1_EV => EventInterruptHandler<peripherals::I2C1>;
-        I2C1_ER => ErrorInterruptHandler<peripherals::I2C1>;
-    });
-
-    let mut i2c = I2c::new(
-        p.I2C1, 
-        p.PB6, 
-        p.PB7, 
-        Irqs,
-        p.DMA1_CH6,
-        p.DMA1_CH7, 
-        Hertz(400_000),
-        Default::default(),
-    );
-    let mut temp_sensor = DualTempSensor::new(i2c, AMBIENT_ADDRESS, VACCINE_ADDRESS, pwrv_nen);
+    SENSOR_BUS.init(board.sensor_i2c).await;
+    // With a bus of its own, a lockup of the sensor bus can't stop the vaccine readings.
+    let vaccine_i2c = match board.vaccine_i2c {
+        Some(vaccine_i2c) => {
+            VACCINE_BUS.init(vaccine_i2c).await;
+            VACCINE_BUS.handle()
+        }
+        None => SENSOR_BUS.handle(),
+    };
+    let mut temp_sensor = DualTempSensor::new(SENSOR_BUS.handle(), AMBIENT_ADDRESS, vaccine_i2c, VACCINE_ADDRESS);
+    let display = match board.display_i2c {
+        Some(display_i2c) => {
+            DISPLAY_BUS.init(display_i2c).await;
+            let mut display = Ssd1306::new(DISPLAY_BUS.handle(), SSD1306_ADDRESS);
+            // Boards wired for a display may be built without one.
+            match display.init().await {
+                Ok(()) => Some(display),
+                Err(_) => {
+                    warn!("No display found");
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    #[cfg(feature = "accelerometer")]
+    let accelerometer = {
+        let mut accelerometer = Accelerometer::new(SENSOR_BUS.handle(), LIS3DH_ADDRESS);
+        match accelerometer.init().await {
+            Ok(()) => Some(accelerometer),
+            Err(_) => {
+                warn!("No accelerometer found");
+                None
+            }
+        }
+    };
+
+    // TODO: also run on demand from the console, and store the report as an event.
+    let mut flash = Flash::new_blocking(board.flash);
+    let selftest = run_selftest(&mut temp_sensor, &rt_clock, &mut flash, &btn, &mut led, buzzer.as_mut(), &mut adc, &mut mains_pin).await;
+    info!("{}", selftest.summary().as_str());
+    for item in SelfTestItem::ALL {
+        if selftest.result(item) == Some(false) {
+            warn!("Self-test failed: {}", item);
+        }
+    }
+
+    flash_store::init(flash);
+    // Kept until the next crash replaces it, since the RAM holding it is lost with the power.
+    if let Some(crash) = &crash {
+        flash_store::save_crash_record(crash);
+    }
+    let saved = flash_store::load_settings();
+    let mut settings = saved.map(|(settings, _)| settings).unwrap_or_default();
+    // Saved straight back on a new device, or after an update to a newer settings version.
+    if !saved.is_some_and(|(_, current)| current) {
+        flash_store::save_settings(&settings);
+    }
+    // A clock started afresh no longer counts from the anchored time.
+    if !rtc_running && settings.epoch_anchor.take().is_some() {
+        warn!("RTC restarted, epoch anchor cleared");
+        flash_store::save_settings(&settings);
+    }
+    // Jumpers from the profile straps to ground select a fixed alarm profile; with none fitted the configured one applies.
+    // The inputs are released afterwards so a fitted jumper doesn't draw current through the pull-up.
+    let [strap0, strap1] = board.straps;
+    if let Some(profile) = AlarmProfile::from_strap(strap0.is_low(), strap1.is_low()) {
+        settings.set_alarm_profile(profile);
+    }
+    drop((strap0, strap1));
+    let alarm_profile = settings.alarm_profile();
+    info!("Alarm profile {}", alarm_profile);
+    // The hardware found, from one acquisition of the sensors and the peripherals initialized above.
+    let acquisition = {
+        let _power = POWER_GATE.acquire(Rail::Sensors).await;
+        temp_sensor.acquire(&mut Delay).await
+    };
+    let capabilities = Capabilities::compiled()
+        .with(Capability::VaccineSensor, acquisition.vaccine.is_ok())
+        .with(Capability::AmbientSensor, acquisition.ambient.is_ok())
+        .with(Capability::DoorSwitch, true)
+        .with(Capability::MainsSense, true)
+        .with(Capability::Display, display.is_some())
+        .with(Capability::RamStore, true)
+        .with(Capability::Uart, true)
+        .with(Capability::FlashStore, selftest.result(SelfTestItem::Flash) != Some(false) && flash_store::is_mounted());
+    #[cfg(feature = "humidity")]
+    let capabilities = capabilities.with(Capability::HumiditySensor, acquisition.humidity.is_ok());
+    #[cfg(feature = "accelerometer")]
+    let capabilities = capabilities.with(Capability::Accelerometer, accelerometer.is_some());
+    info!("Capabilities {:08X}", capabilities.as_u32());
+    // TODO: serve the binary UART protocol in `bus::Frame`s once a board has an RS-485 transceiver,
+    // answering as `BusNode::new(settings.bus_address)` after `bus::turnaround_us(settings.baud_rate)`.
+    // `GetCapabilities` requests are answered with `capabilities.answer`.
+    // Binary downloads start with a `DownloadHeader` carrying `settings.epoch_anchor`.
+    let console = board.console;
+    match console.set_baudrate(settings.baud_rate) {
+        Ok(()) => info!("Bus address {}, {} baud", settings.bus_address, settings.baud_rate),
+        Err(_) => warn!("Serial {} baud not possible", settings.baud_rate),
+    }
+    spawner.spawn(console_task(console, capabilities)).unwrap();
+
+    // Alarms under way before the reset or power cut, unless the clock restarted and their times
+    // mean nothing. The tasks driving them start from these, so one that ended meanwhile clears.
+    let restored_alarms = flash_store::load_alarm_state().filter(|_| rtc_running);
+    let restored = |kind| restored_alarms.is_some_and(|state| state.annunciator.is_active(kind));
 
     // Spawn the button task
-    spawner.spawn(button(btn, CHANNEL.sender())).unwrap();
-    spawner.spawn(led_blink(led)).unwrap();
-    spawner.spawn(get_temperature(temp_sensor, CHANNEL.sender())).unwrap();
+    spawner.spawn(button(btn, &CHANNEL)).unwrap();
+    // The door switch wiring differs between fridge models, see `DoorSwitchConfig`.
+    let door_pull = match settings.door_switch.pull {
+        SwitchPull::Up => Pull::Up,
+        SwitchPull::Down => Pull::Down,
+    };
+    let door = board.door.input(door_pull);
+    DOOR_OPEN.store(restored(AlarmKind::Door), Ordering::Relaxed);
+    spawner.spawn(door_switch(door, settings.door_switch, restored(AlarmKind::Door), &CHANNEL)).unwrap();
+    spawner.spawn(status_led(led)).unwrap();
+    if let Some(buzzer) = buzzer {
+        spawner.spawn(buzzer_task(buzzer)).unwrap();
+    }
+    if let Some(display) = display {
+        spawner.spawn(display_task(display)).unwrap();
+    }
+    anchor_clock(rt_clock.get_timestamp());
+    spawner.spawn(get_temperature(temp_sensor, settings.sampling(), SelfHeating::new(settings.self_heating()), &CHANNEL)).unwrap();
+    #[cfg(feature = "accelerometer")]
+    if let Some(accelerometer) = accelerometer {
+        spawner.spawn(motion_sense(accelerometer, board.accelerometer_int, &CHANNEL)).unwrap();
+    }
+    let lifecycle = flash_store::load_lifecycle().unwrap_or(Lifecycle::new(LifecycleState::Logging));
+    let mut logger = Logger::new(settings.sample_policy(), alarm_profile);
+    // Carry on after a brief reset, so e.g. an excursion keeps the time towards its alarm.
+    if let Some(state) = warm_start::take()
+        && logger.warm_start(&state, rt_clock.get_timestamp())
+    {
+        info!("Warm start from {}, excursion {} s", state.at.seconds, state.high_run_seconds.max(state.low_run_seconds));
+    }
+    // Carry on the records already in flash, unless there's no flash to keep them in.
+    let storage = if capabilities.has(Capability::FlashStore) {
+        Some(resume_records().await)
+    } else {
+        None
+    };
+    let newest = storage.as_ref().and_then(|(_, newest)| *newest);
+    if let Some(newest) = newest {
+        info!("Records in flash through {}", newest.sequence);
+    }
+    // Store the record that was in progress when the supply last failed.
+    let mut store = RamStore::new().with_chain(newest.map_or(RecordChain::new(), |newest| RecordChain::after(&newest)));
+    if let Some(checkpoint) = rt_clock.read_power_fail_checkpoint() {
+        info!("Restored the record from {} saved at power fail", checkpoint.start.seconds);
+        store.append(checkpoint.to_record());
+        rt_clock.clear_power_fail_checkpoint();
+    }
+    power_fail::init();
+    let (high, freeze) = (restored(AlarmKind::HighTemp), restored(AlarmKind::Freeze));
+    let mut task = LoggerTask::new(logger, alarm_profile, lifecycle.state(), store).with_alarms(high, freeze);
+    if let Some(newest) = newest {
+        task = task.with_saved_through(newest.sequence);
+    }
+    let storing = storage.is_some();
+    if let Some((storage, _)) = storage {
+        spawner.spawn(storage_task(storage)).unwrap();
+    }
+    let report = settings.daily_report_minute.map(ReportScheduler::new);
+    spawner.spawn(logger_task(task, TemperatureAlarms { high, freeze }, storing, report, settings.local_time(), &CHANNEL)).unwrap();
+    spawner.spawn(compressor_sense(compressor_input, &CHANNEL)).unwrap();
+    spawner.spawn(mains_sense(adc, mains_pin, &CHANNEL)).unwrap();
+    spawner.spawn(mains_presence(mains_present, &CHANNEL)).unwrap();
+    spawner.spawn(usb_sense(vbus, &CHANNEL)).unwrap();
+    spawner.spawn(watchdog_supervisor(IndependentWatchdog::new(board.iwdg, WATCHDOG_TIMEOUT_US))).unwrap();
+
+    // A device without a saved gauge, e.g. a new one, has a fresh battery.
+    let fuel_gauge = flash_store::load_fuel_gauge().unwrap_or_else(|| FuelGauge::new(BATTERY_CAPACITY_MAH, rt_clock.get_timestamp()));
+    let mut device = DeviceTask::new(&settings, lifecycle, fuel_gauge)
+        .with_bursts(flash_store::load_burst_slots())
+        .with_indicator(flash_store::load_indicator_state().unwrap_or_default())
+        .with_errors(flash_store::load_error_log().unwrap_or_default())
+        .with_notes(flash_store::load_notes().unwrap_or_default())
+        .with_restart_count(count_restart())
+        .with_self_test(selftest);
+    #[cfg(feature = "authentication")]
+    let key = read_provisioning_block().map(|provisioning| provisioning.key);
+    #[cfg(feature = "authentication")]
+    if let Some(key) = key {
+        device = device.with_key(key);
+    }
+    // Carry on snoozes and escalation.
+    if let Some(state) = restored_alarms {
+        device = device.with_alarm_state(state);
+        info!("Restored alarm state");
+    }
+    let slots = flash_store::load_lifetime_slots();
+    device = device.with_lifetime([&slots[0], &slots[1]]);
+    info!("Lifetime: {=str}", device.lifetime().summary().as_str());
+    let mut hardware = Hardware {
+        rt_clock,
+        relay: relay_output,
+        #[cfg(feature = "authentication")]
+        key,
+    };
+    if selftest.result(SelfTestItem::Flash) == Some(false) {
+        device.report_error(ErrorCode::FlashFail, &mut hardware);
+    }
+    // TODO: report the bursts on the console with the events that started them.
+    if let Some(record) = flash_store::load_commissioning() {
+        info!("Commissioned at {=u32}", record.completed.seconds);
+    }
+    info!("Lifecycle {}", lifecycle.state());
+    // In indicator mode a latched excursion survives resets, and only an authenticated command clears it.
+    spawner.spawn(device_task(device, hardware)).unwrap();
+}
+
+/// Serves the console on `uart`: reads the command lines, answering `capabilities` and
+/// `dictionary` itself and passing the other commands to the task that carries them out, and
+/// sends `CONSOLE_OUT`.
+#[embassy_executor::task]
+async fn console_task(uart: BufferedUart<'static>, capabilities: Capabilities) {
+    let (mut tx, mut rx) = uart.split();
+    let receive = async {
+        let mut reader = LineReader::new();
+        let mut staging = None; // The firmware image being uploaded.
+        let mut bytes = [0u8; 16];
+        loop {
+            // Bytes lost to a framing error or overrun spoil their line, which then fails to parse.
+            let Ok(len) = rx.read(&mut bytes).await else {
+                continue;
+            };
+            for &byte in &bytes[..len] {
+                if XMODEM_ACTIVE.load(Ordering::Relaxed) {
+                    // The receiver repeats what is lost.
+                    let _ = XMODEM_RX.try_send(byte);
+                    continue;
+                }
+                let Some(result) = reader.push(byte) else {
+                    continue;
+                };
+                let mut line = ArrayString::<CONSOLE_LINE_LEN>::new();
+                let written = match result {
+                    Ok(Command::Capabilities) => write_capabilities(&mut line, capabilities),
+                    Ok(Command::Dictionary) => {
+                        // Longer than a line, so sent from its own buffer.
+                        let mut dictionary = ArrayString::<DICTIONARY_LEN>::new();
+                        let written = write_dictionary(&mut dictionary);
+                        if written.is_ok() {
+                            CONSOLE_OUT.write_all(dictionary.as_bytes()).await;
+                        }
+                        written
+                    }
+                    Ok(Command::Update(command)) => firmware_update::command(&mut staging, command, &mut line),
+                    Ok(Command::BusAddress(address)) => set_bus_address(address, &mut line),
+                    Ok(Command::ReportTime(minute)) => set_report_time(minute, &mut line),
+                    Ok(Command::Logger(command)) => {
+                        LOGGER_COMMANDS.send(command).await;
+                        Ok(())
+                    }
+                    Ok(Command::Export(command)) => {
+                        XMODEM_ACTIVE.store(command.xmodem, Ordering::Relaxed);
+                        EXPORTS.send(command).await;
+                        Ok(())
+                    }
+                    Ok(Command::Device(command)) => {
+                        CHANNEL.send(DeviceEvent::Command(command));
+                        Ok(())
+                    }
+                    Err(error) => writeln!(line, "error {:?}", error),
+                };
+                if written.is_ok() {
+                    CONSOLE_OUT.write_all(line.as_bytes()).await;
+                }
+            }
+        }
+    };
+    let send = async {
+        let mut bytes = [0u8; 32];
+        loop {
+            let len = CONSOLE_OUT.read(&mut bytes).await;
+            // A UART without flow control can't fail to send.
+            let _ = tx.write_all(&bytes[..len]).await;
+        }
+    };
+    join(receive, send).await;
+}
+
+/// Give the device `address` on the RS-485 bus, in the saved settings, and answer on `out`.
+fn set_bus_address(address: u8, out: &mut impl Write) -> core::fmt::Result {
+    let mut settings = flash_store::load_settings().map(|(settings, _)| settings).unwrap_or_default();
+    match settings.apply(Settings { bus_address: address, ..settings }, &mut BusinessLog) {
+        Ok(_) => {
+            flash_store::save_settings(&settings);
+            writeln!(out, "ok")
+        }
+        Err(error) => writeln!(out, "error {:?}", error),
+    }
+}
+
+/// Send the daily report at `minute` after local midnight from now on, or not at all, in the
+/// saved settings, and answer on `out`.
+fn set_report_time(minute: Option<u16>, out: &mut impl Write) -> core::fmt::Result {
+    let mut settings = flash_store::load_settings().map(|(settings, _)| settings).unwrap_or_default();
+    match settings.apply(Settings { daily_report_minute: minute, ..settings }, &mut BusinessLog) {
+        Ok(_) => {
+            flash_store::save_settings(&settings);
+            REPORT_TIME.signal(minute);
+            writeln!(out, "ok")
+        }
+        Err(error) => writeln!(out, "error {:?}", error),
+    }
+}
+
+/// Console output that never waits: a line that doesn't fit in `CONSOLE_OUT` whole fails, and
+/// the caller drops it.
+struct ConsoleOut;
+
+impl Write for ConsoleOut {
+    fn write_str(&mut self, line: &str) -> core::fmt::Result {
+        if CONSOLE_OUT.free_capacity() < line.len() {
+            return Err(core::fmt::Error);
+        }
+        // The pipe wraps around, so may take it in two parts.
+        let mut rest = line.as_bytes();
+        while !rest.is_empty() {
+            let written = CONSOLE_OUT.try_write(rest).map_err(|_| core::fmt::Error)?;
+            rest = &rest[written..];
+        }
+        Ok(())
+    }
+}
+
+/// Runs the device until the supply fails, then sheds the loads and restarts once it recovers.
+#[embassy_executor::task]
+async fn device_task(mut device: DeviceTask, mut hardware: Hardware) {
+    device.run(&mut DeviceChannel, &mut hardware, &mut BusinessLog).await;
+    warn!("Supply failing, shutting down");
+    POWER_GATE.shut_down();
+    // Ride out a dip, checking in for the tasks that stopped, and restart once the supply recovers.
+    while power_fail::is_failing() {
+        TaskId::ALL.into_iter().for_each(heartbeat);
+        Timer::after_secs(1).await;
+    }
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+/// The device task's events: those from the driver tasks on `CHANNEL`, and the temperature
+/// alarms from the logger task.
+struct DeviceChannel;
+
+impl DeviceEvents for DeviceChannel {
+    async fn receive(&mut self) -> Option<DeviceEvent> {
+        let event = match select(CHANNEL.receive(), TEMPERATURE_ALARMS.wait()).await {
+            Either::First(event) => event,
+            Either::Second((high, freeze)) => DeviceEvent::TemperatureAlarms(high, freeze),
+        };
+        if matches!(event, DeviceEvent::Door(_) | DeviceEvent::ButtonPress(_)) {
+            flash_store::event();
+        }
+        heartbeat(TaskId::Logger);
+        Some(event)
+    }
+
+    fn waiting(&self) -> usize {
+        CHANNEL.len()
+    }
+}
+
+/// The peripherals the device task drives directly, and the signals to the tasks driving the rest.
+struct Hardware {
+    rt_clock: Rtclock,
+    relay: OutputOpenDrain<'static>, // Pulled low while the relay is asserted.
+    #[cfg(feature = "authentication")]
+    key: Option<DeviceKey>, // From the provisioning block, None if the device wasn't provisioned.
+}
+
+impl Hardware {
+    /// Keep the record of a completed commissioning, with its MAC under the device key if there is
+    /// one, and the epoch anchor and alarm profile it set, which apply from the next boot.
+    fn save_commissioning(&self, record: &CommissioningRecord) {
+        #[cfg(feature = "authentication")]
+        let mac = self.key.map(|key| record.mac(&key));
+        #[cfg(not(feature = "authentication"))]
+        let mac: Option<[u8; 0]> = None;
+        flash_store::save_commissioning(record, mac.as_ref().map_or(&[][..], |mac| mac.as_slice()));
+        let mut settings = flash_store::load_settings().map(|(settings, _)| settings).unwrap_or_default();
+        settings.epoch_anchor = Some(record.epoch_anchor);
+        settings.set_alarm_profile(record.profile);
+        flash_store::save_settings(&settings);
+    }
+}
+
+impl Device for Hardware {
+    fn now(&self) -> Timestamp {
+        self.rt_clock.get_timestamp()
+    }
+
+    fn monotonic_us(&self) -> u64 {
+        Instant::now().as_micros()
+    }
+
+    fn uptime_seconds(&self) -> u32 {
+        self.rt_clock.get_uptime_seconds()
+    }
+
+    fn clock_degraded(&self) -> bool {
+        self.rt_clock.is_degraded()
+    }
+
+    fn correct_clock(&mut self) -> u32 {
+        self.rt_clock.apply_clock_correction()
+    }
+
+    fn anchor_clock(&mut self, now: Timestamp) {
+        anchor_clock(now);
+    }
+
+    fn set_clock(&mut self, now: Timestamp, unix_seconds: u32) -> Timestamp {
+        let before = self.rt_clock.set_from_epoch_seconds(now.seconds);
+        let mut settings = flash_store::load_settings().map(|(settings, _)| settings).unwrap_or_default();
+        if settings.clock_set(before, now, Some(unix_seconds)) {
+            flash_store::save_settings(&settings);
+        }
+        before
+    }
+
+    fn log_event(&mut self, event: LoggerEvent) {
+        if matches!(event, LoggerEvent::Sample(_)) {
+            firmware_update::mark_healthy();
+        }
+        LOGGER_EVENTS.send(EVENT_NUMBERS.number(event));
+    }
+
+    fn set_door_open(&mut self, open: bool) {
+        DOOR_OPEN.store(open, Ordering::Relaxed);
+    }
+
+    fn burst_started(&mut self) {
+        BURST_STARTED.signal(());
+    }
+
+    fn set_bursting(&mut self, active: bool) {
+        BURST_ACTIVE.store(active, Ordering::Relaxed);
+    }
+
+    fn sound(&mut self, alarm: Option<(AlarmKind, bool)>) {
+        BUZZER.signal(alarm);
+    }
+
+    fn set_relay(&mut self, asserted: bool) {
+        self.relay.set_level(if asserted { Level::Low } else { Level::High });
+    }
+
+    fn show(&mut self, model: &DisplayModel) {
+        DISPLAY.signal(*model);
+    }
+
+    fn show_status(&mut self, status: DeviceStatus) {
+        STATUS_LED.signal(status);
+    }
+
+    fn set_clock_profile(&mut self, profile: ClockProfile) {
+        apply_clock_profile(profile);
+    }
+
+    fn set_lifecycle(&mut self, state: LifecycleState) {
+        LIFECYCLE.signal(state);
+    }
 
-    warn!("Starting main loop");
+    fn reply(&mut self, line: core::fmt::Arguments<'_>) {
+        let mut out = ArrayString::<CONSOLE_LINE_LEN>::new();
+        if writeln!(out, "{}", line).and_then(|()| ConsoleOut.write_str(&out)).is_err() {
+            warn!("Console reply dropped");
+        }
+    }
+
+    fn save(&mut self, saved: Saved<'_>) {
+        match saved {
+            Saved::Alarms(state) => flash_store::save_alarm_state(&state),
+            Saved::Burst(slot, capture) => flash_store::save_burst(slot, capture),
+            Saved::Indicator(state) => flash_store::save_indicator_state(&state),
+            Saved::Lifetime(slot, bytes) => flash_store::save_lifetime_slot(slot, &bytes),
+            Saved::FuelGauge(gauge) => flash_store::save_fuel_gauge(&gauge),
+            Saved::Errors(errors) => flash_store::save_error_log(&errors),
+            Saved::Checkpoint(checkpoint) => self.rt_clock.write_power_fail_checkpoint(&checkpoint),
+            Saved::Lifecycle(lifecycle) => flash_store::save_lifecycle(&lifecycle),
+            Saved::Commissioning(record) => self.save_commissioning(record),
+            Saved::Notes(notes) => flash_store::save_notes(notes),
+            Saved::TestMode(on) => {
+                let mut settings = flash_store::load_settings().map(|(settings, _)| settings).unwrap_or_default();
+                settings.test_mode = on;
+                flash_store::save_settings(&settings);
+            }
+        }
+    }
+
+    fn driver_health(&self, health: &mut DeviceHealth) {
+        health.flash_erases = FLASH_ERASES.load(Ordering::Relaxed);
+        health.i2c_errors = I2C_ERRORS.load(Ordering::Relaxed);
+        health.worst_acquisition_us = WORST_ACQUISITION_US.load(Ordering::Relaxed);
+        health.worst_erase_stall_us = WORST_ERASE_STALL_US.load(Ordering::Relaxed);
+        health.queue_overflows = CHANNEL.overflows().saturating_add(LOGGER_EVENTS.overflows());
+        health.queue_coalesced = CHANNEL.coalesced().saturating_add(LOGGER_EVENTS.coalesced());
+    }
+
+    fn day_complete(&mut self, report: DayReport<'_>) {
+        info!("Day complete: {=char}, history: {=str}", report.day.symbol(), report.history.ticker().as_str());
+        if !report.errors.is_empty() {
+            warn!("Errors during the day: {=u32:#010x}", report.errors.as_u32());
+        }
+        // TODO: append to the daily report, and answer console queries, once those exist.
+        info!("Health: {=str}", report.health.footer().as_str());
+        let forced = flash_store::forced_erases();
+        if forced > 0 {
+            warn!("Flash erases forced between samples or events: {=u32}", forced);
+        }
+        info!("Lifetime: {=str}", report.lifetime.summary().as_str());
+    }
+}
 
+#[embassy_executor::task]
+async fn button(mut btn: ExtiInput<'static>, msg: &'static EventChannel<DeviceEvent, EVENT_QUEUE_LEN>) {
+    // An EXTI input never fails.
+    let _ = run_button(&mut btn, &mut Delay, &EmbassyClock, |press| msg.send(DeviceEvent::ButtonPress(press))).await;
+}
+
+/// The embassy timer's time since boot.
+struct EmbassyClock;
+
+impl hal::Monotonic for EmbassyClock {
+    fn now_ms(&self) -> u64 {
+        Instant::now().as_millis()
+    }
+}
+
+/// Reports the door opening and closing, from `open`, as it was before a reset.
+#[embassy_executor::task]
+async fn door_switch(mut input: ExtiInput<'static>, wiring: DoorSwitchConfig, mut open: bool, msg: &'static EventChannel<DeviceEvent, EVENT_QUEUE_LEN>) {
+    loop {
+        let level = wiring.is_open(input.is_high());
+        if level != open {
+            open = level;
+            msg.send(DeviceEvent::Door(if open { DoorEvent::Opened } else { DoorEvent::Closed }));
+        }
+        input.wait_for_any_edge().await;
+        // Only accept the new level once it has settled.
+        Timer::after_millis(wiring.debounce_ms.into()).await;
+    }
+}
+
+/// Waits for the accelerometer's shock interrupt, checking for tilts now and then. It is on the
+/// always-on supply, unlike the temperature sensors.
+#[cfg(feature = "accelerometer")]
+#[embassy_executor::task]
+async fn motion_sense(mut accelerometer: Accelerometer<I2cHandle>, mut interrupt: ExtiInput<'static>, msg: &'static EventChannel<DeviceEvent, EVENT_QUEUE_LEN>) {
+    let mut detector = MotionDetector::with_reference(flash_store::load_tilt_reference());
     loop {
-        match CHANNEL.receive().await {
-            Events::Button(ButtonEvent::Pressed) => {
-                info!("Button pressed event received");
-                // let then = rtc.now().unwrap();
-                // info!("time: {:?}:{:?}", then.minute(), then.second());
+        // The interrupt stays latched until `take_shock`, so a shock whose edge was missed is
+        // still seen at the next check.
+        select(interrupt.wait_for_rising_edge(), Timer::after(TILT_CHECK_PERIOD)).await;
+        let previous_reference = detector.reference();
+        let event = match (accelerometer.take_shock().await, accelerometer.read_mg().await) {
+            (Ok(true), Ok(mg)) => Some(detector.shocked(mg, &mut BusinessLog)),
+            (Ok(false), Ok(mg)) => detector.update(mg, &mut BusinessLog),
+            _ => {
+                I2C_ERRORS.fetch_add(1, Ordering::Relaxed);
+                None
             }
-            Events::Button(ButtonEvent::Released) => {
-                info!("Button released event received");
+        };
+        // Keep the installed orientation, so a restart doesn't take a tilted device as upright.
+        if let Some(reference) = detector.reference()
+            && previous_reference != Some(reference)
+        {
+            flash_store::save_tilt_reference(reference);
+        }
+        if let Some(event) = event {
+            msg.send(DeviceEvent::Motion(event));
+        }
+    }
+}
+
+/// Events for the logger task, from `LOGGER_EVENTS`, until the supply fails.
+struct LoggerEvents(&'static EventChannel<Numbered, EVENT_QUEUE_LEN>);
+
+impl EventSource for LoggerEvents {
+    async fn receive(&mut self) -> Option<Numbered> {
+        match select(self.0.receive(), POWER_FAILING.wait()).await {
+            Either::First(event) => Some(event),
+            Either::Second(()) => None,
+        }
+    }
+}
+
+/// Passes the logger task's temperature alarms to the device task, which owns the annunciator.
+#[derive(Default)]
+struct TemperatureAlarms {
+    high: bool,
+    freeze: bool,
+}
+
+impl AlarmOutput for TemperatureAlarms {
+    async fn set_active(&mut self, kind: AlarmKind, active: bool) {
+        match kind {
+            AlarmKind::HighTemp => self.high = active,
+            AlarmKind::Freeze => self.freeze = active,
+            AlarmKind::Power | AlarmKind::Door => {}
+        }
+        TEMPERATURE_ALARMS.signal((self.high, self.freeze));
+    }
+}
+
+/// Stream the report `command` asks for to the console, with the settings' epoch anchor, the
+/// operator notes and the gaps. The events wait in their channel meanwhile, as the store must not
+/// change under the export.
+async fn export(store: &RamStore<RECORD_STORE_LEN>, command: ExportCommand) {
+    let settings = flash_store::load_settings().map(|(settings, _)| settings).unwrap_or_default();
+    let notes = flash_store::load_notes().unwrap_or_default();
+    let report = Report { epoch_anchor: settings.epoch_anchor, ..Report::generate(store.iter(), command.start, command.end) };
+    let chunks = ReportChunks::with_report(store, report).filtered(command.filter).with_notes(&notes).with_gaps(settings.record_period_seconds);
+    if command.xmodem {
+        send_xmodem(ChunkReader::new(chunks)).await;
+        return;
+    }
+    let Ok(written) = write_export(chunks, &mut ConsoleExport).await;
+    info!("Exported {=u32} bytes", written);
+}
+
+/// Send the daily report over the console if `scheduler` has one due at `now`. While an XMODEM
+/// transfer has the console, the attempt fails and the scheduler retries it later.
+async fn send_daily_report(store: &RamStore<RECORD_STORE_LEN>, scheduler: &mut ReportScheduler, local: &LocalTime, now: Timestamp) {
+    let Some((start, end)) = scheduler.poll(now, local, &mut BusinessLog) else {
+        return;
+    };
+    if XMODEM_ACTIVE.load(Ordering::Relaxed) {
+        scheduler.failed(now, &mut BusinessLog);
+        return;
+    }
+    let settings = flash_store::load_settings().map(|(settings, _)| settings).unwrap_or_default();
+    let notes = flash_store::load_notes().unwrap_or_default();
+    let report = Report { epoch_anchor: settings.epoch_anchor, ..Report::generate(store.iter(), start, end) };
+    let chunks = ReportChunks::with_report(store, report).with_notes(&notes).with_gaps(settings.record_period_seconds);
+    let Ok(written) = scheduler.send(chunks, &mut ConsoleExport, now, &mut BusinessLog).await;
+    info!("Sent the daily report, {=u32} bytes", written);
+}
+
+/// Send `source` with XMODEM, answering the receiver's bytes from the console task until the
+/// transfer ends, then hand the console back to the command lines.
+async fn send_xmodem(source: impl ByteSource) {
+    let mut sender = XmodemSender::new(source);
+    while !sender.is_done() {
+        let sent = match with_timeout(Duration::from_secs(TIMEOUT_SECONDS.into()), XMODEM_RX.receive()).await {
+            Ok(byte) => sender.receive(byte),
+            Err(TimeoutError) => sender.timeout(),
+        };
+        match sent {
+            Ok(packet) => CONSOLE_OUT.write_all(packet).await,
+            Err(error) => {
+                warn!("XMODEM export failed: {}", error);
+                break;
             }
-            Events::TempReading(temperature) => {
-                let ts = rt_clock.get_timestamp();
-                info!("Time: {}, TAMB: {} °C, TVC: {} °C", ts.seconds, temperature.0, temperature.1);
-                let ts = rt_clock.get_timestamp();
-                info!("{=str}", ts.create_iso8601_str());
+        }
+    }
+    XMODEM_ACTIVE.store(false, Ordering::Relaxed);
+    XMODEM_RX.clear();
+}
+
+/// Sends an export to the console, waiting for room in `CONSOLE_OUT`.
+struct ConsoleExport;
+
+impl ExportSink for ConsoleExport {
+    type Error = Infallible;
+
+    async fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), Infallible> {
+        CONSOLE_OUT.write_all(chunk).await;
+        Ok(())
+    }
+}
+
+/// Work the logger task does on its store between events.
+enum LoggerJob {
+    Compaction(Compaction),
+}
+
+impl BulkJob<RamStore<RECORD_STORE_LEN>> for LoggerJob {
+    fn step(&mut self, store: &mut RamStore<RECORD_STORE_LEN>, log: &mut impl business_logic::log::Log) -> bool {
+        match self {
+            LoggerJob::Compaction(job) => job.step(store, log),
+        }
+    }
+}
+
+/// Hands the storage task the records from the logger task, until the supply fails.
+struct StorageRecords;
+
+impl RecordSource for StorageRecords {
+    async fn receive(&mut self) -> Option<ChainedRecord> {
+        match select(STORAGE_RECORDS.receive(), POWER_FAILING.wait()).await {
+            Either::First(record) => Some(record),
+            Either::Second(()) => None,
+        }
+    }
+
+    fn try_receive(&mut self) -> Option<ChainedRecord> {
+        STORAGE_RECORDS.try_receive().ok()
+    }
+}
+
+/// Passes the storage task's reports back to the logger task.
+struct LoggerReports;
+
+impl StorageReports for LoggerReports {
+    async fn report(&mut self, report: StorageReport) {
+        STORAGE_REPORTS.send(report).await;
+    }
+}
+
+/// Keeps the completed records in flash.
+#[embassy_executor::task]
+async fn storage_task(mut storage: StorageTask<RecordPages, { FLASH_PAGE_BYTES as usize }>) {
+    storage.run(&mut StorageRecords, &mut LoggerReports).await;
+}
+
+/// Aggregates the readings and events into records and keeps them, in flash too if `storing`.
+#[embassy_executor::task]
+async fn logger_task(
+    mut task: LoggerTask<RamStore<RECORD_STORE_LEN>>,
+    mut alarms: TemperatureAlarms,
+    storing: bool,
+    mut report: Option<ReportScheduler>,
+    local: LocalTime,
+    msg: &'static EventChannel<DeviceEvent, EVENT_QUEUE_LEN>,
+) {
+    // TODO: run downloads as `BulkJob`s behind the events too.
+    // TODO: move the records to the external flash once there is a driver for it; the data
+    // area's pages after the NV store hold them until then.
+    let mut events = LoggerEvents(&LOGGER_EVENTS);
+    let mut dispatcher: Dispatcher<LoggerWork<LoggerJob>, EVENT_QUEUE_LEN> = Dispatcher::new();
+    let mut now = Timestamp { seconds: 0 }; // Of the last event received.
+    let mut watch = Watch::new(ConsoleOut);
+    loop {
+        // Only the events after a lifecycle change are recorded by its rules, so it needn't wake the task.
+        if let Some(lifecycle) = LIFECYCLE.try_take() {
+            task.set_lifecycle(lifecycle);
+        }
+        if let Some(minute) = REPORT_TIME.try_take() {
+            report = minute.map(ReportScheduler::new);
+        }
+        // Wait for events only when there's no other work, so jobs go on between them. While the
+        // urgent lane is full they wait in the channel, which coalesces and counts its overflows.
+        let room = dispatcher.len(Lane::Urgent) < EVENT_QUEUE_LEN;
+        if dispatcher.is_empty() || (room && LOGGER_EVENTS.len() > 0) {
+            // Tick the logger at its next deadline if nothing happens before, so records complete
+            // and alarms are raised on time.
+            // TODO: program the RTC wakeup from it instead once the device sleeps in stop mode
+            // between events.
+            let anchor = CLOCK_ANCHOR.load(Ordering::Relaxed);
+            let clock = anchor.wrapping_add(Instant::now().as_secs() as u32);
+            let deadline = task.next_deadline(Timestamp { seconds: clock.max(now.seconds) }).at;
+            // Or at the daily report, if that comes first.
+            let report_at = report.as_mut().map(|scheduler| clock.saturating_add(scheduler.seconds_until(Timestamp { seconds: clock }, &local)));
+            let wake_at = report_at.map_or(deadline.seconds, |at| at.min(deadline.seconds));
+            let wake = Timer::at(Instant::from_secs(wake_at.wrapping_sub(anchor).into()));
+            let event = match select4(events.receive(), wake, LOGGER_COMMANDS.receive(), EXPORTS.receive()).await {
+                Either4::First(Some(event)) => event,
+                Either4::First(None) => break,
+                Either4::Second(()) if wake_at < deadline.seconds => {
+                    if let Some(scheduler) = report.as_mut() {
+                        send_daily_report(task.store(), scheduler, &local, Timestamp { seconds: wake_at }).await;
+                    }
+                    continue;
+                }
+                // Never behind the last event, or the logger would drop it.
+                Either4::Second(()) => EVENT_NUMBERS.number(LoggerEvent::Tick(Timestamp { seconds: deadline.seconds.max(now.seconds) })),
+                Either4::Third(command) => {
+                    let mut out = ArrayString::<CONSOLE_OUT_LEN>::new();
+                    if task.command(command, &mut watch, &mut out).is_err() {
+                        warn!("Console reply too long for the output");
+                    }
+                    CONSOLE_OUT.write_all(out.as_bytes()).await;
+                    continue;
+                }
+                Either4::Fourth(command) => {
+                    export(task.store(), command).await;
+                    continue;
+                }
+            };
+            now = event.event.timestamp();
+            let work = LoggerWork::Event(event);
+            let queued = dispatcher.push(work.lane(), work).is_ok();
+            debug_assert!(queued);
+        } else if POWER_FAILING.signaled() {
+            break;
+        }
+        let stored = task.store().len();
+        task.dispatch_watched(&mut dispatcher, &mut alarms, &mut BusinessLog, &mut watch).await;
+        // Let the other tasks run between the steps of a job.
+        yield_now().await;
+        if let Some(state) = task.warm_start_state() {
+            warm_start::save(&state);
+        }
+        if storing {
+            while let Ok(report) = STORAGE_REPORTS.try_receive() {
+                task.storage_report(report, &mut BusinessLog);
+            }
+            // Records the channel has no room for go on the next pass.
+            task.send_unsaved(|record| STORAGE_RECORDS.try_send(record).is_ok());
+        }
+        if task.store().len() > stored && task.store().wants_compaction() {
+            let job = LoggerWork::Job(LoggerJob::Compaction(Compaction { now, age_days: COMPACTION_AGE_DAYS }));
+            if dispatcher.push(job.lane(), job).is_err() {
+                warn!("No room to queue a compaction");
+            }
+        }
+    }
+    let flushed = task.flush(&mut BusinessLog);
+    // The record in progress is complete, so a restart must not carry it on.
+    warm_start::clear();
+    // Save the last record, even one restored after an earlier power fail, rather than lose
+    // everything during a run of brownouts, unless it is already in flash.
+    let unsaved = |chained: &ChainedRecord| !storing || task.saved_through() != Some(chained.sequence);
+    let last = flushed.or_else(|| task.store().iter_chained().last().filter(unsaved).map(|chained| chained.record));
+    msg.send(DeviceEvent::PowerFail(last.map(|record| PowerFailCheckpoint::from_record(&record))));
+}
+
+#[embassy_executor::task]
+async fn compressor_sense(mut input: ExtiInput<'static>, msg: &'static EventChannel<DeviceEvent, EVENT_QUEUE_LEN>) {
+    let mut running = false;
+    loop {
+        let level = input.is_high();
+        if level != running {
+            running = level;
+            let event = if running { CompressorEvent::Started } else { CompressorEvent::Stopped };
+            msg.send(DeviceEvent::Compressor(event));
+        }
+        input.wait_for_any_edge().await;
+        // Only accept the new level once it has settled.
+        Timer::after(COMPRESSOR_DEBOUNCE_TIME).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn usb_sense(mut input: ExtiInput<'static>, msg: &'static EventChannel<DeviceEvent, EVENT_QUEUE_LEN>) {
+    let mut connected = false;
+    loop {
+        let level = input.is_high();
+        if level != connected {
+            connected = level;
+            msg.send(DeviceEvent::Usb(if connected { UsbEvent::Connected } else { UsbEvent::Disconnected }));
+        }
+        input.wait_for_any_edge().await;
+        // Only accept the new level once it has settled.
+        Timer::after(USB_DEBOUNCE_TIME).await;
+    }
+}
+
+/// Watches the mains-present input. Its edges wake the device from sleep, and changes are timed
+/// at the edge, so an outage is recorded when it began, however late it is handled.
+#[embassy_executor::task]
+async fn mains_presence(mut input: ExtiInput<'static>, msg: &'static EventChannel<DeviceEvent, EVENT_QUEUE_LEN>) {
+    let mut presence = MainsPresence::new();
+    loop {
+        presence.input(input.is_high(), Instant::now().as_millis());
+        if let Some((event, at_ms)) = presence.poll(Instant::now().as_millis()) {
+            let anchor = CLOCK_ANCHOR.load(Ordering::Relaxed);
+            let at = Timestamp { seconds: anchor.wrapping_add((at_ms / 1000) as u32) };
+            info!("Mains {}, {} glitches filtered", event, presence.glitches());
+            msg.send(DeviceEvent::Power(event, at));
+        }
+        match presence.settles_at_ms() {
+            Some(at_ms) => {
+                select(input.wait_for_any_edge(), Timer::at(Instant::from_millis(at_ms))).await;
             }
+            None => input.wait_for_any_edge().await,
         }
+    }
+}
 
+#[embassy_executor::task]
+async fn mains_sense(
+    mut adc: Adc<'static, MainsAdc>,
+    mut pin: MainsPin,
+    msg: &'static EventChannel<DeviceEvent, EVENT_QUEUE_LEN>,
+) {
+    let mut ticker = Ticker::every(Duration::from_secs(MAINS_SAMPLE_SECONDS.into()));
+    loop {
+        let raw = adc.blocking_read(&mut pin);
+        msg.send(DeviceEvent::MainsReading(raw));
+        heartbeat(TaskId::Mains);
+        ticker.next().await;
+    }
+}
+
+#[embassy_executor::task]
+async fn buzzer_task(mut buzzer: Output<'static>) {
+    let mut alarm: Option<(AlarmKind, bool)> = None;
+    loop {
+        let Some((kind, escalated)) = alarm else {
+            buzzer.set_low();
+            alarm = BUZZER.wait().await;
+            continue;
+        };
+        // Play the pattern for the current alarm until a different alarm is signalled.
+        'pattern: loop {
+            for step in kind.buzzer_pattern(escalated) {
+                buzzer.set_high();
+                if let Either::Second(next) = select(Timer::after_millis(step.on_ms.into()), BUZZER.wait()).await {
+                    alarm = next;
+                    break 'pattern;
+                }
+                buzzer.set_low();
+                if let Either::Second(next) = select(Timer::after_millis(step.off_ms.into()), BUZZER.wait()).await {
+                    alarm = next;
+                    break 'pattern;
+                }
+            }
+        }
     }
 }
 
 #[embassy_executor::task]
-async fn button(mut btn: ExtiInput<'static>, msg: Sender<'static, ThreadModeRawMutex, Events, 8>) {
+async fn display_task(mut display: Ssd1306<I2cHandle>) {
     loop {
-        btn.wait_for_falling_edge().await;
-        info!("Button pressed!");
-        msg.send(Events::Button(ButtonEvent::Pressed)).await;
-        // Debounce delay
-        Timer::after(Duration::from_millis(50)).await;
-        // Wait for release (rising edge)
-        btn.wait_for_rising_edge().await;
-        info!("Button released!");
-        msg.send(Events::Button(ButtonEvent::Released)).await;
-        // Debounce delay
-        Timer::after(Duration::from_millis(50)).await;
+        let model = DISPLAY.wait().await;
+        // Leave a blank page between lines of text.
+        for (page, line) in (0..).step_by(2).zip(model.lines()) {
+            if display.write_line(page, &line).await.is_err() {
+                warn!("Failed to write to display");
+                I2C_ERRORS.fetch_add(1, Ordering::Relaxed);
+                break;
+            }
+        }
     }
 }
 
 #[embassy_executor::task]
-async fn led_blink(mut led: Output<'static>) {
+async fn status_led(mut led: Output<'static>) {
+    let mut status = STATUS_LED.wait().await;
     loop {
-        led.set_high();
-        Timer::after(Duration::from_millis(500)).await;
-        led.set_low();
-        Timer::after(Duration::from_millis(500)).await;
+        // Play the pattern for the current status until a new status is signalled.
+        'pattern: loop {
+            heartbeat(TaskId::StatusLed);
+            for step in status.led_pattern() {
+                if step.color.is_on() {
+                    led.set_high();
+                } else {
+                    led.set_low();
+                }
+                if let Either::Second(next) = select(Timer::after_millis(step.ms.into()), STATUS_LED.wait()).await {
+                    status = next;
+                    break 'pattern;
+                }
+            }
+        }
     }
 }
 
 #[embassy_executor::task]
 async fn get_temperature(
-    mut temp_sensor: DualTempSensor<I2c<'static, embassy_stm32::mode::Async>>,
-    msg: Sender<'static, ThreadModeRawMutex, Events, 8>,
+    mut temp_sensor: DualTempSensor<I2cHandle>,
+    policy: AdaptiveSampling,
+    mut self_heating: SelfHeating,
+    msg: &'static EventChannel<DeviceEvent, EVENT_QUEUE_LEN>,
 ) {
-    let mut ticker = Ticker::every(Duration::from_secs(10)); // Read every 10 seconds
+    let mut rail_off_at = Instant::MIN;
+    let mut last_ambient = None; // The last good ambient reading, to stand in for a failed one.
     loop {
-        match temp_sensor.read_temperature_celsius().await {
-            Ok(ftemp) => {
-                // info!("Temperature: {} °C", ftemp);
-                msg.send(Events::TempReading(ftemp)).await;
+        // All sensors are read in one pass, so the rail is switched on and settles once per sample.
+        let started = Instant::now();
+        let power = POWER_GATE.acquire(Rail::Sensors).await; // Waits for the first conversion.
+        let mut acquisition = temp_sensor.acquire(&mut Delay).await;
+        drop(power);
+        WORST_ACQUISITION_US.fetch_max(started.elapsed().as_micros() as u32, Ordering::Relaxed);
+        // The sensors warm while they power up and convert, not while the reads wait for the
+        // shared bus, so the on-time is the rail's settle time. Readings come at its end, so they
+        // include its warming.
+        let (switched_on, switched_off) = POWER_GATE.last_switched(Rail::Sensors);
+        let off_ms = switched_on.saturating_duration_since(rail_off_at).as_millis();
+        self_heating.powered(off_ms.min(u32::MAX.into()) as u32, Rail::Sensors.settle_ms() as u32);
+        rail_off_at = switched_off.unwrap_or_else(Instant::now);
+        #[cfg(feature = "humidity")]
+        {
+            if acquisition.humidity.is_err() {
+                warn!("Failed to read from humidity sensor");
             }
-            Err(_) => warn!("Failed to read from temperature sensor"),
+            msg.send(DeviceEvent::HumidityReading(acquisition.humidity.ok()));
         }
-        ticker.next().await;
+        // Each sensor is read on its own bus handle, so a fault shows against the sensor it affects.
+        if let Err(error) = acquisition.ambient {
+            warn!("Failed to read from ambient sensor: {}", error);
+            I2C_ERRORS.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Err(error) = acquisition.vaccine {
+            warn!("Failed to read from vaccine sensor: {}", error);
+            I2C_ERRORS.fetch_add(1, Ordering::Relaxed);
+        }
+        if acquisition.temperatures().is_err() {
+            msg.send(DeviceEvent::SensorFault);
+        }
+        // A failed ambient read doesn't stop the vaccine reading being logged.
+        acquisition.substitute_ambient(last_ambient);
+        last_ambient = acquisition.ambient.ok();
+        // TODO: flag readings `SampleFlag::Calibrated` once the sensors have a calibration offset;
+        // the self-heating correction is a model of the logger, not a calibration.
+        if let Ok((amb, vax)) = acquisition.temperatures() {
+            let quality = (acquisition.ambient_quality, acquisition.vaccine_quality);
+            msg.send(DeviceEvent::TempReading((self_heating.compensate(amb), self_heating.compensate(vax)), quality));
+        }
+        // TODO: on a board with a second vaccine probe, read it too, log it as `Channel::SecondaryVaccine`
+        // and pick the reading for the alarms with `settings.probe_cross_check()`, flagging a reading
+        // from the secondary probe with `SampleFlag::Substituted`.
+        // The vaccine reading still paces the sampling when only the ambient sensor failed.
+        let tvc = acquisition.vaccine.ok().map(|vax| self_heating.compensate(vax));
+        // Sample faster when TVC is near a threshold, the door is open or a burst is being
+        // captured, on the wall-clock boundaries of the period so records from different devices line up.
+        let policy = if BURST_ACTIVE.load(Ordering::Relaxed) { policy.bursting(BURST_PERIOD_SECONDS) } else { policy };
+        let anchor = CLOCK_ANCHOR.load(Ordering::Relaxed);
+        let now = Timestamp { seconds: anchor.wrapping_add(Instant::now().as_secs() as u32) };
+        let next_sample = policy.next_sample_at(now, tvc, DOOR_OPEN.load(Ordering::Relaxed));
+        let next_sample = Instant::from_secs(next_sample.seconds.wrapping_sub(anchor).into());
+        flash_store::next_sample(next_sample);
+        heartbeat(TaskId::Temperature);
+        // A new burst starts with a sample straight away.
+        select(Timer::at(next_sample), BURST_STARTED.wait()).await;
     }
 }
+
+/// Switch to the clock profile chosen by the power manager.
+fn apply_clock_profile(profile: ClockProfile) {
+    // The I2C buses are slowed down at runtime. The system clock itself stays at 48 MHz:
+    // embassy-stm32 0.2 can't reconfigure RCC after init, and its TIM time driver would
+    // tick at the wrong rate if SYSCLK/PCLK changed underneath it.
+    // TODO: switch SYSCLK to MSI 2 MHz (PLL off) once the HAL supports runtime clock changes.
+    SENSOR_BUS.set_speed(profile.i2c_hz);
+    VACCINE_BUS.set_speed(profile.i2c_hz);
+    DISPLAY_BUS.set_speed(profile.i2c_hz);
+}
+
+/// Record the wall-clock time `now` against the monotonic timer, for `CLOCK_ANCHOR`.
+fn anchor_clock(now: Timestamp) {
+    CLOCK_ANCHOR.store(now.seconds.wrapping_sub(Instant::now().as_secs() as u32), Ordering::Relaxed);
+}
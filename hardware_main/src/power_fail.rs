@@ -0,0 +1,48 @@
+use embassy_stm32::interrupt::typelevel::{Binding, Handler, Interrupt, PVD_PVM};
+use embassy_stm32::pac::{self, pwr::vals::Pls};
+use embassy_stm32::bind_interrupts;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+const PVD_EXTI_LINE: usize = 16; // The PVD output's EXTI line.
+
+/// Raised once when the supply falls below the PVD threshold, well before the brownout reset,
+/// leaving a few milliseconds on the hold-up capacitor to save what RAM would lose.
+pub static POWER_FAILING: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+struct PvdHandler;
+
+impl Handler<PVD_PVM> for PvdHandler {
+    unsafe fn on_interrupt() {
+        pac::EXTI.pr(0).write(|w| w.set_line(PVD_EXTI_LINE, true));
+        POWER_FAILING.signal(());
+    }
+}
+
+bind_interrupts!(struct Irqs {
+    PVD_PVM => PvdHandler;
+});
+
+/// Watch the supply with the PVD at 2.9 V, raising `POWER_FAILING` when it drops below.
+pub fn init() {
+    fn bound(_: impl Binding<PVD_PVM, PvdHandler>) {}
+    bound(Irqs);
+    pac::PWR.cr2().modify(|w| {
+        w.set_pls(Pls::V2_9);
+        w.set_pvde(true);
+    });
+    // The PVD output rises as the supply falls.
+    pac::EXTI.rtsr(0).modify(|w| w.set_line(PVD_EXTI_LINE, true));
+    pac::EXTI.imr(0).modify(|w| w.set_line(PVD_EXTI_LINE, true));
+    PVD_PVM::unpend();
+    // SAFETY: the handler only touches the EXTI pending bit and a critical-section signal.
+    unsafe { PVD_PVM::enable() };
+    if is_failing() {
+        POWER_FAILING.signal(()); // Already low, so there will be no edge.
+    }
+}
+
+/// Whether the supply is below the PVD threshold now.
+pub fn is_failing() -> bool {
+    pac::PWR.sr2().read().pvdo()
+}
@@ -0,0 +1,126 @@
+use core::cell::RefCell;
+
+use business_logic::power::{Rail, RailCounts};
+use embassy_stm32::gpio::Output;
+use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
+use embassy_time::{Duration, Instant, Timer};
+
+/// Enable pin of a switchable rail.
+pub struct RailPin {
+    pin: Output<'static>,
+    active_low: bool,
+}
+
+impl RailPin {
+    pub fn new(pin: Output<'static>, active_low: bool) -> Self {
+        Self { pin, active_low }
+    }
+
+    fn set(&mut self, on: bool) {
+        if on != self.active_low {
+            self.pin.set_high();
+        } else {
+            self.pin.set_low();
+        }
+    }
+}
+
+struct State {
+    pins: [Option<RailPin>; Rail::COUNT], // None for rails without a gate on this board.
+    counts: RailCounts,
+    ready_at: [Instant; Rail::COUNT],
+    switched: [(Instant, Option<Instant>); Rail::COUNT], // When each rail was last switched on, and off unless it is on.
+    shut_down: bool, // The supply is failing, so no rail is switched on again.
+}
+
+/// Reference-counted power gating shared by all driver tasks.
+///
+/// Drivers hold a `RailGuard` while they use a rail; the rail is switched off
+/// when the last guard is dropped, so it is off whenever its users are sleeping.
+pub struct PowerGate {
+    state: Mutex<ThreadModeRawMutex, RefCell<Option<State>>>,
+}
+
+pub static POWER_GATE: PowerGate = PowerGate::new();
+
+impl PowerGate {
+    const fn new() -> Self {
+        Self { state: Mutex::new(RefCell::new(None)) }
+    }
+
+    /// Take ownership of the rail enable pins, indexed by `Rail`, and switch all rails off.
+    pub fn init(&self, mut pins: [Option<RailPin>; Rail::COUNT]) {
+        for pin in pins.iter_mut().flatten() {
+            pin.set(false);
+        }
+        self.state.lock(|state| {
+            state.replace(Some(State { pins, counts: RailCounts::new(), ready_at: [Instant::MIN; Rail::COUNT], switched: [(Instant::MIN, Some(Instant::MIN)); Rail::COUNT], shut_down: false }));
+        });
+    }
+
+    /// Switch the rail on if needed and wait until it has settled. Never returns after `shut_down`.
+    pub async fn acquire(&self, rail: Rail) -> RailGuard {
+        let ready_at = self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let state = state.as_mut().expect("power gate not initialized");
+            if state.shut_down {
+                return None;
+            }
+            if state.counts.acquire(rail) {
+                if let Some(pin) = &mut state.pins[rail as usize] {
+                    pin.set(true);
+                }
+                let now = Instant::now();
+                state.ready_at[rail as usize] = now + Duration::from_millis(rail.settle_ms());
+                state.switched[rail as usize] = (now, None);
+            }
+            Some(state.ready_at[rail as usize])
+        });
+        let Some(ready_at) = ready_at else {
+            return core::future::pending().await;
+        };
+        Timer::at(ready_at).await; // Later users of a rail that is still settling wait too.
+        RailGuard { rail }
+    }
+
+    /// Switch every rail off, whoever is using it, and keep them off, as the supply fails.
+    pub fn shut_down(&self) {
+        self.state.lock(|state| {
+            if let Some(state) = state.borrow_mut().as_mut() {
+                state.shut_down = true;
+                for pin in state.pins.iter_mut().flatten() {
+                    pin.set(false);
+                }
+            }
+        });
+    }
+
+    /// When the rail was last switched on, and off, or None for off while another user holds it.
+    pub fn last_switched(&self, rail: Rail) -> (Instant, Option<Instant>) {
+        self.state.lock(|state| state.borrow().as_ref().map_or((Instant::MIN, None), |state| state.switched[rail as usize]))
+    }
+
+    fn release(&self, rail: Rail) {
+        self.state.lock(|state| {
+            if let Some(state) = state.borrow_mut().as_mut() {
+                if state.counts.release(rail) && !state.shut_down {
+                    if let Some(pin) = &mut state.pins[rail as usize] {
+                        pin.set(false);
+                    }
+                    state.switched[rail as usize].1 = Some(Instant::now());
+                }
+            }
+        });
+    }
+}
+
+/// Keeps a rail powered until dropped.
+pub struct RailGuard {
+    rail: Rail,
+}
+
+impl Drop for RailGuard {
+    fn drop(&mut self) {
+        POWER_GATE.release(self.rail);
+    }
+}
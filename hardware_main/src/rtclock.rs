@@ -1,14 +1,46 @@
+use core::cell::Cell;
+
 use embassy_stm32::rtc::{Rtc, DateTime, DayOfWeek};
+use embassy_time::Instant;
+use business_logic::clockmonitor::ClockMonitor;
+use business_logic::shutdown::{PowerFailCheckpoint, CHECKPOINT_WORDS};
 use business_logic::timestamp::Timestamp;
+use business_logic::wallclock::{CalendarTime, ClockError, WallClock};
+use crate::BusinessLog;
 
 const RTC_BACKUP_KEY_INDEX: usize = 0; // Index to RTC backup register where key is stored
 const RTC_BACKUP_RTCW_INDEX: usize = 1; // Index to RTC backup register where RTCW is stored
+const RTC_BACKUP_CHECKPOINT_INDEX: usize = 4; // Index to the RTC backup registers where the power-fail checkpoint is stored, after the watchdog registers
 const RTC_BACKUP_KEY_VALUE: u32 = 0xA53C4B69; // Value stored at RTC_BACKUP_KEY_INDEX if RTCW value is good
 const EMBASSY_DATETIME_OFFSET: u16 = 2000; // Offset for the year in DateTime, since embassy-stm32 uses 2000-2099, but the RTC uses 0-99.
 
+// The STM32L4 has 20 backup registers; writes past them are dropped and reads give None.
+const _: () = assert!(RTC_BACKUP_CHECKPOINT_INDEX + CHECKPOINT_WORDS <= Rtc::BACKUP_REGISTER_COUNT);
+
 pub struct Rtclock {
     rtc: Rtc, // <'static, embassy_stm32::rtc::RtcConfig>
     rtcw: u32,
+    monitor: Cell<ClockMonitor>, // Keeps time from the monotonic timer if the LSE crystal stops.
+}
+
+impl business_logic::hal::Rtc for Rtclock {
+    fn now(&self) -> Timestamp {
+        self.get_timestamp()
+    }
+}
+
+// The internal RTC as a `WallClock`, like an external chip on other boards.
+impl WallClock for Rtclock {
+    async fn now(&mut self) -> Result<Timestamp, ClockError> {
+        let now = self.rtc.now().or(Err(ClockError::Stopped))?;
+        let seconds = Rtclock::datetime_to_seconds(now).ok_or(ClockError::OutOfRange)?;
+        Ok(self.monitored(Timestamp { seconds }))
+    }
+
+    async fn set(&mut self, time: Timestamp) -> Result<(), ClockError> {
+        self.set_from_epoch_seconds(time.seconds);
+        Ok(())
+    }
 }
 
 impl Rtclock {
@@ -16,7 +48,7 @@ impl Rtclock {
     pub fn from_running(mut rtc: Rtc) -> Self {
         let rtcw = rtc.read_backup_register(RTC_BACKUP_RTCW_INDEX)
             .unwrap_or(0); // Read the RTCW value from the backup register, or 0 if not set.
-        Self { rtc, rtcw }
+        Self { rtc, rtcw, monitor: Cell::new(ClockMonitor::new()) }
     }
     /// Create a new Rtclock instance with a specific RTCW value.
     /// This is typically used when the RTC is starting from a power outage.
@@ -29,7 +61,44 @@ impl Rtclock {
         let dt = Rtclock::seconds_to_datetime(rtcw);
         rtc.set_datetime(dt).expect("Failed to set datetime");
         // Return the Rtclock instance.
-        Self { rtc, rtcw }
+        Self { rtc, rtcw, monitor: Cell::new(ClockMonitor::new()) }
+    }
+
+    /// Set the clock to `seconds` since the epoch (0, 3, 1), e.g. from a host. Returns the time it replaced,
+    /// for `LoggerEvent::ClockSet`.
+    /// The key is cleared while the registers change, so a reset part way through starts the clock
+    /// afresh at the next boot rather than trusting a half-written state.
+    pub fn set_from_epoch_seconds(&mut self, seconds: u32) -> Timestamp {
+        let before = self.get_timestamp();
+        self.rtc.write_backup_register(RTC_BACKUP_KEY_INDEX, 0);
+        self.rtc.set_datetime(Rtclock::seconds_to_datetime(seconds)).expect("Failed to set datetime");
+        // The last brownout moves with the clock, so it stays the same time ago.
+        self.rtcw = self.rtcw.wrapping_add(seconds.wrapping_sub(before.seconds));
+        self.rtc.write_backup_register(RTC_BACKUP_RTCW_INDEX, self.rtcw);
+        self.rtc.write_backup_register(RTC_BACKUP_KEY_INDEX, RTC_BACKUP_KEY_VALUE);
+        self.monitor.set(ClockMonitor::new());
+        before
+    }
+
+    /// Once the LSE crystal runs again after stopping, move the RTC forward by the time it lost.
+    /// Returns the seconds added. Call regularly.
+    ///
+    /// The RTC can't be switched over to the LSI instead: embassy-stm32 0.2 can't reconfigure
+    /// the clocks after init. Until the crystal runs again the time comes from the monotonic timer.
+    pub fn apply_clock_correction(&mut self) -> u32 {
+        let monitor = self.monitor.get();
+        let lost = monitor.correction_seconds();
+        if lost == 0 || monitor.is_degraded() {
+            return 0;
+        }
+        let now = self.get_timestamp();
+        self.set_from_epoch_seconds(now.seconds);
+        lost
+    }
+
+    /// Whether the LSE crystal has stopped, so the time comes from the monotonic timer.
+    pub fn is_degraded(&self) -> bool {
+        self.monitor.get().is_degraded()
     }
 
     /// Get RELT, the uptime in seconds since first boot.
@@ -45,7 +114,15 @@ impl Rtclock {
         let now = self.rtc.now().unwrap();
         // Convert to seconds since the epoch (0, 3, 1).
         let seconds = Rtclock::datetime_to_seconds(now).expect("Failed to convert datetime to seconds");
-        Timestamp { seconds }
+        self.monitored(Timestamp { seconds })
+    }
+
+    // The RTC reading, or the monotonic timer's extrapolation of it while the RTC isn't ticking.
+    fn monitored(&self, rtc: Timestamp) -> Timestamp {
+        let mut monitor = self.monitor.get();
+        let time = monitor.update(rtc, Instant::now().as_millis(), &mut BusinessLog);
+        self.monitor.set(monitor);
+        time
     }
 
     /// Get RTCWake, the value of RELT at the last "brownout" event.
@@ -53,6 +130,26 @@ impl Rtclock {
         self.rtcw
     }
 
+    /// Get the record in progress saved as the supply failed, if there is one.
+    pub fn read_power_fail_checkpoint(&self) -> Option<PowerFailCheckpoint> {
+        let mut words = [0u32; CHECKPOINT_WORDS];
+        for (offset, word) in words.iter_mut().enumerate() {
+            *word = self.rtc.read_backup_register(RTC_BACKUP_CHECKPOINT_INDEX + offset).unwrap_or(0);
+        }
+        PowerFailCheckpoint::from_words(&words)
+    }
+
+    pub fn write_power_fail_checkpoint(&self, checkpoint: &PowerFailCheckpoint) {
+        for (offset, word) in checkpoint.to_words().into_iter().enumerate() {
+            self.rtc.write_backup_register(RTC_BACKUP_CHECKPOINT_INDEX + offset, word);
+        }
+    }
+
+    /// Forget the checkpoint once its record is stored, so it isn't restored twice.
+    pub fn clear_power_fail_checkpoint(&self) {
+        self.rtc.write_backup_register(RTC_BACKUP_CHECKPOINT_INDEX + CHECKPOINT_WORDS - 1, 0);
+    }
+
     // Static methods for Rtclock
 
     /// Check if the RTC is running.
@@ -63,74 +160,24 @@ impl Rtclock {
 
     /// Convert seconds since the epoch (0, 3, 1) to a DateTime.
     pub fn seconds_to_datetime(seconds: u32) -> DateTime {
-        // Get the total number of days represented, plus the time of day.
-        // A u32 can hold up to 49,710 days, which is about 136 years.
-        let ts = Timestamp { seconds };
-        let (days, hour, minute, second) = ts.to_dhms();
-
-        // Calculate the Julian date from the number of days, since computational epoch
-        // March 1, 0000.
-        // Based on the algorithm from below, but not yet using the Euclidean 
-        // affine function optimizations:
-        // Neri C, Schneider L. "Euclidean affine functions and their application 
-        // to calendar algorithms". Softw Pract Exper. 2022;1-34. doi: 10.1002/spe.3172.
-        // https://onlinelibrary.wiley.com/doi/full/10.1002/spe.3172
-        // References to variable names are to the paper above, Section 5, which uses uppercase.
-        let n_1 = 4 * days + 3;  // N1
-        let year_computational = n_1 / 1461;  // Y
-        let n_y = n_1 % 1461 / 4; // N_Y
-        let n_2 = 5 * n_y + 461;  // N_2
-        let m = n_2 / 153; // M
-        let day_julian: u8 = (n_2 % 153 / 5 + 1) as u8; // D_J (skipped computing D)
-        // J = 1{M>=13}
-        let j = if m >= 13 {
-            1
-        } else {
-            0
-        };
-
-        let month_julian: u8 = (m - 12 * j) as u8; // M_J
-        // Embassy's DateTime uses a year offset of 2000, so we need to add that.
-        let year_julian: u16 = (year_computational + j) as u16 + EMBASSY_DATETIME_OFFSET; // Y_J + 2000
-
+        let time = CalendarTime::from_timestamp(Timestamp { seconds });
         // We do not use day of week, so the choice is arbitrary.
-        DateTime::from(year_julian, month_julian, day_julian, DayOfWeek::Monday, hour as u8, minute as u8, second as u8).expect("Invalid date")
+        DateTime::from(time.year, time.month, time.day, DayOfWeek::Monday, time.hour, time.minute, time.second).expect("Invalid date")
     }
 
     /// Convert a DateTime to seconds since the epoch (0, 3, 1) Julian date.
     pub fn datetime_to_seconds(datetime: DateTime) -> Option<u32> {
         // The STM32 RTC only holds year values 0-99.
         // Embassy's DateTime assumes the year is 2000-2099, so get last two digits.
-
-        let year: u32 = (datetime.year() % 100).into(); 
-        let month: u32 = datetime.month().into();
-        if year == 0 && month < 3 {
-            // The computational calendar does not support this combination with
-            // unsigned values, and we should always initialize the RTC to later than this.
-            return None;
-        }
-        let day: u32 = datetime.day().into();
-        let hour: u32 = datetime.hour().into();
-        let minute: u32 = datetime.minute().into();
-        let second: u32 = datetime.second().into();
-
-        let j: u32 = if month <= 2 {
-            // This is because the computational calendar starts on March 1st.
-            1
-        } else {
-            0
+        let time = CalendarTime {
+            year: EMBASSY_DATETIME_OFFSET + datetime.year() % 100,
+            month: datetime.month(),
+            day: datetime.day(),
+            hour: datetime.hour(),
+            minute: datetime.minute(),
+            second: datetime.second(),
         };
-        let y = year - j;
-        let m = month + 12 * j; // Month is 1-12, but we need to adjust for the computational calendar.
-        let d = day - 1; // Day is 1-31.
-
-        let y0 = 1461 * y / 4;
-        let m0 = (153 * m - 457) / 5;
-
-        let days_since_epoch =  y0 + m0 + d;
-
-        // Calculate the total seconds since the epoch.
-        Some(days_since_epoch * 86400 + hour * 3600 + minute * 60 + second)
+        time.to_timestamp().map(|timestamp| timestamp.seconds)
     }
 
 }
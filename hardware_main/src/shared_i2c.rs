@@ -0,0 +1,74 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use business_logic::power::ClockProfile;
+use embassy_embedded_hal::SetConfig;
+use embassy_stm32::i2c::{Error, I2c};
+use embassy_stm32::mode::Async;
+use embassy_stm32::time::Hertz;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::mutex::Mutex;
+use embedded_hal_async::i2c::{ErrorType, I2c as AsyncI2c, Operation, SevenBitAddress};
+
+struct Bus {
+    i2c: I2c<'static, Async>,
+    speed_hz: u32, // Speed the peripheral is currently configured for.
+}
+
+/// An I2C bus shared by several driver tasks.
+///
+/// Each driver gets its own `I2cHandle`. A transaction holds the bus from start to stop,
+/// so transactions from different tasks interleave but never mix on the wire.
+pub struct SharedI2c {
+    bus: Mutex<ThreadModeRawMutex, Option<Bus>>,
+    speed_hz: AtomicU32, // Speed for the current clock profile, applied before the next transaction.
+}
+
+/// Temperature (and humidity) sensors; later also the NFC tag.
+pub static SENSOR_BUS: SharedI2c = SharedI2c::new();
+/// The status display.
+pub static DISPLAY_BUS: SharedI2c = SharedI2c::new();
+/// The vaccine sensor, on boards that give it a bus of its own.
+pub static VACCINE_BUS: SharedI2c = SharedI2c::new();
+
+impl SharedI2c {
+    const fn new() -> Self {
+        Self { bus: Mutex::new(None), speed_hz: AtomicU32::new(ClockProfile::FULL_SPEED.i2c_hz) }
+    }
+
+    /// Take ownership of the peripheral, which must be configured for `ClockProfile::FULL_SPEED`.
+    pub async fn init(&self, i2c: I2c<'static, Async>) {
+        self.bus.lock().await.replace(Bus { i2c, speed_hz: ClockProfile::FULL_SPEED.i2c_hz });
+    }
+
+    /// A handle for one driver.
+    pub fn handle(&'static self) -> I2cHandle {
+        I2cHandle { bus: self }
+    }
+
+    /// Change the bus speed, e.g. for a new clock profile. Transfers in progress finish at the old speed.
+    pub fn set_speed(&self, hz: u32) {
+        self.speed_hz.store(hz, Ordering::Relaxed);
+    }
+}
+
+/// One driver's access to a `SharedI2c`.
+pub struct I2cHandle {
+    bus: &'static SharedI2c,
+}
+
+impl ErrorType for I2cHandle {
+    type Error = Error;
+}
+
+impl AsyncI2c for I2cHandle {
+    async fn transaction(&mut self, address: SevenBitAddress, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        let mut bus = self.bus.bus.lock().await;
+        let bus = bus.as_mut().expect("I2C bus not initialized");
+        let wanted_hz = self.bus.speed_hz.load(Ordering::Relaxed);
+        if wanted_hz != bus.speed_hz {
+            bus.speed_hz = wanted_hz;
+            let _ = bus.i2c.set_config(&Hertz(wanted_hz));
+        }
+        bus.i2c.transaction(address, operations).await
+    }
+}
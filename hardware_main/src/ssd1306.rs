@@ -0,0 +1,139 @@
+use embedded_hal_async::i2c::I2c;
+
+pub const SSD1306_ADDRESS: u8 = 0x3C; // I2C address with SA0 low.
+const WIDTH: usize = 128;
+const PAGES: u8 = 8; // 64 rows, 8 rows per page.
+const CHAR_WIDTH: usize = 6; // 5 columns of glyph plus 1 column of spacing.
+const COMMAND: u8 = 0x00; // Control byte for a command stream.
+const DATA: u8 = 0x40; // Control byte for a data stream.
+
+// Power-on configuration for a 128x64 panel with the internal charge pump.
+const INIT_SEQUENCE: [u8; 25] = [
+    0xAE, // Display off.
+    0xD5, 0x80, // Clock divide ratio / oscillator frequency.
+    0xA8, 0x3F, // Multiplex ratio 64.
+    0xD3, 0x00, // No display offset.
+    0x40, // Start line 0.
+    0x8D, 0x14, // Enable charge pump.
+    0x20, 0x02, // Page addressing mode.
+    0xA1, // Segment remap (column 127 is SEG0).
+    0xC8, // Scan COM outputs in reverse.
+    0xDA, 0x12, // COM pins configuration.
+    0x81, 0xCF, // Contrast.
+    0xD9, 0xF1, // Pre-charge period.
+    0xDB, 0x40, // VCOMH deselect level.
+    0xA4, // Display follows RAM.
+    0xA6, // Normal (not inverted).
+    0xAF, // Display on.
+];
+
+/// Minimal text-only SSD1306 OLED driver using page addressing.
+pub struct Ssd1306<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C> Ssd1306<I2C>
+where
+    I2C: I2c,
+{
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    pub async fn init(&mut self) -> Result<(), I2C::Error> {
+        let mut buf = [0u8; INIT_SEQUENCE.len() + 1];
+        buf[0] = COMMAND;
+        buf[1..].copy_from_slice(&INIT_SEQUENCE);
+        self.i2c.write(self.address, &buf).await?;
+        for page in 0..PAGES {
+            self.write_line(page, "").await?;
+        }
+        Ok(())
+    }
+
+    /// Write a line of text to one 8-pixel-high page, blanking the rest of the page.
+    pub async fn write_line(&mut self, page: u8, text: &str) -> Result<(), I2C::Error> {
+        // Select the page and column 0.
+        self.i2c.write(self.address, &[COMMAND, 0xB0 | (page & 0x07), 0x00, 0x10]).await?;
+        let mut buf = [0u8; WIDTH + 1];
+        buf[0] = DATA;
+        for (i, c) in text.chars().take(WIDTH / CHAR_WIDTH).enumerate() {
+            let start = 1 + i * CHAR_WIDTH;
+            buf[start..start + 5].copy_from_slice(glyph(c));
+        }
+        self.i2c.write(self.address, &buf).await
+    }
+}
+
+/// Column bitmap of a 5x7 glyph. Lowercase is shown as uppercase; unsupported characters as '?'.
+fn glyph(c: char) -> &'static [u8; 5] {
+    let c = c.to_ascii_uppercase();
+    match c {
+        ' '..='Z' => &FONT[c as usize - ' ' as usize],
+        _ => &FONT['?' as usize - ' ' as usize],
+    }
+}
+
+// Classic 5x7 font, ASCII ' ' to 'Z'.
+const FONT: [[u8; 5]; 59] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // '!'
+    [0x00, 0x07, 0x00, 0x07, 0x00], // '"'
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // '#'
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // '$'
+    [0x23, 0x13, 0x08, 0x64, 0x62], // '%'
+    [0x36, 0x49, 0x56, 0x20, 0x50], // '&'
+    [0x00, 0x08, 0x07, 0x03, 0x00], // '''
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // '('
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // ')'
+    [0x2A, 0x1C, 0x7F, 0x1C, 0x2A], // '*'
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // '+'
+    [0x00, 0x80, 0x70, 0x30, 0x00], // ','
+    [0x08, 0x08, 0x08, 0x08, 0x08], // '-'
+    [0x00, 0x00, 0x60, 0x60, 0x00], // '.'
+    [0x20, 0x10, 0x08, 0x04, 0x02], // '/'
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // '0'
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // '1'
+    [0x72, 0x49, 0x49, 0x49, 0x46], // '2'
+    [0x21, 0x41, 0x49, 0x4D, 0x33], // '3'
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // '4'
+    [0x27, 0x45, 0x45, 0x45, 0x39], // '5'
+    [0x3C, 0x4A, 0x49, 0x49, 0x31], // '6'
+    [0x41, 0x21, 0x11, 0x09, 0x07], // '7'
+    [0x36, 0x49, 0x49, 0x49, 0x36], // '8'
+    [0x46, 0x49, 0x49, 0x29, 0x1E], // '9'
+    [0x00, 0x00, 0x14, 0x00, 0x00], // ':'
+    [0x00, 0x40, 0x34, 0x00, 0x00], // ';'
+    [0x00, 0x08, 0x14, 0x22, 0x41], // '<'
+    [0x14, 0x14, 0x14, 0x14, 0x14], // '='
+    [0x00, 0x41, 0x22, 0x14, 0x08], // '>'
+    [0x02, 0x01, 0x59, 0x09, 0x06], // '?'
+    [0x3E, 0x41, 0x5D, 0x59, 0x4E], // '@'
+    [0x7C, 0x12, 0x11, 0x12, 0x7C], // 'A'
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // 'B'
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // 'C'
+    [0x7F, 0x41, 0x41, 0x41, 0x3E], // 'D'
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // 'E'
+    [0x7F, 0x09, 0x09, 0x09, 0x01], // 'F'
+    [0x3E, 0x41, 0x41, 0x51, 0x73], // 'G'
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // 'H'
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // 'I'
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // 'J'
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // 'K'
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // 'L'
+    [0x7F, 0x02, 0x1C, 0x02, 0x7F], // 'M'
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // 'N'
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // 'O'
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // 'P'
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // 'Q'
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // 'R'
+    [0x26, 0x49, 0x49, 0x49, 0x32], // 'S'
+    [0x03, 0x01, 0x7F, 0x01, 0x03], // 'T'
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // 'U'
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // 'V'
+    [0x3F, 0x40, 0x38, 0x40, 0x3F], // 'W'
+    [0x63, 0x14, 0x08, 0x14, 0x63], // 'X'
+    [0x03, 0x04, 0x78, 0x04, 0x03], // 'Y'
+    [0x61, 0x59, 0x49, 0x4D, 0x43], // 'Z'
+];
@@ -0,0 +1,31 @@
+use core::mem::MaybeUninit;
+
+use business_logic::logger::{WarmStart, WARM_START_WORDS};
+
+// Not zeroed at startup, so the state survives a reset, e.g. by the watchdog. The RTC backup
+// registers are all in use. It is lost if power is removed, which the CRC detects.
+#[unsafe(link_section = ".uninit.WARM_START")]
+static mut WARM_START: MaybeUninit<[u32; WARM_START_WORDS]> = MaybeUninit::uninit();
+
+/// Save the logger's state after an event, for `take` after a reset.
+pub fn save(state: &WarmStart) {
+    write(state.to_words());
+}
+
+/// Read the state saved before the last reset, and clear it.
+pub fn take() -> Option<WarmStart> {
+    // SAFETY: called once at boot before the logger task runs; any bit pattern is a valid [u32].
+    let words = unsafe { (&raw const WARM_START).read_volatile().assume_init() };
+    clear();
+    WarmStart::from_words(&words)
+}
+
+/// Forget the saved state, e.g. once the record in progress has been completed at power fail.
+pub fn clear() {
+    write([0; WARM_START_WORDS]);
+}
+
+fn write(words: [u32; WARM_START_WORDS]) {
+    // SAFETY: only the logger task writes it after boot, and only `take` reads it, before that.
+    unsafe { (&raw mut WARM_START).write_volatile(MaybeUninit::new(words)) };
+}
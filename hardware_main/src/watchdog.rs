@@ -0,0 +1,55 @@
+use core::cell::RefCell;
+
+use business_logic::watchdog::{HeartbeatRegistry, RestartEvent, TaskId};
+use embassy_stm32::{pac, peripherals::IWDG, wdg::IndependentWatchdog};
+use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::fmt::warn;
+
+pub const WATCHDOG_TIMEOUT_US: u32 = 30_000_000; // Close to the IWDG maximum with the 32 kHz LSI.
+const WATCHDOG_FEED_PERIOD: Duration = Duration::from_secs(5);
+const RTC_BACKUP_RESTART_INDEX: usize = 2; // RTC backup register for the restart record, after the Rtclock registers.
+const RTC_BACKUP_RESTART_COUNT_INDEX: usize = 3; // RTC backup register counting restarts.
+
+static HEARTBEATS: Mutex<ThreadModeRawMutex, RefCell<HeartbeatRegistry>> = Mutex::new(RefCell::new(HeartbeatRegistry::new()));
+
+/// Report that a supervised task is still making progress.
+pub fn heartbeat(task: TaskId) {
+    let now_ms = Instant::now().as_millis();
+    HEARTBEATS.lock(|registry| registry.borrow_mut().check_in(task, now_ms));
+}
+
+/// Read and clear the reset flags and the saved restart record.
+pub fn take_restart_event() -> RestartEvent {
+    let csr = pac::RCC.csr().read();
+    let record = pac::RTC.bkpr(RTC_BACKUP_RESTART_INDEX).read().bkp();
+    pac::RTC.bkpr(RTC_BACKUP_RESTART_INDEX).write(|w| w.set_bkp(0));
+    pac::RCC.csr().modify(|w| w.set_rmvf(true));
+    RestartEvent::from_reset(csr.borrstf(), csr.iwdgrstf(), record)
+}
+
+/// Count this restart. Returns the number of restarts since the backup domain was last reset.
+pub fn count_restart() -> u32 {
+    let count = pac::RTC.bkpr(RTC_BACKUP_RESTART_COUNT_INDEX).read().bkp().wrapping_add(1);
+    pac::RTC.bkpr(RTC_BACKUP_RESTART_COUNT_INDEX).write(|w| w.set_bkp(count));
+    count
+}
+
+/// Feeds the watchdog only while every registered task is checking in.
+#[embassy_executor::task]
+pub async fn watchdog_supervisor(mut wdg: IndependentWatchdog<'static, IWDG>) {
+    wdg.unleash();
+    loop {
+        let now_ms = Instant::now().as_millis();
+        match HEARTBEATS.lock(|registry| registry.borrow().overdue(now_ms)) {
+            None => wdg.pet(),
+            Some(task) => {
+                warn!("Task {} stopped checking in, waiting for watchdog reset", task);
+                pac::RTC.bkpr(RTC_BACKUP_RESTART_INDEX).write(|w| w.set_bkp(RestartEvent::missing_task_record(task)));
+                return;
+            }
+        }
+        Timer::after(WATCHDOG_FEED_PERIOD).await;
+    }
+}
@@ -0,0 +1,207 @@
+//! Import of records stored by the legacy C firmware, so devices upgraded in the field keep
+//! their history.
+//!
+//! The C firmware's record layout differs between its releases and isn't recorded here, so the
+//! decoder is driven by a layout table in the data dictionary's style: a `record_len` line, an
+//! optional `epoch_offset` line, then one `name,offset,type,scale` line per stored field, naming
+//! the field of `AggregationRecord` it fills. Fields the legacy records don't have keep their
+//! defaults.
+
+use std::fmt;
+
+use business_logic::aggregator::{FieldType, RecordField, RECORD_FIELDS};
+use business_logic::timestamp::Timestamp;
+
+use crate::AggregationRecord;
+
+/// How a legacy field is stored, little-endian like the C firmware's target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegacyType {
+    U8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+}
+
+impl LegacyType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "u8" => LegacyType::U8,
+            "u16" => LegacyType::U16,
+            "i16" => LegacyType::I16,
+            "u32" => LegacyType::U32,
+            "i32" => LegacyType::I32,
+            "f32" => LegacyType::F32,
+            _ => return None,
+        })
+    }
+
+    fn len(self) -> usize {
+        match self {
+            LegacyType::U8 => 1,
+            LegacyType::U16 | LegacyType::I16 => 2,
+            LegacyType::U32 | LegacyType::I32 | LegacyType::F32 => 4,
+        }
+    }
+
+    fn read(self, bytes: &[u8]) -> f64 {
+        match self {
+            LegacyType::U8 => f64::from(bytes[0]),
+            LegacyType::U16 => f64::from(u16::from_le_bytes([bytes[0], bytes[1]])),
+            LegacyType::I16 => f64::from(i16::from_le_bytes([bytes[0], bytes[1]])),
+            LegacyType::U32 => f64::from(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+            LegacyType::I32 => f64::from(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+            LegacyType::F32 => f64::from(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        }
+    }
+}
+
+/// Why a layout or a legacy dump couldn't be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegacyError {
+    Layout { line: usize }, // The layout line isn't understood, or a field lies outside the record.
+    UnknownField { line: usize }, // The layout names a field `AggregationRecord` doesn't have.
+    Truncated { offset: usize }, // The dump ends partway through the record at `offset`.
+}
+
+impl fmt::Display for LegacyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LegacyError::Layout { line } => write!(f, "layout line {} isn't understood", line),
+            LegacyError::UnknownField { line } => write!(f, "layout line {} names an unknown record field", line),
+            LegacyError::Truncated { offset } => write!(f, "dump ends partway through the record at byte {}", offset),
+        }
+    }
+}
+
+impl std::error::Error for LegacyError {}
+
+// One stored field, and the record field it fills.
+#[derive(Clone, Copy)]
+struct LegacyField {
+    target: RecordField,
+    offset: usize,
+    legacy_type: LegacyType,
+    scale: f64, // The value in the record's unit is the stored value times this.
+}
+
+/// Where the fields are in one release's legacy records.
+#[derive(Clone)]
+pub struct LegacyLayout {
+    record_len: usize,
+    epoch_offset: i64, // Added to legacy start times to get seconds since this firmware's epoch.
+    fields: Vec<LegacyField>,
+}
+
+impl LegacyLayout {
+    /// Read a layout table, see the module documentation. Blank lines and `#` comments are skipped.
+    pub fn parse(table: &str) -> Result<Self, LegacyError> {
+        let mut layout = Self { record_len: 0, epoch_offset: 0, fields: Vec::new() };
+        for (index, text) in table.lines().enumerate() {
+            let line = index + 1;
+            let text = text.split('#').next().unwrap_or("").trim();
+            if text.is_empty() {
+                continue;
+            }
+            let error = LegacyError::Layout { line };
+            match text.split(',').map(str::trim).collect::<Vec<_>>()[..] {
+                ["record_len", len] => layout.record_len = len.parse().map_err(|_| error)?,
+                ["epoch_offset", seconds] => layout.epoch_offset = seconds.parse().map_err(|_| error)?,
+                [name, offset, legacy_type, scale] => {
+                    let target = *RECORD_FIELDS.iter().find(|field| field.name == name).ok_or(LegacyError::UnknownField { line })?;
+                    let legacy_type = LegacyType::parse(legacy_type).ok_or(error)?;
+                    let (offset, scale) = (offset.parse().map_err(|_| error)?, scale.parse().map_err(|_| error)?);
+                    layout.fields.push(LegacyField { target, offset, legacy_type, scale });
+                }
+                _ => return Err(error),
+            }
+        }
+        // Checked once the length is known, whichever order the lines came in.
+        if layout.record_len == 0 || layout.fields.iter().any(|field| field.offset + field.legacy_type.len() > layout.record_len) {
+            return Err(LegacyError::Layout { line: table.lines().count() });
+        }
+        Ok(layout)
+    }
+
+    /// Convert a dump of legacy records, one after another, skipping erased (all 0xFF) slots.
+    pub fn convert(&self, bytes: &[u8]) -> Result<Vec<AggregationRecord>, LegacyError> {
+        let mut records = Vec::new();
+        for (index, legacy) in bytes.chunks(self.record_len).enumerate() {
+            if legacy.len() < self.record_len {
+                return Err(LegacyError::Truncated { offset: index * self.record_len });
+            }
+            if legacy.iter().all(|&byte| byte == 0xFF) {
+                continue;
+            }
+            records.push(self.record(legacy));
+        }
+        Ok(records)
+    }
+
+    fn record(&self, legacy: &[u8]) -> AggregationRecord {
+        let mut record = AggregationRecord::new(Timestamp { seconds: 0 });
+        for field in &self.fields {
+            let mut value = field.legacy_type.read(&legacy[field.offset..]) * field.scale;
+            if field.target.name == "start" {
+                value += self.epoch_offset as f64;
+            }
+            let word = match field.target.field_type {
+                FieldType::F32 => (value as f32).to_bits(),
+                FieldType::U32 | FieldType::Bitmap => value.round() as u32, // Saturates, negatives become 0.
+            };
+            (field.target.set)(&mut record, word);
+        }
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A made-up layout: start time, then TVC min and max in hundredths of a degree, then alarm minutes.
+    const LAYOUT: &str = "
+        record_len,12
+        epoch_offset,-3600 # The legacy clock counted from an hour earlier.
+        start,0,u32,1
+        tvc_min,4,i16,0.01
+        tvc_max,6,i16,0.01
+        high_alarm_seconds,8,u16,60
+        door_openings,10,u8,1
+    ";
+
+    fn legacy(start: u32, min: i16, max: i16, alarm_minutes: u16, doors: u8) -> Vec<u8> {
+        let mut bytes = start.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&min.to_le_bytes());
+        bytes.extend_from_slice(&max.to_le_bytes());
+        bytes.extend_from_slice(&alarm_minutes.to_le_bytes());
+        bytes.extend_from_slice(&[doors, 0]);
+        bytes
+    }
+
+    #[test]
+    fn test_convert() {
+        let layout = LegacyLayout::parse(LAYOUT).unwrap();
+        let mut dump = legacy(4500, -150, 925, 15, 3);
+        dump.extend_from_slice(&[0xFF; 12]); // Erased.
+        dump.extend(legacy(5400, 400, 450, 0, 0));
+        let records = layout.convert(&dump).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].start, Timestamp { seconds: 900 });
+        assert_eq!((records[0].tvc_min, records[0].tvc_max), (-1.5, 9.25));
+        assert_eq!((records[0].high_alarm_seconds, records[0].door_openings), (900, 3));
+        assert_eq!(records[1].start, Timestamp { seconds: 1800 });
+        assert_eq!(records[1].tamb_max, 0.0); // Not in the legacy records.
+        assert_eq!(layout.convert(&dump[..30]), Err(LegacyError::Truncated { offset: 24 }));
+    }
+
+    #[test]
+    fn test_layout_errors() {
+        assert_eq!(LegacyLayout::parse("record_len,4\nstart,0,u64,1").err(), Some(LegacyError::Layout { line: 2 }));
+        assert_eq!(LegacyLayout::parse("record_len,4\nstart_time,0,u32,1").err(), Some(LegacyError::UnknownField { line: 2 }));
+        assert_eq!(LegacyLayout::parse("record_len,4\nstart,2,u32,1").err(), Some(LegacyError::Layout { line: 2 }));
+        assert!(LegacyLayout::parse("start,0,u32,1").is_err()); // No record length.
+    }
+}
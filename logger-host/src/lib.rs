@@ -0,0 +1,216 @@
+//! Host-side companion to the logger firmware: parses binary record downloads, checks their
+//! tamper chain, and converts them to CSV or JSON.
+//!
+//! The record definitions are business_logic's own, so host tools read exactly what the
+//! firmware writes, and a field added there appears in the conversions here without changes.
+
+pub mod legacy;
+
+use std::fmt::{self, Write};
+
+use business_logic::aggregator::{FieldType, RECORD_FIELDS};
+
+pub use business_logic::aggregator::AggregationRecord;
+pub use business_logic::store::{verify_chain, ChainError, ChainedRecord, DownloadHeader, CHAINED_RECORD_LEN};
+
+/// Where a download stops being records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadError {
+    Truncated { offset: usize }, // The download ends partway through the record at `offset`.
+    Invalid { offset: usize }, // The bytes at `offset` aren't a record.
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::Truncated { offset } => write!(f, "download ends partway through the record at byte {}", offset),
+            DownloadError::Invalid { offset } => write!(f, "no valid record at byte {}", offset),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+/// A decoded binary download.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Download {
+    pub epoch_anchor: Option<u32>, // From the download's header, None without one.
+    pub records: Vec<ChainedRecord>,
+}
+
+/// Decode a binary download: its `DownloadHeader`, then `ChainedRecord::to_bytes` of each
+/// record, one after another. Records from any firmware version decode, whatever their length,
+/// and downloads from firmware that wrote no header decode without an epoch anchor.
+pub fn parse_download(bytes: &[u8]) -> Result<Download, DownloadError> {
+    let (header, mut offset) = DownloadHeader::from_bytes(bytes).unwrap_or_default();
+    if offset > bytes.len() {
+        return Err(DownloadError::Truncated { offset: 0 });
+    }
+    let mut records = Vec::new();
+    while offset < bytes.len() {
+        let rest = &bytes[offset..];
+        if rest.get(9).is_none_or(|&len| rest.len() < 8 + usize::from(len)) {
+            return Err(DownloadError::Truncated { offset });
+        }
+        let (record, len) = ChainedRecord::from_bytes(rest).ok_or(DownloadError::Invalid { offset })?;
+        records.push(record);
+        offset += len;
+    }
+    Ok(Download { epoch_anchor: header.epoch_anchor, records })
+}
+
+/// Decrypt and decode a raw dump of an encrypted record store, given the cipher it was
+/// written with.
+#[cfg(feature = "encryption")]
+pub fn parse_encrypted_download(bytes: &[u8], cipher: &mut business_logic::encryption::RecordCipher) -> Result<Vec<ChainedRecord>, DownloadError> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let rest = &bytes[offset..];
+        let Some(len) = rest.get(9).map(|&len| 8 + usize::from(len)).filter(|&len| rest.len() >= len) else {
+            return Err(DownloadError::Truncated { offset });
+        };
+        records.push(cipher.open(&rest[..len]).ok_or(DownloadError::Invalid { offset })?);
+        offset += len;
+    }
+    Ok(records)
+}
+
+/// The Unix time of `seconds` on the logger's clock, given its epoch anchor, from the download
+/// header, the report summary or `Config::epoch_anchor`.
+pub fn unix_seconds(epoch_anchor: u32, seconds: u32) -> u64 {
+    u64::from(epoch_anchor) + u64::from(seconds)
+}
+
+/// The records as CSV: a column header, then a row per record, with the sequence number, previous
+/// hash and Unix start time, if the epoch anchor is known, followed by the record's fields in
+/// stored order.
+pub fn to_csv(records: &[ChainedRecord], epoch_anchor: Option<u32>) -> String {
+    let mut csv = String::from("sequence,previous_hash,start_unix");
+    for field in &RECORD_FIELDS {
+        let _ = write!(csv, ",{}", field.name);
+    }
+    csv.push('\n');
+    for chained in records {
+        let _ = write!(csv, "{},{:08X},", chained.sequence, chained.previous_hash);
+        if let Some(anchor) = epoch_anchor {
+            let _ = write!(csv, "{}", unix_seconds(anchor, chained.record.start.seconds));
+        }
+        for field in &RECORD_FIELDS {
+            let _ = write!(csv, ",{}", Value(field.field_type, (field.get)(&chained.record)));
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+/// The records as a JSON array of objects, one per record, with the same names as `to_csv`.
+pub fn to_json(records: &[ChainedRecord], epoch_anchor: Option<u32>) -> String {
+    let mut json = String::from("[");
+    for (index, chained) in records.iter().enumerate() {
+        let separator = if index == 0 { "\n" } else { ",\n" };
+        let _ = write!(json, "{}  {{\"sequence\": {}, \"previous_hash\": \"{:08X}\"", separator, chained.sequence, chained.previous_hash);
+        let _ = match epoch_anchor {
+            Some(anchor) => write!(json, ", \"start_unix\": {}", unix_seconds(anchor, chained.record.start.seconds)),
+            None => write!(json, ", \"start_unix\": null"),
+        };
+        for field in &RECORD_FIELDS {
+            let value = Value(field.field_type, (field.get)(&chained.record));
+            // JSON has no NaN or infinity.
+            if field.field_type == FieldType::F32 && !f32::from_bits(value.1).is_finite() {
+                let _ = write!(json, ", \"{}\": null", field.name);
+            } else {
+                let _ = write!(json, ", \"{}\": {}", field.name, value);
+            }
+        }
+        json.push('}');
+    }
+    json.push_str(if records.is_empty() { "]\n" } else { "\n]\n" });
+    json
+}
+
+// A stored word, shown as its type.
+struct Value(FieldType, u32);
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            FieldType::U32 | FieldType::Bitmap => write!(f, "{}", self.1),
+            FieldType::F32 => write!(f, "{}", f32::from_bits(self.1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use business_logic::store::RecordChain;
+    use business_logic::timestamp::Timestamp;
+    use business_logic::wallclock::EPOCH_UNIX_SECONDS;
+
+    // Records, and their download without a header.
+    fn download() -> (Vec<ChainedRecord>, Vec<u8>) {
+        let mut chain = RecordChain::new();
+        let records: Vec<ChainedRecord> = (0..3)
+            .map(|index| {
+                let mut record = AggregationRecord::new(Timestamp { seconds: index * 900 });
+                (record.tvc_seconds, record.tvc_max, record.door_openings) = (900, 5.5, index);
+                chain.link(record)
+            })
+            .collect();
+        let bytes = records.iter().flat_map(|chained| chained.to_bytes()).collect();
+        (records, bytes)
+    }
+
+    #[test]
+    fn test_parse_and_verify() {
+        let (records, bytes) = download();
+        assert_eq!(parse_download(&bytes), Ok(Download { epoch_anchor: None, records: records.clone() }));
+        assert_eq!(verify_chain(records.clone()), Ok(()));
+        assert_eq!(parse_download(&bytes[..bytes.len() - 1]), Err(DownloadError::Truncated { offset: 2 * CHAINED_RECORD_LEN }));
+        let mut damaged = bytes.clone();
+        damaged[CHAINED_RECORD_LEN + 8] = 0; // Version 0.
+        assert_eq!(parse_download(&damaged), Err(DownloadError::Invalid { offset: CHAINED_RECORD_LEN }));
+        let mut tampered = bytes.clone();
+        tampered[CHAINED_RECORD_LEN + 10] ^= 1; // Start time of the second record.
+        assert_eq!(verify_chain(parse_download(&tampered).unwrap().records), Err(ChainError::Broken { sequence: 2 }));
+        // With a header, the records follow it.
+        let header = DownloadHeader { epoch_anchor: Some(EPOCH_UNIX_SECONDS) }.to_bytes();
+        let headed = [&header[..], &bytes].concat();
+        assert_eq!(parse_download(&headed), Ok(Download { epoch_anchor: Some(EPOCH_UNIX_SECONDS), records }));
+        assert_eq!(parse_download(&headed[..header.len() + 1]), Err(DownloadError::Truncated { offset: header.len() }));
+        assert_eq!(parse_download(&header[..8]), Err(DownloadError::Truncated { offset: 0 }));
+    }
+
+    #[test]
+    fn test_conversions() {
+        let (records, _) = download();
+        let csv = to_csv(&records, Some(EPOCH_UNIX_SECONDS));
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("sequence,previous_hash,start_unix,start,tvc_seconds,tvc_integral,"));
+        assert!(lines[2].starts_with(&format!("1,{:08X},951869700,900,900,0,0,0,5.5,", records[0].hash())));
+        assert_eq!(lines[0].split(',').count(), lines[2].split(',').count());
+        assert!(to_csv(&records, None).lines().nth(1).unwrap().starts_with("0,00000000,,0,"));
+        let json = to_json(&records[..1], None);
+        assert!(json.starts_with("[\n  {\"sequence\": 0, \"previous_hash\": \"00000000\", \"start_unix\": null, \"start\": 0, \"tvc_seconds\": 900,"));
+        assert!(json.contains("\"tamb_quality\": 0, \"kind\": 0, \"probe0_channel\": 0, \"probe0_seconds\": 0,"));
+        assert!(json.ends_with("\"probe1_low_alarm_seconds\": 0, \"motion_events\": 0, \"door_alarm_seconds\": 0}\n]\n"));
+        let mut nan = records[0];
+        nan.record.tvc_min = f32::NAN;
+        assert!(to_json(&[nan], Some(EPOCH_UNIX_SECONDS)).contains("\"start_unix\": 951868800, \"start\": 0,"));
+        assert!(to_json(&[nan], None).contains("\"tvc_min\": null"));
+        assert_eq!(to_json(&[], None), "[]\n");
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_download() {
+        use business_logic::encryption::RecordCipher;
+
+        let (records, _) = download();
+        let mut cipher = RecordCipher::new(&[3; 16], 1);
+        let bytes: Vec<u8> = records.iter().flat_map(|chained| cipher.seal(chained)).collect();
+        assert_eq!(parse_encrypted_download(&bytes, &mut cipher), Ok(records));
+    }
+}
@@ -0,0 +1,50 @@
+//! Authenticated exports: a last line `mac,<hex>` holding the HMAC-SHA256 of everything before it.
+//! The key is shared with the device, so the tag shows the export wasn't altered by anyone
+//! without the key; it is not a signature.
+
+use business_logic::authentication::{self, Authenticator};
+use business_logic::provisioning::{DeviceKey, DEVICE_KEY_LEN};
+
+const TAG_PREFIX: &str = "mac,";
+
+/// Parse a device key given as hex on the command line.
+pub fn parse_key(hex: &str) -> Option<DeviceKey> {
+    // Keys and tags are both 32 bytes.
+    const _: () = assert!(DEVICE_KEY_LEN == authentication::TAG_LEN);
+    authentication::from_hex(hex).map(DeviceKey)
+}
+
+/// Append the tag line to an export.
+pub fn authenticate(export: &str, key: &DeviceKey) -> String {
+    let mut authenticator = Authenticator::new(key);
+    authenticator.update(export.as_bytes());
+    format!("{}{}{}\n", export, TAG_PREFIX, authentication::to_hex(&authenticator.finish()))
+}
+
+/// Check the tag line of an export, and return the export without it.
+pub fn verify<'a>(authenticated: &'a str, key: &DeviceKey) -> Result<&'a str, String> {
+    let body_len = authenticated.trim_end_matches('\n').rfind('\n').map_or(0, |newline| newline + 1);
+    let (body, last) = authenticated.split_at(body_len);
+    let hex = last.trim_end().strip_prefix(TAG_PREFIX).ok_or("export has no MAC")?;
+    let tag = authentication::from_hex(hex).ok_or("malformed MAC")?;
+    if !authentication::verify(key, body.as_bytes(), &tag) {
+        return Err("MAC does not match: the export was modified or tagged with another key".into());
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authenticate_and_verify() {
+        let key = parse_key(&"ab".repeat(32)).unwrap();
+        let authenticated = authenticate("start,tvc\n0,900\n", &key);
+        assert_eq!(verify(&authenticated, &key), Ok("start,tvc\n0,900\n"));
+        assert!(verify(&authenticated.replace("0,900", "0,901"), &key).is_err());
+        assert!(verify(&authenticated, &DeviceKey([0; 32])).is_err());
+        assert!(verify("start,tvc\n0,900\n", &key).is_err());
+        assert!(parse_key("abc").is_none());
+    }
+}
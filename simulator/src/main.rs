@@ -0,0 +1,330 @@
+//! Feeds a scripted scenario through the business logic `Logger` and prints the resulting records as CSV.
+//!
+//! Usage: `cargo run -p simulator -- scenario.csv`. See `scenarios/` for the file format.
+//! With `--replay events.csv records.csv`, regenerates the records from a downloaded event log
+//! and reports where they differ from the stored ones. `--fahrenheit` before either form exports
+//! temperatures in °F. `--key <hex>` appends a MAC of the export with a device key, or when
+//! replaying, first checks the MAC of the stored records. Operator notes in the scenario follow the
+//! records as `note,<time>,"<text>"` rows, which replaying ignores. `--dictionary` prints the
+//! description of the record format that host tools use to decode downloads.
+
+mod authentication;
+mod replay;
+
+use business_logic::aggregator::AggregationRecord;
+use business_logic::dictionary::write_dictionary;
+use business_logic::log::NullLog;
+use business_logic::logger::{Logger, LoggerEvent, PauseReason};
+use business_logic::notes::NoteLog;
+use business_logic::sample::TemperatureSample;
+use business_logic::timestamp::Timestamp;
+use business_logic::units::TemperatureUnit;
+use std::fmt::Write;
+
+/// One problem in a scenario file, with its 1-based line number.
+#[derive(Debug, PartialEq)]
+struct ParseError {
+    line: usize,
+    message: String,
+}
+
+/// A parsed scenario: logger events in time order, and the operator notes made along the way.
+#[derive(Debug)]
+struct Scenario {
+    events: Vec<LoggerEvent>,
+    notes: NoteLog,
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut unit = TemperatureUnit::Celsius;
+    let mut key = None;
+    loop {
+        match args.first().map(String::as_str) {
+            Some("--fahrenheit") => {
+                args.remove(0);
+                unit = TemperatureUnit::Fahrenheit;
+            }
+            Some("--key") if args.len() > 1 => {
+                let hex = args.drain(..2).nth(1).unwrap_or_default();
+                key = Some(authentication::parse_key(&hex).unwrap_or_else(|| {
+                    eprintln!("--key takes {} hex digits", 2 * business_logic::provisioning::DEVICE_KEY_LEN);
+                    std::process::exit(2);
+                }));
+            }
+            _ => break,
+        }
+    }
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["--dictionary"] => {
+            let mut dictionary = String::new();
+            let _ = write_dictionary(&mut dictionary);
+            print!("{}", dictionary);
+        }
+        [path] => {
+            let scenario = load_scenario(path);
+            let csv = records_csv(&run(&scenario.events), unit) + &notes_csv(&scenario.notes);
+            match &key {
+                Some(key) => print!("{}", authentication::authenticate(&csv, key)),
+                None => print!("{}", csv),
+            }
+        }
+        ["--replay", events_path, records_path] => {
+            let regenerated = records_csv(&run(&load_scenario(events_path).events), unit);
+            let stored = read(records_path);
+            let stored = match &key {
+                Some(key) => authentication::verify(&stored, key).unwrap_or_else(|error| {
+                    eprintln!("{}: {}", records_path, error);
+                    std::process::exit(1);
+                }),
+                None => &stored,
+            };
+            let mismatches = replay::compare(stored, &regenerated);
+            for mismatch in &mismatches {
+                println!("{}", mismatch);
+            }
+            if !mismatches.is_empty() {
+                std::process::exit(1);
+            }
+            println!("{} records match", regenerated.lines().count() - 1);
+        }
+        _ => {
+            eprintln!("usage: simulator [--fahrenheit] [--key <hex>] <scenario.csv>");
+            eprintln!("       simulator [--fahrenheit] [--key <hex>] --replay <events.csv> <records.csv>");
+            eprintln!("       simulator --dictionary");
+            std::process::exit(2);
+        }
+    }
+}
+
+fn read(path: &str) -> String {
+    std::fs::read_to_string(path).unwrap_or_else(|error| {
+        eprintln!("{}: {}", path, error);
+        std::process::exit(1);
+    })
+}
+
+fn load_scenario(path: &str) -> Scenario {
+    parse_scenario(&read(path)).unwrap_or_else(|error| {
+        eprintln!("{}:{}: {}", path, error.line, error.message);
+        std::process::exit(1);
+    })
+}
+
+/// Parse a scenario into events and notes in time order. Events at the same time keep their order in the file.
+fn parse_scenario(scenario: &str) -> Result<Scenario, ParseError> {
+    let mut events = Vec::new();
+    let mut notes = Vec::new();
+    for (index, line) in scenario.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let error = |message| ParseError { line: index + 1, message };
+        if fields.get(1) == Some(&"note") {
+            // The text may itself contain commas.
+            let time = Timestamp { seconds: parse_number(fields[0]).map_err(error)? };
+            notes.push((time, line.splitn(3, ',').nth(2).unwrap_or(""), index + 1));
+            continue;
+        }
+        parse_line(&fields, &mut events).map_err(error)?;
+    }
+    events.sort_by_key(|event| event.timestamp().seconds);
+    notes.sort_by_key(|(time, _, _)| time.seconds);
+    let mut note_log = NoteLog::new();
+    for (time, text, line) in notes {
+        note_log.add(time, text, &mut NullLog).map_err(|error| ParseError { line, message: format!("invalid note: {:?}", error) })?;
+    }
+    Ok(Scenario { events, notes: note_log })
+}
+
+fn parse_line(fields: &[&str], events: &mut Vec<LoggerEvent>) -> Result<(), String> {
+    let time = Timestamp { seconds: parse_number(fields[0])? };
+    match (fields.get(1).copied(), fields.len()) {
+        (Some("sample"), 4) => events.push(sample(time, parse_number(fields[2])?, parse_number(fields[3])?)),
+        (Some("samples"), 7) => {
+            let duration: u32 = parse_number(fields[2])?;
+            let period: u32 = parse_number(fields[3])?;
+            let (tvc_start, tvc_end): (f32, f32) = (parse_number(fields[4])?, parse_number(fields[5])?);
+            let tamb = parse_number(fields[6])?;
+            if period == 0 {
+                return Err("period must be positive".into());
+            }
+            // A linear ramp, one reading per period, excluding the end time.
+            for offset in (0..duration).step_by(period as usize) {
+                let tvc = tvc_start + (tvc_end - tvc_start) * offset as f32 / duration as f32;
+                events.push(sample(Timestamp { seconds: time.seconds + offset }, tvc, tamb));
+            }
+        }
+        (Some("door"), 3) => match fields[2] {
+            "open" => events.push(LoggerEvent::DoorOpened(time)),
+            "close" => events.push(LoggerEvent::DoorClosed(time)),
+            other => return Err(format!("unknown door state '{}'", other)),
+        },
+        (Some("power"), 3) => match fields[2] {
+            "lost" => events.push(LoggerEvent::PowerLost(time)),
+            "restored" => events.push(LoggerEvent::PowerRestored(time)),
+            other => return Err(format!("unknown power state '{}'", other)),
+        },
+        (Some("pause"), 4) => {
+            let reason = match fields[2] {
+                "defrost" => PauseReason::Defrost,
+                "maintenance" => PauseReason::Maintenance,
+                "cleaning" => PauseReason::Cleaning,
+                "relocation" => PauseReason::Relocation,
+                other => return Err(format!("unknown pause reason '{}'", other)),
+            };
+            events.push(LoggerEvent::Paused(time, reason, parse_number(fields[3])?));
+        }
+        (Some("resume"), 2) => events.push(LoggerEvent::Resumed(time)),
+        (Some("moved"), 2) => events.push(LoggerEvent::Moved(time)),
+        (Some(kind), count) => return Err(format!("unknown event '{}' with {} fields", kind, count)),
+        (None, _) => return Err("missing event kind".into()),
+    }
+    Ok(())
+}
+
+fn parse_number<T: std::str::FromStr>(field: &str) -> Result<T, String> {
+    field.parse().map_err(|_| format!("invalid number '{}'", field))
+}
+
+fn sample(timestamp: Timestamp, tvc: f32, tamb: f32) -> LoggerEvent {
+    LoggerEvent::Sample(TemperatureSample {
+        timestamp,
+        tamb,
+        tvc,
+        tamb_quality: 0,
+        tvc_quality: 0,
+        #[cfg(feature = "humidity")]
+        humidity: None,
+    })
+}
+
+/// Feed the events through a fresh logger and collect every record, including the last partial one.
+fn run(events: &[LoggerEvent]) -> Vec<AggregationRecord> {
+    let mut logger = Logger::default();
+    let mut records = Vec::new();
+    for event in events {
+        // Events are sorted, so they can't be out of order.
+        logger.process_event(*event, |record| records.push(record)).unwrap();
+    }
+    logger.flush(|record| records.push(record));
+    records
+}
+
+/// Records as CSV, with temperatures converted to `unit`.
+fn records_csv(records: &[AggregationRecord], unit: TemperatureUnit) -> String {
+    let mut csv = String::from(
+        "start,tvc_seconds,tvc_avg,tvc_min,tvc_max,tamb_avg,tamb_min,tamb_max,diff_avg,diff_max,high_seconds,low_seconds,\
+         high_alarm_seconds,low_alarm_seconds,door_openings,door_open_seconds,power_off_seconds,paused_seconds,pause_reasons,\
+         band_le_-0.5,band_-0.5_2,band_2_8,band_8_15,band_gt_15,errors,tvc_quality,tamb_quality,unit\n",
+    );
+    for record in records {
+        let t = |celsius: f32| unit.from_celsius(celsius);
+        let _ = writeln!(
+            csv,
+            "{},{},{:.2},{:.2},{:.2},{:.2},{:.2},{:.2},{:.2},{:.2},{},{},{},{},{},{},{},{},{:X},{},{:08X},{:X},{:X},{}",
+            record.start.seconds,
+            record.tvc_seconds,
+            t(record.tvc_average().unwrap_or(0.0)),
+            t(record.tvc_min),
+            t(record.tvc_max),
+            t(record.tamb_average().unwrap_or(0.0)),
+            t(record.tamb_min),
+            t(record.tamb_max),
+            unit.from_celsius_difference(record.differential_mean().unwrap_or(0.0)),
+            unit.from_celsius_difference(record.differential_max),
+            record.high_seconds,
+            record.low_seconds,
+            record.high_alarm_seconds,
+            record.low_alarm_seconds,
+            record.door_openings,
+            record.door_open_seconds,
+            record.power_off_seconds,
+            record.paused_seconds,
+            record.pause_reasons,
+            record.band_seconds.map(|seconds| seconds.to_string()).join(","),
+            record.logger_errors.as_u32(),
+            record.tvc_quality,
+            record.tamb_quality,
+            unit.symbol(),
+        );
+    }
+    csv
+}
+
+/// Operator notes as CSV rows following the records. The first column isn't a number, so the
+/// rows are told apart from records.
+fn notes_csv(notes: &NoteLog) -> String {
+    let mut csv = String::new();
+    for note in notes.iter() {
+        let _ = writeln!(csv, "note,{},\"{}\"", note.timestamp.seconds, note.text);
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse_scenario("# comment\n\n0,door,ajar").unwrap_err(), ParseError {
+            line: 3,
+            message: "unknown door state 'ajar'".into()
+        });
+        assert!(parse_scenario("x,sample,5,20").is_err());
+        assert!(parse_scenario("0,samples,60,0,5,5,20").is_err());
+        assert!(parse_scenario("0,pause,lunch,600").is_err());
+    }
+
+    #[test]
+    fn test_pause_export() {
+        let records = run(&parse_scenario("0,sample,5,25
+300,pause,defrost,900
+1200,resume
+1200,sample,5,25").unwrap().events);
+        let csv = records_csv(&records, TemperatureUnit::Celsius);
+        assert!(csv.lines().nth(1).unwrap().contains(",0,600,1,0,0,300,0,0,"), "{}", csv);
+    }
+
+    #[test]
+    fn test_events_sorted_by_time() {
+        let events = parse_scenario("600,door,close\n0,samples,900,300,4,7,20\n0,door,open").unwrap().events;
+        let times: Vec<u32> = events.iter().map(|event| event.timestamp().seconds).collect();
+        assert_eq!(times, [0, 0, 300, 600, 600]);
+        assert_eq!(events[1], LoggerEvent::DoorOpened(Timestamp { seconds: 0 }));
+    }
+
+    #[test]
+    fn test_example_scenario() {
+        let events = parse_scenario(include_str!("../scenarios/who_door_and_power.csv")).unwrap().events;
+        let records = run(&events);
+        assert_eq!(records.len(), 35);
+        assert_eq!(records.iter().map(|record| record.door_openings).sum::<u32>(), 1);
+        assert_eq!(records.iter().map(|record| record.door_open_seconds).sum::<u32>(), 1800);
+        assert_eq!(records.iter().map(|record| record.power_off_seconds).sum::<u32>(), 10800);
+        assert!(records.iter().any(|record| record.high_seconds > 0));
+        assert!(records.iter().all(|record| record.low_seconds == 0));
+    }
+
+    #[test]
+    fn test_fahrenheit_export() {
+        let records = run(&parse_scenario("0,sample,5,25\n900,sample,5,25").unwrap().events);
+        let csv = records_csv(&records, TemperatureUnit::Fahrenheit);
+        let row = csv.lines().nth(1).unwrap();
+        assert!(row.starts_with("0,900,41.00,41.00,41.00,77.00,77.00,77.00,"), "{}", row);
+        assert!(row.ends_with(",F"));
+    }
+
+    #[test]
+    fn test_notes_export() {
+        let scenario = parse_scenario("0,sample,5,25\n1200,note,defrost, then restocked\n300,note,door checked").unwrap();
+        assert_eq!(scenario.events.len(), 1);
+        assert_eq!(notes_csv(&scenario.notes), "note,300,\"door checked\"\nnote,1200,\"defrost, then restocked\"\n");
+        let export = records_csv(&run(&scenario.events), TemperatureUnit::Celsius) + &notes_csv(&scenario.notes);
+        assert_eq!(replay::compare(&export, &records_csv(&run(&scenario.events), TemperatureUnit::Celsius)), []);
+        assert_eq!(parse_scenario("0,sample,5,25\n\n60,note,").unwrap_err(), ParseError { line: 3, message: "invalid note: Empty".into() });
+    }
+}
@@ -0,0 +1,65 @@
+//! Comparison of regenerated records with the ones stored by the logger.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A record that differs between the stored and regenerated CSV, keyed by its start time.
+#[derive(Debug, PartialEq)]
+pub enum Mismatch {
+    Missing(String), // Stored but not regenerated.
+    Extra(String), // Regenerated but not stored.
+    Changed { stored: String, regenerated: String },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Missing(stored) => write!(f, "missing  {}", stored),
+            Mismatch::Extra(regenerated) => write!(f, "extra    {}", regenerated),
+            Mismatch::Changed { stored, regenerated } => write!(f, "stored   {}\nreplayed {}", stored, regenerated),
+        }
+    }
+}
+
+/// Compare two record CSVs row by row, matching rows on the start time in the first column.
+/// Both must use the simulator's output format.
+pub fn compare(stored: &str, regenerated: &str) -> Vec<Mismatch> {
+    let stored = rows_by_start(stored);
+    let mut regenerated = rows_by_start(regenerated);
+    let mut mismatches = Vec::new();
+    for (start, stored_row) in stored {
+        match regenerated.remove(&start) {
+            Some(row) if row == stored_row => {}
+            Some(row) => mismatches.push(Mismatch::Changed { stored: stored_row.into(), regenerated: row.into() }),
+            None => mismatches.push(Mismatch::Missing(stored_row.into())),
+        }
+    }
+    mismatches.extend(regenerated.into_values().map(|row| Mismatch::Extra(row.into())));
+    mismatches
+}
+
+// Rows keyed by start time, so they are compared in time order. Rows without a numeric start,
+// such as the header, are skipped.
+fn rows_by_start(csv: &str) -> BTreeMap<u32, &str> {
+    csv.lines()
+        .map(str::trim)
+        .filter_map(|row| Some((row.split(',').next()?.parse().ok()?, row)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let stored = "start,tvc_avg\n0,5.00\n900,5.10\n1800,5.20\n";
+        let regenerated = "start,tvc_avg\n0,5.00\n900,5.15\n2700,5.30\n";
+        assert_eq!(compare(stored, stored), []);
+        assert_eq!(compare(stored, regenerated), [
+            Mismatch::Changed { stored: "900,5.10".into(), regenerated: "900,5.15".into() },
+            Mismatch::Missing("1800,5.20".into()),
+            Mismatch::Extra("2700,5.30".into()),
+        ]);
+    }
+}